@@ -0,0 +1,475 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Completions inside attributes, which racer doesn't offer: the built-in
+//! attributes after `#[`, derivable traits in `derive(...)`, configuration
+//! options in `cfg(...)` and the features of the package in `feature = "..."`.
+//!
+//! Derive macros of dependencies aren't in the analysis data of the crate
+//! using them. They are found in the data of the crates defining them (see
+//! `DeriveMacros`) and offered when the manifest of the package depends on
+//! these crates.
+
+use actions::manifest::Manifest;
+use build::LibNames;
+use data::{Analysis, ImportKind};
+use lsp_data::{CompletionItem, CompletionItemKind, CompletionRelevance, RankedCompletion};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// What is being completed inside an attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeContext {
+    /// The name of the attribute, e.g. `#[inl`.
+    Name,
+    /// A trait in a derive list, e.g. `#[derive(Debug, Cl`.
+    Derive,
+    /// A configuration option, e.g. `#[cfg(not(un`.
+    Cfg,
+    /// A feature name, e.g. `#[cfg(feature = "se`.
+    Feature,
+}
+
+const BUILTIN_ATTRIBUTES: &[(&str, &str)] = &[
+    ("allow", "Allow lints"),
+    ("cfg", "Conditional compilation"),
+    ("cfg_attr", "Conditional attribute"),
+    ("cold", "Unlikely to be called"),
+    ("crate_name", "Name of the crate"),
+    ("crate_type", "Type of the crate"),
+    ("deny", "Deny lints"),
+    ("deprecated", "Deprecate an item"),
+    ("derive", "Derive trait implementations"),
+    ("doc", "Documentation"),
+    ("export_name", "Symbol name of a function or static"),
+    ("forbid", "Forbid lints"),
+    ("ignore", "Ignore a test"),
+    ("inline", "Inlining hint"),
+    ("link", "Link a native library"),
+    ("link_name", "Symbol name of a foreign item"),
+    ("macro_export", "Export a macro"),
+    ("macro_use", "Import macros"),
+    ("must_use", "Warn when unused"),
+    ("no_main", "No `main` function"),
+    ("no_mangle", "Don't mangle the symbol name"),
+    ("no_std", "Don't link the standard library"),
+    ("path", "Path of a module file"),
+    ("proc_macro_derive", "Custom derive"),
+    ("recursion_limit", "Recursion limit of the compiler"),
+    ("repr", "Data layout"),
+    ("should_panic", "Test passes by panicking"),
+    ("test", "Test function"),
+    ("warn", "Warn about lints"),
+];
+
+const BUILTIN_DERIVES: &[&str] = &[
+    "Clone",
+    "Copy",
+    "Debug",
+    "Default",
+    "Eq",
+    "Hash",
+    "Ord",
+    "PartialEq",
+    "PartialOrd",
+];
+
+const CFG_OPTIONS: &[(&str, &str)] = &[
+    ("all", "All of the predicates hold"),
+    ("any", "Any of the predicates holds"),
+    ("debug_assertions", "Compiled with debug assertions"),
+    ("feature", "A feature of the package is enabled"),
+    ("not", "The predicate doesn't hold"),
+    ("target_arch", "Architecture of the target, e.g. \"x86_64\""),
+    ("target_endian", "\"little\" or \"big\""),
+    ("target_env", "ABI of the target, e.g. \"gnu\""),
+    ("target_family", "\"unix\" or \"windows\""),
+    ("target_os", "Operating system of the target, e.g. \"linux\""),
+    ("target_pointer_width", "\"32\" or \"64\""),
+    ("test", "Compiled as a test"),
+    ("unix", "Unix-like target"),
+    ("windows", "Windows target"),
+];
+
+/// The derive macros of the crates in the analysis data: those defined by
+/// their `#[proc_macro_derive]` functions and those they re-export, e.g.
+/// `failure::Fail` from `failure_derive`. Also keeps the functions of
+/// attribute macros (`#[proc_macro_attribute]`), for going to their
+/// definitions.
+pub struct DeriveMacros {
+    by_crate: Mutex<HashMap<String, CrateDerives>>,
+}
+
+struct CrateDerives {
+    defined: Vec<MacroFunction>,
+    // Imported names, with the crate they are imported from if known (it isn't
+    // for glob imports).
+    imported: Vec<(String, Option<String>)>,
+    external_crates: Vec<String>,
+}
+
+// A function defining a procedural macro.
+struct MacroFunction {
+    // The name the macro is used by: the one in `proc_macro_derive(..)`, or
+    // that of the function for an attribute macro.
+    name: String,
+    derive: bool,
+    function: String,
+    qualname: String,
+}
+
+impl DeriveMacros {
+    /// Construct without any crate known.
+    pub fn new() -> DeriveMacros {
+        DeriveMacros {
+            by_crate: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record the derive macros of the crates in `analysis`, replacing what
+    /// was known about these crates.
+    pub fn record(&self, analysis: &[Analysis]) {
+        let mut by_crate = self.by_crate.lock().unwrap();
+        for crate_analysis in analysis {
+            let prelude = match crate_analysis.prelude {
+                Some(ref prelude) => prelude,
+                None => continue,
+            };
+            let external: HashMap<u32, &str> = prelude.external_crates.iter().map(|c| (c.num, &*c.id.name)).collect();
+            let defined = crate_analysis.defs.iter().flat_map(|def| {
+                def.attributes.iter().filter_map(move |attribute| {
+                    let (name, derive) = match derive_name(&attribute.value) {
+                        Some(name) => (name, true),
+                        None if attribute.value == "proc_macro_attribute" => (def.name.clone(), false),
+                        None => return None,
+                    };
+                    Some(MacroFunction { name, derive, function: def.name.clone(), qualname: def.qualname.clone() })
+                })
+            }).collect();
+            let imported = crate_analysis.imports.iter().flat_map(|import| match import.kind {
+                ImportKind::Use => {
+                    let krate = import.ref_id.and_then(|id| external.get(&id.krate)).map(|name| name.to_string());
+                    vec![(import.name.clone(), krate)]
+                }
+                // The value of a glob import lists the names it imports.
+                ImportKind::GlobUse => import.value.split(", ").map(|name| (name.to_owned(), None)).collect(),
+                ImportKind::ExternCrate => vec![],
+            }).collect();
+            by_crate.insert(prelude.crate_id.name.clone(), CrateDerives {
+                defined,
+                imported,
+                external_crates: external.values().map(|name| name.to_string()).collect(),
+            });
+        }
+    }
+
+    /// The derive macros crate `crate_name` provides, in order.
+    pub fn provided_by(&self, crate_name: &str) -> Vec<String> {
+        let by_crate = self.by_crate.lock().unwrap();
+        let derives = match by_crate.get(crate_name) {
+            Some(derives) => derives,
+            None => return vec![],
+        };
+        let defines = |krate: &str, name: &str| {
+            by_crate.get(krate).map_or(false, |d| d.defined.iter().any(|m| m.derive && m.name == name))
+        };
+        let mut result: Vec<String> = derives.defined.iter().filter(|m| m.derive).map(|m| m.name.clone()).collect();
+        for &(ref name, ref krate) in &derives.imported {
+            let is_derive = match *krate {
+                Some(ref krate) => defines(&**krate, &**name),
+                None => derives.external_crates.iter().any(|krate| defines(&**krate, &**name)),
+            };
+            if is_derive {
+                result.push(name.clone());
+            }
+        }
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    /// The functions (their names and qualified names) defining the derive
+    /// macro (if `derive`) or attribute macro used by `path`, e.g.
+    /// `serde::Serialize`. If the path names a crate, those the crate defines
+    /// or re-exports are preferred.
+    pub fn functions(&self, path: &[String], derive: bool) -> Vec<(String, String)> {
+        let name = match path.last() {
+            Some(name) => name,
+            None => return vec![],
+        };
+        let by_crate = self.by_crate.lock().unwrap();
+        // The crates the macro may come from, given the first segment.
+        let sources: Vec<&str> = match by_crate.get(&path[0]) {
+            Some(derives) if path.len() > 1 => {
+                let mut sources = vec![&*path[0]];
+                for &(ref imported, ref krate) in &derives.imported {
+                    match *krate {
+                        Some(ref krate) if imported == name => sources.push(krate),
+                        None if imported == name => sources.extend(derives.external_crates.iter().map(|k| &**k)),
+                        _ => {}
+                    }
+                }
+                sources
+            }
+            _ => vec![],
+        };
+
+        let mut all = vec![];
+        let mut preferred = vec![];
+        for (krate, derives) in by_crate.iter() {
+            for m in derives.defined.iter().filter(|m| m.derive == derive && m.name == *name) {
+                let function = (m.function.clone(), m.qualname.clone());
+                if sources.contains(&&**krate) {
+                    preferred.push(function.clone());
+                }
+                all.push(function);
+            }
+        }
+        if preferred.is_empty() { all } else { preferred }
+    }
+}
+
+// The name of the derive macro of a `proc_macro_derive(Name, attributes(..))`
+// attribute.
+fn derive_name(attribute: &str) -> Option<String> {
+    const PREFIX: &'static str = "proc_macro_derive(";
+    if !attribute.starts_with(PREFIX) {
+        return None;
+    }
+    let name = attribute[PREFIX.len()..].split(|c| c == ',' || c == ')').next()?.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+/// What is being completed at char column `col` of `line`, if it's inside an
+/// attribute, and the part of the word typed so far.
+pub fn attribute_context(line: &str, col: usize) -> Option<(AttributeContext, String)> {
+    let before: String = line.chars().take(col).collect();
+    let hash = before.rfind('#')?;
+    let rest = &before[hash + 1..];
+    let inner = if rest.starts_with("![") {
+        &rest[2..]
+    } else if rest.starts_with('[') {
+        &rest[1..]
+    } else {
+        return None;
+    };
+
+    // The name before each open paren and whether a comma followed it.
+    let mut parens: Vec<(String, bool)> = vec![];
+    let mut word = String::new();
+    let mut string_start = None;
+    for (i, c) in inner.char_indices() {
+        if string_start.is_some() {
+            if c == '"' {
+                string_start = None;
+            }
+            continue;
+        }
+        match c {
+            '"' => string_start = Some(i),
+            '(' => parens.push((word.clone(), false)),
+            ')' => {
+                parens.pop()?;
+            }
+            ']' if parens.is_empty() => return None,
+            ',' => {
+                if let Some(paren) = parens.last_mut() {
+                    paren.1 = true;
+                }
+            }
+            _ => {}
+        }
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+        } else {
+            word.clear();
+        }
+    }
+
+    if let Some(start) = string_start {
+        let lhs = inner[..start].trim_right();
+        if lhs.ends_with('=') && lhs[..lhs.len() - 1].trim_right().ends_with("feature") {
+            return Some((AttributeContext::Feature, inner[start + 1..].to_owned()));
+        }
+        return None;
+    }
+
+    let preceding = inner[..inner.len() - word.len()].trim_right();
+    let context = match parens.last() {
+        None if preceding.is_empty() => AttributeContext::Name,
+        None => return None,
+        Some(_) if !(preceding.ends_with('(') || preceding.ends_with(',')) => return None,
+        Some(&(ref name, _)) if name == "derive" => AttributeContext::Derive,
+        Some(&(ref name, seen_comma)) if name == "cfg_attr" => {
+            if seen_comma {
+                AttributeContext::Name
+            } else {
+                AttributeContext::Cfg
+            }
+        }
+        Some(&(ref name, _)) if name == "cfg" || name == "not" || name == "all" || name == "any" => {
+            if parens[0].0 != "cfg" && parens[0].0 != "cfg_attr" {
+                return None;
+            }
+            AttributeContext::Cfg
+        }
+        Some(_) => return None,
+    };
+    Some((context, word))
+}
+
+/// The completions starting with `prefix` in an attribute `context`. The
+/// features of a package come from its `manifest`, the derives from the
+/// `derive_macros` of the libraries (see `LibNames`) it depends on.
+pub fn attribute_completions(context: AttributeContext,
+                             prefix: &str,
+                             manifest: Option<&Manifest>,
+                             derive_macros: &DeriveMacros,
+                             lib_names: &LibNames)
+                             -> Vec<RankedCompletion> {
+    let candidates: Vec<(String, String, CompletionItemKind)> = match context {
+        AttributeContext::Name => BUILTIN_ATTRIBUTES.iter()
+            .map(|&(name, detail)| (name.to_owned(), detail.to_owned(), CompletionItemKind::Keyword))
+            .collect(),
+        AttributeContext::Derive => {
+            let mut derives: Vec<_> = BUILTIN_DERIVES.iter()
+                .map(|name| (name.to_string(), "std".to_owned(), CompletionItemKind::Interface))
+                .collect();
+            for dependency in manifest.map_or(&[][..], |m| &m.dependencies[..]) {
+                let provided = derive_macros.provided_by(&lib_names.crate_name(&dependency.name));
+                derives.extend(provided.into_iter().map(|name| {
+                    (name, dependency.name.clone(), CompletionItemKind::Interface)
+                }));
+            }
+            derives
+        }
+        AttributeContext::Cfg => CFG_OPTIONS.iter()
+            .map(|&(name, detail)| (name.to_owned(), detail.to_owned(), CompletionItemKind::Keyword))
+            .collect(),
+        AttributeContext::Feature => manifest.map_or(vec![], |m| m.feature_names())
+            .into_iter()
+            .map(|name| (name.to_owned(), "feature".to_owned(), CompletionItemKind::Value))
+            .collect(),
+    };
+
+    candidates.into_iter()
+        .filter(|&(ref name, _, _)| name.starts_with(prefix))
+        .map(|(name, detail, kind)| {
+            let mut item = CompletionItem::new_simple(name, detail);
+            item.kind = Some(kind);
+            RankedCompletion {
+                item,
+                relevance: CompletionRelevance::Item,
+                in_document: false,
+                definition: None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actions::manifest::parse_manifest;
+
+    fn context(line: &str) -> Option<(AttributeContext, String)> {
+        attribute_context(line, line.chars().count())
+    }
+
+    #[test]
+    fn test_attribute_context() {
+        assert_eq!(context("#[inl"), Some((AttributeContext::Name, "inl".to_owned())));
+        assert_eq!(context("    #![no_"), Some((AttributeContext::Name, "no_".to_owned())));
+        assert_eq!(context("#[derive(Debug, Cl"), Some((AttributeContext::Derive, "Cl".to_owned())));
+        assert_eq!(context("#[derive("), Some((AttributeContext::Derive, String::new())));
+        assert_eq!(context("#[cfg(not(un"), Some((AttributeContext::Cfg, "un".to_owned())));
+        assert_eq!(context("#[cfg_attr(te"), Some((AttributeContext::Cfg, "te".to_owned())));
+        assert_eq!(context("#[cfg_attr(test, der"), Some((AttributeContext::Name, "der".to_owned())));
+        assert_eq!(context("#[cfg_attr(test, derive(Ha"), Some((AttributeContext::Derive, "Ha".to_owned())));
+        assert_eq!(context("#[cfg(all(unix, feature = \"se"), Some((AttributeContext::Feature, "se".to_owned())));
+
+        assert_eq!(context("#[derive(Debug)] struct Fo"), None);
+        assert_eq!(context("#[doc = \"foo"), None);
+        assert_eq!(context("#[cfg(target_os = li"), None);
+        assert_eq!(context("#[allow(dead"), None);
+        assert_eq!(context("#[inline] fn fo"), None);
+        assert_eq!(context("let x = foo"), None);
+    }
+
+    #[test]
+    fn test_attribute_completions() {
+        let manifest = parse_manifest("[dependencies]\nserde_derive = \"1.0\"\nrayon = { version = \"0.9\", optional = true }\n\n[features]\nserde-support = []\n");
+        let derive_macros = DeriveMacros::new();
+        let lib_names = LibNames::new();
+        let labels = |context, prefix| -> Vec<String> {
+            attribute_completions(context, prefix, Some(&manifest), &derive_macros, &lib_names)
+                .into_iter()
+                .map(|c| c.item.label)
+                .collect()
+        };
+        // The derives of `serde_derive` aren't known without its analysis data.
+        assert_eq!(labels(AttributeContext::Derive, "De"), ["Debug", "Default"]);
+        assert_eq!(labels(AttributeContext::Cfg, "target_o"), ["target_os"]);
+        assert_eq!(labels(AttributeContext::Feature, ""), ["serde-support", "rayon"]);
+        assert_eq!(labels(AttributeContext::Name, "must"), ["must_use"]);
+    }
+
+    #[test]
+    fn test_macro_functions() {
+        let derive_macros = DeriveMacros::new();
+        let function = |name: &str, derive, function: &str, qualname: &str| MacroFunction {
+            name: name.to_owned(),
+            derive,
+            function: function.to_owned(),
+            qualname: qualname.to_owned(),
+        };
+        {
+            let mut by_crate = derive_macros.by_crate.lock().unwrap();
+            by_crate.insert("serde_derive".to_owned(), CrateDerives {
+                defined: vec![function("Serialize", true, "derive_serialize", "::derive_serialize")],
+                imported: vec![],
+                external_crates: vec![],
+            });
+            by_crate.insert("other_derive".to_owned(), CrateDerives {
+                defined: vec![function("Serialize", true, "serialize", "::serialize"),
+                              function("route", false, "route", "::route")],
+                imported: vec![],
+                external_crates: vec![],
+            });
+            by_crate.insert("serde".to_owned(), CrateDerives {
+                defined: vec![],
+                imported: vec![("Serialize".to_owned(), Some("serde_derive".to_owned()))],
+                external_crates: vec!["serde_derive".to_owned()],
+            });
+        }
+        let path = |path: &str| -> Vec<String> { path.split("::").map(|s| s.to_owned()).collect() };
+        let mut functions = derive_macros.functions(&path("Serialize"), true);
+        functions.sort();
+        assert_eq!(functions, [("derive_serialize".to_owned(), "::derive_serialize".to_owned()),
+                               ("serialize".to_owned(), "::serialize".to_owned())]);
+        assert_eq!(derive_macros.functions(&path("serde::Serialize"), true),
+                   [("derive_serialize".to_owned(), "::derive_serialize".to_owned())]);
+        assert_eq!(derive_macros.functions(&path("route"), false), [("route".to_owned(), "::route".to_owned())]);
+        assert!(derive_macros.functions(&path("route"), true).is_empty());
+    }
+
+    #[test]
+    fn test_derive_name() {
+        assert_eq!(derive_name("proc_macro_derive(Serialize, attributes(serde))"), Some("Serialize".to_owned()));
+        assert_eq!(derive_name("proc_macro_derive(Fail)"), Some("Fail".to_owned()));
+        assert_eq!(derive_name("proc_macro_derive()"), None);
+        assert_eq!(derive_name("inline"), None);
+    }
+}
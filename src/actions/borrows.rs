@@ -0,0 +1,407 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Where values are borrowed and moved, for the experimental
+//! `rustDocument/borrows` request.
+//!
+//! Neither the analysis data nor the JSON messages of the compiler include the
+//! borrow checker's view of the MIR, its findings only show up in its errors.
+//! So borrows and moves are taken from the labelled spans of the borrow
+//! checking errors of the last build ("first mutable borrow occurs here",
+//! "first borrow ends here", "value moved here", ...), and are only known
+//! where the borrow checker complained. A borrow's region ends where the
+//! compiler says the borrow ends (or is last used), if it does.
+//!
+//! A build only has the errors of the crates it compiled, so the facts are
+//! kept by crate (see `BorrowFacts`), and moved along with the text edited
+//! since.
+
+use actions::diagnostics::PathResolver;
+use actions::lifetimes::{matching_close, tokenize, Token, TokenKind};
+use actions::visibility::CrateFiles;
+use serde_json;
+use span;
+use span::compiler::DiagnosticSpan;
+use Span;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The errors of the borrow checker, by code.
+const BORROWCK_CODES: &[&str] = &[
+    "E0373", "E0381", "E0382", "E0384", "E0499", "E0502", "E0503", "E0505", "E0506", "E0507", "E0597", "E0716",
+];
+
+/// A borrow or move reported by the borrow checker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BorrowFact {
+    /// A value is borrowed at `span`, until `end` (if the compiler says).
+    /// Whether it's borrowed mutably is known if the compiler says so.
+    Borrow {
+        span: Span,
+        end: Option<Span>,
+        mutable: Option<bool>,
+        message: String,
+    },
+    /// A value is moved at `span`.
+    Move {
+        span: Span,
+        message: String,
+    },
+}
+
+impl BorrowFact {
+    /// Where the value is borrowed or moved.
+    pub fn span(&self) -> &Span {
+        match *self {
+            BorrowFact::Borrow { ref span, .. } | BorrowFact::Move { ref span, .. } => span,
+        }
+    }
+}
+
+/// The borrow facts of the crates of a project, kept between builds which
+/// only compile some of them.
+pub struct BorrowFacts {
+    // By the root file of their crate, or by their own file if its crate isn't
+    // known (yet). A file in several crates has its facts in each.
+    by_crate: Mutex<HashMap<PathBuf, Vec<BorrowFact>>>,
+}
+
+impl BorrowFacts {
+    /// Construct without any facts.
+    pub fn new() -> BorrowFacts {
+        BorrowFacts {
+            by_crate: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replace the facts of the crates a build compiled with `facts`, those of
+    /// its errors. `compiled` are the root files of these crates, `None` if
+    /// they aren't known, in which case all facts are replaced.
+    pub fn record(&self, facts: Vec<BorrowFact>, compiled: Option<&[PathBuf]>, crate_files: &CrateFiles) {
+        let mut by_crate = self.by_crate.lock().unwrap_or_else(|e| e.into_inner());
+        let mut new_facts: HashMap<PathBuf, Vec<BorrowFact>> = HashMap::new();
+        for fact in facts {
+            let file = fact.span().file.clone();
+            let mut crates: Vec<PathBuf> = crate_files.crates_of(&file).into_iter().collect();
+            if crates.is_empty() {
+                crates.push(file);
+            }
+            for krate in crates {
+                new_facts.entry(krate).or_insert_with(Vec::new).push(fact.clone());
+            }
+        }
+
+        match compiled {
+            Some(compiled) => {
+                // Facts without a known crate can't be told apart, those of
+                // the crates with errors now were compiled too.
+                by_crate.retain(|krate, _| {
+                    crate_files.is_crate_root(krate) && !compiled.contains(krate) && !new_facts.contains_key(krate)
+                });
+            }
+            None => by_crate.clear(),
+        }
+        by_crate.extend(new_facts);
+    }
+
+    /// The facts in `file`.
+    pub fn in_file(&self, file: &Path) -> Vec<BorrowFact> {
+        let by_crate = self.by_crate.lock().unwrap_or_else(|e| e.into_inner());
+        let mut facts: Vec<BorrowFact> = vec![];
+        for fact in by_crate.values().flat_map(|facts| facts) {
+            if fact.span().file == file && !facts.contains(fact) {
+                facts.push(fact.clone());
+            }
+        }
+        facts
+    }
+
+    /// Move the facts in `file` after `text` replaced `range` in it. Facts
+    /// where the text was edited are dropped, and so are the ends of borrows.
+    pub fn edited(&self, file: &Path, range: span::Range<span::ZeroIndexed>, text: &str) {
+        let mut by_crate = self.by_crate.lock().unwrap_or_else(|e| e.into_inner());
+        for facts in by_crate.values_mut() {
+            let old_facts = ::std::mem::replace(facts, vec![]);
+            facts.extend(old_facts.into_iter().filter_map(|fact| fact.edited(file, range, text)));
+        }
+    }
+
+    /// Drop the facts in `file`, e.g. because all its text was replaced.
+    pub fn forget_file(&self, file: &Path) {
+        let mut by_crate = self.by_crate.lock().unwrap_or_else(|e| e.into_inner());
+        for facts in by_crate.values_mut() {
+            facts.retain(|fact| fact.span().file != file);
+        }
+    }
+}
+
+impl BorrowFact {
+    // The fact after `text` replaced `range` in `file`, `None` if it was
+    // edited.
+    fn edited(self, file: &Path, range: span::Range<span::ZeroIndexed>, text: &str) -> Option<BorrowFact> {
+        if self.span().file != file {
+            return Some(self);
+        }
+        Some(match self {
+            BorrowFact::Borrow { span, end, mutable, message } => BorrowFact::Borrow {
+                span: edited_span(&span, range, text)?,
+                end: end.and_then(|end| if end.file == file { edited_span(&end, range, text) } else { Some(end) }),
+                mutable,
+                message,
+            },
+            BorrowFact::Move { span, message } => BorrowFact::Move {
+                span: edited_span(&span, range, text)?,
+                message,
+            },
+        })
+    }
+}
+
+// `span` after `text` replaced `range`, `None` if they overlap.
+fn edited_span(span: &Span, range: span::Range<span::ZeroIndexed>, text: &str) -> Option<Span> {
+    if row_col(range.end()) <= row_col(span.range.start()) {
+        Some(Span::from_positions(moved_position(span.range.start(), range, text),
+                                  moved_position(span.range.end(), range, text),
+                                  span.file.clone()))
+    } else if row_col(span.range.end()) <= row_col(range.start()) {
+        Some(span.clone())
+    } else {
+        None
+    }
+}
+
+// `position`, which is after `range`, after `text` replaced `range`.
+fn moved_position(position: span::Position<span::ZeroIndexed>,
+                  range: span::Range<span::ZeroIndexed>,
+                  text: &str)
+                  -> span::Position<span::ZeroIndexed> {
+    let added_rows = text.matches('\n').count() as u32;
+    let last_line = text.rsplit('\n').next().unwrap_or("").chars().count() as u32;
+    let row = position.row.0 - (range.row_end.0 - range.row_start.0) + added_rows;
+    let col = if position.row != range.row_end {
+        position.col.0
+    } else if added_rows == 0 {
+        range.col_start.0 + last_line + position.col.0 - range.col_end.0
+    } else {
+        last_line + position.col.0 - range.col_end.0
+    };
+    span::Position::new(span::Row::new_zero_indexed(row), span::Column::new_zero_indexed(col))
+}
+
+fn row_col(position: span::Position<span::ZeroIndexed>) -> (u32, u32) {
+    (position.row.0, position.col.0)
+}
+
+#[derive(Debug, Deserialize)]
+struct CompilerMessage {
+    code: Option<CompilerMessageCode>,
+    spans: Vec<DiagnosticSpan>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompilerMessageCode {
+    code: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Label {
+    Borrow(Option<bool>),
+    BorrowEnd,
+    Move,
+    Other,
+}
+
+/// The borrows and moves in the borrow checking errors among the JSON compiler
+/// `messages` of a build, without duplicates.
+pub fn borrow_facts(messages: &[String], paths: &mut PathResolver) -> Vec<BorrowFact> {
+    let mut facts = vec![];
+    for message in messages {
+        let message = match serde_json::from_str::<CompilerMessage>(message) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if !message.code.map_or(false, |c| BORROWCK_CODES.contains(&&*c.code)) {
+            continue;
+        }
+        for fact in message_facts(&message.spans, paths) {
+            if !facts.contains(&fact) {
+                facts.push(fact);
+            }
+        }
+    }
+    facts
+}
+
+fn message_facts(spans: &[DiagnosticSpan], paths: &mut PathResolver) -> Vec<BorrowFact> {
+    let mut span_of = |span: &DiagnosticSpan| {
+        let mut span = span.rls_span().zero_indexed();
+        span.file = paths.resolve(&span.file);
+        span
+    };
+    let labelled: Vec<(&DiagnosticSpan, &str)> = spans.iter()
+        .filter_map(|s| s.label.as_ref().map(|label| (s, &**label)))
+        .collect();
+    let ends: Vec<(&DiagnosticSpan, &str)> = labelled.iter()
+        .filter(|&&(_, label)| classify(label) == Label::BorrowEnd)
+        .cloned()
+        .collect();
+    let borrow_count = labelled.iter().filter(|&&(_, label)| match classify(label) {
+        Label::Borrow(_) => true,
+        _ => false,
+    }).count();
+
+    let mut facts = vec![];
+    for &(span, label) in &labelled {
+        match classify(label) {
+            Label::Borrow(mutable) => {
+                // "first mutable borrow occurs here" ends at "first borrow
+                // ends here", a single borrow at the only end.
+                let end = ends.iter()
+                    .find(|&&(_, end)| first_word(end) == first_word(label))
+                    .or_else(|| if borrow_count == 1 { ends.first() } else { None })
+                    .map(|&(end, _)| span_of(end));
+                facts.push(BorrowFact::Borrow {
+                    span: span_of(span),
+                    end,
+                    mutable,
+                    message: label.to_owned(),
+                });
+            }
+            Label::Move => facts.push(BorrowFact::Move {
+                span: span_of(span),
+                message: label.to_owned(),
+            }),
+            Label::BorrowEnd | Label::Other => {}
+        }
+    }
+    facts
+}
+
+// What a label of a borrow checking error says happens at its span.
+fn classify(label: &str) -> Label {
+    if label.contains("ends here") || label.contains("later used") || label.contains("needs to live until here") ||
+       label.contains("dropped here while still borrowed") {
+        Label::BorrowEnd
+    } else if label.contains("moved here") || label.contains("move out of") || label.contains("moved into closure") {
+        Label::Move
+    } else if label.contains("borrow") && (label.contains("occurs here") || label.contains("borrowed here")) {
+        let mutable = if label.contains("immutable") {
+            Some(false)
+        } else if label.contains("mutable") {
+            Some(true)
+        } else {
+            None
+        };
+        Label::Borrow(mutable)
+    } else {
+        Label::Other
+    }
+}
+
+fn first_word(label: &str) -> &str {
+    label.split_whitespace().next().unwrap_or("")
+}
+
+/// The rows of the innermost function around `row` in `text`, from its `fn` to
+/// the `}` closing its body.
+pub fn function_rows(text: &str, row: usize) -> Option<(usize, usize)> {
+    let tokens = tokenize(text);
+    let mut result = None;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.row > row {
+            break;
+        }
+        if !token.is(TokenKind::Ident, "fn") {
+            continue;
+        }
+        let close = match body_open(&tokens, i + 1).and_then(|open| matching_close(&tokens, open)) {
+            Some(close) => close,
+            None => continue,
+        };
+        // Functions further down which still contain `row` are nested.
+        if tokens[close].row >= row {
+            result = Some((token.row, tokens[close].row));
+        }
+    }
+    result
+}
+
+// The `{` opening the body of the function whose signature starts at `from`.
+// `None` if it has none, e.g. in a trait, or it's a `fn` pointer type.
+fn body_open(tokens: &[Token], from: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(from) {
+        match token.text {
+            "(" | "[" => depth += 1,
+            ")" | "]" if depth == 0 => return None,
+            ")" | "]" => depth -= 1,
+            ";" | "=" | "," if depth == 0 => return None,
+            "{" if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("first mutable borrow occurs here"), Label::Borrow(Some(true)));
+        assert_eq!(classify("immutable borrow occurs here"), Label::Borrow(Some(false)));
+        assert_eq!(classify("borrow of `x` occurs here"), Label::Borrow(None));
+        assert_eq!(classify("first borrow ends here"), Label::BorrowEnd);
+        assert_eq!(classify("mutable borrow later used here"), Label::BorrowEnd);
+        assert_eq!(classify("value moved here"), Label::Move);
+        assert_eq!(classify("move out of `x` occurs here"), Label::Move);
+        assert_eq!(classify("value used here after move"), Label::Other);
+    }
+
+    #[test]
+    fn test_edited_span() {
+        let span = |row_start, col_start, row_end, col_end| {
+            Span::from_positions(span::Position::new(span::Row::new_zero_indexed(row_start),
+                                                     span::Column::new_zero_indexed(col_start)),
+                                 span::Position::new(span::Row::new_zero_indexed(row_end),
+                                                     span::Column::new_zero_indexed(col_end)),
+                                 PathBuf::from("foo.rs"))
+        };
+        let borrow = span(2, 8, 2, 14);
+        // Before or after the span, on the same row or not.
+        assert_eq!(edited_span(&borrow, span(0, 0, 0, 0).range, "\n\n"), Some(span(4, 8, 4, 14)));
+        assert_eq!(edited_span(&borrow, span(2, 0, 2, 4).range, "x"), Some(span(2, 5, 2, 11)));
+        assert_eq!(edited_span(&borrow, span(1, 3, 2, 2).range, "ab"), Some(span(1, 11, 1, 17)));
+        assert_eq!(edited_span(&borrow, span(2, 14, 2, 14).range, ";\n"), Some(borrow.clone()));
+        assert_eq!(edited_span(&borrow, span(3, 0, 5, 0).range, ""), Some(borrow.clone()));
+        // The span was edited.
+        assert_eq!(edited_span(&borrow, span(2, 9, 2, 10).range, "y"), None);
+        assert_eq!(edited_span(&borrow, span(2, 0, 2, 20).range, ""), None);
+    }
+
+    #[test]
+    fn test_function_rows() {
+        let text = "trait Foo {\n\
+                    \x20   fn foo(&self);\n\
+                    }\n\
+                    fn bar(f: fn(u32) -> u32, x: [u8; 2]) {\n\
+                    \x20   let s = \"}\";\n\
+                    \x20   fn baz() {\n\
+                    \x20   }\n\
+                    }\n";
+        assert_eq!(function_rows(text, 1), None);
+        assert_eq!(function_rows(text, 4), Some((3, 7)));
+        assert_eq!(function_rows(text, 6), Some((5, 6)));
+        assert_eq!(function_rows(text, 7), Some((3, 7)));
+    }
+}
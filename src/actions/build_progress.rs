@@ -0,0 +1,197 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reporting the crates a build compiles as work done progress.
+//!
+//! If the client supports `window.workDoneProgress`, a token is created with
+//! `window/workDoneProgress/create` when the first crate of a build starts
+//! compiling, and each crate is reported in a `$/progress` notification for it
+//! (e.g. "14/87 serde"), until the build is done. Unlike the `compiling` field
+//! of `rustDocument/serverStatus`, this doesn't need the client to opt in.
+
+use actions::client::PendingRequests;
+use build::BuildProgress;
+use lsp_data::{NotificationMessage, NotificationParams, NumberOrString, ProgressParams, WorkDoneProgress,
+               WorkDoneProgressCreateParams, NOTIFICATION_PROGRESS};
+use server::Output;
+
+use serde_json;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The title of the progress of a build.
+const TITLE: &'static str = "Building";
+
+enum State {
+    // No build is reported.
+    Idle,
+    // The client didn't create a token for the running build, its crates
+    // aren't reported.
+    Declined,
+    // The crates of the running build are reported for this token.
+    Reporting(NumberOrString),
+}
+
+/// Reports the progress of builds, for a client which supports work done
+/// progress.
+pub struct BuildProgressReporter {
+    pending: Arc<PendingRequests>,
+    next_token: AtomicUsize,
+    state: Mutex<State>,
+}
+
+impl BuildProgressReporter {
+    /// Report builds using `pending` to create the tokens.
+    pub fn new(pending: Arc<PendingRequests>) -> BuildProgressReporter {
+        BuildProgressReporter {
+            pending,
+            next_token: AtomicUsize::new(1),
+            state: Mutex::new(State::Idle),
+        }
+    }
+
+    /// Report that a crate started compiling, beginning the progress of the
+    /// build if it is the first one.
+    pub fn report<O: Output>(&self, progress: &BuildProgress, out: &O) {
+        let mut state = self.state.lock().unwrap();
+        let value = match *state {
+            State::Declined => return,
+            State::Reporting(_) => WorkDoneProgress::Report {
+                message: Some(message(progress)),
+                percentage: Some(percentage(progress)),
+            },
+            State::Idle => {
+                let token = NumberOrString::String(format!("rls/build/{}",
+                                                           self.next_token.fetch_add(1, Ordering::SeqCst)));
+                let params = WorkDoneProgressCreateParams { token: token.clone() };
+                if let Err(e) = self.pending.request("window/workDoneProgress/create", params, out) {
+                    debug!("could not create a work done progress token: {:?}", e);
+                    *state = State::Declined;
+                    return;
+                }
+                *state = State::Reporting(token);
+                WorkDoneProgress::Begin {
+                    title: TITLE.to_owned(),
+                    message: Some(message(progress)),
+                    percentage: Some(percentage(progress)),
+                }
+            }
+        };
+        if let State::Reporting(ref token) = *state {
+            notify(token.clone(), value, out);
+        }
+    }
+
+    /// Report that the build is done, if its progress began.
+    pub fn finish<O: Output>(&self, out: &O) {
+        let mut state = self.state.lock().unwrap();
+        if let State::Reporting(ref token) = *state {
+            notify(token.clone(), WorkDoneProgress::End { message: None }, out);
+        }
+        *state = State::Idle;
+    }
+}
+
+fn message(progress: &BuildProgress) -> String {
+    format!("{}/{} {}", progress.index, progress.total, progress.crate_name)
+}
+
+fn percentage(progress: &BuildProgress) -> u32 {
+    // The crate just started, so it isn't done yet.
+    ((progress.index - 1) * 100 / progress.total) as u32
+}
+
+fn notify<O: Output>(token: NumberOrString, value: WorkDoneProgress, out: &O) {
+    out.notify(NotificationMessage::new(
+        NOTIFICATION_PROGRESS,
+        Some(NotificationParams::Progress(ProgressParams {
+            token,
+            value: serde_json::to_value(&value).unwrap(),
+        })),
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use jsonrpc_core as jsonrpc;
+    use serde_json::Value;
+
+    // Records the messages and answers the requests straight away.
+    #[derive(Clone)]
+    struct Responder {
+        pending: Arc<PendingRequests>,
+        sent: Arc<Mutex<Vec<String>>>,
+        accept: bool,
+    }
+
+    impl Output for Responder {
+        fn response(&self, output: String) {
+            let is_request = output.contains(r#""method":"window/workDoneProgress/create""#);
+            self.sent.lock().unwrap().push(output);
+            if is_request {
+                let result = if self.accept { Ok(Value::Null) } else { Err(jsonrpc::Error::internal_error()) };
+                assert!(self.pending.handle_response(1, result));
+            }
+        }
+
+        fn provide_id(&self) -> u32 {
+            1
+        }
+    }
+
+    fn report_build(accept: bool) -> Vec<String> {
+        let out = Responder {
+            pending: Arc::new(PendingRequests::new()),
+            sent: Arc::new(Mutex::new(vec![])),
+            accept,
+        };
+        let reporter = BuildProgressReporter::new(out.pending.clone());
+        reporter.report(&progress("serde", 1, 2), &out);
+        reporter.report(&progress("foo", 2, 2), &out);
+        reporter.finish(&out);
+        let sent = out.sent.lock().unwrap().clone();
+        sent
+    }
+
+    fn progress(crate_name: &str, index: usize, total: usize) -> BuildProgress {
+        BuildProgress { crate_name: crate_name.to_owned(), index, total }
+    }
+
+    #[test]
+    fn test_build_progress() {
+        let sent = report_build(true);
+        assert_eq!(sent.len(), 4);
+        assert!(sent[0].contains(r#""method":"window/workDoneProgress/create""#));
+        assert!(sent[1].contains(r#""method":"$/progress""#));
+        assert!(sent[1].contains(r#""kind":"begin""#));
+        assert!(sent[2].contains(r#""message":"2/2 foo""#));
+        assert!(sent[3].contains(r#""kind":"end""#));
+
+        // Nothing is reported if the client doesn't create the token.
+        let sent = report_build(false);
+        assert_eq!(sent.len(), 1);
+    }
+
+    #[test]
+    fn test_work_done_progress() {
+        let value = serde_json::to_value(&WorkDoneProgress::Begin {
+            title: TITLE.to_owned(),
+            message: Some(message(&progress("serde", 14, 87))),
+            percentage: Some(percentage(&progress("serde", 14, 87))),
+        }).unwrap();
+        assert_eq!(value, json!({ "kind": "begin", "title": "Building", "message": "14/87 serde", "percentage": 14 }));
+
+        let value = serde_json::to_value(&WorkDoneProgress::End { message: None }).unwrap();
+        assert_eq!(value, json!({ "kind": "end" }));
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fallback documentation lookup in the HTML generated by `cargo doc`, for
+//! items where neither racer nor the analysis data have any docs (typically
+//! dependencies, for which we only have public-only save-analysis data).
+
+use actions::package_dir_for_file;
+use analysis::DefKind;
+use build::{default_rls_target_dir, rls_target_dir};
+use config::Config;
+#[cfg(feature = "racer")]
+use racer::{Match, MatchType};
+use serde_json::Value;
+
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// How deep below the crate's directory pages are searched for when the module
+// of an item isn't known.
+const MAX_MODULE_DEPTH: usize = 8;
+
+/// The directories `cargo doc` may have written the docs of the project at
+/// `project_dir` to: `doc` in the configured `target_dir`, then `target/doc`
+/// next to the root manifest of its workspace.
+pub fn doc_dirs(project_dir: &Path, config: &Config) -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if config.target_dir.is_some() {
+        dirs.push(rls_target_dir(project_dir, config).join("doc"));
+    }
+    // The RLS builds in `target/rls`, `cargo doc` in `target`.
+    if let Some(target) = default_rls_target_dir(project_dir).parent() {
+        dirs.push(target.join("doc"));
+    }
+    dirs
+}
+
+/// Look up the documentation of an item in the first of `doc_dirs` which has
+/// a page for it. `module_path` is the path of the module the item is in,
+/// below the crate's root; if it's empty, the crate's modules are searched.
+pub fn find_docs(doc_dirs: &[PathBuf],
+                 crate_name: &str,
+                 module_path: &[&str],
+                 item_kind: &str,
+                 item_name: &str)
+                 -> Option<String> {
+    let file_name = format!("{}.{}.html", item_kind, item_name);
+    let page = doc_dirs.iter().filter_map(|dir| {
+        let crate_dir = dir.join(crate_name);
+        if module_path.is_empty() {
+            find_page(&crate_dir, &file_name)
+        } else {
+            let page = module_path.iter().fold(crate_dir, |dir, m| dir.join(m)).join(&file_name);
+            if page.is_file() { Some(page) } else { None }
+        }
+    }).next()?;
+
+    let mut html = String::new();
+    File::open(&page).ok()?.read_to_string(&mut html).ok()?;
+    trace!("cargo_doc: found docs page {:?}", page);
+
+    extract_docblock(&html)
+}
+
+/// The path of the module an item is in below its crate's root, given its
+/// qualified name from the analysis data (e.g. `::de::value::Error` gives
+/// `["de", "value"]`). `None` for items rustdoc has no page for at a module
+/// path, such as methods.
+pub fn module_path<'a>(qualname: &'a str, crate_name: &str) -> Option<Vec<&'a str>> {
+    if qualname.contains('<') {
+        return None;
+    }
+    let mut path: Vec<&str> = qualname.trim_left_matches("::").split("::").collect();
+    path.pop();
+    if !qualname.starts_with("::") && path.first() == Some(&crate_name) {
+        path.remove(0);
+    }
+    Some(path)
+}
+
+// Search the pages below `crate_dir` for `file_name`, modules closer to the
+// root first.
+fn find_page(crate_dir: &Path, file_name: &str) -> Option<PathBuf> {
+    let mut dirs = VecDeque::new();
+    dirs.push_back((crate_dir.to_owned(), 0));
+    while let Some((dir, depth)) = dirs.pop_front() {
+        let page = dir.join(file_name);
+        if page.is_file() {
+            return Some(page);
+        }
+        if depth == MAX_MODULE_DEPTH {
+            continue;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut subdirs: Vec<PathBuf> = entries.filter_map(|e| e.ok())
+            .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .map(|e| e.path())
+            .collect();
+        subdirs.sort();
+        dirs.extend(subdirs.into_iter().map(|d| (d, depth + 1)));
+    }
+    None
+}
+
+/// Guesses the name of the crate that `file` belongs to, by looking for the
+/// nearest `Cargo.toml`. For crates from the registry the version suffix of the
+/// containing directory is removed, e.g. `serde-1.0.18` becomes `serde`.
+pub fn crate_name_for_file(file: &Path) -> Option<String> {
+    let dir_name = package_dir_for_file(file)?.file_name()?.to_str()?;
+
+    let name = match dir_name.rfind('-') {
+        Some(i) if dir_name[i + 1..].chars().next().map_or(false, |c| c.is_digit(10)) => &dir_name[..i],
+        _ => dir_name,
+    };
+    Some(name.replace('-', "_"))
+}
+
+/// Data to attach to a completion item, so that its documentation can be
+/// looked up when the item is resolved.
+#[cfg(feature = "racer")]
+pub fn completion_data(m: &Match) -> Option<Value> {
+    let crate_name = crate_name_for_file(&m.filepath)?;
+    let kind = page_prefix_for_match_type(m.mtype)?;
+    Some(json!({
+        "crate": crate_name,
+        "kind": kind,
+        "name": m.matchstr,
+    }))
+}
+
+/// Look up the documentation for a completion item, given its `completion_data`.
+pub fn docs_from_completion_data(doc_dirs: &[PathBuf], data: &Value) -> Option<String> {
+    let crate_name = data.get("crate")?.as_str()?;
+    let kind = data.get("kind")?.as_str()?;
+    let name = data.get("name")?.as_str()?;
+    find_docs(doc_dirs, crate_name, &[], kind, name)
+}
+
+/// The prefix rustdoc uses for the page of an item of the given racer kind.
+#[cfg(feature = "racer")]
+pub fn page_prefix_for_match_type(m: MatchType) -> Option<&'static str> {
+    match m {
+        MatchType::Struct => Some("struct"),
+        MatchType::Enum => Some("enum"),
+        MatchType::Function => Some("fn"),
+        MatchType::Macro => Some("macro"),
+        MatchType::Trait => Some("trait"),
+        MatchType::Type => Some("type"),
+        MatchType::Const => Some("constant"),
+        MatchType::Static => Some("static"),
+        _ => None,
+    }
+}
+
+/// The prefix rustdoc uses for the page of an item of the given def kind.
+pub fn page_prefix_for_def_kind(k: DefKind) -> Option<&'static str> {
+    match k {
+        DefKind::Struct => Some("struct"),
+        DefKind::Enum => Some("enum"),
+        DefKind::Union => Some("union"),
+        DefKind::Function => Some("fn"),
+        DefKind::Macro => Some("macro"),
+        DefKind::Trait => Some("trait"),
+        DefKind::Type => Some("type"),
+        DefKind::Const => Some("constant"),
+        DefKind::Static => Some("static"),
+        _ => None,
+    }
+}
+
+// The item docs are the first `docblock` div on the page; we strip any markup
+// and return the plain text.
+fn extract_docblock(html: &str) -> Option<String> {
+    let start = html.find("class='docblock'").or_else(|| html.find("class=\"docblock\""))?;
+    let start = start + html[start..].find('>')? + 1;
+
+    // Find the matching closing tag, accounting for nested divs.
+    let mut depth = 1;
+    let mut pos = start;
+    while depth > 0 {
+        let open = html[pos..].find("<div").map(|i| i + pos);
+        let close = html[pos..].find("</div>").map(|i| i + pos)?;
+        match open {
+            Some(open) if open < close => {
+                depth += 1;
+                pos = open + "<div".len();
+            }
+            _ => {
+                depth -= 1;
+                pos = close + "</div>".len();
+            }
+        }
+    }
+    let block = &html[start..pos - "</div>".len()];
+
+    let text = unescape(&strip_tags(block));
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_owned())
+    }
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => result.push(c),
+            _ => {}
+        }
+    }
+    result
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract_docblock() {
+        let html = "<h1>Struct foo::Foo</h1><div class='docblock'><p>A <code>Foo</code> &amp; \
+                    a bar.</p><div class='note'>Note</div></div><div class='docblock'>Other</div>";
+        assert_eq!(extract_docblock(html), Some("A Foo & a bar.Note".to_owned()));
+        assert_eq!(extract_docblock("<div class='docblock'></div>"), None);
+        assert_eq!(extract_docblock("<p>No docs</p>"), None);
+    }
+
+    #[test]
+    fn test_module_path() {
+        assert_eq!(module_path("::de::value::Error", "serde"), Some(vec!["de", "value"]));
+        assert_eq!(module_path("serde::Serialize", "serde"), Some(vec![]));
+        assert_eq!(module_path("::Foo", "foo"), Some(vec![]));
+        assert_eq!(module_path("<Foo as Bar>::baz", "foo"), None);
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Which files changed since the analysis data was last reloaded, for
+//! `rustWorkspace/changedSinceBuild`.
+//!
+//! Files are edited in the client (they are dirty in the build queue until a
+//! build includes them) or modified on disk after the last reload. Each file is
+//! attributed to the package of the closest `Cargo.toml` above it.
+
+use lsp_data::{ChangedFile, DocumentPaths, FileChange};
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The changed files and the packages they belong to. `edited` files are
+/// reported as such even if they were also saved; `sources` are checked for
+/// modifications on disk after `since`, if the analysis was ever loaded.
+pub fn changed_files(edited: &[PathBuf],
+                     sources: &[PathBuf],
+                     since: Option<SystemTime>,
+                     project_dir: &Path,
+                     documents: &DocumentPaths)
+                     -> (Vec<ChangedFile>, Vec<String>) {
+    let mut changes: Vec<(&Path, FileChange)> = edited.iter().map(|f| (&**f, FileChange::Edited)).collect();
+    if let Some(since) = since {
+        for file in sources {
+            if edited.contains(file) {
+                continue;
+            }
+            let modified = fs::metadata(file).and_then(|m| m.modified()).ok();
+            if modified.map_or(false, |m| m > since) {
+                changes.push((file.as_path(), FileChange::ModifiedOnDisk));
+            }
+        }
+    }
+
+    let mut packages = HashMap::new();
+    let mut dirty_crates = BTreeSet::new();
+    let files = changes.into_iter().map(|(file, change)| {
+        let crate_name = package_of(file, project_dir, &mut packages);
+        if let Some(ref name) = crate_name {
+            dirty_crates.insert(name.clone());
+        }
+        ChangedFile {
+            uri: documents.uri(file).unwrap(),
+            change,
+            crate_name,
+        }
+    }).collect();
+    (files, dirty_crates.into_iter().collect())
+}
+
+// The package of the closest manifest above `file`, as a crate name. Manifests
+// are only looked for within the project, and read once.
+fn package_of(file: &Path, project_dir: &Path, packages: &mut HashMap<PathBuf, Option<String>>) -> Option<String> {
+    let mut dir = file.parent();
+    while let Some(d) = dir {
+        if !d.starts_with(project_dir) {
+            return None;
+        }
+        let package = packages.entry(d.to_owned()).or_insert_with(|| {
+            let mut manifest = String::new();
+            File::open(d.join("Cargo.toml")).ok()?.read_to_string(&mut manifest).ok()?;
+            parse_package_name(&manifest)
+        });
+        if package.is_some() {
+            return package.clone();
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+// The name of the `[package]` of a manifest, `None` for a virtual manifest.
+fn parse_package_name(manifest: &str) -> Option<String> {
+    let mut in_package = false;
+    for line in manifest.lines().map(|l| l.trim()) {
+        if line.starts_with('[') {
+            in_package = line == "[package]" || line == "[project]";
+        } else if in_package && line.starts_with("name") {
+            let value = line["name".len()..].trim_left().trim_left_matches('=').trim();
+            return Some(value.trim_matches('"').replace('-', "_"));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_package_name() {
+        let manifest = "[package]\nname = \"rls-vfs\"\nversion = \"0.4.2\"\n\n[dependencies]\nname = \"x\"\n";
+        assert_eq!(parse_package_name(manifest), Some("rls_vfs".to_owned()));
+        let manifest = "[dependencies]\nfoo = \"1\"\n\n[project]\nname=\"bar\"\n";
+        assert_eq!(parse_package_name(manifest), Some("bar".to_owned()));
+        assert_eq!(parse_package_name("[workspace]\nmembers = [\"a\"]\n"), None);
+    }
+}
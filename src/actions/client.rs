@@ -0,0 +1,163 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Requests sent by the RLS to the client, e.g. `workspace/applyEdit`.
+//!
+//! The responses arrive on the main loop like any other message and are passed
+//! to `PendingRequests::handle_response`, the thread which sent the request
+//! waits for them. So requests to the client must never be sent from the main
+//! loop itself, only from the threads spawned by actions.
+
+use jsonrpc_core as jsonrpc;
+use lsp_data::{ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, RequestMessage, WorkspaceEdit};
+use server::Output;
+
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+
+// How long to wait for the client to respond. Applying an edit can involve
+// asking the user, so this is generous.
+const RESPONSE_TIMEOUT: u64 = 30_000;
+
+/// Why a request to the client failed.
+#[derive(Debug, PartialEq)]
+pub enum ClientError {
+    /// The client did not respond in time.
+    Timeout,
+    /// The client responded with an error.
+    Failed(jsonrpc::Error),
+    /// The result of the request was not what we expected.
+    BadResponse,
+    /// The client responded, but did not apply the edit.
+    NotApplied,
+}
+
+/// The requests sent to the client which are still waiting for a response.
+pub struct PendingRequests {
+    senders: Mutex<HashMap<u64, Sender<Result<Value, jsonrpc::Error>>>>,
+}
+
+impl PendingRequests {
+    /// Construct an empty set of requests.
+    pub fn new() -> PendingRequests {
+        PendingRequests {
+            senders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sends a request to the client and waits for its result.
+    pub fn request<O, P>(&self, method: &str, params: P, out: &O) -> Result<Value, ClientError>
+        where O: Output,
+              P: Debug + Serialize,
+    {
+        let id = out.provide_id();
+        let (sender, receiver) = channel();
+        // Registered before sending, so the response can't beat us to it.
+        self.senders.lock().unwrap().insert(id as u64, sender);
+
+        let output = serde_json::to_string(&RequestMessage::new(id, method.to_owned(), params)).unwrap();
+        out.response(output);
+
+        let result = receiver.recv_timeout(Duration::from_millis(RESPONSE_TIMEOUT));
+        self.senders.lock().unwrap().remove(&(id as u64));
+        match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(ClientError::Failed(e)),
+            Err(_) => {
+                debug!("no response from the client to `{}` ({})", method, id);
+                Err(ClientError::Timeout)
+            }
+        }
+    }
+
+    /// Passes the response to the request with the given id on to the thread
+    /// waiting for it. Returns false if nothing was waiting, e.g. because it
+    /// gave up already.
+    pub fn handle_response(&self, id: u64, result: Result<Value, jsonrpc::Error>) -> bool {
+        match self.senders.lock().unwrap().remove(&id) {
+            Some(sender) => sender.send(result).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Asks the client to apply the given edit, succeeding only once it reports
+/// that it did.
+pub fn apply_edit<O: Output>(edit: WorkspaceEdit, pending: &PendingRequests, out: &O) -> Result<(), ClientError> {
+    let result = pending.request("workspace/applyEdit", ApplyWorkspaceEditParams { edit }, out)?;
+    match serde_json::from_value::<ApplyWorkspaceEditResponse>(result) {
+        Ok(ref response) if response.applied => Ok(()),
+        Ok(_) => Err(ClientError::NotApplied),
+        Err(e) => {
+            debug!("apply_edit: unexpected response: {}", e);
+            Err(ClientError::BadResponse)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+
+    // Records the requests and answers them straight away.
+    #[derive(Clone)]
+    struct Responder {
+        pending: Arc<PendingRequests>,
+        sent: Arc<Mutex<Vec<String>>>,
+        applied: bool,
+    }
+
+    impl Output for Responder {
+        fn response(&self, output: String) {
+            self.sent.lock().unwrap().push(output);
+            assert!(self.pending.handle_response(1, Ok(json!({ "applied": self.applied }))));
+        }
+
+        fn provide_id(&self) -> u32 {
+            1
+        }
+    }
+
+    fn responder(applied: bool) -> Responder {
+        Responder {
+            pending: Arc::new(PendingRequests::new()),
+            sent: Arc::new(Mutex::new(vec![])),
+            applied,
+        }
+    }
+
+    #[test]
+    fn test_apply_edit() {
+        let edit = || WorkspaceEdit { changes: HashMap::new() };
+
+        let out = responder(true);
+        assert_eq!(apply_edit(edit(), &out.pending, &out), Ok(()));
+        let sent = out.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].contains(r#""method":"workspace/applyEdit""#));
+
+        let out = responder(false);
+        assert_eq!(apply_edit(edit(), &out.pending, &out), Err(ClientError::NotApplied));
+    }
+
+    #[test]
+    fn test_unexpected_response() {
+        let pending = PendingRequests::new();
+        assert!(!pending.handle_response(7, Ok(Value::Null)));
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The commands of `workspace/executeCommand`.
+//!
+//! Every command is a type implementing `WorkspaceCommand`, listed once in
+//! `command_registry!` below. The registry dispatches commands by name, parses
+//! their arguments into the command's `Args` and lists the names advertised in
+//! the response to `initialize`.
+
+use actions::{InitActionContext, post_build, usage, visibility};
+#[cfg(feature = "rustfmt")]
+use actions::{collect_source_files, format};
+use actions::diagnostics::FileDiagnostic;
+use actions::post_build::DiagnosticFilter;
+use actions::requests::apply_edit_and_respond;
+use build::BuildPriority;
+use lsp_data::*;
+use messages::Message;
+use serde::de::DeserializeOwned;
+use serde_json;
+use server::{Output, Ack, NoResponse};
+use jsonrpc_core::types::ErrorCode;
+
+use std::thread;
+#[cfg(feature = "rustfmt")]
+use std::sync::Arc;
+#[cfg(feature = "rustfmt")]
+use std::sync::atomic::AtomicBool;
+
+/// A command the client can ask the RLS to perform.
+pub trait WorkspaceCommand {
+    /// The arguments of the command, parsed from the `arguments` array of
+    /// `ExecuteCommandParams`.
+    type Args: DeserializeOwned;
+    /// The name of the command, e.g. `rls.applySuggestion`.
+    const NAME: &'static str;
+    /// Whether the command is available, i.e. the components it needs were
+    /// compiled in.
+    const ENABLED: bool = true;
+
+    /// Performs the command. Implementations respond to request `id` on `out`
+    /// themselves, possibly later from another thread.
+    fn execute<O: Output>(id: usize, args: Self::Args, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()>;
+}
+
+/// `rls.restartServer`: starts over as if the RLS had been restarted, see
+/// `ActionContext::restart`. Unlike the commands below it replaces the context
+/// they borrow, so it is performed by `requests::ExecuteCommand` itself.
+pub const RESTART_SERVER: &'static str = "rls.restartServer";
+
+/// The arguments of commands which don't take any.
+#[derive(Debug, Deserialize)]
+pub struct NoArguments {}
+
+macro_rules! command_registry {
+    ($($command: ty),*) => {
+        /// The names of the available commands.
+        pub fn command_names() -> Vec<String> {
+            let mut names = vec![RESTART_SERVER.to_owned()];
+            $(
+                if <$command as WorkspaceCommand>::ENABLED {
+                    names.push(<$command as WorkspaceCommand>::NAME.to_owned());
+                }
+            )*
+            names
+        }
+
+        /// Performs the command `name`, `None` if there is no such command.
+        pub fn execute<O: Output>(id: usize,
+                                  name: &str,
+                                  arguments: Vec<serde_json::Value>,
+                                  ctx: &InitActionContext,
+                                  out: O)
+                                  -> Option<Result<NoResponse, ()>> {
+            $(
+                if <$command as WorkspaceCommand>::ENABLED && name == <$command as WorkspaceCommand>::NAME {
+                    return Some(parse_and_execute::<$command, O>(id, arguments, ctx, out));
+                }
+            )*
+            None
+        }
+    }
+}
+
+command_registry!(ApplySuggestion, AnalyzeVisibility, WidenAnalysisScope, FormatWorkspace, SymbolStats, Build);
+
+// Parses the arguments of command `C`, failing the request if they don't fit.
+fn parse_and_execute<C: WorkspaceCommand, O: Output>(id: usize,
+                                                     arguments: Vec<serde_json::Value>,
+                                                     ctx: &InitActionContext,
+                                                     out: O)
+                                                     -> Result<NoResponse, ()> {
+    match serde_json::from_value(serde_json::Value::Array(arguments)) {
+        Ok(args) => C::execute(id, args, ctx, out),
+        Err(e) => {
+            debug!("Bad arguments for `{}`: {}", C::NAME, e);
+            out.failure_message(id, ErrorCode::InvalidParams,
+                                format!("{}: {}", ctx.message(Message::BadCommandArguments), e));
+            Err(())
+        }
+    }
+}
+
+/// `rls.applySuggestion`: applies the replacement suggested by a diagnostic.
+/// Arguments: the location to replace and the new text.
+pub struct ApplySuggestion;
+
+impl WorkspaceCommand for ApplySuggestion {
+    type Args = (Location, String);
+    const NAME: &'static str = "rls.applySuggestion";
+
+    fn execute<O: Output>(id: usize, (location, new_text): Self::Args, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()> {
+        trace!("apply_suggestion {:?} {}", location, new_text);
+        apply_edit_and_respond(id, make_workspace_edit(location, new_text), ctx, out);
+        Ok(NoResponse)
+    }
+}
+
+/// `rls.analyzeVisibility`: reports `pub` items which could be `pub(crate)`,
+/// along with the diagnostics from the last build. The suggested changes are
+/// then offered as code actions.
+pub struct AnalyzeVisibility;
+
+impl WorkspaceCommand for AnalyzeVisibility {
+    type Args = NoArguments;
+    const NAME: &'static str = "rls.analyzeVisibility";
+
+    fn execute<O: Output>(id: usize, _: NoArguments, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()> {
+        let analysis = ctx.analysis.clone();
+        let vfs = ctx.vfs.clone();
+        let crate_files = ctx.crate_files.clone();
+        let project_dir = ctx.current_project.clone();
+        let build_results = ctx.previous_build_results.clone();
+        let filter = DiagnosticFilter::from_config(&ctx.config.lock().unwrap());
+        let fix_label = ctx.message(Message::RestrictVisibility);
+        let positions = ctx.positions.clone();
+        let documents = ctx.documents.clone();
+
+        thread::spawn(move || {
+            let diagnostics = visibility::unused_pub_items(&analysis, &vfs, &crate_files, &project_dir, &fix_label);
+
+            let mut results = build_results.lock().unwrap();
+            // Forget the results of any previous run.
+            let code = Some(NumberOrString::String("unused_pub".to_owned()));
+            for v in results.values_mut() {
+                v.retain(|&(ref d, _)| d.diagnostic.code != code);
+            }
+            for FileDiagnostic { file_path, mut diagnostic, mut suggestions, .. } in diagnostics {
+                diagnostic.range = positions.char_range_to_client(&file_path, diagnostic.range);
+                for suggestion in &mut suggestions {
+                    suggestion.range = positions.char_range_to_client(&file_path, suggestion.range);
+                }
+                results.entry(file_path).or_insert_with(Vec::new).push((RlsDiagnostic::new(diagnostic), suggestions));
+            }
+            post_build::emit_notifications(&results, &filter, &documents, &out);
+        });
+        out.success(id, &Ack);
+        Ok(NoResponse)
+    }
+}
+
+/// `rls.widenAnalysisScope`: analyzes every member of the workspace, until the
+/// configuration changes again.
+pub struct WidenAnalysisScope;
+
+impl WorkspaceCommand for WidenAnalysisScope {
+    type Args = NoArguments;
+    const NAME: &'static str = "rls.widenAnalysisScope";
+
+    fn execute<O: Output>(id: usize, _: NoArguments, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()> {
+        {
+            let mut config = ctx.config.lock().unwrap();
+            config.analyze_package = None;
+            config.exclude_packages = vec![];
+        }
+        out.success(id, &Ack);
+        ctx.build_current_project(BuildPriority::Cargo, out);
+        Ok(NoResponse)
+    }
+}
+
+/// `rls.build`: builds the project now, whatever `build_on` says. The way to
+/// build with `"build_on": "manual"`.
+pub struct Build;
+
+impl WorkspaceCommand for Build {
+    type Args = NoArguments;
+    const NAME: &'static str = "rls.build";
+
+    fn execute<O: Output>(id: usize, _: NoArguments, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()> {
+        out.success(id, &Ack);
+        ctx.build_current_project(BuildPriority::Cargo, out);
+        Ok(NoResponse)
+    }
+}
+
+/// `rls.formatWorkspace`: formats every source file in the project in the
+/// background. Edits are applied in batches as we go, the response summarises
+/// the changes once all files are done (or the request was cancelled).
+pub struct FormatWorkspace;
+
+impl WorkspaceCommand for FormatWorkspace {
+    type Args = NoArguments;
+    const NAME: &'static str = "rls.formatWorkspace";
+    const ENABLED: bool = cfg!(feature = "rustfmt");
+
+    #[cfg(feature = "rustfmt")]
+    fn execute<O: Output>(id: usize, _: NoArguments, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()> {
+        let vfs = ctx.vfs.clone();
+        let ignore = ctx.config.lock().unwrap().format_ignore.clone();
+        let project_dir = ctx.current_project.clone();
+        let files: Vec<_> = collect_source_files(&project_dir)
+            .into_iter()
+            .filter(|f| !format::is_ignored(f.strip_prefix(&project_dir).unwrap_or(f), &ignore))
+            .collect();
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancellable = ctx.cancellable.clone();
+        cancellable.lock().unwrap().insert(id, cancelled.clone());
+        let pending = ctx.pending.clone();
+        let documents = ctx.documents.clone();
+
+        thread::spawn(move || {
+            let result = format::format_files(&files, &vfs, &documents, &cancelled, &pending, &out);
+            cancellable.lock().unwrap().remove(&id);
+            out.success(id, &result);
+        });
+        Ok(NoResponse)
+    }
+
+    // Not registered without rustfmt, see `ENABLED`.
+    #[cfg(not(feature = "rustfmt"))]
+    fn execute<O: Output>(id: usize, _: NoArguments, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()> {
+        out.failure_message(id, ErrorCode::MethodNotFound, ctx.message(Message::UnknownCommand));
+        Err(())
+    }
+}
+
+/// `rls.symbolStats`: reports the most referenced definitions of the project.
+/// Counting references means going through every symbol, so this is done in
+/// the background.
+pub struct SymbolStats;
+
+/// The arguments of `rls.symbolStats`: optionally, the number of definitions
+/// to report.
+#[derive(Debug, Deserialize)]
+pub struct SymbolStatsArgs {
+    #[serde(default = "default_symbol_count")]
+    count: usize,
+}
+
+fn default_symbol_count() -> usize {
+    usage::DEFAULT_COUNT
+}
+
+impl WorkspaceCommand for SymbolStats {
+    type Args = SymbolStatsArgs;
+    const NAME: &'static str = "rls.symbolStats";
+
+    fn execute<O: Output>(id: usize, args: SymbolStatsArgs, ctx: &InitActionContext, out: O) -> Result<NoResponse, ()> {
+        let analysis = ctx.analysis.clone();
+        let project_dir = ctx.current_project.clone();
+        let positions = ctx.positions.clone();
+
+        thread::spawn(move || {
+            let stats: Vec<_> = usage::most_referenced(&analysis, &project_dir, args.count).into_iter().filter_map(|u| {
+                Some(SymbolStat {
+                    location: positions.rls_to_location(&u.span).ok()?,
+                    name: u.name,
+                    kind: source_kind_from_def_kind(u.kind),
+                    references: u.references,
+                })
+            }).collect();
+            out.success(id, &stats);
+        });
+        Ok(NoResponse)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse<C: WorkspaceCommand>(arguments: serde_json::Value) -> Result<C::Args, serde_json::Error> {
+        serde_json::from_value(arguments)
+    }
+
+    #[test]
+    fn test_command_names() {
+        let names = command_names();
+        assert!(names.contains(&"rls.applySuggestion".to_owned()));
+        assert!(names.contains(&"rls.build".to_owned()));
+        assert!(names.contains(&"rls.restartServer".to_owned()));
+        assert_eq!(names.contains(&"rls.formatWorkspace".to_owned()), cfg!(feature = "rustfmt"));
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        assert_eq!(parse::<SymbolStats>(json!([])).unwrap().count, usage::DEFAULT_COUNT);
+        assert_eq!(parse::<SymbolStats>(json!([5])).unwrap().count, 5);
+        assert!(parse::<SymbolStats>(json!(["five"])).is_err());
+        assert!(parse::<AnalyzeVisibility>(json!([])).is_ok());
+        assert!(parse::<ApplySuggestion>(json!([{
+            "uri": "file:///foo.rs",
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 1 } }
+        }])).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Components of the toolchain which the RLS needs but rustup doesn't install
+//! by default: `rust-src` (the source of the standard library, to go to its
+//! definitions and for racer) and `rust-analysis` (the analysis data of the
+//! standard library). Without them a lot silently doesn't work, so the user is
+//! asked (with `window/showMessageRequest`) whether to install the missing ones
+//! with `rustup component add`. Rustfmt is linked into the RLS, so it can't be
+//! missing.
+
+use actions::client::PendingRequests;
+use actions::sources::rust_src_dir;
+use config::Config;
+use lsp_data::{MessageActionItem, MessageType, NotificationMessage, NotificationParams, ShowMessageParams,
+               ShowMessageRequestParams, NOTIFICATION_SHOW_MESSAGE};
+use messages::Message;
+use server::Output;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+/// A component of a rustup toolchain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    /// The source of the standard library.
+    RustSrc,
+    /// The analysis data of the standard library.
+    RustAnalysis,
+}
+
+impl Component {
+    /// The name rustup knows the component by.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Component::RustSrc => "rust-src",
+            Component::RustAnalysis => "rust-analysis",
+        }
+    }
+}
+
+/// The components missing from the toolchain at `sysroot`.
+pub fn missing_components(sysroot: &Path) -> Vec<Component> {
+    let mut missing = vec![];
+    if !rust_src_dir(sysroot).is_dir() {
+        missing.push(Component::RustSrc);
+    }
+    // The analysis data of each target is in `lib/rustlib/$target/analysis`.
+    let has_analysis = fs::read_dir(sysroot.join("lib").join("rustlib"))
+        .map(|entries| entries.filter_map(|e| e.ok()).any(|e| e.path().join("analysis").is_dir()))
+        .unwrap_or(false);
+    if !has_analysis {
+        missing.push(Component::RustAnalysis);
+    }
+    missing
+}
+
+/// Asks the user whether to install the components missing from the toolchain
+/// at `sysroot`, and installs them if they agree. Nothing happens if none are
+/// missing or the toolchain wasn't installed by rustup. Waits for the user, so
+/// it must not be called from the main loop (see `actions::client`).
+pub fn offer_missing_components<O: Output>(sysroot: &Path, config: &Mutex<Config>, pending: &PendingRequests, out: &O) {
+    let toolchain = match rustup_toolchain(sysroot) {
+        Some(toolchain) => toolchain,
+        None => return,
+    };
+    let missing = missing_components(sysroot);
+    if missing.is_empty() {
+        return;
+    }
+    let names: Vec<&str> = missing.iter().map(|c| c.name()).collect();
+
+    let (question, install) = {
+        let config = config.lock().unwrap();
+        (Message::ComponentsMissing.text(&config), Message::InstallComponents.text(&config))
+    };
+    let params = ShowMessageRequestParams {
+        typ: MessageType::Warning,
+        message: format!("{} {}", question, names.join(", ")),
+        actions: Some(vec![MessageActionItem { title: install.clone() }]),
+    };
+    let chosen = match pending.request("window/showMessageRequest", params, out) {
+        Ok(result) => result.get("title").and_then(|t| t.as_str()).map(|t| t.to_owned()),
+        Err(e) => {
+            debug!("offer_missing_components: {:?}", e);
+            None
+        }
+    };
+    if chosen.as_ref() != Some(&install) {
+        return;
+    }
+
+    let result = Command::new("rustup")
+        .args(&["component", "add", "--toolchain", &toolchain])
+        .args(&names)
+        .output();
+    let (typ, message) = {
+        let config = config.lock().unwrap();
+        match result {
+            Ok(ref output) if output.status.success() => {
+                (MessageType::Info, Message::ComponentsInstalled.text(&config))
+            }
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                (MessageType::Error, format!("{}: {}", Message::ComponentsInstallFailed.text(&config), stderr.trim()))
+            }
+            Err(e) => (MessageType::Error, format!("{}: {}", Message::ComponentsInstallFailed.text(&config), e)),
+        }
+    };
+    out.notify(NotificationMessage::new(
+        NOTIFICATION_SHOW_MESSAGE,
+        Some(NotificationParams::ShowMessage(ShowMessageParams { typ, message })),
+    ));
+}
+
+// The name of the rustup toolchain with the given sysroot, e.g. `nightly` for
+// `~/.rustup/toolchains/nightly`. `None` if rustup didn't install it.
+fn rustup_toolchain(sysroot: &Path) -> Option<String> {
+    if sysroot.parent()?.file_name()? != "toolchains" {
+        return None;
+    }
+    sysroot.file_name()?.to_str().map(|name| name.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_missing_components() {
+        assert_eq!(missing_components(Path::new("/no/such/sysroot")),
+                   [Component::RustSrc, Component::RustAnalysis]);
+    }
+
+    #[test]
+    fn test_rustup_toolchain() {
+        assert_eq!(rustup_toolchain(Path::new("/home/me/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu")),
+                   Some("nightly-x86_64-unknown-linux-gnu".to_owned()));
+        assert_eq!(rustup_toolchain(Path::new("/usr/local")), None);
+    }
+}
@@ -0,0 +1,999 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sources of diagnostics. The compiler is always one of them, but additional
+//! linters (e.g. clippy or in-house tools) can be plugged in as well, either
+//! programmatically via the `DiagnosticsProvider` trait or through the
+//! `linters` configuration option.
+
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actions::{inactive_code, module_cycles};
+use actions::inactive_code::CfgSettings;
+use build::CrateFailures;
+#[cfg(feature = "clippy")]
+use build::{rls_target_dir, toolchain_file, with_toolchain};
+use config::{Config, LinterConfig, LinterOutputFormat};
+use lsp_data::{ls_util, DiagnosticTag};
+use messages::Message;
+use paths;
+use vfs::Vfs;
+use Span;
+
+use ls_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range};
+use serde_json;
+use span::compiler::DiagnosticSpan;
+
+/// A suggested change to the source, which can be applied via a code action.
+#[derive(Debug, PartialEq)]
+pub struct Suggestion {
+    pub range: Range,
+    pub new_text: String,
+    pub label: String,
+}
+
+/// A diagnostic attributed to a single file.
+#[derive(Debug)]
+pub struct FileDiagnostic {
+    pub file_path: PathBuf,
+    pub diagnostic: Diagnostic,
+    pub suggestions: Vec<Suggestion>,
+    /// Other places the diagnostic refers to, e.g. where a borrow starts.
+    pub related: Vec<RelatedInformation>,
+}
+
+/// A location related to a diagnostic, with a note explaining how.
+#[derive(Debug, PartialEq)]
+pub struct RelatedInformation {
+    pub file_path: PathBuf,
+    pub range: Range,
+    pub message: String,
+}
+
+/// Something that can report diagnostics about a project after it has been
+/// built. Diagnostics from every registered provider are published (and offer
+/// quick fixes) in the same way as the ones coming from the compiler.
+pub trait DiagnosticsProvider: Send + Sync {
+    /// A short name identifying the provider, used as the `source` of the
+    /// reported diagnostics.
+    fn name(&self) -> &str;
+
+    /// Report diagnostics for the project at `project_path`. `messages` are
+    /// the JSON messages emitted by the compiler during the last build.
+    fn provide(&self, project_path: &Path, messages: &[String]) -> Vec<FileDiagnostic>;
+
+    /// Report diagnostics while the build is still running, `messages` are
+    /// the ones emitted so far (see `PostBuildHandler::handle_early_messages`).
+    /// Those of `provide` by default, a provider running a slow command should
+    /// report what it found the last time instead.
+    fn provide_early(&self, project_path: &Path, messages: &[String]) -> Vec<FileDiagnostic> {
+        self.provide(project_path, messages)
+    }
+}
+
+/// Reports diagnostics emitted by rustc during the build.
+pub struct RustcProvider {
+    did_you_mean: String,
+}
+
+impl RustcProvider {
+    /// Construct a provider whose fixes are labelled in the locale of
+    /// `config`.
+    pub fn new(config: &Config) -> RustcProvider {
+        RustcProvider {
+            did_you_mean: Message::DidYouMean.text(config),
+        }
+    }
+}
+
+impl DiagnosticsProvider for RustcProvider {
+    fn name(&self) -> &str {
+        "rustc"
+    }
+
+    fn provide(&self, _project_path: &Path, messages: &[String]) -> Vec<FileDiagnostic> {
+        messages.iter().filter_map(|msg| parse_diagnostics(msg, self.name(), &self.did_you_mean)).collect()
+    }
+}
+
+/// The lints clippy reported the last time it ran. It only runs again after a
+/// file was saved (see `ClippyProvider`), the builds in between report these.
+#[cfg_attr(not(feature = "clippy"), allow(dead_code))]
+pub struct ClippyLints {
+    messages: Mutex<Vec<String>>,
+    outdated: AtomicBool,
+}
+
+impl ClippyLints {
+    /// Construct without any lints, clippy runs after the next build.
+    pub fn new() -> ClippyLints {
+        ClippyLints {
+            messages: Mutex::new(vec![]),
+            outdated: AtomicBool::new(true),
+        }
+    }
+
+    /// Run clippy again after the next build, e.g. because a file was saved.
+    pub fn invalidate(&self) {
+        self.outdated.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Runs `cargo clippy` in the project directory and reports its lints. Only
+/// available with the `clippy` feature.
+///
+/// Clippy checks the files on disk and takes about as long as a build, so it
+/// only runs after a build if a file was saved since it last ran, otherwise
+/// its previous lints are reported (see `ClippyLints`).
+#[cfg(feature = "clippy")]
+pub struct ClippyProvider {
+    command: Vec<String>,
+    env: Vec<(String, Option<String>)>,
+    lints: Arc<ClippyLints>,
+    did_you_mean: String,
+}
+
+#[cfg(feature = "clippy")]
+impl ClippyProvider {
+    /// Construct a provider running clippy in the project at `project_path`
+    /// with the features, target and environment of the RLS' builds, using
+    /// the rustup toolchain of the `toolchain` option or else the one of the
+    /// project's `rust-toolchain` file.
+    pub fn new(config: &Config, project_path: &Path, lints: Arc<ClippyLints>) -> ClippyProvider {
+        let toolchain = config.toolchain.clone().or_else(|| toolchain_file(project_path));
+        let command = with_toolchain(&clippy_command(config), toolchain.as_ref().map(|t| &**t));
+
+        // Next to the RLS' own builds rather than in them, clippy and the RLS
+        // would rebuild each other's work otherwise.
+        let target_dir = rls_target_dir(project_path, config).join("clippy");
+        let mut env = vec![("CARGO_TARGET_DIR".to_owned(), Some(target_dir.to_string_lossy().into_owned()))];
+        env.extend(config.env.iter().map(|(name, value)| (name.clone(), value.clone())));
+
+        ClippyProvider {
+            command,
+            env,
+            lints,
+            did_you_mean: Message::DidYouMean.text(config),
+        }
+    }
+
+    // The diagnostics of the lints clippy reported the last time it ran.
+    fn lints(&self) -> Vec<FileDiagnostic> {
+        self.lints.messages.lock().unwrap().iter()
+            .filter_map(|msg| parse_diagnostics(msg, self.name(), &self.did_you_mean))
+            // Compiler diagnostics are already reported by the `rustc` provider.
+            .filter(|d| match d.diagnostic.code {
+                Some(NumberOrString::String(ref code)) => code.starts_with("clippy"),
+                _ => false,
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "clippy")]
+impl DiagnosticsProvider for ClippyProvider {
+    fn name(&self) -> &str {
+        "clippy"
+    }
+
+    fn provide(&self, project_path: &Path, _messages: &[String]) -> Vec<FileDiagnostic> {
+        if self.lints.outdated.swap(false, Ordering::SeqCst) {
+            if let Some(output) = run_linter(&self.command, &self.env, project_path) {
+                *self.lints.messages.lock().unwrap() = output.lines().filter_map(cargo_compiler_message).collect();
+            }
+        }
+        self.lints()
+    }
+
+    fn provide_early(&self, _project_path: &Path, _messages: &[String]) -> Vec<FileDiagnostic> {
+        self.lints()
+    }
+}
+
+// `cargo clippy` with the target and features of the RLS' builds.
+#[cfg(feature = "clippy")]
+fn clippy_command(config: &Config) -> Vec<String> {
+    let mut command = vec!["cargo".to_owned(), "clippy".to_owned(), "--message-format=json".to_owned()];
+    if let Some(ref target) = config.target_triple {
+        command.push("--target".to_owned());
+        command.push(target.clone());
+    }
+    if !config.features.is_empty() {
+        command.push("--features".to_owned());
+        command.push(config.features.join(" "));
+    }
+    if config.all_features {
+        command.push("--all-features".to_owned());
+    }
+    if config.no_default_features {
+        command.push("--no-default-features".to_owned());
+    }
+    command
+}
+
+/// Reports `use` statements forming dependency cycles between the modules of
+/// a crate, see `module_cycles`.
+pub struct ModuleCyclesProvider;
+
+impl DiagnosticsProvider for ModuleCyclesProvider {
+    fn name(&self) -> &str {
+        "rls"
+    }
+
+    fn provide(&self, project_path: &Path, _messages: &[String]) -> Vec<FileDiagnostic> {
+        module_cycles::module_cycles(project_path)
+    }
+}
+
+/// Reports code under a `cfg` attribute which is false with the configuration
+/// of the build, see `inactive_code`.
+pub struct InactiveCodeProvider {
+    settings: CfgSettings,
+    vfs: Arc<Vfs>,
+}
+
+impl InactiveCodeProvider {
+    /// Construct a provider for builds with `config`, of the files in `vfs`.
+    pub fn new(config: &Config, vfs: Arc<Vfs>) -> InactiveCodeProvider {
+        InactiveCodeProvider {
+            settings: CfgSettings::from_config(config),
+            vfs,
+        }
+    }
+}
+
+impl DiagnosticsProvider for InactiveCodeProvider {
+    fn name(&self) -> &str {
+        "rls"
+    }
+
+    fn provide(&self, project_path: &Path, _messages: &[String]) -> Vec<FileDiagnostic> {
+        inactive_code::inactive_code(project_path, &self.settings, &self.vfs)
+    }
+}
+
+/// Marks the root files of workspace members which failed to build, whose
+/// analysis data is missing or out of date, see `build::CrateFailures`.
+pub struct BuildFailuresProvider {
+    failures: Arc<CrateFailures>,
+}
+
+impl BuildFailuresProvider {
+    /// Construct a provider reporting the given failures.
+    pub fn new(failures: Arc<CrateFailures>) -> BuildFailuresProvider {
+        BuildFailuresProvider { failures }
+    }
+}
+
+impl DiagnosticsProvider for BuildFailuresProvider {
+    fn name(&self) -> &str {
+        "rls"
+    }
+
+    fn provide(&self, _project_path: &Path, _messages: &[String]) -> Vec<FileDiagnostic> {
+        let mut result = vec![];
+        for failed in self.failures.failed_crates() {
+            let message = match failed.blocked_by {
+                Some(ref dep) => format!("analysis unavailable: `{}` depends on `{}`, which failed to build",
+                                         failed.name, dep),
+                None => format!("analysis unavailable: build of `{}` failed", failed.name),
+            };
+            result.push(FileDiagnostic {
+                file_path: failed.root,
+                diagnostic: Diagnostic {
+                    range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+                    severity: Some(DiagnosticSeverity::Information),
+                    code: Some(NumberOrString::String("build_failed".to_owned())),
+                    source: Some(self.name().to_owned()),
+                    message,
+                },
+                suggestions: vec![],
+                related: vec![],
+            });
+        }
+        result
+    }
+}
+
+/// An external linter configured by the user, see `LinterConfig`.
+pub struct ExternalProvider {
+    config: LinterConfig,
+    did_you_mean: String,
+}
+
+impl ExternalProvider {
+    /// Construct a provider running the given linter configuration.
+    pub fn new(config: LinterConfig, did_you_mean: String) -> ExternalProvider {
+        ExternalProvider { config, did_you_mean }
+    }
+}
+
+impl DiagnosticsProvider for ExternalProvider {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn provide(&self, project_path: &Path, _messages: &[String]) -> Vec<FileDiagnostic> {
+        let output = match run_linter(&self.config.command, &[], project_path) {
+            Some(output) => output,
+            None => return vec![],
+        };
+
+        match self.config.format {
+            LinterOutputFormat::Json => output.lines()
+                .filter_map(|line| parse_diagnostics(line, self.name(), &self.did_you_mean))
+                .collect(),
+            LinterOutputFormat::Line => {
+                let pattern = match self.config.pattern {
+                    Some(ref pattern) => pattern,
+                    None => {
+                        debug!("linter `{}` uses the `line` format but has no `pattern`", self.name());
+                        return vec![];
+                    }
+                };
+                output.lines()
+                    .filter_map(|line| parse_line_diagnostic(pattern, line, self.name()))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Returns the providers enabled by the given configuration for the project at
+/// `project_path`. The compiler is always the first one, followed by the
+/// failures of workspace members.
+pub fn providers_for_config(config: &Config,
+                            project_path: &Path,
+                            failures: Arc<CrateFailures>,
+                            clippy_lints: Arc<ClippyLints>,
+                            vfs: Arc<Vfs>)
+                            -> Vec<Box<DiagnosticsProvider>> {
+    let mut providers: Vec<Box<DiagnosticsProvider>> = vec![
+        Box::new(RustcProvider::new(config)),
+        Box::new(BuildFailuresProvider::new(failures)),
+    ];
+    providers.extend(clippy_provider(config, project_path, clippy_lints));
+    if config.module_cycles {
+        providers.push(Box::new(ModuleCyclesProvider));
+    }
+    if config.inactive_code {
+        providers.push(Box::new(InactiveCodeProvider::new(config, vfs)));
+    }
+    for linter in &config.linters {
+        providers.push(Box::new(ExternalProvider::new(linter.clone(), Message::DidYouMean.text(config))));
+    }
+    providers
+}
+
+#[cfg(feature = "clippy")]
+fn clippy_provider(config: &Config, project_path: &Path, lints: Arc<ClippyLints>) -> Option<Box<DiagnosticsProvider>> {
+    if config.clippy {
+        Some(Box::new(ClippyProvider::new(config, project_path, lints)))
+    } else {
+        None
+    }
+}
+
+// Without the `clippy` feature the option is ignored.
+#[cfg(not(feature = "clippy"))]
+fn clippy_provider(_config: &Config, _project_path: &Path, _lints: Arc<ClippyLints>) -> Option<Box<DiagnosticsProvider>> {
+    None
+}
+
+/// Maps the file paths in diagnostics to files on disk.
+///
+/// Paths in compiler messages are relative to the directory the compiler ran
+/// in, which is not always the workspace root (e.g. for build scripts, or for
+/// macros defined in another package). We try the path relative to the
+/// workspace root, to the working directory of the RLS and to the root of
+/// every package in the workspace, and then a case-insensitive match of each of
+/// these, before giving up and using the path as reported.
+pub struct PathResolver {
+    project_path: PathBuf,
+    // Only looked up if a path can't be found otherwise.
+    package_dirs: Option<Vec<PathBuf>>,
+}
+
+impl PathResolver {
+    /// Construct a resolver for the workspace at `project_path`.
+    pub fn new(project_path: &Path) -> PathResolver {
+        PathResolver {
+            project_path: project_path.to_owned(),
+            package_dirs: None,
+        }
+    }
+
+    /// Find the file a diagnostic refers to.
+    pub fn resolve(&mut self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            let path = normalize_path(path);
+            if path.exists() {
+                return path;
+            }
+            return find_case_insensitive(&path).unwrap_or(path);
+        }
+
+        let in_workspace = normalize_path(&self.project_path.join(path));
+        if in_workspace.exists() {
+            return in_workspace;
+        }
+
+        let mut candidates = vec![in_workspace];
+        if let Ok(cwd) = ::std::env::current_dir() {
+            candidates.push(normalize_path(&cwd.join(path)));
+        }
+        candidates.extend(self.package_dirs().iter().map(|dir| normalize_path(&dir.join(path))));
+
+        if let Some(found) = candidates.iter().find(|c| c.exists()) {
+            return found.clone();
+        }
+        candidates.iter()
+            .filter_map(|c| find_case_insensitive(c))
+            .next()
+            .unwrap_or_else(|| paths::normalize(path))
+    }
+
+    fn package_dirs(&mut self) -> &[PathBuf] {
+        if self.package_dirs.is_none() {
+            let mut dirs = vec![];
+            find_package_dirs(&self.project_path, &mut dirs);
+            self.package_dirs = Some(dirs);
+        }
+        self.package_dirs.as_ref().unwrap()
+    }
+}
+
+fn find_package_dirs(dir: &Path, dirs: &mut Vec<PathBuf>) {
+    if dir.join("Cargo.toml").is_file() {
+        dirs.push(dir.to_owned());
+    }
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let skip = path.file_name().map_or(true, |name| name == "target" || name.to_string_lossy().starts_with('.'));
+        if !skip && path.is_dir() {
+            find_package_dirs(&path, dirs);
+        }
+    }
+}
+
+// Removes `.` and `..` components without touching the file system, so that
+// e.g. `src/../src/lib.rs` matches the path of the open document, and
+// normalizes the result (see `paths::normalize`).
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    result.push(component.as_os_str());
+                }
+            }
+            _ => result.push(component.as_os_str()),
+        }
+    }
+    paths::normalize(&result)
+}
+
+// Finds an existing file whose path only differs from `path` in case.
+fn find_case_insensitive(path: &Path) -> Option<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        let name = match component {
+            Component::Normal(name) => name,
+            _ => {
+                result.push(component.as_os_str());
+                continue;
+            }
+        };
+        // Look at the directory listing even if the file exists, on a case
+        // insensitive file system that is the only way to find its real name.
+        let lower = name.to_str()?.to_lowercase();
+        let entries: Vec<_> = read_dir(&result).ok()?.filter_map(|e| e.ok()).map(|e| e.file_name()).collect();
+        let found = entries.iter()
+            .find(|n| *n == name)
+            .or_else(|| entries.iter().find(|n| n.to_str().map_or(false, |n| n.to_lowercase() == lower)))?;
+        result.push(found);
+    }
+    Some(result)
+}
+
+// Runs `command` in the project directory, with the environment variables in
+// `env` set (or removed if `None`), and returns what it printed to stdout.
+fn run_linter(command: &[String], env: &[(String, Option<String>)], project_path: &Path) -> Option<String> {
+    let (program, args) = match command.split_first() {
+        Some(split) => split,
+        None => return None,
+    };
+
+    let mut cmd = Command::new(program);
+    cmd.args(args).current_dir(project_path);
+    for &(ref name, ref value) in env {
+        match *value {
+            Some(ref value) => cmd.env(name, value),
+            None => cmd.env_remove(name),
+        };
+    }
+    match cmd.output() {
+        Ok(output) => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Err(e) => {
+            debug!("failed to run linter `{}`: {}", program, e);
+            None
+        }
+    }
+}
+
+// Cargo wraps compiler messages in `{"reason":"compiler-message","message":..}`.
+fn cargo_compiler_message(line: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    if value.get("reason").and_then(|r| r.as_str()) != Some("compiler-message") {
+        return None;
+    }
+    value.get("message").map(|m| m.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+struct CompilerMessage {
+    message: String,
+    code: Option<CompilerMessageCode>,
+    level: String,
+    spans: Vec<DiagnosticSpan>,
+    children: Vec<CompilerMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompilerMessageCode {
+    code: String
+}
+
+/// Parse a single JSON compiler message (in the format emitted by rustc's
+/// `--error-format=json`), attributing it to the given `source`. A fix for a
+/// "did you mean" hint is labelled `did_you_mean`, with the name for `{}`.
+pub fn parse_diagnostics(message: &str, source: &str, did_you_mean: &str) -> Option<FileDiagnostic> {
+    let message = match serde_json::from_str::<CompilerMessage>(message) {
+        Ok(m) => m,
+        Err(e) => {
+            debug!("build error {:?}", e);
+            debug!("from {}", message);
+            return None;
+        }
+    };
+
+    if message.spans.is_empty() {
+        return None;
+    }
+
+    let primary_span = primary_span(&message);
+    let related = related_information(&message);
+    let hinted = hinted_name(&message).and_then(|(name, span)| {
+        let span = span.rls_span().zero_indexed();
+        if span.file != primary_span.file {
+            return None;
+        }
+        Some(Suggestion {
+            range: ls_util::rls_to_range(span.range),
+            label: did_you_mean.replace("{}", &name),
+            new_text: name,
+        })
+    });
+    let mut suggestions = make_suggestions(message.children, &primary_span.file);
+    if suggestions.is_empty() {
+        suggestions.extend(hinted);
+    }
+
+    let diagnostic = Diagnostic {
+        range: ls_util::rls_to_range(primary_span.range),
+        severity: Some(severity(&message.level)),
+        code: Some(NumberOrString::String(match message.code {
+            Some(c) => c.code.clone(),
+            None => String::new(),
+        })),
+        source: Some(source.to_owned()),
+        message: message.message,
+    };
+
+    Some(FileDiagnostic {
+        file_path: primary_span.file,
+        diagnostic: diagnostic,
+        suggestions: suggestions,
+        related: related,
+    })
+}
+
+// The places a compiler message points to besides its primary span: labelled
+// secondary spans ("borrowed here"), notes with a span ("first defined here")
+// and the macro invocations the primary span was expanded from.
+fn related_information(message: &CompilerMessage) -> Vec<RelatedInformation> {
+    let mut related = vec![];
+    for span in message.spans.iter().filter(|s| !s.is_primary) {
+        if let Some(ref label) = span.label {
+            related.push(related_at(span, label.clone()));
+        }
+    }
+    for child in &message.children {
+        // Children with a replacement are offered as suggestions instead.
+        for span in child.spans.iter().filter(|s| s.suggested_replacement.is_none()) {
+            let note = match span.label {
+                Some(ref label) => format!("{}: {}", child.message, label),
+                None => child.message.clone(),
+            };
+            related.push(related_at(span, note));
+        }
+    }
+    if let Some(primary) = message.spans.iter().find(|s| s.is_primary) {
+        let mut expansion = primary.expansion.as_ref();
+        while let Some(e) = expansion {
+            related.push(related_at(&e.span, format!("in this expansion of `{}`", e.macro_decl_name)));
+            expansion = e.span.expansion.as_ref();
+        }
+    }
+    related
+}
+
+// The name in a "did you mean `name`?" hint in the label of a span, the
+// message or a child message, for diagnostics where the compiler doesn't
+// suggest the replacement as such (e.g. E0425), and the span it replaces: the
+// labelled one, the first one of the child message or the primary one.
+fn hinted_name(message: &CompilerMessage) -> Option<(String, &DiagnosticSpan)> {
+    let primary = message.spans.iter().find(|s| s.is_primary)?;
+    let labels = message.spans.iter().filter_map(|s| s.label.as_ref().map(|label| (label, s)));
+    let children = message.children.iter().map(|c| (&c.message, c.spans.first().unwrap_or(primary)));
+    labels.chain(Some((&message.message, primary)))
+        .chain(children)
+        .filter_map(|(text, span)| suggested_name(text).map(|name| (name.to_owned(), span)))
+        .next()
+}
+
+fn suggested_name(text: &str) -> Option<&str> {
+    const HINT: &str = "did you mean `";
+    let start = text.find(HINT)? + HINT.len();
+    let len = text[start..].find('`')?;
+    if len == 0 { None } else { Some(&text[start..start + len]) }
+}
+
+fn related_at(span: &DiagnosticSpan, message: String) -> RelatedInformation {
+    let span = span.rls_span().zero_indexed();
+    RelatedInformation {
+        file_path: span.file,
+        range: ls_util::rls_to_range(span.range),
+        message,
+    }
+}
+
+// The lints (and RLS diagnostics) about code which can be removed. Not every
+// `unused_` lint is, e.g. `unused_must_use` is about a result of live code.
+const UNNECESSARY_CODES: &[&str] = &[
+    "dead_code",
+    "inactive_code",
+    "unreachable_code",
+    "unreachable_patterns",
+    "unused_assignments",
+    "unused_dependency",
+    "unused_extern_crates",
+    "unused_imports",
+    "unused_labels",
+    "unused_lifetimes",
+    "unused_macros",
+    "unused_mut",
+    "unused_parens",
+    "unused_qualifications",
+    "unused_unsafe",
+    "unused_variables",
+];
+
+/// The tags of a diagnostic, by its code: unused and unreachable code is
+/// unnecessary, uses of deprecated items are deprecated.
+pub fn diagnostic_tags(diagnostic: &Diagnostic) -> Vec<DiagnosticTag> {
+    let code = match diagnostic.code {
+        Some(NumberOrString::String(ref code)) => code,
+        _ => return vec![],
+    };
+    if UNNECESSARY_CODES.contains(&&**code) {
+        vec![DiagnosticTag::Unnecessary]
+    } else if code == "deprecated" {
+        vec![DiagnosticTag::Deprecated]
+    } else {
+        vec![]
+    }
+}
+
+fn severity(level: &str) -> DiagnosticSeverity {
+    if level == "error" {
+        DiagnosticSeverity::Error
+    } else {
+        DiagnosticSeverity::Warning
+    }
+}
+
+fn make_suggestions(children: Vec<CompilerMessage>, file: &Path) -> Vec<Suggestion> {
+    let mut suggestions = vec![];
+    for c in children {
+        for sp in c.spans {
+            let span = sp.rls_span().zero_indexed();
+            if span.file == file {
+                if let Some(s) = sp.suggested_replacement {
+                    let suggestion = Suggestion {
+                        new_text: s.clone(),
+                        range: ls_util::rls_to_range(span.range),
+                        label: format!("{}: `{}`", c.message, s),
+                    };
+                    suggestions.push(suggestion);
+                }
+            }
+        }
+    }
+    suggestions
+}
+
+fn primary_span(message: &CompilerMessage) -> Span {
+    let primary = message.spans
+        .iter()
+        .filter(|x| x.is_primary)
+        .next()
+        .unwrap()
+        .clone();
+    primary.rls_span().zero_indexed()
+}
+
+/// Parse a line of linter output using a pattern such as
+/// `{file}:{line}:{column}: {severity}: {message}`. `file`, `line` and
+/// `message` are required, `column` and `severity` are optional. Lines and
+/// columns are one-indexed.
+fn parse_line_diagnostic(pattern: &str, line: &str, source: &str) -> Option<FileDiagnostic> {
+    let fields = match_line_pattern(pattern, line)?;
+
+    let file_path = PathBuf::from(fields.get("file")?);
+    let row = fields.get("line")?.parse::<u64>().ok()?.saturating_sub(1);
+    let col = fields.get("column").and_then(|c| c.parse::<u64>().ok()).unwrap_or(1).saturating_sub(1);
+    let severity = fields.get("severity").map(|s| severity(&s.to_lowercase()));
+
+    let diagnostic = Diagnostic {
+        range: Range { start: Position::new(row, col), end: Position::new(row, col) },
+        severity: Some(severity.unwrap_or(DiagnosticSeverity::Warning)),
+        code: None,
+        source: Some(source.to_owned()),
+        message: fields.get("message")?.clone(),
+    };
+
+    Some(FileDiagnostic {
+        file_path,
+        diagnostic,
+        suggestions: vec![],
+        related: vec![],
+    })
+}
+
+// Matches `line` against `pattern`, where `{name}` placeholders match as few
+// characters as possible up to the next literal part of the pattern (the last
+// placeholder consumes the rest of the line). Deliberately not a regex: the
+// patterns are written by users in their editor settings, this syntax is all
+// that linter output needs and it spares the dependency. The README documents
+// it with the `linters` option.
+fn match_line_pattern(pattern: &str, line: &str) -> Option<HashMap<String, String>> {
+    let mut fields = HashMap::new();
+    let mut pattern = pattern;
+    let mut line = line;
+
+    while !pattern.is_empty() {
+        if pattern.starts_with('{') {
+            let close = pattern.find('}')?;
+            let name = &pattern[1..close];
+            pattern = &pattern[close + 1..];
+
+            let literal_end = pattern.find('{').unwrap_or(pattern.len());
+            let literal = &pattern[..literal_end];
+            let value_end = if literal.is_empty() {
+                line.len()
+            } else {
+                line.find(literal)?
+            };
+            fields.insert(name.to_owned(), line[..value_end].trim().to_owned());
+            line = &line[value_end..];
+        } else {
+            let literal_end = pattern.find('{').unwrap_or(pattern.len());
+            let literal = &pattern[..literal_end];
+            if !line.starts_with(literal) {
+                return None;
+            }
+            pattern = &pattern[literal_end..];
+            line = &line[literal.len()..];
+        }
+    }
+
+    Some(fields)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_match_line_pattern() {
+        let pattern = "{file}:{line}:{column}: {severity}: {message}";
+        let fields = match_line_pattern(pattern, "src/main.rs:10:5: warning: don't do this: really").unwrap();
+        assert_eq!(fields["file"], "src/main.rs");
+        assert_eq!(fields["line"], "10");
+        assert_eq!(fields["column"], "5");
+        assert_eq!(fields["severity"], "warning");
+        assert_eq!(fields["message"], "don't do this: really");
+
+        assert!(match_line_pattern(pattern, "not a diagnostic").is_none());
+        assert!(match_line_pattern("lint: {message}", "other: message").is_none());
+    }
+
+    #[cfg(feature = "clippy")]
+    #[test]
+    fn test_clippy_command() {
+        let mut config = Config::default();
+        assert_eq!(clippy_command(&config), ["cargo", "clippy", "--message-format=json"]);
+
+        config.target_triple = Some("wasm32-unknown-unknown".to_owned());
+        config.features = vec!["foo".to_owned(), "bar".to_owned()];
+        config.no_default_features = true;
+        assert_eq!(clippy_command(&config),
+                   ["cargo", "clippy", "--message-format=json", "--target", "wasm32-unknown-unknown",
+                    "--features", "foo bar", "--no-default-features"]);
+    }
+
+    #[test]
+    fn test_parse_line_diagnostic() {
+        let pattern = "{file}:{line}: {message}";
+        let d = parse_line_diagnostic(pattern, "src/lib.rs:3: bad name", "custom").unwrap();
+        assert_eq!(d.file_path, PathBuf::from("src/lib.rs"));
+        assert_eq!(d.diagnostic.range.start, Position::new(2, 0));
+        assert_eq!(d.diagnostic.severity, Some(DiagnosticSeverity::Warning));
+        assert_eq!(d.diagnostic.source, Some("custom".to_owned()));
+        assert_eq!(d.diagnostic.message, "bad name");
+    }
+
+    fn compiler_span(line: usize, is_primary: bool, label: Option<&str>) -> serde_json::Value {
+        json!({
+            "file_name": "src/main.rs",
+            "byte_start": 0,
+            "byte_end": 0,
+            "line_start": line,
+            "line_end": line,
+            "column_start": 5,
+            "column_end": 10,
+            "is_primary": is_primary,
+            "text": [],
+            "label": label,
+            "suggested_replacement": null,
+            "expansion": null
+        })
+    }
+
+    #[test]
+    fn test_related_information() {
+        let mut in_macro = compiler_span(3, true, Some("second mutable borrow occurs here"));
+        in_macro["expansion"] = json!({
+            "span": compiler_span(9, false, None),
+            "macro_decl_name": "borrow!",
+            "def_site_span": null
+        });
+        let message = json!({
+            "message": "cannot borrow `x` as mutable more than once at a time",
+            "code": { "code": "E0499", "explanation": null },
+            "level": "error",
+            "spans": [in_macro, compiler_span(2, false, Some("first mutable borrow occurs here"))],
+            "children": [{
+                "message": "first borrow ends here",
+                "code": null,
+                "level": "note",
+                "spans": [compiler_span(4, false, None)],
+                "children": [],
+                "rendered": null
+            }],
+            "rendered": null
+        });
+
+        let d = parse_diagnostics(&message.to_string(), "rustc", "").unwrap();
+        assert_eq!(d.diagnostic.range.start, Position::new(2, 4));
+        let related: Vec<_> = d.related.iter().map(|r| (r.range.start.line, &*r.message)).collect();
+        assert_eq!(related, vec![
+            (1, "first mutable borrow occurs here"),
+            (3, "first borrow ends here"),
+            (8, "in this expansion of `borrow!`"),
+        ]);
+        assert!(d.related.iter().all(|r| r.file_path == PathBuf::from("src/main.rs")));
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        assert_eq!(suggested_name("did you mean `foo`?"), Some("foo"));
+        assert_eq!(suggested_name("not found in this scope. did you mean `self.foo`?"), Some("self.foo"));
+        assert_eq!(suggested_name("did you mean ``?"), None);
+        assert_eq!(suggested_name("cannot find value `fo` in this scope"), None);
+
+        let message = json!({
+            "message": "cannot find value `fo` in this scope",
+            "code": { "code": "E0425", "explanation": null },
+            "level": "error",
+            "spans": [compiler_span(3, true, Some("did you mean `foo`?"))],
+            "children": [],
+            "rendered": null
+        });
+        let d = parse_diagnostics(&message.to_string(), "rustc", "Change to `{}`").unwrap();
+        assert_eq!(d.suggestions, vec![Suggestion {
+            range: d.diagnostic.range,
+            new_text: "foo".to_owned(),
+            label: "Change to `foo`".to_owned(),
+        }]);
+
+        // The hint of a child message replaces the span of the child.
+        let message = json!({
+            "message": "cannot find type `Strng` in this scope",
+            "code": { "code": "E0412", "explanation": null },
+            "level": "error",
+            "spans": [compiler_span(3, true, Some("not found in this scope"))],
+            "children": [{
+                "message": "did you mean `String`?",
+                "code": null,
+                "level": "help",
+                "spans": [compiler_span(5, false, None)],
+                "children": [],
+                "rendered": null
+            }],
+            "rendered": null
+        });
+        let d = parse_diagnostics(&message.to_string(), "rustc", "Change to `{}`").unwrap();
+        assert_eq!(d.suggestions.len(), 1);
+        assert_eq!(d.suggestions[0].range.start, Position::new(4, 4));
+    }
+
+    #[test]
+    fn test_diagnostic_tags() {
+        let with_code = |code: &str| Diagnostic {
+            range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            severity: Some(DiagnosticSeverity::Warning),
+            code: Some(NumberOrString::String(code.to_owned())),
+            source: Some("rustc".to_owned()),
+            message: String::new(),
+        };
+        assert_eq!(diagnostic_tags(&with_code("dead_code")), vec![DiagnosticTag::Unnecessary]);
+        assert_eq!(diagnostic_tags(&with_code("unused_imports")), vec![DiagnosticTag::Unnecessary]);
+        assert_eq!(diagnostic_tags(&with_code("unused_must_use")), vec![]);
+        assert_eq!(diagnostic_tags(&with_code("unused_results")), vec![]);
+        assert_eq!(diagnostic_tags(&with_code("deprecated")), vec![DiagnosticTag::Deprecated]);
+        assert_eq!(diagnostic_tags(&with_code("E0308")), vec![]);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path(Path::new("/a/./b/../c.rs")), PathBuf::from("/a/c.rs"));
+        assert_eq!(normalize_path(Path::new("../a.rs")), PathBuf::from("../a.rs"));
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let main = manifest_dir.join("src").join("main.rs");
+
+        assert_eq!(find_case_insensitive(&manifest_dir.join("SRC").join("Main.rs")), Some(main.clone()));
+        assert_eq!(find_case_insensitive(&manifest_dir.join("src").join("missing.rs")), None);
+
+        let mut resolver = PathResolver::new(manifest_dir);
+        assert_eq!(resolver.resolve(Path::new("src/main.rs")), main);
+        assert_eq!(resolver.resolve(Path::new("src/../src/main.rs")), main);
+        // Only exists relative to the packages in `test_data`.
+        let lib = resolver.resolve(Path::new("src/lib.rs"));
+        assert!(lib.is_absolute() && lib.ends_with("src/lib.rs") && lib.exists());
+        assert_eq!(resolver.resolve(Path::new("src/missing.rs")), PathBuf::from("src/missing.rs"));
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The `rls/eventStream` notifications: a structured log of what the server
+//! does (builds, loading analysis data, answering requests), for clients which
+//! want to build dashboards or adapt their UI, e.g. hide lenses while a build
+//! is running.
+//!
+//! Clients opt in with `eventStream: true` in the `initializationOptions`,
+//! otherwise nothing is sent.
+
+use lsp_data::{NotificationMessage, NotificationParams, ServerEvent, ServerEventParams};
+use lsp_data::NOTIFICATION_EVENT_STREAM;
+use server::Output;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Sends events to the client, if it asked for them.
+pub struct EventStream {
+    enabled: AtomicBool,
+    started: Instant,
+}
+
+impl EventStream {
+    /// Construct a disabled stream.
+    pub fn new() -> EventStream {
+        EventStream {
+            enabled: AtomicBool::new(false),
+            started: Instant::now(),
+        }
+    }
+
+    /// Send events from now on.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    /// Send an `rls/eventStream` notification, if the client asked for them.
+    pub fn emit<O: Output>(&self, out: &O, event: ServerEvent) {
+        if !self.enabled.load(Ordering::SeqCst) {
+            return;
+        }
+        out.notify(NotificationMessage::new(
+            NOTIFICATION_EVENT_STREAM,
+            Some(NotificationParams::EventStream(ServerEventParams {
+                time_ms: millis(self.started.elapsed()),
+                event,
+            })),
+        ));
+    }
+}
+
+/// A duration in whole milliseconds, as events report them.
+pub fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lsp_data::BuildOutcome;
+    use serde_json;
+
+    #[test]
+    fn test_event_json() {
+        let params = ServerEventParams {
+            time_ms: 1200,
+            event: ServerEvent::BuildEnded {
+                session_id: 3,
+                outcome: BuildOutcome::Failure,
+                duration_ms: 450,
+            },
+        };
+        assert_eq!(serde_json::to_value(&params).unwrap(), json!({
+            "timeMs": 1200,
+            "event": { "kind": "buildEnded", "sessionId": 3, "outcome": "failure", "durationMs": 450 }
+        }));
+        assert_eq!(millis(Duration::new(2, 345_678_901)), 2345);
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The type of a selected expression, for `rustDocument/typeOfSelection`.
+//!
+//! The analysis data only has types for identifiers (variables, fields,
+//! functions, ...), not for expressions. So the type of an expression is the
+//! type of the identifier it ends with: `self.foo.bar` has the type of the
+//! field `bar`, and `foo.bar(1)?` the return type of the method `bar` (without
+//! unwrapping the `?`, the analysis data doesn't know `Try`). Expressions
+//! ending otherwise, e.g. in a literal or an index, have no known type.
+
+/// The identifier whose type is the type of an expression.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TypedNode {
+    /// The line of the identifier, counted from the first line of the
+    /// expression.
+    pub row: usize,
+    /// The char column of the identifier in its line (in the first line, from
+    /// the start of the expression).
+    pub col: usize,
+    /// Whether the identifier is called, so the type is its return type.
+    pub call: bool,
+}
+
+/// The identifier the expression `text` ends with, see the module docs.
+pub fn typed_node(text: &str) -> Option<TypedNode> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut end = chars.len();
+    skip_back(&chars, &mut end, |c| c.is_whitespace() || c == '?' || c == ';');
+
+    let mut call = false;
+    if end > 0 && chars[end - 1] == ')' {
+        end = matching_open(&chars, end - 1, '(', ')')?;
+        call = true;
+        skip_back(&chars, &mut end, char::is_whitespace);
+        // A turbofish, `foo::<T>()`.
+        if end > 0 && chars[end - 1] == '>' {
+            end = matching_open(&chars, end - 1, '<', '>')?;
+            if end < 2 || chars[end - 2..end] != [':', ':'] {
+                return None;
+            }
+            end -= 2;
+        }
+    }
+
+    let ident_end = end;
+    skip_back(&chars, &mut end, |c| c.is_alphanumeric() || c == '_');
+    if end == ident_end || chars[end].is_numeric() {
+        return None;
+    }
+
+    let row = chars[..end].iter().filter(|&&c| c == '\n').count();
+    let line_start = chars[..end].iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+    Some(TypedNode {
+        row,
+        col: end - line_start,
+        call,
+    })
+}
+
+/// The return type in the signature of a function, e.g. `Option<u32>` for
+/// `fn foo(x: u32) -> Option<u32>`. `()` if it has none.
+pub fn return_type(signature: &str) -> String {
+    let mut depth = 0i32;
+    let mut arrow = None;
+    let bytes = signature.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'(' | b'[' | b'<' => depth += 1,
+            b')' | b']' => depth -= 1,
+            // Not the `>` of `->`.
+            b'>' if i == 0 || bytes[i - 1] != b'-' => depth -= 1,
+            b'>' if depth == 0 => arrow = Some(i + 1),
+            _ => {}
+        }
+    }
+    let ty = match arrow {
+        Some(start) => signature[start..].trim(),
+        None => return "()".to_owned(),
+    };
+    let ty = match ty.find(" where ") {
+        Some(i) => &ty[..i],
+        None => ty,
+    };
+    ty.trim_right_matches('{').trim().to_owned()
+}
+
+fn skip_back<F: Fn(char) -> bool>(chars: &[char], end: &mut usize, skip: F) {
+    while *end > 0 && skip(chars[*end - 1]) {
+        *end -= 1;
+    }
+}
+
+// The index of the `open` matching the `close` at `close_index`.
+fn matching_open(chars: &[char], close_index: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for i in (0..close_index + 1).rev() {
+        if chars[i] == close {
+            depth += 1;
+        } else if chars[i] == open {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(row: usize, col: usize, call: bool) -> Option<TypedNode> {
+        Some(TypedNode { row, col, call })
+    }
+
+    #[test]
+    fn test_typed_node() {
+        assert_eq!(typed_node("foo"), node(0, 0, false));
+        assert_eq!(typed_node("self.foo.bar "), node(0, 9, false));
+        assert_eq!(typed_node("foo.bar(1, (2, 3))?;"), node(0, 4, true));
+        assert_eq!(typed_node("iter.collect::<Vec<_>>()"), node(0, 5, true));
+        assert_eq!(typed_node("foo\n    .bar()\n    .baz"), node(2, 5, false));
+        assert_eq!(typed_node("foo[0]"), None);
+        assert_eq!(typed_node("x + 1"), None);
+        assert_eq!(typed_node("foo.0"), None);
+        assert_eq!(typed_node(""), None);
+    }
+
+    #[test]
+    fn test_return_type() {
+        assert_eq!(return_type("fn foo(x: u32) -> Option<u32>"), "Option<u32>");
+        assert_eq!(return_type("fn foo<F: Fn() -> u32>(f: F)"), "()");
+        assert_eq!(return_type("fn foo(&self) -> Vec<Box<Fn() -> u32>>"), "Vec<Box<Fn() -> u32>>");
+        assert_eq!(return_type("pub fn foo<T>(t: T) -> T where T: Clone {"), "T");
+    }
+}
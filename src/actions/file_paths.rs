@@ -0,0 +1,187 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Completions of file system paths, which racer doesn't offer: files in the
+//! strings of `include!`, `include_str!`, `include_bytes!` and `#[path = "..."]`
+//! (relative to the directory of the source file, like the compiler resolves
+//! them), and the files of modules in `mod` declarations.
+
+use lsp_data::{CompletionItem, CompletionItemKind, CompletionRelevance, RankedCompletion};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What kind of path is being completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathContext {
+    /// A file, in a string.
+    File,
+    /// The name of a module declared by `mod`.
+    Module,
+}
+
+const INCLUDE_MACROS: &[&str] = &["include!(", "include_str!(", "include_bytes!("];
+
+/// What is being completed at char column `col` of `line`, if it's a path,
+/// and the part of the path typed so far.
+pub fn path_context(line: &str, col: usize) -> Option<(PathContext, String)> {
+    let before: String = line.chars().take(col).collect();
+
+    if before.matches('"').count() % 2 == 1 {
+        let quote = before.rfind('"')?;
+        let lhs = before[..quote].trim_right();
+        let in_include = INCLUDE_MACROS.iter().any(|m| lhs.ends_with(m));
+        let in_path_attribute = lhs.ends_with('=') && lhs[..lhs.len() - 1].trim_right().ends_with("path") &&
+                                (lhs.trim_left().starts_with("#[") || lhs.trim_left().starts_with("#!["));
+        if in_include || in_path_attribute {
+            return Some((PathContext::File, before[quote + 1..].to_owned()));
+        }
+        return None;
+    }
+
+    let mut rest = before.trim_left();
+    if rest.starts_with("pub") {
+        rest = rest["pub".len()..].trim_left();
+        if rest.starts_with('(') {
+            rest = rest[rest.find(')')? + 1..].trim_left();
+        }
+    }
+    if !rest.starts_with("mod ") {
+        return None;
+    }
+    let name = rest["mod ".len()..].trim_left();
+    if name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        Some((PathContext::Module, name.to_owned()))
+    } else {
+        None
+    }
+}
+
+/// The completions of the path starting with `prefix` in a `context` in
+/// `file`.
+pub fn path_completions(file: &Path, context: PathContext, prefix: &str) -> Vec<RankedCompletion> {
+    match context {
+        PathContext::File => file_completions(file, prefix),
+        PathContext::Module => module_completions(file, prefix),
+    }
+}
+
+fn file_completions(file: &Path, prefix: &str) -> Vec<RankedCompletion> {
+    let (dir, name_prefix) = match prefix.rfind('/') {
+        Some(slash) => (&prefix[..slash + 1], &prefix[slash + 1..]),
+        None => ("", prefix),
+    };
+    let dir = match file.parent() {
+        Some(parent) => parent.join(dir),
+        None => return vec![],
+    };
+    // Hidden files only when asked for.
+    let show_hidden = name_prefix.starts_with('.');
+    entries(&dir).into_iter()
+        .filter(|&(ref name, _)| name.starts_with(name_prefix) && (show_hidden || !name.starts_with('.')))
+        .map(|(name, is_dir)| {
+            if is_dir {
+                completion(format!("{}/", name), "directory", CompletionItemKind::File)
+            } else {
+                completion(name, "file", CompletionItemKind::File)
+            }
+        })
+        .collect()
+}
+
+fn module_completions(file: &Path, prefix: &str) -> Vec<RankedCompletion> {
+    let dir = match module_dir(file) {
+        Some(dir) => dir,
+        None => return vec![],
+    };
+    entries(&dir).into_iter()
+        .filter_map(|(name, is_dir)| {
+            if is_dir {
+                if dir.join(&name).join("mod.rs").is_file() {
+                    let detail = format!("{}/mod.rs", name);
+                    return Some((name, detail));
+                }
+                return None;
+            }
+            let module = name.trim_right_matches(".rs");
+            if module.len() == name.len() || ["lib", "main", "mod"].contains(&module) || &*dir.join(&name) == file {
+                return None;
+            }
+            Some((module.to_owned(), name.clone()))
+        })
+        .filter(|&(ref module, _)| module.starts_with(prefix))
+        .map(|(module, detail)| completion(module, &detail, CompletionItemKind::Module))
+        .collect()
+}
+
+// The directory of the files of the modules declared in `file`: its own for
+// crate roots and `mod.rs`, the one named after it otherwise.
+fn module_dir(file: &Path) -> Option<PathBuf> {
+    let parent = file.parent()?;
+    let stem = file.file_stem()?.to_str()?;
+    if stem == "lib" || stem == "main" || stem == "mod" {
+        Some(parent.to_owned())
+    } else {
+        Some(parent.join(stem))
+    }
+}
+
+// The names of the entries of `dir` and whether they are directories.
+fn entries(dir: &Path) -> Vec<(String, bool)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries.filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let is_dir = entry.path().is_dir();
+            entry.file_name().into_string().ok().map(|name| (name, is_dir))
+        })
+        .collect()
+}
+
+fn completion(label: String, detail: &str, kind: CompletionItemKind) -> RankedCompletion {
+    let mut item = CompletionItem::new_simple(label, detail.to_owned());
+    item.kind = Some(kind);
+    RankedCompletion {
+        item,
+        relevance: CompletionRelevance::Item,
+        in_document: false,
+        definition: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context(line: &str) -> Option<(PathContext, String)> {
+        path_context(line, line.chars().count())
+    }
+
+    #[test]
+    fn test_path_context() {
+        assert_eq!(context("    include_str!(\"../data/fo"), Some((PathContext::File, "../data/fo".to_owned())));
+        assert_eq!(context("include!(concat!(env!(\"OUT_DIR\"), \"/gen"), None);
+        assert_eq!(context("#[path = \"sys/"), Some((PathContext::File, "sys/".to_owned())));
+        assert_eq!(context("let path = \"foo"), None);
+        assert_eq!(context("mod fo"), Some((PathContext::Module, "fo".to_owned())));
+        assert_eq!(context("    pub(crate) mod "), Some((PathContext::Module, String::new())));
+        assert_eq!(context("mod foo {"), None);
+        assert_eq!(context("model"), None);
+    }
+
+    #[test]
+    fn test_module_dir() {
+        assert_eq!(module_dir(Path::new("/p/src/lib.rs")), Some(PathBuf::from("/p/src")));
+        assert_eq!(module_dir(Path::new("/p/src/foo/mod.rs")), Some(PathBuf::from("/p/src/foo")));
+        assert_eq!(module_dir(Path::new("/p/src/foo.rs")), Some(PathBuf::from("/p/src/foo")));
+    }
+}
@@ -0,0 +1,455 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Formatting every source file of the workspace (`rls.formatWorkspace`),
+//! formatting with a rustfmt binary instead of the bundled rustfmt (the
+//! `rustfmt_path` option), and finding the lines to format for a selection.
+//!
+//! Only formatting the workspace needs the bundled rustfmt (the `rustfmt`
+//! feature), a rustfmt binary can be used without it.
+
+#[cfg(feature = "rustfmt")]
+use actions::FileAccess;
+#[cfg(feature = "rustfmt")]
+use actions::client::{apply_edit, PendingRequests};
+#[cfg(feature = "rustfmt")]
+use config::{find_rustfmt_toml, FmtConfig};
+#[cfg(feature = "rustfmt")]
+use lsp_data::*;
+#[cfg(feature = "rustfmt")]
+use rustfmt::{Input as FmtInput, format_input};
+#[cfg(feature = "rustfmt")]
+use rustfmt::config::Config as RustfmtConfig;
+#[cfg(feature = "rustfmt")]
+use server::Output;
+#[cfg(feature = "rustfmt")]
+use vfs::Vfs;
+use toml;
+
+use std::cmp;
+#[cfg(feature = "rustfmt")]
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+#[cfg(feature = "rustfmt")]
+use std::path::PathBuf;
+use std::path::Path;
+use std::process::{Command, Stdio};
+#[cfg(feature = "rustfmt")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Number of files whose changes are sent to the client in one
+// `workspace/applyEdit` request.
+const BATCH_SIZE: usize = 20;
+
+/// Formats the given files, each with the `rustfmt.toml` closest to it,
+/// sending the changes to the client in batches and reporting progress after
+/// each batch. Stops early once `cancelled` is set. Files are only reported as
+/// changed once the client applied their batch.
+#[cfg(feature = "rustfmt")]
+pub fn format_files<O: Output>(files: &[PathBuf],
+                               vfs: &Vfs,
+                               documents: &DocumentPaths,
+                               cancelled: &AtomicBool,
+                               pending: &PendingRequests,
+                               out: &O)
+                               -> FormatWorkspaceResult {
+    let mut result = FormatWorkspaceResult {
+        files_checked: 0,
+        files_changed: vec![],
+        files_failed: vec![],
+        cancelled: false,
+    };
+    // By the `rustfmt.toml` they are read from, `None` for the default one.
+    let mut configs: HashMap<Option<PathBuf>, RustfmtConfig> = HashMap::new();
+
+    for batch in files.chunks(BATCH_SIZE) {
+        if cancelled.load(Ordering::SeqCst) {
+            result.cancelled = true;
+            break;
+        }
+
+        let mut edit = WorkspaceEdit {
+            changes: HashMap::new(),
+        };
+        let mut changed = vec![];
+        for file in batch {
+            let uri = match documents.uri(file) {
+                Ok(uri) => uri,
+                Err(_) => continue,
+            };
+            let toml = file.parent().and_then(find_rustfmt_toml);
+            let config = &*configs.entry(toml).or_insert_with(|| FmtConfig::for_file(file).get_rustfmt_config().clone());
+            // Rustfmt gets the only copy of the text, we look at the original
+            // again afterwards.
+            let formatted = vfs.with_file(file, |text| text.to_owned()).ok().and_then(|text| format_text(text, config));
+            let formatted = match formatted {
+                Some(formatted) => formatted,
+                None => {
+                    result.files_failed.push(uri);
+                    continue;
+                }
+            };
+            match vfs.with_file(file, |text| (text != formatted, ls_util::range_from_text(text))) {
+                Ok((true, range)) => {
+                    edit.changes.insert(uri.clone(), vec![TextEdit {
+                        range,
+                        new_text: formatted,
+                    }]);
+                    changed.push(uri);
+                }
+                Ok((false, _)) => {}
+                Err(_) => result.files_failed.push(uri),
+            }
+        }
+        result.files_checked += batch.len();
+
+        if !edit.changes.is_empty() {
+            match apply_edit(edit, pending, out) {
+                Ok(()) => result.files_changed.extend(changed),
+                Err(e) => {
+                    debug!("format_files: edit not applied: {:?}", e);
+                    result.files_failed.extend(changed);
+                }
+            }
+        }
+
+        out.notify(NotificationMessage::new(
+            NOTIFICATION_FORMAT_PROGRESS,
+            Some(NotificationParams::FormatWorkspaceProgress(FormatWorkspaceProgressParams {
+                done: result.files_checked,
+                total: files.len(),
+            })),
+        ));
+    }
+
+    result
+}
+
+#[cfg(feature = "rustfmt")]
+fn format_text(text: String, config: &RustfmtConfig) -> Option<String> {
+    let mut buf = Vec::<u8>::new();
+    match format_input(FmtInput::Text(text), config, Some(&mut buf)) {
+        // format_input returns Ok even if there are any errors, i.e., parsing errors.
+        Ok((summary, ..)) if summary.has_no_errors() => String::from_utf8(buf).ok(),
+        Ok((summary, ..)) => {
+            debug!("format_text: format_input failed: has errors, summary = {:?}", summary);
+            None
+        }
+        Err(e) => {
+            debug!("format_text: format_input failed: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Whether rustfmt is told not to format the file, with `#![rustfmt::skip]`
+/// (or the older `#![rustfmt_skip]`, possibly in a `cfg_attr`).
+pub fn skips_formatting(text: &str) -> bool {
+    text.lines().map(|l| l.trim()).any(|l| {
+        l.starts_with("#![") && (l.contains("rustfmt::skip") || l.contains("rustfmt_skip"))
+    })
+}
+
+/// The lines (zero-indexed, inclusive) to format for a selection of lines
+/// `start` to `end`: the selection grown to whole statements or items, so
+/// rustfmt isn't asked to format half of an expression.
+pub fn expand_to_statements(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.is_empty() {
+        return (0, 0);
+    }
+    let depths = bracket_depths(text);
+    let depth_after = |line: usize| depths.get(line + 1).cloned().unwrap_or(0);
+
+    let last = lines.len() - 1;
+    let mut start = cmp::min(start, last);
+    let mut end = cmp::min(cmp::max(start, end), last);
+    // The selection is grown until it starts and ends at the same depth, the
+    // least within the selection, and at the boundaries of statements.
+    let depth = (start..end + 1).map(|l| depths[l]).chain(Some(depth_after(end))).min().unwrap_or(0);
+    while start > 0 && (depths[start] > depth || !ends_statement(lines[start - 1])) {
+        start -= 1;
+    }
+    while end < last && (depth_after(end) > depth || !ends_statement(lines[end])) {
+        end += 1;
+    }
+    (start, end)
+}
+
+// The nesting depth of brackets at the start of each line (and at the end of
+// the text, if it ends with a newline), not counting brackets in strings, char
+// literals and comments.
+fn bracket_depths(text: &str) -> Vec<usize> {
+    let mut depths = vec![0];
+    let mut depth: usize = 0;
+    scan(text, |_, c, lexical| match c {
+        '\n' => depths.push(depth),
+        '(' | '[' | '{' if lexical == Lexical::Code => depth += 1,
+        ')' | ']' | '}' if lexical == Lexical::Code => depth = depth.saturating_sub(1),
+        _ => {}
+    });
+    depths
+}
+
+/// What a character of Rust source is part of, see `scan`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lexical {
+    Code,
+    /// A string or char literal, quotes included.
+    Literal,
+    LineComment,
+    BlockComment,
+}
+
+/// Calls `f` with the byte offset of each character of `text`, Rust source,
+/// the character and what it's part of. Only strings, char literals and
+/// comments are told apart from the code, e.g. raw strings aren't.
+pub fn scan<F: FnMut(usize, char, Lexical)>(text: &str, mut f: F) {
+    let mut block_comments = 0;
+    let mut in_string = false;
+    let mut chars = text.char_indices().peekable();
+    // Passes the next character on as part of `$lexical`.
+    macro_rules! take {
+        ($lexical: expr) => {
+            if let Some((i, c)) = chars.next() {
+                f(i, c, $lexical);
+            }
+        }
+    }
+    while let Some((i, c)) = chars.next() {
+        if block_comments > 0 {
+            f(i, c, Lexical::BlockComment);
+            let next = chars.peek().map(|&(_, c)| c);
+            if c == '*' && next == Some('/') {
+                take!(Lexical::BlockComment);
+                block_comments -= 1;
+            } else if c == '/' && next == Some('*') {
+                take!(Lexical::BlockComment);
+                block_comments += 1;
+            }
+            continue;
+        }
+        if in_string {
+            f(i, c, Lexical::Literal);
+            if c == '\\' && chars.peek().map_or(false, |&(_, c)| c != '\n') {
+                take!(Lexical::Literal);
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        let next = chars.peek().map(|&(_, c)| c);
+        match c {
+            '/' if next == Some('/') => {
+                f(i, c, Lexical::LineComment);
+                while chars.peek().map_or(false, |&(_, c)| c != '\n') {
+                    take!(Lexical::LineComment);
+                }
+            }
+            '/' if next == Some('*') => {
+                f(i, c, Lexical::BlockComment);
+                take!(Lexical::BlockComment);
+                block_comments = 1;
+            }
+            '"' => {
+                f(i, c, Lexical::Literal);
+                in_string = true;
+            }
+            '\'' => {
+                // A char literal, rather than a lifetime.
+                let mut ahead = chars.clone().map(|(_, c)| c);
+                match (ahead.next(), ahead.next()) {
+                    (Some('\\'), _) => {
+                        f(i, c, Lexical::Literal);
+                        take!(Lexical::Literal);
+                        take!(Lexical::Literal);
+                        while chars.peek().map_or(false, |&(_, c)| c != '\'' && c != '\n') {
+                            take!(Lexical::Literal);
+                        }
+                        take!(Lexical::Literal);
+                    }
+                    (Some(next), Some('\'')) if next != '\n' => {
+                        f(i, c, Lexical::Literal);
+                        take!(Lexical::Literal);
+                        take!(Lexical::Literal);
+                    }
+                    _ => f(i, c, Lexical::Code),
+                }
+            }
+            _ => f(i, c, Lexical::Code),
+        }
+    }
+}
+
+/// `line` without its trailing `//` comment, if any. A `//` in a string isn't
+/// one.
+pub fn strip_line_comment(line: &str) -> &str {
+    let mut end = line.len();
+    scan(line, |i, _, lexical| if lexical == Lexical::LineComment && i < end {
+        end = i;
+    });
+    &line[..end]
+}
+
+// Whether the next line starts a new statement or item, judging by how this
+// one ends.
+fn ends_statement(line: &str) -> bool {
+    let code = strip_line_comment(line).trim();
+    code.is_empty() || code.starts_with("#[") || code.ends_with(|c| c == ';' || c == '{' || c == '}' || c == ',')
+}
+
+/// The `--config` overrides passing the client's formatting options to a
+/// rustfmt binary, leaving out those which `rustfmt_toml` (the configuration
+/// of the file, if any) sets.
+pub fn client_overrides(rustfmt_toml: Option<&Path>, hard_tabs: bool, tab_spaces: u64) -> Vec<String> {
+    let mut text = String::new();
+    if let Some(file) = rustfmt_toml {
+        if let Err(e) = File::open(file).and_then(|mut file| file.read_to_string(&mut text)) {
+            debug!("Could not read {:?}: {}", file, e);
+        }
+    }
+    unset_overrides(&text, hard_tabs, tab_spaces)
+}
+
+fn unset_overrides(rustfmt_toml: &str, hard_tabs: bool, tab_spaces: u64) -> Vec<String> {
+    let set = rustfmt_toml.parse::<toml::Value>().ok();
+    let is_set = |key: &str| set.as_ref().and_then(|toml| toml.get(key)).is_some();
+    let mut overrides = vec![];
+    if !is_set("hard_tabs") {
+        overrides.push(format!("hard_tabs={}", hard_tabs));
+    }
+    if !is_set("tab_spaces") {
+        overrides.push(format!("tab_spaces={}", tab_spaces));
+    }
+    overrides
+}
+
+/// Formats `text` with the rustfmt binary `rustfmt`, run in `dir` (so it finds
+/// the configuration of files there) with the given arguments. Fails with the
+/// error output of rustfmt.
+pub fn format_with_binary(rustfmt: &str, text: &str, dir: &Path, args: &[String]) -> Result<String, String> {
+    let mut child = Command::new(rustfmt)
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run `{}`: {}", rustfmt, e))?;
+    // Rustfmt reads all of its input before writing anything. Dropping stdin
+    // closes it.
+    child.stdin.take().unwrap().write_all(text.as_bytes()).map_err(|e| e.to_string())?;
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).map_err(|e| e.to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_owned())
+    }
+}
+
+/// Is the given path (relative to the project root) matched by any of the
+/// given glob patterns? Directories match as well, ignoring all files in them.
+pub fn is_ignored(path: &Path, patterns: &[String]) -> bool {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut prefixes = path.match_indices('/').map(|(i, _)| &path[..i]).collect::<Vec<_>>();
+    prefixes.push(&path);
+
+    patterns.iter().any(|pattern| {
+        prefixes.iter().any(|prefix| glob_matches(pattern.as_bytes(), prefix.as_bytes()))
+    })
+}
+
+// Supports `?`, `*` (within a path segment) and `**` (any number of path
+// segments).
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    if pattern.starts_with(b"**") {
+        let rest = &pattern[2..];
+        let rest = if rest.starts_with(b"/") { &rest[1..] } else { rest };
+        if rest.is_empty() {
+            return true;
+        }
+        return (0..path.len() + 1)
+            .filter(|&i| i == 0 || path[i - 1] == b'/')
+            .any(|i| glob_matches(rest, &path[i..]));
+    }
+
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&b'*'), _) => {
+            (0..path.len() + 1)
+                .take_while(|&i| i == 0 || path[i - 1] != b'/')
+                .any(|i| glob_matches(&pattern[1..], &path[i..]))
+        }
+        (Some(&b'?'), Some(&c)) if c != b'/' => glob_matches(&pattern[1..], &path[1..]),
+        (Some(p), Some(c)) if p == c => glob_matches(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_to_statements() {
+        let text = "fn main() {\n    let x = foo(1,\n                2);\n    let s = \"{\";\n    bar(x);\n}\n\nfn baz() {}\n";
+        // Within a call, grown to the statement.
+        assert_eq!(expand_to_statements(text, 2, 2), (1, 2));
+        // A bracket in a string doesn't count.
+        assert_eq!(expand_to_statements(text, 3, 4), (3, 4));
+        // Across the end of the function, grown to the function.
+        assert_eq!(expand_to_statements(text, 4, 6), (0, 6));
+        assert_eq!(expand_to_statements(text, 7, 20), (7, 7));
+        assert_eq!(bracket_depths("a('{', '\\'', 'b) {\n}\n"), vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn test_strip_line_comment() {
+        assert_eq!(strip_line_comment("let x = 1; // one"), "let x = 1; ");
+        assert_eq!(strip_line_comment("let url = \"http://example.com\";"), "let url = \"http://example.com\";");
+        assert_eq!(strip_line_comment("let c = '/'; /* a */ f(c) // b"), "let c = '/'; /* a */ f(c) ");
+        assert!(ends_statement("let url = \"http://example.com\"; // fetched"));
+        assert!(!ends_statement("let url = \"http://example.com\" // later"));
+    }
+
+    #[test]
+    fn test_skips_formatting() {
+        assert!(skips_formatting("//! Generated.\n#![rustfmt::skip]\n\nfn main() {}\n"));
+        assert!(skips_formatting("#![cfg_attr(rustfmt, rustfmt_skip)]\n"));
+        assert!(!skips_formatting("#[rustfmt::skip]\nfn table() {}\n"));
+        assert!(!skips_formatting("fn main() {}\n"));
+    }
+
+    #[test]
+    fn test_unset_overrides() {
+        assert_eq!(unset_overrides("", true, 4), ["hard_tabs=true", "tab_spaces=4"]);
+        assert_eq!(unset_overrides("tab_spaces = 2\nmax_width = 80\n", false, 4), ["hard_tabs=false"]);
+        // An invalid configuration is rustfmt's to report.
+        assert_eq!(unset_overrides("tab_spaces =", false, 4), ["hard_tabs=false", "tab_spaces=4"]);
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let patterns = vec!["src/generated".to_owned(), "**/*_pb.rs".to_owned(), "tests/?.rs".to_owned()];
+        assert!(is_ignored(Path::new("src/generated/foo.rs"), &patterns));
+        assert!(is_ignored(Path::new("foo_pb.rs"), &patterns));
+        assert!(is_ignored(Path::new("src/a/b/foo_pb.rs"), &patterns));
+        assert!(is_ignored(Path::new("tests/a.rs"), &patterns));
+        assert!(!is_ignored(Path::new("tests/ab.rs"), &patterns));
+        assert!(!is_ignored(Path::new("src/lib.rs"), &patterns));
+        assert!(!is_ignored(Path::new("src/generated.rs"), &patterns));
+
+        let patterns = vec!["src/*.rs".to_owned(), "benches/**".to_owned()];
+        assert!(is_ignored(Path::new("src/lib.rs"), &patterns));
+        assert!(!is_ignored(Path::new("src/a/lib.rs"), &patterns));
+        assert!(is_ignored(Path::new("benches/a/b.rs"), &patterns));
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Removing save-analysis files which are no longer needed.
+//!
+//! Every build of a crate with different dependencies or flags gets a new file,
+//! the old ones are never removed by Cargo. We remove:
+//!
+//! * the files of crates which are no longer in the dependency graph (the
+//!   crates of the targets of the packages Cargo resolved, see `LibNames`), and
+//! * files superseded by a newer one for the same crate, once they are older
+//!   than the `analysis_gc_age_days` option (another configuration may still
+//!   use them).
+//!
+//! This runs on startup and on shutdown. On startup Cargo didn't resolve the
+//! dependency graph yet, so only superseded files are removed then. The newest
+//! file of a crate in the graph is always kept, Cargo doesn't rebuild fresh
+//! dependencies, so it couldn't be recreated.
+
+use actions::memory::{analysis_files, AnalysisFile};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Removes the garbage in the given save-analysis directory, including the
+/// files of crates not in `crates` (the crate names of the dependency graph)
+/// if that is known. Returns the number of bytes reclaimed.
+pub fn collect_garbage(analysis_dir: &Path, crates: Option<&HashSet<String>>, max_age_days: u64) -> u64 {
+    let max_age = Duration::from_secs(max_age_days * 24 * 60 * 60);
+    let garbage = garbage_files(analysis_files(analysis_dir), crates, max_age, SystemTime::now());
+
+    let mut reclaimed = 0;
+    let mut removed = 0;
+    for file in garbage {
+        match fs::remove_file(&file.path) {
+            Ok(()) => {
+                reclaimed += file.size;
+                removed += 1;
+            }
+            Err(e) => debug!("analysis GC: could not remove {:?}: {}", file.path, e),
+        }
+    }
+    if removed > 0 {
+        info!("analysis GC: removed {} save-analysis files, reclaimed {} bytes", removed, reclaimed);
+    }
+    reclaimed
+}
+
+// The files to remove. Without `crates` only superseded files are.
+fn garbage_files(files: Vec<AnalysisFile>,
+                 crates: Option<&HashSet<String>>,
+                 max_age: Duration,
+                 now: SystemTime)
+                 -> Vec<AnalysisFile> {
+    let mut newest: HashMap<String, SystemTime> = HashMap::new();
+    for file in &files {
+        let entry = newest.entry(file.crate_name.clone()).or_insert(file.modified);
+        if file.modified > *entry {
+            *entry = file.modified;
+        }
+    }
+
+    files.into_iter().filter(|file| {
+        // Build scripts aren't targets Cargo resolves.
+        let unused = file.crate_name != "build_script_build" &&
+                     crates.map_or(false, |crates| !crates.contains(&file.crate_name));
+        let superseded = file.modified < newest[&file.crate_name] &&
+                         now.duration_since(file.modified).map_or(false, |age| age > max_age);
+        unused || superseded
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn file(crate_name: &str, days_old: u64, now: SystemTime) -> AnalysisFile {
+        AnalysisFile {
+            path: PathBuf::from(format!("lib{}-{}.json", crate_name, days_old)),
+            crate_name: crate_name.to_owned(),
+            size: 1,
+            modified: now - Duration::from_secs(days_old * 24 * 60 * 60),
+        }
+    }
+
+    #[test]
+    fn test_garbage_files() {
+        let now = SystemTime::now();
+        let files = || vec![file("foo", 0, now), file("foo", 3, now), file("foo", 10, now),
+                            file("bar", 20, now), file("removed", 0, now), file("build_script_build", 0, now)];
+        let crates: HashSet<_> = ["foo", "bar"].iter().map(|s| s.to_string()).collect();
+        let max_age = Duration::from_secs(7 * 24 * 60 * 60);
+        let paths = |garbage: Vec<AnalysisFile>| garbage.into_iter().map(|f| f.path).collect::<Vec<_>>();
+
+        assert_eq!(paths(garbage_files(files(), Some(&crates), max_age, now)),
+                   [PathBuf::from("libfoo-10.json"), PathBuf::from("libremoved-0.json")]);
+        // The newest file of a crate stays, however old it is, while the
+        // dependency graph isn't known.
+        assert_eq!(paths(garbage_files(files(), None, max_age, now)), [PathBuf::from("libfoo-10.json")]);
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Describing where the item under the cursor is defined, for hovers: its
+//! fully-qualified path, its crate and the feature it is gated on. Resolving a
+//! completion reads the full signature and doc comment of its definition from
+//! the source, see `definition_signature` and `doc_comment`.
+//!
+//! Primitive types and keywords have no analysis data, and nothing has before
+//! the first build. Hovering them shows a short description from the table
+//! below instead of nothing.
+
+use actions::format::strip_line_comment;
+use actions::inactive_code::required_feature;
+use span;
+use vfs::Vfs;
+
+use std::path::Path;
+
+/// Attributes and doc comments above a definition which are looked at.
+const MAX_ATTRIBUTE_LINES: u32 = 20;
+
+/// Lines of a signature which are looked at.
+const MAX_SIGNATURE_LINES: usize = 30;
+
+/// Keywords of items which have a signature worth showing.
+const SIGNATURE_KEYWORDS: &[&str] = &[
+    "fn", "struct", "enum", "union", "trait", "type", "const", "static", "mod", "macro_rules!",
+];
+
+/// Descriptions of the primitive types.
+const PRIMITIVES: &[(&str, &str)] = &[
+    ("bool", "The boolean type."),
+    ("char", "A character type, a Unicode scalar value."),
+    ("str", "String slices, UTF-8 encoded."),
+    ("i8", "The 8-bit signed integer type."),
+    ("i16", "The 16-bit signed integer type."),
+    ("i32", "The 32-bit signed integer type."),
+    ("i64", "The 64-bit signed integer type."),
+    ("i128", "The 128-bit signed integer type."),
+    ("isize", "The pointer-sized signed integer type."),
+    ("u8", "The 8-bit unsigned integer type."),
+    ("u16", "The 16-bit unsigned integer type."),
+    ("u32", "The 32-bit unsigned integer type."),
+    ("u64", "The 64-bit unsigned integer type."),
+    ("u128", "The 128-bit unsigned integer type."),
+    ("usize", "The pointer-sized unsigned integer type."),
+    ("f32", "The 32-bit floating point type."),
+    ("f64", "The 64-bit floating point type."),
+];
+
+/// Descriptions of the keywords.
+const KEYWORDS: &[(&str, &str)] = &[
+    ("as", "Casts between types, or renames an import."),
+    ("break", "Exits a loop early, optionally with a value (`loop` only)."),
+    ("const", "Defines a compile-time constant, or marks a raw pointer as immutable (`*const T`)."),
+    ("continue", "Skips to the next iteration of a loop."),
+    ("crate", "Links an external crate (`extern crate`), or restricts visibility to the crate (`pub(crate)`)."),
+    ("else", "The branch taken when the condition of an `if` is false."),
+    ("enum", "Defines a type which is one of several variants."),
+    ("extern", "Links an external crate or declares foreign functions, or sets the ABI of a function."),
+    ("false", "The boolean value false."),
+    ("fn", "Defines a function, or a function pointer type."),
+    ("for", "Loops over the items of an iterator, or introduces higher-ranked lifetimes."),
+    ("if", "Branches on a condition."),
+    ("impl", "Implements inherent methods or a trait for a type."),
+    ("in", "Separates the pattern and the iterator of a `for` loop."),
+    ("let", "Binds a value to a pattern."),
+    ("loop", "Loops until a `break`."),
+    ("match", "Branches on the pattern a value matches."),
+    ("mod", "Declares a module."),
+    ("move", "Makes a closure take ownership of the variables it captures."),
+    ("mut", "Makes a binding or a reference mutable."),
+    ("pub", "Makes an item visible outside of its module."),
+    ("ref", "Binds by reference in a pattern."),
+    ("return", "Returns a value from a function."),
+    ("self", "The receiver of a method, or the current module in a path."),
+    ("Self", "The implementing type within a trait or `impl` block."),
+    ("static", "Defines a global variable, or the `'static` lifetime."),
+    ("struct", "Defines a type with named or positional fields."),
+    ("super", "The parent of the current module in a path."),
+    ("trait", "Defines a set of methods types can implement."),
+    ("true", "The boolean value true."),
+    ("type", "Defines a type alias, or an associated type."),
+    ("unsafe", "Marks code which the compiler can't check for memory safety."),
+    ("use", "Imports items into the current scope."),
+    ("where", "Introduces bounds on type parameters."),
+    ("while", "Loops while a condition holds."),
+];
+
+/// Documentation of a primitive type or keyword.
+#[derive(Debug, PartialEq)]
+pub struct BuiltinDocs {
+    /// What the primitive type or keyword is.
+    pub docs: String,
+    /// The page of a primitive type in the standard library docs.
+    pub doc_url: Option<String>,
+}
+
+/// The documentation of `word`, if it is a primitive type or a keyword.
+pub fn builtin_docs(word: &str) -> Option<BuiltinDocs> {
+    if let Some(&(name, docs)) = PRIMITIVES.iter().find(|&&(name, _)| name == word) {
+        return Some(BuiltinDocs {
+            docs: format!("Primitive type `{}`: {}", name, docs),
+            doc_url: Some(format!("https://doc.rust-lang.org/std/primitive.{}.html", name)),
+        });
+    }
+    KEYWORDS.iter().find(|&&(name, _)| name == word).map(|&(name, docs)| {
+        BuiltinDocs {
+            docs: format!("Keyword `{}`: {}", name, docs),
+            doc_url: None,
+        }
+    })
+}
+
+/// The identifier (or keyword) around char column `col` of `line`.
+pub fn word_at(line: &str, col: usize) -> Option<&str> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let (byte_col, _) = line.char_indices().nth(col).or_else(|| line.char_indices().last())?;
+    let start = line[..byte_col].rfind(|c: char| !is_ident(c)).map_or(0, |i| i + 1);
+    let end = line[byte_col..].find(|c: char| !is_ident(c)).map_or(line.len(), |i| byte_col + i);
+    if start >= end {
+        None
+    } else {
+        Some(&line[start..end])
+    }
+}
+
+/// A one line summary of where a definition comes from, e.g. "`std::fmt::Debug`
+/// (crate `std`)". `qualname` is the path from the analysis data, which starts
+/// with `::` for items of the crate root.
+pub fn definition_summary(qualname: &str, crate_name: Option<&str>, feature: Option<&str>, markdown: bool) -> String {
+    let quote = |s: &str| if markdown { format!("`{}`", s) } else { s.to_owned() };
+
+    let path = match crate_name {
+        Some(crate_name) if qualname.starts_with("::") => format!("{}{}", crate_name, qualname),
+        _ => qualname.to_owned(),
+    };
+    let mut summary = quote(&path);
+    match (crate_name, feature) {
+        (Some(crate_name), Some(feature)) => {
+            summary.push_str(&format!(" (crate {}, feature {})", quote(crate_name), quote(feature)))
+        }
+        (Some(crate_name), None) => summary.push_str(&format!(" (crate {})", quote(crate_name))),
+        (None, Some(feature)) => summary.push_str(&format!(" (feature {})", quote(feature))),
+        (None, None) => {}
+    }
+    summary
+}
+
+/// The feature the definition starting on `row` of `file` is gated on, from a
+/// `#[cfg(feature = "...")]` attribute above it.
+pub fn feature_gate(vfs: &Vfs, file: &Path, row: span::Row<span::ZeroIndexed>) -> Option<String> {
+    let mut row = row.0;
+    let stop = row.saturating_sub(MAX_ATTRIBUTE_LINES);
+    while row > stop {
+        row -= 1;
+        let line = vfs.load_line(file, span::Row::new_zero_indexed(row)).ok()?;
+        let line = line.trim();
+        if line.starts_with("#[") {
+            if let Some(feature) = cfg_feature(line) {
+                return Some(feature);
+            }
+        } else if !line.starts_with("//") {
+            return None;
+        }
+    }
+    None
+}
+
+// The feature a `cfg` attribute requires, e.g. `#[cfg(feature = "serde")]` or
+// `#[cfg(all(unix, feature = "serde"))]`, see `required_feature`.
+fn cfg_feature(attribute: &str) -> Option<String> {
+    const PREFIX: &'static str = "#[cfg(";
+    if !attribute.starts_with(PREFIX) {
+        return None;
+    }
+    let end = attribute.rfind(")]")?;
+    if end < PREFIX.len() {
+        return None;
+    }
+    required_feature(&attribute[PREFIX.len()..end])
+}
+
+/// The signature of the item defined at byte `offset` of `text`, with its
+/// generics and where clause, on one line: everything from the start of the
+/// line up to its body (or the `;`). `None` if no item is defined there, e.g.
+/// for local variables and fields.
+pub fn definition_signature(text: &str, offset: usize) -> Option<String> {
+    let start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let mut lines = text[start..].lines().take(MAX_SIGNATURE_LINES);
+    let first = lines.next()?;
+    if !first.split_whitespace().any(|w| SIGNATURE_KEYWORDS.contains(&w)) {
+        return None;
+    }
+
+    let mut signature = String::new();
+    let mut depth = 0i32;
+    'lines: for line in Some(first).into_iter().chain(lines) {
+        let code = strip_line_comment(line);
+        for c in code.chars() {
+            match c {
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth -= 1,
+                '{' | ';' if depth <= 0 => break 'lines,
+                _ => {}
+            }
+            signature.push(c);
+        }
+        signature.push(' ');
+    }
+
+    let signature = signature.split_whitespace().collect::<Vec<_>>().join(" ")
+        .replace("( ", "(")
+        .replace(", )", ")")
+        .replace(" )", ")");
+    Some(signature.trim_right_matches(',').to_owned())
+}
+
+/// The doc comment (`///` lines) above the definition at byte `offset` of
+/// `text`, as markdown. Attributes between the comment and the definition are
+/// skipped.
+pub fn doc_comment(text: &str, offset: usize) -> Option<String> {
+    let row = text[..offset].matches('\n').count();
+    let mut docs = vec![];
+    for line in text.lines().take(row).collect::<Vec<_>>().into_iter().rev() {
+        let line = line.trim();
+        if line.starts_with("///") && !line.starts_with("////") {
+            let doc = &line["///".len()..];
+            docs.push(if doc.starts_with(' ') { &doc[1..] } else { doc });
+        } else if !line.starts_with("#[") {
+            break;
+        }
+    }
+    if docs.is_empty() {
+        return None;
+    }
+    docs.reverse();
+    Some(docs.join("\n"))
+}
+
+/// Documentation for clients which don't render markdown: the code fences are
+/// removed, the rest is readable as is.
+pub fn plain_text(docs: &str) -> String {
+    docs.lines()
+        .filter(|line| !line.trim_left().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_definition_summary() {
+        assert_eq!(definition_summary("::fmt::Debug", Some("std"), None, true), "`std::fmt::Debug` (crate `std`)");
+        assert_eq!(definition_summary("::de::Deserialize", Some("serde"), Some("std"), false),
+                   "serde::de::Deserialize (crate serde, feature std)");
+        assert_eq!(definition_summary("<Foo as Bar>::baz", None, None, true), "`<Foo as Bar>::baz`");
+    }
+
+    #[test]
+    fn test_cfg_feature() {
+        assert_eq!(cfg_feature(r#"#[cfg(feature = "serde")]"#), Some("serde".to_owned()));
+        assert_eq!(cfg_feature(r#"#[cfg(all(unix, feature="std"))]"#), Some("std".to_owned()));
+        assert_eq!(cfg_feature(r#"#[cfg(target_feature = "sse2")]"#), None);
+        assert_eq!(cfg_feature(r#"#[cfg(not(feature = "std"))]"#), None);
+        assert_eq!(cfg_feature("#[cfg(test)]"), None);
+        assert_eq!(cfg_feature("#[derive(Debug)]"), None);
+    }
+
+    #[test]
+    fn test_builtin_docs() {
+        let docs = builtin_docs("u32").unwrap();
+        assert_eq!(docs.docs, "Primitive type `u32`: The 32-bit unsigned integer type.");
+        assert_eq!(docs.doc_url, Some("https://doc.rust-lang.org/std/primitive.u32.html".to_owned()));
+        assert_eq!(builtin_docs("impl").unwrap().doc_url, None);
+        assert_eq!(builtin_docs("HashMap"), None);
+    }
+
+    #[test]
+    fn test_word_at() {
+        let line = "    let x: u32 = foo_bar();";
+        assert_eq!(word_at(line, 5), Some("let"));
+        assert_eq!(word_at(line, 12), Some("u32"));
+        assert_eq!(word_at(line, 20), Some("foo_bar"));
+        assert_eq!(word_at(line, 8), Some("x"));
+        assert_eq!(word_at(line, 1), None);
+    }
+
+    #[test]
+    fn test_definition_signature() {
+        let text = "/// Adds.\npub fn add<T>(\n    a: T, // the first\n    b: T,\n) -> T\nwhere\n    T: Add,\n{\n    a + b\n}\n";
+        let offset = text.find("add").unwrap();
+        assert_eq!(definition_signature(text, offset), Some("pub fn add<T>(a: T, b: T) -> T where T: Add".to_owned()));
+
+        let text = "trait Foo {\n    fn foo(&self) -> Vec<[u8; 4]>;\n}\n";
+        let offset = text.find("foo").unwrap();
+        assert_eq!(definition_signature(text, offset), Some("fn foo(&self) -> Vec<[u8; 4]>".to_owned()));
+
+        let text = "fn main() {\n    let x = 1;\n}\n";
+        assert_eq!(definition_signature(text, text.find('x').unwrap()), None);
+    }
+
+    #[test]
+    fn test_doc_comment() {
+        let text = "//// Not docs.\n/// Adds `a` and `b`.\n///\n///     indented\n#[inline]\npub fn add() {}\n";
+        let offset = text.find("add(").unwrap();
+        assert_eq!(doc_comment(text, offset), Some("Adds `a` and `b`.\n\n    indented".to_owned()));
+        assert_eq!(doc_comment("fn f() {}\n", 3), None);
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(plain_text("Adds one.\n\n```rust\nassert_eq!(inc(1), 2);\n```"),
+                   "Adds one.\n\nassert_eq!(inc(1), 2);");
+    }
+}
@@ -0,0 +1,672 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Inserting `use` statements according to the `import_granularity`,
+//! `import_placement` and `import_blank_line` options.
+//!
+//! Every quick fix adding an import goes through `import_edit`, so imports are
+//! placed the same way whoever suggested them. The compiler suggests imports
+//! for unresolved names as `use path;\n` at a place of its choosing, those
+//! suggestions are rewritten after each build (see `imported_path`).
+//!
+//! The source is only scanned line by line: braces are counted to find the
+//! scope of inline modules, ignoring those in `//` comments (but not those in
+//! strings). Glob imports expanded by `rustWorkspace/deglob` are found the
+//! same way, see `glob_imports`.
+
+use actions::format::strip_line_comment;
+use config::{Config, ImportGranularity, ImportPlacement};
+use lsp_data::{Position, Range};
+
+/// How imports are inserted.
+#[derive(Clone, Copy, Debug)]
+pub struct ImportPolicy {
+    pub granularity: ImportGranularity,
+    pub placement: ImportPlacement,
+    pub blank_line: bool,
+}
+
+impl ImportPolicy {
+    /// The policy given by the configuration.
+    pub fn from_config(config: &Config) -> ImportPolicy {
+        ImportPolicy {
+            granularity: config.import_granularity,
+            placement: config.import_placement,
+            blank_line: config.import_blank_line,
+        }
+    }
+}
+
+/// An edit of the source adding an import. The range is in chars.
+#[derive(Debug, PartialEq)]
+pub struct ImportEdit {
+    pub range: Range,
+    pub new_text: String,
+}
+
+/// The path imported by a suggested replacement, if it is a single `use`
+/// statement such as `use std::collections::HashMap;\n`.
+pub fn imported_path(new_text: &str) -> Option<&str> {
+    let text = new_text.trim();
+    if !text.starts_with("use ") || !text.ends_with(';') || text.contains('\n') {
+        return None;
+    }
+    let path = text["use ".len()..text.len() - 1].trim();
+    if path.is_empty() || path.contains('{') || path.contains('*') {
+        return None;
+    }
+    Some(path)
+}
+
+/// The edit importing `path` into the source `text`, where the imported item
+/// is used on line `used_at`.
+pub fn import_edit(text: &str, path: &str, used_at: usize, policy: &ImportPolicy) -> ImportEdit {
+    let lines: Vec<&str> = text.lines().collect();
+    let depths = brace_depths(&lines);
+    let scope = match policy.placement {
+        ImportPlacement::Module => module_scope(&lines, &depths, used_at),
+        ImportPlacement::Top | ImportPlacement::Group => Scope::file(&lines),
+    };
+    let uses = use_statements(&lines, &depths, &scope);
+
+    if policy.granularity == ImportGranularity::Module {
+        if let Some(edit) = merge_into_existing(&lines, &uses, path) {
+            return edit;
+        }
+    }
+
+    let header_end = header_end(&lines, &scope);
+    let line = match policy.placement {
+        ImportPlacement::Top => uses.first().map_or(header_end, |u| u.first),
+        ImportPlacement::Group | ImportPlacement::Module => {
+            let group = import_group(path);
+            uses.iter()
+                .filter(|u| import_group(&u.path) == group)
+                .last()
+                .or_else(|| uses.last())
+                .map_or(header_end, |u| u.last + 1)
+        }
+    };
+
+    let mut new_text = format!("{}use {};\n", scope.indent, path);
+    if line >= lines.len() && !text.is_empty() && !text.ends_with('\n') {
+        new_text.insert(0, '\n');
+    }
+    if uses.is_empty() && policy.blank_line && lines.get(line).map_or(false, |l| !l.trim().is_empty()) {
+        new_text.push('\n');
+    }
+    let position = Position::new(line as u64, 0);
+    ImportEdit {
+        range: Range { start: position, end: position },
+        new_text,
+    }
+}
+
+/// The line and column (in chars) of the `*` of each glob import in `text`,
+/// including those in nested groups such as `use a::{b::*, c};`.
+pub fn glob_imports(text: &str) -> Vec<(usize, usize)> {
+    let mut result = vec![];
+    scan_use_statements(text, |row, col, c| {
+        if c == '*' {
+            result.push((row, col));
+        }
+    });
+    result
+}
+
+/// Whether `name` is imported (or renamed to) by a `use` statement anywhere in
+/// `text`. What glob imports import isn't known.
+pub fn is_imported(text: &str, name: &str) -> bool {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut found = false;
+    let mut word = String::new();
+    let mut last_row = 0;
+    scan_use_statements(text, |row, _, c| {
+        if is_ident_char(c) && row == last_row {
+            word.push(c);
+        } else {
+            found = found || word == name;
+            word.clear();
+            if is_ident_char(c) {
+                word.push(c);
+            }
+        }
+        last_row = row;
+    });
+    found || word == name
+}
+
+// Calls `f` with the line, column (in chars) and char of every char of the
+// `use` statements of `text`, from after the `use` keyword up to and including
+// the `;`. `use` statements may span several lines.
+fn scan_use_statements<F: FnMut(usize, usize, char)>(text: &str, mut f: F) {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut in_use = false;
+    // The rest of the keyword, once it was found.
+    let mut skip = 0;
+    for (row, line) in text.lines().enumerate() {
+        let code = strip_line_comment(line);
+        let mut previous = None;
+        for (col, (i, c)) in code.char_indices().enumerate() {
+            if skip > 0 {
+                skip -= 1;
+            } else if in_use {
+                f(row, col, c);
+                in_use = c != ';';
+            } else if code[i..].starts_with("use ") && !previous.map_or(false, &is_ident_char) {
+                in_use = true;
+                skip = "se ".len();
+            }
+            previous = Some(c);
+        }
+    }
+}
+
+/// The text replacing the `*` of a glob import, from the names it imports as
+/// listed by the analysis data (`Foo, bar`): the names sorted, and in braces
+/// unless there is exactly one.
+pub fn glob_replacement(names: &str) -> String {
+    group(names.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()).collect())
+}
+
+/// Merges the `use` statements on lines `start..=end` into a single nested
+/// one, e.g. `use foo::a;` and `use foo::b;` into `use foo::{a, b};`. Without a
+/// selection (`start == end`), the statement on that line is merged with the
+/// statements around it which import from the same module. `None` if there
+/// is nothing to merge.
+pub fn nest_imports(text: &str, start: usize, end: usize) -> Option<ImportEdit> {
+    let lines: Vec<&str> = text.lines().collect();
+    let depths = brace_depths(&lines);
+    let scope = module_scope(&lines, &depths, start);
+    let uses: Vec<UseStatement> = use_statements(&lines, &depths, &scope)
+        .into_iter()
+        .filter(|u| is_rewritable(&lines, u))
+        .collect();
+
+    let (first, last) = if start == end {
+        let at = uses.iter().position(|u| u.first <= start && start <= u.last)?;
+        let module = parent_module(&uses[at].path)?;
+        let same_module = |u: &UseStatement| parent_module(&u.path).as_ref() == Some(&module);
+        let mergeable = |i: usize, j: usize| uses[i].last + 1 == uses[j].first && same_module(&uses[i]) && same_module(&uses[j]);
+        let mut first = at;
+        while first > 0 && mergeable(first - 1, first) {
+            first -= 1;
+        }
+        let mut last = at;
+        while last + 1 < uses.len() && mergeable(last, last + 1) {
+            last += 1;
+        }
+        (first, last)
+    } else {
+        let first = uses.iter().position(|u| u.first >= start)?;
+        let last = uses.iter().rposition(|u| u.last <= end)?;
+        // Only blank lines may be between the statements, they are removed.
+        let adjacent = (first..last).all(|i| {
+            (uses[i].last + 1..uses[i + 1].first).all(|l| lines[l].trim().is_empty())
+        });
+        if last <= first || !adjacent {
+            return None;
+        }
+        (first, last)
+    };
+    let selected = &uses[first..last + 1];
+    if selected.len() < 2 || selected.iter().any(|u| visibility(lines[u.first]) != visibility(lines[selected[0].first])) {
+        return None;
+    }
+
+    // The longest module all statements import from.
+    let segments: Vec<Vec<&str>> = selected.iter().map(|u| top_level_split(&u.path, "::")).collect();
+    let common = (0..segments[0].len() - 1)
+        .take_while(|&i| segments.iter().all(|s| i + 1 < s.len() && s[i] == segments[0][i]))
+        .count();
+    if common == 0 || (common == 1 && segments[0][0].is_empty()) {
+        return None;
+    }
+    let mut names: Vec<String> = vec![];
+    for s in &segments {
+        let rest = &s[common..];
+        if rest.len() == 1 && rest[0].starts_with('{') && rest[0].ends_with('}') {
+            let list = top_level_split(&rest[0][1..rest[0].len() - 1], ",");
+            names.extend(list.into_iter().filter(|n| !n.is_empty()).map(|n| n.to_owned()));
+        } else {
+            names.push(rest.join("::"));
+        }
+    }
+    let path = format!("{}::{}", segments[0][..common].join("::"), group(names.iter().map(|n| &**n).collect()));
+
+    Some(replace_statements(&lines, selected[0].first, selected[selected.len() - 1].last, &[path]))
+}
+
+/// Splits the nested `use` statement on line `line` into one statement per
+/// imported item, e.g. `use foo::{a, b::{c}};` into `use foo::a;` and `use
+/// foo::b::c;`. `None` if there is no such statement.
+pub fn flatten_imports(text: &str, line: usize) -> Option<ImportEdit> {
+    let lines: Vec<&str> = text.lines().collect();
+    let depths = brace_depths(&lines);
+    let scope = module_scope(&lines, &depths, line);
+    let uses = use_statements(&lines, &depths, &scope);
+    let statement = uses.iter().find(|u| u.first <= line && line <= u.last)?;
+    if !statement.path.contains('{') || !is_rewritable(&lines, statement) {
+        return None;
+    }
+    Some(replace_statements(&lines, statement.first, statement.last, &flat_paths(&statement.path)))
+}
+
+// The names of a `{...}` group, sorted (`self` first), or just the name if
+// there is only one.
+fn group(mut names: Vec<&str>) -> String {
+    names.sort_by_key(|n| (*n != "self", *n));
+    names.dedup();
+    if names.len() == 1 {
+        names[0].to_owned()
+    } else {
+        format!("{{{}}}", names.join(", "))
+    }
+}
+
+// The lines of a module: `start..end`, at brace depth `depth`.
+struct Scope {
+    start: usize,
+    end: usize,
+    depth: usize,
+    indent: String,
+}
+
+impl Scope {
+    fn file(lines: &[&str]) -> Scope {
+        Scope {
+            start: 0,
+            end: lines.len(),
+            depth: 0,
+            indent: String::new(),
+        }
+    }
+}
+
+// A `use` statement on lines `first..=last`, importing `path` (which may end
+// in a `{...}` list).
+struct UseStatement {
+    first: usize,
+    last: usize,
+    path: String,
+}
+
+// The brace depth at the start of each line.
+fn brace_depths(lines: &[&str]) -> Vec<usize> {
+    let mut depth = 0usize;
+    lines.iter().map(|line| {
+        let start = depth;
+        let code = strip_line_comment(line);
+        for c in code.chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        start
+    }).collect()
+}
+
+// The narrowest inline module (`mod foo { ... }`) containing line `used_at`,
+// or the whole file.
+fn module_scope(lines: &[&str], depths: &[usize], used_at: usize) -> Scope {
+    let mut scope = Scope::file(lines);
+    for (i, line) in lines.iter().enumerate().take(used_at) {
+        if !is_inline_module(line) {
+            continue;
+        }
+        let depth = depths[i] + 1;
+        // The closing brace is the first line after which we are outside the
+        // module again.
+        let end = (i + 1..lines.len())
+            .find(|&j| depths.get(j + 1).map_or(true, |&d| d < depth))
+            .unwrap_or(lines.len());
+        if end >= used_at && depth > scope.depth {
+            let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+            scope = Scope {
+                start: i + 1,
+                end,
+                depth,
+                indent: indent + "    ",
+            };
+        }
+    }
+    scope
+}
+
+fn is_inline_module(line: &str) -> bool {
+    let item = without_visibility(line.trim());
+    item.starts_with("mod ") && item.ends_with('{')
+}
+
+fn without_visibility(item: &str) -> &str {
+    if !item.starts_with("pub") {
+        return item;
+    }
+    let rest = item["pub".len()..].trim_left();
+    if rest.starts_with('(') {
+        match rest.find(')') {
+            Some(i) => rest[i + 1..].trim_left(),
+            None => item,
+        }
+    } else {
+        rest
+    }
+}
+
+// The `use` statements directly in the scope.
+fn use_statements(lines: &[&str], depths: &[usize], scope: &Scope) -> Vec<UseStatement> {
+    let mut result = vec![];
+    let mut i = scope.start;
+    while i < scope.end {
+        let item = without_visibility(lines[i].trim());
+        if depths[i] != scope.depth || !item.starts_with("use ") {
+            i += 1;
+            continue;
+        }
+        let first = i;
+        let mut statement = item.to_owned();
+        while !statement.contains(';') && i + 1 < scope.end {
+            i += 1;
+            statement.push_str(lines[i].trim());
+        }
+        let path = statement["use ".len()..].trim_right_matches(';').trim().to_owned();
+        result.push(UseStatement { first, last: i, path });
+        i += 1;
+    }
+    result
+}
+
+// The first line after the comments, inner attributes and `extern crate`s at
+// the start of the scope.
+fn header_end(lines: &[&str], scope: &Scope) -> usize {
+    let mut end = scope.start;
+    for (i, line) in lines.iter().enumerate().take(scope.end).skip(scope.start) {
+        let line = line.trim();
+        let item = without_visibility(line);
+        if line.starts_with("//") || line.starts_with("#!") || line.starts_with("#[macro_use]") ||
+           item.starts_with("extern crate ") {
+            end = i + 1;
+        } else if !line.is_empty() {
+            break;
+        }
+    }
+    // Keep a blank line after the header.
+    if end > scope.start && lines.get(end).map_or(false, |l| l.trim().is_empty()) && end < scope.end {
+        end += 1;
+    }
+    end
+}
+
+// Merges the import into a single line `use` statement of the same module, if
+// there is one.
+fn merge_into_existing(lines: &[&str], uses: &[UseStatement], path: &str) -> Option<ImportEdit> {
+    let split = path.rfind("::")?;
+    let (module, name) = (&path[..split], &path[split + 2..]);
+    let prefix = format!("{}::", module);
+
+    for u in uses.iter().filter(|u| u.first == u.last && u.path.starts_with(&prefix)) {
+        let rest = &u.path[prefix.len()..];
+        let mut names: Vec<&str> = if rest.starts_with('{') && rest.ends_with('}') {
+            let list = &rest[1..rest.len() - 1];
+            if list.contains('{') {
+                continue;
+            }
+            list.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()).collect()
+        } else if rest.contains("::") || rest.contains('{') {
+            continue;
+        } else {
+            vec![rest]
+        };
+        if !names.contains(&name) {
+            names.push(name);
+        }
+
+        let line = lines[u.first];
+        let new_line = line.replacen(&*u.path, &format!("{}{{{}}}", prefix, names.join(", ")), 1);
+        return Some(ImportEdit {
+            range: Range {
+                start: Position::new(u.first as u64, 0),
+                end: Position::new(u.first as u64, line.chars().count() as u64),
+            },
+            new_text: new_line,
+        });
+    }
+    None
+}
+
+// Whether the statement can be rewritten: it has no attributes (which would
+// then apply to other imports too) and no other code on its lines.
+fn is_rewritable(lines: &[&str], statement: &UseStatement) -> bool {
+    let attributed = statement.first > 0 && lines[statement.first - 1].trim().starts_with("#[");
+    !attributed && !statement.path.contains(';') && !statement.path.contains("//") &&
+        lines[statement.last].trim_right().ends_with(';')
+}
+
+// The visibility of the item on the line, e.g. `pub(crate) `.
+fn visibility(line: &str) -> &str {
+    let item = line.trim_left();
+    &item[..item.len() - without_visibility(item).len()]
+}
+
+// The module a path imports from, e.g. `foo::bar` for `foo::bar::{a, b}`.
+fn parent_module(path: &str) -> Option<String> {
+    let segments = top_level_split(path, "::");
+    if segments.len() < 2 {
+        return None;
+    }
+    Some(segments[..segments.len() - 1].join("::"))
+}
+
+// The paths imported by a `use` statement, e.g. `foo::a` and `foo::b::c` for
+// `foo::{a, b::{c}}`.
+fn flat_paths(path: &str) -> Vec<String> {
+    let segments = top_level_split(path, "::");
+    let last = segments[segments.len() - 1];
+    if !last.starts_with('{') || !last.ends_with('}') {
+        return vec![path.to_owned()];
+    }
+    let module = segments[..segments.len() - 1].join("::");
+    let mut result = vec![];
+    for name in top_level_split(&last[1..last.len() - 1], ",").into_iter().filter(|n| !n.is_empty()) {
+        if name == "self" {
+            result.push(module.clone());
+        } else if segments.len() == 1 {
+            result.extend(flat_paths(name));
+        } else {
+            result.extend(flat_paths(name).into_iter().map(|p| format!("{}::{}", module, p)));
+        }
+    }
+    result
+}
+
+// The parts of `text` between the `separator`s outside of braces, trimmed.
+fn top_level_split<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if depth == 0 && i >= start && text[i..].starts_with(separator) {
+            parts.push(text[start..i].trim());
+            start = i + separator.len();
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+// Replaces the `use` statements on lines `first..=last` by one statement for
+// each of `paths`, keeping the indentation and visibility of the first one.
+fn replace_statements(lines: &[&str], first: usize, last: usize, paths: &[String]) -> ImportEdit {
+    let line = lines[first];
+    let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+    let statements: Vec<String> = paths.iter()
+        .map(|p| format!("{}{}use {};", indent, visibility(line), p))
+        .collect();
+    ImportEdit {
+        range: Range {
+            start: Position::new(first as u64, 0),
+            end: Position::new(last as u64, lines[last].chars().count() as u64),
+        },
+        new_text: statements.join("\n"),
+    }
+}
+
+// Imports are grouped by the crate they come from, the standard library and
+// the current crate being one group each.
+fn import_group(path: &str) -> &str {
+    let path = path.trim_left_matches("::");
+    match path.split("::").next().unwrap_or("") {
+        "std" | "core" | "alloc" => "std",
+        "self" | "super" | "crate" => "crate",
+        first => first,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy(granularity: ImportGranularity, placement: ImportPlacement) -> ImportPolicy {
+        ImportPolicy {
+            granularity,
+            placement,
+            blank_line: true,
+        }
+    }
+
+    fn edit(line: u64, new_text: &str) -> ImportEdit {
+        ImportEdit {
+            range: Range {
+                start: Position::new(line, 0),
+                end: Position::new(line, 0),
+            },
+            new_text: new_text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_imported_path() {
+        assert_eq!(imported_path("use std::collections::HashMap;\n"), Some("std::collections::HashMap"));
+        assert_eq!(imported_path("use foo::*;\n"), None);
+        assert_eq!(imported_path("HashMap"), None);
+    }
+
+    #[test]
+    fn test_placement() {
+        let text = "//! Docs.\n\nextern crate serde;\n\nuse std::fmt;\nuse serde::Serialize;\n\nfn main() {}\n";
+        let group = policy(ImportGranularity::Item, ImportPlacement::Group);
+        assert_eq!(import_edit(text, "std::mem", 7, &group), edit(5, "use std::mem;\n"));
+        assert_eq!(import_edit(text, "foo::Bar", 7, &group), edit(6, "use foo::Bar;\n"));
+        let top = policy(ImportGranularity::Item, ImportPlacement::Top);
+        assert_eq!(import_edit(text, "serde::Deserialize", 7, &top), edit(4, "use serde::Deserialize;\n"));
+
+        let text = "#![feature(rustc_private)]\nfn main() {}\n";
+        assert_eq!(import_edit(text, "std::mem", 1, &top), edit(1, "use std::mem;\n\n"));
+    }
+
+    #[test]
+    fn test_module_placement() {
+        let text = "use std::fmt;\n\nmod inner {\n    // Comment {\n    fn f() {\n        g();\n    }\n}\n";
+        let module = policy(ImportGranularity::Item, ImportPlacement::Module);
+        assert_eq!(import_edit(text, "foo::g", 5, &module), edit(4, "    use foo::g;\n\n"));
+        assert_eq!(import_edit(text, "foo::g", 1, &module), edit(1, "use foo::g;\n"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let text = "use std::fmt::Debug;\npub use std::io::{Read, Write};\n";
+        let merge = policy(ImportGranularity::Module, ImportPlacement::Group);
+        let replace = |line: u64, len: u64, new_text: &str| ImportEdit {
+            range: Range {
+                start: Position::new(line, 0),
+                end: Position::new(line, len),
+            },
+            new_text: new_text.to_owned(),
+        };
+        assert_eq!(import_edit(text, "std::fmt::Display", 2, &merge),
+                   replace(0, 20, "use std::fmt::{Debug, Display};"));
+        assert_eq!(import_edit(text, "std::io::BufRead", 2, &merge),
+                   replace(1, 31, "pub use std::io::{Read, Write, BufRead};"));
+        assert_eq!(import_edit(text, "std::mem", 2, &merge), edit(2, "use std::mem;\n"));
+    }
+
+    #[test]
+    fn test_glob_imports() {
+        let text = "use std::io::*; use std::fmt::*;\n\
+                    pub use foo::{bar::*,\n    baz::*, qux};\n\
+                    // use commented::*;\n\
+                    fn f() { let x = 2 * 3; }\n";
+        assert_eq!(glob_imports(text), vec![(0, 13), (0, 30), (1, 19), (2, 9)]);
+        assert!(glob_imports("fn reuse() -> u32 { 2 * 3 }\n").is_empty());
+    }
+
+    fn replace(start: (u64, u64), end: (u64, u64), new_text: &str) -> ImportEdit {
+        ImportEdit {
+            range: Range {
+                start: Position::new(start.0, start.1),
+                end: Position::new(end.0, end.1),
+            },
+            new_text: new_text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_nest_imports() {
+        let text = "use foo::a;\nuse foo::b;\nuse bar::c;\n";
+        assert_eq!(nest_imports(text, 0, 0), Some(replace((0, 0), (1, 11), "use foo::{a, b};")));
+        assert_eq!(nest_imports(text, 2, 2), None);
+        assert_eq!(nest_imports(text, 1, 2), None);
+
+        let text = "use std::io::Read;\n\nuse std::fmt::{Debug, Display};\nfn main() {}\n";
+        assert_eq!(nest_imports(text, 0, 3),
+                   Some(replace((0, 0), (2, 31), "use std::{fmt::{Debug, Display}, io::Read};")));
+
+        let text = "pub use foo::a;\nuse foo::b;\n#[cfg(test)]\nuse foo::c;\n";
+        assert_eq!(nest_imports(text, 0, 0), None);
+        assert_eq!(nest_imports(text, 1, 3), None);
+    }
+
+    #[test]
+    fn test_flatten_imports() {
+        let text = "mod m {\n    pub use foo::{self, a, b::{c, d}};\n}\n";
+        assert_eq!(flatten_imports(text, 1),
+                   Some(replace((1, 0), (1, 38), "    pub use foo;\n    pub use foo::a;\n    pub use foo::b::c;\n    pub use foo::b::d;")));
+
+        let text = "use foo::{\n    a,\n    b,\n};\n";
+        assert_eq!(flatten_imports(text, 1), Some(replace((0, 0), (3, 2), "use foo::a;\nuse foo::b;")));
+        assert_eq!(flatten_imports("use foo::a;\n", 0), None);
+        assert_eq!(flatten_imports("use foo::{a, b}; fn f() {}\n", 0), None);
+    }
+
+    #[test]
+    fn test_is_imported() {
+        let text = "use std::fmt::{\n    Debug,\n    Display as Show};\nuse std::io::Read;\n// use foo::Bar;\nfn f(_: &Write) {}\n";
+        assert!(is_imported(text, "Debug"));
+        assert!(is_imported(text, "Show"));
+        assert!(is_imported(text, "Read"));
+        assert!(!is_imported(text, "Bar"));
+        assert!(!is_imported(text, "Write"));
+        assert!(!is_imported(text, "Deb"));
+    }
+
+    #[test]
+    fn test_glob_replacement() {
+        assert_eq!(glob_replacement("Foo"), "Foo");
+        assert_eq!(glob_replacement("bar, Foo, bar, self"), "{self, Foo, bar}");
+        assert_eq!(glob_replacement(""), "{}");
+    }
+}
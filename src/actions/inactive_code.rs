@@ -0,0 +1,528 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Code under a `#[cfg(...)]` which is false with the configuration the RLS
+//! builds with isn't compiled, so it has no diagnostics, analysis data or
+//! completions. It's reported with hints (tagged as unnecessary, which editors
+//! usually render faded), so users know why.
+//!
+//! The configuration is the one of the build: the `features`, `all_features`
+//! and `no_default_features` options (applied to the manifests of the packages
+//! Cargo builds with them, the others have their default features), the
+//! options of the target of the `target_triple` option or the host as printed
+//! by `rustc --print cfg`, `test` if the `cfg_test` option is set and
+//! `debug_assertions`. Anything else (e.g. target features enabled by flags,
+//! or names set by build scripts) is unknown, and code only inactive because of
+//! something unknown isn't reported.
+//!
+//! The source files are read like the compiler reads them, with the unsaved
+//! changes in the VFS.
+//!
+//! The attributes and the code they apply to are found in the tokens of the
+//! source files (see `lifetimes::tokenize`), up to the `;` or `}` ending an
+//! item or statement, or the `,` after a field, variant or match arm.
+
+use actions::collect_source_files;
+use actions::diagnostics::FileDiagnostic;
+use actions::lifetimes::{matching_close, tokenize, Token, TokenKind};
+use actions::manifest::{enabled_features, parse_manifest};
+use actions::package_dir_for_file;
+use config::Config;
+use ls_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range};
+use vfs::Vfs;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+// The options of each target, by triple (`None` for the host), asking rustc
+// takes a while.
+lazy_static! {
+    static ref TARGET_CFGS: Mutex<HashMap<Option<String>, TargetCfg>> = Mutex::new(HashMap::new());
+}
+
+/// What `cfg` predicates are evaluated on.
+#[derive(Debug, Clone)]
+pub struct CfgSettings {
+    features: Vec<String>,
+    all_features: bool,
+    no_default_features: bool,
+    // Cargo applies the features to the workspace members it builds (every one
+    // or `analyze_package`), or to the package of the project.
+    workspace_mode: bool,
+    analyze_package: Option<String>,
+    test: bool,
+    target_triple: Option<String>,
+}
+
+// The configuration options of the target as printed by `rustc --print cfg`,
+// names (e.g. `unix`) and `key="value"` pairs (e.g. `target_os="linux"`).
+// Empty if rustc couldn't tell them.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct TargetCfg {
+    cfg: HashSet<String>,
+}
+
+impl CfgSettings {
+    /// The settings of builds with `config`.
+    pub fn from_config(config: &Config) -> CfgSettings {
+        CfgSettings {
+            features: config.features.clone(),
+            all_features: config.all_features,
+            no_default_features: config.no_default_features,
+            workspace_mode: config.workspace_mode,
+            analyze_package: config.analyze_package.clone(),
+            test: config.cfg_test,
+            target_triple: config.target_triple.clone(),
+        }
+    }
+
+    // The features enabled in the `manifest` of a package, `is_root` if it's
+    // the package of the project.
+    fn package_features(&self, manifest: &str, is_root: bool) -> HashSet<String> {
+        let requested = match (self.workspace_mode, &self.analyze_package) {
+            (true, &None) => true,
+            (true, &Some(ref package)) => parse_manifest(manifest).name.as_ref() == Some(package),
+            (false, _) => is_root,
+        };
+        if requested {
+            enabled_features(manifest, &self.features, self.all_features, self.no_default_features)
+        } else {
+            enabled_features(manifest, &[], false, false)
+        }
+    }
+}
+
+impl TargetCfg {
+    // The options of the target `triple` (the host if `None`), from rustc.
+    fn of_target(triple: Option<&str>) -> TargetCfg {
+        let mut cfgs = TARGET_CFGS.lock().unwrap();
+        cfgs.entry(triple.map(|t| t.to_owned())).or_insert_with(|| {
+            let mut cmd = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned()));
+            cmd.args(&["--print", "cfg"]);
+            if let Some(triple) = triple {
+                cmd.args(&["--target", triple]);
+            }
+            match cmd.output() {
+                Ok(ref output) if output.status.success() => {
+                    TargetCfg::parse(&String::from_utf8_lossy(&output.stdout))
+                }
+                Ok(ref output) => {
+                    debug!("no cfg for target {:?}: {}", triple, String::from_utf8_lossy(&output.stderr));
+                    TargetCfg::default()
+                }
+                Err(e) => {
+                    debug!("no cfg for target {:?}: {}", triple, e);
+                    TargetCfg::default()
+                }
+            }
+        }).clone()
+    }
+
+    // The output of `rustc --print cfg`, an option per line.
+    fn parse(text: &str) -> TargetCfg {
+        TargetCfg {
+            cfg: text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).map(|line| line.to_owned()).collect(),
+        }
+    }
+}
+
+/// A `cfg` predicate.
+#[derive(Debug, PartialEq)]
+enum Cfg {
+    All(Vec<Cfg>),
+    Any(Vec<Cfg>),
+    Not(Box<Cfg>),
+    Name(String),
+    KeyValue(String, String),
+}
+
+impl Cfg {
+    // Whether the predicate holds, `None` if that depends on something
+    // unknown.
+    fn eval(&self, target: &TargetCfg, features: Option<&HashSet<String>>, test: bool) -> Option<bool> {
+        match *self {
+            Cfg::All(ref cfgs) => {
+                let values: Vec<_> = cfgs.iter().map(|c| c.eval(target, features, test)).collect();
+                if values.contains(&Some(false)) {
+                    Some(false)
+                } else if values.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                }
+            }
+            Cfg::Any(ref cfgs) => {
+                let values: Vec<_> = cfgs.iter().map(|c| c.eval(target, features, test)).collect();
+                if values.contains(&Some(true)) {
+                    Some(true)
+                } else if values.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+            Cfg::Not(ref cfg) => cfg.eval(target, features, test).map(|value| !value),
+            Cfg::Name(ref name) => match &**name {
+                "test" => Some(test),
+                "debug_assertions" => Some(true),
+                _ if target.cfg.contains(name) => Some(true),
+                "unix" | "windows" if !target.cfg.is_empty() => Some(false),
+                _ => None,
+            },
+            Cfg::KeyValue(ref key, ref value) => {
+                if key == "feature" {
+                    return features.map(|features| features.contains(value));
+                }
+                // Target features can also be enabled by flags.
+                if !key.starts_with("target_") || target.cfg.is_empty() {
+                    return None;
+                }
+                if target.cfg.contains(&format!("{}=\"{}\"", key, value)) {
+                    Some(true)
+                } else if key == "target_feature" {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+        }
+    }
+}
+
+/// The feature which must be enabled for the `cfg` predicate to hold, e.g.
+/// `std` for `all(unix, feature = "std")`, but none for `not(feature = "std")`
+/// or `any(feature = "std", test)`.
+pub fn required_feature(predicate: &str) -> Option<String> {
+    parse_cfg(predicate)?.required_feature().map(|feature| feature.to_owned())
+}
+
+impl Cfg {
+    fn required_feature(&self) -> Option<&str> {
+        match *self {
+            Cfg::All(ref cfgs) => cfgs.iter().filter_map(|c| c.required_feature()).next(),
+            Cfg::Any(ref cfgs) if cfgs.len() == 1 => cfgs[0].required_feature(),
+            Cfg::KeyValue(ref key, ref value) if key == "feature" => Some(&**value),
+            _ => None,
+        }
+    }
+}
+
+// Parses a `cfg` predicate, e.g. `all(unix, feature = "std")`.
+fn parse_cfg(text: &str) -> Option<Cfg> {
+    let mut chars = text.chars().peekable();
+    let cfg = parse_predicate(&mut chars)?;
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(cfg)
+}
+
+type Chars<'a> = ::std::iter::Peekable<::std::str::Chars<'a>>;
+
+fn parse_predicate(chars: &mut Chars) -> Option<Cfg> {
+    skip_whitespace(chars);
+    let mut name = String::new();
+    while let Some(&c) = chars.peek() {
+        if !(c.is_alphanumeric() || c == '_') {
+            break;
+        }
+        name.push(c);
+        chars.next();
+    }
+    if name.is_empty() {
+        return None;
+    }
+    skip_whitespace(chars);
+    match chars.peek().cloned() {
+        Some('=') => {
+            chars.next();
+            skip_whitespace(chars);
+            if chars.next() != Some('"') {
+                return None;
+            }
+            let value: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            Some(Cfg::KeyValue(name, value))
+        }
+        Some('(') => {
+            chars.next();
+            let mut list = vec![];
+            loop {
+                skip_whitespace(chars);
+                if chars.peek() == Some(&')') {
+                    chars.next();
+                    break;
+                }
+                list.push(parse_predicate(chars)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => {}
+                    Some(')') => break,
+                    _ => return None,
+                }
+            }
+            match &*name {
+                "all" => Some(Cfg::All(list)),
+                "any" => Some(Cfg::Any(list)),
+                "not" if list.len() == 1 => Some(Cfg::Not(Box::new(list.pop().unwrap()))),
+                _ => None,
+            }
+        }
+        _ => Some(Cfg::Name(name)),
+    }
+}
+
+fn skip_whitespace(chars: &mut Chars) {
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// Code inactive with `settings` in the source files of the project at
+/// `project_path`, as they are in `vfs`.
+pub fn inactive_code(project_path: &Path, settings: &CfgSettings, vfs: &Vfs) -> Vec<FileDiagnostic> {
+    let target = TargetCfg::of_target(settings.target_triple.as_ref().map(|t| &**t));
+    let changed = vfs.get_cached_files();
+    // The enabled features of each package, by its directory.
+    let mut features: HashMap<PathBuf, Option<HashSet<String>>> = HashMap::new();
+    let mut result = vec![];
+    for file_path in collect_source_files(project_path) {
+        let text = match changed.get(&file_path) {
+            Some(text) => text.clone(),
+            None => {
+                let mut text = String::new();
+                if File::open(&file_path).and_then(|mut file| file.read_to_string(&mut text)).is_err() {
+                    continue;
+                }
+                text
+            }
+        };
+        if !text.contains("cfg") {
+            continue;
+        }
+        let package_features = match package_dir_for_file(&file_path) {
+            Some(dir) => features.entry(dir.to_owned()).or_insert_with(|| {
+                let mut manifest = String::new();
+                File::open(dir.join("Cargo.toml")).and_then(|mut file| file.read_to_string(&mut manifest)).ok()?;
+                Some(settings.package_features(&manifest, dir == project_path))
+            }).as_ref(),
+            None => None,
+        };
+        for (range, predicate) in inactive_ranges(&text, &target, package_features, settings.test) {
+            result.push(FileDiagnostic {
+                file_path: file_path.clone(),
+                diagnostic: Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::Hint),
+                    code: Some(NumberOrString::String("inactive_code".to_owned())),
+                    source: Some("rls".to_owned()),
+                    message: format!("code is inactive due to `#[cfg({})]`", predicate),
+                },
+                suggestions: vec![],
+                related: vec![],
+            });
+        }
+    }
+    result
+}
+
+// The (char) ranges of the code in `text` under a `cfg` attribute which is
+// false, with the attribute's predicate.
+fn inactive_ranges(text: &str,
+                   target: &TargetCfg,
+                   features: Option<&HashSet<String>>,
+                   test: bool)
+                   -> Vec<(Range, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let tokens = tokenize(text);
+    let mut result = vec![];
+    let mut i = 0;
+    while i + 4 < tokens.len() {
+        let inner = tokens[i + 1].is(TokenKind::Punct, "!");
+        let open = if inner { i + 2 } else { i + 1 };
+        let is_cfg = tokens[i].is(TokenKind::Punct, "#") && tokens[open].is(TokenKind::Punct, "[") &&
+                     tokens.get(open + 1).map_or(false, |t| t.is(TokenKind::Ident, "cfg")) &&
+                     tokens.get(open + 2).map_or(false, |t| t.is(TokenKind::Punct, "("));
+        if !is_cfg {
+            i += 1;
+            continue;
+        }
+        let (paren, close) = match closing_bracket(&tokens, open + 2).and_then(|p| closing_bracket(&tokens, open).map(|c| (p, c))) {
+            Some(found) => found,
+            None => break,
+        };
+        let predicate = text_between(&lines, &tokens[open + 2], &tokens[paren]);
+        let active = parse_cfg(&predicate).and_then(|cfg| cfg.eval(target, features, test));
+        if active == Some(false) {
+            // An inner attribute applies to the rest of the module.
+            let end = if inner { tokens.len() - 1 } else { code_end(&tokens, close + 1) };
+            let start = Position::new(tokens[i].row as u64, tokens[i].col as u64);
+            let end = Position::new(tokens[end].row as u64, tokens[end].end_col() as u64);
+            result.push((Range::new(start, end), predicate.trim().to_owned()));
+        }
+        i = close + 1;
+    }
+    result
+}
+
+// The text between the tokens `open` and `close`, exclusive.
+fn text_between(lines: &[&str], open: &Token, close: &Token) -> String {
+    let mut result = String::new();
+    for row in open.row..close.row + 1 {
+        let line = lines.get(row).cloned().unwrap_or("");
+        let start = if row == open.row { open.col + 1 } else { 0 };
+        let end = if row == close.row { close.col } else { line.chars().count() };
+        result.extend(line.chars().skip(start).take(end.saturating_sub(start)));
+        if row != close.row {
+            result.push('\n');
+        }
+    }
+    result
+}
+
+// The `)` or `]` closing the bracket at `open`.
+fn closing_bracket(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token.text {
+            "(" | "[" | "{" => depth += 1,
+            ")" | "]" | "}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// The last token of the item, statement, field, variant or match arm starting
+// at `from`, after any more attributes.
+fn code_end(tokens: &[Token], from: usize) -> usize {
+    let last = tokens.len() - 1;
+    let mut from = from;
+    while from + 1 < tokens.len() && tokens[from].is(TokenKind::Punct, "#") && tokens[from + 1].is(TokenKind::Punct, "[") {
+        from = match closing_bracket(tokens, from + 1) {
+            Some(close) => close + 1,
+            None => return last,
+        };
+    }
+    let mut depth = 0;
+    let mut angle = 0;
+    for (i, token) in tokens.iter().enumerate().skip(from) {
+        match token.text {
+            "(" | "[" => depth += 1,
+            ")" | "]" | "}" if depth == 0 => return i.saturating_sub(1),
+            ")" | "]" => depth -= 1,
+            "<" => angle += 1,
+            ">" if angle > 0 => angle -= 1,
+            "{" if depth == 0 => return matching_close(tokens, i).unwrap_or(last),
+            "{" => depth += 1,
+            "}" => depth -= 1,
+            ";" if depth == 0 => return i,
+            "," if depth == 0 && angle == 0 => return i,
+            _ => {}
+        }
+    }
+    last
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // `rustc --print cfg --target x86_64-unknown-linux-gnu`.
+    fn linux() -> TargetCfg {
+        TargetCfg::parse("debug_assertions\n\
+                          target_arch=\"x86_64\"\n\
+                          target_endian=\"little\"\n\
+                          target_env=\"gnu\"\n\
+                          target_family=\"unix\"\n\
+                          target_feature=\"sse\"\n\
+                          target_os=\"linux\"\n\
+                          target_pointer_width=\"64\"\n\
+                          target_vendor=\"unknown\"\n\
+                          unix\n")
+    }
+
+    #[test]
+    fn test_target_cfg() {
+        let eval = |cfg: &str, target: &TargetCfg| parse_cfg(cfg).and_then(|cfg| cfg.eval(target, None, false));
+        assert_eq!(eval("target_env = \"gnu\"", &linux()), Some(true));
+        assert_eq!(eval("target_env = \"gnueabihf\"", &linux()), Some(false));
+        assert_eq!(eval("target_vendor = \"apple\"", &linux()), Some(false));
+        assert_eq!(eval("target_feature = \"sse\"", &linux()), Some(true));
+        assert_eq!(eval("target_feature = \"avx2\"", &linux()), None);
+        assert_eq!(eval("windows", &linux()), Some(false));
+        assert_eq!(eval("windows", &TargetCfg::default()), None);
+        assert_eq!(eval("target_os = \"linux\"", &TargetCfg::default()), None);
+    }
+
+    #[test]
+    fn test_eval() {
+        let features: HashSet<String> = vec!["std".to_owned()].into_iter().collect();
+        let eval = |cfg: &str| parse_cfg(cfg).and_then(|cfg| cfg.eval(&linux(), Some(&features), false));
+        assert_eq!(eval("unix"), Some(true));
+        assert_eq!(eval("windows"), Some(false));
+        assert_eq!(eval("feature = \"std\""), Some(true));
+        assert_eq!(eval("not(feature = \"std\")"), Some(false));
+        assert_eq!(eval("all(test, foo)"), Some(false));
+        assert_eq!(eval("all(unix, foo)"), None);
+        assert_eq!(eval("any(windows, target_os = \"linux\")"), Some(true));
+        assert_eq!(eval("any(windows, foo)"), None);
+        assert_eq!(eval("not(a, b)"), None);
+    }
+
+    #[test]
+    fn test_required_feature() {
+        assert_eq!(required_feature("feature = \"std\""), Some("std".to_owned()));
+        assert_eq!(required_feature("all(unix, feature=\"std\")"), Some("std".to_owned()));
+        assert_eq!(required_feature("target_feature = \"sse2\""), None);
+        assert_eq!(required_feature("not(feature = \"std\")"), None);
+        assert_eq!(required_feature("any(feature = \"std\", test)"), None);
+    }
+
+    #[test]
+    fn test_inactive_ranges() {
+        let text = "#[cfg(test)]\n\
+                    mod test {\n\
+                    \x20   fn foo() {}\n\
+                    }\n\
+                    struct Foo {\n\
+                    \x20   #[cfg(windows)] handle: Vec<u8, X>,\n\
+                    \x20   #[cfg(unix)] fd: u32,\n\
+                    }\n\
+                    fn main() {\n\
+                    \x20   #[cfg(feature = \"nightly\")]\n\
+                    \x20   #[inline]\n\
+                    \x20   let x = 1;\n\
+                    }\n";
+        let features = HashSet::new();
+        let ranges: Vec<_> = inactive_ranges(text, &linux(), Some(&features), false).into_iter().map(|(range, cfg)| {
+            (range.start.line, range.start.character, range.end.line, range.end.character, cfg)
+        }).collect();
+        assert_eq!(ranges, [(0, 0, 3, 1, "test".to_owned()),
+                            (5, 4, 5, 39, "windows".to_owned()),
+                            (9, 4, 11, 14, "feature = \"nightly\"".to_owned())]);
+
+        let text = "#![cfg(windows)]\n\nfn foo() {}\n";
+        let ranges: Vec<_> = inactive_ranges(text, &linux(), None, false).into_iter().map(|(range, _)| {
+            (range.start.line, range.end.line, range.end.character)
+        }).collect();
+        assert_eq!(ranges, [(0, 2, 11)]);
+    }
+}
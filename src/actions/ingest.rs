@@ -0,0 +1,154 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading save-analysis data from disk, one crate per thread.
+//!
+//! When the RLS starts up in a project which has been built before, the data of
+//! every dependency is read from disk. rls-analysis does this one crate after
+//! the other, so for big dependency trees most of the startup time is spent
+//! deserializing JSON on a single core. Instead, we deserialize the files on a
+//! pool of threads and hand the results to rls-analysis, which only has to
+//! lower them.
+//!
+//! The crates the RLS compiles itself pass their data in memory instead, it is
+//! only written to disk when the RLS shuts down (see `UnsavedAnalysis`).
+
+use actions::memory::AnalysisFile;
+use data::Analysis;
+
+use rayon;
+use rayon::prelude::*;
+use serde_json;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+lazy_static! {
+    // Uses one thread per CPU, separate from the `WORK_POOL` for requests so
+    // that reading the data doesn't make the RLS unresponsive.
+    static ref INGEST_POOL: rayon::ThreadPool = rayon::ThreadPool::new(
+        rayon::Configuration::default()
+            .thread_name(|num| format!("ingest-worker-{}", num))
+            .panic_handler(|err| warn!("{:?}", err))
+    ).unwrap();
+}
+
+/// How often progress is reported while reading the data.
+pub const PROGRESS_STEPS: usize = 20;
+
+/// Reads the given save-analysis files, in parallel unless `parallel` is
+/// false. `progress` is called with the number of files read so far and the
+/// total number of files, about `PROGRESS_STEPS` times. Files which can't be
+/// read are skipped.
+pub fn read_analysis<F>(files: &[AnalysisFile], parallel: bool, progress: F) -> Vec<Analysis>
+    where F: Fn(usize, usize) + Sync,
+{
+    let total = files.len();
+    let step = ::std::cmp::max(total / PROGRESS_STEPS, 1);
+    let done = AtomicUsize::new(0);
+    let read = |file: &AnalysisFile| {
+        let analysis = read_file(file);
+        let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+        if done % step == 0 || done == total {
+            progress(done, total);
+        }
+        analysis
+    };
+
+    if !parallel {
+        return files.iter().filter_map(read).collect();
+    }
+    INGEST_POOL.install(|| files.par_iter().filter_map(read).collect())
+}
+
+fn read_file(file: &AnalysisFile) -> Option<Analysis> {
+    let reader = match File::open(&file.path) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => {
+            debug!("read_analysis: could not open {:?}: {}", file.path, e);
+            return None;
+        }
+    };
+    match serde_json::from_reader(reader) {
+        Ok(analysis) => Some(analysis),
+        Err(e) => {
+            debug!("read_analysis: could not deserialize {:?}: {}", file.path, e);
+            None
+        }
+    }
+}
+
+/// The analysis data of the last build of each crate compiled by the RLS
+/// itself, which isn't on disk. Writing it when the RLS shuts down lets the
+/// next start read it before the first build is done.
+pub struct UnsavedAnalysis {
+    // By file name, see `file_name`.
+    by_file: Mutex<HashMap<String, Analysis>>,
+}
+
+impl UnsavedAnalysis {
+    /// Construct without any data.
+    pub fn new() -> UnsavedAnalysis {
+        UnsavedAnalysis {
+            by_file: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep the data of the given crates, replacing what was kept for them.
+    pub fn record(&self, analysis: &[Analysis]) {
+        let mut by_file = self.by_file.lock().unwrap();
+        for crate_analysis in analysis {
+            if let Some(name) = file_name(crate_analysis) {
+                by_file.insert(name, crate_analysis.clone());
+            }
+        }
+    }
+
+    /// Writes the data kept to `dir`, where rustc writes save-analysis files,
+    /// and forgets it. Each file is written under another name first, so that
+    /// nothing reads it half-written. Returns the number of files written.
+    pub fn flush(&self, dir: &Path) -> usize {
+        let by_file: Vec<_> = self.by_file.lock().unwrap().drain().collect();
+        if by_file.is_empty() {
+            return 0;
+        }
+        if let Err(e) = fs::create_dir_all(dir) {
+            debug!("flush: could not create {:?}: {}", dir, e);
+            return 0;
+        }
+        by_file.into_iter().filter(|&(ref name, ref analysis)| {
+            let path = dir.join(name);
+            let temp_path = path.with_extension("json.tmp");
+            let written = write_file(&temp_path, analysis).and_then(|_| fs::rename(&temp_path, &path));
+            if let Err(ref e) = written {
+                debug!("flush: could not write {:?}: {}", path, e);
+                let _ = fs::remove_file(&temp_path);
+            }
+            written.is_ok()
+        }).count()
+    }
+}
+
+// `libfoo_bar-<disambiguator>.json`, like the names rustc gives the files (see
+// `memory::crate_name_from_file_name`).
+fn file_name(analysis: &Analysis) -> Option<String> {
+    let crate_id = &analysis.prelude.as_ref()?.crate_id;
+    let (high, low) = crate_id.disambiguator;
+    Some(format!("lib{}-{:016x}{:016x}.json", crate_id.name, high, low))
+}
+
+fn write_file(path: &Path, analysis: &Analysis) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(writer, analysis).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
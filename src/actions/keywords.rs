@@ -0,0 +1,362 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Completions of keywords where an item or a statement starts, which racer
+//! only offers identifiers for. Clients expanding snippets get the skeletons of
+//! items and expressions, e.g. `fn ${1:name}($2) {\n    $0\n}` for `fn`.
+//!
+//! Whether the position is in an item or in a statement is found from the
+//! blocks enclosing it, by the text before their braces, e.g. a block whose
+//! header has `fn` is a function body.
+
+use lsp_data::{CompletionItem, CompletionItemKind, CompletionRelevance, InsertTextFormat, RankedCompletion};
+
+/// Where a keyword is being completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// An item in a module.
+    Item,
+    /// An item in an impl or a trait.
+    AssociatedItem,
+    /// A statement in a function body.
+    Statement,
+    /// Anywhere else, e.g. the fields of a struct.
+    Other,
+}
+
+struct Keyword {
+    name: &'static str,
+    positions: &'static [Position],
+    // Whether it can follow `pub`.
+    after_pub: bool,
+    // The skeleton inserted by clients expanding snippets.
+    snippet: Option<&'static str>,
+}
+
+const ITEM: &[Position] = &[Position::Item, Position::Statement];
+const ANY_ITEM: &[Position] = &[Position::Item, Position::AssociatedItem, Position::Statement];
+const MODULE_ITEM: &[Position] = &[Position::Item];
+const STATEMENT: &[Position] = &[Position::Statement];
+
+const KEYWORDS: &[Keyword] = &[
+    Keyword { name: "break", positions: STATEMENT, after_pub: false, snippet: None },
+    Keyword { name: "const", positions: ANY_ITEM, after_pub: true, snippet: None },
+    Keyword { name: "continue", positions: STATEMENT, after_pub: false, snippet: None },
+    Keyword {
+        name: "derive",
+        positions: ITEM,
+        after_pub: false,
+        snippet: Some("#[derive(${1:Debug})]"),
+    },
+    Keyword {
+        name: "enum",
+        positions: ITEM,
+        after_pub: true,
+        snippet: Some("enum ${1:Name} {\n    $0\n}"),
+    },
+    Keyword { name: "extern", positions: MODULE_ITEM, after_pub: true, snippet: None },
+    Keyword {
+        name: "fn",
+        positions: ANY_ITEM,
+        after_pub: true,
+        snippet: Some("fn ${1:name}($2) {\n    $0\n}"),
+    },
+    Keyword {
+        name: "for",
+        positions: STATEMENT,
+        after_pub: false,
+        snippet: Some("for ${1:item} in ${2:iter} {\n    $0\n}"),
+    },
+    Keyword {
+        name: "if",
+        positions: STATEMENT,
+        after_pub: false,
+        snippet: Some("if ${1:condition} {\n    $0\n}"),
+    },
+    Keyword {
+        name: "impl",
+        positions: ITEM,
+        after_pub: false,
+        snippet: Some("impl ${1:Type} {\n    $0\n}"),
+    },
+    Keyword {
+        name: "impl for",
+        positions: ITEM,
+        after_pub: false,
+        snippet: Some("impl ${1:Trait} for ${2:Type} {\n    $0\n}"),
+    },
+    Keyword { name: "let", positions: STATEMENT, after_pub: false, snippet: None },
+    Keyword {
+        name: "loop",
+        positions: STATEMENT,
+        after_pub: false,
+        snippet: Some("loop {\n    $0\n}"),
+    },
+    Keyword {
+        name: "match",
+        positions: STATEMENT,
+        after_pub: false,
+        snippet: Some("match ${1:expr} {\n    ${2:pattern} => $0,\n}"),
+    },
+    Keyword {
+        name: "mod",
+        positions: MODULE_ITEM,
+        after_pub: true,
+        snippet: Some("mod ${1:name} {\n    $0\n}"),
+    },
+    Keyword { name: "pub", positions: &[Position::Item, Position::AssociatedItem], after_pub: false, snippet: None },
+    Keyword { name: "return", positions: STATEMENT, after_pub: false, snippet: None },
+    Keyword { name: "static", positions: ITEM, after_pub: true, snippet: None },
+    Keyword {
+        name: "struct",
+        positions: ITEM,
+        after_pub: true,
+        snippet: Some("struct ${1:Name} {\n    $0\n}"),
+    },
+    Keyword {
+        name: "trait",
+        positions: ITEM,
+        after_pub: true,
+        snippet: Some("trait ${1:Name} {\n    $0\n}"),
+    },
+    Keyword { name: "type", positions: ANY_ITEM, after_pub: true, snippet: None },
+    Keyword { name: "unsafe", positions: ANY_ITEM, after_pub: true, snippet: None },
+    Keyword { name: "use", positions: ITEM, after_pub: true, snippet: None },
+    Keyword {
+        name: "while",
+        positions: STATEMENT,
+        after_pub: false,
+        snippet: Some("while ${1:condition} {\n    $0\n}"),
+    },
+];
+
+/// The keywords which can start an item or statement at char column `col` of
+/// line `row` of `text`, as snippets if the client expands them. Nothing if
+/// the position isn't at the start of one.
+pub fn keyword_completions(text: &str, row: usize, col: usize, snippets: bool) -> Vec<RankedCompletion> {
+    let line_start = if row == 0 {
+        0
+    } else {
+        match text.match_indices('\n').nth(row - 1) {
+            Some((newline, _)) => newline + 1,
+            None => return vec![],
+        }
+    };
+    let line = text[line_start..].lines().next().unwrap_or("");
+    let before: String = line.chars().take(col).collect();
+    let (after_pub, prefix) = match keyword_prefix(&before) {
+        Some(prefix) => prefix,
+        None => return vec![],
+    };
+    let position = block_position(&text[..line_start]);
+
+    KEYWORDS.iter()
+        .filter(|k| k.positions.contains(&position) && (!after_pub || k.after_pub) && k.name.starts_with(prefix))
+        .filter_map(|k| {
+            let mut item = CompletionItem::new_simple(k.name.to_owned(), "keyword".to_owned());
+            item.kind = Some(CompletionItemKind::Keyword);
+            match k.snippet {
+                Some(snippet) if snippets => {
+                    item.kind = Some(CompletionItemKind::Snippet);
+                    item.detail = Some(snippet_preview(snippet));
+                    item.insert_text = Some(snippet.to_owned());
+                    item.insert_text_format = Some(InsertTextFormat::Snippet);
+                }
+                // Skeletons of attributes need a snippet.
+                Some(_) if k.name == "derive" => return None,
+                // `impl for` is only a skeleton.
+                _ if k.name.contains(' ') => return None,
+                _ => {}
+            }
+            Some(RankedCompletion {
+                item,
+                relevance: CompletionRelevance::Keyword,
+                in_document: false,
+                definition: None,
+            })
+        })
+        .collect()
+}
+
+// Whether `before`, the line before the cursor, follows `pub` and the part of
+// the keyword typed so far, e.g. `(true, "fn")` for `    pub(crate) fn`.
+// `None` if the cursor isn't on the first word of an item or statement.
+fn keyword_prefix(before: &str) -> Option<(bool, &str)> {
+    let mut rest = before.trim_left();
+    let mut after_pub = false;
+    if rest.starts_with("pub ") || rest.starts_with("pub(") {
+        after_pub = true;
+        rest = rest["pub".len()..].trim_left();
+        if rest.starts_with('(') {
+            rest = rest[rest.find(')')? + 1..].trim_left();
+        }
+    }
+    if rest.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        Some((after_pub, rest))
+    } else {
+        None
+    }
+}
+
+// What the blocks enclosing the end of `text` make that position.
+fn block_position(text: &str) -> Position {
+    let mut blocks = vec![];
+    let mut header = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                // Skip the string.
+                let mut escaped = false;
+                while let Some(c) = chars.next() {
+                    if c == '"' && !escaped {
+                        break;
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+            }
+            '\'' => {
+                // Skip a char literal, e.g. `'{'` or `'\''`, but not a
+                // lifetime.
+                let mut ahead = chars.clone();
+                match (ahead.next(), ahead.next()) {
+                    (Some('\\'), _) => {
+                        chars.next();
+                        chars.next();
+                        while let Some(c) = chars.next() {
+                            if c == '\'' {
+                                break;
+                            }
+                        }
+                    }
+                    (Some(_), Some('\'')) => {
+                        chars.next();
+                        chars.next();
+                    }
+                    _ => header.push(c),
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(c) = chars.next() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '{' => {
+                let position = header_position(&header, blocks.last().cloned());
+                blocks.push(position);
+                header.clear();
+            }
+            '}' => {
+                blocks.pop();
+                header.clear();
+            }
+            ';' => header.clear(),
+            _ => header.push(c),
+        }
+    }
+    blocks.last().cloned().unwrap_or(Position::Item)
+}
+
+// What a block with `header` (the text since the end of the previous item or
+// statement) makes the positions in it, given the position of the block itself.
+fn header_position(header: &str, outer: Option<Position>) -> Position {
+    let words: Vec<&str> = header.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+        .collect();
+    let has = |keyword: &str| words.contains(&keyword);
+    if has("match") || has("struct") || has("enum") || has("union") {
+        Position::Other
+    } else if has("fn") {
+        Position::Statement
+    } else if outer == Some(Position::Statement) {
+        // Blocks of `if`, `loop`, etc.
+        Position::Statement
+    } else if has("impl") || has("trait") {
+        Position::AssociatedItem
+    } else if has("mod") {
+        Position::Item
+    } else {
+        Position::Other
+    }
+}
+
+// A snippet as it's inserted without its placeholders, on one line, e.g. `fn
+// name() { }` for `fn ${1:name}($2) {\n    $0\n}`.
+fn snippet_preview(snippet: &str) -> String {
+    let mut result = String::new();
+    let mut chars = snippet.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            // Keep the default text of `${1:text}`.
+            let mut placeholder = String::new();
+            while let Some(c) = chars.next() {
+                if c == '}' {
+                    break;
+                }
+                placeholder.push(c);
+            }
+            result.push_str(placeholder.splitn(2, ':').nth(1).unwrap_or(""));
+        } else {
+            while chars.peek().map_or(false, |c| c.is_digit(10)) {
+                chars.next();
+            }
+        }
+    }
+    result.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn labels(text: &str, snippets: bool) -> Vec<String> {
+        let row = text.lines().count() - 1;
+        let col = text.lines().last().unwrap().chars().count();
+        keyword_completions(text, row, col, snippets).into_iter().map(|c| c.item.label).collect()
+    }
+
+    #[test]
+    fn test_keyword_completions() {
+        assert_eq!(labels("use foo;\n\nst", false), ["static", "struct"]);
+        assert_eq!(labels("fn foo() {\n    if x {\n        le", false), ["let"]);
+        assert_eq!(labels("impl Foo {\n    pub f", false), ["fn"]);
+        assert_eq!(labels("impl Foo {\n    st", false), Vec::<String>::new());
+        assert_eq!(labels("struct Foo {\n    f", false), Vec::<String>::new());
+        assert_eq!(labels("fn foo() {\n    let s = \"{\";\n    m", false), ["match"]);
+        assert_eq!(labels("fn foo<'a>(x: &'a str) {\n    let c = '{';\n    let d = '\\'';\n    m", false), ["match"]);
+        assert_eq!(labels("fn foo() {\n    let c = '}';\n    m", false), ["match"]);
+        assert_eq!(labels("mod a {\n}\nim", false), ["impl"]);
+        assert_eq!(labels("mod a {\n}\nim", true), ["impl", "impl for"]);
+        assert_eq!(labels("let x = f", false), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_snippet_completion() {
+        let completion = keyword_completions("f", 0, 1, true).into_iter().next().unwrap();
+        assert_eq!(completion.item.insert_text.as_ref().map(|t| &**t), Some("fn ${1:name}($2) {\n    $0\n}"));
+        assert_eq!(completion.item.insert_text_format, Some(InsertTextFormat::Snippet));
+        assert_eq!(completion.item.detail.as_ref().map(|d| &**d), Some("fn name() { }"));
+    }
+
+    #[test]
+    fn test_keyword_prefix() {
+        assert_eq!(keyword_prefix("    pub(crate) fn"), Some((true, "fn")));
+        assert_eq!(keyword_prefix(""), Some((false, "")));
+        assert_eq!(keyword_prefix("public"), Some((false, "public")));
+        assert_eq!(keyword_prefix("foo.ba"), None);
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Lifetimes (`'a`) and loop labels (`'outer: loop`) aren't in the analysis
+//! data, so highlighting and renaming them works on the source text.
+//!
+//! The text is split into tokens (skipping comments, strings and character
+//! literals), and each occurrence is resolved to its declaration:
+//!
+//! * A lifetime is declared in the generics of an item (`fn`, `impl`,
+//!   `struct`, ...) or in `for<'a>`, and is in scope up to the end of the item
+//!   (or of the bound, for `for<'a>`). Lifetimes can't be shadowed, but items
+//!   nested in a function may declare the same name again.
+//! * A label is declared before a loop or block, and is in scope up to the end
+//!   of its body. It's used by `break` and `continue`. Labels and lifetimes
+//!   with the same name are unrelated.
+//!
+//! This doesn't understand macros, and `'static` and `'_` have no declaration.
+//! The tokens are also used to find the function around a position, see
+//! `borrows`.
+
+use span;
+use Span;
+
+use std::path::Path;
+
+/// Keywords starting an item whose generics may declare lifetimes.
+const ITEM_KEYWORDS: &[&str] = &["fn", "impl", "struct", "enum", "trait", "type", "union", "for"];
+
+/// Keywords which may follow a label.
+const LABELLED: &[&str] = &["loop", "while", "for"];
+
+/// The occurrences of the lifetime or label at char column `col` of `row` in
+/// `text` (the source of `file`), each spanning the `'` and the name. `None`
+/// if there is no lifetime or label there, or its declaration isn't found.
+pub fn occurrences(text: &str, file: &Path, row: usize, col: usize) -> Option<Vec<Span>> {
+    let tokens = tokenize(text);
+    let at = tokens.iter().position(|t| {
+        t.kind == TokenKind::Lifetime && t.row == row && t.col <= col && col <= t.end_col()
+    })?;
+    let name = tokens[at].text;
+    if name == "'static" || name == "'_" {
+        return None;
+    }
+
+    let label = is_label(&tokens, at);
+    let scopes: Vec<Scope> = scopes(&tokens).into_iter()
+        .filter(|s| s.label == label && tokens[s.declaration].text == name)
+        .collect();
+    let scope = scopes.iter()
+        .filter(|s| s.contains(at))
+        .max_by_key(|s| s.start)?;
+    // Other declarations of the name inside the scope hide it.
+    let hidden: Vec<&Scope> = scopes.iter()
+        .filter(|s| s.start > scope.start && s.end <= scope.end)
+        .collect();
+
+    let result = (scope.start..scope.end + 1)
+        .filter(|&i| {
+            tokens[i].kind == TokenKind::Lifetime && tokens[i].text == name &&
+                is_label(&tokens, i) == label && !hidden.iter().any(|s| s.contains(i))
+        })
+        .map(|i| tokens[i].span(file))
+        .collect();
+    Some(result)
+}
+
+/// The kind of a token, see `tokenize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident,
+    Lifetime,
+    Punct,
+}
+
+/// A token of Rust source, see `tokenize`.
+#[derive(Debug)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub row: usize,
+    /// In chars.
+    pub col: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn end_col(&self) -> usize {
+        self.col + self.text.chars().count()
+    }
+
+    pub fn is(&self, kind: TokenKind, text: &str) -> bool {
+        self.kind == kind && self.text == text
+    }
+
+    fn span(&self, file: &Path) -> Span {
+        let row = span::Row::new_zero_indexed(self.row as u32);
+        Span::from_positions(span::Position::new(row, span::Column::new_zero_indexed(self.col as u32)),
+                             span::Position::new(row, span::Column::new_zero_indexed(self.end_col() as u32)),
+                             file)
+    }
+}
+
+// Where a lifetime or label is declared and the tokens it's in scope for
+// (`start` to `end`, inclusive).
+#[derive(Debug)]
+struct Scope {
+    declaration: usize,
+    start: usize,
+    end: usize,
+    label: bool,
+}
+
+impl Scope {
+    fn contains(&self, i: usize) -> bool {
+        self.start <= i && i <= self.end
+    }
+}
+
+// Whether the lifetime token at `i` is a label: declared before a loop or
+// block, or used by `break` or `continue`.
+fn is_label(tokens: &[Token], i: usize) -> bool {
+    if i > 0 && (tokens[i - 1].is(TokenKind::Ident, "break") || tokens[i - 1].is(TokenKind::Ident, "continue")) {
+        return true;
+    }
+    match (tokens.get(i + 1), tokens.get(i + 2)) {
+        (Some(colon), Some(next)) if colon.is(TokenKind::Punct, ":") => {
+            next.is(TokenKind::Punct, "{") || (next.kind == TokenKind::Ident && LABELLED.contains(&next.text))
+        }
+        _ => false,
+    }
+}
+
+// The declarations of lifetimes and labels in `tokens`, with their scopes.
+fn scopes(tokens: &[Token]) -> Vec<Scope> {
+    let mut result = vec![];
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind == TokenKind::Lifetime && is_label(tokens, i) && tokens.get(i + 1).map_or(false, |t| t.text == ":") {
+            // The scope ends with the body of the loop.
+            let end = (i..tokens.len()).find(|&j| tokens[j].is(TokenKind::Punct, "{"))
+                .and_then(|open| matching_close(tokens, open))
+                .unwrap_or(tokens.len() - 1);
+            result.push(Scope { declaration: i, start: i, end, label: true });
+            continue;
+        }
+
+        if token.kind != TokenKind::Ident || !ITEM_KEYWORDS.contains(&token.text) {
+            continue;
+        }
+        // `impl<'a>` and `for<'a>`, the others are followed by their name.
+        let open = if token.text == "impl" || token.text == "for" { i + 1 } else { i + 2 };
+        if !tokens.get(open).map_or(false, |t| t.is(TokenKind::Punct, "<")) {
+            continue;
+        }
+        let mut declarations = vec![];
+        let mut depth = 0;
+        let mut close = None;
+        for j in open..tokens.len() {
+            match tokens[j].text {
+                "<" => depth += 1,
+                ">" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(j);
+                        break;
+                    }
+                }
+                _ if depth == 1 && tokens[j].kind == TokenKind::Lifetime &&
+                     (tokens[j - 1].text == "<" || tokens[j - 1].text == ",") => declarations.push(j),
+                _ => {}
+            }
+        }
+        let close = match close {
+            Some(close) => close,
+            None => continue,
+        };
+        let end = if token.text == "for" {
+            bound_end(tokens, close + 1)
+        } else {
+            item_end(tokens, close + 1)
+        };
+        for declaration in declarations {
+            result.push(Scope { declaration, start: i, end, label: false });
+        }
+    }
+    result
+}
+
+// The last token of an item, starting after its generics: its `;` or the `}`
+// closing its body.
+fn item_end(tokens: &[Token], from: usize) -> usize {
+    let mut depth = 0;
+    for i in from..tokens.len() {
+        match tokens[i].text {
+            "(" | "[" => depth += 1,
+            ")" | "]" => depth -= 1,
+            ";" if depth == 0 => return i,
+            "{" if depth == 0 => return matching_close(tokens, i).unwrap_or(tokens.len() - 1),
+            "{" => depth += 1,
+            "}" => depth -= 1,
+            _ => {}
+        }
+    }
+    tokens.len() - 1
+}
+
+// The last token of the bound after `for<...>`, before the `,`, `;`, `{`,
+// `=` or closing bracket ending it.
+fn bound_end(tokens: &[Token], from: usize) -> usize {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(from) {
+        match token.text {
+            "(" | "[" | "<" => depth += 1,
+            ")" | "]" | ">" | "}" if depth == 0 => return i - 1,
+            ")" | "]" | ">" => depth -= 1,
+            "," | ";" | "{" | "=" if depth == 0 => return i - 1,
+            _ => {}
+        }
+    }
+    tokens.len() - 1
+}
+
+/// The `}` closing the `{` at `open`.
+pub fn matching_close(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token.text {
+            "{" => depth += 1,
+            "}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits `text` into identifiers, lifetimes and punctuation, skipping
+/// whitespace, comments and literals. `->` and `=>` are single tokens, so their
+/// `>` isn't taken for a closing bracket.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let char_at = |i: usize| chars.get(i).map(|&(_, c)| c);
+    let byte_at = |i: usize| chars.get(i).map_or(text.len(), |&(b, _)| b);
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut tokens = vec![];
+    // The index in `chars` of the start of the current row.
+    let mut row_start = 0;
+    let mut row = 0;
+    let mut i = 0;
+    // Moves past the char at `i`, keeping track of rows.
+    macro_rules! bump {
+        () => {{
+            if char_at(i) == Some('\n') {
+                row += 1;
+                row_start = i + 1;
+            }
+            i += 1;
+        }}
+    }
+
+    while let Some(c) = char_at(i) {
+        let start = i;
+        let col = i - row_start;
+        let next = char_at(i + 1);
+        if c == '/' && next == Some('/') {
+            while char_at(i).map_or(false, |c| c != '\n') {
+                bump!();
+            }
+        } else if c == '/' && next == Some('*') {
+            let mut depth = 0;
+            while let Some(c) = char_at(i) {
+                if c == '/' && char_at(i + 1) == Some('*') {
+                    depth += 1;
+                    bump!();
+                } else if c == '*' && char_at(i + 1) == Some('/') {
+                    depth -= 1;
+                    bump!();
+                    if depth == 0 {
+                        bump!();
+                        break;
+                    }
+                }
+                bump!();
+            }
+        } else if c == '"' || (c == 'b' && next == Some('"')) {
+            if c == 'b' {
+                bump!();
+            }
+            bump!();
+            while let Some(c) = char_at(i) {
+                bump!();
+                if c == '\\' {
+                    bump!();
+                } else if c == '"' {
+                    break;
+                }
+            }
+        } else if (c == 'r' || (c == 'b' && next == Some('r'))) && raw_string_hashes(&chars, if c == 'b' { i + 2 } else { i + 1 }).is_some() {
+            let open = if c == 'b' { i + 2 } else { i + 1 };
+            let hashes = raw_string_hashes(&chars, open).unwrap();
+            while i < open + hashes + 1 {
+                bump!();
+            }
+            while let Some(c) = char_at(i) {
+                bump!();
+                if c == '"' && (i..i + hashes).all(|j| char_at(j) == Some('#')) {
+                    for _ in 0..hashes {
+                        bump!();
+                    }
+                    break;
+                }
+            }
+        } else if c == '\'' || (c == 'b' && next == Some('\'')) {
+            let quote = if c == 'b' { i + 1 } else { i };
+            let after = char_at(quote + 1);
+            if c == '\'' && after.map_or(false, |c| is_ident_char(c) && !c.is_digit(10)) && char_at(quote + 2) != Some('\'') {
+                // A lifetime or label.
+                bump!();
+                while char_at(i).map_or(false, &is_ident_char) {
+                    bump!();
+                }
+                tokens.push(Token { kind: TokenKind::Lifetime, text: &text[byte_at(start)..byte_at(i)], row, col });
+            } else {
+                // A character literal, e.g. `'a'` or `'\n'`.
+                while i <= quote {
+                    bump!();
+                }
+                while let Some(c) = char_at(i) {
+                    bump!();
+                    if c == '\\' {
+                        bump!();
+                    } else if c == '\'' || c == '\n' {
+                        break;
+                    }
+                }
+            }
+        } else if c.is_digit(10) {
+            while char_at(i).map_or(false, &is_ident_char) {
+                bump!();
+            }
+        } else if is_ident_char(c) {
+            // Raw identifiers are identifiers.
+            if c == 'r' && next == Some('#') && char_at(i + 2).map_or(false, &is_ident_char) {
+                bump!();
+                bump!();
+            }
+            while char_at(i).map_or(false, &is_ident_char) {
+                bump!();
+            }
+            tokens.push(Token { kind: TokenKind::Ident, text: &text[byte_at(start)..byte_at(i)], row, col });
+        } else if c.is_whitespace() {
+            bump!();
+        } else {
+            bump!();
+            if (c == '-' || c == '=') && next == Some('>') {
+                bump!();
+            }
+            tokens.push(Token { kind: TokenKind::Punct, text: &text[byte_at(start)..byte_at(i)], row, col });
+        }
+    }
+    tokens
+}
+
+// The number of `#`s of a raw string whose `r` is followed by `chars[from]`,
+// `None` if it's not a raw string (e.g. a raw identifier).
+fn raw_string_hashes(chars: &[(usize, char)], from: usize) -> Option<usize> {
+    let hashes = chars[from..].iter().take_while(|&&(_, c)| c == '#').count();
+    match chars.get(from + hashes) {
+        Some(&(_, '"')) => Some(hashes),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The (row, col) of each occurrence.
+    fn find(text: &str, row: usize, col: usize) -> Option<Vec<(u32, u32)>> {
+        occurrences(text, Path::new("a.rs"), row, col).map(|spans| {
+            spans.iter().map(|s| (s.range.row_start.0, s.range.col_start.0)).collect()
+        })
+    }
+
+    #[test]
+    fn test_lifetimes() {
+        let text = "impl<'a> Foo<'a> {\n\
+                    \x20   fn get<'b>(&'b self) -> &'a str { self.0 }\n\
+                    }\n\
+                    fn other<'a>(x: &'a str) -> &'a str { let c = 'a'; x }\n";
+        assert_eq!(find(text, 0, 6), Some(vec![(0, 5), (0, 13), (1, 29)]));
+        assert_eq!(find(text, 1, 12), Some(vec![(1, 11), (1, 16)]));
+        assert_eq!(find(text, 3, 18), Some(vec![(3, 9), (3, 17), (3, 29)]));
+        // The character literal isn't a lifetime.
+        assert_eq!(find(text, 3, 47), None);
+    }
+
+    #[test]
+    fn test_labels() {
+        let text = "fn f<'a>(x: &'a [u32]) {\n\
+                    \x20   'a: for i in x {\n\
+                    \x20       'inner: loop { break 'a; }\n\
+                    \x20       continue 'a;\n\
+                    \x20   }\n\
+                    }\n";
+        assert_eq!(find(text, 1, 5), Some(vec![(1, 4), (2, 29), (3, 17)]));
+        assert_eq!(find(text, 2, 9), Some(vec![(2, 8)]));
+        assert_eq!(find(text, 0, 14), Some(vec![(0, 5), (0, 13)]));
+    }
+
+    #[test]
+    fn test_higher_ranked() {
+        let text = "fn call<F>(f: F) where F: for<'a> Fn(&'a u8) -> &'a u8, { let s: &'static str = \"'a\"; }\n";
+        assert_eq!(find(text, 0, 31), Some(vec![(0, 30), (0, 38), (0, 49)]));
+        assert_eq!(find(text, 0, 67), None);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let text = "// 'a\n/* 'b /* 'c */ */ r#\"'d\"# b'e' '\\'' r#fn 'f";
+        let tokens: Vec<_> = tokenize(text).into_iter().map(|t| (t.kind, t.text)).collect();
+        assert_eq!(tokens, [(TokenKind::Ident, "r#fn"), (TokenKind::Lifetime, "'f")]);
+    }
+}
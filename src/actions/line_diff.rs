@@ -0,0 +1,201 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Line-level diffs, so formatting a document only replaces the lines which
+//! changed (replacing all of it makes editors lose the cursor position and
+//! marks).
+//!
+//! This is a patience diff: lines occurring exactly once in both texts are
+//! matched up (the longest sequence of them in the same order), and the
+//! stretches between them are diffed in the same way. Stretches without such
+//! lines are replaced as a whole.
+
+use ls_types::{Position, Range, TextEdit};
+
+use std::collections::HashMap;
+
+/// The edits turning `old` into `new`, each replacing a run of whole lines.
+/// Columns are counted in chars.
+pub fn line_edits(old: &str, new: &str) -> Vec<TextEdit> {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let mut hunks = vec![];
+    diff(&old_lines, &new_lines, 0, 0, &mut hunks);
+
+    hunks.into_iter().map(|h| {
+        TextEdit {
+            range: Range::new(line_start(&old_lines, h.old_start), line_start(&old_lines, h.old_end)),
+            new_text: new_lines[h.new_start..h.new_end].concat(),
+        }
+    }).collect()
+}
+
+// Lines `old_start..old_end` of the old text are replaced by lines
+// `new_start..new_end` of the new one.
+#[derive(Debug)]
+struct Hunk {
+    old_start: usize,
+    old_end: usize,
+    new_start: usize,
+    new_end: usize,
+}
+
+// The lines of `text`, including their line endings.
+fn lines(text: &str) -> Vec<&str> {
+    let mut result = vec![];
+    let mut start = 0;
+    for (i, _) in text.match_indices('\n') {
+        result.push(&text[start..i + 1]);
+        start = i + 1;
+    }
+    if start < text.len() {
+        result.push(&text[start..]);
+    }
+    result
+}
+
+// The position where line `index` starts, or the end of the text if there is
+// no such line.
+fn line_start(lines: &[&str], index: usize) -> Position {
+    match lines.last() {
+        Some(last) if index == lines.len() && !last.ends_with('\n') => {
+            Position::new(index as u64 - 1, last.chars().count() as u64)
+        }
+        _ => Position::new(index as u64, 0),
+    }
+}
+
+fn diff(old: &[&str], new: &[&str], old_offset: usize, new_offset: usize, hunks: &mut Vec<Hunk>) {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+    let old_offset = old_offset + prefix;
+    let new_offset = new_offset + prefix;
+    if old.is_empty() && new.is_empty() {
+        return;
+    }
+
+    let anchors = if old.is_empty() || new.is_empty() { vec![] } else { unique_common_lines(old, new) };
+    if anchors.is_empty() {
+        hunks.push(Hunk {
+            old_start: old_offset,
+            old_end: old_offset + old.len(),
+            new_start: new_offset,
+            new_end: new_offset + new.len(),
+        });
+        return;
+    }
+
+    let (mut o, mut n) = (0, 0);
+    for (anchor_old, anchor_new) in anchors {
+        diff(&old[o..anchor_old], &new[n..anchor_new], old_offset + o, new_offset + n, hunks);
+        o = anchor_old + 1;
+        n = anchor_new + 1;
+    }
+    diff(&old[o..], &new[n..], old_offset + o, new_offset + n, hunks);
+}
+
+// The indices of lines which occur exactly once in both `old` and `new`, the
+// longest sequence of them in the same order in both.
+fn unique_common_lines(old: &[&str], new: &[&str]) -> Vec<(usize, usize)> {
+    // Occurrences and index of each line in the old and the new text.
+    let mut occurrences: HashMap<&str, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, line) in old.iter().enumerate() {
+        let entry = occurrences.entry(*line).or_insert((0, 0, 0, 0));
+        entry.0 += 1;
+        entry.2 = i;
+    }
+    for (i, line) in new.iter().enumerate() {
+        let entry = occurrences.entry(*line).or_insert((0, 0, 0, 0));
+        entry.1 += 1;
+        entry.3 = i;
+    }
+    let mut pairs: Vec<(usize, usize)> = occurrences.values()
+        .filter(|&&(in_old, in_new, _, _)| in_old == 1 && in_new == 1)
+        .map(|&(_, _, i, j)| (i, j))
+        .collect();
+    pairs.sort();
+
+    // Longest increasing subsequence of the new indices (patience sorting):
+    // `tails[k]` is the pair ending the best sequence of length `k + 1`.
+    let mut tails: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (p, &(_, j)) in pairs.iter().enumerate() {
+        let k = match tails.binary_search_by_key(&j, |&t| pairs[t].1) {
+            Ok(k) | Err(k) => k,
+        };
+        previous[p] = if k == 0 { None } else { Some(tails[k - 1]) };
+        if k == tails.len() {
+            tails.push(p);
+        } else {
+            tails[k] = p;
+        }
+    }
+
+    let mut result = vec![];
+    let mut p = tails.last().cloned();
+    while let Some(i) = p {
+        result.push(pairs[i]);
+        p = previous[i];
+    }
+    result.reverse();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn apply(text: &str, edits: &[TextEdit]) -> String {
+        // Edits are ordered and don't overlap, apply them from the end.
+        let mut lines: Vec<String> = lines(text).into_iter().map(|l| l.to_owned()).collect();
+        for edit in edits.iter().rev() {
+            let start = edit.range.start.line as usize;
+            let end = if edit.range.end.character > 0 { edit.range.end.line as usize + 1 } else { edit.range.end.line as usize };
+            lines.splice(start..end, Some(edit.new_text.clone()));
+        }
+        lines.concat()
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(lines("a\nb\r\nc"), vec!["a\n", "b\r\n", "c"]);
+        assert_eq!(lines("a\n"), vec!["a\n"]);
+        assert!(lines("").is_empty());
+    }
+
+    #[test]
+    fn test_line_edits() {
+        let old = "fn main() {\nlet x = 1;\n    println!(\"{}\", x);\n}\n";
+        let new = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+        let edits = line_edits(old, new);
+        assert_eq!(edits, vec![TextEdit {
+            range: Range::new(Position::new(1, 0), Position::new(2, 0)),
+            new_text: "    let x = 1;\n".to_owned(),
+        }]);
+
+        assert!(line_edits(old, old).is_empty());
+    }
+
+    #[test]
+    fn test_line_edits_apply() {
+        let cases = [
+            ("a\nb\nc\nd\n", "a\nx\nc\ny\nd\n"),
+            ("}\n}\nfoo\n}\n", "foo\n}\n}\n}\n"),
+            ("a\nb", "a\nc"),
+            ("a\nb\n", ""),
+            ("", "a\n"),
+        ];
+        for &(old, new) in &cases {
+            assert_eq!(apply(old, &line_edits(old, new)), new, "{:?} -> {:?}", old, new);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Where the lines of a file start, so that positions can be converted to
+//! offsets (and lines looked up) without walking the whole text every time.
+//!
+//! Columns are counted in chars, like everywhere else in the RLS.
+
+use actions::FileAccess;
+use ls_types::Position;
+use vfs::{self, Vfs};
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The line starts of a single version of a file.
+#[derive(Debug)]
+pub struct LineIndex {
+    // Byte offset of the start of each line.
+    line_starts: Vec<usize>,
+    len: usize,
+    // Byte offset (relative to the line start) of every char in lines which
+    // aren't plain ASCII, where bytes and chars differ.
+    char_offsets: HashMap<usize, Vec<usize>>,
+}
+
+impl LineIndex {
+    /// Index the given text.
+    pub fn new(text: &str) -> LineIndex {
+        let mut line_starts = vec![0];
+        let mut char_offsets = HashMap::new();
+        for (row, line) in text.split('\n').enumerate() {
+            let start = line_starts[row];
+            line_starts.push(start + line.len() + 1);
+            if line.bytes().any(|b| b >= 0x80) {
+                char_offsets.insert(row, line.char_indices().map(|(i, _)| i).collect());
+            }
+        }
+        // There is no line after the last one.
+        line_starts.pop();
+
+        LineIndex {
+            line_starts,
+            len: text.len(),
+            char_offsets,
+        }
+    }
+
+    /// Number of lines in the text, a trailing newline starts an (empty) line.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The byte range of the given (zero-indexed) line, without the `\n`.
+    pub fn line_range(&self, row: usize) -> Option<Range<usize>> {
+        let start = *self.line_starts.get(row)?;
+        let end = match self.line_starts.get(row + 1) {
+            Some(next) => next - 1,
+            None => self.len,
+        };
+        Some(start..end)
+    }
+
+    /// The byte offset of the given position. Columns past the end of the
+    /// line are clamped to its end.
+    pub fn position_to_offset(&self, pos: Position) -> Option<usize> {
+        let row = pos.line as usize;
+        let range = self.line_range(row)?;
+        let col = pos.character as usize;
+        let offset = match self.char_offsets.get(&row) {
+            Some(offsets) => offsets.get(col).cloned().unwrap_or(range.end - range.start),
+            None => ::std::cmp::min(col, range.end - range.start),
+        };
+        Some(range.start + offset)
+    }
+
+    /// The position of the given byte offset, `None` if it is out of bounds.
+    pub fn offset_to_position(&self, offset: usize) -> Option<Position> {
+        if offset > self.len {
+            return None;
+        }
+        let row = match self.line_starts.binary_search(&offset) {
+            Ok(row) => row,
+            Err(next_row) => next_row - 1,
+        };
+        let in_line = offset - self.line_starts[row];
+        let col = match self.char_offsets.get(&row) {
+            Some(offsets) => offsets.iter().take_while(|&&o| o < in_line).count(),
+            None => in_line,
+        };
+        Some(Position::new(row as u64, col as u64))
+    }
+}
+
+/// The line indices of the files in the VFS, built when first needed.
+///
+/// Entries must be invalidated whenever a file changes, see `DidChange`.
+pub struct LineIndexCache {
+    indices: Mutex<HashMap<PathBuf, Arc<LineIndex>>>,
+}
+
+impl LineIndexCache {
+    /// Construct an empty cache.
+    pub fn new() -> LineIndexCache {
+        LineIndexCache {
+            indices: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Calls `f` with the current text of the file at `path` and its index.
+    pub fn with_index<F, R>(&self, vfs: &Vfs, path: &Path, f: F) -> Result<R, vfs::Error>
+        where F: FnOnce(&str, &LineIndex) -> R
+    {
+        // The index is built and stored while the VFS is locked, so it always
+        // matches the text we were given.
+        vfs.with_file(path, |text| {
+            let index = self.indices.lock().unwrap()
+                .entry(path.to_owned())
+                .or_insert_with(|| Arc::new(LineIndex::new(text)))
+                .clone();
+            f(text, &index)
+        })
+    }
+
+    /// The given (zero-indexed) line of the file at `path`.
+    pub fn load_line(&self, vfs: &Vfs, path: &Path, row: usize) -> Result<String, vfs::Error> {
+        self.with_index(vfs, path, |text, index| {
+            index.line_range(row).and_then(|range| text.get(range)).map(|line| line.trim_right_matches('\r').to_owned())
+        })?.ok_or(vfs::Error::BadLocation)
+    }
+
+    /// Forget the index of the file at `path`, because its text changed.
+    pub fn invalidate(&self, path: &Path) {
+        self.indices.lock().unwrap().remove(path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        let text = "fn main() {\r\n    let é = 1;\n}\n";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(&text[index.line_range(1).unwrap()], "    let é = 1;");
+        assert_eq!(&text[index.line_range(3).unwrap()], "");
+        assert_eq!(index.line_range(4), None);
+
+        assert_eq!(index.position_to_offset(Position::new(0, 3)), Some(3));
+        assert_eq!(index.position_to_offset(Position::new(1, 9)), Some(23));
+        assert_eq!(index.position_to_offset(Position::new(1, 10)), Some(24));
+        assert_eq!(index.position_to_offset(Position::new(2, 100)), Some(30));
+        assert_eq!(index.position_to_offset(Position::new(5, 0)), None);
+
+        assert_eq!(index.offset_to_position(3), Some(Position::new(0, 3)));
+        assert_eq!(index.offset_to_position(24), Some(Position::new(1, 10)));
+        assert_eq!(index.offset_to_position(31), Some(Position::new(3, 0)));
+        assert_eq!(index.offset_to_position(32), None);
+    }
+}
@@ -0,0 +1,1142 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `Cargo.toml` manifests: what they declare, for completions in source files,
+//! and the handling of manifests opened in the editor, which aren't Rust
+//! source. Dependency names, versions and features are completed from the copy
+//! of the registry index Cargo keeps, and keys Cargo doesn't know are reported.
+//!
+//! Manifests are parsed with the `toml` crate, as Cargo parses them. It
+//! doesn't keep where keys are, so the lines are scanned to find them: tables
+//! start with a `[header]` line, entries with a `key =` line. Completions are
+//! in manifests being typed, which often aren't valid TOML, so what is being
+//! completed is found from the lines alone.
+//!
+//! Dependencies whose crate none of the analysed crates of the package refers
+//! to are reported as unused, see `used_crates`.
+
+use actions::InitActionContext;
+use actions::positions::PositionConverter;
+use actions::post_build::publish;
+use build::LibNames;
+use cargo::util::homedir;
+use git2;
+use lsp_data::{CompletionItem, CompletionItemKind, DiagnosticTag, RlsDiagnostic};
+use ls_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range, TextEdit};
+use serde_json;
+use server::Output;
+use toml::{self, Value};
+use toml::value::Table as TomlTable;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// The parts of a manifest the RLS knows about.
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    /// The name of the package.
+    pub name: Option<String>,
+    /// The features of the package.
+    pub features: Vec<String>,
+    /// The dependencies of the package, of any kind and for any target.
+    pub dependencies: Vec<Dependency>,
+}
+
+/// A dependency in a manifest.
+#[derive(Debug, PartialEq)]
+pub struct Dependency {
+    /// The name of the package depended on.
+    pub name: String,
+    /// Whether the dependency is optional, i.e. also a feature.
+    pub optional: bool,
+    /// Whether it's in `[dependencies]`, i.e. not only for some platforms,
+    /// for tests or for the build script.
+    pub normal: bool,
+    /// The zero-indexed line of its entry (or table header) and the last line
+    /// of its entry (or table).
+    pub row: usize,
+    pub last_row: usize,
+}
+
+/// The manifest in `dir`, if there is one.
+pub fn read_manifest(dir: &Path) -> Option<Manifest> {
+    let mut text = String::new();
+    File::open(dir.join("Cargo.toml")).ok()?.read_to_string(&mut text).ok()?;
+    Some(parse_manifest(&text))
+}
+
+/// Reads the features and dependencies declared by the manifest `text`, none
+/// if it isn't valid TOML.
+pub fn parse_manifest(text: &str) -> Manifest {
+    match toml::from_str(text) {
+        Ok(value) => manifest_of(text, &value),
+        Err(e) => {
+            debug!("could not parse manifest: {}", e);
+            Manifest::default()
+        }
+    }
+}
+
+// What the manifest `text`, parsed as `value`, declares.
+fn manifest_of(text: &str, value: &Value) -> Manifest {
+    let lines = manifest_lines(text);
+    let mut dependencies = vec![];
+    for (table, entries) in dependency_tables(value) {
+        for (name, dependency) in entries {
+            // Skips the dependencies whose lines can't be found, e.g. of a
+            // `dependencies = { ... }` entry.
+            let (row, last_row) = match dependency_rows(&lines, &table, name) {
+                Some(rows) => rows,
+                None => continue,
+            };
+            dependencies.push(Dependency {
+                name: name.clone(),
+                optional: dependency.get("optional").and_then(Value::as_bool).unwrap_or(false),
+                normal: table == ["dependencies"],
+                row,
+                last_row,
+            });
+        }
+    }
+    dependencies.sort_by_key(|d| d.row);
+
+    let package = value.get("package").or_else(|| value.get("project"));
+    Manifest {
+        name: package.and_then(|p| p.get("name")).and_then(Value::as_str).map(|name| name.to_owned()),
+        features: value.get("features").and_then(Value::as_table).map_or(vec![], |f| f.keys().cloned().collect()),
+        dependencies,
+    }
+}
+
+impl Manifest {
+    /// What can be named in `feature = "..."`: the features and the optional
+    /// dependencies.
+    pub fn feature_names(&self) -> Vec<&str> {
+        let optional = self.dependencies.iter().filter(|d| d.optional).map(|d| &*d.name);
+        self.features.iter().map(|f| &**f).chain(optional).collect()
+    }
+}
+
+/// The features enabled in the manifest `text` by building with the
+/// `requested` features (as with `--features`), with every feature
+/// (`--all-features`) or without the default features
+/// (`--no-default-features`): those and the ones they enable, transitively.
+/// Optional dependencies count as features.
+pub fn enabled_features(text: &str, requested: &[String], all: bool, no_default: bool) -> HashSet<String> {
+    let value = match toml::from_str(text) {
+        Ok(value) => value,
+        Err(_) => return HashSet::new(),
+    };
+    let manifest = manifest_of(text, &value);
+    let names = manifest.feature_names();
+    if all {
+        return names.into_iter().map(|name| name.to_owned()).collect();
+    }
+    let values = feature_values(&value);
+    let mut todo = requested.to_vec();
+    if !no_default {
+        todo.push("default".to_owned());
+    }
+    let mut enabled = HashSet::new();
+    while let Some(feature) = todo.pop() {
+        // `dependency/feature` enables the dependency too.
+        let feature = match feature.find('/') {
+            Some(slash) => feature[..slash].to_owned(),
+            None => feature,
+        };
+        if !names.contains(&&*feature) || enabled.contains(&feature) {
+            continue;
+        }
+        if let Some(implied) = values.get(&feature) {
+            todo.extend(implied.iter().cloned());
+        }
+        enabled.insert(feature);
+    }
+    enabled
+}
+
+// The features each feature of the manifest `value` enables, e.g. `std` for
+// `default = ["std"]`.
+fn feature_values(value: &Value) -> HashMap<String, Vec<String>> {
+    let features = match value.get("features").and_then(Value::as_table) {
+        Some(features) => features,
+        None => return HashMap::new(),
+    };
+    features.iter().map(|(feature, implied)| {
+        let implied = implied.as_array().map_or(vec![], |implied| {
+            implied.iter().filter_map(Value::as_str).map(|f| f.to_owned()).collect()
+        });
+        (feature.clone(), implied)
+    }).collect()
+}
+
+/// Whether `path` is a manifest, handled by this module rather than as Rust
+/// source.
+pub fn is_manifest(path: &Path) -> bool {
+    path.file_name().map_or(false, |name| name == "Cargo.toml")
+}
+
+/// Publishes the diagnostics of the manifest `file` as it is in the VFS.
+pub fn publish_manifest_diagnostics<O: Output>(ctx: &InitActionContext, file: &Path, out: &O) {
+    let used = if ctx.config.lock().unwrap().unused_dependencies {
+        file.parent().and_then(|dir| ctx.used_crates.package_uses(dir))
+    } else {
+        None
+    };
+    let diagnostics = ctx.vfs.with_file(file, |text| {
+        manifest_diagnostics(text, file, used.as_ref(), &ctx.build_queue.lib_names(), &ctx.positions,
+                             ctx.client.diagnostic_tags())
+    });
+    if let Ok(diagnostics) = diagnostics {
+        publish(file, diagnostics, &ctx.documents, out);
+    }
+}
+
+/// The diagnostics of the manifest `file` with the contents `text`: its
+/// unknown keys and, given the crates its package `used` (see `used_crates`),
+/// its unused dependencies.
+pub fn manifest_diagnostics(text: &str,
+                            file: &Path,
+                            used: Option<&HashSet<String>>,
+                            lib_names: &LibNames,
+                            positions: &PositionConverter,
+                            tags: bool)
+                            -> Vec<RlsDiagnostic> {
+    let diagnostic = |row: usize, col_start: usize, col_end: usize, code: &str, message: String| {
+        let range = Range::new(Position::new(row as u64, col_start as u64), Position::new(row as u64, col_end as u64));
+        RlsDiagnostic::new(Diagnostic {
+            range: positions.char_range_to_client(file, range),
+            severity: Some(DiagnosticSeverity::Warning),
+            code: Some(NumberOrString::String(code.to_owned())),
+            source: Some("rls".to_owned()),
+            message,
+        })
+    };
+    let mut diagnostics: Vec<_> = unknown_keys(text).into_iter().map(|key| {
+        diagnostic(key.row, key.col_start, key.col_end, "unused_manifest_key", format!("unused manifest key: `{}`", key.name))
+    }).collect();
+    if let Some(used) = used {
+        for dependency in unused_dependencies(text, used, lib_names) {
+            let mut unused = diagnostic(dependency.row,
+                                        dependency.col_start,
+                                        dependency.col_end,
+                                        "unused_dependency",
+                                        format!("unused dependency: `{}`", dependency.name));
+            if tags {
+                unused.tags = vec![DiagnosticTag::Unnecessary];
+            }
+            diagnostics.push(unused);
+        }
+    }
+    diagnostics
+}
+
+/// A dependency which no crate of its package uses.
+#[derive(Debug, PartialEq)]
+pub struct UnusedDependency {
+    /// The name of the package depended on.
+    pub name: String,
+    /// The zero-indexed line of the name.
+    pub row: usize,
+    /// The char columns of the name.
+    pub col_start: usize,
+    pub col_end: usize,
+    /// The lines to remove to remove the dependency.
+    pub removal: Range,
+}
+
+/// The dependencies of the manifest `text` whose library (see `LibNames`)
+/// isn't among the crates `used` by its package. Only those in
+/// `[dependencies]` which aren't optional are reported, others may just not be
+/// compiled.
+pub fn unused_dependencies(text: &str, used: &HashSet<String>, lib_names: &LibNames) -> Vec<UnusedDependency> {
+    let lines: Vec<&str> = text.lines().collect();
+    parse_manifest(text).dependencies.into_iter()
+        .filter(|d| d.normal && !d.optional && !used.contains(&lib_names.crate_name(&d.name)))
+        .filter_map(|d| {
+            let line = lines.get(d.row)?;
+            let start = key_start(line, &d.name)?;
+            let col_start = line[..start].chars().count();
+            Some(UnusedDependency {
+                row: d.row,
+                col_start,
+                col_end: col_start + d.name.chars().count(),
+                removal: Range::new(Position::new(d.row as u64, 0), Position::new(d.last_row as u64 + 1, 0)),
+                name: d.name,
+            })
+        })
+        .collect()
+}
+
+// The byte index of the dependency `name` on its `line`: the key of its entry,
+// or the last part of its table header (e.g. `[dependencies.name]`), quoted or
+// not.
+fn key_start(line: &str, name: &str) -> Option<usize> {
+    let trimmed = line.trim_left();
+    let indent = line.len() - trimmed.len();
+    let start = if trimmed.starts_with('[') {
+        let header = &line[..line.find(']')?];
+        header.rfind(name)?
+    } else if trimmed.starts_with('"') || trimmed.starts_with('\'') {
+        indent + 1
+    } else {
+        indent
+    };
+    if line[start..].starts_with(name) {
+        Some(start)
+    } else {
+        None
+    }
+}
+
+/// A key of a manifest Cargo doesn't know and ignores.
+#[derive(Debug, PartialEq)]
+pub struct UnknownKey {
+    /// The key, with the table it's in, e.g. `package.autor`.
+    pub name: String,
+    /// The zero-indexed line of the key.
+    pub row: usize,
+    /// The char columns of the key.
+    pub col_start: usize,
+    pub col_end: usize,
+}
+
+const TABLES: &[&str] = &[
+    "badges", "bench", "bin", "build-dependencies", "dependencies", "dev-dependencies", "example", "features",
+    "lib", "package", "patch", "profile", "project", "replace", "target", "test", "workspace",
+];
+
+const PACKAGE_KEYS: &[&str] = &[
+    "authors", "autobenches", "autobins", "autoexamples", "autotests", "badges", "build", "categories",
+    "default-run", "description", "documentation", "edition", "exclude", "homepage", "include", "keywords",
+    "license", "license-file", "links", "metadata", "name", "publish", "readme", "repository", "version",
+    "workspace",
+];
+
+const TARGET_KEYS: &[&str] = &[
+    "bench", "crate-type", "crate_type", "doc", "doctest", "edition", "harness", "name", "path", "plugin",
+    "proc-macro", "proc_macro", "required-features", "test",
+];
+
+const PROFILE_KEYS: &[&str] = &[
+    "codegen-units", "debug", "debug-assertions", "incremental", "lto", "opt-level", "overflow-checks", "panic",
+    "rpath",
+];
+
+const WORKSPACE_KEYS: &[&str] = &["default-members", "exclude", "members", "metadata"];
+
+const DEPENDENCY_KEYS: &[&str] = &[
+    "branch", "default-features", "default_features", "features", "git", "optional", "package", "path",
+    "registry", "rev", "tag", "version",
+];
+
+/// The keys of the manifest `text` Cargo doesn't know: tables, keys of the
+/// tables it knows the keys of and keys of dependency tables, inline or not.
+pub fn unknown_keys(text: &str) -> Vec<UnknownKey> {
+    let value: Value = match toml::from_str(text) {
+        Ok(value) => value,
+        Err(_) => return vec![],
+    };
+    let root = match value.as_table() {
+        Some(root) => root,
+        None => return vec![],
+    };
+    let mut paths = vec![];
+    for (key, table) in root {
+        let path = vec![key.clone()];
+        match &**key {
+            "package" | "project" => unknown_in(&path, table, PACKAGE_KEYS, &mut paths),
+            "lib" => unknown_in(&path, table, TARGET_KEYS, &mut paths),
+            "bin" | "example" | "test" | "bench" => {
+                for target in table.as_array().map_or(&[][..], |targets| &targets[..]) {
+                    unknown_in(&path, target, TARGET_KEYS, &mut paths);
+                }
+            }
+            "workspace" => unknown_in(&path, table, WORKSPACE_KEYS, &mut paths),
+            "profile" => {
+                for (name, profile) in table.as_table().into_iter().flat_map(|profiles| profiles) {
+                    unknown_in(&[key.clone(), name.clone()], profile, PROFILE_KEYS, &mut paths);
+                }
+            }
+            _ if TABLES.contains(&&**key) || key == "cargo-features" => {}
+            _ => paths.push(path),
+        }
+    }
+    for (table, dependencies) in dependency_tables(&value) {
+        for (name, dependency) in dependencies {
+            let mut path = table.clone();
+            path.push(name.clone());
+            unknown_in(&path, dependency, DEPENDENCY_KEYS, &mut paths);
+        }
+    }
+
+    let lines = manifest_lines(text);
+    let mut result: Vec<_> = paths.into_iter().filter_map(|path| {
+        let (line, start) = locate_key(&lines, &path)?;
+        let key = &path[path.len() - 1];
+        let col_start = line.code[..start].chars().count();
+        Some(UnknownKey {
+            name: path.join("."),
+            row: line.row,
+            col_start,
+            col_end: col_start + key.chars().count(),
+        })
+    }).collect();
+    result.sort_by_key(|key| (key.row, key.col_start));
+    result
+}
+
+// Adds the paths of the keys of `table`, at `path`, which aren't `known` to
+// `result`.
+fn unknown_in(path: &[String], table: &Value, known: &[&str], result: &mut Vec<Vec<String>>) {
+    for key in table.as_table().into_iter().flat_map(|table| table.keys()) {
+        if !known.contains(&&**key) {
+            let mut key_path = path.to_vec();
+            key_path.push(key.clone());
+            result.push(key_path);
+        }
+    }
+}
+
+// The line of the key at `path` and the byte index of the key on it: in a
+// table header, as the key of an entry or in an inline table.
+fn locate_key<'a, 'b>(lines: &'a [Line<'b>], path: &[String]) -> Option<(&'a Line<'b>, usize)> {
+    let key = &*path[path.len() - 1];
+    let table = &path[..path.len() - 1];
+    lines.iter().filter_map(|line| match line.kind {
+        LineKind::Header if line.table.starts_with(path) => line.code.find(&**key).map(|start| (line, start)),
+        LineKind::Entry { key: entry_key, key_start, .. } if line.table == table && entry_key == key => {
+            Some((line, key_start))
+        }
+        LineKind::Entry { key: entry_key, value_start, .. }
+            if path.len() > 1 && line.table == &table[..table.len() - 1] && entry_key == table[table.len() - 1] => {
+            inline_keys(&line.code[value_start..]).into_iter()
+                .find(|&(_, inline_key)| inline_key == key)
+                .map(|(offset, _)| (line, value_start + offset))
+        }
+        _ => None,
+    }).next()
+}
+
+// The keys of an inline table `value`, with their byte offsets, e.g. `(2,
+// "version")` for `{ version = "1.0" }`.
+fn inline_keys(value: &str) -> Vec<(usize, &str)> {
+    let mut result = vec![];
+    if !value.starts_with('{') {
+        return result;
+    }
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut key_start = Some(1);
+    for (i, c) in value.char_indices() {
+        if in_string {
+            in_string = c != '"';
+            continue;
+        }
+        match c {
+            '"' if key_start.is_none() => in_string = true,
+            '[' | '{' if i > 0 => depth += 1,
+            ']' | '}' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => key_start = Some(i + 1),
+            '=' if depth == 0 => {
+                if let Some(start) = key_start.take() {
+                    let key = value[start..i].trim().trim_matches('"');
+                    if !key.is_empty() {
+                        let offset = start + value[start..].find(key).unwrap_or(0);
+                        result.push((offset, key));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// What is being completed in a manifest.
+#[derive(Debug, PartialEq)]
+pub enum ManifestContext {
+    /// The name of a dependency, starting with the given prefix.
+    DependencyName(String),
+    /// A version of dependency `name`.
+    Version { name: String, prefix: String },
+    /// A feature of dependency `name`.
+    DependencyFeature { name: String, prefix: String },
+    /// A feature of the package or an optional dependency, in `[features]`.
+    Feature(String),
+}
+
+/// What is being completed at char column `col` of line `row` of the manifest
+/// `text`.
+pub fn manifest_context(text: &str, row: usize, col: usize) -> Option<ManifestContext> {
+    let table = manifest_lines(text).into_iter()
+        .take_while(|line| line.row < row)
+        .last()
+        .map_or(Table::Other, |line| table_kind(&line.table));
+    let before: String = text.lines().nth(row).unwrap_or("").chars().take(col).collect();
+    let in_string = before.matches('"').count() % 2 == 1;
+    let prefix = before.rsplit('"').next().unwrap_or("").to_owned();
+
+    if table == Table::Features {
+        if !in_string {
+            return None;
+        }
+        return Some(match prefix.find('/') {
+            Some(slash) => ManifestContext::DependencyFeature {
+                name: prefix[..slash].to_owned(),
+                prefix: prefix[slash + 1..].to_owned(),
+            },
+            None => ManifestContext::Feature(prefix),
+        });
+    }
+
+    let eq = match before.find('=') {
+        Some(eq) => eq,
+        None => {
+            let key = before.trim();
+            let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+            if table == Table::Dependencies && !key.is_empty() && key.chars().all(is_name_char) {
+                return Some(ManifestContext::DependencyName(key.to_owned()));
+            }
+            return None;
+        }
+    };
+    if !in_string {
+        return None;
+    }
+    let key = before[..eq].trim().trim_matches('"').to_owned();
+    // The key of the value being typed, e.g. `features` in `serde = {
+    // features = ["de`.
+    let quote = before.rfind('"').unwrap_or(0);
+    let value_key = match before[..quote].rfind('=') {
+        Some(value_eq) if value_eq > eq => {
+            let lhs = before[..value_eq].trim_right();
+            let start = lhs.rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-')).map_or(0, |i| i + 1);
+            lhs[start..].to_owned()
+        }
+        _ => key.clone(),
+    };
+    let (name, value_key) = match table {
+        Table::Dependencies if value_key == key => (key, "version".to_owned()),
+        Table::Dependencies => (key, value_key),
+        Table::Dependency(name) => (name, value_key),
+        _ => return None,
+    };
+    match &*value_key {
+        "version" => Some(ManifestContext::Version { name, prefix }),
+        "features" => Some(ManifestContext::DependencyFeature { name, prefix }),
+        _ => None,
+    }
+}
+
+/// The completions at char column `col` of line `row` of the manifest `text`,
+/// best first, from the registry `index` if it was downloaded.
+pub fn manifest_completions(text: &str, row: usize, col: usize, index: Option<&RegistryIndex>) -> Vec<CompletionItem> {
+    let context = match manifest_context(text, row, col) {
+        Some(context) => context,
+        None => return vec![],
+    };
+    let item = |label: String, detail: &str, kind| {
+        let mut item = CompletionItem::new_simple(label, detail.to_owned());
+        item.kind = Some(kind);
+        item
+    };
+    match context {
+        ManifestContext::DependencyName(prefix) => index.map_or(vec![], |index| index.package_names(&prefix))
+            .into_iter()
+            .map(|name| item(name, "crate", CompletionItemKind::Module))
+            .collect(),
+        ManifestContext::Version { name, prefix } => {
+            // The whole version is replaced, clients would only replace what
+            // follows its last dot.
+            let range = string_range(text, row, col, &prefix);
+            index.map_or(vec![], |index| index.versions(&name))
+                .into_iter()
+                .rev()
+                .filter(|v| !v.yanked && v.version.starts_with(&prefix))
+                .map(|v| {
+                    let mut item = item(v.version.clone(), &name, CompletionItemKind::Value);
+                    item.text_edit = Some(TextEdit {
+                        range,
+                        new_text: v.version,
+                    });
+                    item
+                })
+                .collect()
+        }
+        ManifestContext::DependencyFeature { name, prefix } => {
+            let latest = index.and_then(|index| index.versions(&name).into_iter().rev().find(|v| !v.yanked));
+            let mut features = latest.map_or(vec![], |v| v.feature_names());
+            features.sort();
+            features.into_iter()
+                .filter(|f| f.starts_with(&prefix))
+                .map(|f| item(f, &name, CompletionItemKind::Property))
+                .collect()
+        }
+        ManifestContext::Feature(prefix) => {
+            // The line being typed is left out, it's often not valid TOML yet.
+            let others: Vec<_> = text.lines().enumerate().filter(|&(i, _)| i != row).map(|(_, line)| line).collect();
+            let manifest = parse_manifest(&others.join("\n"));
+            let mut features: Vec<_> = manifest.feature_names().into_iter().map(|f| f.to_owned()).collect();
+            // Features of dependencies are `name/feature`.
+            features.extend(manifest.dependencies.iter().map(|d| format!("{}/", d.name)));
+            features.into_iter()
+                .filter(|f| f.starts_with(&prefix))
+                .map(|f| item(f, "feature", CompletionItemKind::Property))
+                .collect()
+        }
+    }
+}
+
+// The char range of the string at char column `col` of line `row` of `text`,
+// whose part before the column is `prefix`.
+fn string_range(text: &str, row: usize, col: usize, prefix: &str) -> Range {
+    let line = text.lines().nth(row).unwrap_or("");
+    let rest = line.chars().skip(col).take_while(|&c| c != '"').count();
+    let start = col - prefix.chars().count();
+    Range::new(Position::new(row as u64, start as u64), Position::new(row as u64, (col + rest) as u64))
+}
+
+/// A version of a package in the registry index.
+#[derive(Debug, Deserialize)]
+pub struct IndexVersion {
+    #[serde(rename = "vers")]
+    pub version: String,
+    #[serde(default)]
+    pub deps: Vec<IndexDependency>,
+    #[serde(default)]
+    pub features: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// A dependency of a version in the registry index.
+#[derive(Debug, Deserialize)]
+pub struct IndexDependency {
+    pub name: String,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl IndexVersion {
+    /// The features of this version, including its optional dependencies.
+    pub fn feature_names(&self) -> Vec<String> {
+        let optional = self.deps.iter().filter(|d| d.optional).map(|d| d.name.clone());
+        self.features.keys().cloned().chain(optional).collect()
+    }
+}
+
+/// The copy of the crates.io index Cargo keeps in its home directory, so
+/// completing dependencies doesn't need the network. It only has the packages
+/// as of the last time Cargo updated it.
+///
+/// Cargo doesn't check out the index, it's a git repository of which only the
+/// objects are there, so it's read through them, at the commit Cargo fetched.
+pub struct RegistryIndex {
+    repo: git2::Repository,
+    tree: git2::Oid,
+}
+
+impl RegistryIndex {
+    /// The most package names completed at once.
+    const MAX_NAMES: usize = 100;
+
+    /// The index of the Cargo home used for the package in `dir`, if Cargo
+    /// downloaded it.
+    pub fn find(dir: &Path) -> Option<RegistryIndex> {
+        let indices = homedir(dir)?.join("registry").join("index");
+        // Each registry has a directory, crates.io's is e.g.
+        // `github.com-1ecc6299db9ec823`.
+        let root = fs::read_dir(indices).ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| path.file_name().map_or(false, |name| name.to_string_lossy().starts_with("github.com-")))?;
+        RegistryIndex::open(&root)
+    }
+
+    // The index in the git repository at `root`, as of the commit Cargo
+    // fetched last.
+    fn open(root: &Path) -> Option<RegistryIndex> {
+        let repo = git2::Repository::open(root).ok()?;
+        let tree = {
+            let commit = repo.refname_to_id("refs/remotes/origin/master").ok()?;
+            repo.find_commit(commit).ok()?.tree_id()
+        };
+        Some(RegistryIndex { repo, tree })
+    }
+
+    /// The versions of package `name`, oldest first.
+    pub fn versions(&self, name: &str) -> Vec<IndexVersion> {
+        let text = match index_path(name).and_then(|path| self.read(&path)) {
+            Some(text) => text,
+            None => return vec![],
+        };
+        text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect()
+    }
+
+    /// The names of the packages starting with `prefix`, in order, at most
+    /// `MAX_NAMES`.
+    pub fn package_names(&self, prefix: &str) -> Vec<String> {
+        let prefix = prefix.to_lowercase();
+        if prefix.is_empty() || !is_ascii(&prefix) {
+            return vec![];
+        }
+        // Names of up to three chars are in `1`, `2` and `3/a`, longer ones in
+        // directories named after their first four chars, e.g. `se/rd`.
+        let overlaps = |dir: &str, part: &str| dir.starts_with(part) || part.starts_with(dir);
+        let mut dirs = vec![PathBuf::from("1"), PathBuf::from("2"), Path::new("3").join(&prefix[..1])];
+        for first in self.entries(Path::new(""), true) {
+            if first.chars().count() == 2 && overlaps(&first, &prefix) {
+                for second in self.entries(Path::new(&first), true) {
+                    if overlaps(&format!("{}{}", first, second), &prefix) {
+                        dirs.push(Path::new(&first).join(second));
+                    }
+                }
+            }
+        }
+
+        let mut names: Vec<String> = dirs.iter()
+            .flat_map(|dir| self.entries(dir, false))
+            .filter(|name| name.starts_with(&prefix))
+            .collect();
+        names.sort();
+        names.truncate(Self::MAX_NAMES);
+        names
+    }
+
+    // The text of the file at `path`.
+    fn read(&self, path: &Path) -> Option<String> {
+        let entry = self.repo.find_tree(self.tree).ok()?.get_path(path).ok()?;
+        let blob = self.repo.find_blob(entry.id()).ok()?;
+        String::from_utf8(blob.content().to_vec()).ok()
+    }
+
+    // The names of the directories (if `dirs`, otherwise of the files) in
+    // `dir`, the root if it's empty.
+    fn entries(&self, dir: &Path, dirs: bool) -> Vec<String> {
+        let tree = match self.repo.find_tree(self.tree) {
+            Ok(root) => if dir.as_os_str().is_empty() {
+                root
+            } else {
+                match root.get_path(dir).ok().and_then(|entry| self.repo.find_tree(entry.id()).ok()) {
+                    Some(tree) => tree,
+                    None => return vec![],
+                }
+            },
+            Err(_) => return vec![],
+        };
+        let kind = if dirs { git2::ObjectType::Tree } else { git2::ObjectType::Blob };
+        tree.iter()
+            .filter(|entry| entry.kind() == Some(kind))
+            .filter_map(|entry| entry.name().map(|name| name.to_owned()))
+            .filter(|name| !name.starts_with('.'))
+            .collect()
+    }
+}
+
+// The path of the file of package `name` in the index, `None` if it can't be
+// the name of a package.
+fn index_path(name: &str) -> Option<PathBuf> {
+    let name = name.to_lowercase();
+    if !is_ascii(&name) {
+        return None;
+    }
+    Some(match name.len() {
+        0 => return None,
+        1 => Path::new("1").join(&name),
+        2 => Path::new("2").join(&name),
+        3 => Path::new("3").join(&name[..1]).join(&name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(&name),
+    })
+}
+
+fn is_ascii(s: &str) -> bool {
+    s.bytes().all(|b| b < 0x80)
+}
+
+// The kind of table entries are in.
+#[derive(Debug, PartialEq)]
+enum Table {
+    Features,
+    // `[dependencies]`, `[dev-dependencies]`, `[target.'cfg(unix)'.dependencies]` etc.
+    Dependencies,
+    // `[dependencies.name]`.
+    Dependency(String),
+    Other,
+}
+
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+fn table_kind(path: &[String]) -> Table {
+    let is_dependencies = |path: &[String]| {
+        let platform = path.len() == 3 && path[0] == "target";
+        (path.len() == 1 || platform) && DEPENDENCY_TABLES.contains(&&*path[path.len() - 1])
+    };
+    if path == ["features"] {
+        Table::Features
+    } else if !path.is_empty() && is_dependencies(path) {
+        Table::Dependencies
+    } else if path.len() > 1 && is_dependencies(&path[..path.len() - 1]) {
+        Table::Dependency(path[path.len() - 1].clone())
+    } else {
+        Table::Other
+    }
+}
+
+// The dependency tables of the manifest `value`, with their paths, e.g.
+// `["target", "cfg(unix)", "dependencies"]`.
+fn dependency_tables(value: &Value) -> Vec<(Vec<String>, &TomlTable)> {
+    let mut result = vec![];
+    for kind in DEPENDENCY_TABLES {
+        if let Some(table) = value.get(*kind).and_then(Value::as_table) {
+            result.push((vec![(*kind).to_owned()], table));
+        }
+    }
+    for (target, platform) in value.get("target").and_then(Value::as_table).into_iter().flat_map(|t| t) {
+        for kind in DEPENDENCY_TABLES {
+            if let Some(table) = platform.get(*kind).and_then(Value::as_table) {
+                result.push((vec!["target".to_owned(), target.clone(), (*kind).to_owned()], table));
+            }
+        }
+    }
+    result
+}
+
+// A line of a manifest which isn't empty.
+struct Line<'a> {
+    row: usize,
+    // The line without its comment.
+    code: &'a str,
+    // The path of the table the line is in, or starts.
+    table: Vec<String>,
+    kind: LineKind<'a>,
+}
+
+enum LineKind<'a> {
+    Header,
+    // The byte indices are in `code`.
+    Entry { key: &'a str, key_start: usize, value_start: usize },
+    // The rest of a value spanning several lines.
+    Continuation,
+}
+
+fn manifest_lines(text: &str) -> Vec<Line> {
+    let mut table = vec![];
+    let mut result = vec![];
+    for (row, line) in text.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        let trimmed = code.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let kind = if trimmed.starts_with('[') {
+            table = key_path(trimmed.trim_matches(|c| c == '[' || c == ']'));
+            LineKind::Header
+        } else {
+            entry(code)
+        };
+        result.push(Line { row, code, table: table.clone(), kind });
+    }
+    result
+}
+
+// The keys of a table header, e.g. `["target", "cfg(unix)", "dependencies"]`
+// for `target.'cfg(unix)'.dependencies`.
+fn key_path(header: &str) -> Vec<String> {
+    let mut path = vec![];
+    let mut key = String::new();
+    let mut quote = None;
+    for c in header.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => key.push(c),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '.' => path.push(mem::replace(&mut key, String::new()).trim().to_owned()),
+            None => key.push(c),
+        }
+    }
+    path.push(key.trim().to_owned());
+    path
+}
+
+// An entry line, or the rest of a value spanning several lines.
+fn entry(code: &str) -> LineKind {
+    let eq = match code.find('=') {
+        Some(eq) => eq,
+        None => return LineKind::Continuation,
+    };
+    let key = code[..eq].trim().trim_matches(|c| c == '"' || c == '\'');
+    let is_key_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    if key.is_empty() || !key.chars().all(is_key_char) {
+        return LineKind::Continuation;
+    }
+    let after_eq = &code[eq + 1..];
+    LineKind::Entry {
+        key,
+        key_start: code.find(key).unwrap_or(0),
+        value_start: code.len() - after_eq.trim_left().len(),
+    }
+}
+
+// The line of the entry (or table header) of dependency `name` in the
+// dependency `table` and the last line of its entry (or table).
+fn dependency_rows(lines: &[Line], table: &[String], name: &str) -> Option<(usize, usize)> {
+    let mut path = table.to_vec();
+    path.push(name.to_owned());
+    let start = lines.iter().position(|line| match line.kind {
+        LineKind::Header => line.table == path,
+        LineKind::Entry { key, .. } => line.table == table && key == name,
+        LineKind::Continuation => false,
+    })?;
+    let is_table = match lines[start].kind {
+        LineKind::Header => true,
+        _ => false,
+    };
+    let mut last_row = lines[start].row;
+    for line in &lines[start + 1..] {
+        // The lines of a table up to the next header, or those continuing the
+        // value of an entry.
+        let belongs = match line.kind {
+            LineKind::Header => false,
+            _ if is_table => true,
+            LineKind::Continuation => line.row == last_row + 1,
+            LineKind::Entry { .. } => false,
+        };
+        if !belongs {
+            break;
+        }
+        last_row = line.row;
+    }
+    Some((lines[start].row, last_row))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_parse_manifest() {
+        let text = r#"
+[package]
+name = "foo"
+
+[dependencies]
+serde = "1.0"
+racer = { version = "2.0", optional = true } # completions
+
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+
+[dev-dependencies.tempdir]
+version = "0.3"
+optional = true
+
+[features]
+default = ["racer",
+           "std"]
+std = []
+"#;
+        let manifest = parse_manifest(text);
+        assert_eq!(manifest.name, Some("foo".to_owned()));
+        assert_eq!(manifest.features, ["default", "std"]);
+        let dependencies: Vec<_> = manifest.dependencies.iter().map(|d| (&*d.name, d.optional)).collect();
+        assert_eq!(dependencies, [("serde", false), ("racer", true), ("libc", false), ("tempdir", true)]);
+        assert_eq!(manifest.feature_names(), ["default", "std", "racer", "tempdir"]);
+        let rows: Vec<_> = manifest.dependencies.iter().map(|d| (d.normal, d.row, d.last_row)).collect();
+        assert_eq!(rows, [(true, 5, 5), (true, 6, 6), (false, 9, 9), (false, 11, 13)]);
+    }
+
+    #[test]
+    fn test_enabled_features() {
+        let text = r#"[dependencies]
+serde = { version = "1.0", optional = true }
+
+[features]
+default = ["std",
+           "serde/derive"]
+std = ["alloc"]
+alloc = []
+nightly = []
+"#;
+        let enabled = |requested: &[&str], all, no_default| {
+            let requested: Vec<_> = requested.iter().map(|f| f.to_string()).collect();
+            let mut features: Vec<_> = enabled_features(text, &requested, all, no_default).into_iter().collect();
+            features.sort();
+            features
+        };
+        assert_eq!(enabled(&[], false, false), ["alloc", "default", "serde", "std"]);
+        assert_eq!(enabled(&["nightly"], false, true), ["nightly"]);
+        assert_eq!(enabled(&["unknown"], false, true), Vec::<String>::new());
+        assert_eq!(enabled(&[], true, false), ["alloc", "default", "nightly", "serde", "std"]);
+    }
+
+    #[test]
+    fn test_unused_dependencies() {
+        let text = r#"[package]
+name = "foo"
+
+[dependencies]
+serde = "1.0"
+rls-data = { version = "0.12", features = [
+    "serialize-serde",
+] }
+racer = { version = "2.0", optional = true }
+
+[dependencies.log]
+version = "0.4"
+
+[dev-dependencies]
+tempdir = "0.3"
+
+[dependencies.dep]
+version = "1"
+"#;
+        let used = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<HashSet<_>>();
+        let lib_names = LibNames::new();
+        let unused: Vec<_> = unused_dependencies(text, &used(&["serde"]), &lib_names).into_iter().map(|d| {
+            (d.name, d.row, d.col_start, d.col_end, d.removal.start.line, d.removal.end.line)
+        }).collect();
+        assert_eq!(unused, [("rls-data".to_owned(), 5, 0, 8, 5, 8),
+                            ("log".to_owned(), 10, 14, 17, 10, 12),
+                            ("dep".to_owned(), 16, 14, 17, 16, 18)]);
+        assert_eq!(unused_dependencies(text, &used(&["serde", "rls_data", "log", "dep"]), &lib_names), []);
+
+        // The library of a package may have another name.
+        lib_names.record(vec![("log".to_owned(), "logging".to_owned())].into_iter().collect());
+        let unused: Vec<_> = unused_dependencies(text, &used(&["serde", "rls_data", "log", "dep"]), &lib_names)
+            .into_iter().map(|d| d.name).collect();
+        assert_eq!(unused, ["log"]);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let text = r#"[package]
+name = "foo"
+autor = "me"
+
+[dependencies]
+serde = { version = "1.0", featurs = ["derive"] }
+
+[profile.release]
+opt-level = 3
+
+[dependencies.rand]
+verison = "0.4"
+
+[packages.metadata]
+foo = 1
+"#;
+        let keys: Vec<_> = unknown_keys(text).into_iter().map(|k| (k.name, k.row, k.col_start, k.col_end)).collect();
+        assert_eq!(keys, [("package.autor".to_owned(), 2, 0, 5),
+                          ("dependencies.serde.featurs".to_owned(), 5, 27, 34),
+                          ("dependencies.rand.verison".to_owned(), 11, 0, 7),
+                          ("packages".to_owned(), 13, 1, 9)]);
+    }
+
+    #[test]
+    fn test_manifest_context() {
+        let text = "[dependencies]\nser\nserde = \"1.\"\nrand = { version = \"0.4\", features = [\"st\"] }\n\n[dependencies.log]\nversion = \"0.\"\n\n[features]\ndefault = [\"serde/de\", \"st\"]\n";
+        let context = |row, col| manifest_context(text, row, col);
+        let owned = |s: &str| s.to_owned();
+        assert_eq!(context(1, 3), Some(ManifestContext::DependencyName(owned("ser"))));
+        assert_eq!(context(2, 11), Some(ManifestContext::Version { name: owned("serde"), prefix: owned("1.") }));
+        assert_eq!(context(3, 23), Some(ManifestContext::Version { name: owned("rand"), prefix: owned("0.4") }));
+        assert_eq!(context(3, 41),
+                   Some(ManifestContext::DependencyFeature { name: owned("rand"), prefix: owned("st") }));
+        assert_eq!(context(6, 13), Some(ManifestContext::Version { name: owned("log"), prefix: owned("0.") }));
+        assert_eq!(context(9, 20),
+                   Some(ManifestContext::DependencyFeature { name: owned("serde"), prefix: owned("de") }));
+        assert_eq!(context(9, 26), Some(ManifestContext::Feature(owned("st"))));
+        assert_eq!(context(2, 7), None);
+        assert_eq!(context(8, 5), None);
+    }
+
+    #[test]
+    fn test_key_path() {
+        assert_eq!(key_path("dependencies"), ["dependencies"]);
+        assert_eq!(key_path("target.'cfg(unix)'.dependencies"), ["target", "cfg(unix)", "dependencies"]);
+        assert_eq!(key_path(r#"dependencies . "a.b""#), ["dependencies", "a.b"]);
+    }
+
+    #[test]
+    fn test_string_range() {
+        // Completing after `1.1` replaces all of `1.1.0`.
+        let text = "[dependencies]\nserde = \"1.1.0\"\n";
+        let range = string_range(text, 1, 12, "1.1");
+        assert_eq!(range, Range::new(Position::new(1, 9), Position::new(1, 14)));
+    }
+
+    #[test]
+    fn test_index_path() {
+        assert_eq!(index_path("a"), Some(PathBuf::from("1/a")));
+        assert_eq!(index_path("cc"), Some(PathBuf::from("2/cc")));
+        assert_eq!(index_path("Log"), Some(PathBuf::from("3/l/log")));
+        assert_eq!(index_path("serde"), Some(PathBuf::from("se/rd/serde")));
+        assert_eq!(index_path("säge"), None);
+    }
+
+    fn write_tree(repo: &git2::Repository, entries: &[(&str, git2::Oid, i32)]) -> git2::Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for &(name, oid, mode) in entries {
+            builder.insert(name, oid, mode).unwrap();
+        }
+        builder.write().unwrap()
+    }
+
+    #[test]
+    fn test_registry_index() {
+        // Laid out like Cargo keeps the index: the objects of the commit it
+        // fetched, nothing checked out.
+        let dir = env::temp_dir().join("rls-test-registry-index");
+        let _ = fs::remove_dir_all(&dir);
+        let repo = git2::Repository::init(&dir).unwrap();
+        let (file, subtree) = (0o100644, 0o040000);
+        let blob = |text: &str| repo.blob(text.as_bytes()).unwrap();
+        let serde = blob("{\"name\":\"serde\",\"vers\":\"0.9.0\"}\n{\"name\":\"serde\",\"vers\":\"1.0.0\",\"features\":{\"std\":[]}}\n");
+        let rd = write_tree(&repo, &[("serde", serde, file), ("serde_json", blob("{\"vers\":\"1.0.0\"}"), file)]);
+        let mv = write_tree(&repo, &[("semver", blob("{\"vers\":\"0.9.0\"}"), file)]);
+        let se = write_tree(&repo, &[("rd", rd, subtree), ("mv", mv, subtree)]);
+        let l = write_tree(&repo, &[("log", blob("{\"vers\":\"0.4.0\"}"), file)]);
+        let three = write_tree(&repo, &[("l", l, subtree)]);
+        let one = write_tree(&repo, &[("a", blob("{\"vers\":\"0.1.0\"}"), file)]);
+        let root = write_tree(&repo, &[("se", se, subtree), ("3", three, subtree), ("1", one, subtree),
+                                       ("config.json", blob("{}"), file)]);
+        let signature = git2::Signature::now("rls", "rls@example.com").unwrap();
+        let commit = repo.commit(None, &signature, &signature, "index", &repo.find_tree(root).unwrap(), &[]).unwrap();
+        repo.reference("refs/remotes/origin/master", commit, true, "fetch").unwrap();
+
+        let index = RegistryIndex::open(&dir).unwrap();
+        let versions: Vec<_> = index.versions("Serde").into_iter().map(|v| v.version).collect();
+        assert_eq!(versions, ["0.9.0", "1.0.0"]);
+        assert_eq!(index.versions("serde")[1].feature_names(), ["std"]);
+        assert!(index.versions("rand").is_empty());
+        assert_eq!(index.package_names("se"), ["semver", "serde", "serde_json"]);
+        assert_eq!(index.package_names("serd"), ["serde", "serde_json"]);
+        assert_eq!(index.package_names("lo"), ["log"]);
+        assert_eq!(index.package_names("a"), ["a"]);
+        assert!(index.package_names("x").is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_index_version() {
+        let line = r#"{"name":"foo","vers":"0.1.0","deps":[{"name":"bar","req":"^1","features":[],"optional":true,"default_features":true,"target":null,"kind":"normal"}],"cksum":"00","features":{"std":[]},"yanked":false}"#;
+        let version: IndexVersion = serde_json::from_str(line).unwrap();
+        assert_eq!(version.version, "0.1.0");
+        let mut features = version.feature_names();
+        features.sort();
+        assert_eq!(features, ["bar", "std"]);
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Keeping the memory used by the analysis data within the `memory_budget`.
+//!
+//! We can't tell how much memory the data of a single crate takes up, so we
+//! use the size of its save-analysis file as an estimate. If the data of the
+//! loaded crates takes up more than the budget allows, the data of the crates
+//! which were used least recently (by requests, see `CrateUses`, or by being
+//! built) is dropped. Dropped crates are reconsidered after every build and
+//! loaded again from disk once there is room for them.
+//!
+//! The resident set size of the RLS isn't used for this, it doesn't shrink
+//! once memory is freed.
+
+use build::rls_target_dir;
+use config::Config;
+use actions::visibility::CrateFiles;
+
+use std::collections::HashMap;
+use std::fs::{read_dir, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The resident set size of the RLS in bytes, if we know how to find out.
+pub fn memory_usage() -> Option<u64> {
+    let mut status = String::new();
+    File::open("/proc/self/status").ok()?.read_to_string(&mut status).ok()?;
+    parse_vm_rss(&status)
+}
+
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line["VmRSS:".len()..].trim().trim_right_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// The directory the RLS' builds of the project write save-analysis data to,
+/// in the configured target directory and for the configured target.
+pub fn analysis_dir(project_path: &Path, config: &Config) -> PathBuf {
+    let target_dir = rls_target_dir(project_path, config);
+    let target_dir = match config.target_triple {
+        Some(ref triple) => target_dir.join(triple),
+        None => target_dir,
+    };
+    target_dir.join("debug").join("deps").join("save-analysis")
+}
+
+/// Save-analysis data of a single crate on disk.
+pub struct AnalysisFile {
+    /// Path of the file.
+    pub path: PathBuf,
+    /// Name of the crate.
+    pub crate_name: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// When the crate was last built.
+    pub modified: SystemTime,
+}
+
+/// The save-analysis files in the given directory.
+pub fn analysis_files(dir: &Path) -> Vec<AnalysisFile> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries.filter_map(|e| e.ok()).filter_map(|entry| {
+        let file_name = entry.file_name();
+        let crate_name = crate_name_from_file_name(file_name.to_str()?)?;
+        let metadata = entry.metadata().ok()?;
+        Some(AnalysisFile {
+            path: entry.path(),
+            crate_name,
+            size: metadata.len(),
+            modified: metadata.modified().ok()?,
+        })
+    }).collect()
+}
+
+// `libfoo_bar-0123abcd.json` -> `foo_bar`.
+fn crate_name_from_file_name(file_name: &str) -> Option<String> {
+    if !file_name.ends_with(".json") {
+        return None;
+    }
+    let stem = &file_name[..file_name.len() - ".json".len()];
+    let name = match stem.rfind('-') {
+        Some(i) => &stem[..i],
+        None => stem,
+    };
+    let name = if name.starts_with("lib") { &name["lib".len()..] } else { name };
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+/// When the analysis data of each crate was last used by a request.
+pub struct CrateUses {
+    crate_files: Arc<CrateFiles>,
+    last_used: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl CrateUses {
+    /// Construct without any use, finding the crates of files in `crate_files`.
+    pub fn new(crate_files: Arc<CrateFiles>) -> CrateUses {
+        CrateUses {
+            crate_files,
+            last_used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record that a request used the data of the crates `file` is part of.
+    pub fn file_used(&self, file: &Path) {
+        let names = self.crate_files.crate_names_of(file);
+        if names.is_empty() {
+            return;
+        }
+        let now = SystemTime::now();
+        let mut last_used = self.last_used.lock().unwrap();
+        for name in names {
+            last_used.insert(name, now);
+        }
+    }
+
+    /// When a request last used the data of the crate, if one did.
+    pub fn last_used(&self, crate_name: &str) -> Option<SystemTime> {
+        self.last_used.lock().unwrap().get(crate_name).cloned()
+    }
+}
+
+/// The size of the analysis data of the crates in `files` which aren't in
+/// `evicted`.
+pub fn loaded_size(files: &[AnalysisFile], evicted: &[String]) -> u64 {
+    files.iter().filter(|f| !evicted.contains(&f.crate_name)).map(|f| f.size).sum()
+}
+
+/// Picks the crates to drop so that the data of the others fits in `budget`
+/// bytes, keeping the most recently used ones: those used by a request last
+/// (see `last_used`) or built last. The result is sorted.
+pub fn crates_to_evict<F>(files: Vec<AnalysisFile>, budget: u64, last_used: F) -> Vec<String>
+    where F: Fn(&str) -> Option<SystemTime>
+{
+    // A crate may have several files, e.g. for its lib and test targets.
+    let mut crates: HashMap<String, (u64, SystemTime)> = HashMap::new();
+    for file in files {
+        let entry = crates.entry(file.crate_name).or_insert((0, file.modified));
+        entry.0 += file.size;
+        if file.modified > entry.1 {
+            entry.1 = file.modified;
+        }
+    }
+    let mut crates: Vec<(String, u64, SystemTime)> = crates.into_iter().map(|(name, (size, modified))| {
+        let used = match last_used(&name) {
+            Some(used) if used > modified => used,
+            _ => modified,
+        };
+        (name, size, used)
+    }).collect();
+    crates.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+
+    let mut kept = 0;
+    let mut result = vec![];
+    for (name, size, _) in crates {
+        if result.is_empty() && kept + size <= budget {
+            kept += size;
+        } else {
+            result.push(name);
+        }
+    }
+    result.sort();
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_parse_vm_rss() {
+        assert_eq!(parse_vm_rss("Name:\trls\nVmRSS:\t  12345 kB\nThreads:\t4\n"), Some(12345 * 1024));
+        assert_eq!(parse_vm_rss("Name:\trls\n"), None);
+    }
+
+    #[test]
+    fn test_crate_name_from_file_name() {
+        assert_eq!(crate_name_from_file_name("libfoo_bar-0123abcd.json"), Some("foo_bar".to_owned()));
+        assert_eq!(crate_name_from_file_name("main-0123abcd.json"), Some("main".to_owned()));
+        assert_eq!(crate_name_from_file_name("libfoo-0123abcd.rlib"), None);
+    }
+
+    #[test]
+    fn test_crates_to_evict() {
+        let file = |name: &str, size, secs| AnalysisFile {
+            path: PathBuf::from(format!("lib{}.json", name)),
+            crate_name: name.to_owned(),
+            size,
+            modified: UNIX_EPOCH + Duration::from_secs(secs),
+        };
+        let files = || vec![file("primary", 100, 30), file("old", 50, 10), file("older", 20, 5)];
+        let unused = |_: &str| None;
+
+        assert!(crates_to_evict(files(), 170, &unused).is_empty());
+        assert_eq!(crates_to_evict(files(), 150, &unused), vec!["older".to_owned()]);
+        assert_eq!(crates_to_evict(files(), 149, &unused), vec!["old".to_owned(), "older".to_owned()]);
+
+        // A crate a request used recently is kept, even if it was built long ago.
+        let used = |name: &str| if name == "older" { Some(UNIX_EPOCH + Duration::from_secs(40)) } else { None };
+        assert_eq!(crates_to_evict(files(), 149, &used), vec!["old".to_owned()]);
+        assert_eq!(loaded_size(&files(), &["old".to_owned()]), 120);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! How often each method was handled and how long that took, so users can
+//! report performance problems with numbers. Returned by the `rls/metrics`
+//! request, and written to the `metrics_file` when the RLS shuts down.
+
+use actions::events::millis;
+use lsp_data::{LatencyBucket, MethodMetrics, ServerMetrics};
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency buckets, in milliseconds. Slower messages go in
+/// a last bucket without bound.
+const BUCKET_BOUNDS_MS: &[u64] = &[1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// The metrics of every method handled since the server started.
+pub struct Metrics {
+    methods: Mutex<HashMap<&'static str, Counts>>,
+    started: Instant,
+}
+
+#[derive(Default)]
+struct Counts {
+    count: u64,
+    errors: u64,
+    total_ms: u64,
+    max_ms: u64,
+    // One more than `BUCKET_BOUNDS_MS`.
+    buckets: Vec<u64>,
+}
+
+impl Metrics {
+    /// Construct metrics without any message handled.
+    pub fn new() -> Metrics {
+        Metrics {
+            methods: Mutex::new(HashMap::new()),
+            started: Instant::now(),
+        }
+    }
+
+    /// Record that a message of `method` was handled, taking `duration`.
+    pub fn record(&self, method: &'static str, success: bool, duration: Duration) {
+        let ms = millis(duration);
+        let mut methods = self.methods.lock().unwrap();
+        let counts = methods.entry(method).or_insert_with(|| Counts {
+            buckets: vec![0; BUCKET_BOUNDS_MS.len() + 1],
+            ..Counts::default()
+        });
+        counts.count += 1;
+        if !success {
+            counts.errors += 1;
+        }
+        counts.total_ms += ms;
+        counts.max_ms = ::std::cmp::max(counts.max_ms, ms);
+        let bucket = BUCKET_BOUNDS_MS.iter().position(|&bound| ms <= bound).unwrap_or(BUCKET_BOUNDS_MS.len());
+        counts.buckets[bucket] += 1;
+    }
+
+    /// The metrics so far, ordered by method.
+    pub fn report(&self) -> ServerMetrics {
+        let methods = self.methods.lock().unwrap();
+        let mut names: Vec<&&str> = methods.keys().collect();
+        names.sort();
+        ServerMetrics {
+            uptime_ms: millis(self.started.elapsed()),
+            methods: names.into_iter().map(|name| {
+                let counts = &methods[*name];
+                MethodMetrics {
+                    method: name.to_string(),
+                    count: counts.count,
+                    errors: counts.errors,
+                    total_ms: counts.total_ms,
+                    max_ms: counts.max_ms,
+                    latency: counts.buckets.iter().enumerate().map(|(i, &count)| {
+                        LatencyBucket {
+                            max_ms: BUCKET_BOUNDS_MS.get(i).cloned(),
+                            count,
+                        }
+                    }).collect(),
+                }
+            }).collect(),
+        }
+    }
+
+    /// Write the report as JSON to `path`.
+    pub fn write_report(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, &self.report()).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metrics_report() {
+        let metrics = Metrics::new();
+        metrics.record("textDocument/hover", true, Duration::from_millis(3));
+        metrics.record("textDocument/hover", false, Duration::from_millis(70));
+        metrics.record("textDocument/didOpen", true, Duration::from_millis(9000));
+
+        let report = metrics.report();
+        let methods: Vec<&str> = report.methods.iter().map(|m| &*m.method).collect();
+        assert_eq!(methods, ["textDocument/didOpen", "textDocument/hover"]);
+
+        let hover = &report.methods[1];
+        assert_eq!((hover.count, hover.errors, hover.total_ms, hover.max_ms), (2, 1, 73, 70));
+        let counts: Vec<u64> = hover.latency.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [0, 1, 0, 0, 1, 0, 0, 0, 0]);
+
+        let slowest = report.methods[0].latency.last().unwrap();
+        assert_eq!((slowest.max_ms, slowest.count), (None, 1));
+    }
+}
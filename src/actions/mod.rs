@@ -11,47 +11,121 @@
 //! Actions that the RLS can perform: responding to requests, watching files,
 //! etc.
 
-use analysis::AnalysisHost;
-use vfs::Vfs;
-use config::{Config, FmtConfig};
+use analysis::{AnalysisHost, Target};
+use vfs::{self, Vfs};
+use config::Config;
 use serde_json;
 use url::Url;
 use span;
 use Span;
 
-use actions::post_build::{BuildResults, PostBuildHandler};
+use actions::attributes::DeriveMacros;
+use actions::borrows::BorrowFacts;
+use actions::build_progress::BuildProgressReporter;
+use actions::client::PendingRequests;
+use actions::components::offer_missing_components;
+use actions::diagnostics::{providers_for_config, ClippyLints};
+use actions::events::{millis, EventStream};
+use actions::imports::ImportPolicy;
+use actions::ingest::UnsavedAnalysis;
+use actions::post_build::{clear_diagnostics, BuildResults, DiagnosticFilter, PostBuildHandler};
+use actions::line_index::LineIndexCache;
+use actions::memory::{analysis_dir, CrateUses};
+use actions::metrics::Metrics;
+use actions::positions::PositionConverter;
+use actions::racer_lookup::Racer;
+use actions::used_crates::UsedCrates;
+use actions::visibility::CrateFiles;
+use actions::status::StatusTracker;
+use actions::symbol_index::SymbolIndex;
+use actions::workers::SerialThreads;
 use build::*;
 use lsp_data::*;
+use messages::Message;
+use paths::PathMappings;
 use server::Output;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs::read_dir;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 
 
-// TODO: Support non-`file` URI schemes in VFS. We're currently ignoring them because
-// we don't want to crash the RLS in case a client opens a file under different URI scheme
-// like with git:/ or perforce:/ (Probably even http:/? We currently don't support remote schemes).
+// We don't want to crash the RLS in case a client sends a URI we can't make
+// sense of, e.g. a `file` URI which isn't a valid path.
 macro_rules! ignore_non_file_uri {
     ($expr: expr, $uri: expr, $log_name: expr) => {
         $expr.map_err(|_| {
-            trace!("{}: Non-`file` URI scheme, ignoring: {:?}", $log_name, $uri);
+            trace!("{}: Invalid URI, ignoring: {:?}", $log_name, $uri);
             ()
         })
     };
 }
 
+// Paths are mapped and documents which aren't files (e.g. new, unsaved
+// buffers) handled with the `DocumentPaths` of the context `$ctx`. Requests
+// pass their id and output, an invalid URI is answered with an error instead
+// of leaving the client waiting.
 macro_rules! parse_file_path {
-    ($uri: expr, $log_name: expr) => {
-        ignore_non_file_uri!(parse_file_path($uri), $uri, $log_name)
-    }
+    ($ctx: expr, $uri: expr, $log_name: expr) => {
+        ignore_non_file_uri!($ctx.documents.path($uri), $uri, $log_name)
+    };
+    ($ctx: expr, $uri: expr, $log_name: expr, $id: expr, $out: expr) => {
+        $ctx.documents.path($uri).map_err(|err| {
+            trace!("{}: Invalid URI: {:?}", $log_name, $uri);
+            $out.failure(::jsonrpc_core::Id::Num($id as u64), invalid_uri_error($uri, err));
+        })
+    };
 }
 
 mod post_build;
+mod build_progress;
+mod cargo_doc;
+mod components;
+mod visibility;
+mod status;
+mod memory;
+mod metrics;
+mod ingest;
+mod events;
+mod format;
+mod line_diff;
+mod hover;
+mod expression_type;
+mod imports;
+mod module_cycles;
+mod navigation;
+mod gc;
+mod changes;
+mod pages;
+mod workers;
+mod racer_lookup;
+mod usage;
+mod positions;
+mod sources;
+mod symbol_index;
+mod trait_methods;
+mod attributes;
+mod file_paths;
+mod keywords;
+mod lifetimes;
+mod borrows;
+mod manifest;
+mod used_crates;
+mod inactive_code;
+pub mod client;
+pub mod commands;
+pub mod diagnostics;
+pub mod line_index;
 pub mod requests;
 pub mod notifications;
 
+/// How long shutting down waits for the running build, in seconds.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+
 /// Persistent context shared across all requests and notifications.
 pub enum ActionContext {
     /// Context after server initialization.
@@ -64,16 +138,33 @@ impl ActionContext {
     /// Construct a new, uninitialized context.
     pub fn new(analysis: Arc<AnalysisHost>,
                vfs: Arc<Vfs>,
-               config: Arc<Mutex<Config>>) -> ActionContext {
-        ActionContext::Uninit(UninitActionContext::new(analysis, vfs, config))
+               config: Arc<Mutex<Config>>,
+               pending: Arc<PendingRequests>) -> ActionContext {
+        ActionContext::Uninit(UninitActionContext::new(analysis, vfs, config, pending))
     }
 
-    /// Initialize this context. Panics if it has already been initialized.
-    pub fn init<O: Output>(&mut self, current_project: PathBuf, init_options: &InitializationOptions, out: O) {
+    /// Initialize this context for the workspace at `current_project`, the
+    /// paths of the client mapped with `mappings`. Panics if it has already
+    /// been initialized.
+    pub fn init<O: Output>(&mut self,
+                           current_project: PathBuf,
+                           mappings: PathMappings,
+                           init_options: &InitializationOptions,
+                           position_encoding: PositionEncoding,
+                           client: RlsClientCapabilities,
+                           out: O) {
         let ctx = match *self {
             ActionContext::Uninit(ref uninit) => {
-                let ctx = InitActionContext::new(uninit.analysis.clone(), uninit.vfs.clone(), uninit.config.clone(), current_project);
-                ctx.init(init_options, out);
+                let ctx = InitActionContext::new(uninit.analysis.clone(),
+                                                 uninit.vfs.clone(),
+                                                 uninit.config.clone(),
+                                                 uninit.pending.clone(),
+                                                 Arc::new(DocumentPaths::new(mappings)),
+                                                 current_project,
+                                                 init_options.clone(),
+                                                 position_encoding,
+                                                 client);
+                ctx.init(out);
                 ctx
             }
             ActionContext::Init(_) => panic!("ActionContext already initialized"),
@@ -81,6 +172,142 @@ impl ActionContext {
         *self = ActionContext::Init(ctx);
     }
 
+    /// The config, the same before and after initialization.
+    pub fn config(&self) -> Arc<Mutex<Config>> {
+        match *self {
+            ActionContext::Init(ref ctx) => ctx.config.clone(),
+            ActionContext::Uninit(ref ctx) => ctx.config.clone(),
+        }
+    }
+
+    /// Whether the context has been initialized.
+    pub fn is_initialized(&self) -> bool {
+        match *self {
+            ActionContext::Init(_) => true,
+            ActionContext::Uninit(_) => false,
+        }
+    }
+
+    /// Clean up and go back to the uninitialized context, so that it can be
+    /// initialized again, e.g. when the client restarts its session. The files
+    /// the client opened are forgotten, it opens them again. So are the
+    /// analysis data and the client's settings: the config goes back to the
+    /// default one, with the overrides given on the command line.
+    pub fn reset(&mut self) {
+        self.shutdown();
+        let uninit = match *self {
+            ActionContext::Init(ref ctx) => {
+                ctx.vfs.clear();
+                {
+                    let mut config = ctx.config.lock().unwrap();
+                    let overrides = config.overrides.clone();
+                    *config = Config::with_overrides(overrides).unwrap_or_default();
+                }
+                UninitActionContext::new(Arc::new(AnalysisHost::new(Target::Debug)),
+                                         ctx.vfs.clone(),
+                                         ctx.config.clone(),
+                                         ctx.pending.clone())
+            }
+            ActionContext::Uninit(_) => return,
+        };
+        *self = ActionContext::Uninit(uninit);
+    }
+
+    /// Start over without restarting the RLS, e.g. when its state is wedged:
+    /// stop building and forget everything learned about the project (the
+    /// build results, the analysis data, Cargo's view of the workspace and
+    /// every cache, the toolchains' included), infer the config again and
+    /// rebuild. A running build can't be interrupted, it isn't waited for but
+    /// its results are dropped. Unlike `reset`, the files the client opened
+    /// are kept.
+    pub fn restart<O: Output>(&mut self, out: O) {
+        let current_project = match *self {
+            ActionContext::Init(ref ctx) => ctx.current_project.clone(),
+            ActionContext::Uninit(_) => return,
+        };
+        self.restart_at(current_project, out);
+    }
+
+    /// Restart (see `restart`) in the workspace at `current_project`, e.g.
+    /// when the path mappings moved its root.
+    pub fn restart_at<O: Output>(&mut self, current_project: PathBuf, out: O) {
+        let ctx = match *self {
+            ActionContext::Init(ref ctx) => {
+                ctx.build_queue.stop();
+                ctx.restart(current_project, out)
+            }
+            ActionContext::Uninit(_) => return,
+        };
+        *self = ActionContext::Init(ctx);
+    }
+
+    /// Start over (see `restart`) if a panic, in an action or on another
+    /// thread, poisoned a lock of the context: every later use of it would
+    /// panic too. Returns whether it did.
+    pub fn restart_if_poisoned<O: Output>(&mut self, out: O) -> bool {
+        let poisoned = match *self {
+            ActionContext::Init(ref ctx) => ctx.is_poisoned(),
+            ActionContext::Uninit(_) => false,
+        };
+        if poisoned {
+            debug!("a lock of the context is poisoned, restarting");
+            self.restart(out);
+        }
+        poisoned
+    }
+
+    /// Clean up before the RLS exits: finish the running build and the
+    /// lookups of racer, write the analysis data only kept in memory, remove
+    /// the save-analysis data no longer needed and the directory of a
+    /// standalone project, then write the metrics.
+    pub fn shutdown(&self) {
+        if let ActionContext::Init(ref ctx) = *self {
+            let started = Instant::now();
+            let timeout = Duration::from_secs(SHUTDOWN_TIMEOUT_SECS);
+            if !ctx.finish_builds(timeout) {
+                debug!("shutdown - gave up waiting for the build");
+            }
+            if !ctx.racer.shut_down(timeout.checked_sub(started.elapsed()).unwrap_or_default()) {
+                debug!("shutdown - gave up waiting for racer");
+            }
+            let analysis_dir = analysis_dir(&ctx.current_project, &ctx.config.lock().unwrap());
+            let flushed = ctx.unsaved_analysis.flush(&analysis_dir);
+            debug!("shutdown - wrote the analysis data of {} crates", flushed);
+            // Cargo resolved the dependency graph by now (if it built the
+            // project), the files of crates no longer in it can go as well.
+            collect_analysis_garbage(&ctx.current_project, &ctx.config, ctx.build_queue.lib_names().crate_names());
+            if let Err(e) = remove_standalone_project(&ctx.current_project) {
+                debug!("Could not remove the standalone project {:?}: {}", ctx.current_project, e);
+            }
+            let metrics_file = ctx.config.lock().unwrap().metrics_file.clone();
+            if let Some(path) = metrics_file {
+                if let Err(e) = ctx.metrics.write_report(Path::new(&path)) {
+                    debug!("Could not write the metrics to {}: {}", path, e);
+                }
+            }
+        }
+    }
+
+    /// Record that a request was answered, for the `rls/eventStream` and the
+    /// `rls/metrics`.
+    pub fn request_served<O: Output>(&self, out: &O, method: &'static str, success: bool, duration: Duration) {
+        if let ActionContext::Init(ref ctx) = *self {
+            ctx.metrics.record(method, success, duration);
+            ctx.events.emit(out, ServerEvent::RequestServed {
+                method,
+                success,
+                duration_ms: millis(duration),
+            });
+        }
+    }
+
+    /// Record that a notification was handled, for the `rls/metrics`.
+    pub fn notification_handled(&self, method: &'static str, success: bool, duration: Duration) {
+        if let ActionContext::Init(ref ctx) = *self {
+            ctx.metrics.record(method, success, duration);
+        }
+    }
+
     fn inited(&self) -> &InitActionContext {
         match *self {
             ActionContext::Uninit(_) => panic!("ActionContext not initialized"),
@@ -98,10 +325,49 @@ pub struct InitActionContext {
     current_project: PathBuf,
 
     previous_build_results: Arc<Mutex<BuildResults>>,
+    borrow_facts: Arc<BorrowFacts>,
+    clippy_lints: Arc<ClippyLints>,
+    used_crates: Arc<UsedCrates>,
+    crate_files: Arc<CrateFiles>,
+    crate_uses: Arc<CrateUses>,
+    derive_macros: Arc<DeriveMacros>,
     build_queue: BuildQueue,
+    // Id of the next build session, see `BeginBuildParams`.
+    next_build_session: AtomicU64,
+    status: Arc<Mutex<StatusTracker>>,
+    // The threads handling the results of builds, see `finish_builds`.
+    post_build_threads: Arc<SerialThreads>,
+    // The analysis data of the crates built in memory, written to disk on
+    // shutdown.
+    unsaved_analysis: Arc<UnsavedAnalysis>,
+    // Set if the client shows work done progress.
+    build_progress: Option<Arc<BuildProgressReporter>>,
+    events: Arc<EventStream>,
+    metrics: Arc<Metrics>,
+    // Cancellation flags of long running requests, by request id.
+    cancellable: Arc<Mutex<HashMap<usize, Arc<AtomicBool>>>>,
+    line_indices: Arc<LineIndexCache>,
+    // The paths of the documents the client refers to by URI, and the path
+    // mappings.
+    documents: Arc<DocumentPaths>,
+    positions: PositionConverter,
+    // Requests we sent to the client, waiting for a response.
+    pending: Arc<PendingRequests>,
+    symbol_index: Arc<SymbolIndex>,
+    racer: Arc<Racer>,
+    // The capabilities of the client, which responses adapt to.
+    client: RlsClientCapabilities,
+    init_options: InitializationOptions,
+    // See `PostBuildHandler::stale_analysis`.
+    stale_analysis: Arc<AtomicBool>,
+    // How many times the context was restarted, shared with the contexts
+    // restarted from it. See `PostBuildHandler::generation`.
+    generation: Arc<AtomicUsize>,
+    // Whether the client was told that the source of the standard library is
+    // missing, which is only done once. Shared with the restarted contexts.
+    rust_src_missing_shown: Arc<AtomicBool>,
 
     config: Arc<Mutex<Config>>,
-    fmt_config: FmtConfig,
 }
 
 /// Persistent context shared across all requests and actions before the RLS has
@@ -110,16 +376,19 @@ pub struct UninitActionContext {
     analysis: Arc<AnalysisHost>,
     vfs: Arc<Vfs>,
     config: Arc<Mutex<Config>>,
+    pending: Arc<PendingRequests>,
 }
 
 impl UninitActionContext {
     fn new(analysis: Arc<AnalysisHost>,
                vfs: Arc<Vfs>,
-               config: Arc<Mutex<Config>>) -> UninitActionContext {
+               config: Arc<Mutex<Config>>,
+               pending: Arc<PendingRequests>) -> UninitActionContext {
         UninitActionContext {
             analysis,
             vfs,
             config,
+            pending,
         }
     }
 
@@ -129,21 +398,62 @@ impl InitActionContext {
     fn new(analysis: Arc<AnalysisHost>,
                vfs: Arc<Vfs>,
                config: Arc<Mutex<Config>>,
-               current_project: PathBuf) -> InitActionContext {
+               pending: Arc<PendingRequests>,
+               documents: Arc<DocumentPaths>,
+               current_project: PathBuf,
+               init_options: InitializationOptions,
+               position_encoding: PositionEncoding,
+               client: RlsClientCapabilities) -> InitActionContext {
         let build_queue = BuildQueue::new(vfs.clone(), config.clone());
-        let fmt_config = FmtConfig::from(&current_project);
+        let line_indices = Arc::new(LineIndexCache::new());
+        let crate_files = Arc::new(CrateFiles::new());
+        let crate_uses = Arc::new(CrateUses::new(crate_files.clone()));
+        let positions = PositionConverter::new(vfs.clone(),
+                                               line_indices.clone(),
+                                               documents.clone(),
+                                               crate_uses.clone(),
+                                               position_encoding);
         InitActionContext {
             analysis,
+            racer: Arc::new(Racer::new(vfs.clone())),
             vfs,
             config,
             current_project,
             previous_build_results: Arc::new(Mutex::new(HashMap::new())),
+            borrow_facts: Arc::new(BorrowFacts::new()),
+            clippy_lints: Arc::new(ClippyLints::new()),
+            used_crates: Arc::new(UsedCrates::new()),
+            crate_files,
+            crate_uses,
+            derive_macros: Arc::new(DeriveMacros::new()),
             build_queue,
-            fmt_config,
+            next_build_session: AtomicU64::new(1),
+            status: Arc::new(Mutex::new(StatusTracker::new())),
+            post_build_threads: Arc::new(SerialThreads::new()),
+            unsaved_analysis: Arc::new(UnsavedAnalysis::new()),
+            build_progress: if client.work_done_progress() {
+                Some(Arc::new(BuildProgressReporter::new(pending.clone())))
+            } else {
+                None
+            },
+            events: Arc::new(EventStream::new()),
+            metrics: Arc::new(Metrics::new()),
+            cancellable: Arc::new(Mutex::new(HashMap::new())),
+            line_indices,
+            documents,
+            positions,
+            pending,
+            symbol_index: Arc::new(SymbolIndex::new()),
+            client,
+            init_options,
+            stale_analysis: Arc::new(AtomicBool::new(false)),
+            generation: Arc::new(AtomicUsize::new(0)),
+            rust_src_missing_shown: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    fn init<O: Output>(&self, init_options: &InitializationOptions, out: O) {
+    fn init<O: Output>(&self, out: O) {
+        let init_options = &self.init_options;
         let current_project = self.current_project.clone();
         let config = self.config.clone();
         // Spawn another thread since we're shelling out to Cargo and this can
@@ -155,29 +465,155 @@ impl InitActionContext {
             }
         });
 
+        // Removing old save-analysis data can take a while on a slow disk,
+        // and it's not needed for anything.
+        let current_project = self.current_project.clone();
+        let config = self.config.clone();
+        thread::spawn(move || collect_analysis_garbage(&current_project, &config, None));
+
+        // Without some components of the toolchain features silently don't
+        // work, offer to install them. The project of another toolchain is
+        // either built outside of the RLS, without the unsaved changes, or
+        // (if only its `rust-toolchain` file names it) with the RLS's compiler,
+        // so suggest running the RLS of that toolchain. (Working out the
+        // toolchain may ask rustup, the config may be locked while it's
+        // inferred.)
+        let current_project = self.current_project.clone();
+        let config = self.config.clone();
+        let pending = self.pending.clone();
+        let toolchain = self.build_queue.toolchain();
+        let toolchain_out = out.clone();
+        thread::spawn(move || {
+            let project_toolchain = toolchain.get(&config, &current_project);
+            if let (true, Some(name)) = (project_toolchain.foreign, project_toolchain.name.as_ref()) {
+                let text = if project_toolchain.builds_outside() {
+                    Message::ForeignToolchain
+                } else {
+                    Message::ToolchainFileDiffers
+                };
+                let message = format!("{} `rustup run {} rls`", text.text(&config.lock().unwrap()), name);
+                toolchain_out.notify(NotificationMessage::new(
+                    NOTIFICATION_SHOW_MESSAGE,
+                    Some(NotificationParams::ShowMessage(ShowMessageParams {
+                        typ: MessageType::Warning,
+                        message,
+                    })),
+                ));
+            }
+            if let Some(ref sysroot) = project_toolchain.sysroot {
+                offer_missing_components(Path::new(sysroot), &config, &pending, &toolchain_out);
+            }
+        });
+
+        // Racer parses the standard library and the crates of the workspace
+        // the first time it's asked to complete from them, do that now (unless
+        // the config says not to, it may be locked while it's inferred).
+        let current_project = self.current_project.clone();
+        let config = self.config.clone();
+        let racer = self.racer.clone();
+        thread::spawn(move || {
+            {
+                let config = config.lock().unwrap();
+                if !config.racer_warm_up || !config.racer_completion {
+                    return;
+                }
+            }
+            let crate_roots = collect_source_files(&current_project)
+                .into_iter()
+                .filter(|file| file.ends_with("src/lib.rs") || file.ends_with("src/main.rs"))
+                .collect();
+            racer_lookup::warm_up(&racer, crate_roots);
+        });
+
+        if init_options.status_notifications {
+            self.status.lock().unwrap().enable_notifications();
+        }
+        if init_options.event_stream {
+            self.events.enable();
+        }
+
+        // Report each crate Cargo compiles, the first build of a project with
+        // many dependencies takes a while.
+        let status = self.status.clone();
+        let build_progress = self.build_progress.clone();
+        let progress_out = out.clone();
+        self.build_queue.on_progress(move |progress| {
+            {
+                let mut status = status.lock().unwrap();
+                status.compiling_progress(&progress.crate_name, progress.index, progress.total);
+                status.notify(&progress_out);
+            }
+            if let Some(ref build_progress) = build_progress {
+                build_progress.report(progress, &progress_out);
+            }
+        });
+
         if !init_options.omit_init_build {
             self.build_current_project(BuildPriority::Cargo, out);
         }
     }
 
     fn build<O: Output>(&self, project_path: &Path, priority: BuildPriority, out: O) {
+        let session_id = self.next_build_session.fetch_add(1, Ordering::SeqCst);
         let pbh = {
             let config = self.config.lock().unwrap();
             PostBuildHandler {
                 analysis: self.analysis.clone(),
                 previous_build_results: self.previous_build_results.clone(),
+                borrow_facts: self.borrow_facts.clone(),
+                used_crates: if config.unused_dependencies { Some(self.used_crates.clone()) } else { None },
+                crate_files: self.crate_files.clone(),
+                crate_uses: self.crate_uses.clone(),
+                derive_macros: self.derive_macros.clone(),
+                lib_names: self.build_queue.lib_names(),
                 project_path: project_path.to_owned(),
+                analysis_dir: analysis_dir(project_path, &config),
                 out: out.clone(),
-                show_warnings: config.show_warnings,
+                session_id,
+                started: Instant::now(),
+                status: self.status.clone(),
+                threads: self.post_build_threads.clone(),
+                unsaved_analysis: self.unsaved_analysis.clone(),
+                events: self.events.clone(),
+                build_progress: self.build_progress.clone(),
+                diagnostic_filter: DiagnosticFilter::from_config(&config),
                 use_black_list: config.use_crate_blacklist,
+                memory_budget: config.memory_budget.map(|mb| mb * 1024 * 1024),
+                low_power: config.low_power(),
+                diagnostics_providers: Arc::new(providers_for_config(&config,
+                                                                     project_path,
+                                                                     self.build_queue.crate_failures(),
+                                                                     self.clippy_lints.clone(),
+                                                                     self.vfs.clone())),
+                positions: self.positions.clone(),
+                documents: self.documents.clone(),
+                symbol_index: self.symbol_index.clone(),
+                vfs: self.vfs.clone(),
+                import_policy: ImportPolicy::from_config(&config),
+                related_information: self.client.related_information(),
+                diagnostic_tags: self.client.diagnostic_tags(),
+                stale_analysis: self.stale_analysis.clone(),
+                generation: self.generation.load(Ordering::SeqCst),
+                current_generation: self.generation.clone(),
             }
         };
 
         out.notify(NotificationMessage::new(
             NOTIFICATION_BUILD_BEGIN,
-            None,
+            Some(NotificationParams::BeginBuild(BeginBuildParams { session_id })),
         ));
-        self.build_queue.request_build(project_path, priority, move |result| {
+        self.events.emit(&out, ServerEvent::BuildStarted { session_id });
+        {
+            let mut status = self.status.lock().unwrap();
+            if status.build_started() {
+                status.notify(&out);
+            }
+        }
+        let early_pbh = pbh.clone();
+        let edited = self.build_queue.dirty_files();
+        self.build_queue.request_build(project_path, priority, move |messages| {
+            early_pbh.handle_early_messages(messages, &edited)
+        }, move |result| {
             pbh.handle(result)
         });
     }
@@ -186,11 +622,107 @@ impl InitActionContext {
         self.build(&self.current_project, priority, out);
     }
 
+    // Stops building and waits (at most `timeout`) until the running build and
+    // the handling of its results are done. Returns whether they are.
+    fn finish_builds(&self, timeout: Duration) -> bool {
+        let started = Instant::now();
+        if !self.build_queue.shut_down(timeout) {
+            return false;
+        }
+        // The results are handled on other threads, e.g. the analysis data is
+        // reloaded, the build only counts as finished after that.
+        let remaining = timeout.checked_sub(started.elapsed()).unwrap_or_default();
+        self.post_build_threads.join(remaining)
+    }
+
+    // A new context for the client in the workspace at `current_project`,
+    // initialized like this one was but always building. The diagnostics
+    // published so far are cleared and running requests cancelled, their
+    // results would be stale.
+    fn restart<O: Output>(&self, current_project: PathBuf, out: O) -> InitActionContext {
+        // The locks may be poisoned, see `restart_if_poisoned`.
+        for flag in self.cancellable.lock().unwrap_or_else(|e| e.into_inner()).values() {
+            flag.store(true, Ordering::SeqCst);
+        }
+        {
+            let mut results = self.previous_build_results.lock().unwrap_or_else(|e| e.into_inner());
+            let files: Vec<PathBuf> = results.keys().cloned().collect();
+            clear_diagnostics(&mut results, &files, &self.documents, &out);
+        }
+        forget_sysroots();
+
+        let init_options = InitializationOptions {
+            omit_init_build: false,
+            ..self.init_options.clone()
+        };
+        let config = if self.config.is_poisoned() {
+            let config = self.config.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            Arc::new(Mutex::new(config))
+        } else {
+            self.config.clone()
+        };
+        let mut ctx = InitActionContext::new(self.analysis.clone(),
+                                             self.vfs.clone(),
+                                             config,
+                                             self.pending.clone(),
+                                             self.documents.clone(),
+                                             current_project,
+                                             init_options,
+                                             self.positions.encoding(),
+                                             self.client.clone());
+        ctx.stale_analysis.store(true, Ordering::SeqCst);
+        // The builds of this context may still finish, drop their results.
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        ctx.generation = self.generation.clone();
+        ctx.rust_src_missing_shown = self.rust_src_missing_shown.clone();
+        ctx.init(out);
+        ctx
+    }
+
+    // Whether a thread panicked while holding one of the locks of the context.
+    fn is_poisoned(&self) -> bool {
+        self.config.is_poisoned() ||
+        self.previous_build_results.is_poisoned() ||
+        self.status.is_poisoned() ||
+        self.cancellable.is_poisoned()
+    }
+
+    // Maps paths with the given prefixes (see `PathMappings`) from now on. The
+    // documents in the VFS move to their new paths. Returns the new path of
+    // the workspace root, if it moved.
+    fn update_path_mappings(&self, prefixes: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+        let mappings = self.documents.mappings().with_prefixes(prefixes);
+        let old = self.documents.set_mappings(mappings.clone());
+        if mappings == old {
+            return None;
+        }
+        for (path, text) in self.vfs.get_cached_files() {
+            if is_virtual_document(&path) {
+                continue;
+            }
+            let new_path = mappings.from_client(&old.to_client(&path));
+            if new_path != path {
+                trace!("update_path_mappings: {:?} moved to {:?}", path, new_path);
+                self.vfs.set_file(&new_path, &text);
+                let _ = self.vfs.flush_file(&path);
+            }
+        }
+        match mappings.root() {
+            Some(ref root) if *root == self.current_project => None,
+            root => root,
+        }
+    }
+
+    // The text of one of the RLS' own messages, in the configured locale.
+    fn message(&self, message: Message) -> String {
+        message.text(&self.config.lock().unwrap())
+    }
+
     fn convert_pos_to_span(&self, file_path: PathBuf, pos: Position) -> Span {
         trace!("convert_pos_to_span: {:?} {:?}", file_path, pos);
 
-        let pos = ls_util::position_to_rls(pos);
-        let line = self.vfs.load_line(&file_path, pos.row).unwrap();
+        let pos = self.positions.position_to_rls(&file_path, pos);
+        let line = self.line_indices.load_line(&self.vfs, &file_path, pos.row.0 as usize).unwrap();
         trace!("line: `{}`", line);
 
         let (start, end) = find_word_at_pos(&line, &pos.col);
@@ -202,6 +734,24 @@ impl InitActionContext {
     }
 }
 
+/// The prefixes of the path mappings (see `PathMappings`), those of the
+/// initialization options first.
+pub fn path_mappings(init_options: &InitializationOptions, config: &Config) -> Vec<(PathBuf, PathBuf)> {
+    init_options.path_mappings.iter().chain(config.path_mappings.iter()).cloned().collect()
+}
+
+// Removes save-analysis data which is no longer needed, if enabled, see `gc`.
+// `crates` are the crate names of the dependency graph, if known.
+fn collect_analysis_garbage(project_path: &Path, config: &Mutex<Config>, crates: Option<HashSet<String>>) {
+    let (enabled, analysis_dir, max_age_days) = {
+        let config = config.lock().unwrap();
+        (config.analysis_gc, analysis_dir(project_path, &config), config.analysis_gc_age_days)
+    };
+    if enabled {
+        gc::collect_garbage(&analysis_dir, crates.as_ref(), max_age_days);
+    }
+}
+
 /// Represents a text cursor between characters, pointing at the next character
 /// in the buffer.
 type Column = span::Column<span::ZeroIndexed>;
@@ -225,30 +775,101 @@ fn find_word_at_pos(line: &str, pos: &Column) -> (Column, Column) {
     (span::Column::new_zero_indexed(start), span::Column::new_zero_indexed(end))
 }
 
+/// Returns the directory of the package the given file belongs to, i.e. the
+/// nearest ancestor directory containing a `Cargo.toml`.
+pub(crate) fn package_dir_for_file(file: &Path) -> Option<&Path> {
+    let mut dir = file.parent();
+    while let Some(d) = dir {
+        if d.join("Cargo.toml").is_file() {
+            return Some(d);
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+/// Returns all Rust source files in the given directory and its
+/// subdirectories, except for `target` and hidden directories.
+pub(crate) fn collect_source_files(dir: &Path) -> Vec<PathBuf> {
+    fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+        let entries = match read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                if path.file_name().map_or(false, |name| name == "target" || name.to_string_lossy().starts_with('.')) {
+                    continue;
+                }
+                collect(&path, files);
+            } else if path.extension().map_or(false, |ext| ext == "rs") {
+                files.push(path);
+            }
+        }
+    }
+
+    let mut files = vec![];
+    collect(dir, &mut files);
+    files
+}
+
+/// Access to the text of files in the VFS without copying it.
+pub trait FileAccess {
+    /// Calls `f` with the current text of the file at `path`, loading it from
+    /// disk if necessary. The VFS is locked while `f` runs, so it should not
+    /// take long (e.g., copy the parts it needs instead of formatting the
+    /// whole file).
+    fn with_file<F, R>(&self, path: &Path, f: F) -> Result<R, vfs::Error>
+        where F: FnOnce(&str) -> R;
+}
+
+impl FileAccess for Vfs {
+    fn with_file<F, R>(&self, path: &Path, f: F) -> Result<R, vfs::Error>
+        where F: FnOnce(&str) -> R
+    {
+        // The user data of a file lives next to its text, which is the only
+        // way the VFS hands out a reference to the text.
+        self.ensure_user_data(path, |_| Ok(()))?;
+        self.with_user_data(path, |data| match data? {
+            (Some(text), _) => Ok(f(text)),
+            (None, _) => Err(vfs::Error::BadFileKind),
+        })
+    }
+}
+
 // TODO include workspace Cargo.tomls in watchers / relevant
 /// Client file-watching request / filtering logic
-/// We want to watch workspace 'Cargo.toml', root 'Cargo.lock' & the root 'target' dir
-pub struct FileWatch<'ctx> {
-    project_str: &'ctx str,
+/// We want to watch workspace 'Cargo.toml', root 'Cargo.lock' & the root 'target' dir,
+/// and source files being deleted (to clear their diagnostics)
+pub struct FileWatch {
+    // The project as the client knows it, see `PathMappings::to_client`.
+    project_str: String,
     project_uri: String,
 }
 
-impl<'ctx> FileWatch<'ctx> {
+impl FileWatch {
     /// Construct a new `FileWatch`.
-    pub fn new(ctx: &'ctx InitActionContext) -> Self {
+    pub fn new(ctx: &InitActionContext) -> Self {
+        let project = ctx.documents.mappings().to_client(&ctx.current_project);
         Self {
-            project_str: ctx.current_project.to_str().unwrap(),
-            project_uri: Url::from_file_path(&ctx.current_project).unwrap().into_string(),
+            project_uri: Url::from_file_path(&project).unwrap().into_string(),
+            project_str: project.to_str().unwrap().to_owned(),
         }
     }
 
     /// Returns json config for desired file watches
     pub fn watchers_config(&self) -> serde_json::Value {
-        let pattern = format!("{}/Cargo{{.toml,.lock}}", self.project_str);
+        let pattern = format!("{}/{{Cargo.toml,Cargo.lock,rust-project.json}}", self.project_str);
         let target_pattern = format!("{}/target", self.project_str);
-        // For target, we only watch if it gets deleted.
+        let source_pattern = format!("{}/**/*.rs", self.project_str);
+        // For target and source files, we only watch if they get deleted.
         json!({
-            "watchers": [{ "globPattern": pattern }, { "globPattern": target_pattern, "kind": 4 }]
+            "watchers": [
+                { "globPattern": pattern },
+                { "globPattern": target_pattern, "kind": 4 },
+                { "globPattern": source_pattern, "kind": 4 }
+            ]
         })
     }
 
@@ -265,7 +886,7 @@ impl<'ctx> FileWatch<'ctx> {
 
         let local = &path[self.project_uri.len()..];
 
-        local == "/Cargo.lock" || local == "/Cargo.toml"
+        local == "/Cargo.lock" || local == "/Cargo.toml" || local == "/rust-project.json"
             || local == "/target" && change.typ == FileChangeType::Deleted
     }
 }
@@ -0,0 +1,374 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finds cycles between the modules of a crate formed by their `use`
+//! statements, e.g. `a` importing from `b` while `b` imports from `a`.
+//!
+//! The analysis is purely textual: every file below `src` is a module (inline
+//! modules are attributed to their file) and paths are resolved against the
+//! known modules the way Rust 2015 does, i.e. relative to the crate root unless
+//! they start with `self` or `super`. Imports between a module and its
+//! ancestors are not considered, child modules are a part of their parents
+//! anyway.
+
+use actions::diagnostics::FileDiagnostic;
+use actions::{collect_source_files, package_dir_for_file};
+
+use ls_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+type ModulePath = Vec<String>;
+
+/// Checks every package in the project and reports a diagnostic on each `use`
+/// statement which is part of a cycle.
+pub fn module_cycles(project_dir: &Path) -> Vec<FileDiagnostic> {
+    let mut packages: HashMap<PathBuf, Vec<(PathBuf, ModulePath)>> = HashMap::new();
+    for file in collect_source_files(project_dir) {
+        let package_dir = match package_dir_for_file(&file) {
+            Some(dir) => dir.to_owned(),
+            None => continue,
+        };
+        let module = match file.strip_prefix(&package_dir.join("src")).ok().and_then(module_path) {
+            Some(module) => module,
+            None => continue,
+        };
+        packages.entry(package_dir).or_insert_with(Vec::new).push((file, module));
+    }
+
+    let mut result = vec![];
+    for (_, files) in packages {
+        let modules: Vec<_> = files.into_iter().filter_map(|(file, module)| {
+            let mut text = String::new();
+            File::open(&file).ok()?.read_to_string(&mut text).ok()?;
+            Some((file, module, parse_uses(&text)))
+        }).collect();
+
+        let cycles = {
+            let uses: Vec<_> = modules.iter().map(|&(_, ref module, ref uses)| (module.clone(), &uses[..])).collect();
+            find_cycles(&uses)
+        };
+        for (module_idx, use_idx, cycle) in cycles {
+            let (ref file, _, ref uses) = modules[module_idx];
+            let stmt = &uses[use_idx];
+            let cycle: Vec<_> = cycle.iter().map(|m| format!("`{}`", display_module(m))).collect();
+            result.push(FileDiagnostic {
+                file_path: file.clone(),
+                diagnostic: Diagnostic {
+                    range: Range::new(Position::new(stmt.row as u64, stmt.col_start as u64),
+                                      Position::new(stmt.row as u64, stmt.col_end as u64)),
+                    severity: Some(DiagnosticSeverity::Information),
+                    code: Some(NumberOrString::String("module_cycle".to_owned())),
+                    source: Some("rls".to_owned()),
+                    message: format!("this import is part of a dependency cycle between modules: {}",
+                                     cycle.join(" -> ")),
+                },
+                suggestions: vec![],
+                related: vec![],
+            });
+        }
+    }
+    result
+}
+
+// `foo/bar.rs` -> `foo::bar`, `foo/mod.rs` -> `foo`, `lib.rs` -> the crate
+// root. Binaries in `src/bin` are separate crates and ignored.
+fn module_path(relative: &Path) -> Option<ModulePath> {
+    let mut segments = relative.iter().map(|s| s.to_str().map(|s| s.to_owned())).collect::<Option<Vec<_>>>()?;
+    let file_name = segments.pop()?;
+    if segments.is_empty() && (file_name == "lib.rs" || file_name == "main.rs") {
+        return Some(vec![]);
+    }
+    if segments.first().map_or(false, |s| s == "bin") {
+        return None;
+    }
+    if file_name != "mod.rs" {
+        segments.push(file_name.trim_right_matches(".rs").to_owned());
+    }
+    Some(segments)
+}
+
+fn display_module(module: &[String]) -> String {
+    if module.is_empty() {
+        "crate".to_owned()
+    } else {
+        module.join("::")
+    }
+}
+
+/// A `use` statement in a source file.
+#[derive(Debug, PartialEq)]
+struct UseStmt {
+    // Zero-indexed line and (char) columns of the first line of the
+    // statement.
+    row: usize,
+    col_start: usize,
+    col_end: usize,
+    // The imported paths, as written.
+    paths: Vec<Vec<String>>,
+}
+
+fn parse_uses(text: &str) -> Vec<UseStmt> {
+    let mut result = vec![];
+    let mut lines = text.lines().enumerate();
+    while let Some((row, line)) = lines.next() {
+        let trimmed = line.trim_left();
+        let tree_start = match use_tree_start(trimmed) {
+            Some(start) => start,
+            None => continue,
+        };
+
+        let mut tree = trimmed[tree_start..].to_owned();
+        while !tree.contains(';') {
+            match lines.next() {
+                Some((_, next)) => tree.push_str(next),
+                None => break,
+            }
+        }
+        let tree = tree.split(';').next().unwrap();
+
+        let col_start = line.chars().count() - trimmed.chars().count();
+        result.push(UseStmt {
+            row,
+            col_start,
+            col_end: col_start + trimmed.trim_right().chars().count(),
+            paths: use_paths(tree),
+        });
+    }
+    result
+}
+
+// Returns the index of the use tree if `line` starts a `use` statement
+// (possibly with a visibility).
+fn use_tree_start(line: &str) -> Option<usize> {
+    let mut rest = line;
+    if rest.starts_with("pub") {
+        rest = rest["pub".len()..].trim_left();
+        if rest.starts_with('(') {
+            rest = rest[rest.find(')')? + 1..].trim_left();
+        }
+    }
+    if !rest.starts_with("use ") {
+        return None;
+    }
+    Some(line.len() - rest.len() + "use ".len())
+}
+
+// `a::{b, c::{d, e as f}}` -> `a::b`, `a::c::d`, `a::c::e`.
+fn use_paths(tree: &str) -> Vec<Vec<String>> {
+    let tree = tree.trim();
+    let open = match tree.find('{') {
+        Some(open) => open,
+        None => return vec![path_segments(tree)],
+    };
+    let close = tree.rfind('}').unwrap_or_else(|| tree.len());
+    let prefix = path_segments(&tree[..open]);
+
+    let mut result = vec![];
+    for sub_tree in split_top_level(&tree[open + 1..close]) {
+        for path in use_paths(sub_tree) {
+            result.push(prefix.iter().cloned().chain(path).collect());
+        }
+    }
+    result
+}
+
+fn path_segments(path: &str) -> Vec<String> {
+    path.split("::")
+        .filter_map(|s| s.split_whitespace().next())
+        .filter(|s| *s != "*")
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut result = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(&list[start..]);
+    result.into_iter().filter(|s| !s.trim().is_empty()).collect()
+}
+
+// Resolves an imported path to an absolute one, as in Rust 2015.
+fn resolve(module: &[String], path: &[String]) -> ModulePath {
+    let mut i = 0;
+    let mut result = vec![];
+    match path.first().map(|s| &**s) {
+        Some("self") => {
+            result = module.to_vec();
+            i = 1;
+        }
+        Some("super") => result = module.to_vec(),
+        Some("crate") => i = 1,
+        _ => {}
+    }
+    while path.get(i).map_or(false, |s| s == "super") {
+        result.pop();
+        i += 1;
+    }
+    result.extend(path[i..].iter().filter(|s| *s != "self").cloned());
+    result
+}
+
+// Returns, for every `use` statement which is part of a cycle, the index of
+// its module, the index of the statement and the cycle (starting and ending at
+// the statement's module).
+fn find_cycles(modules: &[(ModulePath, &[UseStmt])]) -> Vec<(usize, usize, Vec<ModulePath>)> {
+    let known: HashSet<&[String]> = modules.iter().map(|&(ref m, _)| &m[..]).collect();
+
+    // The modules imported by each statement.
+    let mut imports: Vec<Vec<Vec<ModulePath>>> = vec![];
+    let mut graph: HashMap<&[String], HashSet<ModulePath>> = HashMap::new();
+    for &(ref module, uses) in modules {
+        let mut stmt_imports = vec![];
+        for stmt in uses {
+            let mut targets = vec![];
+            for path in &stmt.paths {
+                let path = resolve(module, path);
+                let target = match (1..path.len() + 1).rev().map(|n| &path[..n]).find(|p| known.contains(p)) {
+                    Some(target) => target.to_vec(),
+                    None => continue,
+                };
+                if target.starts_with(module) || module.starts_with(&target) {
+                    continue;
+                }
+                graph.entry(&module[..]).or_insert_with(HashSet::new).insert(target.clone());
+                targets.push(target);
+            }
+            stmt_imports.push(targets);
+        }
+        imports.push(stmt_imports);
+    }
+
+    let mut result = vec![];
+    for (module_idx, stmt_imports) in imports.iter().enumerate() {
+        let module = &modules[module_idx].0;
+        for (use_idx, targets) in stmt_imports.iter().enumerate() {
+            if let Some(path) = targets.iter().filter_map(|t| shortest_path(&graph, t, module)).next() {
+                let mut cycle = vec![module.clone()];
+                cycle.extend(path);
+                result.push((module_idx, use_idx, cycle));
+            }
+        }
+    }
+    result
+}
+
+// Breadth-first search from `from` to `to`, returning the modules on the way
+// (including both ends).
+fn shortest_path(graph: &HashMap<&[String], HashSet<ModulePath>>, from: &[String], to: &[String]) -> Option<Vec<ModulePath>> {
+    let mut previous: HashMap<&[String], &[String]> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(from);
+    while let Some(module) = queue.pop_front() {
+        if module == to {
+            let mut path = vec![module.to_vec()];
+            let mut current = module;
+            while let Some(prev) = previous.get(current) {
+                path.push(prev.to_vec());
+                current = prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for next in graph.get(module).into_iter().flat_map(|targets| targets.iter()) {
+            if &next[..] != from && !previous.contains_key(&next[..]) {
+                previous.insert(&next[..], module);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(s: &str) -> Vec<String> {
+        if s.is_empty() {
+            vec![]
+        } else {
+            s.split("::").map(|s| s.to_owned()).collect()
+        }
+    }
+
+    #[test]
+    fn test_module_path() {
+        assert_eq!(module_path(Path::new("lib.rs")), Some(vec![]));
+        assert_eq!(module_path(Path::new("foo.rs")), Some(path("foo")));
+        assert_eq!(module_path(Path::new("foo/mod.rs")), Some(path("foo")));
+        assert_eq!(module_path(Path::new("foo/bar.rs")), Some(path("foo::bar")));
+        assert_eq!(module_path(Path::new("bin/tool.rs")), None);
+    }
+
+    #[test]
+    fn test_parse_uses() {
+        let text = "use foo::Bar;\n    pub(crate) use self::baz::{a, b::{c, d as e}};\nfn f() {}\nuse qux::{\n    X,\n};\n";
+        assert_eq!(parse_uses(text), vec![
+            UseStmt { row: 0, col_start: 0, col_end: 13, paths: vec![path("foo::Bar")] },
+            UseStmt {
+                row: 1,
+                col_start: 4,
+                col_end: 50,
+                paths: vec![path("self::baz::a"), path("self::baz::b::c"), path("self::baz::b::d")],
+            },
+            UseStmt { row: 3, col_start: 0, col_end: 10, paths: vec![path("qux::X")] },
+        ]);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let module = path("a::b");
+        assert_eq!(resolve(&module, &path("c::D")), path("c::D"));
+        assert_eq!(resolve(&module, &path("self::c")), path("a::b::c"));
+        assert_eq!(resolve(&module, &path("super::c")), path("a::c"));
+        assert_eq!(resolve(&module, &path("super::super::c")), path("c"));
+        assert_eq!(resolve(&module, &path("crate::c::self")), path("c"));
+    }
+
+    #[test]
+    fn test_find_cycles() {
+        let stmt = |row, p: &str| UseStmt { row, col_start: 0, col_end: 0, paths: vec![path(p)] };
+        let root = vec![stmt(0, "a::A")];
+        let a = vec![stmt(0, "b::B"), stmt(1, "super::Root"), stmt(2, "std::fmt")];
+        let b = vec![stmt(0, "c::C")];
+        let c = vec![stmt(0, "a::A")];
+        let inner = vec![stmt(0, "super::A"), stmt(1, "c::C")];
+        let modules = vec![
+            (vec![], &root[..]),
+            (path("a"), &a[..]),
+            (path("b"), &b[..]),
+            (path("c"), &c[..]),
+            (path("a::inner"), &inner[..]),
+        ];
+
+        let cycles = find_cycles(&modules);
+        assert_eq!(cycles, vec![
+            (1, 0, vec![path("a"), path("b"), path("c"), path("a")]),
+            (2, 0, vec![path("b"), path("c"), path("a"), path("b")]),
+            (3, 0, vec![path("c"), path("a"), path("b"), path("c")]),
+        ]);
+    }
+}
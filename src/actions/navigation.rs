@@ -0,0 +1,370 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Navigation which the analysis data doesn't answer directly: from an
+//! expression to the definition of its type (`textDocument/typeDefinition`),
+//! from a method of a trait impl to the method declared in the trait
+//! (`textDocument/declaration`), and to definitions the analysis data doesn't
+//! know at all.
+//!
+//! The analysis data only has the type of an expression as a string, and only
+//! knows a method of a trait impl by its qualified name, e.g.
+//! `<Foo as Bar>::baz`. Both are resolved by name in the symbol index, so only
+//! types and traits defined in the workspace are found.
+//!
+//! Without analysis data (before the first build, or in code generated by
+//! macros) definitions are searched for in the source files of the workspace,
+//! as lines like `pub fn name(`. This is only a guess, see `search_definitions`.
+//!
+//! The analysis data doesn't know every segment of the paths in `use`
+//! statements (e.g. in nested groups or re-exports), nor the paths in
+//! attributes. Those are looked up by name in the analysis data of every
+//! crate, see `use_path_definitions` and `macro_definitions`. A derive or an
+//! attribute macro goes to the function defining the macro (see
+//! `attributes::DeriveMacros`), a built-in derive to the trait it implements.
+
+use actions::attributes::DeriveMacros;
+use actions::cargo_doc::crate_name_for_file;
+use actions::format::strip_line_comment;
+use actions::symbol_index::SymbolIndex;
+use analysis::{AnalysisHost, Def, DefKind};
+use span;
+use Span;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Keywords followed by the name of the item they define.
+const DEFINITION_KEYWORDS: &[&str] = &[
+    "fn", "struct", "enum", "union", "trait", "type", "const", "static", "mod", "macro_rules!",
+];
+
+/// The path of the outermost named type in a type as printed by the compiler,
+/// without its generic arguments, e.g. `std::vec::Vec` for `&mut
+/// std::vec::Vec<Foo>`. `None` for types without a name, e.g. tuples.
+pub fn type_path(ty: &str) -> Option<&str> {
+    let mut ty = ty.trim();
+    loop {
+        let rest = if ty.starts_with('&') {
+            let rest = ty[1..].trim_left();
+            // Skip the lifetime of the reference.
+            if rest.starts_with('\'') {
+                rest.find(' ').map_or("", |i| &rest[i..])
+            } else {
+                rest
+            }
+        } else if ty.starts_with('[') {
+            &ty[1..]
+        } else {
+            match ["mut ", "*const ", "*mut ", "dyn ", "impl "].iter().find(|p| ty.starts_with(*p)) {
+                Some(prefix) => &ty[prefix.len()..],
+                None => break,
+            }
+        };
+        ty = rest.trim_left();
+    }
+
+    let end = ty.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':')).unwrap_or(ty.len());
+    let path = ty[..end].trim_right_matches(':');
+    if path.is_empty() { None } else { Some(path) }
+}
+
+/// The name of the trait and of the method, from the qualified name of a
+/// method of a trait impl, e.g. `("Bar", "baz")` for `<Foo as a::Bar>::baz`.
+pub fn trait_method(qualname: &str) -> Option<(&str, &str)> {
+    if !qualname.starts_with('<') {
+        return None;
+    }
+    let close = qualname.rfind(">::")?;
+    let method = &qualname[close + ">::".len()..];
+    let as_ = qualname[..close].rfind(" as ")?;
+    let trait_path = &qualname[as_ + " as ".len()..close];
+    // Generic arguments of the trait aren't part of its name.
+    let trait_path = trait_path.split('<').next().unwrap_or(trait_path);
+    let trait_name = trait_path.rsplit("::").next().unwrap_or(trait_path);
+    if trait_name.is_empty() || method.is_empty() {
+        return None;
+    }
+    Some((trait_name, method))
+}
+
+/// The definitions of types (and traits) in the workspace named like the last
+/// segment of `path`.
+pub fn type_definitions(index: &SymbolIndex, path: &str) -> Vec<Span> {
+    let name = path.rsplit("::").next().unwrap_or(path);
+    index.search(name)
+        .into_iter()
+        .filter(|s| s.name == name && is_type(s.kind))
+        .map(|s| s.span)
+        .collect()
+}
+
+/// The declarations of method `method` in traits named `trait_name` in the
+/// workspace.
+pub fn trait_method_declarations(index: &SymbolIndex, trait_name: &str, method: &str) -> Vec<Span> {
+    index.search(method)
+        .into_iter()
+        .filter(|s| {
+            s.name == method && s.kind == DefKind::Method &&
+                s.container_name.as_ref().map_or(false, |c| c == trait_name)
+        })
+        .map(|s| s.span)
+        .collect()
+}
+
+/// The definitions of `name` in `files`, found by looking for lines which
+/// define an item of that name (as read from disk, so without unsaved
+/// changes). Anything from a local variable to an item in another crate with
+/// the same name may be what was meant instead.
+pub fn search_definitions(files: &[PathBuf], name: &str) -> Vec<Span> {
+    let len = name.chars().count() as u32;
+    let mut result = vec![];
+    for file in files {
+        let mut text = String::new();
+        if File::open(file).and_then(|mut f| f.read_to_string(&mut text)).is_err() || !text.contains(name) {
+            continue;
+        }
+        for (row, line) in text.lines().enumerate() {
+            if let Some(col) = defined_at(line, name) {
+                let row = span::Row::new_zero_indexed(row as u32);
+                result.push(Span::from_positions(span::Position::new(row, span::Column::new_zero_indexed(col)),
+                                                 span::Position::new(row, span::Column::new_zero_indexed(col + len)),
+                                                 file.clone()));
+            }
+        }
+    }
+    result
+}
+
+// The column (in chars) of `name` on `line`, if the line defines an item of
+// that name, e.g. `pub(crate) fn name<T>(`.
+fn defined_at(line: &str, name: &str) -> Option<u32> {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let code = strip_line_comment(line);
+    for keyword in DEFINITION_KEYWORDS {
+        for (start, _) in code.match_indices(keyword) {
+            if code[..start].chars().next_back().map_or(false, &is_ident_char) {
+                continue;
+            }
+            let after = &code[start + keyword.len()..];
+            if !after.starts_with(char::is_whitespace) {
+                continue;
+            }
+            let mut rest = after.trim_left();
+            if *keyword == "static" && rest.starts_with("mut ") {
+                rest = rest["mut ".len()..].trim_left();
+            }
+            if rest.starts_with(name) && !rest[name.len()..].starts_with(&is_ident_char) {
+                let offset = code.len() - rest.len();
+                return Some(code[..offset].chars().count() as u32);
+            }
+        }
+    }
+    None
+}
+
+/// The path of a `use` statement up to its last word, which is where the
+/// cursor is, e.g. `["std", "collections", "HashMap"]` for `pub use
+/// std::{fmt, collections::{HashMap`. `statement` is the text of the statement
+/// up to there, over several lines if it has. On the new name of an `as`, the
+/// path of the item renamed.
+pub fn use_path(statement: &str) -> Option<Vec<String>> {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let start = statement.match_indices("use ")
+        .map(|(i, _)| i)
+        .find(|&i| !statement[..i].chars().next_back().map_or(false, &is_ident_char))?;
+
+    // The prefix of each open group, e.g. `std::collections` for
+    // `std::collections::{`.
+    let mut groups: Vec<Vec<String>> = vec![];
+    let mut path: Vec<String> = vec![];
+    let mut word = String::new();
+    let mut renamed = false;
+    for c in statement[start + "use ".len()..].chars().chain(Some(' ')) {
+        if is_ident_char(c) {
+            word.push(c);
+            continue;
+        }
+        if word == "as" {
+            renamed = true;
+        } else if !word.is_empty() && !renamed {
+            path.push(word.clone());
+        }
+        word.clear();
+        match c {
+            '{' => groups.push(path.clone()),
+            ',' => {
+                path = groups.last().cloned().unwrap_or_default();
+                renamed = false;
+            }
+            '}' => {
+                groups.pop();
+            }
+            _ => {}
+        }
+    }
+    // `foo::{self}` is `foo`.
+    if path.len() > 1 && path.last().map_or(false, |s| s == "self") {
+        path.pop();
+    }
+    if path.is_empty() { None } else { Some(path) }
+}
+
+/// The definitions `path` (from a `use` statement in `file`) may refer to: the
+/// ones named like its last segment whose qualified names end with the path,
+/// with or without its first segment (the crate, or `self`, `super` or
+/// `crate`). If there are none, the item may be re-exported under another
+/// path, so those of that name in the crate the path starts in.
+pub fn use_path_definitions(analysis: &AnalysisHost, file: &Path, path: &[String]) -> Vec<Span> {
+    let name = match path.last() {
+        Some(name) => name,
+        None => return vec![],
+    };
+    let defs: Vec<_> = analysis.search_for_id(name)
+        .unwrap_or_else(|_| vec![])
+        .into_iter()
+        .filter_map(|id| analysis.get_def(id).ok())
+        .collect();
+    let matching: Vec<Span> = defs.iter()
+        .filter(|def| matches_use_path(path, &def.qualname))
+        .map(|def| def.span.clone())
+        .collect();
+    if !matching.is_empty() {
+        return matching;
+    }
+
+    // The first segment is a crate, otherwise the path starts in the crate of
+    // `file` (a module, `self`, `super` or `crate`).
+    let in_crate = |def: &Def, krate: &String| crate_name_for_file(&def.span.file).as_ref() == Some(krate);
+    let krate = if path.len() > 1 && defs.iter().any(|def| in_crate(def, &path[0])) {
+        Some(path[0].clone())
+    } else {
+        crate_name_for_file(file)
+    };
+    match krate {
+        Some(krate) => defs.into_iter().filter(|def| in_crate(def, &krate)).map(|def| def.span).collect(),
+        None => vec![],
+    }
+}
+
+/// The definitions of the macro used by `path` in an attribute: the derive
+/// macro (if `derive`) or attribute macro, e.g. `serde::Serialize` in
+/// `#[derive(serde::Serialize)]`. For derives without a macro (the built-in
+/// ones), the traits of that name.
+pub fn macro_definitions(analysis: &AnalysisHost, derive_macros: &DeriveMacros, path: &[String], derive: bool) -> Vec<Span> {
+    let mut spans = vec![];
+    for (function, qualname) in derive_macros.functions(path, derive) {
+        spans.extend(analysis.search_for_id(&function)
+            .unwrap_or_else(|_| vec![])
+            .into_iter()
+            .filter_map(|id| analysis.get_def(id).ok())
+            .filter(|def| def.qualname == qualname)
+            .map(|def| def.span));
+    }
+    if !spans.is_empty() || !derive {
+        return spans;
+    }
+
+    let name = match path.last() {
+        Some(name) => name,
+        None => return vec![],
+    };
+    let traits: Vec<_> = analysis.search_for_id(name)
+        .unwrap_or_else(|_| vec![])
+        .into_iter()
+        .filter_map(|id| analysis.get_def(id).ok())
+        .filter(|def| def.kind == DefKind::Trait)
+        .collect();
+    let matching: Vec<Span> = traits.iter()
+        .filter(|def| matches_use_path(path, &def.qualname))
+        .map(|def| def.span.clone())
+        .collect();
+    if !matching.is_empty() || path.len() > 1 {
+        return matching;
+    }
+    traits.into_iter().map(|def| def.span).collect()
+}
+
+// Whether `qualname` (e.g. `::de::Deserialize`) ends with `path`, with or
+// without its first segment.
+fn matches_use_path(path: &[String], qualname: &str) -> bool {
+    let qualname: Vec<&str> = qualname.trim_left_matches("::").split("::").collect();
+    let ends_with = |path: &[String]| {
+        !path.is_empty() && path.len() <= qualname.len() &&
+            qualname[qualname.len() - path.len()..].iter().zip(path).all(|(q, p)| q == p)
+    };
+    ends_with(path) || ends_with(&path[1..])
+}
+
+/// Whether definitions of this kind are types (or traits).
+pub fn is_type(kind: DefKind) -> bool {
+    match kind {
+        DefKind::Struct | DefKind::Enum | DefKind::Union | DefKind::Trait | DefKind::Type => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_type_path() {
+        assert_eq!(type_path("Foo"), Some("Foo"));
+        assert_eq!(type_path("&mut std::vec::Vec<foo::Bar>"), Some("std::vec::Vec"));
+        assert_eq!(type_path("&'a str"), Some("str"));
+        assert_eq!(type_path("[Foo; 3]"), Some("Foo"));
+        assert_eq!(type_path("*const Box<Trait>"), Some("Box"));
+        assert_eq!(type_path("(u32, Foo)"), None);
+        assert_eq!(type_path(""), None);
+    }
+
+    #[test]
+    fn test_trait_method() {
+        assert_eq!(trait_method("<Foo as Bar>::baz"), Some(("Bar", "baz")));
+        assert_eq!(trait_method("<foo::Foo<T> as std::convert::From<T>>::from"), Some(("From", "from")));
+        assert_eq!(trait_method("<Foo>::new"), None);
+        assert_eq!(trait_method("::foo::Foo::new"), None);
+    }
+
+    #[test]
+    fn test_use_path() {
+        let path = |statement| use_path(statement).map(|p| p.join("::"));
+        assert_eq!(path("use std::collections::HashMap"), Some("std::collections::HashMap".to_owned()));
+        assert_eq!(path("pub use std::{fmt, collections::{HashMap"), Some("std::collections::HashMap".to_owned()));
+        assert_eq!(path("use serde::{de::{Deserialize, Visitor}, Serialize"), Some("serde::Serialize".to_owned()));
+        assert_eq!(path("use foo::{\n    bar::Baz,\n    Qux"), Some("foo::Qux".to_owned()));
+        assert_eq!(path("use foo::Bar as Baz"), Some("foo::Bar".to_owned()));
+        assert_eq!(path("use foo::{self"), Some("foo".to_owned()));
+        assert_eq!(path("reuse foo"), None);
+        assert_eq!(path("use "), None);
+    }
+
+    #[test]
+    fn test_matches_use_path() {
+        let path = |p: &str| p.split("::").map(|s| s.to_owned()).collect::<Vec<_>>();
+        assert!(matches_use_path(&path("serde::de::Deserialize"), "::de::Deserialize"));
+        assert!(matches_use_path(&path("serde::Deserialize"), "::de::Deserialize"));
+        assert!(matches_use_path(&path("foo::Bar"), "::foo::Bar"));
+        assert!(!matches_use_path(&path("serde::ser::Serializer"), "::de::Serializer"));
+        assert!(!matches_use_path(&path("std::fmt::Result"), "::io::Result"));
+    }
+
+    #[test]
+    fn test_defined_at() {
+        assert_eq!(defined_at("pub(crate) fn foo<T>(x: T) {", "foo"), Some(14));
+        assert_eq!(defined_at("    static mut FOO: u32 = 0;", "FOO"), Some(15));
+        assert_eq!(defined_at("macro_rules! foo {", "foo"), Some(13));
+        assert_eq!(defined_at("struct Foobar;", "Foo"), None);
+        assert_eq!(defined_at("let foo = bar_fn foo();", "foo"), None);
+        assert_eq!(defined_at("// fn foo() {}", "foo"), None);
+    }
+}
@@ -10,10 +10,12 @@
 
 //! One-way notifications that the RLS receives from the client.
 
-use actions::ActionContext;
+use actions::{path_mappings, ActionContext, InitActionContext};
 use actions::FileWatch;
+use actions::manifest;
+use actions::post_build::{clear_diagnostics, emit_notifications, DiagnosticFilter};
 use vfs::Change;
-use config::Config;
+use config::{BuildOn, Config};
 use serde::Deserialize;
 use serde::de::Error;
 use serde_json;
@@ -23,7 +25,9 @@ use build::*;
 use lsp_data::*;
 use server::{Output, Action, NotificationAction, LsState, NoParams};
 
+use std::path::PathBuf;
 use std::thread;
+use std::sync::atomic::Ordering;
 
 /// Notification from the client that it has completed initialization.
 #[derive(Debug, PartialEq)]
@@ -72,12 +76,54 @@ impl<'a> Action<'a> for DidOpen {
 }
 
 impl<'a> NotificationAction<'a> for DidOpen {
-    fn handle<O: Output>(&mut self, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<(), ()> {
+    fn handle<O: Output>(&mut self, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<(), ()> {
         trace!("on_open: {:?}", params.text_document.uri);
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "on_open")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "on_open")?;
 
         ctx.vfs.set_file(&file_path, &params.text_document.text);
+        ctx.line_indices.invalidate(&file_path);
+        ctx.racer.file_changed(&file_path);
+        if manifest::is_manifest(&file_path) {
+            manifest::publish_manifest_diagnostics(ctx, &file_path, &out);
+        }
+
+        // Without a workspace, every Rust file opened is a crate of its own.
+        if is_standalone_project(&ctx.current_project) && !is_virtual_document(&file_path) &&
+           file_path.extension().map_or(false, |ext| ext == "rs") {
+            match add_standalone_file(&ctx.current_project, &file_path) {
+                Ok(true) => ctx.build_current_project(BuildPriority::Cargo, out),
+                Ok(false) => {}
+                Err(e) => debug!("Couldn't add {:?} to the standalone project: {}", file_path, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Notification from the client that it closed the given document. The path
+/// made up for a document which isn't a file is forgotten, see
+/// `DocumentPaths`.
+#[derive(Debug)]
+pub struct DidClose;
+
+impl<'a> Action<'a> for DidClose {
+    type Params = DidCloseTextDocumentParams;
+    const METHOD: &'static str = "textDocument/didClose";
+
+    fn new(_: &'a mut LsState) -> Self {
+        DidClose
+    }
+}
+
+impl<'a> NotificationAction<'a> for DidClose {
+    fn handle<O: Output>(&mut self, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<(), ()> {
+        trace!("on_close: {:?}", params.text_document.uri);
+        let ctx = ctx.inited();
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "on_close")?;
+        if is_virtual_document(&file_path) {
+            ctx.documents.close(&file_path);
+        }
         Ok(())
     }
 }
@@ -100,29 +146,48 @@ impl<'a> NotificationAction<'a> for DidChange {
         trace!("on_change: {:?}, thread: {:?}", params, thread::current().id());
 
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "on_change")?;
-
-        let changes: Vec<Change> = params.content_changes.iter().map(|i| {
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "on_change")?;
+
+        // Each change is relative to the text after the previous ones, so they
+        // are applied one at a time to convert the columns of their ranges.
+        // Changes without a range replace the whole text (clients which only
+        // implement full sync send nothing else), as when opening the file.
+        for i in &params.content_changes {
+            // Invalidate before and after changing the text, so a request can't
+            // cache an index of the old text in between.
+            ctx.line_indices.invalidate(&file_path);
             if let Some(range) = i.range {
-                let range = ls_util::range_to_rls(range);
-                Change::ReplaceText {
+                let range = ctx.positions.range_to_rls(&file_path, range);
+                let change = Change::ReplaceText {
                     span: Span::from_range(range, file_path.clone()),
                     len: i.range_length,
                     text: i.text.clone()
-                }
+                };
+                ctx.vfs.on_changes(&[change]).expect("error committing to VFS");
+                ctx.borrow_facts.edited(&file_path, range, &i.text);
             } else {
-                Change::AddFile {
-                    file: file_path.clone(),
-                    text: i.text.clone(),
-                }
+                ctx.vfs.set_file(&file_path, &i.text);
+                ctx.borrow_facts.forget_file(&file_path);
             }
-        }).collect();
-        ctx.vfs.on_changes(&changes).expect("error committing to VFS");
-        if !changes.is_empty() {
+            ctx.line_indices.invalidate(&file_path);
+        }
+        ctx.racer.file_changed(&file_path);
+        if manifest::is_manifest(&file_path) {
+            manifest::publish_manifest_diagnostics(ctx, &file_path, &out);
+            // Manifests are built once saved (see `DidChangeWatchedFiles`),
+            // not on each keystroke.
+            return Ok(());
+        }
+        // Documents which aren't files are never part of a build.
+        if is_virtual_document(&file_path) {
+            return Ok(());
+        }
+        if !params.content_changes.is_empty() {
+            ctx.symbol_index.invalidate(&file_path);
             ctx.build_queue.mark_file_dirty(file_path, params.text_document.version)
         }
 
-        if !ctx.config.lock().unwrap().build_on_save {
+        if *ctx.config.lock().unwrap().build_on.as_ref() == BuildOn::Change {
             ctx.build_current_project(BuildPriority::Normal, out);
         }
         Ok(())
@@ -143,8 +208,16 @@ impl<'a> Action<'a> for Cancel {
 }
 
 impl<'a> NotificationAction<'a> for Cancel {
-    fn handle<O: Output>(&mut self, _params: CancelParams, _ctx: &mut ActionContext, _out: O) -> Result<(), ()> {
-        // Nothing to do.
+    fn handle<O: Output>(&mut self, params: CancelParams, ctx: &mut ActionContext, _out: O) -> Result<(), ()> {
+        // Only long running requests can be cancelled, anything else is done
+        // by the time we see the notification.
+        if let ActionContext::Init(ref ctx) = *ctx {
+            if let NumberOrString::Number(id) = params.id {
+                if let Some(flag) = ctx.cancellable.lock().unwrap().get(&(id as usize)) {
+                    flag.store(true, Ordering::SeqCst);
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -166,10 +239,27 @@ impl<'a> Action<'a> for DidChangeConfiguration {
 impl<'a> NotificationAction<'a> for DidChangeConfiguration {
     fn handle<O: Output>(&mut self, params: DidChangeConfigurationParams, ctx: &mut ActionContext, out: O) -> Result<(), ()> {
         trace!("config change: {:?}", params.settings);
-        let ctx = ctx.inited();
+        let moved_root = self.update(params, ctx.inited(), out.clone())?;
+        // The path mappings moved the root of the workspace, start over there.
+        if let Some(root) = moved_root {
+            ctx.restart_at(root, out);
+        }
+        Ok(())
+    }
+}
+
+impl DidChangeConfiguration {
+    // Returns the new path of the workspace root, if it moved.
+    fn update<O: Output>(&self, params: DidChangeConfigurationParams, ctx: &InitActionContext, out: O)
+                         -> Result<Option<PathBuf>, ()> {
+        let overrides = ctx.config.lock().unwrap().overrides.clone();
         let config = params.settings.get("rust")
                          .ok_or(serde_json::Error::missing_field("rust"))
-                         .and_then(|value| Config::deserialize(value));
+                         .and_then(|value| {
+                             let mut value = value.clone();
+                             overrides.apply(&mut value);
+                             Config::deserialize(value)
+                         });
 
         let new_config = match config {
             Ok(mut value) => {
@@ -182,19 +272,25 @@ impl<'a> NotificationAction<'a> for DidChangeConfiguration {
             }
         };
 
-        let unstable_features = new_config.unstable_features;
-
-        {
+        let path_mappings = {
             let mut config = ctx.config.lock().unwrap();
 
             // User may specify null (to be inferred) options, in which case
             // we schedule further inference on a separate thread not to block
             // the main thread
             let needs_inference = new_config.needs_inference();
+            let previous_filter = DiagnosticFilter::from_config(&config);
             // In case of null options, we provide default values for now
             config.update(new_config);
             trace!("Updated config: {:?}", *config);
 
+            // Republish the diagnostics we have straight away, the build below
+            // may well not report them again.
+            let filter = DiagnosticFilter::from_config(&config);
+            if filter != previous_filter {
+                emit_notifications(&ctx.previous_build_results.lock().unwrap(), &filter, &ctx.documents, &out);
+            }
+
             if needs_inference {
                 let project_dir = ctx.current_project.clone();
                 let config = ctx.config.clone();
@@ -207,31 +303,24 @@ impl<'a> NotificationAction<'a> for DidChangeConfiguration {
                     }
                 });
             }
+            path_mappings(&ctx.init_options, &config)
+        };
+        // The toolchain may have changed, it's worked out again when needed.
+        ctx.build_queue.toolchain().invalidate();
+        let moved_root = ctx.update_path_mappings(&path_mappings);
+        if moved_root.is_some() {
+            return Ok(moved_root);
         }
+
         // We do a clean build so that if we've changed any relevant options
         // for Cargo, we'll notice them. But if nothing relevant changes
         // then we don't do unnecessary building (i.e., we don't delete
-        // artifacts on disk).
-        ctx.build_current_project(BuildPriority::Cargo, out.clone());
-
-        const RANGE_FORMATTING_ID: &'static str = "rls-range-formatting";
-        // FIXME should handle the response
-        if unstable_features {
-            let output = serde_json::to_string(
-                &RequestMessage::new(out.provide_id(),
-                                        NOTIFICATION__RegisterCapability.to_owned(),
-                                        RegistrationParams { registrations: vec![Registration { id: RANGE_FORMATTING_ID.to_owned(), method: REQUEST__RangeFormatting.to_owned(), register_options: serde_json::Value::Null }] })
-            ).unwrap();
-            out.response(output);
-        } else {
-            let output = serde_json::to_string(
-                &RequestMessage::new(out.provide_id(),
-                                        NOTIFICATION__UnregisterCapability.to_owned(),
-                                        UnregistrationParams { unregisterations: vec![Unregistration { id: RANGE_FORMATTING_ID.to_owned(), method: REQUEST__RangeFormatting.to_owned() }] })
-            ).unwrap();
-            out.response(output);
+        // artifacts on disk). Unless builds are only started on request.
+        if *ctx.config.lock().unwrap().build_on.as_ref() != BuildOn::Manual {
+            ctx.build_current_project(BuildPriority::Cargo, out);
         }
-        Ok(())
+
+        Ok(None)
     }
 }
 
@@ -251,11 +340,25 @@ impl<'a> Action<'a> for DidSave {
 impl<'a> NotificationAction<'a> for DidSave {
     fn handle<O: Output>(&mut self, params: DidSaveTextDocumentParams, ctx: &mut ActionContext, out: O) -> Result<(), ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "on_save")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "on_save")?;
+        if is_virtual_document(&file_path) {
+            return Ok(());
+        }
 
         ctx.vfs.file_saved(&file_path).unwrap();
-
-        if ctx.config.lock().unwrap().build_on_save {
+        ctx.clippy_lints.invalidate();
+
+        // Clippy only runs again after a save, with `build_on` "change" the
+        // last build finished before it.
+        let build = {
+            let config = ctx.config.lock().unwrap();
+            match *config.build_on.as_ref() {
+                BuildOn::Save => true,
+                BuildOn::Change => config.clippy && cfg!(feature = "clippy"),
+                BuildOn::Manual => false,
+            }
+        };
+        if build {
             ctx.build_current_project(BuildPriority::Normal, out);
         }
 
@@ -289,7 +392,16 @@ impl<'a> NotificationAction<'a> for DidChangeWatchedFiles {
         let ctx = ctx.inited();
         let file_watch = FileWatch::new(&ctx);
 
-        if params.changes.iter().any(|c| file_watch.is_relevant(c)) {
+        let deleted: Vec<_> = params.changes.iter()
+            .filter(|c| c.typ == FileChangeType::Deleted)
+            .filter_map(|c| ctx.documents.path(&c.uri).ok())
+            .collect();
+        if !deleted.is_empty() {
+            clear_diagnostics(&mut ctx.previous_build_results.lock().unwrap(), &deleted, &ctx.documents, &out);
+        }
+
+        let manual = *ctx.config.lock().unwrap().build_on.as_ref() == BuildOn::Manual;
+        if !manual && params.changes.iter().any(|c| file_watch.is_relevant(c)) {
             ctx.build_current_project(BuildPriority::Cargo, out);
         }
 
@@ -0,0 +1,113 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Streaming the results of a request in pages, as partial results.
+//!
+//! If the client passes a `partialResultToken` with a request, the results are
+//! sent in `$/progress` notifications for that token while they are computed,
+//! and the response itself is empty. The client can show the first pages of a
+//! large result before the rest is ready.
+
+use lsp_data::{NotificationMessage, NotificationParams, NumberOrString, ProgressParams, NOTIFICATION_PROGRESS};
+use server::Output;
+
+use serde::Serialize;
+use serde_json;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Number of results in a page.
+pub const PAGE_SIZE: usize = 100;
+
+/// Collects the results of a request, sending them in pages if the client
+/// asked for partial results.
+pub struct ResultPages<T, O> {
+    token: Option<NumberOrString>,
+    results: Vec<T>,
+    // Set once the response was sent (e.g. after a timeout), no progress may
+    // be reported after that.
+    responded: Arc<AtomicBool>,
+    out: O,
+}
+
+impl<T: Serialize, O: Output> ResultPages<T, O> {
+    /// Collect results, sent as partial results for `token` if there is one.
+    pub fn new(token: Option<NumberOrString>, responded: Arc<AtomicBool>, out: O) -> ResultPages<T, O> {
+        ResultPages {
+            token,
+            results: vec![],
+            responded,
+            out,
+        }
+    }
+
+    /// Add a result, sending the page if it is full.
+    pub fn push(&mut self, result: T) {
+        self.results.push(result);
+        if self.token.is_some() && self.results.len() >= PAGE_SIZE {
+            self.send_page();
+        }
+    }
+
+    /// The results to respond with: those not sent as partial results, i.e.
+    /// none if the client asked for them.
+    pub fn finish(mut self) -> Vec<T> {
+        if self.token.is_some() {
+            self.send_page();
+        }
+        self.results
+    }
+
+    fn send_page(&mut self) {
+        let page: Vec<T> = self.results.drain(..).collect();
+        if page.is_empty() || self.responded.load(Ordering::SeqCst) {
+            return;
+        }
+        let token = self.token.clone().unwrap();
+        self.out.notify(NotificationMessage::new(
+            NOTIFICATION_PROGRESS,
+            Some(NotificationParams::Progress(ProgressParams {
+                token,
+                value: serde_json::to_value(&page).unwrap(),
+            })),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test::harness::RecordOutput;
+
+    fn pages(token: Option<NumberOrString>, count: usize) -> (Vec<usize>, Vec<String>) {
+        let out = RecordOutput::new();
+        let mut pages = ResultPages::new(token, Arc::new(AtomicBool::new(false)), out.clone());
+        for i in 0..count {
+            pages.push(i);
+        }
+        let results = pages.finish();
+        let sent = out.output.lock().unwrap().clone();
+        (results, sent)
+    }
+
+    #[test]
+    fn test_result_pages() {
+        let (results, sent) = pages(None, PAGE_SIZE + 1);
+        assert_eq!(results.len(), PAGE_SIZE + 1);
+        assert!(sent.is_empty());
+
+        let (results, sent) = pages(Some(NumberOrString::String("t".to_owned())), PAGE_SIZE + 1);
+        assert!(results.is_empty());
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].contains(r#""method":"$/progress""#));
+        assert!(sent[1].contains(r#""value":[100]"#));
+    }
+}
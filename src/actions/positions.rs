@@ -0,0 +1,120 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Converting positions between the protocol, whose columns are counted in
+//! the negotiated `PositionEncoding`, and the RLS, which counts chars.
+//!
+//! This needs the text of the line, which is taken from the VFS. If the line
+//! can't be loaded (e.g., a file of the standard library which isn't
+//! installed) the column is passed through unchanged.
+
+use actions::line_index::LineIndexCache;
+use actions::memory::CrateUses;
+use lsp_data::{ls_util, DocumentPaths, Location, Position, PositionEncoding, Range, UrlFileParseError};
+use span;
+use vfs::Vfs;
+use Span;
+
+use std::path::Path;
+use std::sync::Arc;
+
+/// Converts positions in the files of the VFS.
+#[derive(Clone)]
+pub struct PositionConverter {
+    vfs: Arc<Vfs>,
+    line_indices: Arc<LineIndexCache>,
+    documents: Arc<DocumentPaths>,
+    // Positions in a file, and locations in the results, are uses of the
+    // analysis data of their crates.
+    crate_uses: Arc<CrateUses>,
+    encoding: PositionEncoding,
+}
+
+impl PositionConverter {
+    /// Construct a converter for positions in the given encoding, in the
+    /// files and `documents` of the VFS, recording their crates' use in
+    /// `crate_uses`.
+    pub fn new(vfs: Arc<Vfs>,
+               line_indices: Arc<LineIndexCache>,
+               documents: Arc<DocumentPaths>,
+               crate_uses: Arc<CrateUses>,
+               encoding: PositionEncoding)
+               -> PositionConverter {
+        PositionConverter {
+            vfs,
+            line_indices,
+            documents,
+            crate_uses,
+            encoding,
+        }
+    }
+
+    /// The encoding of the client's columns.
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding
+    }
+
+    /// Convert a language server protocol position in `file` into an RLS
+    /// position.
+    pub fn position_to_rls(&self, file: &Path, pos: Position) -> span::Position<span::ZeroIndexed> {
+        self.crate_uses.file_used(file);
+        let col = match self.line(file, pos.line) {
+            Some(line) => ls_util::col_to_rls(&line, pos.character, self.encoding),
+            None => pos.character as u32,
+        };
+        span::Position::new(span::Row::new_zero_indexed(pos.line as u32),
+                            span::Column::new_zero_indexed(col))
+    }
+
+    /// Convert a language server protocol range in `file` into an RLS range.
+    pub fn range_to_rls(&self, file: &Path, range: Range) -> span::Range<span::ZeroIndexed> {
+        span::Range::from_positions(self.position_to_rls(file, range.start),
+                                    self.position_to_rls(file, range.end))
+    }
+
+    /// Convert an RLS position in `file` into a language server protocol
+    /// position.
+    pub fn rls_to_position(&self, file: &Path, pos: span::Position<span::ZeroIndexed>) -> Position {
+        let row = pos.row.0 as u64;
+        let character = match self.line(file, row) {
+            Some(line) => ls_util::col_from_rls(&line, pos.col.0, self.encoding),
+            None => pos.col.0 as u64,
+        };
+        Position::new(row, character)
+    }
+
+    /// Convert an RLS range in `file` into a language server protocol range.
+    pub fn rls_to_range(&self, file: &Path, range: span::Range<span::ZeroIndexed>) -> Range {
+        Range {
+            start: self.rls_to_position(file, range.start()),
+            end: self.rls_to_position(file, range.end()),
+        }
+    }
+
+    /// Convert an RLS span into a language server protocol location. Fails
+    /// if the span's file has no URI (its path is relative).
+    pub fn rls_to_location(&self, span: &Span) -> Result<Location, UrlFileParseError> {
+        self.crate_uses.file_used(&span.file);
+        Ok(Location {
+            uri: self.documents.uri(&span.file)?,
+            range: self.rls_to_range(&span.file, span.range),
+        })
+    }
+
+    /// Convert a range whose columns are counted in chars (like the ones of
+    /// the diagnostics providers) into the client's encoding.
+    pub fn char_range_to_client(&self, file: &Path, range: Range) -> Range {
+        self.rls_to_range(file, ls_util::range_to_rls(range))
+    }
+
+    fn line(&self, file: &Path, row: u64) -> Option<String> {
+        self.line_indices.load_line(&self.vfs, file, row as usize).ok()
+    }
+}
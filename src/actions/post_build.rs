@@ -9,248 +9,761 @@
 // except according to those terms.
 
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
 
-use build::BuildResult;
-use lsp_data::{NotificationMessage, PublishDiagnosticsParams, ls_util};
+use actions::attributes::DeriveMacros;
+use actions::borrows::{borrow_facts, BorrowFacts};
+use actions::build_progress::BuildProgressReporter;
+use actions::diagnostics::{diagnostic_tags, DiagnosticsProvider, FileDiagnostic, PathResolver, RelatedInformation, Suggestion};
+use actions::events::{millis, EventStream};
+use actions::imports::{import_edit, imported_path, ImportPolicy};
+use actions::ingest::{self, UnsavedAnalysis};
+use actions::manifest::manifest_diagnostics;
+use actions::memory::{analysis_files, crates_to_evict, loaded_size, CrateUses};
+use actions::positions::PositionConverter;
+use actions::status::StatusTracker;
+use actions::symbol_index::SymbolIndex;
+use actions::used_crates::UsedCrates;
+use actions::visibility::CrateFiles;
+use actions::workers::SerialThreads;
+use actions::collect_source_files;
+use build::{BuildResult, LibNames};
+use config::Config;
+use lsp_data::{BuildOutcome, DocumentPaths, NotificationMessage, NotificationParams, ServerEvent};
+use lsp_data::{DiagnosticRelatedInformation, RlsDiagnostic, RlsPublishDiagnosticsParams};
+use lsp_data::{DiagnosticsSummary, FileDiagnostics, SeverityCounts};
+use lsp_data::{DiagnosticsBeginParams, DiagnosticsEndParams};
 use lsp_data::{NOTIFICATION_DIAGNOSTICS_BEGIN, NOTIFICATION_DIAGNOSTICS_END};
+use paths;
 use server::Output;
+use vfs::Vfs;
 use CRATE_BLACKLIST;
-use Span;
 
 use analysis::AnalysisHost;
 use data::Analysis;
-use ls_types::{self, Diagnostic, Range, DiagnosticSeverity, NumberOrString};
-use serde_json;
-use span::compiler::DiagnosticSpan;
-use url::Url;
+use ls_types::{self, Diagnostic, DiagnosticSeverity, Location, NumberOrString};
 
 
-pub type BuildResults = HashMap<PathBuf, Vec<(Diagnostic, Vec<Suggestion>)>>;
+pub type BuildResults = HashMap<PathBuf, Vec<(RlsDiagnostic, Vec<Suggestion>)>>;
 
+#[derive(Clone)]
 pub struct PostBuildHandler<O: Output> {
     pub analysis: Arc<AnalysisHost>,
     pub previous_build_results: Arc<Mutex<BuildResults>>,
+    /// See `actions::borrows`.
+    pub borrow_facts: Arc<BorrowFacts>,
+    /// Set if unused dependencies are reported, see `actions::used_crates`.
+    pub used_crates: Option<Arc<UsedCrates>>,
+    /// See `actions::visibility`.
+    pub crate_files: Arc<CrateFiles>,
+    /// Which crates' analysis data is kept within the `memory_budget`.
+    pub crate_uses: Arc<CrateUses>,
+    /// See `attributes::DeriveMacros`.
+    pub derive_macros: Arc<DeriveMacros>,
+    pub lib_names: Arc<LibNames>,
     pub project_path: PathBuf,
+    /// See `memory::analysis_dir`.
+    pub analysis_dir: PathBuf,
     pub out: O,
-    pub show_warnings: bool,
+    pub session_id: u64,
+    pub started: Instant,
+    pub status: Arc<Mutex<StatusTracker>>,
+    /// The results are handled on these, see `actions::workers`.
+    pub threads: Arc<SerialThreads>,
+    /// Keeps the analysis data of the build, see `ingest::UnsavedAnalysis`.
+    pub unsaved_analysis: Arc<UnsavedAnalysis>,
+    pub events: Arc<EventStream>,
+    /// Set if the client shows work done progress, see `actions::build_progress`.
+    pub build_progress: Option<Arc<BuildProgressReporter>>,
+    pub diagnostic_filter: DiagnosticFilter,
     pub use_black_list: bool,
+    /// In bytes.
+    pub memory_budget: Option<u64>,
+    /// See `PerformanceProfile::LowPower`.
+    pub low_power: bool,
+    pub diagnostics_providers: Arc<Vec<Box<DiagnosticsProvider>>>,
+    pub positions: PositionConverter,
+    pub documents: Arc<DocumentPaths>,
+    pub symbol_index: Arc<SymbolIndex>,
+    pub vfs: Arc<Vfs>,
+    pub import_policy: ImportPolicy,
+    /// The client shows the related information of diagnostics, otherwise it
+    /// is added to their message.
+    pub related_information: bool,
+    /// The client renders diagnostic tags, see `diagnostic_tags`.
+    pub diagnostic_tags: bool,
+    /// Set to drop all analysis data on the next reload, see
+    /// `ActionContext::restart`.
+    pub stale_analysis: Arc<AtomicBool>,
+    /// The restart of the context the build was started in, and the current
+    /// one. Results of the builds of earlier ones are dropped, see
+    /// `ActionContext::restart`.
+    pub generation: usize,
+    pub current_generation: Arc<AtomicUsize>,
 }
 
 impl<O: Output> PostBuildHandler<O> {
     pub fn handle(self, result: BuildResult) {
+        // Nothing is compiling any more.
+        if let Some(ref build_progress) = self.build_progress {
+            build_progress.finish(&self.out);
+        }
+        if self.is_outdated() {
+            debug!("dropping the results of build {} of a previous session", self.session_id);
+            return;
+        }
+
         // We use `rustDocument` document here since these notifications are
         // custom to the RLS and not part of the LS protocol.
         self.out.notify(NotificationMessage::new(
             NOTIFICATION_DIAGNOSTICS_BEGIN,
-            None,
+            Some(NotificationParams::DiagnosticsBegin(DiagnosticsBeginParams {
+                session_id: self.session_id,
+            })),
         ));
 
+        let failed = match result {
+            BuildResult::Success(..) => Some(false),
+            BuildResult::Squashed => None,
+            BuildResult::Failure(..) | BuildResult::Err => Some(true),
+        };
+        let outcome = match result {
+            BuildResult::Success(..) => BuildOutcome::Success,
+            BuildResult::Failure(..) => BuildOutcome::Failure,
+            BuildResult::Squashed => BuildOutcome::Squashed,
+            BuildResult::Err => BuildOutcome::Error,
+        };
+        let ended = Instant::now();
+        self.events.emit(&self.out, ServerEvent::BuildEnded {
+            session_id: self.session_id,
+            outcome,
+            duration_ms: millis(self.started.elapsed()),
+        });
+
         match result {
             BuildResult::Success(messages, new_analysis) |
             BuildResult::Failure(messages, new_analysis) => {
-                thread::spawn(move || {
+                let threads = self.threads.clone();
+                threads.spawn(move || {
                     trace!("build - Success");
 
-                    self.handle_messages(messages);
+                    let (file_count, diagnostic_count) = self.handle_messages(messages, &new_analysis);
+                    if self.low_power {
+                        // Don't keep the client waiting for the indexing.
+                        self.notify_end(file_count, diagnostic_count);
+                    }
 
                     // Handle the analysis data.
                     debug!("reload analysis: {:?}", self.project_path);
-                    if new_analysis.is_empty() {
-                        self.reload_analysis_from_disk();
-                    } else {
-                        self.reload_analysis_from_memory(new_analysis);
+                    let (evicted, changed) = self.evicted_crates();
+                    let mut blacklist: Vec<&str> = evicted.iter().map(|c| &**c).collect();
+                    if self.use_black_list {
+                        blacklist.extend(CRATE_BLACKLIST.iter().cloned());
                     }
+                    let hard_reload = self.stale_analysis.swap(false, Ordering::SeqCst) || changed;
+                    if hard_reload {
+                        // Dropping data is only possible by starting over.
+                        let cwd = ::std::env::current_dir().unwrap();
+                        self.analysis.hard_reload_with_blacklist(&self.project_path, &cwd, &blacklist).unwrap();
+                        self.symbol_index.invalidate_all();
+                    }
+                    if let Some(ref used_crates) = self.used_crates {
+                        self.report_unused_dependencies(used_crates, &new_analysis);
+                    }
+                    let indexed_crates = if new_analysis.is_empty() {
+                        self.reload_analysis_from_disk(&blacklist, hard_reload)
+                    } else {
+                        self.unsaved_analysis.record(&new_analysis);
+                        if hard_reload {
+                            // rls-analysis read the data without normalizing
+                            // its paths.
+                            self.reload_analysis_from_disk(&blacklist, true);
+                        }
+                        self.reload_analysis_from_memory(new_analysis, &blacklist)
+                    };
+                    self.events.emit(&self.out, ServerEvent::IndexLoaded {
+                        session_id: self.session_id,
+                        indexed_crates,
+                        duration_ms: millis(ended.elapsed()),
+                    });
+                    let source_files = collect_source_files(&self.project_path);
+                    let indexed_files = self.symbol_index.update(&self.analysis, &source_files);
+                    debug!("indexed the symbols of {} files", indexed_files);
+                    self.events.emit(&self.out, ServerEvent::Cache {
+                        cache: "symbolIndex",
+                        hits: source_files.len() - indexed_files,
+                        misses: indexed_files,
+                    });
 
-                    self.out.notify(NotificationMessage::new(
-                        NOTIFICATION_DIAGNOSTICS_END,
-                        None,
-                    ));
+                    let mut status = self.status.lock().unwrap();
+                    status.analysis_reloaded(indexed_crates, evicted, self.memory_budget);
+                    status.build_finished(failed);
+                    // The analysis changed, so always notify.
+                    status.notify(&self.out);
+                    // Still holding the lock, so that nobody (e.g. shutting
+                    // down) sees the build finished before the client does.
+                    if !self.low_power {
+                        self.notify_end(file_count, diagnostic_count);
+                    }
                 });
             }
             BuildResult::Squashed => {
                 trace!("build - Squashed");
-                self.out.notify(NotificationMessage::new(
-                    NOTIFICATION_DIAGNOSTICS_END,
-                    None,
-                ));
+                self.finish_build(failed);
+                self.notify_end(0, 0);
             },
             BuildResult::Err => {
                 trace!("build - Error");
-                self.out.notify(NotificationMessage::new(
-                    NOTIFICATION_DIAGNOSTICS_END,
-                    None,
-                ));
+                self.finish_build(failed);
+                self.notify_end(0, 0);
             },
         }
     }
 
-    fn handle_messages(&self, messages: Vec<String>) {
+    fn finish_build(&self, failed: Option<bool>) {
+        let mut status = self.status.lock().unwrap();
+        if status.build_finished(failed) {
+            status.notify(&self.out);
+        }
+    }
+
+    fn notify_end(&self, file_count: usize, diagnostic_count: usize) {
+        self.out.notify(NotificationMessage::new(
+            NOTIFICATION_DIAGNOSTICS_END,
+            Some(NotificationParams::DiagnosticsEnd(DiagnosticsEndParams {
+                session_id: self.session_id,
+                file_count,
+                diagnostic_count,
+                duration_ms: millis(self.started.elapsed()),
+            })),
+        ));
+    }
+
+    // Returns the number of files with diagnostics and the total number of
+    // diagnostics. `analysis` is that of the crates the build compiled, if it
+    // was kept in memory.
+    fn handle_messages(&self, messages: Vec<String>, analysis: &[Analysis]) -> (usize, usize) {
         // These notifications will include empty sets of errors for files
         // which had errors, but now don't. This instructs the IDE to clear
         // errors for those files.
         let mut results = self.previous_build_results.lock().unwrap();
-        // We must not clear the hashmap, just the values in each list.
-        // This allows us to save allocated before memory.
+        // We must not clear the hashmap, just the values in each list, so
+        // files which no longer have any are cleared in the client.
         for v in &mut results.values_mut() {
             v.clear();
         }
 
-        for msg in &messages {
-            if let Some(FileDiagnostic { file_path, diagnostic, suggestions }) = parse_diagnostics(msg) {
-                results.entry(file_path).or_insert_with(Vec::new).push((diagnostic, suggestions));
+        let mut paths = PathResolver::new(&self.project_path);
+        results.extend(self.diagnostics(&messages, false, &mut paths));
+
+        // Without the analysis data, which crates were compiled isn't known.
+        let compiled: Vec<PathBuf> = analysis.iter()
+            .filter_map(|a| a.prelude.as_ref())
+            .map(|prelude| paths.resolve(Path::new(&prelude.crate_root)))
+            .collect();
+        let compiled = if compiled.is_empty() { None } else { Some(&compiled[..]) };
+        self.borrow_facts.record(borrow_facts(&messages, &mut paths), compiled, &self.crate_files);
+
+        emit_notifications(&results, &self.diagnostic_filter, &self.documents, &self.out);
+        // The files left without diagnostics have just been cleared, there is
+        // no need to publish for them again (they may not even exist anymore).
+        results.retain(|_, v| !v.is_empty());
+
+        let file_count = results.values().filter(|v| !v.is_empty()).count();
+        let diagnostic_count = results.values().map(|v| v.len()).sum();
+        (file_count, diagnostic_count)
+    }
+
+    // Whether the context was restarted since the build started.
+    fn is_outdated(&self) -> bool {
+        self.current_generation.load(Ordering::SeqCst) != self.generation
+    }
+
+    /// Publishes the diagnostics of a build which isn't finished yet, see
+    /// `BuildQueue::request_build`. Only the files with diagnostics and the
+    /// `edited` files are published, the others keep theirs until the build
+    /// finishes.
+    pub fn handle_early_messages(&self, messages: Vec<String>, edited: &[PathBuf]) {
+        if self.is_outdated() {
+            return;
+        }
+        let mut paths = PathResolver::new(&self.project_path);
+        let mut published = self.diagnostics(&messages, true, &mut paths);
+        for file in edited {
+            published.entry(file.clone()).or_insert_with(Vec::new);
+        }
+
+        let mut results = self.previous_build_results.lock().unwrap();
+        emit_notifications(&published, &self.diagnostic_filter, &self.documents, &self.out);
+        for (file_path, diagnostics) in published {
+            if diagnostics.is_empty() {
+                results.remove(&file_path);
+            } else {
+                results.insert(file_path, diagnostics);
             }
         }
+    }
 
-        emit_notifications(&results, self.show_warnings, &self.out);
+    // The diagnostics of the compiler `messages`, by file. `early` if the build
+    // isn't finished yet, see `DiagnosticsProvider::provide_early`.
+    fn diagnostics(&self, messages: &[String], early: bool, paths: &mut PathResolver) -> BuildResults {
+        let mut results = HashMap::new();
+        for provider in self.diagnostics_providers.iter() {
+            trace!("collecting diagnostics from `{}`", provider.name());
+            let diagnostics = if early {
+                provider.provide_early(&self.project_path, messages)
+            } else {
+                provider.provide(&self.project_path, messages)
+            };
+            for FileDiagnostic { file_path, mut diagnostic, mut suggestions, related } in diagnostics {
+                let file_path = paths.resolve(&file_path);
+                diagnostic.range = self.positions.char_range_to_client(&file_path, diagnostic.range);
+                for suggestion in &mut suggestions {
+                    self.place_import(&file_path, diagnostic.range.start.line, suggestion);
+                    suggestion.range = self.positions.char_range_to_client(&file_path, suggestion.range);
+                }
+                let mut diagnostic = self.add_related_information(diagnostic, related, paths);
+                if self.diagnostic_tags {
+                    diagnostic.tags = diagnostic_tags(&diagnostic.diagnostic);
+                }
+                merge_diagnostic(results.entry(file_path).or_insert_with(Vec::new), diagnostic, suggestions);
+            }
+        }
+        results
     }
 
-    fn reload_analysis_from_disk(&self) {
-        let cwd = ::std::env::current_dir().unwrap();
-        if self.use_black_list {
-            self.analysis.reload_with_blacklist(&self.project_path, &cwd, &CRATE_BLACKLIST).unwrap();
-        } else {
-            self.analysis.reload(&self.project_path, &cwd).unwrap();
+    // Attaches the related information to the diagnostic if the client shows
+    // it, otherwise appends it to the message as `note (file:line:column)`.
+    fn add_related_information(&self,
+                               mut diagnostic: Diagnostic,
+                               related: Vec<RelatedInformation>,
+                               paths: &mut PathResolver)
+                               -> RlsDiagnostic {
+        let mut related_information = vec![];
+        for RelatedInformation { file_path, range, message } in related {
+            let file_path = paths.resolve(&file_path);
+            let range = self.positions.char_range_to_client(&file_path, range);
+            let uri = if self.related_information {
+                self.documents.uri(&file_path).ok()
+            } else {
+                None
+            };
+            match uri {
+                Some(uri) => related_information.push(DiagnosticRelatedInformation {
+                    location: Location { uri, range },
+                    message,
+                }),
+                None => {
+                    let shown_path = file_path.strip_prefix(&self.project_path).unwrap_or(&file_path);
+                    diagnostic.message.push_str(&format!("\n{} ({}:{}:{})",
+                                                         message,
+                                                         shown_path.display(),
+                                                         range.start.line + 1,
+                                                         range.start.character + 1));
+                }
+            }
+        }
+        RlsDiagnostic {
+            diagnostic,
+            related_information,
+            tags: vec![],
         }
     }
 
-    fn reload_analysis_from_memory(&self, analysis: Vec<Analysis>) {
-        let cwd = ::std::env::current_dir().unwrap();
-        if self.use_black_list {
-            self.analysis.reload_from_analysis(analysis, &self.project_path, &cwd, &CRATE_BLACKLIST).unwrap();
-        } else {
-            self.analysis.reload_from_analysis(analysis, &self.project_path, &cwd, &[]).unwrap();
+    // Moves a suggested import (of an item used on line `used_at`) to where
+    // the import policy puts it.
+    fn place_import(&self, file_path: &Path, used_at: u64, suggestion: &mut Suggestion) {
+        let edit = match imported_path(&suggestion.new_text) {
+            Some(path) => self.vfs.with_file(file_path, |text| import_edit(text, path, used_at as usize, &self.import_policy)),
+            None => return,
+        };
+        if let Ok(edit) = edit {
+            suggestion.range = edit.range;
+            suggestion.new_text = edit.new_text;
         }
     }
-}
 
-#[derive(Debug)]
-pub struct Suggestion {
-    pub range: Range,
-    pub new_text: String,
-    pub label: String,
-}
+    // Decides which crates to leave out of the analysis data to stay within
+    // the memory budget (see the `memory` module). Returns them and whether
+    // they changed since the last reload.
+    fn evicted_crates(&self) -> (Vec<String>, bool) {
+        let previous = self.status.lock().unwrap().evicted_crates().to_vec();
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return (vec![], !previous.is_empty()),
+        };
 
-#[derive(Debug)]
-struct FileDiagnostic {
-    file_path: PathBuf,
-    diagnostic: Diagnostic,
-    suggestions: Vec<Suggestion>,
-}
+        let files = analysis_files(&self.analysis_dir);
+        let loaded = loaded_size(&files, &previous);
+        let total = loaded_size(&files, &[]);
+        let evicted = if total <= budget {
+            vec![]
+        } else if loaded > budget || loaded < budget / 10 * 8 {
+            // Too much data, or enough room for some of the dropped crates.
+            let evicted = crates_to_evict(files, budget, |name| self.crate_uses.last_used(name));
+            info!("analysis data ({} bytes) exceeds the budget ({} bytes), dropping that of {:?}",
+                  total, budget, evicted);
+            evicted
+        } else {
+            previous.clone()
+        };
 
-#[derive(Debug, Deserialize)]
-struct CompilerMessage {
-    message: String,
-    code: Option<CompilerMessageCode>,
-    level: String,
-    spans: Vec<DiagnosticSpan>,
-    children: Vec<CompilerMessage>,
-}
+        let changed = evicted != previous;
+        (evicted, changed)
+    }
+
+    // Records the crates used by the crates just built, and publishes the
+    // diagnostics of the manifests of their packages again.
+    fn report_unused_dependencies(&self, used_crates: &UsedCrates, analysis: &[Analysis]) {
+        let mut paths = PathResolver::new(&self.project_path);
+        for package_dir in used_crates.record(analysis, &mut paths) {
+            let manifest = package_dir.join("Cargo.toml");
+            let mut text = String::new();
+            if File::open(&manifest).and_then(|mut file| file.read_to_string(&mut text)).is_err() {
+                continue;
+            }
+            let used = used_crates.package_uses(&package_dir);
+            let diagnostics = manifest_diagnostics(&text, &manifest, used.as_ref(), &self.lib_names, &self.positions,
+                                                   self.diagnostic_tags);
+            publish(&manifest, diagnostics, &self.documents, &self.out);
+        }
+    }
 
-#[derive(Debug, Deserialize)]
-struct CompilerMessageCode {
-    code: String
+    // Both reload functions return the number of crates whose analysis data
+    // was loaded.
+    // Reads the data of the crates built since the last reload (of every
+    // crate if `everything`) from disk. The data goes through
+    // `reload_analysis_from_memory` rather than being read by rls-analysis,
+    // which reads files in one thread and doesn't normalize paths.
+    fn reload_analysis_from_disk(&self, blacklist: &[&str], everything: bool) -> usize {
+        let since = if everything {
+            None
+        } else {
+            self.status.lock().unwrap().last_analysis_time()
+        };
+        let files: Vec<_> = analysis_files(&self.analysis_dir)
+            .into_iter()
+            .filter(|f| !blacklist.contains(&&*f.crate_name))
+            .collect();
+        let crate_count = files.len();
+        let changed: Vec<_> = files.into_iter()
+            .filter(|f| since.map_or(true, |since| f.modified >= since))
+            .collect();
+
+        // See the `ingest` module.
+        let analysis = ingest::read_analysis(&changed, !self.low_power, |done, total| {
+            let mut status = self.status.lock().unwrap();
+            status.indexing_progress(done, total);
+            status.notify(&self.out);
+        });
+        self.reload_analysis_from_memory(analysis, blacklist);
+        crate_count
+    }
+
+    fn reload_analysis_from_memory(&self, mut analysis: Vec<Analysis>, blacklist: &[&str]) -> usize {
+        let cwd = ::std::env::current_dir().unwrap();
+        let indexed_crates = analysis.len();
+        for crate_analysis in &mut analysis {
+            paths::normalize_analysis(crate_analysis);
+        }
+        self.crate_files.record(&analysis, &mut PathResolver::new(&self.project_path));
+        self.derive_macros.record(&analysis);
+        self.analysis.reload_from_analysis(analysis, &self.project_path, &cwd, blacklist).unwrap();
+        indexed_crates
+    }
 }
 
-fn parse_diagnostics(message: &str) -> Option<FileDiagnostic> {
-    let message = match serde_json::from_str::<CompilerMessage>(message) {
-        Ok(m) => m,
-        Err(e) => {
-            debug!("build error {:?}", e);
-            debug!("from {}", message);
-            return None;
+/// Adds a diagnostic to those of a file. Files compiled more than once (e.g.
+/// for both the lib and the test target) get the same diagnostics each time:
+/// one with the range and message of a diagnostic already there is merged into
+/// it instead, keeping the more severe severity and the suggestions and related
+/// information of both.
+pub fn merge_diagnostic(diagnostics: &mut Vec<(RlsDiagnostic, Vec<Suggestion>)>,
+                        diagnostic: RlsDiagnostic,
+                        suggestions: Vec<Suggestion>) {
+    let existing = diagnostics.iter().position(|&(ref d, _)| {
+        d.diagnostic.range == diagnostic.diagnostic.range && d.diagnostic.message == diagnostic.diagnostic.message
+    });
+    let index = match existing {
+        Some(index) => index,
+        None => {
+            diagnostics.push((diagnostic, suggestions));
+            return;
         }
     };
+    let (ref mut merged, ref mut merged_suggestions) = diagnostics[index];
 
-    if message.spans.is_empty() {
-        return None;
+    if severity_rank(diagnostic.diagnostic.severity) < severity_rank(merged.diagnostic.severity) {
+        merged.diagnostic.severity = diagnostic.diagnostic.severity;
+    }
+    for suggestion in suggestions {
+        if !merged_suggestions.contains(&suggestion) {
+            merged_suggestions.push(suggestion);
+        }
+    }
+    for related in diagnostic.related_information {
+        if !merged.related_information.contains(&related) {
+            merged.related_information.push(related);
+        }
     }
+}
 
-    let primary_span = primary_span(&message);
-    let suggestions = make_suggestions(message.children, &primary_span.file);
-
-    let diagnostic = Diagnostic {
-        range: ls_util::rls_to_range(primary_span.range),
-        severity: Some(severity(&message.level)),
-        code: Some(NumberOrString::String(match message.code {
-            Some(c) => c.code.clone(),
-            None => String::new(),
-        })),
-        source: Some("rustc".into()),
-        message: message.message,
-    };
+// Lower is more severe, as in the protocol.
+fn severity_rank(severity: Option<DiagnosticSeverity>) -> u8 {
+    match severity {
+        Some(DiagnosticSeverity::Error) => 1,
+        Some(DiagnosticSeverity::Warning) => 2,
+        Some(DiagnosticSeverity::Information) => 3,
+        Some(DiagnosticSeverity::Hint) => 4,
+        None => 5,
+    }
+}
 
-    Some(FileDiagnostic {
-        file_path: primary_span.file,
-        diagnostic: diagnostic,
-        suggestions: suggestions,
-    })
+/// Which diagnostics are published, and with which severity, as configured.
+/// Applied when publishing, so changing these options doesn't need a rebuild.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticFilter {
+    pub show_warnings: bool,
+    /// Codes of diagnostics (lint names, e.g. `dead_code`) not to publish.
+    pub ignored_lints: Vec<String>,
+    /// Publish warnings as hints.
+    pub warnings_as_hints: bool,
 }
 
-fn severity(level: &str) -> DiagnosticSeverity {
-    if level == "error" {
-        DiagnosticSeverity::Error
-    } else {
-        DiagnosticSeverity::Warning
+impl DiagnosticFilter {
+    /// The filter set up by `config`.
+    pub fn from_config(config: &Config) -> DiagnosticFilter {
+        DiagnosticFilter {
+            show_warnings: config.show_warnings,
+            ignored_lints: config.ignored_lints.clone(),
+            warnings_as_hints: config.warnings_as_hints,
+        }
     }
-}
 
-fn make_suggestions(children: Vec<CompilerMessage>, file: &Path) -> Vec<Suggestion> {
-    let mut suggestions = vec![];
-    for c in children {
-        for sp in c.spans {
-            let span = sp.rls_span().zero_indexed();
-            if span.file == file {
-                if let Some(s) = sp.suggested_replacement {
-                    let suggestion = Suggestion {
-                        new_text: s.clone(),
-                        range: ls_util::rls_to_range(span.range),
-                        label: format!("{}: `{}`", c.message, s),
-                    };
-                    suggestions.push(suggestion);
-                }
+    /// The diagnostic as published, `None` if it is filtered out.
+    pub fn apply(&self, diagnostic: &RlsDiagnostic) -> Option<RlsDiagnostic> {
+        if let Some(NumberOrString::String(ref code)) = diagnostic.diagnostic.code {
+            if self.ignored_lints.contains(code) {
+                return None;
             }
         }
+        if diagnostic.diagnostic.severity != Some(DiagnosticSeverity::Warning) {
+            return Some(diagnostic.clone());
+        }
+        if !self.show_warnings {
+            return None;
+        }
+        let mut diagnostic = diagnostic.clone();
+        if self.warnings_as_hints {
+            diagnostic.diagnostic.severity = Some(DiagnosticSeverity::Hint);
+        }
+        Some(diagnostic)
     }
-    suggestions
-}
-
-fn primary_span(message: &CompilerMessage) -> Span {
-    let primary = message.spans
-        .iter()
-        .filter(|x| x.is_primary)
-        .next()
-        .unwrap()
-        .clone();
-    primary.rls_span().zero_indexed()
 }
 
-fn emit_notifications<O: Output>(
+/// Publish the given diagnostics to the client.
+pub fn emit_notifications<O: Output>(
     build_results: &BuildResults,
-    show_warnings: bool,
+    filter: &DiagnosticFilter,
+    documents: &DocumentPaths,
     out: &O,
 ) {
     let cwd = ::std::env::current_dir().unwrap();
 
     for (path, diagnostics) in build_results {
-        let params = PublishDiagnosticsParams {
-            uri: Url::from_file_path(cwd.join(path)).unwrap(),
-            diagnostics: diagnostics.iter()
-                .filter_map(|&(ref d, _)| {
-                    if show_warnings || d.severity != Some(DiagnosticSeverity::Warning) {
-                        Some(d.clone())
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
+        let diagnostics = diagnostics.iter().filter_map(|&(ref d, _)| filter.apply(d)).collect();
+        publish(&cwd.join(path), diagnostics, documents, out);
+    }
+}
+
+/// The diagnostics as published, with their number per severity, for
+/// `rls/diagnostics`.
+pub fn diagnostics_summary(build_results: &BuildResults,
+                           filter: &DiagnosticFilter,
+                           documents: &DocumentPaths)
+                           -> DiagnosticsSummary {
+    let cwd = ::std::env::current_dir().unwrap();
+    let mut paths: Vec<&PathBuf> = build_results.keys().collect();
+    paths.sort();
+
+    let mut counts = SeverityCounts::default();
+    let mut files = vec![];
+    for path in paths {
+        let diagnostics: Vec<_> = build_results[path].iter().filter_map(|&(ref d, _)| filter.apply(d)).collect();
+        if diagnostics.is_empty() {
+            continue;
+        }
+        // Paths which aren't absolute (e.g. of macro spans) have no URI.
+        let uri = match documents.uri(&cwd.join(path)).ok() {
+            Some(uri) => uri,
+            None => continue,
         };
+        let mut file_counts = SeverityCounts::default();
+        for d in &diagnostics {
+            file_counts.add(d.diagnostic.severity);
+            counts.add(d.diagnostic.severity);
+        }
+        files.push(FileDiagnostics {
+            uri,
+            counts: file_counts,
+            diagnostics,
+        });
+    }
+    DiagnosticsSummary { counts, files }
+}
 
-        out.notify(NotificationMessage::new(
-            ls_types::NOTIFICATION__PublishDiagnostics,
-            Some(params),
-        ));
+/// Clear the diagnostics of files which were deleted, given the deleted files
+/// (or directories).
+pub fn clear_diagnostics<O: Output>(build_results: &mut BuildResults,
+                                    deleted: &[PathBuf],
+                                    documents: &DocumentPaths,
+                                    out: &O) {
+    let cleared: Vec<PathBuf> = build_results.keys()
+        .filter(|path| deleted.iter().any(|d| path.starts_with(d)))
+        .cloned()
+        .collect();
+    for path in cleared {
+        build_results.remove(&path);
+        publish(&path, vec![], documents, out);
+    }
+}
+
+/// Publish the diagnostics of `path`, replacing the ones published before.
+/// Those of a path without a URI (e.g. a relative one of a macro span) are
+/// dropped.
+pub fn publish<O: Output>(path: &Path, diagnostics: Vec<RlsDiagnostic>, documents: &DocumentPaths, out: &O) {
+    let uri = match documents.uri(path).ok() {
+        Some(uri) => uri,
+        None => {
+            debug!("publish: no URI for {:?}", path);
+            return;
+        }
+    };
+    let params = RlsPublishDiagnosticsParams { uri, diagnostics };
+
+    out.notify(NotificationMessage::new(
+        ls_types::NOTIFICATION__PublishDiagnostics,
+        Some(NotificationParams::PublishDiagnostics(params)),
+    ));
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ls_types::{Position, Range};
+    use paths::PathMappings;
+    use test::harness::RecordOutput;
+
+    fn diagnostic(line: u64, severity: DiagnosticSeverity, message: &str) -> RlsDiagnostic {
+        RlsDiagnostic::new(Diagnostic {
+            range: Range::new(Position::new(line, 0), Position::new(line, 3)),
+            severity: Some(severity),
+            code: None,
+            source: Some("rustc".to_owned()),
+            message: message.to_owned(),
+        })
+    }
+
+    fn suggestion(new_text: &str) -> Suggestion {
+        Suggestion {
+            range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            new_text: new_text.to_owned(),
+            label: new_text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_merge_diagnostic() {
+        let mut diagnostics = vec![];
+        merge_diagnostic(&mut diagnostics, diagnostic(1, DiagnosticSeverity::Warning, "unused"), vec![suggestion("a")]);
+        // The same diagnostic from the test target.
+        merge_diagnostic(&mut diagnostics,
+                         diagnostic(1, DiagnosticSeverity::Error, "unused"),
+                         vec![suggestion("a"), suggestion("b")]);
+        merge_diagnostic(&mut diagnostics, diagnostic(2, DiagnosticSeverity::Warning, "unused"), vec![]);
+        merge_diagnostic(&mut diagnostics, diagnostic(1, DiagnosticSeverity::Warning, "other"), vec![]);
+
+        assert_eq!(diagnostics.len(), 3);
+        let (ref merged, ref suggestions) = diagnostics[0];
+        assert_eq!(merged.diagnostic.severity, Some(DiagnosticSeverity::Error));
+        assert_eq!(suggestions.iter().map(|s| &*s.new_text).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_diagnostic_filter() {
+        let mut lint = diagnostic(1, DiagnosticSeverity::Warning, "unused variable: `x`");
+        lint.diagnostic.code = Some(NumberOrString::String("unused_variables".to_owned()));
+        let error = diagnostic(2, DiagnosticSeverity::Error, "mismatched types");
+
+        let mut filter = DiagnosticFilter {
+            show_warnings: true,
+            ignored_lints: vec![],
+            warnings_as_hints: true,
+        };
+        assert_eq!(filter.apply(&lint).unwrap().diagnostic.severity, Some(DiagnosticSeverity::Hint));
+        assert_eq!(filter.apply(&error).unwrap().diagnostic.severity, Some(DiagnosticSeverity::Error));
+
+        filter.ignored_lints = vec!["unused_variables".to_owned()];
+        assert!(filter.apply(&lint).is_none());
+
+        filter.ignored_lints = vec![];
+        filter.show_warnings = false;
+        assert!(filter.apply(&lint).is_none());
+        assert!(filter.apply(&error).is_some());
+    }
+
+    #[test]
+    fn test_clear_diagnostics() {
+        let out = RecordOutput::new();
+        let mut results = BuildResults::new();
+        results.insert(PathBuf::from("/project/src/a.rs"), vec![(diagnostic(1, DiagnosticSeverity::Error, "a"), vec![])]);
+        results.insert(PathBuf::from("/project/src/b/mod.rs"), vec![]);
+        results.insert(PathBuf::from("/project/src/bc.rs"), vec![]);
+
+        let documents = DocumentPaths::new(PathMappings::default());
+        clear_diagnostics(&mut results,
+                          &[PathBuf::from("/project/src/a.rs"), PathBuf::from("/project/src/b")],
+                          &documents,
+                          &out);
+
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec![Path::new("/project/src/bc.rs")]);
+        let sent = out.output.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|n| n.contains(r#""diagnostics":[]"#)));
+    }
+
+    #[test]
+    fn test_publish_without_uri() {
+        let out = RecordOutput::new();
+        let documents = DocumentPaths::new(PathMappings::default());
+        publish(Path::new("<std macros>"), vec![diagnostic(1, DiagnosticSeverity::Error, "a")], &documents, &out);
+        assert!(out.output.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diagnostics_summary() {
+        let mut results = BuildResults::new();
+        results.insert(PathBuf::from("/project/src/b.rs"), vec![
+            (diagnostic(1, DiagnosticSeverity::Error, "a"), vec![]),
+            (diagnostic(2, DiagnosticSeverity::Warning, "b"), vec![]),
+        ]);
+        results.insert(PathBuf::from("/project/src/a.rs"), vec![(diagnostic(1, DiagnosticSeverity::Warning, "c"), vec![])]);
+        results.insert(PathBuf::from("/project/src/c.rs"), vec![]);
+        let filter = DiagnosticFilter {
+            show_warnings: true,
+            ignored_lints: vec![],
+            warnings_as_hints: false,
+        };
+
+        let summary = diagnostics_summary(&results, &filter, &DocumentPaths::new(PathMappings::default()));
+        assert_eq!(summary.counts, SeverityCounts { error: 1, warning: 2, information: 0, hint: 0 });
+        let files: Vec<_> = summary.files.iter().map(|f| (f.uri.path(), f.counts.error, f.counts.warning)).collect();
+        assert_eq!(files, vec![("/project/src/a.rs", 0, 1), ("/project/src/b.rs", 1, 1)]);
     }
 }
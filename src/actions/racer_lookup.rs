@@ -0,0 +1,392 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Lookups answered by racer, which parses the source on the fly instead of
+//! using the analysis data: completions, and definitions where there is no
+//! analysis data (yet).
+//!
+//! Racer is only compiled in with the `racer` feature. Without it nothing is
+//! ever found here, completions still come from the other sources (see
+//! `requests::Completion`).
+//!
+//! The lookups of a context run on one thread, which keeps the files racer
+//! read and parsed between them, see `Racer`.
+
+use actions::positions::PositionConverter;
+use lsp_data::{Location, RankedCompletion};
+use serde_json::Value;
+use span;
+use vfs::Vfs;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "racer")]
+use actions::cargo_doc;
+#[cfg(feature = "racer")]
+use actions::workers::WorkerThread;
+#[cfg(feature = "racer")]
+use lsp_data::{completion_item_from_racer_match, completion_relevance};
+#[cfg(feature = "racer")]
+use paths;
+#[cfg(feature = "racer")]
+use racer;
+#[cfg(feature = "racer")]
+use Span;
+
+#[cfg(feature = "racer")]
+use std::collections::{HashMap, HashSet, VecDeque};
+#[cfg(feature = "racer")]
+use std::io;
+#[cfg(feature = "racer")]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "racer")]
+use std::sync::{mpsc, Mutex};
+#[cfg(feature = "racer")]
+use std::sync::mpsc::TryRecvError;
+
+/// Runs the lookups of one context (so a restart starts with fresh caches).
+///
+/// Racer's caches can't be shared between threads, so the lookups run on one
+/// thread which keeps them. It is started by the first lookup (or after it
+/// died) and reads the files through the context's VFS.
+#[cfg(feature = "racer")]
+pub struct Racer {
+    vfs: Arc<Vfs>,
+    // Files changed in the VFS since racer may have read them, see
+    // `refresh_changed_files`.
+    changed: Arc<Mutex<HashSet<PathBuf>>>,
+    // The sender of the lookups to the thread and the thread, `None` until
+    // the first lookup.
+    thread: Mutex<Option<(mpsc::Sender<Lookup>, WorkerThread)>>,
+}
+
+/// Without racer there is nothing to run.
+#[cfg(not(feature = "racer"))]
+pub struct Racer;
+
+// A lookup to run on the racer thread.
+#[cfg(feature = "racer")]
+struct Lookup {
+    run: Box<FnMut(&racer::Session) + Send>,
+    // Warming up waits for the other lookups, see `warm_up`.
+    warm_up: bool,
+}
+
+#[cfg(feature = "racer")]
+impl Racer {
+    /// Construct for the files of `vfs`, without starting the thread yet.
+    pub fn new(vfs: Arc<Vfs>) -> Racer {
+        Racer {
+            vfs,
+            changed: Arc::new(Mutex::new(HashSet::new())),
+            thread: Mutex::new(None),
+        }
+    }
+
+    /// Record that `path` changed in the VFS, so that racer reads it again
+    /// before the next lookup if it read it before.
+    pub fn file_changed(&self, path: &Path) {
+        self.changed.lock().unwrap().insert(path.to_owned());
+    }
+
+    /// Stops the thread once the running lookup is done, the pending warm up
+    /// is dropped. Waits for it at most `timeout`, returns whether it stopped.
+    pub fn shut_down(&self, timeout: Duration) -> bool {
+        let thread = self.thread.lock().unwrap().take();
+        match thread {
+            Some((sender, thread)) => {
+                drop(sender);
+                thread.join(timeout).is_ok()
+            }
+            None => true,
+        }
+    }
+
+    // Runs `f` on the racer thread and waits for its result, `None` if racer
+    // panicked.
+    fn with_session<F, R>(&self, f: F) -> Option<R>
+        where F: FnOnce(&racer::Session) -> R + Send + 'static,
+              R: Send + 'static
+    {
+        let (result_sender, result_receiver) = mpsc::channel();
+        let mut f = Some(f);
+        self.send(Lookup {
+            run: Box::new(move |session| {
+                if let Some(f) = f.take() {
+                    let _ = result_sender.send(f(session));
+                }
+            }),
+            warm_up: false,
+        });
+        result_receiver.recv().ok()
+    }
+
+    fn send(&self, lookup: Lookup) {
+        let mut thread = self.thread.lock().unwrap();
+        let unsent = match thread.as_ref() {
+            Some(&(ref sender, _)) => sender.send(lookup).err().map(|mpsc::SendError(lookup)| lookup),
+            None => Some(lookup),
+        };
+        if let Some(lookup) = unsent {
+            let (sender, worker) = spawn_racer_thread(self.vfs.clone(), self.changed.clone());
+            if sender.send(lookup).is_ok() {
+                *thread = Some((sender, worker));
+            }
+        }
+    }
+}
+
+/// The completions at `position`, unranked (see `rank_completions`). Their
+/// data is where they are defined, and if `cargo_doc_fallback` is set, what
+/// is needed to look up their docs in the output of `cargo doc`. The docs and
+/// the full signature are only looked up when the client resolves an item.
+#[cfg(feature = "racer")]
+pub fn completions(racer: &Racer,
+                   file_path: PathBuf,
+                   position: span::Position<span::ZeroIndexed>,
+                   cargo_doc_fallback: bool)
+                   -> Vec<RankedCompletion> {
+    racer.with_session(move |session| {
+        let results = racer::complete_from_file(&file_path, racer_location(position), session);
+        results.map(|comp| {
+            let filepath = paths::normalize(&comp.filepath);
+            // Remember where to look for the docs when the item is resolved.
+            let mut data = json!({
+                "file": filepath,
+                "offset": comp.point
+            });
+            if cargo_doc_fallback {
+                if let Some(Value::Object(doc_data)) = cargo_doc::completion_data(&comp) {
+                    if let Value::Object(ref mut data) = data {
+                        data.extend(doc_data);
+                    }
+                }
+            }
+            let relevance = completion_relevance(&comp);
+            let in_document = filepath == file_path;
+            let definition = Some((filepath, comp.point));
+            let mut item = completion_item_from_racer_match(comp);
+            item.data = Some(data);
+            RankedCompletion {
+                item,
+                relevance,
+                in_document,
+                definition,
+            }
+        }).collect()
+    }).unwrap_or_default()
+}
+
+/// The definition of the item at `position`.
+#[cfg(feature = "racer")]
+pub fn definition(racer: &Racer,
+                  file_path: PathBuf,
+                  position: span::Position<span::ZeroIndexed>,
+                  positions: &PositionConverter)
+                  -> Option<Location> {
+    let (file, coord) = racer.with_session(move |session| {
+        let m = racer::find_definition(file_path, racer_location(position), session)?;
+        Some((paths::normalize(&m.filepath), m.coords?))
+    })??;
+    let pos = span::Position::new(span::Row::new_one_indexed(coord.line as u32).zero_indexed(),
+                                  span::Column::new_zero_indexed(coord.column as u32));
+    positions.rls_to_location(&Span::from_positions(pos, pos, file)).ok()
+}
+
+/// The signature and the docs of the item at `position`.
+#[cfg(feature = "racer")]
+pub fn signature_and_docs(racer: &Racer,
+                          file_path: PathBuf,
+                          position: span::Position<span::ZeroIndexed>)
+                          -> Option<(String, String)> {
+    racer.with_session(move |session| {
+        let m = racer::find_definition(file_path, racer_location(position), session)?;
+        Some((m.contextstr.trim().to_owned(), m.docs))
+    })?
+}
+
+/// Has racer read and parse the standard library and the crates with the
+/// given root files, so the first lookups don't wait for that. Doesn't block,
+/// the warm up runs in small steps while there are no other lookups.
+#[cfg(feature = "racer")]
+pub fn warm_up(racer: &Racer, crate_roots: Vec<PathBuf>) {
+    // Paths are resolved relative to a file, any file of the workspace does.
+    let anchor = match crate_roots.first() {
+        Some(root) => root.clone(),
+        None => return,
+    };
+    let crate_paths = crate_roots.into_iter().map(|root| ("self::".to_owned(), root));
+    let std_paths = WARM_UP_PATHS.iter().map(|path| ((*path).to_owned(), anchor.clone()));
+    for (path, file) in crate_paths.chain(std_paths) {
+        racer.send(Lookup {
+            run: Box::new(move |session| {
+                racer::complete_fully_qualified_name(&path[..], &file, session).count();
+            }),
+            warm_up: true,
+        });
+    }
+}
+
+// The modules and types of the standard library most completions start in.
+#[cfg(feature = "racer")]
+const WARM_UP_PATHS: &[&str] = &[
+    "std::",
+    "std::collections::",
+    "std::fmt::",
+    "std::io::",
+    "std::string::String::",
+    "std::vec::Vec::",
+];
+
+#[cfg(feature = "racer")]
+fn spawn_racer_thread(vfs: Arc<Vfs>, changed: Arc<Mutex<HashSet<PathBuf>>>) -> (mpsc::Sender<Lookup>, WorkerThread) {
+    let (sender, receiver) = mpsc::channel::<Lookup>();
+    let worker = WorkerThread::spawn(move || {
+        let loaded = Arc::new(Mutex::new(HashMap::new()));
+        let cache = racer::FileCache::new(VfsLoader { vfs: vfs.clone(), loaded: loaded.clone() });
+        let mut warm_ups = VecDeque::new();
+        loop {
+            // The other lookups go first, warming up only runs when there are
+            // none.
+            let received = match receiver.try_recv() {
+                Ok(lookup) => Some(lookup),
+                Err(TryRecvError::Empty) if warm_ups.is_empty() => match receiver.recv() {
+                    Ok(lookup) => Some(lookup),
+                    Err(_) => return,
+                },
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            };
+            let mut lookup = match received {
+                Some(lookup) => {
+                    if lookup.warm_up {
+                        warm_ups.push_back(lookup);
+                        continue;
+                    }
+                    lookup
+                }
+                None => warm_ups.pop_front().unwrap(),
+            };
+
+            let session = racer::Session::new(&cache);
+            refresh_changed_files(&vfs, &loaded, &changed, &session);
+            // A panic in racer only loses this lookup, not the cache.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| (lookup.run)(&session)));
+        }
+    });
+    (sender, worker)
+}
+
+// Racer keeps files as it first read them, replace those which changed in the
+// VFS since.
+#[cfg(feature = "racer")]
+fn refresh_changed_files(vfs: &Vfs,
+                         loaded: &Mutex<HashMap<PathBuf, PathBuf>>,
+                         changed: &Mutex<HashSet<PathBuf>>,
+                         session: &racer::Session) {
+    let changed: Vec<_> = changed.lock().unwrap().drain().collect();
+    let loaded = loaded.lock().unwrap();
+    for path in changed {
+        let racer_path = match loaded.get(&path) {
+            Some(racer_path) => racer_path.clone(),
+            None => continue,
+        };
+        if let Ok(text) = vfs.with_file(&path, |text| text.to_owned()) {
+            session.cache_file_contents(racer_path, text);
+        }
+    }
+}
+
+/// Where the completion with the given data is defined (file and byte
+/// offset), see `completions`.
+pub fn completion_definition(data: &Value) -> Option<(PathBuf, usize)> {
+    let file = data.get("file")?.as_str()?;
+    let offset = data.get("offset")?.as_u64()?;
+    Some((PathBuf::from(file), offset as usize))
+}
+
+#[cfg(feature = "racer")]
+fn racer_location(pos: span::Position<span::ZeroIndexed>) -> racer::Location {
+    racer::Location::Coords(racer::Coordinate {
+        line: pos.row.one_indexed().0 as usize,
+        column: pos.col.0 as usize,
+    })
+}
+
+// Racer reads files through the VFS, so it sees unsaved changes. What it
+// read is remembered, to read it again when it changes: by the normalized path
+// the VFS knows it by, and the path racer used.
+#[cfg(feature = "racer")]
+struct VfsLoader {
+    vfs: Arc<Vfs>,
+    loaded: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+}
+
+#[cfg(feature = "racer")]
+impl racer::FileLoader for VfsLoader {
+    fn load_file(&self, path: &Path) -> io::Result<String> {
+        let normalized = paths::normalize(path);
+        let text = self.vfs.with_file(&normalized, |text| text.to_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+        self.loaded.lock().unwrap().insert(normalized, path.to_owned());
+        Ok(text)
+    }
+}
+
+#[cfg(not(feature = "racer"))]
+impl Racer {
+    /// Construct without anything to run.
+    pub fn new(_vfs: Arc<Vfs>) -> Racer {
+        Racer
+    }
+
+    /// Without racer there is nothing to read again.
+    pub fn file_changed(&self, _path: &Path) {}
+
+    /// Without racer there is no thread to stop.
+    pub fn shut_down(&self, _timeout: Duration) -> bool {
+        true
+    }
+}
+
+/// Without racer there are no completions.
+#[cfg(not(feature = "racer"))]
+pub fn completions(_racer: &Racer,
+                   _file_path: PathBuf,
+                   _position: span::Position<span::ZeroIndexed>,
+                   _cargo_doc_fallback: bool)
+                   -> Vec<RankedCompletion> {
+    vec![]
+}
+
+/// Without racer only the analysis data knows definitions.
+#[cfg(not(feature = "racer"))]
+pub fn definition(_racer: &Racer,
+                  _file_path: PathBuf,
+                  _position: span::Position<span::ZeroIndexed>,
+                  _positions: &PositionConverter)
+                  -> Option<Location> {
+    None
+}
+
+/// Without racer only the analysis data knows signatures and docs.
+#[cfg(not(feature = "racer"))]
+pub fn signature_and_docs(_racer: &Racer,
+                          _file_path: PathBuf,
+                          _position: span::Position<span::ZeroIndexed>)
+                          -> Option<(String, String)> {
+    None
+}
+
+/// Without racer there is nothing to warm up.
+#[cfg(not(feature = "racer"))]
+pub fn warm_up(_racer: &Racer, _crate_roots: Vec<PathBuf>) {}
@@ -10,31 +10,59 @@
 
 //! Requests that the RLS can respond to.
 
-use actions::ActionContext;
+use actions::{ActionContext, InitActionContext, FileAccess, client, collect_source_files, commands, find_word_at_pos, hover, navigation, package_dir_for_file, racer_lookup, sources};
+use actions::borrows::{self, BorrowFact};
+use actions::cargo_doc;
+use actions::expression_type;
+use actions::imports::{self, ImportPolicy};
+use actions::trait_methods;
+use actions::attributes::{self, DeriveMacros};
+use actions::file_paths;
+use actions::keywords;
+use actions::lifetimes;
+use actions::positions::PositionConverter;
+use actions::manifest;
+use actions::changes::changed_files;
+use actions::symbol_index::{self, IndexedSymbol};
+use actions::line_index::LineIndexCache;
+use actions::pages::ResultPages;
+use actions::post_build::{diagnostics_summary, DiagnosticFilter};
+use analysis;
 use data;
 use url::Url;
-use vfs::FileContents;
-use racer;
+#[cfg(feature = "rustfmt")]
 use rustfmt::{Input as FmtInput, format_input};
+#[cfg(feature = "rustfmt")]
 use rustfmt::file_lines::{Range as RustfmtRange, FileLines};
+use actions::format;
+use actions::line_diff::line_edits;
+use config::find_rustfmt_toml;
+#[cfg(feature = "rustfmt")]
+use config::FmtConfig;
 use serde_json;
 use span;
 use rayon;
+use vfs::Vfs;
+use Span;
 
 use lsp_data;
 use lsp_data::*;
-use server::{Output, Ack, Action, RequestAction, LsState};
-use jsonrpc_core::types::ErrorCode;
+use messages::Message;
+use server::{Output, Ack, Action, RequestAction, LsState, NoParams, NoResponse};
+use jsonrpc_core::types::{Error as RpcError, ErrorCode, Id};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::thread;
 use std::time::{Duration};
 use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// A request for information about a symbol in this workspace.
 pub struct WorkspaceSymbol;
 
 impl<'a> Action<'a> for WorkspaceSymbol {
-    type Params = lsp_data::WorkspaceSymbolParams;
+    type Params = RlsWorkspaceSymbolParams;
     const METHOD: &'static str = "workspace/symbol";
 
     fn new(_: &'a mut LsState) -> Self {
@@ -45,25 +73,70 @@ impl<'a> Action<'a> for WorkspaceSymbol {
 impl<'a> RequestAction<'a> for WorkspaceSymbol {
     type Response = Vec<SymbolInformation>;
 
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
+        let (query, dependencies) = {
+            let config = ctx.config.lock().unwrap();
+            split_symbol_query(&params.query, config.workspace_symbol_dependencies)
+        };
+        // Only needed to find the source of the standard library.
+        let toolchain = ctx.build_queue.toolchain();
+        let config = ctx.config.clone();
+        let symbol_index = ctx.symbol_index.clone();
         let analysis = ctx.analysis.clone();
+        let project_dir = ctx.current_project.clone();
+        let positions = ctx.positions.clone();
+        let responded = Arc::new(AtomicBool::new(false));
+        let mut pages = ResultPages::new(params.partial_result_token, responded.clone(), out);
 
         let receiver = receive_from_thread(move || {
-            let defs = analysis.name_defs(&params.query).unwrap_or_else(|_| vec![]);
-
-            defs.into_iter().map(|d| {
-                SymbolInformation {
-                    name: d.name,
-                    kind:  source_kind_from_def_kind(d.kind),
-                    location: ls_util::rls_to_location(&d.span),
-                    container_name: d.parent.and_then(|id| analysis.get_def(id).ok()).map(|parent| parent.name)
+            // Symbols in files without a URI are left out.
+            let to_information = |s: IndexedSymbol| {
+                let location = positions.rls_to_location(&s.span).ok()?;
+                Some(SymbolInformation {
+                    name: s.name,
+                    kind:  source_kind_from_def_kind(s.kind),
+                    location,
+                    container_name: s.container_name,
+                })
+            };
+            for s in symbol_index.search(&query) {
+                if let Some(information) = to_information(s) {
+                    pages.push(information);
+                }
+            }
+            if dependencies {
+                let sysroot = toolchain.get(&config, &project_dir).sysroot;
+                for mut s in symbol_index::search_all_crates(&analysis, &query) {
+                    let source = sources::locate(&s.span.file, &project_dir, sysroot.as_ref().map(|s| &**s));
+                    match source {
+                        // The symbols of the workspace were found in the index.
+                        sources::SourceFile::Found(ref file) if file.starts_with(&project_dir) => continue,
+                        sources::SourceFile::Found(file) => s.span.file = file,
+                        _ => continue,
+                    }
+                    if let Some(information) = to_information(s) {
+                        pages.push(information);
+                    }
                 }
-            }).collect()
+            }
+            pages.finish()
         });
 
-        Ok(receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
-            .unwrap_or_else(|_| vec![]))
+        let result = receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
+            .unwrap_or_else(|_| vec![]);
+        responded.store(true, Ordering::SeqCst);
+        Ok(result)
+    }
+}
+
+// The query of a `workspace/symbol` request, and whether dependencies are
+// searched too: always if configured, else if the query starts with `#`.
+fn split_symbol_query(query: &str, dependencies: bool) -> (String, bool) {
+    if query.starts_with('#') {
+        (query[1..].trim().to_owned(), true)
+    } else {
+        (query.to_owned(), dependencies)
     }
 }
 
@@ -80,32 +153,87 @@ impl<'a> Action<'a> for Symbols {
 }
 
 impl<'a> RequestAction<'a> for Symbols {
-    type Response = Vec<SymbolInformation>;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    type Response = DocumentSymbolResponse;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "symbols")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "symbols", id, out)?;
 
         let analysis = ctx.analysis.clone();
+        let positions = ctx.positions.clone();
+        let hierarchical = ctx.client.hierarchical_symbols();
 
         let receiver = receive_from_thread(move || {
             let symbols = analysis.symbols(&file_path).unwrap_or_else(|_| vec![]);
 
-            symbols.into_iter().map(|s| {
-                SymbolInformation {
+            if hierarchical {
+                let parents = symbols.iter().map(|s| analysis.get_def(s.id).ok().and_then(|def| def.parent)).collect();
+                let ranges = symbols.iter().map(|s| positions.rls_to_range(&s.span.file, s.span.range)).collect();
+                return DocumentSymbolResponse::Nested(symbol_tree(symbols, parents, ranges));
+            }
+            DocumentSymbolResponse::Flat(symbols.into_iter().filter_map(|s| {
+                Some(SymbolInformation {
+                    location: positions.rls_to_location(&s.span).ok()?,
                     name: s.name,
                     kind: source_kind_from_def_kind(s.kind),
-                    location: ls_util::rls_to_location(&s.span),
                     container_name: None // FIXME: more info could be added here
-                }
-            }).collect()
+                })
+            }).collect())
         });
 
         Ok(receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
-            .unwrap_or_else(|_| vec![]))
+            .unwrap_or_else(|_| DocumentSymbolResponse::Flat(vec![])))
+    }
+}
+
+// Nests the symbols of a document in those of their parents, given the parent
+// and the (client) range of each symbol. Symbols whose parent isn't in the
+// document are roots. The range of a symbol covers its children.
+fn symbol_tree(symbols: Vec<analysis::SymbolResult>,
+               parents: Vec<Option<analysis::Id>>,
+               ranges: Vec<Range>)
+               -> Vec<RlsDocumentSymbol> {
+    let ids: Vec<_> = symbols.iter().map(|s| s.id).collect();
+    let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut roots = vec![];
+    for (i, parent) in parents.iter().enumerate() {
+        match parent.and_then(|p| ids.iter().position(|&id| id == p)) {
+            Some(p) if p != i => children.entry(p).or_insert_with(Vec::new).push(i),
+            _ => roots.push(i),
+        }
+    }
+
+    fn build(i: usize,
+             symbols: &[analysis::SymbolResult],
+             ranges: &[Range],
+             children: &HashMap<usize, Vec<usize>>)
+             -> RlsDocumentSymbol {
+        let nested: Vec<_> = children.get(&i)
+            .map_or(vec![], |c| c.iter().map(|&c| build(c, symbols, ranges, children)).collect());
+        let key = |p: Position| (p.line, p.character);
+        let mut range = ranges[i];
+        for child in &nested {
+            if key(child.range.start) < key(range.start) {
+                range.start = child.range.start;
+            }
+            if key(child.range.end) > key(range.end) {
+                range.end = child.range.end;
+            }
+        }
+        RlsDocumentSymbol {
+            name: symbols[i].name.clone(),
+            kind: source_kind_from_def_kind(symbols[i].kind),
+            range,
+            selection_range: ranges[i],
+            children: nested,
+        }
     }
+    roots.into_iter().map(|i| build(i, &symbols, &ranges, &children)).collect()
 }
 
-/// Handles requests for hover information at a given point.
+/// Handles requests for hover information at a given point: the documentation,
+/// where the item is defined and its type. Without analysis data for the
+/// position, primitive types and keywords get a short description, racer is
+/// asked about anything else.
 pub struct Hover;
 
 impl<'a> Action<'a> for Hover {
@@ -119,28 +247,87 @@ impl<'a> Action<'a> for Hover {
 
 impl<'a> RequestAction<'a> for Hover {
     type Response = lsp_data::Hover;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "hover")?;
-        let span = ctx.convert_pos_to_span(file_path, params.position);
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "hover", id, out)?;
+        let position = ctx.positions.position_to_rls(&file_path, params.position);
+        let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
 
         trace!("hover: {:?}", span);
 
         let analysis = ctx.analysis.clone();
+        let vfs = ctx.vfs.clone();
+        let racer = ctx.racer.clone();
+        let doc_dirs = {
+            let config = ctx.config.lock().unwrap();
+            if config.cargo_doc_fallback {
+                cargo_doc::doc_dirs(&ctx.current_project, &config)
+            } else {
+                vec![]
+            }
+        };
+        let markdown = ctx.client.markdown_hover();
         let receiver = receive_from_thread(move || {
-            let ty = analysis.show_type(&span).unwrap_or_else(|_| String::new());
-            let docs = analysis.docs(&span).unwrap_or_else(|_| String::new());
-            let doc_url = analysis.doc_url(&span).unwrap_or_else(|_| String::new());
+            let mut ty = analysis.show_type(&span).unwrap_or_else(|_| String::new());
+            let mut docs = analysis.docs(&span).unwrap_or_else(|_| String::new());
+            let mut doc_url = analysis.doc_url(&span).unwrap_or_else(|_| String::new());
+            let def = analysis.id(&span).and_then(|id| analysis.get_def(id)).ok();
+            if docs.is_empty() && !doc_dirs.is_empty() {
+                if let Some(ref def) = def {
+                    let crate_name = cargo_doc::crate_name_for_file(&def.span.file);
+                    let kind = cargo_doc::page_prefix_for_def_kind(def.kind);
+                    if let (Some(crate_name), Some(kind)) = (crate_name, kind) {
+                        let modules = cargo_doc::module_path(&def.qualname, &crate_name).unwrap_or_default();
+                        docs = cargo_doc::find_docs(&doc_dirs, &crate_name, &modules, kind, &def.name)
+                            .unwrap_or_default();
+                    }
+                }
+            }
+
+            let mut summary = String::new();
+            match def {
+                Some(ref def) if def.kind != data::DefKind::Local => {
+                    let crate_name = cargo_doc::crate_name_for_file(&def.span.file);
+                    let feature = hover::feature_gate(&vfs, &def.span.file, def.span.range.row_start);
+                    summary = hover::definition_summary(&def.qualname,
+                                                        crate_name.as_ref().map(|c| &**c),
+                                                        feature.as_ref().map(|f| &**f),
+                                                        markdown);
+                }
+                Some(_) => {}
+                None if ty.is_empty() && docs.is_empty() => {
+                    // No analysis data (yet). Primitive types and keywords
+                    // never have any, for other items ask racer.
+                    let line = vfs.load_line(&file_path, position.row).unwrap_or_default();
+                    match hover::word_at(&line, position.col.0 as usize).and_then(hover::builtin_docs) {
+                        Some(builtin) => {
+                            docs = builtin.docs;
+                            doc_url = builtin.doc_url.unwrap_or_default();
+                        }
+                        None => {
+                            if let Some((signature, racer_docs)) = racer_lookup::signature_and_docs(&racer, file_path, position) {
+                                ty = signature;
+                                docs = racer_docs;
+                            }
+                        }
+                    }
+                }
+                None => {}
+            }
 
             let mut contents = vec![];
             if !docs.is_empty() {
-                contents.push(MarkedString::from_markdown(docs.into()));
+                let docs = if markdown { docs } else { hover::plain_text(&docs) };
+                contents.push(MarkedString::from_markdown(docs));
+            }
+            if !summary.is_empty() {
+                contents.push(MarkedString::from_markdown(summary));
             }
             if !doc_url.is_empty() {
-                contents.push(MarkedString::from_markdown(doc_url.into()));
+                contents.push(MarkedString::from_markdown(doc_url));
             }
             if !ty.is_empty() {
-                contents.push(MarkedString::from_language_code("rust".into(), ty.into()));
+                contents.push(MarkedString::from_language_code("rust".into(), ty));
             }
             lsp_data::Hover {
                 contents: contents,
@@ -172,14 +359,15 @@ impl<'a> RequestAction<'a> for FindImpls {
     type Response = Vec<Location>;
     fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "find_impls")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "find_impls", id, out)?;
         let span = ctx.convert_pos_to_span(file_path, params.position);
         let analysis = ctx.analysis.clone();
+        let positions = ctx.positions.clone();
 
         let receiver = receive_from_thread(move || {
             let type_id = analysis.id(&span)?;
             let result = analysis.find_impls(type_id).map(|spans| {
-                spans.into_iter().map(|x| ls_util::rls_to_location(&x)).collect()
+                spans.into_iter().filter_map(|x| positions.rls_to_location(&x).ok()).collect()
             });
             result
         });
@@ -190,17 +378,32 @@ impl<'a> RequestAction<'a> for FindImpls {
         match result {
             Ok(Ok(r)) => Ok(r),
             _ => {
-                out.failure_message(
-                    id,
-                    ErrorCode::InternalError,
-                    "Find Implementations failed to complete successfully"
-                );
+                out.failure_message(id, ErrorCode::InternalError, ctx.message(Message::FindImplsFailed));
                 Err(())
             }
         }
     }
 }
 
+/// `textDocument/implementation`, the standard name of `FindImpls`.
+pub struct Implementation;
+
+impl<'a> Action<'a> for Implementation {
+    type Params = TextDocumentPositionParams;
+    const METHOD: &'static str = "textDocument/implementation";
+
+    fn new(_: &'a mut LsState) -> Self {
+        Implementation
+    }
+}
+
+impl<'a> RequestAction<'a> for Implementation {
+    type Response = Vec<Location>;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        FindImpls.handle(id, params, ctx, out)
+    }
+}
+
 /// Get a list of definitions for item at the given point or identifier.
 pub struct Definition;
 
@@ -214,52 +417,321 @@ impl<'a> Action<'a> for Definition {
 }
 
 impl<'a> RequestAction<'a> for Definition {
-    type Response = Vec<Location>;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    type Response = Vec<DefinitionLocation>;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "goto_def")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "goto_def", id, out)?;
         let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
         let analysis = Arc::clone(&ctx.analysis);
         let vfs = Arc::clone(&ctx.vfs);
-        let config = Arc::clone(&ctx.config);
+        let racer = Arc::clone(&ctx.racer);
+        let (racer_fallback, search_fallback) = {
+            let config = ctx.config.lock().unwrap();
+            (config.goto_def_racer_fallback, config.goto_def_search_fallback)
+        };
+        let toolchain = ctx.build_queue.toolchain();
+        let config = ctx.config.clone();
+        let rust_src_missing = ctx.message(Message::RustSrcMissing);
+        let rust_src_missing_shown = ctx.rust_src_missing_shown.clone();
+        let project_dir = ctx.current_project.clone();
+        let positions = ctx.positions.clone();
+        let line_indices = ctx.line_indices.clone();
+        let derive_macros = ctx.derive_macros.clone();
+        let racer_first = racer_fallback && ctx.status.lock().unwrap().build_state() == BuildState::Building;
 
         let receiver = receive_from_thread(move || {
             // If configured start racer concurrently and fallback to racer result
-            let racer_receiver = {
-                if config.lock().unwrap().goto_def_racer_fallback {
+            let mut racer_receiver = {
+                if racer_fallback {
+                    let positions = positions.clone();
+                    let racer = racer.clone();
                     Some(receive_from_thread(move || {
-                        let cache = racer::FileCache::new(vfs);
-                        let session = racer::Session::new(&cache);
-                        let location = pos_to_racer_location(params.position);
-
-                        racer::find_definition(file_path, location, &session)
-                            .and_then(location_from_racer_match)
+                        let position = positions.position_to_rls(&file_path, params.position);
+                        racer_lookup::definition(&racer, file_path, position, &positions)
                     }))
                 }
                 else { None }
             };
 
-            match analysis.goto_def(&span) {
-                Ok(out) => {
-                    let result = vec![ls_util::rls_to_location(&out)];
-                    trace!("goto_def (compiler): {:?}", result);
-                    return result
+            // The analysis data may be out of date while building.
+            if racer_first {
+                if let Some(receiver) = racer_receiver.take() {
+                    if let Ok(Some(r)) = receiver.recv() {
+                        trace!("goto_def (Racer, while building): {:?}", r);
+                        return vec![DefinitionLocation::new(r, DefinitionSource::Racer)];
+                    }
                 }
-                _ => match racer_receiver {
-                    Some(receiver) => match receiver.recv() {
-                        Ok(Some(r)) =>  {
-                            trace!("goto_def (Racer): {:?}", r);
-                            return vec![r]
+            }
+
+            // Paths in `use` statements and derives aren't always in the
+            // analysis data, see `navigation`.
+            let def_spans = match analysis.goto_def(&span) {
+                Ok(def_span) => vec![def_span],
+                Err(_) => path_definitions(&vfs, &line_indices, &analysis, &derive_macros, &span),
+            };
+            if !def_spans.is_empty() {
+                let mut locations = vec![];
+                let sysroot = toolchain.get(&config, &project_dir).sysroot;
+                for mut def_span in def_spans {
+                    // Definitions in dependencies and the standard library.
+                    match sources::locate(&def_span.file, &project_dir, sysroot.as_ref().map(|s| &**s)) {
+                        sources::SourceFile::Found(file) => def_span.file = file,
+                        sources::SourceFile::StdMissing => {
+                            if !rust_src_missing_shown.swap(true, Ordering::SeqCst) {
+                                out.notify(NotificationMessage::new(
+                                    NOTIFICATION_SHOW_MESSAGE,
+                                    Some(NotificationParams::ShowMessage(ShowMessageParams {
+                                        typ: MessageType::Info,
+                                        message: rust_src_missing.clone(),
+                                    })),
+                                ));
+                            }
+                            continue;
                         }
-                        Ok(None) => {
-                            trace!("goto_def (Racer): None");
-                            return vec![]
+                        sources::SourceFile::Unknown => continue,
+                    }
+                    if let Ok(location) = positions.rls_to_location(&def_span) {
+                        locations.push(DefinitionLocation::new(location, DefinitionSource::Compiler));
+                    }
+                }
+                trace!("goto_def (compiler): {:?}", locations);
+                return locations;
+            }
+            if let Some(receiver) = racer_receiver {
+                if let Ok(Some(r)) = receiver.recv() {
+                    trace!("goto_def (Racer): {:?}", r);
+                    return vec![DefinitionLocation::new(r, DefinitionSource::Racer)];
+                }
+                trace!("goto_def (Racer): None");
+            }
+            if !search_fallback {
+                return vec![];
+            }
+
+            // Last resort, a textual search of the project.
+            let name = match vfs.load_span(span) {
+                Ok(ref name) if !name.is_empty() && !RUST_KEYWORDS.contains(&&**name) => name.clone(),
+                _ => return vec![],
+            };
+            let result: Vec<_> = navigation::search_definitions(&collect_source_files(&project_dir), &name)
+                .iter()
+                .filter_map(|s| positions.rls_to_location(s).ok())
+                .map(|location| DefinitionLocation::new(location, DefinitionSource::Search))
+                .collect();
+            trace!("goto_def (search): {:?}", result);
+            result
+        });
+
+        Ok(receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
+            .unwrap_or_else(|_| vec![]))
+    }
+}
+
+/// The type of the selected expression, see `expression_type`. Responds with
+/// `null` if it isn't known.
+pub struct TypeOfSelectionRequest;
+
+impl<'a> Action<'a> for TypeOfSelectionRequest {
+    type Params = TypeOfSelectionParams;
+    const METHOD: &'static str = "rustDocument/typeOfSelection";
+
+    fn new(_: &'a mut LsState) -> Self {
+        TypeOfSelectionRequest
+    }
+}
+
+impl<'a> RequestAction<'a> for TypeOfSelectionRequest {
+    type Response = Option<TypeOfSelection>;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "type_of_selection", id, out)?;
+        let range = ctx.positions.range_to_rls(&file_path, params.range);
+        let selection = Span::from_range(range, file_path.clone());
+
+        // The identifier the selected expression ends with, or the one at the
+        // start of the selection if it ends with none (e.g. it is empty).
+        let node = ctx.vfs.load_span(selection).ok().and_then(|text| expression_type::typed_node(&text));
+        let (row, col, call) = match node {
+            Some(node) if node.row == 0 => (range.row_start, range.col_start.0 as usize + node.col, node.call),
+            Some(node) => (span::Row::new_zero_indexed(range.row_start.0 + node.row as u32), node.col, node.call),
+            None => (range.row_start, range.col_start.0 as usize, false),
+        };
+        let line = match ctx.line_indices.load_line(&ctx.vfs, &file_path, row.0 as usize) {
+            Ok(line) => line,
+            Err(_) => return Ok(None),
+        };
+        let (start, end) = find_word_at_pos(&line, &span::Column::new_zero_indexed(col as u32));
+        if start == end {
+            return Ok(None);
+        }
+        let span = Span::from_positions(span::Position::new(row, start), span::Position::new(row, end), file_path);
+
+        let analysis = ctx.analysis.clone();
+        let span_ = span.clone();
+        let receiver = receive_from_thread(move || {
+            let ty = analysis.show_type(&span_).ok()?;
+            if call {
+                Some(expression_type::return_type(&ty))
+            } else {
+                Some(ty)
+            }
+        });
+        let ty = match receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT)) {
+            Ok(Some(ref ty)) if !ty.is_empty() => ty.clone(),
+            _ => return Ok(None),
+        };
+        trace!("type_of_selection: {:?} -> {:?}", span, ty);
+        Ok(Some(TypeOfSelection {
+            ty,
+            range: ctx.positions.rls_to_range(&span.file, span.range),
+        }))
+    }
+}
+
+/// Experimental: where values are borrowed and moved in the function at the
+/// given point, see `borrows`.
+pub struct BorrowsRequest;
+
+impl<'a> Action<'a> for BorrowsRequest {
+    type Params = TextDocumentPositionParams;
+    const METHOD: &'static str = "rustDocument/borrows";
+
+    fn new(_: &'a mut LsState) -> Self {
+        BorrowsRequest
+    }
+}
+
+impl<'a> RequestAction<'a> for BorrowsRequest {
+    type Response = Borrows;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "borrows", id, out)?;
+        let position = ctx.positions.position_to_rls(&file_path, params.position);
+        let mut result = Borrows { borrows: vec![], moves: vec![] };
+        let rows = ctx.vfs.with_file(&file_path, |text| borrows::function_rows(text, position.row.0 as usize));
+        let (first, last) = match rows {
+            Ok(Some(rows)) => rows,
+            _ => return Ok(result),
+        };
+
+        let facts = ctx.borrow_facts.in_file(&file_path);
+        let in_function = |span: &Span| {
+            let row = span.range.row_start.0 as usize;
+            first <= row && row <= last
+        };
+        for fact in facts.iter().filter(|f| in_function(f.span())) {
+            match *fact {
+                BorrowFact::Borrow { ref span, ref end, mutable, ref message } => {
+                    let mut region = span.range;
+                    if let Some(ref end) = *end {
+                        if end.file == span.file {
+                            region.row_end = end.range.row_end;
+                            region.col_end = end.range.col_end;
                         }
-                        _ => vec![]
                     }
-                    _ => vec![]
+                    result.borrows.push(BorrowRegion {
+                        range: ctx.positions.rls_to_range(&span.file, span.range),
+                        region: ctx.positions.rls_to_range(&span.file, region),
+                        mutable,
+                        message: message.clone(),
+                    });
+                }
+                BorrowFact::Move { ref span, ref message } => result.moves.push(MoveSite {
+                    range: ctx.positions.rls_to_range(&span.file, span.range),
+                    message: message.clone(),
+                }),
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Go to the definition of the type of the expression at the given point, e.g.
+/// from a variable to its struct.
+pub struct TypeDefinition;
+
+impl<'a> Action<'a> for TypeDefinition {
+    type Params = TextDocumentPositionParams;
+    const METHOD: &'static str = "textDocument/typeDefinition";
+
+    fn new(_: &'a mut LsState) -> Self {
+        TypeDefinition
+    }
+}
+
+impl<'a> RequestAction<'a> for TypeDefinition {
+    type Response = Vec<Location>;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "goto_type_def", id, out)?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+        let analysis = ctx.analysis.clone();
+        let symbol_index = ctx.symbol_index.clone();
+        let positions = ctx.positions.clone();
+
+        let receiver = receive_from_thread(move || {
+            // The type of a type is itself.
+            if let Ok(def) = analysis.id(&span).and_then(|id| analysis.get_def(id)) {
+                if navigation::is_type(def.kind) {
+                    return positions.rls_to_location(&def.span).into_iter().collect();
                 }
             }
+            let ty = match analysis.show_type(&span) {
+                Ok(ty) => ty,
+                Err(_) => return vec![],
+            };
+            let spans = match navigation::type_path(&ty) {
+                Some(path) => navigation::type_definitions(&symbol_index, path),
+                None => vec![],
+            };
+            trace!("goto_type_def: {:?} -> {:?}", ty, spans);
+            spans.iter().filter_map(|s| positions.rls_to_location(s).ok()).collect()
+        });
+
+        Ok(receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
+            .unwrap_or_else(|_| vec![]))
+    }
+}
+
+/// Go to the declaration of the item at the given point: for a method of a
+/// trait impl the method in the trait, otherwise the definition.
+pub struct Declaration;
+
+impl<'a> Action<'a> for Declaration {
+    type Params = TextDocumentPositionParams;
+    const METHOD: &'static str = "textDocument/declaration";
+
+    fn new(_: &'a mut LsState) -> Self {
+        Declaration
+    }
+}
+
+impl<'a> RequestAction<'a> for Declaration {
+    type Response = Vec<Location>;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "goto_declaration", id, out)?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+        let analysis = ctx.analysis.clone();
+        let symbol_index = ctx.symbol_index.clone();
+        let positions = ctx.positions.clone();
+
+        let receiver = receive_from_thread(move || {
+            let def = match analysis.id(&span).and_then(|id| analysis.get_def(id)) {
+                Ok(def) => def,
+                Err(_) => return vec![],
+            };
+            let mut spans = match navigation::trait_method(&def.qualname) {
+                Some((trait_name, method)) => navigation::trait_method_declarations(&symbol_index, trait_name, method),
+                None => vec![],
+            };
+            // Traits outside the workspace aren't indexed, their methods are
+            // declared where we know them to be defined.
+            if spans.is_empty() {
+                spans.push(def.span);
+            }
+            trace!("goto_declaration: {:?} -> {:?}", def.qualname, spans);
+            spans.iter().filter_map(|s| positions.rls_to_location(s).ok()).collect()
         });
 
         Ok(receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
@@ -271,7 +743,7 @@ impl<'a> RequestAction<'a> for Definition {
 pub struct References;
 
 impl<'a> Action<'a> for References {
-    type Params = ReferenceParams;
+    type Params = RlsReferenceParams;
     const METHOD: &'static str = "textDocument/references";
 
     fn new(_: &'a mut LsState) -> Self {
@@ -281,23 +753,185 @@ impl<'a> Action<'a> for References {
 
 impl<'a> RequestAction<'a> for References {
     type Response = Vec<Location>;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "find_all_refs")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "find_all_refs", id, out)?;
         let span = ctx.convert_pos_to_span(file_path, params.position);
         let analysis = ctx.analysis.clone();
+        let vfs = ctx.vfs.clone();
+        let line_indices = ctx.line_indices.clone();
+        let positions = ctx.positions.clone();
+        let responded = Arc::new(AtomicBool::new(false));
+        let mut pages = ResultPages::new(params.partial_result_token.clone(), responded.clone(), out);
 
         let receiver = receive_from_thread(move || {
-            analysis.find_all_refs(&span, params.context.include_declaration)
+            // The declaration is left out here rather than by the analysis,
+            // which only recognises it when asked at the declaration itself.
+            let refs = analysis.find_all_refs(&span, true).unwrap_or_else(|_| vec![]);
+            let declaration = analysis.id(&span).and_then(|id| analysis.get_def(id)).ok().map(|def| def.span);
+
+            // `Location` isn't `Hash`, so the references are keyed by their
+            // URI and range.
+            let mut seen = HashSet::new();
+            for item in &refs {
+                if !params.context.include_declaration && Some(item) == declaration.as_ref() {
+                    continue;
+                }
+                if !params.kinds.is_empty() {
+                    match reference_kind(&vfs, &line_indices, item) {
+                        Some(kind) if params.kinds.contains(&kind) => {}
+                        _ => continue,
+                    }
+                }
+                let location = match positions.rls_to_location(item) {
+                    Ok(location) => location,
+                    Err(_) => continue,
+                };
+                let range = &location.range;
+                let key = (location.uri.clone(), range.start.line, range.start.character,
+                           range.end.line, range.end.character);
+                if seen.insert(key) {
+                    pages.push(location);
+                }
+            }
+            pages.finish()
         });
 
-        let result = match receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT)) {
-            Ok(Ok(t)) => t,
-            _ => vec![],
-        };
+        let result = receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
+            .unwrap_or_else(|_| vec![]);
+        responded.store(true, Ordering::SeqCst);
+
+        Ok(result)
+    }
+}
+
+// How many lines above a reference are searched for the `use` it's part of.
+const MAX_USE_LINES: usize = 50;
+
+// The definitions of the word at `span`, if it's a segment of the path of a
+// `use` statement, or the last one of a derive or an attribute macro.
+fn path_definitions(vfs: &Vfs,
+                    line_indices: &LineIndexCache,
+                    analysis: &analysis::AnalysisHost,
+                    derive_macros: &DeriveMacros,
+                    span: &Span)
+                    -> Vec<Span> {
+    let row = span.range.row_start.0 as usize;
+    let line = match line_indices.load_line(vfs, &span.file, row) {
+        Ok(line) => line,
+        Err(_) => return vec![],
+    };
+    let start = span.range.col_start.0 as usize;
+    let end = span.range.col_end.0 as usize;
+    let chars: Vec<char> = line.chars().collect();
+    let path_start = chars[..start.min(chars.len())].iter()
+        .rposition(|&c| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .map_or(0, |i| i + 1);
+    let derive = match attributes::attribute_context(&line, path_start) {
+        Some((attributes::AttributeContext::Derive, _)) => Some(true),
+        Some((attributes::AttributeContext::Name, _)) => Some(false),
+        _ => None,
+    };
+    if let Some(derive) = derive {
+        let path: String = chars[path_start..end.min(chars.len())].iter().collect();
+        let path: Vec<String> = path.split("::").map(|s| s.to_owned()).collect();
+        return navigation::macro_definitions(analysis, derive_macros, &path, derive);
+    }
 
-        Ok(result.iter().map(|item| ls_util::rls_to_location(item)).collect())
+    let previous = (0..row).rev().map(|r| line_indices.load_line(vfs, &span.file, r).unwrap_or_default());
+    let mut statement: String = line.chars().take(end).collect();
+    for line in previous.take(MAX_USE_LINES) {
+        if statement.contains("use ") {
+            break;
+        }
+        let trimmed = line.trim_right().to_owned();
+        if trimmed.ends_with(';') || trimmed.ends_with('}') {
+            return vec![];
+        }
+        statement = trimmed + "\n" + &statement;
+    }
+    match navigation::use_path(&statement) {
+        Some(path) => navigation::use_path_definitions(analysis, &span.file, &path),
+        None => vec![],
+    }
+}
+
+// How the reference at `span` uses its symbol, judging by the source around
+// it. `None` if it's none of the kinds of `ReferenceKind`.
+fn reference_kind(vfs: &Vfs, line_indices: &LineIndexCache, span: &Span) -> Option<ReferenceKind> {
+    let row = span.range.row_start.0 as usize;
+    let line = line_indices.load_line(vfs, &span.file, row).ok()?;
+    let previous = (0..row).rev().map(|r| line_indices.load_line(vfs, &span.file, r).unwrap_or_default());
+    if in_use_statement(&line, previous) {
+        return Some(ReferenceKind::Import);
+    }
+    classify_reference(&line, span.range.col_start.0 as usize, span.range.col_end.0 as usize)
+}
+
+// Whether `line` is (part of) a `use` statement, given the lines before it,
+// closest first.
+fn in_use_statement<I: Iterator<Item = String>>(line: &str, previous: I) -> bool {
+    let is_use = |line: &str| {
+        let line = line.trim_left();
+        line.starts_with("use ") || (line.starts_with("pub") && line.contains(" use "))
+    };
+    if is_use(line) {
+        return true;
+    }
+    for line in previous.take(MAX_USE_LINES) {
+        if is_use(&line) {
+            return true;
+        }
+        let line = line.trim_right();
+        if line.ends_with(';') || line.ends_with('{') || line.ends_with('}') {
+            return false;
+        }
+    }
+    false
+}
+
+// Whether the use of a symbol between the (char) columns `start` and `end` of
+// `line` is a call or a struct literal.
+fn classify_reference(line: &str, start: usize, end: usize) -> Option<ReferenceKind> {
+    let byte = |col: usize| line.char_indices().nth(col).map_or(line.len(), |(i, _)| i);
+    let before = line[..byte(start)].trim_right();
+    let mut after = line[byte(end)..].trim_left();
+    if after.starts_with("::<") {
+        after = skip_generic_args(&after[2..]).trim_left();
     }
+    let last_word = before.rsplit(|c: char| c.is_whitespace()).next().unwrap_or("");
+
+    if after.starts_with('(') && last_word != "fn" {
+        return Some(ReferenceKind::Call);
+    }
+    if after.starts_with('{') {
+        // Not a type in a declaration, a return type or a condition.
+        let first_word = line.trim_left().split(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap_or("");
+        let declaration = ["impl", "struct", "enum", "union", "trait", "mod", "fn", "pub", "unsafe"].contains(&first_word);
+        let after_keyword = ["->", "if", "while", "match", "in", "for"].contains(&last_word);
+        if !declaration && !after_keyword {
+            return Some(ReferenceKind::StructLiteral);
+        }
+    }
+    None
+}
+
+// The text after the generic arguments at the start of `text` (e.g. `<T>`).
+fn skip_generic_args(text: &str) -> &str {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &text[i + 1..];
+                }
+            }
+            _ => {}
+        }
+    }
+    ""
 }
 
 /// Get a list of possible completions at the given location.
@@ -313,27 +947,103 @@ impl<'a> Action<'a> for Completion {
 }
 
 impl<'a> RequestAction<'a> for Completion {
-    type Response = Vec<CompletionItem>;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    type Response = CompletionList;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
         let vfs = ctx.vfs.clone();
-        let file_path = parse_file_path!(&params.text_document.uri, "complete")?;
+        let racer = ctx.racer.clone();
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "complete", id, out)?;
+        let (cargo_doc_fallback, limit, import_policy, use_racer) = {
+            let config = ctx.config.lock().unwrap();
+            (config.cargo_doc_fallback, config.completion_limit, ImportPolicy::from_config(&config), config.racer_completion)
+        };
+        let position = ctx.positions.position_to_rls(&file_path, params.position);
+
+        if manifest::is_manifest(&file_path) {
+            let index = package_dir_for_file(&file_path).and_then(manifest::RegistryIndex::find);
+            let items = vfs.with_file(&file_path, |text| {
+                manifest::manifest_completions(text, position.row.0 as usize, position.col.0 as usize, index.as_ref())
+            }).unwrap_or_default();
+            let mut list = manifest_completion_list(items, limit);
+            for item in &mut list.items {
+                if let Some(ref mut edit) = item.text_edit {
+                    edit.range = ctx.positions.char_range_to_client(&file_path, edit.range);
+                }
+            }
+            return Ok(list);
+        }
 
-        let receiver = receive_from_thread(move || {
-            let cache = racer::FileCache::new(vfs);
-            let session = racer::Session::new(&cache);
+        // Racer doesn't complete inside attributes or paths.
+        let line = vfs.load_line(&file_path, position.row).unwrap_or_default();
+        if let Some((context, prefix)) = attributes::attribute_context(&line, position.col.0 as usize) {
+            let package_manifest = package_dir_for_file(&file_path).and_then(manifest::read_manifest);
+            let completions = attributes::attribute_completions(context,
+                                                                &prefix,
+                                                                package_manifest.as_ref(),
+                                                                &ctx.derive_macros,
+                                                                &ctx.build_queue.lib_names());
+            return Ok(rank_completions(completions, limit));
+        }
+        if let Some((context, prefix)) = file_paths::path_context(&line, position.col.0 as usize) {
+            let completions = file_paths::path_completions(&file_path, context, &prefix);
+            return Ok(rank_completions(completions, limit));
+        }
 
-            let location = pos_to_racer_location(params.position);
-            let results = racer::complete_from_file(file_path, location, &session);
+        let analysis = ctx.analysis.clone();
+        let symbol_index = ctx.symbol_index.clone();
+        let file_path_ = file_path.clone();
 
-            results.map(|comp| completion_item_from_racer_match(comp)).collect()
+        let receiver = receive_from_thread(move || {
+            let mut completions = if use_racer {
+                racer_lookup::completions(&racer, file_path_.clone(), position, cargo_doc_fallback)
+            } else {
+                vec![]
+            };
+            // Racer misses methods of traits implemented where it doesn't
+            // look, the analysis data knows them.
+            let trait_methods = trait_methods::trait_method_completions(&analysis, &symbol_index, &vfs, &file_path_,
+                                                                        position, &import_policy);
+            for completion in trait_methods {
+                if !completions.iter().any(|c| c.item.label == completion.item.label) {
+                    completions.push(completion);
+                }
+            }
+            completions
         });
 
-        let result = receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
+        let mut result = receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
             .unwrap_or_else(|_| vec![]);
+        // Racer only completes identifiers.
+        let snippets = ctx.client.snippet_completions();
+        let keywords = ctx.vfs.with_file(&file_path, |text| {
+            keywords::keyword_completions(text, position.row.0 as usize, position.col.0 as usize, snippets)
+        });
+        result.extend(keywords.unwrap_or_default());
 
-        Ok(result)
+        let mut list = rank_completions(result, limit);
+        for item in &mut list.items {
+            if let Some(ref mut edits) = item.additional_text_edits {
+                for edit in edits {
+                    edit.range = ctx.positions.char_range_to_client(&file_path, edit.range);
+                }
+            }
+        }
+        Ok(list)
+    }
+}
+
+// The manifest completions are in order already (e.g. versions newest first),
+// rather than ranked like those of Rust source.
+fn manifest_completion_list(mut items: Vec<CompletionItem>, limit: usize) -> CompletionList {
+    let is_incomplete = limit > 0 && items.len() > limit;
+    if is_incomplete {
+        items.truncate(limit);
     }
+    let width = items.len().to_string().len();
+    for (rank, item) in items.iter_mut().enumerate() {
+        item.sort_text = Some(format!("{:0width$}", rank, width = width));
+    }
+    CompletionList { is_incomplete, items }
 }
 
 /// Find all references to the thing at the given location within this document,
@@ -352,30 +1062,79 @@ impl<'a> Action<'a> for DocumentHighlight {
 
 impl<'a> RequestAction<'a> for DocumentHighlight {
     type Response = Vec<lsp_data::DocumentHighlight>;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "highlight")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "highlight", id, out)?;
         let span = ctx.convert_pos_to_span(file_path, params.position);
+        if let Some(spans) = lifetime_occurrences(&ctx.vfs, &span) {
+            return Ok(spans.iter().map(|span| lsp_data::DocumentHighlight {
+                range: ctx.positions.rls_to_range(&span.file, span.range),
+                kind: Some(DocumentHighlightKind::Text),
+            }).collect());
+        }
         let analysis = ctx.analysis.clone();
 
         let receiver = receive_from_thread(move || {
-            analysis.find_all_refs(&span, true)
+            let def = analysis.id(&span).and_then(|id| analysis.get_def(id)).ok();
+            analysis.find_all_refs(&span, true).map(|refs| (refs, def))
         });
 
-        let result = match receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT)) {
+        let (result, def) = match receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT)) {
             Ok(Ok(t)) => t,
-            _ => vec![],
+            _ => (vec![], None),
         };
 
-        let refs: Vec<_> = result.iter().map(|span| lsp_data::DocumentHighlight {
-            range: ls_util::rls_to_range(span.range),
-            kind: Some(DocumentHighlightKind::Text),
+        // Only variables and fields can be written to, their declaration
+        // counts as a write.
+        let def_span = match def {
+            Some(ref def) if def.kind == data::DefKind::Local ||
+                             def.kind == data::DefKind::Static ||
+                             def.kind == data::DefKind::Field => Some(&def.span),
+            _ => None,
+        };
+        let refs: Vec<_> = result.iter().map(|span| {
+            let kind = match def_span {
+                Some(def_span) if def_span == span => DocumentHighlightKind::Write,
+                Some(_) => {
+                    let line = ctx.line_indices.load_line(&ctx.vfs, &span.file, span.range.row_start.0 as usize);
+                    match line {
+                        Ok(ref line) if span.range.row_start == span.range.row_end => {
+                            access_kind(line, span.range.col_start.0 as usize, span.range.col_end.0 as usize)
+                        }
+                        _ => DocumentHighlightKind::Read,
+                    }
+                }
+                None => DocumentHighlightKind::Text,
+            };
+            lsp_data::DocumentHighlight {
+                range: ctx.positions.rls_to_range(&span.file, span.range),
+                kind: Some(kind),
+            }
         }).collect();
 
         Ok(refs)
     }
 }
 
+// Whether the use of a variable between the (char) columns `start` and `end`
+// of `line` writes to it: it is assigned to or mutably borrowed.
+fn access_kind(line: &str, start: usize, end: usize) -> DocumentHighlightKind {
+    let byte = |col: usize| line.char_indices().nth(col).map_or(line.len(), |(i, _)| i);
+    let before = line[..byte(start)].trim_right();
+    let after = line[byte(end)..].trim_left();
+
+    let mut_borrow = before.ends_with("&mut");
+    let assignment = after.starts_with('=') && !after.starts_with("==") && !after.starts_with("=>");
+    let compound_assignment = ["+=", "-=", "*=", "/=", "%=", "^=", "&=", "|=", "<<=", ">>="]
+        .iter()
+        .any(|op| after.starts_with(op));
+    if mut_borrow || assignment || compound_assignment {
+        DocumentHighlightKind::Write
+    } else {
+        DocumentHighlightKind::Read
+    }
+}
+
 /// Rename the given symbol within the whole project.
 pub struct Rename;
 
@@ -389,20 +1148,50 @@ impl<'a> Action<'a> for Rename {
 }
 
 impl<'a> RequestAction<'a> for Rename {
-    type Response = WorkspaceEdit;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    type Response = RlsWorkspaceEdit;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "rename")?;
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "rename", id, out)?;
         let span = ctx.convert_pos_to_span(file_path, params.position);
 
+        if let Some(spans) = lifetime_occurrences(&ctx.vfs, &span) {
+            // The new name may be given with or without the `'`.
+            let name = params.new_name.trim_left_matches('\'');
+            if !is_lifetime_name(name) {
+                out.failure_message(id, ErrorCode::InvalidParams, ctx.message(Message::InvalidName));
+                return Err(());
+            }
+            return match text_edits(&ctx.positions, &spans, &format!("'{}", name)) {
+                Ok(edits) => Ok(RlsWorkspaceEdit::Changes(WorkspaceEdit { changes: edits })),
+                Err(_) => {
+                    out.failure_message(id, ErrorCode::InternalError, ctx.message(Message::RenameNoUri));
+                    Err(())
+                }
+            };
+        }
+        if CANNOT_BE_RAW.contains(&&*params.new_name) {
+            out.failure_message(id, ErrorCode::InvalidParams, ctx.message(Message::InvalidName));
+            return Err(());
+        }
+        // Keywords can still be used as names, as raw identifiers.
+        let new_text = if RUST_KEYWORDS.contains(&&*params.new_name) || RESERVED_KEYWORDS.contains(&&*params.new_name) {
+            format!("r#{}", params.new_name)
+        } else {
+            params.new_name.clone()
+        };
+
+        let rename_files = ctx.client.file_renames() && ctx.config.lock().unwrap().rename_module_files;
         let analysis = ctx.analysis.clone();
+        let vfs = ctx.vfs.clone();
+        let project_dir = ctx.current_project.clone();
+        let new_name = params.new_name.clone();
         let receiver = receive_from_thread(move || {
             macro_rules! unwrap_or_empty {
                 ($e: expr) => {
                     match $e {
                         Ok(e) => e,
                         Err(_) => {
-                            return vec![];
+                            return Ok((vec![], 0, None));
                         }
                     }
                 }
@@ -410,38 +1199,291 @@ impl<'a> RequestAction<'a> for Rename {
 
             let id = unwrap_or_empty!(analysis.crate_local_id(&span));
             let def = unwrap_or_empty!(analysis.get_def(id));
-            if def.name == "self" || def.name == "Self"
-                // FIXME(#578)
-                || def.kind == data::DefKind::Mod {
-                return vec![];
+            if def.name == "self" || def.name == "Self" {
+                return Ok((vec![], 0, None));
             }
 
-            analysis.find_all_refs(&span, true).unwrap_or_else(|_| vec![])
+            // A module in a file named after it needs the file renamed too.
+            let moved = match module_path(&def, &project_dir) {
+                Some(_) if !rename_files => return Err(Message::RenameModule),
+                Some(path) => {
+                    let new_path = if path.extension().is_some() {
+                        path.with_file_name(format!("{}.rs", new_name))
+                    } else {
+                        path.with_file_name(&new_name)
+                    };
+                    Some((path, new_path))
+                }
+                None => None,
+            };
+
+            // Uses in macro invocations may have the span of the whole
+            // invocation, or one which only exists in the expansion.
+            let refs = analysis.find_all_refs(&span, true).unwrap_or_else(|_| vec![]);
+            let mut spans = vec![];
+            let mut skipped = 0;
+            for r in &refs {
+                match identifier_span(&vfs, r, &def.name) {
+                    // Several uses in one invocation can map to the same token.
+                    Some(ref span) if spans.contains(span) => {}
+                    Some(span) => spans.push(span),
+                    None => skipped += 1,
+                }
+            }
+            Ok((spans, skipped, moved))
         });
 
-        let result = receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT))
-            .unwrap_or_else(|_| vec![]);
+        let (result, skipped, moved) = match receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT)) {
+            Ok(Ok(result)) => result,
+            Ok(Err(message)) => {
+                out.failure_message(id, ErrorCode::InvalidParams, ctx.message(message));
+                return Err(());
+            }
+            Err(_) => (vec![], 0, None),
+        };
+        if skipped > 0 {
+            warn!("rename: skipped {} uses which are not in the source", skipped);
+            out.notify(NotificationMessage::new(
+                NOTIFICATION_SHOW_MESSAGE,
+                Some(NotificationParams::ShowMessage(ShowMessageParams {
+                    typ: MessageType::Warning,
+                    message: ctx.message(Message::RenameSkippedExpansions),
+                })),
+            ));
+        }
 
-        let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        let edits = match text_edits(&ctx.positions, &result, &new_text) {
+            Ok(edits) => edits,
+            Err(_) => {
+                out.failure_message(id, ErrorCode::InternalError, ctx.message(Message::RenameNoUri));
+                return Err(());
+            }
+        };
 
-        for item in result.iter() {
-            let loc = ls_util::rls_to_location(item);
-            edits.entry(loc.uri).or_insert_with(Vec::new).push(TextEdit {
-                range: loc.range,
-                new_text: params.new_name.clone(),
-            });
+        let (old_path, new_path) = match moved {
+            Some(moved) => moved,
+            None => return Ok(RlsWorkspaceEdit::Changes(WorkspaceEdit { changes: edits })),
+        };
+        // The files are edited before the module is renamed, the edits refer
+        // to the old paths.
+        let mut document_changes: Vec<_> = edits.into_iter().map(|(uri, edits)| {
+            DocumentChange::Edit(DocumentEdit {
+                text_document: VersionedDocument { uri, version: None },
+                edits,
+            })
+        }).collect();
+        match (ctx.documents.uri(&old_path), ctx.documents.uri(&new_path)) {
+            (Ok(old_uri), Ok(new_uri)) => {
+                document_changes.push(DocumentChange::Rename(RenameFile::new(old_uri, new_uri)));
+            }
+            _ => {
+                out.failure_message(id, ErrorCode::InternalError, ctx.message(Message::RenameNoUri));
+                return Err(());
+            }
+        }
+        Ok(RlsWorkspaceEdit::DocumentChanges { document_changes })
+    }
+}
+
+// Edits replacing each span with `new_text`, by file. Fails if any of the
+// files has no URI, a partial rename would break the code.
+fn text_edits(positions: &PositionConverter, spans: &[Span], new_text: &str)
+              -> Result<HashMap<Url, Vec<TextEdit>>, UrlFileParseError> {
+    let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for span in spans {
+        let loc = positions.rls_to_location(span)?;
+        edits.entry(loc.uri).or_insert_with(Vec::new).push(TextEdit {
+            range: loc.range,
+            new_text: new_text.to_owned(),
+        });
+    }
+    Ok(edits)
+}
+
+// The occurrences of the lifetime or label at the start of `span`, see
+// `lifetimes`. `None` if there is none.
+fn lifetime_occurrences(vfs: &Vfs, span: &Span) -> Option<Vec<Span>> {
+    let row = span.range.row_start.0 as usize;
+    let col = span.range.col_start.0 as usize;
+    vfs.with_file(&span.file, |text| {
+        // Only tokenize the file if the word at `col` starts with a `'`.
+        let line: Vec<char> = text.lines().nth(row)?.chars().collect();
+        let before = &line[..::std::cmp::min(col, line.len())];
+        let quote = before.iter().rposition(|&c| !(c.is_alphanumeric() || c == '_')).map(|i| before[i]);
+        if quote != Some('\'') && line.get(col) != Some(&'\'') {
+            return None;
         }
+        lifetimes::occurrences(text, &span.file, row, col)
+    }).ok()?
+}
+
+// Whether `name` can be the name of a lifetime or label (after the `'`).
+fn is_lifetime_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_ident = chars.next().map_or(false, |c| c.is_alphabetic() || c == '_');
+    starts_ident && chars.all(|c| c.is_alphanumeric() || c == '_') && name != "_" && name != "static" &&
+        !RUST_KEYWORDS.contains(&name) && !RESERVED_KEYWORDS.contains(&name)
+}
 
-        Ok(WorkspaceEdit { changes: edits })
+// The file backing a module declared as `mod foo;` (save-analysis records it
+// as the value of the module), or its directory if it is a `mod.rs`. `None` for
+// inline modules and modules whose file is not named after them (`#[path]`).
+fn module_path(def: &analysis::Def, project_dir: &Path) -> Option<PathBuf> {
+    if def.kind != data::DefKind::Mod || def.value.is_empty() {
+        return None;
     }
+    let file = project_dir.join(&def.value);
+    if file == project_dir.join(&def.span.file) {
+        return None;
+    }
+    let path = if file.file_name()? == "mod.rs" {
+        file.parent()?.to_owned()
+    } else {
+        file
+    };
+    if path.file_stem()? != def.name.as_str() {
+        return None;
+    }
+    Some(path)
 }
 
+/// Check whether the symbol at the given position can be renamed, before the
+/// client asks for a new name. Responds with the range of the identifier, or
+/// fails with the reason it can't be renamed.
+pub struct PrepareRename;
+
+impl<'a> Action<'a> for PrepareRename {
+    type Params = TextDocumentPositionParams;
+    const METHOD: &'static str = "textDocument/prepareRename";
+
+    fn new(_: &'a mut LsState) -> Self {
+        PrepareRename
+    }
+}
+
+impl<'a> RequestAction<'a> for PrepareRename {
+    type Response = Range;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "prepare_rename", id, out)?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+        if let Some(spans) = lifetime_occurrences(&ctx.vfs, &span) {
+            // The range of the whole lifetime, with its `'`.
+            let row = span.range.row_start;
+            let col = span.range.col_start;
+            if let Some(lifetime) = spans.iter().find(|s| s.range.row_start == row && s.range.col_start <= col && col <= s.range.col_end) {
+                return Ok(ctx.positions.rls_to_range(&lifetime.file, lifetime.range));
+            }
+        }
+
+        let rename_files = ctx.client.file_renames() && ctx.config.lock().unwrap().rename_module_files;
+        let analysis = ctx.analysis.clone();
+        let vfs = ctx.vfs.clone();
+        let project_dir = ctx.current_project.clone();
+        let span_ = span.clone();
+        let receiver = receive_from_thread(move || {
+            let name = match vfs.load_span(span_.clone()) {
+                Ok(ref name) if !name.is_empty() => name.clone(),
+                _ => return Err(Message::NothingToRename),
+            };
+            if RUST_KEYWORDS.contains(&&*name) {
+                return Err(Message::RenameKeyword);
+            }
+            let id = match analysis.id(&span_) {
+                Ok(id) => id,
+                Err(_) if PRIMITIVE_TYPES.contains(&&*name) => return Err(Message::RenamePrimitive),
+                Err(_) => return Err(Message::NothingToRename),
+            };
+            if analysis.crate_local_id(&span_).is_err() {
+                return Err(Message::RenameExternal);
+            }
+            match analysis.get_def(id) {
+                Ok(ref def) if !rename_files && module_path(def, &project_dir).is_some() => Err(Message::RenameModule),
+                _ => Ok(()),
+            }
+        });
+
+        match receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT)) {
+            Ok(Ok(())) => Ok(ctx.positions.rls_to_range(&span.file, span.range)),
+            Ok(Err(message)) => {
+                out.failure_message(id, ErrorCode::InvalidParams, ctx.message(message));
+                Err(())
+            }
+            Err(_) => {
+                out.failure_message(id, ErrorCode::InternalError, ctx.message(Message::NothingToRename));
+                Err(())
+            }
+        }
+    }
+}
+
+// Identifiers which can't be renamed, because they aren't names.
+const RUST_KEYWORDS: &'static [&'static str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while",
+];
+
+// Keywords reserved for future use, which also need to be raw identifiers.
+const RESERVED_KEYWORDS: &'static [&'static str] = &[
+    "abstract", "async", "await", "become", "box", "do", "dyn", "final", "macro", "override",
+    "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+// Keywords which can't be raw identifiers either.
+const CANNOT_BE_RAW: &'static [&'static str] = &["self", "Self", "super", "crate", "_"];
+
+// The span of the identifier `name` in the source at `span`: the span itself if
+// that is just the identifier (possibly raw), otherwise the only occurrence of
+// the identifier in the (single line) span, e.g. of a macro invocation. `None`
+// if there is no such occurrence, e.g. for a span in the macro definition.
+fn identifier_span(vfs: &Vfs, span: &Span, name: &str) -> Option<Span> {
+    let text = vfs.load_span(span.clone()).ok()?;
+    let raw_name = format!("r#{}", name);
+    if text == name || text == raw_name {
+        return Some(span.clone());
+    }
+    if span.range.row_start != span.range.row_end {
+        return None;
+    }
+
+    let start = find_identifier(&text, name)?;
+    let mut result = span.clone();
+    result.range.col_start = span::Column::new_zero_indexed(span.range.col_start.0 + start);
+    result.range.col_end = span::Column::new_zero_indexed(result.range.col_start.0 + name.chars().count() as u32);
+    Some(result)
+}
+
+// The column (in chars) of the only occurrence of the identifier `name` as a
+// whole token in `text`.
+fn find_identifier(text: &str, name: &str) -> Option<u32> {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut occurrences = text.match_indices(name).filter(|&(i, _)| {
+        !text[..i].chars().next_back().map_or(false, &is_ident_char) &&
+            !text[i + name.len()..].chars().next().map_or(false, &is_ident_char)
+    });
+    match (occurrences.next(), occurrences.next()) {
+        (Some((i, _)), None) => Some(text[..i].chars().count() as u32),
+        _ => None,
+    }
+}
+
+const PRIMITIVE_TYPES: &'static [&'static str] = &[
+    "bool", "char", "str", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64",
+    "u128", "usize", "f32", "f64",
+];
+
 /// Turn wildcard style glob imports (`use foo::*`) into an import of each item
 /// that is actually used (`use foo::{Bar, Quux}`).
+///
+/// Expands the selected glob, every glob on the line of the cursor (e.g. in
+/// `use foo::{bar::*, baz::*};`) or, with `all`, every glob import of the file,
+/// with a single edit.
 pub struct Deglob;
 
 impl<'a> Action<'a> for Deglob {
-    type Params = Location;
+    type Params = DeglobParams;
     const METHOD: &'static str = "rustWorkspace/deglob";
 
     fn new(_: &'a mut LsState) -> Self {
@@ -450,102 +1492,101 @@ impl<'a> Action<'a> for Deglob {
 }
 
 impl<'a> RequestAction<'a> for Deglob {
-    type Response = Ack;
-    fn handle<O: Output>(&mut self, id: usize, location: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+    type Response = NoResponse;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         let ctx = ctx.inited();
-        let span = ls_util::location_to_rls(location.clone());
-        let mut span = ignore_non_file_uri!(span, &location.uri, "deglob")?;
+        let file_path = parse_file_path!(ctx, &params.uri, "deglob", id, out)?;
+        let range = ctx.positions.range_to_rls(&file_path, params.range);
 
-        trace!("deglob {:?}", span);
+        trace!("deglob {:?} {:?} (all: {})", file_path, range, params.all);
 
-        // Start by checking that the user has selected a glob import.
-        if span.range.start() == span.range.end() {
-            // search for a glob in the line
-            let vfs = ctx.vfs.clone();
-            let line = match vfs.load_line(&span.file, span.range.row_start) {
-                Ok(l) => l,
-                Err(_) => {
-                    out.failure_message(id, ErrorCode::InvalidParams, "Could not retrieve line from VFS.");
+        let globs = if !params.all && range.start() != range.end() {
+            vec![Span::from_range(range, file_path.clone())]
+        } else {
+            let globs = match ctx.vfs.with_file(&file_path, |text| imports::glob_imports(text)) {
+                Ok(globs) => globs,
+                Err(e) => {
+                    debug!("Deglob failed: {:?}", e);
+                    out.failure_message(id, ErrorCode::InternalError, ctx.message(Message::CouldNotOpenFile));
                     return Err(());
                 }
             };
-
-            // search for exactly one "::*;" in the line. This should work fine for formatted text, but
-            // multiple use statements could be in the same line, then it is not possible to find which
-            // one to deglob.
-            let matches: Vec<_> = line.char_indices().filter(|&(_, chr)| chr == '*').collect();
-            if matches.len() == 0 {
-                out.failure_message(id, ErrorCode::InvalidParams, "No glob in selection.");
-                return Err(());
-            } else if matches.len() > 1 {
-                out.failure_message(id, ErrorCode::InvalidParams, "Multiple globs in selection.");
-                return Err(());
-            }
-            let index = matches[0].0 as u32;
-            span.range.col_start = span::Column::new_zero_indexed(index);
-            span.range.col_end = span::Column::new_zero_indexed(index+1);
+            globs.into_iter()
+                .filter(|&(row, _)| params.all || row == range.row_start.0 as usize)
+                .map(|(row, col)| {
+                    let row = span::Row::new_zero_indexed(row as u32);
+                    let col = col as u32;
+                    Span::from_positions(span::Position::new(row, span::Column::new_zero_indexed(col)),
+                                         span::Position::new(row, span::Column::new_zero_indexed(col + 1)),
+                                         file_path.clone())
+                })
+                .collect()
+        };
+        if globs.is_empty() {
+            out.failure_message(id, ErrorCode::InvalidParams, ctx.message(Message::NoGlob));
+            return Err(());
         }
 
         // Save-analysis exports the deglobbed version of a glob import as its type string.
         let vfs = ctx.vfs.clone();
         let analysis = ctx.analysis.clone();
-        let out_clone = out.clone();
-        let span_ = span.clone();
-
         let receiver = receive_from_thread(move || {
-            match vfs.load_span(span_.clone()) {
-                Ok(ref s) if s != "*" => {
-                    out_clone.failure_message(id, ErrorCode::InvalidParams, "Not a glob");
-                    return Err("Not a glob");
-                }
-                Err(e) => {
-                    debug!("Deglob failed: {:?}", e);
-                    out_clone.failure_message(id, ErrorCode::InternalError, "Couldn't open file");
-                    return Err("Couldn't open file");
-                }
-                _ => {}
-            }
-
-            let ty = analysis.show_type(&span_);
-            ty.map_err(|_| {
-                out_clone.failure_message(id, ErrorCode::InternalError, "Couldn't get info from analysis");
-                "Couldn't get info from analysis"
-            })
+            globs.into_iter().map(|span| {
+                let names = match vfs.load_span(span.clone()) {
+                    Ok(ref s) if s != "*" => Err(Message::NotAGlob),
+                    Err(e) => {
+                        debug!("Deglob failed: {:?}", e);
+                        Err(Message::CouldNotOpenFile)
+                    }
+                    Ok(_) => analysis.show_type(&span).map_err(|_| Message::NoAnalysisInfo),
+                };
+                (span, names)
+            }).collect::<Vec<_>>()
         });
 
-        let result = receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT));
-        let mut deglob_str = match result {
-            Ok(Ok(s)) => s,
-            _ => {
-                return Err(());
-            }
+        let results = match receiver.recv_timeout(Duration::from_millis(::COMPILER_TIMEOUT)) {
+            Ok(results) => results,
+            Err(_) => return Err(()),
         };
 
-        // Handle multiple imports.
-        if deglob_str.contains(',') {
-            deglob_str = format!("{{{}}}", deglob_str);
+        // Any glob which can't be expanded fails the request, unless expanding
+        // all of them, where those are left alone.
+        let mut edits = vec![];
+        let mut error = None;
+        for (span, names) in results {
+            match names {
+                Ok(names) => edits.push(TextEdit {
+                    range: ctx.positions.rls_to_range(&span.file, span.range),
+                    new_text: imports::glob_replacement(&names),
+                }),
+                Err(message) => {
+                    debug!("Could not deglob {:?}: {:?}", span, message);
+                    error = error.or(Some(message));
+                }
+            }
         }
-
-        // Send a workspace edit to make the actual change.
-        // FIXME should handle the response
-        let output = serde_json::to_string(
-            &RequestMessage::new(out.provide_id(),
-                                 "workspace/applyEdit".to_owned(),
-                                 ApplyWorkspaceEditParams { edit: make_workspace_edit(ls_util::rls_to_location(&span), deglob_str) })
-        ).unwrap();
-        out.response(output);
-
-        // Nothing to actually send in the response.
-        Ok(Ack)
+        if let Some(message) = error {
+            if !params.all || edits.is_empty() {
+                let code = if message == Message::NotAGlob { ErrorCode::InvalidParams } else { ErrorCode::InternalError };
+                out.failure_message(id, code, ctx.message(message));
+                return Err(());
+            }
+        }
+        edits.sort_by_key(|e| (e.range.start.line, e.range.start.character));
+
+        // Send a workspace edit to make the actual change, the request succeeds
+        // once the client applied it.
+        let mut changes = HashMap::new();
+        changes.insert(params.uri, edits);
+        apply_edit_and_respond(id, WorkspaceEdit { changes }, ctx, out);
+        Ok(NoResponse)
     }
 }
 
 /// Execute a command within the workspace.
 ///
 /// These are *not* shell commands, but commands given by the client and
-/// performed by the RLS.
-///
-/// Currently, only the "rls.applySuggestion" command is supported.
+/// performed by the RLS, see `actions::commands` for the supported commands.
 pub struct ExecuteCommand;
 
 impl<'a> Action<'a> for ExecuteCommand {
@@ -558,34 +1599,29 @@ impl<'a> Action<'a> for ExecuteCommand {
 }
 
 impl<'a> RequestAction<'a> for ExecuteCommand {
-    type Response = Ack;
-    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, _ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
-        match &*params.command {
-            "rls.applySuggestion" => {
-                let location = serde_json::from_value(params.arguments[0].clone()).expect("Bad argument");
-                let new_text = serde_json::from_value(params.arguments[1].clone()).expect("Bad argument");
-                self.apply_suggestion(id, location, new_text, out)
-            }
-            c => {
-                debug!("Unknown command: {}", c);
-                out.failure_message(id, ErrorCode::MethodNotFound, "Unknown command");
-                Err(())
-            }
+    type Response = NoResponse;
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        // Restarting replaces the context, which the other commands only
+        // borrow.
+        if params.command == commands::RESTART_SERVER {
+            return Reload.handle(id, NoParams, ctx, out);
         }
-    }
-}
 
-impl ExecuteCommand {
-    fn apply_suggestion<O: Output>(&self, _id: usize, location: Location, new_text: String, out: O) -> Result<Ack, ()> {
-        trace!("apply_suggestion {:?} {}", location, new_text);
-        // FIXME should handle the response
-        let output = serde_json::to_string(
-            &RequestMessage::new(out.provide_id(),
-                                 "workspace/applyEdit".to_owned(),
-                                 ApplyWorkspaceEditParams { edit: make_workspace_edit(location, new_text) })
-        ).unwrap();
-        out.response(output);
-        Ok(Ack)
+        let ctx = ctx.inited();
+        if let Some(result) = commands::execute(id, &params.command, params.arguments, ctx, out.clone()) {
+            return result;
+        }
+
+        debug!("Unknown command: {}", params.command);
+        out.failure(Id::Num(id as u64), RpcError {
+            code: ErrorCode::MethodNotFound,
+            message: ctx.message(Message::UnknownCommand),
+            data: Some(json!({
+                "command": params.command,
+                "commands": commands::command_names()
+            })),
+        });
+        Err(())
     }
 }
 
@@ -604,40 +1640,77 @@ impl<'a> Action<'a> for CodeAction {
 
 impl<'a> RequestAction<'a> for CodeAction {
     type Response = Vec<Command>;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+    fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         trace!("code_action {:?}", params);
 
         let ctx = ctx.inited();
-        let file_path = parse_file_path!(&params.text_document.uri, "code_action")?;
-
-        match ctx.previous_build_results.lock().unwrap().get(&file_path) {
-            Some(ref diagnostics) => {
-                let suggestions = diagnostics.iter().filter(|&&(ref d, _)| d.range == params.range).flat_map(|&(_, ref ss)| ss.iter());
-                let mut cmds = vec![];
-                for s in suggestions {
-                    let span = Location {
-                        uri: params.text_document.uri.clone(),
-                        range: s.range,
-                    };
-                    let span = serde_json::to_value(&span).unwrap();
-                    let new_text = serde_json::to_value(&s.new_text).unwrap();
-                    let cmd = Command {
-                        title: s.label.clone(),
-                        command: "rls.applySuggestion".to_owned(),
-                        arguments: Some(vec![span, new_text]),
-                    };
-                    cmds.push(cmd);
-                }
-
-                Ok(cmds)
+        let file_path = parse_file_path!(ctx, &params.text_document.uri, "code_action", id, out)?;
+        let uri = &params.text_document.uri;
+
+        let mut cmds = vec![];
+        if let Some(ref diagnostics) = ctx.previous_build_results.lock().unwrap().get(&file_path) {
+            let suggestions = diagnostics.iter().filter(|&&(ref d, _)| d.diagnostic.range == params.range).flat_map(|&(_, ref ss)| ss.iter());
+            for s in suggestions {
+                cmds.push(apply_suggestion_command(s.label.clone(), uri, s.range, &s.new_text));
             }
-            None => {
-                Ok(vec![])
+        }
+
+        if manifest::is_manifest(&file_path) {
+            cmds.extend(remove_dependency_commands(ctx, &file_path, uri, params.range));
+            return Ok(cmds);
+        }
+
+        // Rewrites of the `use` statements in the selection.
+        let (start, end) = (params.range.start.line as usize, params.range.end.line as usize);
+        let rewrites = ctx.vfs.with_file(&file_path, |text| {
+            vec![(Message::NestImports, imports::nest_imports(text, start, end)),
+                 (Message::FlattenImports, imports::flatten_imports(text, start))]
+        }).unwrap_or_default();
+        for (title, edit) in rewrites {
+            if let Some(edit) = edit {
+                let range = ctx.positions.char_range_to_client(&file_path, edit.range);
+                cmds.push(apply_suggestion_command(ctx.message(title), uri, range, &edit.new_text));
             }
         }
+
+        Ok(cmds)
+    }
+}
+
+// A command replacing `range` of the document by `new_text`.
+fn apply_suggestion_command(title: String, uri: &Url, range: Range, new_text: &str) -> Command {
+    let location = Location {
+        uri: uri.clone(),
+        range,
+    };
+    Command {
+        title,
+        command: "rls.applySuggestion".to_owned(),
+        arguments: Some(vec![serde_json::to_value(&location).unwrap(), serde_json::to_value(new_text).unwrap()]),
     }
 }
 
+// Quick fixes removing the unused dependency reported at `range` of the
+// manifest `file`.
+fn remove_dependency_commands(ctx: &InitActionContext, file: &Path, uri: &Url, range: Range) -> Vec<Command> {
+    if !ctx.config.lock().unwrap().unused_dependencies {
+        return vec![];
+    }
+    let used = match file.parent().and_then(|dir| ctx.used_crates.package_uses(dir)) {
+        Some(used) => used,
+        None => return vec![],
+    };
+    let lib_names = ctx.build_queue.lib_names();
+    let unused = ctx.vfs.with_file(file, |text| manifest::unused_dependencies(text, &used, &lib_names)).unwrap_or_default();
+    unused.into_iter().filter(|d| {
+        let name = Range::new(Position::new(d.row as u64, d.col_start as u64), Position::new(d.row as u64, d.col_end as u64));
+        ctx.positions.char_range_to_client(file, name) == range
+    }).map(|d| {
+        let title = format!("{} `{}`", ctx.message(Message::RemoveDependency), d.name);
+        apply_suggestion_command(title, uri, ctx.positions.char_range_to_client(file, d.removal), "")
+    }).collect()
+}
+
 /// Pretty print the given document.
 pub struct Formatting;
 
@@ -651,7 +1724,7 @@ impl<'a> Action<'a> for Formatting {
 }
 
 impl<'a> RequestAction<'a> for Formatting {
-    type Response = [TextEdit; 1];
+    type Response = Vec<TextEdit>;
     fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         reformat(id, params.text_document, None, &params.options, ctx, out)
     }
@@ -670,79 +1743,203 @@ impl<'a> Action<'a> for RangeFormatting {
 }
 
 impl<'a> RequestAction<'a> for RangeFormatting {
-    type Response = [TextEdit; 1];
+    type Response = Vec<TextEdit>;
     fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
         reformat(id, params.text_document, Some(params.range), &params.options, ctx, out)
     }
 }
 
-fn reformat<O: Output>(id: usize, doc: TextDocumentIdentifier, selection: Option<Range>, opts: &FormattingOptions, ctx: &mut ActionContext, out: O) -> Result<[TextEdit; 1], ()> {
+/// Format a document before it's saved, if `format_on_save` is set. The
+/// client applies the edits and then saves, so the build started by the save
+/// sees the formatted text.
+pub struct WillSaveWaitUntil;
+
+impl<'a> Action<'a> for WillSaveWaitUntil {
+    type Params = WillSaveTextDocumentParams;
+    const METHOD: &'static str = "textDocument/willSaveWaitUntil";
+
+    fn new(_: &'a mut LsState) -> Self {
+        WillSaveWaitUntil
+    }
+}
+
+impl<'a> RequestAction<'a> for WillSaveWaitUntil {
+    type Response = Vec<TextEdit>;
+    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let (format_on_save, ignore) = {
+            let config = ctx.config.lock().unwrap();
+            (config.format_on_save, config.format_ignore.clone())
+        };
+        // Formatting after a delay would move the text around while typing.
+        if !format_on_save || params.reason == TextDocumentSaveReason::AfterDelay {
+            return Ok(vec![]);
+        }
+        let path = match parse_file_path!(ctx, &params.text_document.uri, "will_save_wait_until") {
+            Ok(path) => path,
+            Err(()) => return Ok(vec![]),
+        };
+        // Files not to be formatted (e.g. generated ones) are left as they are,
+        // like by `rls.formatWorkspace`.
+        let relative = path.strip_prefix(&ctx.current_project).unwrap_or(&path);
+        if path.extension().map_or(true, |ext| ext != "rs") || format::is_ignored(relative, &ignore) {
+            return Ok(vec![]);
+        }
+        // A document which can't be formatted is saved as it is, the save
+        // mustn't fail (or complain every time) because of a syntax error.
+        let text = match ctx.vfs.with_file(&path, |s| s.to_owned()) {
+            Ok(text) => text,
+            Err(_) => return Ok(vec![]),
+        };
+        let options = FormattingOptions {
+            tab_size: 4,
+            insert_spaces: true,
+            properties: HashMap::new(),
+        };
+        Ok(format_edits(ctx, &path, text, None, &options).unwrap_or_else(|e| {
+            debug!("Formatting on save failed: {}", e);
+            vec![]
+        }))
+    }
+}
+
+fn reformat<O: Output>(id: usize, doc: TextDocumentIdentifier, selection: Option<Range>, opts: &FormattingOptions, ctx: &mut ActionContext, out: O) -> Result<Vec<TextEdit>, ()> {
     trace!("Reformat: {} {:?} {:?} {} {}", id, doc, selection, opts.tab_size, opts.insert_spaces);
     let ctx = ctx.inited();
-    let path = parse_file_path!(&doc.uri, "reformat")?;
+    let path = parse_file_path!(ctx, &doc.uri, "reformat", id, out)?;
 
-    let input = match ctx.vfs.load_file(&path) {
-        Ok(FileContents::Text(s)) => FmtInput::Text(s),
-        Ok(_) => {
-            debug!("Reformat failed, found binary file");
-            out.failure_message(id, ErrorCode::InternalError, "Reformat failed to complete successfully");
-            return Err(());
-        }
+    // Rustfmt needs its own copy of the text, but we only make one.
+    let text = match ctx.vfs.with_file(&path, |s| s.to_owned()) {
+        Ok(result) => result,
         Err(e) => {
             debug!("Reformat failed: {:?}", e);
-            out.failure_message(id, ErrorCode::InternalError, "Reformat failed to complete successfully");
+            out.failure_message(id, ErrorCode::InternalError, ctx.message(Message::ReformatFailed));
             return Err(());
         }
     };
+    match format_edits(ctx, &path, text, selection, opts) {
+        Ok(edits) => Ok(edits),
+        Err(e) => {
+            debug!("Reformat failed: {}", e);
+            let message = format!("{}: {}", ctx.message(Message::ReformatFailed), e);
+            if ctx.config.lock().unwrap().show_format_errors {
+                out.notify(NotificationMessage::new(
+                    NOTIFICATION_SHOW_MESSAGE,
+                    Some(NotificationParams::ShowMessage(ShowMessageParams {
+                        typ: MessageType::Warning,
+                        message: message.clone(),
+                    })),
+                ));
+            }
+            out.failure_message(id, ErrorCode::InternalError, message);
+            Err(())
+        }
+    }
+}
+
+// The edits formatting `text`, the text of the document at `path`, or of the
+// lines of `selection` in it. With the `rustfmt_path` option that rustfmt
+// binary formats it, otherwise the bundled rustfmt (if compiled in).
+fn format_edits(ctx: &InitActionContext, path: &Path, text: String, selection: Option<Range>, opts: &FormattingOptions) -> Result<Vec<TextEdit>, String> {
+    if format::skips_formatting(&text) {
+        return Ok(vec![]);
+    }
+
+    // Rustfmt formats whole lines (one-indexed), those of the statements or
+    // items in the selection. A selection ending at the start of a line
+    // doesn't include that line.
+    let rows = selection.map(|r| {
+        let last = if r.end.line > r.start.line && r.end.character == 0 { r.end.line - 1 } else { r.end.line };
+        let (start, end) = format::expand_to_statements(&text, r.start.line as usize, last as usize);
+        (start + 1, end + 1)
+    });
+
+    let rustfmt_path = ctx.config.lock().unwrap().rustfmt_path.clone();
+    let formatted = match rustfmt_path {
+        Some(rustfmt) => {
+            let dir = path.parent().unwrap_or(&ctx.current_project);
+            let toml = find_rustfmt_toml(dir);
+            // The `rustfmt.toml` closest to the file wins over the client's
+            // options.
+            let overrides = format::client_overrides(toml.as_ref().map(|toml| &**toml),
+                                                     !opts.insert_spaces,
+                                                     opts.tab_size);
+            let mut args = vec![];
+            if let Some(toml) = toml {
+                args.push("--config-path".to_owned());
+                args.push(toml.to_string_lossy().into_owned());
+            }
+            if !overrides.is_empty() {
+                args.push("--config".to_owned());
+                args.push(overrides.join(","));
+            }
+            if let Some((start, end)) = rows {
+                args.push("--file-lines".to_owned());
+                args.push(json!([{ "file": "stdin", "range": [start, end] }]).to_string());
+            }
+            format::format_with_binary(&rustfmt, &text, dir, &args)
+        }
+        None => format_with_library(path, text, rows, opts),
+    };
+
+    // Only the lines which changed are replaced, so the client keeps the
+    // cursor position and marks elsewhere. Note that we don't need to update
+    // the VFS, the client echos back the change to us.
+    let mut edits = formatted.and_then(|formatted| {
+        ctx.vfs.with_file(path, |original| {
+            // Never replace a document with nothing.
+            if formatted.trim().is_empty() && !original.trim().is_empty() {
+                Err("rustfmt returned no output".to_owned())
+            } else {
+                Ok(line_edits(original, &formatted))
+            }
+        }).map_err(|e| format!("{:?}", e)).and_then(|edits| edits)
+    })?;
+    for edit in &mut edits {
+        edit.range = ctx.positions.char_range_to_client(path, edit.range);
+    }
+    Ok(edits)
+}
 
-    let range_whole_file = ls_util::range_from_vfs_file(&ctx.vfs, &path);
-    let mut config = ctx.fmt_config.get_rustfmt_config().clone();
+// Formats `text`, the text of the document at `path` (or its one-indexed
+// `rows`), with the bundled rustfmt. The `rustfmt.toml` closest to the file
+// wins over the client's options.
+#[cfg(feature = "rustfmt")]
+fn format_with_library(path: &Path, text: String, rows: Option<(usize, usize)>, opts: &FormattingOptions) -> Result<String, String> {
+    let mut config = FmtConfig::for_file(path).get_rustfmt_config().clone();
     if !config.was_set().hard_tabs() {
         config.set().hard_tabs(!opts.insert_spaces);
     }
     if !config.was_set().tab_spaces() {
         config.set().tab_spaces(opts.tab_size as usize);
     }
-
-    if let Some(r) = selection {
-        let range_of_rls = ls_util::range_to_rls(r).one_indexed();
-        let range = RustfmtRange::new(range_of_rls.row_start.0 as usize, range_of_rls.row_end.0 as usize);
+    if let Some((start, end)) = rows {
         let mut ranges = HashMap::new();
-        ranges.insert("stdin".to_owned(), vec![range]);
+        ranges.insert("stdin".to_owned(), vec![RustfmtRange::new(start, end)]);
         let file_lines = FileLines::from_ranges(ranges);
         config.set().file_lines(file_lines);
     };
 
     let mut buf = Vec::<u8>::new();
-    match format_input(input, &config, Some(&mut buf)) {
+    match format_input(FmtInput::Text(text), &config, Some(&mut buf)) {
+        // format_input returns Ok even if there are any errors, i.e., parsing errors.
+        Ok((summary, ..)) if summary.has_no_errors() => String::from_utf8(buf).map_err(|e| e.to_string()),
+        Ok((ref summary, ..)) if summary.has_parsing_errors() => Err("the document has syntax errors".to_owned()),
         Ok((summary, ..)) => {
-            // format_input returns Ok even if there are any errors, i.e., parsing errors.
-            if summary.has_no_errors() {
-                // Note that we don't need to update the VFS, the client
-                // echos back the change to us.
-                let text = String::from_utf8(buf).unwrap();
-
-                // If Rustfmt returns range of text that changed,
-                // we will be able to pass only range of changed text to the client.
-                Ok([TextEdit {
-                    range: range_whole_file,
-                    new_text: text,
-                }])
-            } else {
-                debug!("reformat: format_input failed: has errors, summary = {:?}", summary);
-
-                out.failure_message(id, ErrorCode::InternalError, "Reformat failed to complete successfully");
-                Err(())
-            }
-        }
-        Err(e) => {
-            debug!("Reformat failed: {:?}", e);
-            out.failure_message(id, ErrorCode::InternalError, "Reformat failed to complete successfully");
-            Err(())
+            debug!("format_input failed: has errors, summary = {:?}", summary);
+            Err("the document could not be formatted".to_owned())
         }
+        Err(e) => Err(format!("{:?}", e)),
     }
 }
 
+// Without the bundled rustfmt only a rustfmt binary formats. Formatting isn't
+// advertised then, but refuse politely anyway.
+#[cfg(not(feature = "rustfmt"))]
+fn format_with_library(_path: &Path, _text: String, _rows: Option<(usize, usize)>, _opts: &FormattingOptions) -> Result<String, String> {
+    Err("the RLS was built without rustfmt, set `rustfmt_path` to format with a rustfmt binary".to_owned())
+}
+
 /// Resolve additional information about the given completion item
 /// suggestion. This allows completion items to be yielded as quickly as
 /// possible, with more details (which are presumably more expensive to compute)
@@ -760,41 +1957,185 @@ impl<'a> Action<'a> for ResolveCompletion {
 
 impl<'a> RequestAction<'a> for ResolveCompletion {
     type Response = CompletionItem;
-    fn handle<O: Output>(&mut self, _id: usize, params: Self::Params, _ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
-        // currently, we safely ignore this as a pass-through since we fully handle
-        // textDocument/completion.  In the future, we may want to use this method as a
-        // way to more lazily fill out completion information
+    fn handle<O: Output>(&mut self, _id: usize, mut params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+        // The completion list only has names and racer's one line signatures,
+        // the full signature and docs are looked up in the source of the
+        // definition when an item is shown, or dug out of `cargo doc` output.
+        let ctx = ctx.inited();
+        if let Some((file, offset)) = params.data.as_ref().and_then(racer_lookup::completion_definition) {
+            let found = ctx.vfs.with_file(&file, |text| {
+                if offset > text.len() || !text.is_char_boundary(offset) {
+                    return (None, None);
+                }
+                (hover::definition_signature(text, offset), hover::doc_comment(text, offset))
+            });
+            if let Ok((signature, docs)) = found {
+                if signature.is_some() {
+                    params.detail = signature;
+                }
+                if params.documentation.is_none() {
+                    let markdown = ctx.client.markdown_hover();
+                    params.documentation = docs.map(|d| if markdown { d } else { hover::plain_text(&d) });
+                }
+            }
+        }
+        let doc_dirs = {
+            let config = ctx.config.lock().unwrap();
+            if config.cargo_doc_fallback && params.documentation.is_none() {
+                cargo_doc::doc_dirs(&ctx.current_project, &config)
+            } else {
+                vec![]
+            }
+        };
+        if !doc_dirs.is_empty() {
+            params.documentation = params.data.as_ref()
+                .and_then(|data| cargo_doc::docs_from_completion_data(&doc_dirs, data));
+        }
         Ok(params)
     }
 }
 
+/// Get the status of the RLS: the build state, indexed crates, age of the
+/// analysis data and memory usage.
+pub struct Status;
 
-fn racer_coord(line: span::Row<span::OneIndexed>,
-               column: span::Column<span::ZeroIndexed>)
-               -> racer::Coordinate {
-    racer::Coordinate {
-        line: line.0 as usize,
-        column: column.0 as usize,
+impl<'a> Action<'a> for Status {
+    type Params = NoParams;
+    const METHOD: &'static str = "rls/status";
+
+    fn new(_: &'a mut LsState) -> Self {
+        Status
     }
 }
 
-fn from_racer_coord(coord: racer::Coordinate) -> (span::Row<span::OneIndexed>,span::Column<span::ZeroIndexed>) {
-    (span::Row::new_one_indexed(coord.line as u32), span::Column::new_zero_indexed(coord.column as u32))
+impl<'a> RequestAction<'a> for Status {
+    type Response = ServerStatus;
+
+    fn handle<O: Output>(&mut self, _id: usize, _params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let status = ctx.status.lock().unwrap().status();
+        Ok(status)
+    }
 }
 
-fn pos_to_racer_location(pos: Position) -> racer::Location {
-    let pos = ls_util::position_to_rls(pos);
-    racer::Location::Coords(racer_coord(pos.row.one_indexed(), pos.col))
+/// Get how often each method was handled and how long that took.
+pub struct MetricsRequest;
+
+impl<'a> Action<'a> for MetricsRequest {
+    type Params = NoParams;
+    const METHOD: &'static str = "rls/metrics";
+
+    fn new(_: &'a mut LsState) -> Self {
+        MetricsRequest
+    }
 }
 
-fn location_from_racer_match(a_match: racer::Match) -> Option<Location> {
-    let source_path = &a_match.filepath;
+impl<'a> RequestAction<'a> for MetricsRequest {
+    type Response = ServerMetrics;
 
-    a_match.coords.map(|coord| {
-        let (row, col) = from_racer_coord(coord);
-        let loc = span::Location::new(row.zero_indexed(), col, source_path);
-        ls_util::rls_location_to_location(&loc)
-    })
+    fn handle<O: Output>(&mut self, _id: usize, _params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        Ok(ctx.metrics.report())
+    }
+}
+
+/// Get the diagnostics currently published, grouped by file, with their number
+/// per severity.
+pub struct DiagnosticsRequest;
+
+impl<'a> Action<'a> for DiagnosticsRequest {
+    type Params = NoParams;
+    const METHOD: &'static str = "rls/diagnostics";
+
+    fn new(_: &'a mut LsState) -> Self {
+        DiagnosticsRequest
+    }
+}
+
+impl<'a> RequestAction<'a> for DiagnosticsRequest {
+    type Response = DiagnosticsSummary;
+
+    fn handle<O: Output>(&mut self, _id: usize, _params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let filter = DiagnosticFilter::from_config(&ctx.config.lock().unwrap());
+        let results = ctx.previous_build_results.lock().unwrap();
+        Ok(diagnostics_summary(&results, &filter, &ctx.documents))
+    }
+}
+
+/// List the files changed since the analysis data was last reloaded, and the
+/// crates they belong to, so clients can explain stale results.
+pub struct ChangedSinceBuildRequest;
+
+impl<'a> Action<'a> for ChangedSinceBuildRequest {
+    type Params = NoParams;
+    const METHOD: &'static str = "rustWorkspace/changedSinceBuild";
+
+    fn new(_: &'a mut LsState) -> Self {
+        ChangedSinceBuildRequest
+    }
+}
+
+impl<'a> RequestAction<'a> for ChangedSinceBuildRequest {
+    type Response = ChangedSinceBuild;
+
+    fn handle<O: Output>(&mut self, _id: usize, _params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+        let ctx = ctx.inited();
+        let (since, analysis_age_ms) = {
+            let status = ctx.status.lock().unwrap();
+            (status.last_analysis_time(), status.status().analysis_age_ms)
+        };
+        let edited = ctx.build_queue.dirty_files();
+        let sources = collect_source_files(&ctx.current_project);
+        let (files, dirty_crates) = changed_files(&edited, &sources, since, &ctx.current_project, &ctx.documents);
+        Ok(ChangedSinceBuild {
+            analysis_age_ms,
+            files,
+            dirty_crates,
+        })
+    }
+}
+
+/// Start over as if the RLS had been restarted, but keeping the documents the
+/// client opened, see `ActionContext::restart`. The `rls.restartServer`
+/// command does the same.
+pub struct Reload;
+
+impl<'a> Action<'a> for Reload {
+    type Params = NoParams;
+    const METHOD: &'static str = "rls/reload";
+
+    fn new(_: &'a mut LsState) -> Self {
+        Reload
+    }
+}
+
+impl<'a> RequestAction<'a> for Reload {
+    type Response = NoResponse;
+
+    fn handle<O: Output>(&mut self, id: usize, _params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<Self::Response, ()> {
+        // Respond first, like to the `initialize` request the new build
+        // follows.
+        out.success(id, &Ack);
+        ctx.restart(out);
+        Ok(NoResponse)
+    }
+}
+
+// Asks the client to apply `edit` on another thread (the response arrives on
+// the main loop), then responds to request `id` with whether it was applied.
+pub(crate) fn apply_edit_and_respond<O: Output>(id: usize, edit: WorkspaceEdit, ctx: &InitActionContext, out: O) {
+    let pending = ctx.pending.clone();
+    let not_applied = ctx.message(Message::EditNotApplied);
+    thread::spawn(move || {
+        match client::apply_edit(edit, &pending, &out) {
+            Ok(()) => out.success(id, &Ack),
+            Err(e) => {
+                debug!("edit not applied: {:?}", e);
+                out.failure_message(id, ErrorCode::InternalError, not_applied);
+            }
+        }
+    });
 }
 
 lazy_static! {
@@ -817,3 +2158,70 @@ pub fn receive_from_thread<T, F>(work_fn: F) -> mpsc::Receiver<T>
     });
     receiver
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_identifier() {
+        assert_eq!(find_identifier("println!(\"{}\", foo)", "foo"), Some(15));
+        assert_eq!(find_identifier("bar!(r#foo)", "foo"), Some(7));
+        assert_eq!(find_identifier("bar!(föö, foo)", "foo"), Some(10));
+        assert_eq!(find_identifier("bar!(foo_bar, foo2)", "foo"), None);
+        assert_eq!(find_identifier("bar!(foo, foo)", "foo"), None);
+    }
+
+    #[test]
+    fn test_split_symbol_query() {
+        assert_eq!(split_symbol_query("Deserialize", false), ("Deserialize".to_owned(), false));
+        assert_eq!(split_symbol_query("Deserialize", true), ("Deserialize".to_owned(), true));
+        assert_eq!(split_symbol_query("#Deserialize", false), ("Deserialize".to_owned(), true));
+        assert_eq!(split_symbol_query("# Deserialize", false), ("Deserialize".to_owned(), true));
+    }
+
+    #[test]
+    fn test_is_lifetime_name() {
+        assert!(is_lifetime_name("a"));
+        assert!(is_lifetime_name("outer_2"));
+        assert!(!is_lifetime_name(""));
+        assert!(!is_lifetime_name("2a"));
+        assert!(!is_lifetime_name("static"));
+        assert!(!is_lifetime_name("_"));
+        assert!(!is_lifetime_name("loop"));
+        assert!(!is_lifetime_name("a b"));
+    }
+
+    #[test]
+    fn test_access_kind() {
+        assert_eq!(access_kind("    x = 1;", 4, 5), DocumentHighlightKind::Write);
+        assert_eq!(access_kind("    x += 1;", 4, 5), DocumentHighlightKind::Write);
+        assert_eq!(access_kind("    foo(&mut x);", 13, 14), DocumentHighlightKind::Write);
+        assert_eq!(access_kind("    x == 1;", 4, 5), DocumentHighlightKind::Read);
+        assert_eq!(access_kind("    y = x;", 8, 9), DocumentHighlightKind::Read);
+        assert_eq!(access_kind("    y = x >= 2;", 8, 9), DocumentHighlightKind::Read);
+        assert_eq!(access_kind("    ä.b = 1;", 6, 7), DocumentHighlightKind::Write);
+    }
+
+    #[test]
+    fn test_classify_reference() {
+        assert_eq!(classify_reference("    foo(1);", 4, 7), Some(ReferenceKind::Call));
+        assert_eq!(classify_reference("    x.foo::<Vec<u8>>();", 6, 9), Some(ReferenceKind::Call));
+        assert_eq!(classify_reference("fn foo() {", 3, 6), None);
+        assert_eq!(classify_reference("    let p = Point { x: 1 };", 12, 17), Some(ReferenceKind::StructLiteral));
+        assert_eq!(classify_reference("impl Point {", 5, 10), None);
+        assert_eq!(classify_reference("fn origin() -> Point {", 15, 20), None);
+        assert_eq!(classify_reference("    if done {", 7, 11), None);
+        assert_eq!(classify_reference("    let p: Point = q;", 11, 16), None);
+    }
+
+    #[test]
+    fn test_in_use_statement() {
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<Vec<_>>().into_iter();
+        assert!(in_use_statement("use foo::Bar;", lines(&[])));
+        assert!(in_use_statement("pub(crate) use foo::Bar;", lines(&[])));
+        assert!(in_use_statement("    Bar,", lines(&["    Baz,", "use foo::{"])));
+        assert!(!in_use_statement("    Bar::new();", lines(&["fn main() {", "use foo::Bar;"])));
+        assert!(!in_use_statement("    Bar::new();", lines(&["    let x = 1;"])));
+    }
+}
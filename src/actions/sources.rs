@@ -0,0 +1,101 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finding the source files of definitions outside the project.
+//!
+//! The analysis data of dependencies refers to their source in the cargo
+//! registry (`~/.cargo/registry/src`) by absolute path, which is used as is.
+//! That of the standard library refers to the paths its source had when it
+//! was built, e.g. `/checkout/src/libcore/option.rs` or just
+//! `src/libcore/option.rs`. These are mapped into the `rust-src` component of
+//! the sysroot, i.e. `lib/rustlib/src/rust/src/libcore/option.rs`.
+
+use build::current_sysroot;
+
+use std::path::{Path, PathBuf};
+
+/// Where the source of a file named in the analysis data is.
+#[derive(Debug, PartialEq)]
+pub enum SourceFile {
+    /// The file, as an absolute path.
+    Found(PathBuf),
+    /// A file of the standard library, but `rust-src` isn't installed.
+    StdMissing,
+    /// A relative path which isn't in the project.
+    Unknown,
+}
+
+/// Finds `file` from the analysis data of a build of `project_dir`, using
+/// `sysroot` (or the current one if not given) for the standard library.
+pub fn locate(file: &Path, project_dir: &Path, sysroot: Option<&str>) -> SourceFile {
+    if file.is_absolute() && file.exists() {
+        return SourceFile::Found(file.to_owned());
+    }
+    if file.is_relative() {
+        let local = project_dir.join(file);
+        if local.exists() {
+            return SourceFile::Found(local);
+        }
+    }
+    if let Some(std_path) = std_relative_path(file) {
+        let sysroot = sysroot.map(|s| s.to_owned()).or_else(current_sysroot);
+        return match sysroot.map(|s| rust_src_dir(Path::new(&s)).join(std_path)) {
+            Some(ref path) if path.exists() => SourceFile::Found(path.clone()),
+            _ => SourceFile::StdMissing,
+        };
+    }
+    if file.is_absolute() {
+        SourceFile::Found(file.to_owned())
+    } else {
+        SourceFile::Unknown
+    }
+}
+
+/// The source of the standard library, as installed by `rustup component add
+/// rust-src`.
+pub fn rust_src_dir(sysroot: &Path) -> PathBuf {
+    sysroot.join("lib").join("rustlib").join("src").join("rust").join("src")
+}
+
+// The path of a file of the standard library relative to the `src` directory
+// of the Rust repository, e.g. `libcore/option.rs`.
+fn std_relative_path(file: &Path) -> Option<PathBuf> {
+    let components: Vec<_> = file.components().map(|c| c.as_os_str()).collect();
+    let crate_dir = components.windows(3).position(|w| {
+        w[0] == "src" && w[1].to_str().map_or(false, |c| c.starts_with("lib"))
+    })? + 1;
+    Some(components[crate_dir..].iter().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_std_relative_path() {
+        assert_eq!(std_relative_path(Path::new("/checkout/src/libcore/option.rs")),
+                   Some(PathBuf::from("libcore/option.rs")));
+        assert_eq!(std_relative_path(Path::new("src/libstd/collections/hash/map.rs")),
+                   Some(PathBuf::from("libstd/collections/hash/map.rs")));
+        assert_eq!(std_relative_path(Path::new("/home/user/project/src/lib.rs")), None);
+        assert_eq!(std_relative_path(Path::new("src/main.rs")), None);
+    }
+
+    #[test]
+    fn test_locate() {
+        let sysroot = "/no/such/sysroot";
+        assert_eq!(locate(Path::new("src/libcore/option.rs"), Path::new("/no/such/project"), Some(sysroot)),
+                   SourceFile::StdMissing);
+        assert_eq!(locate(Path::new("src/foo.rs"), Path::new("/no/such/project"), Some(sysroot)),
+                   SourceFile::Unknown);
+        assert_eq!(locate(Path::new("/no/such/registry/foo-0.1.0/src/lib.rs"), Path::new("/no/such/project"), Some(sysroot)),
+                   SourceFile::Found(PathBuf::from("/no/such/registry/foo-0.1.0/src/lib.rs")));
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Keeps track of the state of the RLS, reported by the `rls/status` request
+//! and the `rustDocument/serverStatus` notifications.
+
+use lsp_data::{BuildState, CompilingProgress, IndexingProgress, NotificationMessage, NotificationParams, ServerStatus};
+use lsp_data::NOTIFICATION_SERVER_STATUS;
+use actions::memory::memory_usage;
+use server::Output;
+
+use std::time::{Instant, SystemTime};
+
+/// The state of builds and analysis data.
+pub struct StatusTracker {
+    // Only clients which ask for `rustDocument/serverStatus` get it.
+    send_notifications: bool,
+    running_builds: usize,
+    last_build_failed: bool,
+    indexed_crates: usize,
+    evicted_crates: Vec<String>,
+    last_analysis: Option<Instant>,
+    memory_budget: Option<u64>,
+    indexing: Option<IndexingProgress>,
+    compiling: Option<CompilingProgress>,
+}
+
+impl StatusTracker {
+    /// Construct a new tracker, before any build has run.
+    pub fn new() -> StatusTracker {
+        StatusTracker {
+            send_notifications: false,
+            running_builds: 0,
+            last_build_failed: false,
+            indexed_crates: 0,
+            evicted_crates: vec![],
+            last_analysis: None,
+            memory_budget: None,
+            indexing: None,
+            compiling: None,
+        }
+    }
+
+    /// Send `rustDocument/serverStatus` notifications from now on.
+    pub fn enable_notifications(&mut self) {
+        self.send_notifications = true;
+    }
+
+    /// The current state of builds.
+    pub fn build_state(&self) -> BuildState {
+        if self.running_builds > 0 {
+            BuildState::Building
+        } else if self.last_build_failed {
+            BuildState::Failed
+        } else {
+            BuildState::Idle
+        }
+    }
+
+    /// Record that a build was requested. Returns true if the build state
+    /// changed.
+    pub fn build_started(&mut self) -> bool {
+        let old_state = self.build_state();
+        self.running_builds += 1;
+        old_state != self.build_state()
+    }
+
+    /// Record that a build is done. `failed` is `None` if the build was
+    /// squashed. Returns true if the build state changed.
+    pub fn build_finished(&mut self, failed: Option<bool>) -> bool {
+        let old_state = self.build_state();
+        self.running_builds = self.running_builds.saturating_sub(1);
+        self.compiling = None;
+        if let Some(failed) = failed {
+            self.last_build_failed = failed;
+        }
+        old_state != self.build_state()
+    }
+
+    /// Record that the analysis data was reloaded for `indexed_crates` crates,
+    /// leaving out `evicted_crates` to stay within `memory_budget`.
+    pub fn analysis_reloaded(&mut self, indexed_crates: usize, evicted_crates: Vec<String>, memory_budget: Option<u64>) {
+        self.indexed_crates = indexed_crates;
+        self.evicted_crates = evicted_crates;
+        self.memory_budget = memory_budget;
+        self.last_analysis = Some(Instant::now());
+        self.indexing = None;
+    }
+
+    /// When the analysis data was last reloaded, if it ever was.
+    pub fn last_analysis_time(&self) -> Option<SystemTime> {
+        self.last_analysis.map(|t| SystemTime::now() - t.elapsed())
+    }
+
+    /// Record that `done` out of `total` crates' analysis data has been read
+    /// from disk.
+    pub fn indexing_progress(&mut self, done: usize, total: usize) {
+        self.indexing = Some(IndexingProgress { done, total });
+        self.compiling = None;
+    }
+
+    /// Record that Cargo started compiling `crate_name`, the `index`th out of
+    /// `total` crates of the running build.
+    pub fn compiling_progress(&mut self, crate_name: &str, index: usize, total: usize) {
+        self.compiling = Some(CompilingProgress { crate_name: crate_name.to_owned(), index, total });
+    }
+
+    /// Crates left out of the analysis data to stay within the memory budget.
+    pub fn evicted_crates(&self) -> &[String] {
+        &self.evicted_crates
+    }
+
+    /// A snapshot of the current status.
+    pub fn status(&self) -> ServerStatus {
+        ServerStatus {
+            build_state: self.build_state(),
+            indexed_crates: self.indexed_crates,
+            analysis_age_ms: self.last_analysis.map(|t| {
+                let age = t.elapsed();
+                age.as_secs() * 1000 + u64::from(age.subsec_nanos() / 1_000_000)
+            }),
+            memory_usage: memory_usage(),
+            memory_budget: self.memory_budget,
+            evicted_crates: self.evicted_crates.len(),
+            indexing: self.indexing,
+            compiling: self.compiling.clone(),
+        }
+    }
+
+    /// Send a `rustDocument/serverStatus` notification, if the client asked
+    /// for them.
+    pub fn notify<O: Output>(&self, out: &O) {
+        if !self.send_notifications {
+            return;
+        }
+        out.notify(NotificationMessage::new(
+            NOTIFICATION_SERVER_STATUS,
+            Some(NotificationParams::ServerStatus(self.status())),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_state() {
+        let mut tracker = StatusTracker::new();
+        assert_eq!(tracker.build_state(), BuildState::Idle);
+        assert!(tracker.build_started());
+        assert!(!tracker.build_started());
+        assert!(!tracker.build_finished(None));
+        assert_eq!(tracker.build_state(), BuildState::Building);
+        assert!(tracker.build_finished(Some(true)));
+        assert_eq!(tracker.build_state(), BuildState::Failed);
+        tracker.build_started();
+        assert!(tracker.build_finished(Some(false)));
+        assert_eq!(tracker.build_state(), BuildState::Idle);
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An index of the symbols defined in the workspace, for `workspace/symbol`.
+//!
+//! Symbols are kept sorted by their lower-cased name, so a search only looks
+//! at the symbols matching the query. The index is updated after every build,
+//! but only for the files which changed since they were last indexed: files
+//! edited in the client are marked as dirty (see `invalidate`), files changed
+//! on disk are found by their modification time.
+//!
+//! Dependencies aren't indexed, `search_all_crates` looks up their symbols in
+//! the analysis data instead.
+
+use analysis::{AnalysisHost, DefKind, Id};
+use Span;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A symbol in the index.
+#[derive(Debug, Clone)]
+pub struct IndexedSymbol {
+    /// The name of the symbol.
+    pub name: String,
+    /// What kind of thing the symbol is.
+    pub kind: DefKind,
+    /// Where the symbol is defined.
+    pub span: Span,
+    /// The name of the item the symbol is defined in, if any.
+    pub container_name: Option<String>,
+    /// The qualified name of that item, e.g. `::fmt::Display`.
+    pub container_qualname: Option<String>,
+}
+
+/// The symbols of the workspace.
+pub struct SymbolIndex {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Lower-cased name -> the symbols with that name.
+    by_name: BTreeMap<String, Vec<IndexedSymbol>>,
+    files: HashMap<PathBuf, FileEntry>,
+}
+
+struct FileEntry {
+    // When the file was last modified on disk when we indexed it.
+    modified: Option<SystemTime>,
+    // Set when the file was changed in the client.
+    dirty: bool,
+    // The lower-cased names of the symbols in the file.
+    names: HashSet<String>,
+}
+
+impl SymbolIndex {
+    /// Construct an empty index.
+    pub fn new() -> SymbolIndex {
+        SymbolIndex {
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// The symbols whose name starts with `query`, ignoring case.
+    pub fn search(&self, query: &str) -> Vec<IndexedSymbol> {
+        if query.is_empty() {
+            return vec![];
+        }
+        let query = query.to_lowercase();
+        let inner = self.inner.lock().unwrap();
+        inner.by_name.range(query.clone()..)
+            .take_while(|&(name, _)| name.starts_with(&query))
+            .flat_map(|(_, symbols)| symbols.iter().cloned())
+            .collect()
+    }
+
+    /// The methods declared in the trait (or impl) with the qualified name
+    /// `container`.
+    pub fn methods_of(&self, container: &str) -> Vec<IndexedSymbol> {
+        let inner = self.inner.lock().unwrap();
+        inner.by_name.values()
+            .flat_map(|symbols| symbols.iter())
+            .filter(|s| s.kind == DefKind::Method && s.container_qualname.as_ref().map_or(false, |c| c == container))
+            .cloned()
+            .collect()
+    }
+
+    /// Marks the file as changed, it is indexed again after the next build.
+    pub fn invalidate(&self, file: &Path) {
+        if let Some(entry) = self.inner.lock().unwrap().files.get_mut(file) {
+            entry.dirty = true;
+        }
+    }
+
+    /// Marks every file as changed, e.g. because the analysis data was
+    /// reloaded from scratch.
+    pub fn invalidate_all(&self) {
+        for entry in self.inner.lock().unwrap().files.values_mut() {
+            entry.dirty = true;
+        }
+    }
+
+    /// Brings the index up to date with the analysis data for the given
+    /// files, forgetting any other files. Returns the number of files which
+    /// were (re-)indexed.
+    pub fn update(&self, analysis: &AnalysisHost, files: &[PathBuf]) -> usize {
+        let stale: Vec<_> = {
+            let mut inner = self.inner.lock().unwrap();
+            let current: HashSet<_> = files.iter().collect();
+            let removed: Vec<_> = inner.files.keys().filter(|f| !current.contains(f)).cloned().collect();
+            for file in removed {
+                inner.remove_file(&file);
+            }
+            files.iter().filter(|f| inner.is_stale(f)).cloned().collect()
+        };
+
+        // The analysis is queried without holding the lock, so searches don't
+        // have to wait for it.
+        for file in &stale {
+            let symbols = file_symbols(analysis, file);
+            let mut inner = self.inner.lock().unwrap();
+            inner.remove_file(file);
+            inner.add_file(file, symbols);
+        }
+        stale.len()
+    }
+}
+
+impl Inner {
+    fn is_stale(&self, file: &Path) -> bool {
+        match self.files.get(file) {
+            Some(entry) => entry.dirty || entry.modified != modified(file),
+            None => true,
+        }
+    }
+
+    fn remove_file(&mut self, file: &Path) {
+        let entry = match self.files.remove(file) {
+            Some(entry) => entry,
+            None => return,
+        };
+        for name in entry.names {
+            let now_empty = match self.by_name.get_mut(&name) {
+                Some(symbols) => {
+                    symbols.retain(|s| s.span.file != file);
+                    symbols.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.by_name.remove(&name);
+            }
+        }
+    }
+
+    fn add_file(&mut self, file: &Path, symbols: Vec<IndexedSymbol>) {
+        let mut names = HashSet::new();
+        for symbol in symbols {
+            let name = symbol.name.to_lowercase();
+            self.by_name.entry(name.clone()).or_insert_with(Vec::new).push(symbol);
+            names.insert(name);
+        }
+        self.files.insert(file.to_owned(), FileEntry {
+            modified: modified(file),
+            dirty: false,
+            names,
+        });
+    }
+}
+
+/// The symbols named exactly `name` in the analysis data of every crate, the
+/// workspace and its dependencies. The analysis data of dependencies only has
+/// their public items.
+pub fn search_all_crates(analysis: &AnalysisHost, name: &str) -> Vec<IndexedSymbol> {
+    let ids = analysis.search_for_id(name).unwrap_or_else(|_| vec![]);
+    ids.into_iter().filter_map(|id| {
+        let def = analysis.get_def(id).ok()?;
+        let (container_name, container_qualname) = container(analysis, def.parent);
+        Some(IndexedSymbol {
+            container_name,
+            container_qualname,
+            name: def.name,
+            kind: def.kind,
+            span: def.span,
+        })
+    }).collect()
+}
+
+fn file_symbols(analysis: &AnalysisHost, file: &Path) -> Vec<IndexedSymbol> {
+    let symbols = analysis.symbols(file).unwrap_or_else(|_| vec![]);
+    symbols.into_iter().map(|s| {
+        let parent = analysis.get_def(s.id).ok().and_then(|def| def.parent);
+        let (container_name, container_qualname) = container(analysis, parent);
+        IndexedSymbol {
+            name: s.name,
+            kind: s.kind,
+            span: s.span,
+            container_name,
+            container_qualname,
+        }
+    }).collect()
+}
+
+// The name and qualified name of the item `parent`.
+fn container(analysis: &AnalysisHost, parent: Option<Id>) -> (Option<String>, Option<String>) {
+    match parent.and_then(|parent| analysis.get_def(parent).ok()) {
+        Some(parent) => (Some(parent.name), Some(parent.qualname)),
+        None => (None, None),
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use span;
+
+    fn symbol(name: &str, file: &str) -> IndexedSymbol {
+        IndexedSymbol {
+            name: name.to_owned(),
+            kind: DefKind::Function,
+            span: Span::from_positions(span::Position::new(span::Row::new_zero_indexed(0), span::Column::new_zero_indexed(0)),
+                                       span::Position::new(span::Row::new_zero_indexed(0), span::Column::new_zero_indexed(1)),
+                                       file),
+            container_name: None,
+            container_qualname: None,
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let index = SymbolIndex::new();
+        {
+            let mut inner = index.inner.lock().unwrap();
+            inner.add_file(Path::new("a.rs"), vec![symbol("foo", "a.rs"), symbol("FooBar", "a.rs"), symbol("bar", "a.rs")]);
+            inner.add_file(Path::new("b.rs"), vec![symbol("foo", "b.rs"), symbol("fob", "b.rs")]);
+        }
+        let names = |query| index.search(query).into_iter().map(|s| s.name).collect::<Vec<_>>();
+
+        assert_eq!(names("foo"), ["foo", "foo", "FooBar"]);
+        assert_eq!(names("FO"), ["fob", "foo", "foo", "FooBar"]);
+        assert_eq!(names("baz"), Vec::<String>::new());
+        assert_eq!(names(""), Vec::<String>::new());
+
+        index.inner.lock().unwrap().remove_file(Path::new("a.rs"));
+        assert_eq!(names("foo"), ["foo"]);
+        assert_eq!(names("bar"), Vec::<String>::new());
+        assert!(!index.inner.lock().unwrap().by_name.contains_key("bar"));
+    }
+
+    #[test]
+    fn test_methods_of() {
+        let index = SymbolIndex::new();
+        let method = |name: &str, container: &str| IndexedSymbol {
+            kind: DefKind::Method,
+            container_name: container.rsplit("::").next().map(|name| name.to_owned()),
+            container_qualname: Some(container.to_owned()),
+            ..symbol(name, "a.rs")
+        };
+        index.inner.lock().unwrap().add_file(Path::new("a.rs"), vec![method("fmt", "::fmt::Display"),
+                                                                    method("fmt", "::fmt::Debug"),
+                                                                    method("to_string", "::fmt::Display"),
+                                                                    method("show", "::ui::Display"),
+                                                                    symbol("fmt", "a.rs")]);
+        let mut names: Vec<_> = index.methods_of("::fmt::Display").into_iter().map(|s| s.name).collect();
+        names.sort();
+        assert_eq!(names, ["fmt", "to_string"]);
+        assert!(index.methods_of("Display").is_empty());
+        assert!(index.methods_of("::clone::Clone").is_empty());
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let index = SymbolIndex::new();
+        index.inner.lock().unwrap().add_file(Path::new("missing.rs"), vec![]);
+        assert!(!index.inner.lock().unwrap().is_stale(Path::new("missing.rs")));
+        index.invalidate(Path::new("missing.rs"));
+        assert!(index.inner.lock().unwrap().is_stale(Path::new("missing.rs")));
+        assert!(index.inner.lock().unwrap().is_stale(Path::new("other.rs")));
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Completions of trait methods after a `.`, which racer misses when the trait
+//! is implemented somewhere it doesn't look.
+//!
+//! The type of the receiver comes from the analysis data, the impls of the
+//! type from `find_impls` and the methods of their traits from the symbol
+//! index, so only types and traits defined in the workspace are known (like
+//! in `navigation`). Which trait an impl implements is read from its source
+//! line. Completing a method of a trait which isn't imported imports it too.
+
+use actions::hover;
+use actions::imports::{import_edit, is_imported, ImportPolicy};
+use actions::navigation;
+use actions::symbol_index::{IndexedSymbol, SymbolIndex};
+use analysis::{AnalysisHost, DefKind};
+use lsp_data::{CompletionItem, CompletionItemKind, CompletionRelevance, RankedCompletion, TextEdit};
+use span;
+use vfs::Vfs;
+use Span;
+
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Completions of the methods of the traits implemented for the receiver of
+/// the method call typed at `position` of `file`. The ranges of the edits
+/// importing the traits are in chars.
+pub fn trait_method_completions(analysis: &AnalysisHost,
+                                index: &SymbolIndex,
+                                vfs: &Vfs,
+                                file: &Path,
+                                position: span::Position<span::ZeroIndexed>,
+                                policy: &ImportPolicy)
+                                -> Vec<RankedCompletion> {
+    let line = match vfs.load_line(file, position.row) {
+        Ok(line) => line,
+        Err(_) => return vec![],
+    };
+    let (start, end, prefix) = match method_call(&line, position.col.0 as usize) {
+        Some(call) => call,
+        None => return vec![],
+    };
+    let receiver = Span::from_positions(span::Position::new(position.row, span::Column::new_zero_indexed(start)),
+                                        span::Position::new(position.row, span::Column::new_zero_indexed(end)),
+                                        file);
+    let ty = match analysis.show_type(&receiver) {
+        Ok(ty) => ty,
+        Err(_) => return vec![],
+    };
+    let text = match vfs.with_file(file, |text| text.to_owned()) {
+        Ok(text) => text,
+        Err(_) => return vec![],
+    };
+
+    let mut result = vec![];
+    let mut traits = HashSet::new();
+    let type_spans = navigation::type_path(&ty).map_or(vec![], |path| navigation::type_definitions(index, path));
+    for type_span in type_spans {
+        let impls = analysis.id(&type_span).and_then(|id| analysis.find_impls(id)).unwrap_or_default();
+        for impl_span in impls {
+            let impl_line = vfs.load_line(&impl_span.file, impl_span.range.row_start).unwrap_or_default();
+            let (trait_start, trait_path) = match implemented_trait(&impl_line) {
+                Some((start, path)) => (start, path.to_owned()),
+                None => continue,
+            };
+            let trait_qualnames = trait_qualnames(analysis, index, &impl_span, &impl_line, trait_start, &trait_path);
+            let trait_qualnames: Vec<_> = trait_qualnames.into_iter().filter(|q| traits.insert(q.clone())).collect();
+            if trait_qualnames.is_empty() {
+                continue;
+            }
+
+            let trait_name = trait_path.rsplit("::").next().unwrap_or(&trait_path).to_owned();
+            let import = if is_imported(&text, &trait_name) {
+                None
+            } else {
+                trait_import_path(analysis, index, file, &trait_name)
+                    .map(|path| import_edit(&text, &path, position.row.0 as usize, policy))
+            };
+            let methods = trait_qualnames.iter().flat_map(|qualname| index.methods_of(qualname));
+            for method in methods.filter(|m| m.name.starts_with(&prefix)) {
+                let mut completion = method_completion(vfs, &method, &trait_path, file);
+                completion.item.additional_text_edits = import.as_ref().map(|edit| {
+                    vec![TextEdit {
+                        range: edit.range,
+                        new_text: edit.new_text.clone(),
+                    }]
+                });
+                result.push(completion);
+            }
+        }
+    }
+    result
+}
+
+fn method_completion(vfs: &Vfs, method: &IndexedSymbol, trait_path: &str, file: &Path) -> RankedCompletion {
+    let row = method.span.range.row_start.0 as usize;
+    let col = method.span.range.col_start.0 as usize;
+    let found = vfs.with_file(&method.span.file, |text| {
+        let offset = offset_at(text, row, col)?;
+        Some((offset, hover::definition_signature(text, offset), hover::doc_comment(text, offset)))
+    });
+    let (offset, signature, docs) = match found {
+        Ok(Some(found)) => found,
+        _ => (0, None, None),
+    };
+
+    let signature = signature.unwrap_or_else(|| format!("fn {}", method.name));
+    let mut item = CompletionItem::new_simple(method.name.clone(), format!("{} (trait {})", signature, trait_path));
+    item.kind = Some(CompletionItemKind::Method);
+    item.documentation = docs;
+    RankedCompletion {
+        item,
+        relevance: CompletionRelevance::Method,
+        in_document: method.span.file == file,
+        definition: Some((method.span.file.clone(), offset)),
+    }
+}
+
+// The qualified names of the traits `trait_path` may be, which starts at byte
+// `start` of `impl_line`, the line of the impl at `impl_span`: the one the
+// analysis data resolves the path to, otherwise those in the workspace named
+// like it whose qualified names end with it.
+fn trait_qualnames(analysis: &AnalysisHost,
+                   index: &SymbolIndex,
+                   impl_span: &Span,
+                   impl_line: &str,
+                   start: usize,
+                   trait_path: &str)
+                   -> Vec<String> {
+    let name_start = start + trait_path.rfind("::").map_or(0, |i| i + 2);
+    let col = impl_line[..name_start].chars().count() as u32;
+    let name = &impl_line[name_start..start + trait_path.len()];
+    let row = impl_span.range.row_start;
+    let name_span = Span::from_positions(span::Position::new(row, span::Column::new_zero_indexed(col)),
+                                         span::Position::new(row, span::Column::new_zero_indexed(col + name.chars().count() as u32)),
+                                         impl_span.file.clone());
+    if let Ok(def) = analysis.id(&name_span).and_then(|id| analysis.get_def(id)) {
+        return vec![def.qualname];
+    }
+
+    let segments: Vec<&str> = trait_path.split("::").collect();
+    index.search(name)
+        .into_iter()
+        .filter(|s| s.name == name && s.kind == DefKind::Trait)
+        .filter_map(|s| analysis.id(&s.span).and_then(|id| analysis.get_def(id)).ok())
+        .map(|def| def.qualname)
+        .filter(|qualname| qualname.split("::").collect::<Vec<_>>().ends_with(&segments))
+        .collect()
+}
+
+// The path to import trait `name` with, if the workspace defines exactly one
+// trait of that name and it isn't defined in `file` itself.
+fn trait_import_path(analysis: &AnalysisHost, index: &SymbolIndex, file: &Path, name: &str) -> Option<String> {
+    let traits: Vec<_> = index.search(name).into_iter().filter(|s| s.name == name && s.kind == DefKind::Trait).collect();
+    if traits.len() != 1 || traits[0].span.file == file {
+        return None;
+    }
+    let def = analysis.id(&traits[0].span).and_then(|id| analysis.get_def(id)).ok()?;
+    Some(def.qualname.trim_left_matches("::").to_owned())
+}
+
+// The method call typed at char column `col` of `line`: the columns of the
+// identifier before the `.` and the part of the method name typed so far, e.g.
+// `(4, 7, "pu")` for `    foo.pu`.
+fn method_call(line: &str, col: usize) -> Option<(u32, u32, String)> {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let chars: Vec<char> = line.chars().take(col).collect();
+    let name_start = chars.iter().rposition(|&c| !is_ident_char(c)).map_or(0, |i| i + 1);
+    if name_start == 0 || chars[name_start - 1] != '.' {
+        return None;
+    }
+    let end = name_start - 1;
+    let start = chars[..end].iter().rposition(|&c| !is_ident_char(c)).map_or(0, |i| i + 1);
+    // `1.` is a float, not a method call.
+    if start == end || chars[start].is_numeric() {
+        return None;
+    }
+    Some((start as u32, end as u32, chars[name_start..].iter().collect()))
+}
+
+// The trait implemented by the impl on `line`, without its generic arguments,
+// and the byte offset it starts at, e.g. `fmt::Display` for `impl<T: Debug>
+// fmt::Display for Foo<T> {`. `None` for inherent impls.
+fn implemented_trait(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_left();
+    let trimmed = if trimmed.starts_with("unsafe ") { trimmed["unsafe ".len()..].trim_left() } else { trimmed };
+    if !trimmed.starts_with("impl") {
+        return None;
+    }
+    let mut rest = &trimmed["impl".len()..];
+    if rest.starts_with('<') {
+        let mut depth = 0;
+        let end = rest.char_indices().find(|&(_, c)| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        })?.0;
+        rest = &rest[end + 1..];
+    } else if !rest.starts_with(' ') {
+        return None;
+    }
+    let rest = rest.trim_left();
+    let trait_ref = &rest[..rest.find(" for ")?];
+    let path = trait_ref.split('<').next().unwrap_or(trait_ref).trim_right();
+    if path.is_empty() || path.starts_with('!') {
+        None
+    } else {
+        // `rest` is the end of `line`.
+        Some((line.len() - rest.len(), path))
+    }
+}
+
+// The byte offset of char column `col` of line `row`.
+fn offset_at(text: &str, row: usize, col: usize) -> Option<usize> {
+    let line_start = if row == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(row - 1)?.0 + 1
+    };
+    let line = text[line_start..].lines().next().unwrap_or("");
+    let col = line.char_indices().nth(col).map_or(line.len(), |(i, _)| i);
+    Some(line_start + col)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_method_call() {
+        assert_eq!(method_call("    foo.pu", 10), Some((4, 7, "pu".to_owned())));
+        assert_eq!(method_call("self.items.", 11), Some((5, 10, String::new())));
+        assert_eq!(method_call("let x = 1.", 10), None);
+        assert_eq!(method_call("foo(bar)", 8), None);
+        assert_eq!(method_call("a..b", 4), None);
+    }
+
+    #[test]
+    fn test_implemented_trait() {
+        assert_eq!(implemented_trait("impl<T: Debug> fmt::Display for Foo<T> {"), Some((15, "fmt::Display")));
+        assert_eq!(implemented_trait("    unsafe impl Send for Foo {}"), Some((16, "Send")));
+        assert_eq!(implemented_trait("impl From<u32> for Foo {"), Some((5, "From")));
+        assert_eq!(implemented_trait("impl<T> Foo<T> {"), None);
+        assert_eq!(implemented_trait("impl !Sync for Foo {}"), None);
+        assert_eq!(implemented_trait("implementation for x"), None);
+    }
+
+    #[test]
+    fn test_offset_at() {
+        let text = "fn a() {}\n    fn é() {}\n";
+        assert_eq!(offset_at(text, 0, 3), Some(3));
+        assert_eq!(offset_at(text, 1, 7), Some(17));
+        assert_eq!(offset_at(text, 5, 0), None);
+    }
+}
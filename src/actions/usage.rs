@@ -0,0 +1,94 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Counting the references to the definitions of the project, for the
+//! `rls.symbolStats` command.
+//!
+//! The most referenced definitions are the hot spots of a code base, where
+//! documentation and tests pay off the most.
+
+use actions::collect_source_files;
+use analysis::{AnalysisHost, DefKind};
+use Span;
+
+use std::path::Path;
+
+/// The number of definitions reported if the client doesn't ask for a number.
+pub const DEFAULT_COUNT: usize = 20;
+
+/// A definition and how often it is referenced.
+#[derive(Debug, Clone)]
+pub struct SymbolUsage {
+    /// The name of the definition.
+    pub name: String,
+    /// What kind of thing is defined.
+    pub kind: DefKind,
+    /// Where it is defined.
+    pub span: Span,
+    /// Number of references, not counting the definition itself.
+    pub references: usize,
+}
+
+/// The `count` most referenced definitions in the source files of the project.
+pub fn most_referenced(analysis: &AnalysisHost, project_dir: &Path, count: usize) -> Vec<SymbolUsage> {
+    let mut usages = vec![];
+    for file in collect_source_files(project_dir) {
+        let symbols = match analysis.symbols(&file) {
+            Ok(symbols) => symbols,
+            Err(_) => continue,
+        };
+        for symbol in symbols {
+            let refs = analysis.find_all_refs_by_id(symbol.id).unwrap_or_else(|_| vec![]);
+            let references = refs.iter().filter(|r| **r != symbol.span).count();
+            if references == 0 {
+                continue;
+            }
+            usages.push(SymbolUsage {
+                name: symbol.name,
+                kind: symbol.kind,
+                span: symbol.span,
+                references,
+            });
+        }
+    }
+    top(usages, count)
+}
+
+// The `count` usages with the most references, ties are ordered by name so the
+// result is stable between runs.
+fn top(mut usages: Vec<SymbolUsage>, count: usize) -> Vec<SymbolUsage> {
+    usages.sort_by(|a, b| b.references.cmp(&a.references).then_with(|| a.name.cmp(&b.name)));
+    usages.truncate(count);
+    usages
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use span;
+
+    fn usage(name: &str, references: usize) -> SymbolUsage {
+        SymbolUsage {
+            name: name.to_owned(),
+            kind: DefKind::Function,
+            span: Span::from_positions(span::Position::new(span::Row::new_zero_indexed(0), span::Column::new_zero_indexed(0)),
+                                       span::Position::new(span::Row::new_zero_indexed(0), span::Column::new_zero_indexed(1)),
+                                       "lib.rs"),
+            references,
+        }
+    }
+
+    #[test]
+    fn test_top() {
+        let usages = vec![usage("c", 2), usage("a", 5), usage("b", 2), usage("d", 1)];
+        let names: Vec<_> = top(usages, 3).into_iter().map(|u| (u.name, u.references)).collect();
+        assert_eq!(names, [("a".to_owned(), 5), ("b".to_owned(), 2), ("c".to_owned(), 2)]);
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Which external crates the crates of the workspace use, to report the
+//! dependencies in their manifests which are never used (see
+//! `manifest::unused_dependencies`).
+//!
+//! A crate uses another if its analysis data refers to anything in it: an
+//! `extern crate`, an import, a path (as with the 2018 edition, without
+//! `extern crate`) or an implemented trait. Only the crates compiled by the
+//! RLS in the last builds are known, so a dependency used only by targets
+//! which aren't built (or only by derive macros, which leave no references) is
+//! taken for unused.
+
+use actions::diagnostics::PathResolver;
+use actions::package_dir_for_file;
+use data::{Analysis, Id};
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The external crates used by each crate of the workspace, by the root file
+/// of the crate.
+pub struct UsedCrates {
+    by_root: Mutex<HashMap<PathBuf, HashSet<String>>>,
+}
+
+impl UsedCrates {
+    /// Construct without any crate known.
+    pub fn new() -> UsedCrates {
+        UsedCrates {
+            by_root: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record the crates used by the crates in `analysis`, replacing what was
+    /// known about them. Returns the directories of their packages.
+    pub fn record(&self, analysis: &[Analysis], paths: &mut PathResolver) -> Vec<PathBuf> {
+        let mut by_root = self.by_root.lock().unwrap();
+        let mut packages = vec![];
+        for crate_analysis in analysis {
+            let root = match crate_analysis.prelude {
+                Some(ref prelude) => paths.resolve(Path::new(&prelude.crate_root)),
+                None => continue,
+            };
+            if let Some(dir) = package_dir_for_file(&root) {
+                if !packages.iter().any(|p| p == dir) {
+                    packages.push(dir.to_owned());
+                }
+            }
+            by_root.insert(root, referenced_crates(crate_analysis));
+        }
+        packages
+    }
+
+    /// The crates used by the crates of the package in `package_dir`, `None`
+    /// if none of them is known.
+    pub fn package_uses(&self, package_dir: &Path) -> Option<HashSet<String>> {
+        let by_root = self.by_root.lock().unwrap();
+        let mut result = None;
+        for (root, used) in by_root.iter() {
+            if package_dir_for_file(root) == Some(package_dir) {
+                result.get_or_insert_with(HashSet::new).extend(used.iter().cloned());
+            }
+        }
+        result
+    }
+}
+
+// The names of the external crates `analysis` refers to.
+fn referenced_crates(analysis: &Analysis) -> HashSet<String> {
+    let external = match analysis.prelude {
+        Some(ref prelude) => &prelude.external_crates,
+        None => return HashSet::new(),
+    };
+    let ids = analysis.refs.iter().map(|r| r.ref_id)
+        .chain(analysis.imports.iter().filter_map(|i| i.ref_id))
+        .chain(analysis.relations.iter().flat_map(|r| vec![r.from, r.to]));
+    let krates: HashSet<u32> = ids.map(|id: Id| id.krate).collect();
+    external.iter()
+        .filter(|c| krates.contains(&c.num))
+        .map(|c| c.id.name.clone())
+        .collect()
+}
@@ -0,0 +1,197 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Finds `pub` items which are never referenced outside of the crate defining
+//! them and suggests restricting their visibility to `pub(crate)`.
+
+use actions::diagnostics::{FileDiagnostic, PathResolver, Suggestion};
+use actions::{collect_source_files, package_dir_for_file};
+use analysis::{AnalysisHost, DefKind};
+use data::Analysis;
+use lsp_data::ls_util;
+use span;
+use vfs::Vfs;
+
+use ls_types::{Diagnostic, DiagnosticSeverity, NumberOrString};
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The crates each source file is compiled into, by the root files of the
+/// crates. A file can be part of several crates, e.g. a module shared by the
+/// library and the binary of a package.
+pub struct CrateFiles {
+    by_file: Mutex<HashMap<PathBuf, HashSet<PathBuf>>>,
+    // The names of the crates, by root file.
+    names: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl CrateFiles {
+    /// Construct without any crate known.
+    pub fn new() -> CrateFiles {
+        CrateFiles {
+            by_file: Mutex::new(HashMap::new()),
+            names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record the files of the crates in `analysis` (those with definitions
+    /// in them), replacing what was known about these crates.
+    pub fn record(&self, analysis: &[Analysis], paths: &mut PathResolver) {
+        let mut by_file = self.by_file.lock().unwrap();
+        for crate_analysis in analysis {
+            let prelude = match crate_analysis.prelude {
+                Some(ref prelude) => prelude,
+                None => continue,
+            };
+            let root = paths.resolve(Path::new(&prelude.crate_root));
+            self.names.lock().unwrap().insert(root.clone(), prelude.crate_id.name.clone());
+            for crates in by_file.values_mut() {
+                crates.remove(&root);
+            }
+            for def in &crate_analysis.defs {
+                let file = paths.resolve(&def.span.file_name);
+                by_file.entry(file).or_insert_with(HashSet::new).insert(root.clone());
+            }
+        }
+        by_file.retain(|_, crates| !crates.is_empty());
+    }
+
+    /// Whether `file` is the root file of a crate.
+    pub fn is_crate_root(&self, file: &Path) -> bool {
+        self.names.lock().unwrap().contains_key(file)
+    }
+
+    /// The root files of the crates `file` is part of, empty if unknown.
+    pub fn crates_of(&self, file: &Path) -> HashSet<PathBuf> {
+        self.by_file.lock().unwrap().get(file).cloned().unwrap_or_default()
+    }
+
+    /// The names of the crates `file` is part of, empty if unknown.
+    pub fn crate_names_of(&self, file: &Path) -> Vec<String> {
+        let roots = self.crates_of(file);
+        let names = self.names.lock().unwrap();
+        roots.iter().filter_map(|root| names.get(root).cloned()).collect()
+    }
+}
+
+/// Checks every source file of the project and reports a diagnostic (with a
+/// quick fix, titled `fix_label`) for each `pub` item only used from within its
+/// own crate.
+///
+/// The crates are those recorded in `crate_files`; for files no crate is known
+/// of, the package takes the place of the crate.
+pub fn unused_pub_items(analysis: &AnalysisHost, vfs: &Vfs, crate_files: &CrateFiles, project_dir: &Path,
+                        fix_label: &str) -> Vec<FileDiagnostic> {
+    let files = collect_source_files(project_dir);
+
+    let mut result = vec![];
+    for file in files {
+        let symbols = match analysis.symbols(&file) {
+            Ok(symbols) => symbols,
+            Err(_) => continue,
+        };
+        let package_dir = package_dir_for_file(&file).map(|d| d.to_owned());
+        let crates = crate_files.crates_of(&file);
+
+        for symbol in symbols {
+            if !is_checked_kind(symbol.kind) {
+                continue;
+            }
+
+            let line = match vfs.load_line(&symbol.span.file, symbol.span.range.row_start) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            let pub_col = match pub_keyword_col(&line, symbol.span.range.col_start.0 as usize) {
+                Some(col) => col,
+                None => continue,
+            };
+
+            let refs = analysis.find_all_refs_by_id(symbol.id).unwrap_or_else(|_| vec![]);
+            let used_outside = refs.iter().any(|r| {
+                let ref_crates = crate_files.crates_of(&r.file);
+                if crates.is_empty() || ref_crates.is_empty() {
+                    package_dir_for_file(&r.file).map(|d| d.to_owned()) != package_dir
+                } else {
+                    !ref_crates.is_subset(&crates)
+                }
+            });
+            if used_outside {
+                continue;
+            }
+
+            let row = symbol.span.range.row_start;
+            let range = ls_util::rls_to_range(span::Range::from_positions(
+                span::Position::new(row, span::Column::new_zero_indexed(pub_col as u32)),
+                span::Position::new(row, span::Column::new_zero_indexed(pub_col as u32 + 3)),
+            ));
+
+            result.push(FileDiagnostic {
+                file_path: symbol.span.file.clone(),
+                diagnostic: Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::Information),
+                    code: Some(NumberOrString::String("unused_pub".to_owned())),
+                    source: Some("rls".to_owned()),
+                    message: format!("`{}` is never used outside of its crate, consider \
+                                      restricting its visibility to `pub(crate)`", symbol.name),
+                },
+                suggestions: vec![Suggestion {
+                    range,
+                    new_text: "pub(crate)".to_owned(),
+                    label: fix_label.to_owned(),
+                }],
+                related: vec![],
+            });
+        }
+    }
+    result
+}
+
+fn is_checked_kind(kind: DefKind) -> bool {
+    match kind {
+        DefKind::Function |
+        DefKind::Struct |
+        DefKind::Enum |
+        DefKind::Union |
+        DefKind::Trait |
+        DefKind::Type |
+        DefKind::Const |
+        DefKind::Static => true,
+        _ => false,
+    }
+}
+
+// Returns the (char) column of an unrestricted `pub` keyword preceding the
+// item name at `name_col`, if there is one.
+fn pub_keyword_col(line: &str, name_col: usize) -> Option<usize> {
+    let prefix: String = line.chars().take(name_col).collect();
+    let byte_idx = prefix.find("pub")?;
+    if prefix[byte_idx + "pub".len()..].trim_left().starts_with('(') {
+        return None;
+    }
+    Some(prefix[..byte_idx].chars().count())
+}
+
+#[cfg(test)]
+mod test {
+    use super::pub_keyword_col;
+
+    #[test]
+    fn test_pub_keyword_col() {
+        assert_eq!(pub_keyword_col("pub fn foo() {}", 7), Some(0));
+        assert_eq!(pub_keyword_col("    pub struct Foo;", 15), Some(4));
+        assert_eq!(pub_keyword_col("pub(crate) fn foo() {}", 14), None);
+        assert_eq!(pub_keyword_col("pub (crate) fn foo() {}", 15), None);
+        assert_eq!(pub_keyword_col("fn foo() {}", 3), None);
+    }
+}
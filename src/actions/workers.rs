@@ -0,0 +1,137 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Threads the RLS waits for when it shuts down: those handling the results of
+//! builds and the racer thread. A `JoinHandle` can't be joined with a timeout,
+//! so each thread also holds a channel which is closed once it is done.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A thread which can be joined with a timeout.
+pub struct WorkerThread {
+    handle: JoinHandle<()>,
+    // Disconnected once the thread is done, whether it panicked or not.
+    done: Receiver<()>,
+}
+
+impl WorkerThread {
+    /// Run `f` on a new thread. A panic is logged, a lock it poisoned makes
+    /// the context start over (see `ActionContext::restart_if_poisoned`).
+    pub fn spawn<F: FnOnce() + Send + 'static>(f: F) -> WorkerThread {
+        let (done_sender, done) = channel();
+        let handle = thread::spawn(move || {
+            let _done = done_sender;
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                let message = payload.downcast_ref::<&str>().map(|m| m.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown error".to_owned());
+                warn!("worker thread panicked: {}", message);
+            }
+        });
+        WorkerThread { handle, done }
+    }
+
+    /// Waits (at most `timeout`) for the thread to finish. Gives the thread
+    /// back if it didn't.
+    pub fn join(self, timeout: Duration) -> Result<(), WorkerThread> {
+        match self.done.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => Err(self),
+            _ => {
+                let _ = self.handle.join();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Threads which run one after the other, each waiting for the previous one
+/// before it starts, e.g. so that the analysis data isn't reloaded by two
+/// threads at once.
+pub struct SerialThreads {
+    // Joining the last one joins them all.
+    last: Mutex<Option<WorkerThread>>,
+}
+
+impl SerialThreads {
+    /// Construct without any thread.
+    pub fn new() -> SerialThreads {
+        SerialThreads {
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Run `f` on a new thread once the previous ones are done.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let mut last = self.last.lock().unwrap();
+        let previous = last.take();
+        *last = Some(WorkerThread::spawn(move || {
+            if let Some(previous) = previous {
+                let _ = previous.handle.join();
+            }
+            f();
+        }));
+    }
+
+    /// Waits (at most `timeout`) for the threads to finish. Returns whether
+    /// they did.
+    pub fn join(&self, timeout: Duration) -> bool {
+        let mut last = self.last.lock().unwrap();
+        match last.take() {
+            Some(thread) => match thread.join(timeout) {
+                Ok(()) => true,
+                Err(thread) => {
+                    *last = Some(thread);
+                    false
+                }
+            },
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_serial_threads() {
+        let threads = SerialThreads::new();
+        let (sender, receiver) = channel::<()>();
+        let done = Arc::new(AtomicUsize::new(0));
+        let first_done = done.clone();
+        threads.spawn(move || {
+            let _ = receiver.recv();
+            first_done.fetch_add(1, Ordering::SeqCst);
+        });
+        let second_done = done.clone();
+        threads.spawn(move || {
+            // The first thread finished before this one started.
+            assert_eq!(second_done.fetch_add(1, Ordering::SeqCst), 1);
+        });
+
+        assert!(!threads.join(Duration::from_millis(10)));
+        drop(sender);
+        assert!(threads.join(Duration::from_secs(10)));
+        assert_eq!(done.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_panicking_thread() {
+        let thread = WorkerThread::spawn(|| panic!("worker"));
+        assert!(thread.join(Duration::from_secs(10)).is_ok());
+    }
+}
@@ -9,12 +9,15 @@
 // except according to those terms.
 
 use cargo::core::{PackageId, Shell, Target, TargetKind, Workspace, Verbosity};
-use cargo::ops::{compile_with_exec, Executor, Context, Packages, CompileOptions, CompileMode, CompileFilter, Unit};
+use cargo::ops::{compile_with_exec, resolve_ws, Executor, Context, Packages, CompileOptions, CompileMode, CompileFilter,
+                 Unit};
 use cargo::util::{Config as CargoConfig, ProcessBuilder, homedir, important_paths, ConfigValue, CargoResult};
 use serde_json;
 
 use data::Analysis;
-use build::{Internals, BufWriter, BuildResult, CompilationContext};
+use build::{Internals, BufWriter, BuildProgress, BuildResult, CompilationContext, CrateFailures,
+            DiagnosticsHandler, ProgressHandler};
+use build::plan::UnitKey;
 use build::environment::{self, Environment, EnvironmentLock};
 use config::Config;
 use vfs::Vfs;
@@ -23,19 +26,27 @@ use std::collections::{HashMap, HashSet, BTreeMap};
 use std::env;
 use std::ffi::OsString;
 use std::fs::{read_dir, remove_file};
-use std::path::{Path};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::cmp;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 
-// Runs an in-process instance of Cargo.
-pub(super) fn cargo(internals: &Internals) -> BuildResult {
+// Runs an in-process instance of Cargo. Cargo decides the order the crates
+// are built in, the messages are passed to `early_diagnostics` once the
+// crates of the edited files are built (see `BuildQueue::request_build`).
+pub(super) fn cargo(internals: &Internals, early_diagnostics: Option<DiagnosticsHandler>) -> BuildResult {
     let workspace_mode = internals.config.lock().unwrap().workspace_mode;
 
     let compilation_cx = internals.compilation_cx.clone();
     let config = internals.config.clone();
     let vfs = internals.vfs.clone();
     let env_lock = internals.env_lock.clone();
+    let failures = internals.failures.clone();
+    let lib_names = internals.lib_names.clone();
+    let progress = internals.progress.lock().unwrap().clone();
+    let edited: HashSet<_> = internals.dirty_files.lock().unwrap().keys().cloned().collect();
 
     let diagnostics = Arc::new(Mutex::new(vec![]));
     let diagnostics_clone = diagnostics.clone();
@@ -48,8 +59,9 @@ pub(super) fn cargo(internals: &Internals) -> BuildResult {
     // we may be in separate threads we need to block and wait our thread.
     // However, if Cargo doesn't run a separate thread, then we'll just wait
     // forever. Therefore, we spawn an extra thread here to be safe.
-    let handle = thread::spawn(|| run_cargo(compilation_cx, config, vfs, env_lock,
-                                            diagnostics, analysis, out));
+    let handle = thread::spawn(|| run_cargo(compilation_cx, config, vfs, env_lock, failures, lib_names,
+                                            diagnostics, analysis, out, progress, edited,
+                                            early_diagnostics));
 
     match handle.join().map_err(|_| "thread panicked".into()).and_then(|res| res) {
         Ok(_) if workspace_mode => {
@@ -70,9 +82,14 @@ fn run_cargo(compilation_cx: Arc<Mutex<CompilationContext>>,
              rls_config: Arc<Mutex<Config>>,
              vfs: Arc<Vfs>,
              env_lock: Arc<EnvironmentLock>,
+             failures: Arc<CrateFailures>,
+             lib_names: Arc<LibNames>,
              compiler_messages: Arc<Mutex<Vec<String>>>,
              analysis: Arc<Mutex<Vec<Analysis>>>,
-             out: Arc<Mutex<Vec<u8>>>) -> CargoResult<()> {
+             out: Arc<Mutex<Vec<u8>>>,
+             progress: Option<ProgressHandler>,
+             edited: HashSet<PathBuf>,
+             early_diagnostics: Option<DiagnosticsHandler>) -> CargoResult<()> {
     // Lock early to guarantee synchronized access to env var for the scope of Cargo routine.
     // Additionally we need to pass inner lock to RlsExecutor, since it needs to hand it down
     // during exec() callback when calling linked compiler in parallel, for which we need to
@@ -107,7 +124,7 @@ fn run_cargo(compilation_cx: Arc<Mutex<CompilationContext>>,
 
     // TODO: It might be feasible to keep this CargoOptions structure cached and regenerate
     // it on every relevant configuration change
-    let (opts, rustflags, clear_env_rust_log) = {
+    let (opts, rustflags, clear_env_rust_log, config_env) = {
         // We mustn't lock configuration for the whole build process
         let rls_config = rls_config.lock().unwrap();
 
@@ -115,6 +132,17 @@ fn run_cargo(compilation_cx: Arc<Mutex<CompilationContext>>,
         trace!("Cargo compilation options:\n{:?}", opts);
         let rustflags = prepare_cargo_rustflags(&rls_config);
 
+        // The sysroot the crates are compiled with, see `RlsExecutor::exec`.
+        if let Some(ref target) = opts.target {
+            let sysroot = rls_config.sysroot.clone().or_else(current_sysroot);
+            if let Some(sysroot) = sysroot {
+                if !target_std_installed(&sysroot, target) {
+                    warn!("cargo - standard library for target `{}` not found in `{}`, \
+                           try `rustup target add {}`", target, sysroot, target);
+                }
+            }
+        }
+
         // Warn about invalid specified bin target or package depending on current mode
         // TODO: Return client notifications along with diagnostics to inform the user
         if !rls_config.workspace_mode {
@@ -132,9 +160,14 @@ fn run_cargo(compilation_cx: Arc<Mutex<CompilationContext>>,
                     warn!("cargo - couldn't find member package `{}` specified in `analyze_package` configuration", package);
                 }
             }
+            for package in &opts.exclude {
+                if let None =  ws.members().find(|x| x.name() == package) {
+                    warn!("cargo - couldn't find member package `{}` specified in `exclude_packages` configuration", package);
+                }
+            }
         }
 
-        (opts, rustflags, rls_config.clear_env_rust_log)
+        (opts, rustflags, rls_config.clear_env_rust_log, rls_config.env.clone())
     };
 
     let spec = Packages::from_flags(ws.is_virtual(), opts.all, &opts.exclude, &opts.package)?;
@@ -150,11 +183,14 @@ fn run_cargo(compilation_cx: Arc<Mutex<CompilationContext>>,
         features: &opts.features,
         all_features: opts.all_features,
         no_default_features: opts.no_default_features,
+        jobs: opts.jobs,
         .. CompileOptions::default(&config, CompileMode::Check { test: false })
     };
 
     // Create a custom environment for running cargo, the environment is reset afterwards automatically
-    let mut env: HashMap<String, Option<OsString>> = HashMap::new();
+    let mut env: HashMap<String, Option<OsString>> = config_env.into_iter()
+        .map(|(k, v)| (k, v.map(OsString::from)))
+        .collect();
     env.insert("RUSTFLAGS".to_owned(), Some(rustflags.into()));
 
     if clear_env_rust_log {
@@ -168,17 +204,81 @@ fn run_cargo(compilation_cx: Arc<Mutex<CompilationContext>>,
                                 rls_config.clone(),
                                 inner_lock,
                                 vfs,
+                                failures,
                                 compiler_messages,
-                                analysis);
+                                analysis,
+                                progress,
+                                edited,
+                                early_diagnostics);
 
     compile_with_exec(&ws, &compile_opts, Arc::new(exec))?;
 
+    // The packages are resolved (and downloaded) by now.
+    match resolve_ws(&ws) {
+        Ok((packages, resolve)) => {
+            let packages: Vec<_> = resolve.iter().filter_map(|id| packages.get(id).ok()).collect();
+            lib_names.record(packages.iter().filter_map(|package| {
+                let lib = package.targets().iter().find(|target| target.is_lib())?;
+                Some((package.name().to_owned(), lib.crate_name()))
+            }).collect());
+            lib_names.record_crates(packages.iter()
+                .flat_map(|package| package.targets().iter().map(|target| target.crate_name()))
+                .collect());
+        }
+        Err(e) => debug!("cargo - couldn't resolve the library names: {}", e),
+    }
+
     trace!("Created build plan after Cargo compilation routine: {:?}",
         compilation_cx.lock().unwrap().build_plan);
 
     Ok(())
 }
 
+/// The names of the libraries of the packages Cargo resolved (as used in Rust
+/// code), which may differ from the names of the packages, e.g. with a `[lib]`
+/// name in the manifest.
+pub struct LibNames {
+    by_package: Mutex<HashMap<String, String>>,
+    // The crate names of every target of the packages, `None` until Cargo
+    // resolved them.
+    crates: Mutex<Option<HashSet<String>>>,
+}
+
+impl LibNames {
+    /// Construct without any package known.
+    pub fn new() -> LibNames {
+        LibNames {
+            by_package: Mutex::new(HashMap::new()),
+            crates: Mutex::new(None),
+        }
+    }
+
+    /// The crate name of the library of `package`. Before Cargo resolved the
+    /// package, the name Cargo would give the library by default.
+    pub fn crate_name(&self, package: &str) -> String {
+        self.by_package.lock().unwrap()
+            .get(package)
+            .cloned()
+            .unwrap_or_else(|| package.replace('-', "_"))
+    }
+
+    /// Replaces the known libraries with `names`, by package name.
+    pub fn record(&self, names: HashMap<String, String>) {
+        *self.by_package.lock().unwrap() = names;
+    }
+
+    /// The crate names of all targets (libraries, binaries, tests, ...) of the
+    /// packages in the dependency graph, `None` if Cargo didn't resolve it yet.
+    pub fn crate_names(&self) -> Option<HashSet<String>> {
+        self.crates.lock().unwrap().clone()
+    }
+
+    /// Replaces the crate names of the targets of the packages.
+    pub fn record_crates(&self, names: HashSet<String>) {
+        *self.crates.lock().unwrap() = Some(names);
+    }
+}
+
 struct RlsExecutor {
     compilation_cx: Arc<Mutex<CompilationContext>>,
     cur_package_id: Mutex<Option<PackageId>>,
@@ -189,12 +289,29 @@ struct RlsExecutor {
     env_lock: environment::InnerLock,
     vfs: Arc<Vfs>,
     analysis: Arc<Mutex<Vec<Analysis>>>,
+    /// Workspace members which failed to build
+    failures: Arc<CrateFailures>,
     workspace_mode: bool,
     /// Packages which are directly a member of the workspace, for which
     /// analysis and diagnostics will be provided
     member_packages: Mutex<HashSet<PackageId>>,
+    /// Members of the workspace left out via `analyze_package` or
+    /// `exclude_packages`, which are built without being indexed
+    excluded_packages: HashSet<PackageId>,
     /// JSON compiler messages emitted for each primary compiled crate
     compiler_messages: Arc<Mutex<Vec<String>>>,
+    /// Reports each crate starting to compile, see `BuildQueue::on_progress`
+    progress: Option<ProgressHandler>,
+    /// The units rustc will compile, collected by `init`: those forced to
+    /// rebuild and those Cargo didn't build before (see `has_output`)
+    units: Mutex<HashSet<UnitKey>>,
+    /// Number of units compiled so far
+    compiled: AtomicUsize,
+    /// The directories of the primary units' root files, collected by `init`
+    src_dirs: Mutex<HashMap<UnitKey, PathBuf>>,
+    /// Edited files whose crates weren't compiled yet, see `publish_early`
+    edited: Mutex<HashSet<PathBuf>>,
+    early_diagnostics: Option<DiagnosticsHandler>,
 }
 
 impl RlsExecutor {
@@ -203,20 +320,27 @@ impl RlsExecutor {
            config: Arc<Mutex<Config>>,
            env_lock: environment::InnerLock,
            vfs: Arc<Vfs>,
+           failures: Arc<CrateFailures>,
            compiler_messages: Arc<Mutex<Vec<String>>>,
-           analysis: Arc<Mutex<Vec<Analysis>>>)
+           analysis: Arc<Mutex<Vec<Analysis>>>,
+           progress: Option<ProgressHandler>,
+           edited: HashSet<PathBuf>,
+           early_diagnostics: Option<DiagnosticsHandler>)
     -> RlsExecutor {
-        let workspace_mode = config.lock().unwrap().workspace_mode;
-        let (cur_package_id, member_packages) = if workspace_mode {
-            let member_packages = ws.members()
-                                    .map(|x| x.package_id().clone())
-                                    .collect();
-            (None, member_packages)
+        let (workspace_mode, analyze_package, exclude_packages) = {
+            let config = config.lock().unwrap();
+            (config.workspace_mode, config.analyze_package.clone(), config.exclude_packages.clone())
+        };
+        let (cur_package_id, member_packages, excluded_packages) = if workspace_mode {
+            let (member_packages, excluded_packages) = ws.members()
+                .map(|x| x.package_id().clone())
+                .partition(|id| is_selected_package(id.name(), &analyze_package, &exclude_packages));
+            (None, member_packages, excluded_packages)
         } else {
             let pkg_id = ws.current_opt().expect("No current package in Cargo")
                            .package_id()
                            .clone();
-            (Some(pkg_id), HashSet::new())
+            (Some(pkg_id), HashSet::new(), HashSet::new())
         };
 
         RlsExecutor {
@@ -226,9 +350,44 @@ impl RlsExecutor {
             env_lock,
             vfs,
             analysis,
+            failures,
             workspace_mode,
             member_packages: Mutex::new(member_packages),
+            excluded_packages,
             compiler_messages,
+            progress,
+            units: Mutex::new(HashSet::new()),
+            compiled: AtomicUsize::new(0),
+            src_dirs: Mutex::new(HashMap::new()),
+            edited: Mutex::new(edited),
+            early_diagnostics,
+        }
+    }
+
+    /// Passes the messages so far to `early_diagnostics` once the crates of
+    /// all edited files are compiled, if other crates are still to come.
+    fn publish_early(&self, unit: &UnitKey) {
+        let early_diagnostics = match self.early_diagnostics {
+            Some(ref early_diagnostics) => early_diagnostics,
+            None => return,
+        };
+        {
+            let src_dirs = self.src_dirs.lock().unwrap();
+            let mut edited = self.edited.lock().unwrap();
+            if edited.is_empty() {
+                return;
+            }
+            // Files outside of every crate (e.g. `Cargo.toml`) aren't waited for.
+            edited.retain(|file| match owning_unit(&src_dirs, file) {
+                Some(owner) => owner != unit,
+                None => false,
+            });
+            if !edited.is_empty() {
+                return;
+            }
+        }
+        if self.compiled.load(Ordering::SeqCst) < self.units.lock().unwrap().len() {
+            early_diagnostics(self.compiler_messages.lock().unwrap().clone());
         }
     }
 
@@ -257,6 +416,21 @@ impl Executor for RlsExecutor {
         if let Err(err) = plan.emplace_dep_with_filter(&unit, &cx, &only_primary) {
             error!("{:?}", err);
         }
+
+        // Running a build script doesn't involve rustc.
+        if !unit.profile.run_custom_build {
+            let key = (unit.pkg.package_id().clone(), unit.target.kind().clone());
+            if only_primary(unit) {
+                if let Some(dir) = unit.target.src_path().parent() {
+                    self.src_dirs.lock().unwrap().insert(key.clone(), dir.to_owned());
+                }
+            }
+            // Fresh units aren't compiled, so counting them would leave the
+            // progress of an incremental build at e.g. "3/87".
+            if self.force_rebuild(unit) || !has_output(cx, unit) {
+                self.units.lock().unwrap().insert(key);
+            }
+        }
     }
 
     fn force_rebuild(&self, unit: &Unit) -> bool {
@@ -282,6 +456,12 @@ impl Executor for RlsExecutor {
         let crate_name = parse_arg(cargo_args, "--crate-name").expect("no crate-name in rustc command line");
         trace!("exec: {}", crate_name);
 
+        let index = self.compiled.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(ref progress) = self.progress {
+            let total = cmp::max(self.units.lock().unwrap().len(), index);
+            progress(&BuildProgress { crate_name: crate_name.clone(), index, total });
+        }
+
         let out_dir = parse_arg(cargo_args, "--out-dir").expect("no out-dir in rustc command line");
         let analysis_dir = Path::new(&out_dir).join("save-analysis");
         if let Ok(dir_contents) = read_dir(&analysis_dir) {
@@ -327,6 +507,11 @@ impl Executor for RlsExecutor {
             };
             trace!("rustc not intercepted - {}{}", id.name(), build_script_notice);
 
+            if self.excluded_packages.contains(id) {
+                // Don't index workspace members which are out of scope.
+                trace!("package is excluded from analysis");
+                return cargo_cmd.exec();
+            }
             if ::CRATE_BLACKLIST.contains(&&*crate_name) {
                 // By running the original command (rather than using our shim), we
                 // avoid producing save-analysis data.
@@ -411,16 +596,29 @@ impl Executor for RlsExecutor {
                 cx.build_dir.clone().unwrap()
             };
 
+            // Members depending on a failed one would only report that it's
+            // missing, the others are checked regardless.
+            if let Some(dep) = self.failures.failed_dependency(&args) {
+                self.failures.record(&args, &build_dir, true, Some(dep));
+                return Ok(());
+            }
+
             let env_lock = self.env_lock.as_facade();
 
             match super::rustc::rustc(&self.vfs, &args, &envs, &build_dir, self.config.clone(), env_lock) {
-                BuildResult::Success(mut messages, mut analysis) |
+                BuildResult::Success(mut messages, mut analysis) => {
+                    self.failures.record(&args, &build_dir, false, None);
+                    self.compiler_messages.lock().unwrap().append(&mut messages);
+                    self.analysis.lock().unwrap().append(&mut analysis);
+                }
                 BuildResult::Failure(mut messages, mut analysis) => {
+                    self.failures.record(&args, &build_dir, true, None);
                     self.compiler_messages.lock().unwrap().append(&mut messages);
                     self.analysis.lock().unwrap().append(&mut analysis);
                 }
                 _ => {}
             }
+            self.publish_early(&(id.clone(), target.kind().clone()));
         } else {
             cmd.exec()?;
         }
@@ -446,6 +644,7 @@ struct CargoOptions {
     all_features: bool,
     no_default_features: bool,
     features: Vec<String>,
+    jobs: Option<u32>,
 }
 
 impl Default for CargoOptions {
@@ -461,6 +660,7 @@ impl Default for CargoOptions {
             all_features: false,
             no_default_features: false,
             features: vec![],
+            jobs: None,
         }
     }
 }
@@ -468,18 +668,20 @@ impl Default for CargoOptions {
 impl CargoOptions {
     fn new(config: &Config) -> CargoOptions {
         if config.workspace_mode {
-            let (package, all) = match config.analyze_package {
-                Some(ref pkg_name) => (vec![pkg_name.clone()], false),
-                None => (vec![], true),
+            let (package, all, exclude) = match config.analyze_package {
+                Some(ref pkg_name) => (vec![pkg_name.clone()], false, vec![]),
+                None => (vec![], true, config.exclude_packages.clone()),
             };
 
             CargoOptions {
                 package,
                 all,
-                target: config.target.clone(),
+                exclude,
+                target: config.target_triple.clone(),
                 features: config.features.clone(),
                 all_features: config.all_features,
                 no_default_features: config.no_default_features,
+                jobs: config.build_jobs,
                 .. CargoOptions::default()
             }
         } else {
@@ -499,10 +701,11 @@ impl CargoOptions {
             CargoOptions {
                 lib,
                 bin,
-                target: config.target.clone(),
+                target: config.target_triple.clone(),
                 features: config.features.clone(),
                 all_features: config.all_features,
                 no_default_features: config.no_default_features,
+                jobs: config.build_jobs,
                 .. CargoOptions::default()
             }
         }
@@ -546,8 +749,8 @@ pub fn make_cargo_config(build_dir: &Path, target_dir: Option<&Path>, shell: She
     {
         let build_value = config_value_map.entry("build".to_owned()).or_insert(ConfigValue::Table(HashMap::new(), config_path.clone()));
 
-        let target_dir = target_dir.map(|d| d.to_str().unwrap().to_owned())
-                .unwrap_or_else(|| build_dir.join("target").join("rls").to_str().unwrap().to_owned());
+        let target_dir = target_dir.map(|d| d.to_owned()).unwrap_or_else(|| default_target_dir(build_dir));
+        let target_dir = target_dir.to_str().unwrap().to_owned();
         let td_value = ConfigValue::String(target_dir, config_path);
         if let &mut ConfigValue::Table(ref mut build_table, _) = build_value {
             build_table.insert("target-dir".to_owned(), td_value);
@@ -560,6 +763,33 @@ pub fn make_cargo_config(build_dir: &Path, target_dir: Option<&Path>, shell: She
     config
 }
 
+/// The target directory of the RLS' builds of the project at `project_path`:
+/// the configured `target_dir`, otherwise `target/rls` next to the root
+/// manifest of its workspace (see `make_cargo_config`).
+pub fn rls_target_dir(project_path: &Path, config: &Config) -> PathBuf {
+    match config.target_dir {
+        Some(ref target_dir) => target_dir.clone(),
+        None => default_rls_target_dir(project_path),
+    }
+}
+
+/// The target directory of the RLS' builds of the project at `project_path`
+/// if none is configured: `target/rls` next to the root manifest of its
+/// workspace.
+pub fn default_rls_target_dir(project_path: &Path) -> PathBuf {
+    let manifest_dir = important_paths::find_root_manifest_for_wd(None, project_path)
+        .ok()
+        .and_then(|manifest| manifest.parent().map(Path::to_owned))
+        .unwrap_or_else(|| project_path.to_owned());
+    default_target_dir(&manifest_dir)
+}
+
+// Kept apart from `target`, so that the RLS and Cargo don't rebuild each other's
+// work all the time.
+fn default_target_dir(manifest_dir: &Path) -> PathBuf {
+    manifest_dir.join("target").join("rls")
+}
+
 fn parse_arg(args: &[OsString], arg: &str) -> Option<String> {
     for (i, a) in args.iter().enumerate() {
         if a == arg {
@@ -569,7 +799,47 @@ fn parse_arg(args: &[OsString], arg: &str) -> Option<String> {
     None
 }
 
-fn current_sysroot() -> Option<String> {
+// Whether a workspace member should be analyzed, given the `analyze_package`
+// and `exclude_packages` configuration.
+fn is_selected_package(name: &str, analyze_package: &Option<String>, exclude_packages: &[String]) -> bool {
+    match *analyze_package {
+        Some(ref package) => package == name,
+        None => !exclude_packages.iter().any(|p| p == name),
+    }
+}
+
+// The unit whose root file is in the closest directory above `file`.
+fn owning_unit<'a>(src_dirs: &'a HashMap<UnitKey, PathBuf>, file: &Path) -> Option<&'a UnitKey> {
+    src_dirs.iter()
+        .filter(|&(_, dir)| file.starts_with(dir))
+        .max_by_key(|&(_, dir)| dir.components().count())
+        .map(|(unit, _)| unit)
+}
+
+// Whether Cargo built `unit` before, i.e. it is most likely fresh. Cargo doesn't
+// tell an executor which units are, so we look for the output named after the
+// unit's hash (`libfoo-<hash>.rmeta`, `build_script_build-<hash>`, ...).
+fn has_output(cx: &Context, unit: &Unit) -> bool {
+    let stem = cx.file_stem(unit);
+    let lib_stem = format!("lib{}", stem);
+    match read_dir(cx.out_dir(unit)) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).any(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(&stem) || name.starts_with(&lib_stem)
+        }),
+        Err(_) => false,
+    }
+}
+
+// The standard library for every installed target lives in
+// `$sysroot/lib/rustlib/$target`.
+fn target_std_installed(sysroot: &str, target: &str) -> bool {
+    Path::new(sysroot).join("lib").join("rustlib").join(target).join("lib").is_dir()
+}
+
+/// The sysroot of the toolchain the RLS runs with.
+pub fn current_sysroot() -> Option<String> {
     let home = env::var("RUSTUP_HOME").or(env::var("MULTIRUST_HOME"));
     let toolchain = env::var("RUSTUP_TOOLCHAIN").or(env::var("MULTIRUST_TOOLCHAIN"));
     if let (Ok(home), Ok(toolchain)) = (home, toolchain) {
@@ -654,7 +924,17 @@ fn dedup_flags(flag_str: &str) -> String {
 
 #[cfg(test)]
 mod test {
-    use super::dedup_flags;
+    use super::{dedup_flags, is_selected_package};
+
+    #[test]
+    fn test_is_selected_package() {
+        let excluded = vec!["bar".to_owned()];
+        assert!(is_selected_package("foo", &None, &[]));
+        assert!(is_selected_package("foo", &None, &excluded));
+        assert!(!is_selected_package("bar", &None, &excluded));
+        assert!(is_selected_package("foo", &Some("foo".to_owned()), &excluded));
+        assert!(!is_selected_package("baz", &Some("foo".to_owned()), &[]));
+    }
 
     #[test]
     fn test_dedup_flags() {
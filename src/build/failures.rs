@@ -0,0 +1,162 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Keeps track of the workspace members which failed to build.
+//!
+//! In `workspace_mode`, a member with errors doesn't stop the others from
+//! being checked (and their analysis data from being loaded). Members depending
+//! on a failed one are not compiled at all, they would only report errors about
+//! the missing crate. The root file of a failed crate gets a diagnostic saying
+//! that its analysis data is unavailable, until it builds again.
+//!
+//! Crates are told apart by their root file, as the library and binaries of a
+//! package share their name.
+
+use paths;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A workspace member whose last build failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FailedCrate {
+    /// The name of the crate.
+    pub name: String,
+    /// The root source file of the crate (e.g. `src/lib.rs`).
+    pub root: PathBuf,
+    /// Whether the crate is a library, which other crates can depend on.
+    pub is_lib: bool,
+    /// The failed crate this one depends on, if it wasn't compiled because of
+    /// that.
+    pub blocked_by: Option<String>,
+}
+
+/// The workspace members which currently fail to build, by root file.
+pub struct CrateFailures {
+    failed: Mutex<HashMap<PathBuf, FailedCrate>>,
+}
+
+impl CrateFailures {
+    /// Construct an empty set of failures.
+    pub fn new() -> CrateFailures {
+        CrateFailures {
+            failed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The crates which failed to build, sorted by name.
+    pub fn failed_crates(&self) -> Vec<FailedCrate> {
+        let mut failed: Vec<_> = self.failed.lock().unwrap().values().cloned().collect();
+        failed.sort_by(|a, b| a.name.cmp(&b.name));
+        failed
+    }
+
+    /// The failed library the compiler invocation with the given arguments
+    /// depends on (via `--extern`), if any.
+    pub fn failed_dependency(&self, args: &[String]) -> Option<String> {
+        let failed = self.failed.lock().unwrap();
+        args.iter()
+            .zip(args.iter().skip(1))
+            .filter(|&(flag, _)| flag == "--extern")
+            .filter_map(|(_, value)| value.split('=').next())
+            .find(|name| failed.values().any(|krate| krate.is_lib && krate.name == *name))
+            .map(|name| name.to_owned())
+    }
+
+    /// Record the outcome of building the crate compiled with the given
+    /// arguments, whose paths are relative to `build_dir`.
+    pub fn record(&self, args: &[String], build_dir: &Path, failed: bool, blocked_by: Option<String>) {
+        let (name, root) = match (crate_name(args), crate_root(args)) {
+            (Some(name), Some(root)) => (name, paths::normalize(&build_dir.join(root))),
+            _ => return,
+        };
+        let mut failures = self.failed.lock().unwrap();
+        if !failed {
+            failures.remove(&root);
+            return;
+        }
+        info!("build of `{}` failed{}", name,
+              blocked_by.as_ref().map_or(String::new(), |dep| format!(" (depends on `{}`)", dep)));
+        let is_lib = is_lib(args);
+        failures.insert(root.clone(), FailedCrate { name, root, is_lib, blocked_by });
+    }
+}
+
+fn crate_name(args: &[String]) -> Option<String> {
+    args.iter()
+        .position(|a| a == "--crate-name")
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+// Binaries (and tests) can't be depended on, everything else is some kind of
+// library.
+fn is_lib(args: &[String]) -> bool {
+    let crate_types: Vec<&str> = args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|&(flag, _)| flag == "--crate-type")
+        .map(|(_, value)| &**value)
+        .collect();
+    !args.iter().any(|a| a == "--test") && !crate_types.is_empty() && !crate_types.contains(&"bin")
+}
+
+// The root source file is the only positional argument ending in `.rs`.
+fn crate_root(args: &[String]) -> Option<&str> {
+    args.iter().skip(1).map(|a| &**a).find(|a| !a.starts_with('-') && a.ends_with(".rs"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(name: &str, externs: &[&str]) -> Vec<String> {
+        let mut args = vec!["rustc".to_owned(), "--crate-name".to_owned(), name.to_owned(),
+                            format!("{}/src/lib.rs", name), "--crate-type".to_owned(), "lib".to_owned()];
+        for dep in externs {
+            args.push("--extern".to_owned());
+            args.push(format!("{}=target/debug/deps/lib{}.rmeta", dep, dep));
+        }
+        args
+    }
+
+    #[test]
+    fn test_failures() {
+        let failures = CrateFailures::new();
+        let build_dir = Path::new("/ws");
+        failures.record(&args("a", &[]), build_dir, true, None);
+        assert_eq!(failures.failed_dependency(&args("b", &["serde", "a"])), Some("a".to_owned()));
+        assert_eq!(failures.failed_dependency(&args("c", &["serde"])), None);
+
+        failures.record(&args("b", &["a"]), build_dir, true, Some("a".to_owned()));
+        let failed = failures.failed_crates();
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].root, PathBuf::from("/ws/a/src/lib.rs"));
+        assert_eq!(failed[1].blocked_by, Some("a".to_owned()));
+
+        failures.record(&args("a", &[]), build_dir, false, None);
+        assert_eq!(failures.failed_crates().len(), 1);
+    }
+
+    #[test]
+    fn test_failures_by_root() {
+        let failures = CrateFailures::new();
+        let build_dir = Path::new("/ws");
+        // The binary of package `a` fails, its library doesn't.
+        let bin = vec!["rustc".to_owned(), "--crate-name".to_owned(), "a".to_owned(), "a/src/main.rs".to_owned(),
+                       "--crate-type".to_owned(), "bin".to_owned()];
+        failures.record(&bin, build_dir, true, None);
+        failures.record(&args("a", &[]), build_dir, false, None);
+        let failed = failures.failed_crates();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].root, PathBuf::from("/ws/a/src/main.rs"));
+        assert_eq!(failures.failed_dependency(&args("b", &["a"])), None);
+    }
+}
@@ -10,29 +10,41 @@
 
 //! Running builds as-needed for the server to answer questions.
 
-pub use self::cargo::make_cargo_config;
+pub use self::cargo::{current_sysroot, default_rls_target_dir, make_cargo_config, rls_target_dir};
+pub use self::cargo::LibNames;
+pub use self::failures::{CrateFailures, FailedCrate};
+pub use self::standalone::{add_standalone_file, is_standalone_project, remove_standalone_project, standalone_project};
+pub use self::toolchain::{forget_sysroots, toolchain_file, with_toolchain, ProjectToolchain, ToolchainCache};
 
 use data::Analysis;
 use vfs::Vfs;
 use config::Config;
+use paths;
 
 use self::environment::EnvironmentLock;
+use self::rust_project::project_json;
 
 use std::boxed::FnBox;
+use std::cmp;
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::io::{self, Write};
 use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 mod environment;
 mod cargo;
 mod rustc;
 mod plan;
+mod remote;
+mod rust_project;
+mod standalone;
+mod failures;
+mod toolchain;
 
 use self::plan::{Plan as BuildPlan, WorkStatus};
 
@@ -82,12 +94,50 @@ struct Internals {
     env_lock: Arc<EnvironmentLock>,
     /// Set of files that were modified since last build.
     dirty_files: Arc<Mutex<HashMap<PathBuf, FileVersion>>>,
+    /// When each dirty file was last edited, the crates of the most recent
+    /// edits are built first (see `Plan::prepare_work`).
+    edit_times: Mutex<HashMap<PathBuf, Instant>>,
     vfs: Arc<Vfs>,
     // This lock should only be held transiently.
     config: Arc<Mutex<Config>>,
-    building: AtomicBool,
+    // Whether the build thread is running, `build_thread_done` is notified
+    // when it stops.
+    building: Mutex<bool>,
+    build_thread_done: Condvar,
+    // No more builds are run once the RLS shuts down.
+    shut_down: AtomicBool,
+    /// Workspace members which failed to build.
+    failures: Arc<CrateFailures>,
+    /// The library names of the packages of the last Cargo build.
+    lib_names: Arc<LibNames>,
+    /// The toolchain the project is built with.
+    toolchain: Arc<ToolchainCache>,
+    // See `BuildQueue::on_progress`.
+    progress: Mutex<Option<ProgressHandler>>,
+    /// The messages of the last compilation of each crate of a
+    /// `rust-project.json`, by root module, and whether it failed.
+    project_crates: Mutex<HashMap<PathBuf, (Vec<String>, bool)>>,
 }
 
+/// A crate starting to compile during a Cargo build, see
+/// `BuildQueue::on_progress`.
+#[derive(Debug, Clone)]
+pub struct BuildProgress {
+    /// The name of the crate.
+    pub crate_name: String,
+    /// The number of crates which started compiling so far, this one included.
+    pub index: usize,
+    /// The number of crates the build compiles, leaving out the fresh ones.
+    /// Freshness is guessed from Cargo's output, so this is raised to `index`
+    /// if more crates are compiled.
+    pub total: usize,
+}
+
+type ProgressHandler = Arc<Fn(&BuildProgress) + Send + Sync>;
+
+// See `BuildQueue::request_build`.
+type DiagnosticsHandler = Arc<Fn(Vec<String>) + Send + Sync>;
+
 /// The result of a build request.
 #[derive(Debug)]
 pub enum BuildResult {
@@ -155,6 +205,8 @@ struct PendingBuild {
     build_dir: PathBuf,
     priority: BuildPriority,
     built_files: HashMap<PathBuf, FileVersion>,
+    // Closure to publish the messages of the edited crates early.
+    early_diagnostics: DiagnosticsHandler,
     // Closure to execute once the build is complete.
     and_then: Box<FnBox(BuildResult) + Send + 'static>,
 }
@@ -229,10 +281,27 @@ impl BuildQueue {
     /// `and_then` is a closure to run after a build has completed or been
     /// squashed.  It must return quickly and without blocking. If it has work
     /// to do, it should spawn a thread to do it.
-    pub fn request_build<F>(&self, new_build_dir: &Path, mut priority: BuildPriority, and_then: F)
-        where F: FnOnce(BuildResult) + Send + 'static
+    ///
+    /// The crates of the most recently edited files are built first. If other
+    /// crates still have to be built after them, `early_diagnostics` is called
+    /// with the compiler messages so far, so that the diagnostics of the edited
+    /// files don't wait for the whole build. `and_then` is still called with
+    /// all of them. It is called on the build thread and must return quickly as
+    /// well.
+    pub fn request_build<D, F>(&self,
+                               new_build_dir: &Path,
+                               mut priority: BuildPriority,
+                               early_diagnostics: D,
+                               and_then: F)
+        where D: Fn(Vec<String>) + Send + Sync + 'static,
+              F: FnOnce(BuildResult) + Send + 'static
     {
         trace!("request_build {:?}", priority);
+        if self.internals.shut_down.load(Ordering::SeqCst) {
+            trace!("request_build - shut down");
+            and_then(BuildResult::Squashed);
+            return;
+        }
         let needs_compilation_ctx_from_cargo = {
             let context = self.internals.compilation_cx.lock().unwrap();
             context.args.is_empty() && context.envs.is_empty()
@@ -242,9 +311,10 @@ impl BuildQueue {
         }
 
         let build = PendingBuild {
-            build_dir: new_build_dir.to_owned(),
+            build_dir: paths::normalize(new_build_dir),
             built_files: self.internals.dirty_files.lock().unwrap().clone(),
             priority,
+            early_diagnostics: Arc::new(early_diagnostics),
             and_then: Box::new(and_then),
         };
 
@@ -255,11 +325,15 @@ impl BuildQueue {
         Self::push_build(&mut queued, build);
 
         // Need to spawn while holding the lock on queued so that we don't race.
-        if !self.internals.building.swap(true, Ordering::SeqCst) {
+        let mut building = self.internals.building.lock().unwrap();
+        if !*building {
+            *building = true;
             thread::spawn(move || {
                 BuildQueue::run_thread(queued_clone, &internals_clone);
-                let building = internals_clone.building.swap(false, Ordering::SeqCst);
-                assert!(building);
+                let mut building = internals_clone.building.lock().unwrap();
+                assert!(*building);
+                *building = false;
+                internals_clone.build_thread_done.notify_all();
             });
         }
     }
@@ -328,10 +402,12 @@ impl BuildQueue {
                 trace!("sleeping");
                 thread::sleep(Duration::from_millis(wait_to_build));
 
-                // Check if a new build arrived while we were sleeping.
+                // Check if a new build arrived (or the RLS shut down) while we
+                // were sleeping.
                 let interrupt = {
                     let queued = queued.lock().unwrap();
-                    queued.0.is_pending() || queued.1.is_pending()
+                    queued.0.is_pending() || queued.1.is_pending() ||
+                        internals.shut_down.load(Ordering::SeqCst)
                 };
                 if interrupt {
                     and_then(BuildResult::Squashed);
@@ -341,7 +417,7 @@ impl BuildQueue {
 
             // Run the build.
             let result = internals.run_build(&build.build_dir, build.priority,
-                                             &build.built_files);
+                                             &build.built_files, &build.early_diagnostics);
             // Assert that the build was not squashed.
             if let BuildResult::Squashed = result {
                 unreachable!();
@@ -358,13 +434,77 @@ impl BuildQueue {
         }
     }
 
+    /// Stops building: squashes the pending builds, refuses new ones and waits
+    /// (at most `timeout`) for the running build to finish. Builds can't be
+    /// interrupted, and waiting means the compiler doesn't leave half-written
+    /// analysis data or processes behind when the RLS exits. Returns whether
+    /// no build is running anymore.
+    pub fn shut_down(&self, timeout: Duration) -> bool {
+        self.stop();
+
+        let started = Instant::now();
+        let mut building = self.internals.building.lock().unwrap();
+        while *building {
+            let remaining = match timeout.checked_sub(started.elapsed()) {
+                Some(remaining) => remaining,
+                None => return false,
+            };
+            building = self.internals.build_thread_done.wait_timeout(building, remaining).unwrap().0;
+        }
+        true
+    }
+
+    /// Squashes the pending builds and refuses new ones, without waiting for
+    /// the running build.
+    pub fn stop(&self) {
+        self.internals.shut_down.store(true, Ordering::SeqCst);
+        let mut queued = self.queued.lock().unwrap();
+        if queued.0.is_pending() {
+            Self::squash_build(&mut queued.0);
+        }
+        if queued.1.is_pending() {
+            Self::squash_build(&mut queued.1);
+        }
+    }
+
     /// Marks a given versioned file as dirty since last build. The dirty flag
     /// will be cleared by a successful build that builds this or a more recent
     /// version of this file.
     pub fn mark_file_dirty(&self, file: PathBuf, version: FileVersion) {
         trace!("Marking file as dirty: {:?} ({})", file, version);
+        self.internals.edit_times.lock().unwrap().insert(file.clone(), Instant::now());
         self.internals.dirty_files.lock().unwrap().insert(file, version);
     }
+
+    /// The files changed since the last successful build which included them.
+    pub fn dirty_files(&self) -> Vec<PathBuf> {
+        self.internals.dirty_files.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// The workspace members which failed to build, see the `failures` module.
+    pub fn crate_failures(&self) -> Arc<CrateFailures> {
+        self.internals.failures.clone()
+    }
+
+    /// The library names of the packages of the project, see `LibNames`.
+    pub fn lib_names(&self) -> Arc<LibNames> {
+        self.internals.lib_names.clone()
+    }
+
+    /// The toolchain of the project, see `ToolchainCache`. Builds of the
+    /// project of another toolchain than the RLS's are run with it.
+    pub fn toolchain(&self) -> Arc<ToolchainCache> {
+        self.internals.toolchain.clone()
+    }
+
+    /// Call `handler` whenever a crate starts compiling while Cargo builds
+    /// the project (the first build, and those re-running Cargo), usually
+    /// dependencies. The other builds only compile the workspace's crates.
+    pub fn on_progress<F>(&self, handler: F)
+        where F: Fn(&BuildProgress) + Send + Sync + 'static
+    {
+        *self.internals.progress.lock().unwrap() = Some(Arc::new(handler));
+    }
 }
 
 impl Internals {
@@ -374,10 +514,18 @@ impl Internals {
             vfs,
             config,
             dirty_files: Arc::new(Mutex::new(HashMap::new())),
+            edit_times: Mutex::new(HashMap::new()),
             // Since environment is global mutable state and we can run multiple server
             // instances, be sure to use a global lock to ensure env var consistency
             env_lock: EnvironmentLock::get(),
-            building: AtomicBool::new(false),
+            building: Mutex::new(false),
+            build_thread_done: Condvar::new(),
+            shut_down: AtomicBool::new(false),
+            failures: Arc::new(CrateFailures::new()),
+            lib_names: Arc::new(LibNames::new()),
+            toolchain: Arc::new(ToolchainCache::new()),
+            progress: Mutex::new(None),
+            project_crates: Mutex::new(HashMap::new()),
         }
     }
 
@@ -387,6 +535,7 @@ impl Internals {
         new_build_dir: &Path,
         priority: BuildPriority,
         built_files: &HashMap<PathBuf, FileVersion>,
+        early_diagnostics: &DiagnosticsHandler,
     ) -> BuildResult {
         trace!("run_build, {:?} {:?}", new_build_dir, priority);
 
@@ -403,10 +552,15 @@ impl Internals {
                 // Killing these args indicates we'll do a full Cargo build.
                 compilation_cx.args = vec![];
                 compilation_cx.envs = HashMap::new();
+                // The cached build plan was created for the previous Cargo
+                // options (e.g. the enabled set of features), so we need
+                // Cargo to regenerate it as well.
+                compilation_cx.build_plan.clear();
+                self.project_crates.lock().unwrap().clear();
             }
         }
 
-        let result = self.build();
+        let result = self.build(early_diagnostics);
         // On a successful build, clear dirty files that were successfully built
         // now. It's possible that a build was scheduled with given files, but
         // user later changed them. These should still be left as dirty (not built).
@@ -419,6 +573,7 @@ impl Internals {
                     .unwrap_or(false)
                 });
                 trace!("Files still dirty after the build: {:?}", *dirty_files);
+                self.edit_times.lock().unwrap().retain(|file, _| dirty_files.contains_key(file));
             },
             _ => {}
         };
@@ -426,7 +581,7 @@ impl Internals {
     }
 
     // Build the project.
-    fn build(&self) -> BuildResult {
+    fn build(&self, early_diagnostics: &DiagnosticsHandler) -> BuildResult {
         trace!("running build");
         // When we change build directory (presumably because the IDE is
         // changing project), we must do a cargo build of the whole project.
@@ -444,6 +599,31 @@ impl Internals {
         // do this so we can load changed code from the VFS, rather than from
         // disk).
 
+        // If configured, the build runs entirely outside of the RLS process.
+        let remote_build = self.config.lock().unwrap().remote_build.clone();
+        if let Some(ref remote_build) = remote_build {
+            return remote::remote(self, remote_build, early_diagnostics);
+        }
+
+        // The compiler of the RLS can't build the project of another
+        // toolchain, if the `toolchain` option asks for one its Cargo builds
+        // the project instead.
+        let build_dir = self.compilation_cx.lock().unwrap().build_dir.clone();
+        if let Some(ref build_dir) = build_dir {
+            let toolchain = self.toolchain.get(&self.config, build_dir);
+            if toolchain.builds_outside() {
+                if let Some(ref name) = toolchain.name {
+                    return remote::toolchain(self, name, toolchain.nightly, early_diagnostics);
+                }
+            }
+        }
+
+        // Projects which don't use Cargo describe their crates themselves.
+        let project_file = build_dir.as_ref().and_then(|dir| project_json(dir));
+        if let Some(path) = project_file {
+            return rust_project::rust_project(self, &path);
+        }
+
         // Don't hold this lock when we run Cargo.
         let needs_to_run_cargo = self.compilation_cx.lock().unwrap().args.is_empty();
         let workspace_mode = self.config.lock().unwrap().workspace_mode;
@@ -452,21 +632,23 @@ impl Internals {
             // If the build plan has already been cached, use it, unless Cargo
             // has to be specifically rerun (e.g. when build scripts changed)
             let work = {
-                let modified: Vec<_> = self.dirty_files.lock().unwrap()
-                                           .keys().cloned().collect();
+                let mut modified: Vec<_> = self.dirty_files.lock().unwrap()
+                                               .keys().cloned().collect();
+                let edit_times = self.edit_times.lock().unwrap();
+                modified.sort_by_key(|file| cmp::Reverse(edit_times.get(file).cloned()));
                 let cx = self.compilation_cx.lock().unwrap();
                 cx.build_plan.prepare_work(&modified)
             };
             return match work {
                 // In workspace_mode, cargo performs the full build and returns
                 // appropriate diagnostics/analysis data
-                WorkStatus::NeedsCargo => cargo::cargo(self),
-                WorkStatus::Execute(job_queue) => job_queue.execute(self),
+                WorkStatus::NeedsCargo => cargo::cargo(self, Some(early_diagnostics.clone())),
+                WorkStatus::Execute(job_queue) => job_queue.execute(self, early_diagnostics),
             };
         // In single package mode Cargo needs to be run to cache args/envs for
         // future rustc calls
         } else if needs_to_run_cargo {
-            if let BuildResult::Err = cargo::cargo(self) {
+            if let BuildResult::Err = cargo::cargo(self, None) {
                 return BuildResult::Err;
             }
         }
@@ -26,13 +26,14 @@
 
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::Hash;
 use std::path::Path;
 
 use cargo::core::{PackageId, Profile, Target, TargetKind};
 use cargo::ops::{Kind, Unit, Context};
 use cargo::util::{CargoResult, ProcessBuilder};
 
-use super::{BuildResult, Internals};
+use super::{BuildResult, DiagnosticsHandler, Internals};
 
 /// Main key type by which `Unit`s will be distinguished in the build plan.
 pub type UnitKey = (PackageId, TargetKind);
@@ -236,6 +237,10 @@ impl Plan {
         }
     }
 
+    /// Returns the compiler calls rebuilding what `modified` affects, ordered
+    /// by how recently the files were edited (most recent first): the unit of
+    /// the most recently edited file is built first (after the dirty units it
+    /// depends on, of course), so its results are ready soonest.
     pub fn prepare_work<T: AsRef<Path> + fmt::Debug>(&self, modified: &[T]) -> WorkStatus {
         if self.is_ready() == false { return WorkStatus::NeedsCargo; }
 
@@ -250,6 +255,16 @@ impl Plan {
 
             let queue = self.topological_sort(&graph);
             trace!("Topologically sorted dirty graph: {:?}", queue);
+            let edited: Vec<_> = modified.iter().filter_map(|file| {
+                self.fetch_dirty_units(&[file]).into_iter().next()
+            }).collect();
+            let queue = prioritize(queue, &self.dep_graph, &edited);
+            trace!("Prioritized for the edited units {:?}: {:?}", edited, queue);
+            // The edited units are built first (from the end of the queue),
+            // up to the one closest to its start.
+            let edited_jobs = queue.iter()
+                .position(|unit| edited.contains(unit))
+                .map_or(0, |index| queue.len() - index);
             let jobs: Vec<_> = queue.iter()
                 .map(|x| self.compiler_jobs.get(x).unwrap().clone())
                 .collect();
@@ -257,7 +272,7 @@ impl Plan {
             if jobs.is_empty() {
                 WorkStatus::NeedsCargo
             } else {
-                WorkStatus::Execute(JobQueue(jobs))
+                WorkStatus::Execute(JobQueue { jobs, edited_jobs })
             }
         }
     }
@@ -268,20 +283,28 @@ pub enum WorkStatus {
     Execute(JobQueue)
 }
 
-pub struct JobQueue(Vec<ProcessBuilder>);
+pub struct JobQueue {
+    jobs: Vec<ProcessBuilder>,
+    /// The number of jobs building the units of the edited files, which are
+    /// dequeued first.
+    edited_jobs: usize,
+}
 
 impl JobQueue {
     pub fn dequeue(&mut self) -> Option<ProcessBuilder> {
-        self.0.pop()
+        self.jobs.pop()
     }
 
-    /// Performs a rustc build using cached compiler invocations.
-    pub(super) fn execute(mut self, internals: &Internals) -> BuildResult {
+    /// Performs a rustc build using cached compiler invocations. The messages
+    /// are passed to `early_diagnostics` once the edited units are built, if
+    /// other units are left.
+    pub(super) fn execute(mut self, internals: &Internals, early_diagnostics: &DiagnosticsHandler) -> BuildResult {
         // TODO: In case of an empty job queue we shouldn't be here, since the
         // returned results will replace currently held diagnostics/analyses.
         // Either allow to return a BuildResult::Squashed here or just delegate
         // to Cargo (which we do currently) in `prepare_work`
-        assert!(self.0.is_empty() == false);
+        assert!(self.jobs.is_empty() == false);
+        let early_after = self.jobs.len() - self.edited_jobs;
 
         let build_dir = internals.compilation_cx.lock().unwrap().build_dir.clone().unwrap();
 
@@ -290,17 +313,33 @@ impl JobQueue {
         // Go through cached compiler invocations sequentially, collecting each
         // invocation's compiler messages for diagnostics and analysis data
         while let Some(job) = self.dequeue() {
+            // The edited units were just built.
+            if self.edited_jobs > 0 && early_after > 0 && self.jobs.len() + 1 == early_after {
+                early_diagnostics(compiler_messages.clone());
+            }
             trace!("Executing: {:?}", job);
             let mut args: Vec<_> = job.get_args().iter().cloned()
                 .map(|x| x.into_string().unwrap()).collect();
 
             args.insert(0, job.get_program().clone().into_string().unwrap());
 
+            // Skip the members depending on a failed one, see the `failures`
+            // module.
+            if let Some(dep) = internals.failures.failed_dependency(&args) {
+                internals.failures.record(&args, &build_dir, true, Some(dep));
+                continue;
+            }
+
             match super::rustc::rustc(&internals.vfs, &args, job.get_envs(),
                                       &build_dir, internals.config.clone(),
                                       internals.env_lock.as_facade()) {
-                BuildResult::Success(mut messages, mut analysis) |
+                BuildResult::Success(mut messages, mut analysis) => {
+                    internals.failures.record(&args, &build_dir, false, None);
+                    compiler_messages.append(&mut messages);
+                    analyses.append(&mut analysis);
+                },
                 BuildResult::Failure(mut messages, mut analysis) => {
+                    internals.failures.record(&args, &build_dir, true, None);
                     compiler_messages.append(&mut messages);
                     analyses.append(&mut analysis);
                 },
@@ -313,6 +352,34 @@ impl JobQueue {
     }
 }
 
+// Reorders `stack` (a topological ordering, built from the last element) to
+// build the units of `first` as early as possible, in that order, each right
+// after its dependencies in `stack`. The others keep their relative order.
+fn prioritize<K: Clone + Eq + Hash>(stack: Vec<K>, deps: &HashMap<K, HashSet<K>>, first: &[K]) -> Vec<K> {
+    let dirty: HashSet<K> = stack.iter().cloned().collect();
+    let mut order = vec![];
+    let mut done = HashSet::new();
+    for unit in first.iter().chain(stack.iter().rev()) {
+        visit(unit, deps, &dirty, &mut done, &mut order);
+    }
+    order.reverse();
+    return order;
+
+    fn visit<K: Clone + Eq + Hash>(unit: &K, deps: &HashMap<K, HashSet<K>>, dirty: &HashSet<K>,
+                                   done: &mut HashSet<K>, order: &mut Vec<K>) {
+        if !dirty.contains(unit) || done.contains(unit) {
+            return;
+        }
+        done.insert(unit.clone());
+        if let Some(unit_deps) = deps.get(unit) {
+            for dep in unit_deps {
+                visit(dep, deps, dirty, done, order);
+            }
+        }
+        order.push(unit.clone());
+    }
+}
+
 fn key_from_unit(unit: &Unit) -> UnitKey {
     (unit.pkg.package_id().clone(), unit.target.kind().clone())
 }
@@ -358,3 +425,27 @@ impl<'a> From<&'a Unit<'a>> for OwnedUnit {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prioritize() {
+        // `c` depends on `a` and `b`, `b` on `a`, `d` on nothing.
+        let mut deps = HashMap::new();
+        deps.insert("a", HashSet::new());
+        deps.insert("b", vec!["a"].into_iter().collect());
+        deps.insert("c", vec!["a", "b"].into_iter().collect());
+        deps.insert("d", HashSet::new());
+        // Built from the last element.
+        let stack = vec!["c", "b", "a", "d"];
+
+        assert_eq!(prioritize(stack.clone(), &deps, &[]), stack);
+        assert_eq!(prioritize(stack.clone(), &deps, &["b"]), vec!["c", "d", "b", "a"]);
+        assert_eq!(prioritize(stack.clone(), &deps, &["c", "d"]), vec!["d", "c", "b", "a"]);
+        assert_eq!(prioritize(stack.clone(), &deps, &["d", "c"]), vec!["c", "b", "a", "d"]);
+        // Units which aren't dirty aren't built.
+        assert_eq!(prioritize(vec!["c", "b"], &deps, &["c"]), vec!["c", "b"]);
+    }
+}
@@ -0,0 +1,327 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Builds which are run outside of the RLS process, in an environment that
+//! has a different view of the file system (e.g. inside a container or on
+//! another machine over SSH). We run `cargo check` there and map the paths in
+//! the emitted diagnostics back to the local project, like the paths of the
+//! client are mapped (see `paths`).
+//!
+//! Projects whose `toolchain` option names another toolchain than the RLS's
+//! are built outside of the process as well, by that toolchain's `cargo
+//! check`, locally.
+
+use serde_json::{self, Value};
+
+use build::{rls_target_dir, BuildResult, DiagnosticsHandler, Internals};
+use config::{Config, RemoteBuildConfig};
+use paths::PrefixMap;
+
+use std::env;
+use std::io::{BufRead, BufReader, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Runs `cargo check` using the configured remote command, e.g.
+/// `["docker", "exec", "my-container", "sh", "-c"]` or `["ssh", "build-host"]`.
+/// The command gets the whole shell command line as its last argument.
+/// The messages are passed to `early_diagnostics` as the crates are checked.
+pub(super) fn remote(internals: &Internals,
+                     remote: &RemoteBuildConfig,
+                     early_diagnostics: &DiagnosticsHandler)
+                     -> BuildResult {
+    let build_dir = internals.compilation_cx.lock().unwrap().build_dir.clone().unwrap();
+
+    let (program, prefix_args) = match remote.command.split_first() {
+        Some(split) => split,
+        None => {
+            debug!("remote build - empty `command` in `remote_build` configuration");
+            return BuildResult::Err;
+        }
+    };
+
+    let remote_cmd = format!("sh -c {}", shell_quote(&check_command(&internals.config.lock().unwrap(),
+                                                                   &remote.remote_root)));
+    trace!("remote build - running {:?} {:?} {}", program, prefix_args, remote_cmd);
+
+    // A single argument, SSH would join several ones with spaces anyway.
+    let mut cmd = Command::new(program);
+    cmd.args(prefix_args).arg(&remote_cmd);
+    run_check(cmd, &remote.remote_root, &build_dir, early_diagnostics)
+}
+
+/// Runs `cargo check` of the rustup `toolchain` in the project. Only a
+/// `nightly` compiler accepts `-Zsave-analysis`, then the analysis data is saved
+/// where the RLS' own builds save it (see `memory::analysis_dir`). The files
+/// are read from disk, the VFS's unsaved changes aren't seen. The messages are
+/// passed to `early_diagnostics` as the crates are checked.
+pub(super) fn toolchain(internals: &Internals,
+                        toolchain: &str,
+                        nightly: bool,
+                        early_diagnostics: &DiagnosticsHandler)
+                        -> BuildResult {
+    let build_dir = internals.compilation_cx.lock().unwrap().build_dir.clone().unwrap();
+    let (check_cmd, rustflags, target_dir) = {
+        let config = internals.config.lock().unwrap();
+        let mut rustflags = format!("{} {}",
+                                    env::var("RUSTFLAGS").unwrap_or_default(),
+                                    config.rustflags.clone().unwrap_or_default());
+        if nightly {
+            rustflags.push_str(" -Zsave-analysis");
+        }
+        let target_dir = rls_target_dir(&build_dir, &config);
+        (check_command(&config, build_dir.to_str().unwrap()), rustflags, target_dir)
+    };
+    trace!("toolchain build - running {} with `{}`", check_cmd, toolchain);
+
+    let mut cmd = Command::new("rustup");
+    cmd.args(&["run", toolchain, "sh", "-c", &check_cmd[..]])
+        .env("RUSTFLAGS", rustflags.trim())
+        .env("CARGO_TARGET_DIR", target_dir);
+    let build_dir_str = build_dir.to_str().unwrap().to_owned();
+    run_check(cmd, &build_dir_str, &build_dir, early_diagnostics)
+}
+
+// Runs `cmd`, a `cargo check --message-format=json`, and collects the compiler
+// messages, with the paths under `root` (where it runs) mapped to `build_dir`.
+// Whenever a crate was checked, the messages so far are passed to
+// `early_diagnostics` if there are new ones. If it fails without an error from
+// the compiler (e.g. SSH couldn't connect or Cargo rejected the flags), what it
+// wrote to stderr is reported as an error.
+fn run_check(mut cmd: Command, root: &str, build_dir: &Path, early_diagnostics: &DiagnosticsHandler) -> BuildResult {
+    let roots = PrefixMap::new(&[(PathBuf::from(root), build_dir.to_owned())]);
+    let mut child = match cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            info!("remote build - couldn't spawn {:?}: {}", cmd, e);
+            return BuildResult::Err;
+        }
+    };
+
+    // Read on another thread, the command blocks if either pipe is full.
+    let mut stderr = child.stderr.take().expect("remote build stderr is piped");
+    let stderr_reader = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stderr.read_to_string(&mut text);
+        text
+    });
+
+    // Cargo emits a line for each message and, once a crate is checked, one
+    // for the crate, so the diagnostics are published crate by crate rather
+    // than after the whole build.
+    let mut messages = vec![];
+    let mut published = 0;
+    let mut errors = false;
+    {
+        let stdout = child.stdout.as_mut().expect("remote build stdout is piped");
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let value: Value = match serde_json::from_str(&line) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            let checked = value.get("reason").and_then(|r| r.as_str()) == Some("compiler-artifact");
+            if let Some(msg) = compiler_message(value, &roots, build_dir) {
+                errors |= msg.get("level").and_then(|l| l.as_str()) == Some("error");
+                messages.push(msg.to_string());
+            } else if checked && messages.len() > published {
+                early_diagnostics(messages.clone());
+                published = messages.len();
+            }
+        }
+    }
+
+    let status = child.wait();
+    let stderr = stderr_reader.join().unwrap_or_default();
+    match status {
+        Ok(status) if status.success() => BuildResult::Success(messages, vec![]),
+        Ok(status) => {
+            if !errors {
+                info!("remote build - failed with {}: {}", status, stderr);
+                messages.push(failure_message(build_dir, &stderr, &status.to_string()));
+            }
+            BuildResult::Failure(messages, vec![])
+        }
+        Err(e) => {
+            info!("remote build - failed waiting for the build: {}", e);
+            BuildResult::Err
+        }
+    }
+}
+
+// An error on the first line of the project's `Cargo.toml`, in the format of
+// the compiler's messages, saying that the build failed with `stderr`.
+fn failure_message(build_dir: &Path, stderr: &str, status: &str) -> String {
+    let stderr = stderr.trim();
+    let message = if stderr.is_empty() {
+        format!("The build failed ({})", status)
+    } else {
+        format!("The build failed ({}):\n{}", status, stderr)
+    };
+    json!({
+        "message": message,
+        "code": null,
+        "level": "error",
+        "spans": [{
+            "file_name": build_dir.join("Cargo.toml").to_str().unwrap(),
+            "byte_start": 0,
+            "byte_end": 0,
+            "line_start": 1,
+            "line_end": 1,
+            "column_start": 1,
+            "column_end": 1,
+            "is_primary": true,
+            "text": [],
+            "label": null,
+            "suggested_replacement": null,
+            "expansion": null
+        }],
+        "children": [],
+        "rendered": null
+    }).to_string()
+}
+
+// The shell command line checking the project in `remote_root`, with the
+// environment, target and features of the config.
+fn check_command(config: &Config, remote_root: &str) -> String {
+    let mut cmd = format!("cd {}", shell_quote(remote_root));
+    let mut env: Vec<_> = config.env.iter().collect();
+    env.sort();
+    for (name, value) in env {
+        match *value {
+            Some(ref value) => cmd.push_str(&format!(" && export {}={}", name, shell_quote(value))),
+            None => cmd.push_str(&format!(" && unset {}", name)),
+        }
+    }
+    cmd.push_str(" && cargo check --message-format=json");
+    if let Some(ref target) = config.target_triple {
+        cmd.push_str(&format!(" --target {}", shell_quote(target)));
+    }
+    if !config.features.is_empty() {
+        cmd.push_str(&format!(" --features {}", shell_quote(&config.features.join(" "))));
+    }
+    if config.all_features {
+        cmd.push_str(" --all-features");
+    }
+    if config.no_default_features {
+        cmd.push_str(" --no-default-features");
+    }
+    cmd
+}
+
+// Quotes `s` as a single word for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// Extracts the compiler message from a line of Cargo JSON output, with remote
+// paths mapped to local ones by `roots`. Relative paths are relative to
+// `local_root`.
+fn compiler_message(mut value: Value, roots: &PrefixMap, local_root: &Path) -> Option<Value> {
+    if value.get("reason").and_then(|r| r.as_str()) != Some("compiler-message") {
+        return None;
+    }
+    let mut message = mem::replace(value.get_mut("message")?, Value::Null);
+    translate_paths(&mut message, roots, local_root);
+    Some(message)
+}
+
+// Rewrites every `file_name` in a compiler message (including child messages
+// and macro expansions) from the remote to the local project root.
+fn translate_paths(value: &mut Value, roots: &PrefixMap, local_root: &Path) {
+    match *value {
+        Value::Object(ref mut map) => {
+            for (key, v) in map.iter_mut() {
+                let local = match *v {
+                    Value::String(ref file) if key == "file_name" => {
+                        Some(translate_path(file, roots, local_root))
+                    }
+                    _ => None,
+                };
+                match local {
+                    Some(local) => *v = Value::String(local),
+                    None => translate_paths(v, roots, local_root),
+                }
+            }
+        }
+        Value::Array(ref mut values) => {
+            for v in values {
+                translate_paths(v, roots, local_root);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn translate_path(file: &str, roots: &PrefixMap, local_root: &Path) -> String {
+    let path = Path::new(file);
+    let local = if path.is_relative() {
+        local_root.join(path)
+    } else {
+        match roots.forward(path) {
+            Some(local) => local,
+            // Outside of the project (e.g. the remote's registry or sysroot),
+            // we can't map these to anything sensible locally.
+            None => return file.to_owned(),
+        }
+    };
+    local.to_str().unwrap().to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_translate_paths() {
+        let local_root = PathBuf::from("/home/user/project");
+        let mut message = json!({
+            "message": "unused variable",
+            "spans": [{ "file_name": "/workspace/src/main.rs", "expansion": null }],
+            "children": [{ "spans": [{ "file_name": "src/lib.rs" }] }, { "spans": [{ "file_name": "/usr/lib/foo.rs" }] }]
+        });
+
+        let roots = PrefixMap::new(&[(PathBuf::from("/workspace"), local_root.clone())]);
+        translate_paths(&mut message, &roots, &local_root);
+
+        assert_eq!(message["spans"][0]["file_name"], local_root.join("src/main.rs").to_str().unwrap());
+        assert_eq!(message["children"][0]["spans"][0]["file_name"], local_root.join("src/lib.rs").to_str().unwrap());
+        assert_eq!(message["children"][1]["spans"][0]["file_name"], "/usr/lib/foo.rs");
+        assert_eq!(translate_path("/workspace2/src/lib.rs", &roots, &local_root), "/workspace2/src/lib.rs");
+    }
+
+    #[test]
+    fn test_failure_message() {
+        let build_dir = PathBuf::from("/home/user/project");
+        let message = failure_message(&build_dir, "error: Unrecognized option: 'Z'\n", "exit code: 101");
+        let diagnostic = ::actions::diagnostics::parse_diagnostics(&message, "rustc", "{}").unwrap();
+        assert_eq!(diagnostic.file_path, build_dir.join("Cargo.toml"));
+        assert_eq!(diagnostic.diagnostic.message,
+                   "The build failed (exit code: 101):\nerror: Unrecognized option: 'Z'");
+    }
+
+    #[test]
+    fn test_check_command() {
+        let mut config = Config::default();
+        config.target_triple = Some("wasm32-unknown-unknown".to_owned());
+        config.features = vec!["foo".to_owned(), "bar".to_owned()];
+        config.env.insert("FOO".to_owned(), Some("it's".to_owned()));
+        config.env.insert("BAR".to_owned(), None);
+        assert_eq!(check_command(&config, "/my project"),
+                   "cd '/my project' && unset BAR && export FOO='it'\\''s' && cargo check --message-format=json \
+                    --target 'wasm32-unknown-unknown' --features 'foo bar'");
+        assert_eq!(shell_quote("cd 'a'"), r"'cd '\''a'\'''");
+    }
+}
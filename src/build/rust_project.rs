@@ -0,0 +1,386 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Builds of projects which don't use Cargo (e.g. built with Bazel, Buck or
+//! Make). Such a project describes its crates in a `rust-project.json` at its
+//! root, which is used instead of Cargo if present:
+//!
+//! ```json
+//! {
+//!     "crates": [
+//!         { "root_module": "util/lib.rs", "edition": "2018", "cfg": ["feature=\"std\""] },
+//!         { "root_module": "app/main.rs", "crate_type": "bin", "deps": [{ "crate": 0, "name": "util" }] }
+//!     ]
+//! }
+//! ```
+//!
+//! `deps` refer to other crates by their index in `crates`, with the name the
+//! depending crate uses. Relative paths are relative to the directory of the
+//! file. Every crate is compiled in-process (so its analysis data is loaded
+//! and unsaved changes are seen), dependencies first, emitting only metadata
+//! into `rust-project` in the target directory. After the first build, only
+//! the crates containing changed files and the crates depending on them are
+//! compiled again.
+
+use build::{BuildResult, Internals};
+use build::cargo::current_sysroot;
+use build::rustc::rustc;
+
+use serde_json;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The description of a project's crates.
+#[derive(Debug, Deserialize)]
+pub struct ProjectJson {
+    pub crates: Vec<CrateJson>,
+}
+
+/// A crate in `rust-project.json`.
+#[derive(Debug, Deserialize)]
+pub struct CrateJson {
+    /// The root source file of the crate.
+    pub root_module: PathBuf,
+    /// Default: "2015"
+    #[serde(default = "default_edition")]
+    pub edition: String,
+    /// The `--crate-type` of the crate, e.g. `bin` or `proc-macro`.
+    /// Default: "lib"
+    #[serde(default = "default_crate_type")]
+    pub crate_type: String,
+    #[serde(default)]
+    pub deps: Vec<DepJson>,
+    /// The `--cfg` options of the crate, e.g. `unix` or `feature="std"`.
+    #[serde(default)]
+    pub cfg: Vec<String>,
+    /// The name of the crate. Default: the name dependent crates use, or
+    /// derived from `root_module`.
+    pub display_name: Option<String>,
+}
+
+/// A dependency of a crate in `rust-project.json`.
+#[derive(Debug, Deserialize)]
+pub struct DepJson {
+    /// The index of the crate depended on.
+    #[serde(rename = "crate")]
+    pub krate: usize,
+    /// The name the dependency goes by in the depending crate.
+    pub name: String,
+}
+
+fn default_edition() -> String {
+    "2015".to_owned()
+}
+
+fn default_crate_type() -> String {
+    "lib".to_owned()
+}
+
+/// The `rust-project.json` of the project in `project_dir`, if it has one.
+pub fn project_json(project_dir: &Path) -> Option<PathBuf> {
+    let path = project_dir.join("rust-project.json");
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Compiles the crates described by the `rust-project.json` at `path`.
+pub(super) fn rust_project(internals: &Internals, path: &Path) -> BuildResult {
+    let project = match load(path) {
+        Ok(project) => project,
+        Err(e) => {
+            info!("rust-project.json - {}", e);
+            return BuildResult::Err;
+        }
+    };
+    let order = match build_order(&project) {
+        Ok(order) => order,
+        Err(e) => {
+            info!("rust-project.json - {}", e);
+            return BuildResult::Err;
+        }
+    };
+
+    let build_dir = internals.compilation_cx.lock().unwrap().build_dir.clone().unwrap();
+    let (out_dir, sysroot, rustflags) = {
+        let config = internals.config.lock().unwrap();
+        let target_dir = config.target_dir.clone().unwrap_or_else(|| build_dir.join("target").join("rls"));
+        // The crates are compiled in-process, so only the sysroot of the
+        // compiler the RLS is linked to fits, as for Cargo builds.
+        (target_dir.join("rust-project"), config.sysroot.clone().or_else(current_sysroot), config.rustflags.clone())
+    };
+    if let Err(e) = fs::create_dir_all(&out_dir) {
+        info!("rust-project.json - couldn't create {}: {}", out_dir.display(), e);
+        return BuildResult::Err;
+    }
+
+    let dirty_files: Vec<_> = internals.dirty_files.lock().unwrap().keys().cloned().collect();
+    let dirty = dirty_crates(&project, &dirty_files);
+    let mut built = internals.project_crates.lock().unwrap();
+    let mut rebuilt = HashSet::new();
+
+    let mut messages = vec![];
+    let mut analysis = vec![];
+    let mut failed = false;
+    for index in order {
+        let krate = &project.crates[index];
+        let needs_build = dirty.contains(&index) ||
+                          krate.deps.iter().any(|dep| rebuilt.contains(&dep.krate));
+        if !needs_build {
+            // Its analysis data is still loaded, only the diagnostics of
+            // its last compilation have to be reported again.
+            if let Some(&(ref crate_messages, crate_failed)) = built.get(&krate.root_module) {
+                messages.extend(crate_messages.iter().cloned());
+                failed |= crate_failed;
+                continue;
+            }
+        }
+        rebuilt.insert(index);
+
+        let mut args = crate_args(&project, index, &out_dir);
+        if let Some(ref rustflags) = rustflags {
+            args.extend(rustflags.split_whitespace().map(|flag| flag.to_owned()));
+        }
+        if let Some(ref sysroot) = sysroot {
+            args.push("--sysroot".to_owned());
+            args.push(sysroot.clone());
+        }
+
+        // Crates depending on a failed one would only report that it's
+        // missing.
+        if let Some(dep) = internals.failures.failed_dependency(&args) {
+            internals.failures.record(&args, &build_dir, true, Some(dep));
+            built.insert(krate.root_module.clone(), (vec![], true));
+            failed = true;
+            continue;
+        }
+        let env_lock = internals.env_lock.as_facade();
+        match rustc(&internals.vfs, &args, &HashMap::new(), &build_dir, internals.config.clone(), env_lock) {
+            BuildResult::Success(crate_messages, mut crate_analysis) => {
+                internals.failures.record(&args, &build_dir, false, None);
+                messages.extend(crate_messages.iter().cloned());
+                analysis.append(&mut crate_analysis);
+                built.insert(krate.root_module.clone(), (crate_messages, false));
+            }
+            BuildResult::Failure(crate_messages, mut crate_analysis) => {
+                internals.failures.record(&args, &build_dir, true, None);
+                messages.extend(crate_messages.iter().cloned());
+                analysis.append(&mut crate_analysis);
+                built.insert(krate.root_module.clone(), (crate_messages, true));
+                failed = true;
+            }
+            BuildResult::Squashed | BuildResult::Err => {
+                // Compile it again the next time.
+                built.remove(&krate.root_module);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        BuildResult::Failure(messages, analysis)
+    } else {
+        BuildResult::Success(messages, analysis)
+    }
+}
+
+fn load(path: &Path) -> Result<ProjectJson, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    parse(&text, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+// Parses the `text` of a `rust-project.json` in `dir`.
+fn parse(text: &str, dir: &Path) -> Result<ProjectJson, String> {
+    let mut project: ProjectJson = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let count = project.crates.len();
+    for krate in &mut project.crates {
+        krate.root_module = dir.join(&krate.root_module);
+        if let Some(dep) = krate.deps.iter().find(|dep| dep.krate >= count) {
+            return Err(format!("`{}` depends on crate {}, there are only {} crates",
+                               krate.root_module.display(), dep.krate, count));
+        }
+    }
+    Ok(project)
+}
+
+// The indices of the crates of `project`, dependencies before the crates
+// depending on them.
+fn build_order(project: &ProjectJson) -> Result<Vec<usize>, String> {
+    fn visit(project: &ProjectJson, index: usize, visiting: &mut Vec<usize>, order: &mut Vec<usize>) -> Result<(), String> {
+        if order.contains(&index) {
+            return Ok(());
+        }
+        if visiting.contains(&index) {
+            return Err(format!("dependency cycle through `{}`", project.crates[index].root_module.display()));
+        }
+        visiting.push(index);
+        for dep in &project.crates[index].deps {
+            visit(project, dep.krate, visiting, order)?;
+        }
+        visiting.pop();
+        order.push(index);
+        Ok(())
+    }
+
+    let mut order = vec![];
+    for index in 0..project.crates.len() {
+        visit(project, index, &mut vec![], &mut order)?;
+    }
+    Ok(order)
+}
+
+// The indices of the crates containing the `files`, i.e. the crates whose
+// root module is in the closest directory above each file (as for Cargo
+// targets, see `Plan::fetch_dirty_units`).
+fn dirty_crates<T: AsRef<Path>>(project: &ProjectJson, files: &[T]) -> HashSet<usize> {
+    files.iter().filter_map(|file| {
+        let file = file.as_ref();
+        project.crates.iter().enumerate()
+            .filter_map(|(index, krate)| {
+                let dir = krate.root_module.parent()?;
+                if file.starts_with(dir) {
+                    Some((index, dir.components().count()))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|&(_, depth)| depth)
+            .map(|(index, _)| index)
+    }).collect()
+}
+
+// The name of the crate at `index`, see `CrateJson::display_name`.
+fn crate_name(project: &ProjectJson, index: usize) -> String {
+    let krate = &project.crates[index];
+    if let Some(ref name) = krate.display_name {
+        return name.replace('-', "_");
+    }
+    let used_as = project.crates.iter().flat_map(|c| c.deps.iter()).find(|dep| dep.krate == index);
+    if let Some(dep) = used_as {
+        return dep.name.replace('-', "_");
+    }
+    // `foo.rs`, or `foo/lib.rs` and `foo/src/main.rs`.
+    let mut path = krate.root_module.as_path();
+    let mut name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("main");
+    while name == "lib" || name == "main" || name == "mod" || name == "src" {
+        path = match path.parent() {
+            Some(parent) if parent.file_name().is_some() => parent,
+            _ => break,
+        };
+        name = path.file_name().and_then(|s| s.to_str()).unwrap_or(name);
+    }
+    name.replace('-', "_")
+}
+
+// The arguments compiling the crate at `index` of `project`, with its
+// dependencies in `out_dir`.
+fn crate_args(project: &ProjectJson, index: usize, out_dir: &Path) -> Vec<String> {
+    let krate = &project.crates[index];
+    let mut args = vec![
+        "rustc".to_owned(),
+        "--crate-name".to_owned(),
+        crate_name(project, index),
+        krate.root_module.to_string_lossy().into_owned(),
+        "--crate-type".to_owned(),
+        krate.crate_type.clone(),
+        "--emit=metadata".to_owned(),
+        "--error-format=json".to_owned(),
+        "--out-dir".to_owned(),
+        out_dir.to_string_lossy().into_owned(),
+        "-L".to_owned(),
+        format!("dependency={}", out_dir.display()),
+    ];
+    if krate.edition != "2015" {
+        args.push(format!("--edition={}", krate.edition));
+    }
+    for cfg in &krate.cfg {
+        args.push("--cfg".to_owned());
+        args.push(cfg.clone());
+    }
+    for dep in &krate.deps {
+        let rmeta = out_dir.join(format!("lib{}.rmeta", crate_name(project, dep.krate)));
+        args.push("--extern".to_owned());
+        args.push(format!("{}={}", dep.name.replace('-', "_"), rmeta.display()));
+    }
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn project() -> ProjectJson {
+        let text = r#"{
+            "crates": [
+                { "root_module": "app/src/main.rs", "crate_type": "bin", "deps": [{ "crate": 2, "name": "my-util" }, { "crate": 1, "name": "log" }] },
+                { "root_module": "third_party/log/lib.rs", "edition": "2018", "cfg": ["feature=\"std\""], "display_name": "log" },
+                { "root_module": "util/src/lib.rs", "deps": [{ "crate": 1, "name": "log" }] }
+            ]
+        }"#;
+        parse(text, Path::new("/p")).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let project = project();
+        assert_eq!(project.crates[0].root_module, Path::new("/p/app/src/main.rs"));
+        assert_eq!(project.crates[0].edition, "2015");
+        assert_eq!(project.crates[2].crate_type, "lib");
+        assert!(parse(r#"{ "crates": [{ "root_module": "lib.rs", "deps": [{ "crate": 1, "name": "a" }] }] }"#,
+                      Path::new("/p")).is_err());
+    }
+
+    #[test]
+    fn test_build_order() {
+        assert_eq!(build_order(&project()), Ok(vec![1, 2, 0]));
+        let cycle = parse(r#"{ "crates": [
+            { "root_module": "a.rs", "deps": [{ "crate": 1, "name": "b" }] },
+            { "root_module": "b.rs", "deps": [{ "crate": 0, "name": "a" }] }
+        ] }"#, Path::new("/p")).unwrap();
+        assert!(build_order(&cycle).is_err());
+    }
+
+    #[test]
+    fn test_dirty_crates() {
+        let project = project();
+        let dirty = dirty_crates(&project, &["/p/util/src/lib.rs", "/p/util/src/a/b.rs"]);
+        assert_eq!(dirty, [2].iter().cloned().collect());
+        let dirty = dirty_crates(&project, &["/p/app/src/main.rs", "/p/third_party/log/x.rs", "/q/c.rs"]);
+        assert_eq!(dirty, [0, 1].iter().cloned().collect());
+    }
+
+    #[test]
+    fn test_crate_name() {
+        let project = project();
+        assert_eq!(crate_name(&project, 0), "app");
+        assert_eq!(crate_name(&project, 1), "log");
+        assert_eq!(crate_name(&project, 2), "my_util");
+    }
+
+    #[test]
+    fn test_crate_args() {
+        let project = project();
+        let out_dir = Path::new("/p/target/rls/rust-project");
+        let args = crate_args(&project, 0, out_dir);
+        assert_eq!(&args[..6], ["rustc", "--crate-name", "app", "/p/app/src/main.rs", "--crate-type", "bin"]);
+        assert!(args.contains(&"my_util=/p/target/rls/rust-project/libmy_util.rmeta".to_owned()));
+        let args = crate_args(&project, 1, out_dir);
+        assert!(args.contains(&"--edition=2018".to_owned()));
+        assert!(args.contains(&"feature=\"std\"".to_owned()));
+    }
+}
@@ -30,6 +30,7 @@ use config::Config;
 use build::{BufWriter, BuildResult};
 use build::environment::{Environment, EnvironmentLockFacade};
 use data::Analysis;
+use paths;
 use vfs::Vfs;
 
 use std::collections::HashMap;
@@ -47,8 +48,14 @@ pub fn rustc(vfs: &Vfs, args: &[String], envs: &HashMap<String, Option<OsString>
 
     let mut local_envs = envs.clone();
 
-    if rls_config.lock().unwrap().clear_env_rust_log {
-        local_envs.insert(String::from("RUST_LOG"), None);
+    {
+        let rls_config = rls_config.lock().unwrap();
+        for (k, v) in &rls_config.env {
+            local_envs.insert(k.clone(), v.as_ref().map(OsString::from));
+        }
+        if rls_config.clear_env_rust_log {
+            local_envs.insert(String::from("RUST_LOG"), None);
+        }
     }
 
     let (guard, _) = env_lock.lock();
@@ -206,9 +213,10 @@ impl FileLoader for ReplacedFileLoader {
     }
 
     fn read_file(&self, path: &Path) -> io::Result<String> {
-        if let Some(abs_path) = self.abs_path(path) {
-            if self.replacements.contains_key(&abs_path) {
-                return Ok(self.replacements[&abs_path].clone());
+        // The VFS is keyed by normalized paths.
+        if let Some(abs_path) = self.abs_path(path).map(|p| paths::normalize(&p)) {
+            if let Some(text) = self.replacements.get(&abs_path) {
+                return Ok(text.clone());
             }
         }
         self.real_file_loader.read_file(path)
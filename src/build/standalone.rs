@@ -0,0 +1,213 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Standalone files, opened by a client which has no workspace (so there is
+//! no root in `initialize`), e.g. a scratch `main.rs`.
+//!
+//! The RLS then makes up a project in a temporary directory, described by a
+//! `rust-project.json` (see `rust_project`) which gets a crate for every Rust
+//! file opened, unless it's a module of the crate of a file opened before. Each
+//! crate is checked as `rustc file.rs` would, with the sysroot of the
+//! toolchain the RLS runs with. The directory is removed when the RLS shuts
+//! down.
+
+use serde_json::{self, Value};
+
+use std::collections::HashSet;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PREFIX: &str = "rls-standalone-";
+
+// Makes the names of the projects of one RLS differ.
+static PROJECT_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Creates the directory of a project without any crates yet, unique to this
+/// RLS: it's never a directory which existed before.
+pub fn standalone_project() -> io::Result<PathBuf> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    loop {
+        let count = PROJECT_COUNT.fetch_add(1, Ordering::SeqCst);
+        let name = format!("{}{}-{}-{}", PREFIX, now.as_secs(), now.subsec_nanos(), count);
+        let dir = env::temp_dir().join(name);
+        match fs::create_dir(&dir) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+        write_project(&dir, &json!({ "crates": [] }))?;
+        return Ok(dir);
+    }
+}
+
+/// Whether `dir` was created by `standalone_project`.
+pub fn is_standalone_project(dir: &Path) -> bool {
+    dir.parent() == Some(env::temp_dir().as_path()) &&
+        dir.file_name().and_then(|name| name.to_str()).map_or(false, |name| name.starts_with(PREFIX))
+}
+
+/// Removes the directory of a standalone project, when the RLS is done with
+/// it. Other directories are left alone.
+pub fn remove_standalone_project(dir: &Path) -> io::Result<()> {
+    if !is_standalone_project(dir) {
+        return Ok(());
+    }
+    fs::remove_dir_all(dir)
+}
+
+/// Adds a crate with the root `file` to the standalone project in `dir`.
+/// Returns whether it was added, i.e. it wasn't part of the project yet,
+/// neither as a crate nor as a module of one. The crates of files which turn
+/// out to be modules of `file` are removed.
+pub fn add_standalone_file(dir: &Path, file: &Path) -> io::Result<bool> {
+    let mut text = String::new();
+    File::open(dir.join("rust-project.json"))?.read_to_string(&mut text)?;
+    let mut project: Value = serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let root = Value::String(file.to_string_lossy().into_owned());
+    {
+        let crates = match project.get_mut("crates").and_then(|crates| crates.as_array_mut()) {
+            Some(crates) => crates,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "no crates")),
+        };
+        let is_module = |krate: &Value, root: &Path| {
+            krate.get("root_module").and_then(|r| r.as_str()).map_or(false, |r| {
+                module_files(Path::new(r)).contains(root)
+            })
+        };
+        if crates.iter().any(|krate| krate.get("root_module") == Some(&root) || is_module(krate, file)) {
+            return Ok(false);
+        }
+        let modules = module_files(file);
+        crates.retain(|krate| {
+            krate.get("root_module").and_then(|r| r.as_str()).map_or(true, |r| !modules.contains(Path::new(r)))
+        });
+        crates.push(json!({ "root_module": root }));
+    }
+    write_project(dir, &project)?;
+    Ok(true)
+}
+
+// The files of the modules of the crate with the root `root`, following its
+// `mod name;` declarations (but not `#[path]` attributes or inline modules).
+fn module_files(root: &Path) -> HashSet<PathBuf> {
+    let mut result = HashSet::new();
+    // Files and the directory of the files of the modules they declare.
+    let mut todo = match root.parent() {
+        Some(dir) => vec![(root.to_owned(), dir.to_owned())],
+        None => return result,
+    };
+    while let Some((file, dir)) = todo.pop() {
+        let mut text = String::new();
+        if File::open(&file).and_then(|mut file| file.read_to_string(&mut text)).is_err() {
+            continue;
+        }
+        for name in text.lines().filter_map(module_declaration) {
+            let (module, module_dir) = if dir.join(format!("{}.rs", name)).is_file() {
+                (dir.join(format!("{}.rs", name)), dir.join(name))
+            } else {
+                (dir.join(name).join("mod.rs"), dir.join(name))
+            };
+            if module.is_file() && module != root && result.insert(module.clone()) {
+                todo.push((module, module_dir));
+            }
+        }
+    }
+    result
+}
+
+// The name of the module declared (without a body) on `line`, e.g. `foo` for
+// `pub(crate) mod foo;`.
+fn module_declaration(line: &str) -> Option<&str> {
+    let mut rest = line.trim();
+    if rest.starts_with("pub") {
+        rest = rest["pub".len()..].trim_left();
+        if rest.starts_with('(') {
+            rest = rest[rest.find(')')? + 1..].trim_left();
+        }
+    }
+    if !rest.starts_with("mod ") {
+        return None;
+    }
+    let rest = rest["mod ".len()..].trim_left();
+    let name = rest.trim_right_matches(';').trim_right();
+    if rest.ends_with(';') && !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+fn write_project(dir: &Path, project: &Value) -> io::Result<()> {
+    let mut file = File::create(dir.join("rust-project.json"))?;
+    file.write_all(project.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_standalone_project() {
+        let dir = standalone_project().unwrap();
+        assert!(is_standalone_project(&dir));
+        assert!(!is_standalone_project(dir.parent().unwrap()));
+
+        let file = Path::new("/scratch/main.rs");
+        assert!(add_standalone_file(&dir, file).unwrap());
+        assert!(!add_standalone_file(&dir, file).unwrap());
+        assert!(add_standalone_file(&dir, Path::new("/scratch/other.rs")).unwrap());
+
+        let mut text = String::new();
+        File::open(dir.join("rust-project.json")).unwrap().read_to_string(&mut text).unwrap();
+        let project: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(project["crates"].as_array().map(|crates| crates.len()), Some(2));
+
+        let other = standalone_project().unwrap();
+        assert_ne!(dir, other);
+        remove_standalone_project(&other).unwrap();
+        assert!(!other.exists());
+        remove_standalone_project(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_standalone_modules() {
+        let dir = standalone_project().unwrap();
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("b")).unwrap();
+        let write = |path: &Path, text: &str| File::create(path).unwrap().write_all(text.as_bytes()).unwrap();
+        write(&src.join("main.rs"), "mod a;\npub(crate) mod b;\nmod c { }\n");
+        write(&src.join("a.rs"), "");
+        write(&src.join("b").join("mod.rs"), "mod d;\n");
+        write(&src.join("b").join("d.rs"), "");
+
+        // A module opened before its crate root is replaced by it.
+        assert!(add_standalone_file(&dir, &src.join("a.rs")).unwrap());
+        assert!(add_standalone_file(&dir, &src.join("main.rs")).unwrap());
+        assert!(!add_standalone_file(&dir, &src.join("b").join("d.rs")).unwrap());
+
+        let mut text = String::new();
+        File::open(dir.join("rust-project.json")).unwrap().read_to_string(&mut text).unwrap();
+        let project: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(project["crates"].as_array().map(|crates| crates.len()), Some(1));
+        remove_standalone_project(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_module_declaration() {
+        assert_eq!(module_declaration("mod foo;"), Some("foo"));
+        assert_eq!(module_declaration("  pub(crate) mod foo ;"), Some("foo"));
+        assert_eq!(module_declaration("mod foo {"), None);
+        assert_eq!(module_declaration("// mod foo;"), None);
+    }
+}
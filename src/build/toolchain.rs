@@ -0,0 +1,247 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The rustup toolchain of a project: the `toolchain` option, or the one named
+//! by a `rust-toolchain` file in the project (or a directory above it), like
+//! rustup picks it.
+//!
+//! The compiler linked into the RLS can only compile the project with its own
+//! standard library. If the `toolchain` option names another toolchain, the
+//! project is built outside of the RLS process by that toolchain's `cargo
+//! check` (see `remote::toolchain`), without the unsaved changes. A
+//! `rust-toolchain` file alone doesn't change how the project is built, it's
+//! still built in-process and the user is told once that the toolchains
+//! differ. The toolchain is used as well for what else runs outside the RLS,
+//! e.g. `cargo clippy`.
+
+use build::current_sysroot;
+use config::Config;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+/// The toolchain of a project and its sysroot.
+#[derive(Clone, Debug, Default)]
+pub struct ProjectToolchain {
+    /// The toolchain selected for the project, if any.
+    pub name: Option<String>,
+    /// The sysroot of the toolchain the project is built with, see
+    /// `project_sysroot`.
+    pub sysroot: Option<String>,
+    /// Whether `name` is another toolchain than the RLS runs with.
+    pub foreign: bool,
+    /// Whether `name` comes from the `toolchain` option rather than from a
+    /// `rust-toolchain` file.
+    pub configured: bool,
+    /// Whether the project is built outside of the RLS with a nightly
+    /// toolchain, only those accept `-Z` flags such as `-Zsave-analysis`.
+    pub nightly: bool,
+}
+
+impl ProjectToolchain {
+    /// Work out the toolchain of the project in `project_dir`, this may ask
+    /// rustup.
+    pub fn new(config: &Config, project_dir: &Path) -> ProjectToolchain {
+        let name = project_toolchain(config, project_dir);
+        let foreign = name.as_ref().map_or(false, |name| is_foreign_toolchain(name));
+        let configured = config.toolchain.is_some();
+        let nightly = foreign && configured && name.as_ref().map_or(false, |name| is_nightly_toolchain(name));
+        let sysroot = if foreign && configured {
+            project_sysroot(config, project_dir)
+        } else {
+            config.sysroot.clone().or_else(current_sysroot)
+        };
+        ProjectToolchain {
+            name,
+            sysroot,
+            foreign,
+            configured,
+            nightly,
+        }
+    }
+
+    /// Whether the project is built outside of the RLS process, by the
+    /// toolchain's own Cargo.
+    pub fn builds_outside(&self) -> bool {
+        self.foreign && self.configured
+    }
+}
+
+/// The toolchain of the project, worked out the first time it's needed and
+/// again after the config changed, rather than for every request and build.
+pub struct ToolchainCache {
+    current: Mutex<Option<ProjectToolchain>>,
+}
+
+impl ToolchainCache {
+    /// Construct without the toolchain known.
+    pub fn new() -> ToolchainCache {
+        ToolchainCache {
+            current: Mutex::new(None),
+        }
+    }
+
+    /// The toolchain of the project in `project_dir`. The config is only
+    /// locked if it isn't known yet.
+    pub fn get(&self, config: &Mutex<Config>, project_dir: &Path) -> ProjectToolchain {
+        let mut current = self.current.lock().unwrap();
+        if current.is_none() {
+            *current = Some(ProjectToolchain::new(&config.lock().unwrap(), project_dir));
+        }
+        current.clone().unwrap()
+    }
+
+    /// Forget the toolchain, e.g. when the config changes.
+    pub fn invalidate(&self) {
+        *self.current.lock().unwrap() = None;
+    }
+}
+
+// Sysroots by toolchain, asking rustup takes a while.
+lazy_static! {
+    static ref SYSROOTS: Mutex<HashMap<String, Option<String>>> = Mutex::new(HashMap::new());
+}
+
+/// The toolchain selected for the project in `project_dir`, if any.
+pub fn project_toolchain(config: &Config, project_dir: &Path) -> Option<String> {
+    config.toolchain.clone().or_else(|| toolchain_file(project_dir))
+}
+
+/// The sysroot of the project in `project_dir`: the `sysroot` option, the one
+/// of the project's toolchain, or the one of the toolchain the RLS runs with.
+pub fn project_sysroot(config: &Config, project_dir: &Path) -> Option<String> {
+    if let Some(ref sysroot) = config.sysroot {
+        return Some(sysroot.clone());
+    }
+    project_toolchain(config, project_dir)
+        .and_then(|toolchain| toolchain_sysroot(&toolchain))
+        .or_else(current_sysroot)
+}
+
+/// The sysroot of a rustup toolchain, `None` if rustup doesn't know it.
+pub fn toolchain_sysroot(toolchain: &str) -> Option<String> {
+    let mut sysroots = SYSROOTS.lock().unwrap();
+    sysroots.entry(toolchain.to_owned()).or_insert_with(|| {
+        let output = Command::new("rustup")
+            .args(&["run", toolchain, "rustc", "--print", "sysroot"])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            debug!("no sysroot for toolchain `{}`: {}", toolchain, String::from_utf8_lossy(&output.stderr));
+            return None;
+        }
+        String::from_utf8(output.stdout).ok().map(|s| s.trim().to_owned())
+    }).clone()
+}
+
+/// Forget the sysroots of the toolchains, e.g. after they were reinstalled.
+pub fn forget_sysroots() {
+    SYSROOTS.lock().unwrap().clear();
+}
+
+/// Whether `toolchain` is another one than the RLS runs with.
+pub fn is_foreign_toolchain(toolchain: &str) -> bool {
+    match (toolchain_sysroot(toolchain), current_sysroot()) {
+        (Some(sysroot), Some(current)) => Path::new(&sysroot) != Path::new(&current),
+        _ => false,
+    }
+}
+
+/// Whether `toolchain` is a nightly (or locally built) one, judging by its
+/// name or else the version of its compiler.
+pub fn is_nightly_toolchain(toolchain: &str) -> bool {
+    if toolchain.starts_with("nightly") {
+        return true;
+    }
+    Command::new("rustup")
+        .args(&["run", toolchain, "rustc", "--version"])
+        .output()
+        .ok()
+        .map_or(false, |output| is_nightly_version(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// Whether the output of `rustc --version` is the one of a nightly or dev
+// compiler, e.g. `rustc 1.24.0-nightly (5a2465e2b 2017-12-06)`.
+fn is_nightly_version(version: &str) -> bool {
+    version.split_whitespace()
+        .nth(1)
+        .map_or(false, |version| version.ends_with("-nightly") || version.ends_with("-dev"))
+}
+
+/// `command` (a program and its arguments) to run with `toolchain`, through
+/// `rustup run`.
+pub fn with_toolchain(command: &[String], toolchain: Option<&str>) -> Vec<String> {
+    match toolchain {
+        Some(toolchain) => {
+            let mut result = vec!["rustup".to_owned(), "run".to_owned(), toolchain.to_owned()];
+            result.extend(command.iter().cloned());
+            result
+        }
+        None => command.to_vec(),
+    }
+}
+
+/// The toolchain named by the nearest `rust-toolchain` file in `dir` or above.
+pub fn toolchain_file(dir: &Path) -> Option<String> {
+    let mut dir = Some(dir);
+    while let Some(d) = dir {
+        let mut text = String::new();
+        if let Ok(mut file) = File::open(d.join("rust-toolchain")) {
+            if file.read_to_string(&mut text).is_ok() {
+                return parse_toolchain_file(&text);
+            }
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+// The first line of a `rust-toolchain` file is the name of the toolchain.
+fn parse_toolchain_file(text: &str) -> Option<String> {
+    let name = text.lines().next()?.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_toolchain_file() {
+        assert_eq!(parse_toolchain_file("nightly-2018-01-01\n"), Some("nightly-2018-01-01".to_owned()));
+        assert_eq!(parse_toolchain_file("  stable  "), Some("stable".to_owned()));
+        assert_eq!(parse_toolchain_file("\n"), None);
+        assert_eq!(parse_toolchain_file(""), None);
+    }
+
+    #[test]
+    fn test_with_toolchain() {
+        let command = vec!["cargo".to_owned(), "clippy".to_owned()];
+        assert_eq!(with_toolchain(&command, Some("nightly")), ["rustup", "run", "nightly", "cargo", "clippy"]);
+        assert_eq!(with_toolchain(&command, None), ["cargo", "clippy"]);
+    }
+
+    #[test]
+    fn test_is_nightly_version() {
+        assert!(is_nightly_version("rustc 1.24.0-nightly (5a2465e2b 2017-12-06)\n"));
+        assert!(is_nightly_version("rustc 1.25.0-dev"));
+        assert!(!is_nightly_version("rustc 1.22.1 (05e2e1c41 2017-11-22)"));
+        assert!(!is_nightly_version("rustc 1.23.0-beta.2 (1e9b6b8f4 2017-12-01)"));
+        assert!(!is_nightly_version(""));
+    }
+}
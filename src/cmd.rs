@@ -10,15 +10,20 @@
 
 //! This module presents the RLS as a command line interface, it takes simple
 //! versions of commands, turns them into messages the RLS will understand, runs
-//! the RLS as usual and prints the JSON result back on the command line.
+//! the RLS as usual and prints the result back on the command line (in a
+//! human-readable form for locations, symbols, hover and edits).
 
 use actions::requests;
 use analysis::{AnalysisHost, Target};
 use config::Config;
+use lsp_data::NOTIFICATION_DIAGNOSTICS_END;
+use paths;
 use server::{self, Request, Notification, LsService, NoParams};
 use vfs::Vfs;
 
-use ls_types::{ClientCapabilities, TextDocumentPositionParams, TextDocumentIdentifier, TraceOption, Position, InitializeParams, RenameParams, WorkspaceSymbolParams, DocumentFormattingParams, DocumentRangeFormattingParams, Range, FormattingOptions};
+use ls_types::{NOTIFICATION__PublishDiagnostics, ClientCapabilities, TextDocumentPositionParams, ReferenceParams, ReferenceContext, TextDocumentIdentifier, TraceOption, Position, InitializeParams, RenameParams, WorkspaceSymbolParams, DocumentFormattingParams, DocumentRangeFormattingParams, Range, FormattingOptions};
+
+use serde_json::{self, Value};
 
 use std::collections::HashMap;
 use std::fmt;
@@ -42,8 +47,9 @@ macro_rules! print_verb {
 }
 
 /// Run the RLS in command line mode.
-pub fn run() {
-    let sender = init();
+pub fn run(config: Config) {
+    let sender = init(config, PrintlnOutput);
+    println!("Initializing (look for `diagnosticsEnd` message)...");
 
     loop {
         // Present a prompt and read from stdin.
@@ -63,25 +69,27 @@ pub fn run() {
         // Switch on the action and build an appropriate message.
         let msg = match action {
             "def" => {
-                let file_name = bits.next().expect("Expected file name");
-                let row = bits.next().expect("Expected line number");
-                let col = bits.next().expect("Expected column number");
+                let (file_name, row, col) = position_args(&mut bits);
                 def(file_name, row, col).to_string()
             }
+            "refs" => {
+                let (file_name, row, col) = position_args(&mut bits);
+                refs(file_name, row, col).to_string()
+            }
+            "impls" => {
+                let (file_name, row, col) = position_args(&mut bits);
+                impls(file_name, row, col).to_string()
+            }
             "rename" => {
-                let file_name = bits.next().expect("Expected file name");
-                let row = bits.next().expect("Expected line number");
-                let col = bits.next().expect("Expected column number");
+                let (file_name, row, col) = position_args(&mut bits);
                 let new_name = bits.next().expect("Expected new name");
                 rename(file_name, row, col, new_name).to_string()
             }
             "hover" => {
-                let file_name = bits.next().expect("Expected file name");
-                let row = bits.next().expect("Expected line number");
-                let col = bits.next().expect("Expected column number");
+                let (file_name, row, col) = position_args(&mut bits);
                 hover(file_name, row, col).to_string()
             }
-            "symbol" => {
+            "symbol" | "symbols" => {
                 let query = bits.next().expect("Expected a query");
                 workspace_symbol(query).to_string()
             }
@@ -126,6 +134,69 @@ pub fn run() {
     }
 }
 
+/// Build and analyze the project in the current directory once, print the
+/// resulting diagnostics as JSON and return the exit code for the process:
+/// 1 if there were any errors, 0 otherwise.
+pub fn index(config: Config) -> i32 {
+    let (output, messages) = ChannelOutput::new();
+    let sender = init(config, output);
+
+    // Keep the latest diagnostics for every file until the build is done.
+    let mut diagnostics: HashMap<String, Value> = HashMap::new();
+    for msg in messages.iter() {
+        let msg: Value = match serde_json::from_str(&msg) {
+            Ok(msg) => msg,
+            Err(_) => continue,
+        };
+        match msg.get("method").and_then(|m| m.as_str()) {
+            Some(NOTIFICATION__PublishDiagnostics) => {
+                let params = &msg["params"];
+                if let Some(uri) = params["uri"].as_str() {
+                    diagnostics.insert(uri.to_owned(), params["diagnostics"].clone());
+                }
+            }
+            Some(NOTIFICATION_DIAGNOSTICS_END) => break,
+            _ => {}
+        }
+    }
+
+    sender.send(shutdown().to_string()).expect("Error sending on channel");
+    sender.send(exit().to_string()).expect("Error sending on channel");
+
+    let mut has_errors = false;
+    let mut files = vec![];
+    for (uri, diagnostics) in diagnostics {
+        let diagnostics = match diagnostics {
+            Value::Array(diagnostics) => diagnostics,
+            _ => continue,
+        };
+        if diagnostics.is_empty() {
+            continue;
+        }
+        // Severity 1 is `DiagnosticSeverity::Error`.
+        has_errors |= diagnostics.iter().any(|d| d["severity"] == 1);
+        files.push(json!({ "uri": uri, "diagnostics": diagnostics }));
+    }
+    println!("{}", serde_json::to_string_pretty(&files).unwrap());
+
+    if has_errors { 1 } else { 0 }
+}
+
+// Reads a position either as `file_name line column` or as
+// `file_name:line:column`.
+fn position_args<'a, I: Iterator<Item = &'a str>>(bits: &mut I) -> (&'a str, &'a str, &'a str) {
+    let first = bits.next().expect("Expected file name");
+    let mut parts = first.rsplitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(col), Some(row), Some(file_name)) => (file_name, row, col),
+        _ => {
+            let row = bits.next().expect("Expected line number");
+            let col = bits.next().expect("Expected column number");
+            (first, row, col)
+        }
+    }
+}
+
 fn def<'a>(file_name: &str, row: &str, col: &str) -> Request<'a, requests::Definition> {
     let params = TextDocumentPositionParams {
         text_document: TextDocumentIdentifier::new(url(file_name)),
@@ -139,6 +210,35 @@ fn def<'a>(file_name: &str, row: &str, col: &str) -> Request<'a, requests::Defin
     }
 }
 
+fn refs<'a>(file_name: &str, row: &str, col: &str) -> Request<'a, requests::References> {
+    let params = ReferenceParams {
+        text_document: TextDocumentIdentifier::new(url(file_name)),
+        position: Position::new(u64::from_str(row).expect("Bad line number"),
+                                u64::from_str(col).expect("Bad column number")),
+        context: ReferenceContext {
+            include_declaration: true,
+        },
+    };
+    Request {
+        id: next_id(),
+        params: params.into(),
+        _action: PhantomData,
+    }
+}
+
+fn impls<'a>(file_name: &str, row: &str, col: &str) -> Request<'a, requests::FindImpls> {
+    let params = TextDocumentPositionParams {
+        text_document: TextDocumentIdentifier::new(url(file_name)),
+        position: Position::new(u64::from_str(row).expect("Bad line number"),
+                                u64::from_str(col).expect("Bad column number")),
+    };
+    Request {
+        id: next_id(),
+        params,
+        _action: PhantomData,
+    }
+}
+
 fn rename<'a>(file_name: &str, row: &str, col: &str, new_name: &str) -> Request<'a, requests::Rename> {
     let params = RenameParams {
         text_document: TextDocumentIdentifier::new(url(file_name)),
@@ -172,7 +272,7 @@ fn workspace_symbol<'a>(query: &str) -> Request<'a, requests::WorkspaceSymbol> {
     };
     Request {
         id: next_id(),
-        params,
+        params: params.into(),
         _action: PhantomData,
     }
 }
@@ -241,14 +341,14 @@ fn initialize<'a>(root_path: String) -> Request<'a, server::InitializeRequest> {
     };
     Request {
         id: next_id(),
-        params,
+        params: params.into(),
         _action: PhantomData,
     }
 }
 
 fn url(file_name: &str) -> Url {
     let path = Path::new(file_name).canonicalize().expect("Could not canonicalize file name");
-    Url::parse(&format!("file://{}", path.to_str().unwrap())).expect("Bad file name")
+    Url::from_file_path(paths::normalize(&path)).expect("Bad file name")
 }
 
 fn next_id() -> usize {
@@ -273,7 +373,103 @@ impl server::Output for PrintlnOutput {
     }
 
     fn success<D: ::serde::Serialize + fmt::Debug>(&self, id: usize, data: &D) {
-        println!("{}: {:#?}", id, data);
+        match serde_json::to_value(data).ok().and_then(|v| human_readable(&v)) {
+            Some(text) => println!("{}:\n{}", id, text),
+            None => println!("{}: {:#?}", id, data),
+        }
+    }
+}
+
+// Formats the results we know about (locations, symbols, hover and workspace
+// edits) in a more readable way than their JSON or debug representation.
+fn human_readable(result: &Value) -> Option<String> {
+    if let Some(items) = result.as_array() {
+        if items.is_empty() {
+            return Some("    no results".to_owned());
+        }
+        let lines: Option<Vec<String>> = items.iter().map(|item| {
+            if let Some(name) = item["name"].as_str() {
+                // A symbol.
+                Some(format!("    {} {}", name, location(&item["location"])?))
+            } else {
+                Some(format!("    {}", location(item)?))
+            }
+        }).collect();
+        return lines.map(|lines| lines.join("\n"));
+    }
+
+    if let Some(contents) = result["contents"].as_array() {
+        let lines: Vec<_> = contents.iter().filter_map(|c| {
+            c.as_str().or_else(|| c["value"].as_str())
+        }).collect();
+        return Some(if lines.is_empty() {
+            "    no information".to_owned()
+        } else {
+            lines.join("\n\n")
+        });
+    }
+
+    if let Some(changes) = result["changes"].as_object() {
+        let mut lines = vec![];
+        for (uri, edits) in changes {
+            let file = file_name(uri);
+            for edit in edits.as_array()? {
+                lines.push(format!("    {}:{} -> {}", file, range(&edit["range"])?, edit["newText"].as_str()?));
+            }
+        }
+        return Some(lines.join("\n"));
+    }
+
+    None
+}
+
+// `file_name:line:column-line:column` for an LSP `Location`.
+fn location(location: &Value) -> Option<String> {
+    let uri = location["uri"].as_str()?;
+    Some(format!("{}:{}", file_name(uri), range(&location["range"])?))
+}
+
+fn range(range: &Value) -> Option<String> {
+    Some(format!("{}:{}-{}:{}",
+                 range["start"]["line"].as_u64()?,
+                 range["start"]["character"].as_u64()?,
+                 range["end"]["line"].as_u64()?,
+                 range["end"]["character"].as_u64()?))
+}
+
+// Prints file paths relative to the current directory where possible.
+fn file_name(uri: &str) -> String {
+    let path = match Url::parse(uri).ok().and_then(|u| u.to_file_path().ok()) {
+        Some(path) => path,
+        None => return uri.to_owned(),
+    };
+    let cwd = ::std::env::current_dir().unwrap();
+    let path = path.strip_prefix(&cwd).unwrap_or(&path);
+    path.display().to_string()
+}
+
+// Output which forwards every message to a channel, so that they can be
+// inspected rather than printed.
+#[derive(Clone)]
+struct ChannelOutput {
+    channel: Arc<Mutex<Sender<String>>>,
+}
+
+impl ChannelOutput {
+    fn new() -> (ChannelOutput, Receiver<String>) {
+        let (sender, receiver) = channel();
+        (ChannelOutput { channel: Arc::new(Mutex::new(sender)) }, receiver)
+    }
+}
+
+impl server::Output for ChannelOutput {
+    fn response(&self, output: String) {
+        // The receiving end goes away once we're done, ignore any late messages.
+        let _ = self.channel.lock().unwrap().send(output);
+    }
+
+    fn provide_id(&self) -> u32 {
+        0
     }
 }
 
@@ -299,20 +495,19 @@ impl server::MessageReader for ChannelMsgReader {
 
 // Initialize a server, returns the sender end of a channel for posting messages.
 // The initialized server will live on its own thread and look after the receiver.
-fn init() -> Sender<String> {
+fn init<O: server::Output>(config: Config, output: O) -> Sender<String> {
     let analysis = Arc::new(AnalysisHost::new(Target::Debug));
     let vfs = Arc::new(Vfs::new());
     let (sender, receiver) = channel();
 
     let service = LsService::new(analysis,
                                  vfs,
-                                 Arc::new(Mutex::new(Config::default())),
+                                 Arc::new(Mutex::new(config)),
                                  Box::new(ChannelMsgReader::new(receiver)),
-                                 PrintlnOutput);
+                                 output);
     thread::spawn(move || LsService::run(service));
 
     sender.send(initialize(::std::env::current_dir().unwrap().to_str().unwrap().to_owned()).to_string()).expect("Error sending init");
-    println!("Initializing (look for `diagnosticsEnd` message)...");
 
     sender
 }
@@ -321,23 +516,33 @@ fn init() -> Sender<String> {
 fn help() {
     println!("RLS command line interface.");
     println!("\nLine and column numbers are zero indexed");
+    println!("Positions are given either as 'file_name line_number column_number' or as");
+    println!("'file_name:line_number:column_number'");
     println!("\nSupported commands:");
     println!("    help          display this message");
     println!("    quit          exit");
     println!("");
-    println!("    def           file_name line_number column_number");
+    println!("    def           position");
     println!("                  textDocument/definition");
     println!("                  used for 'goto def'");
     println!("");
-    println!("    rename        file_name line_number column_number new_name");
+    println!("    refs          position");
+    println!("                  textDocument/references");
+    println!("                  used for 'find all references'");
+    println!("");
+    println!("    impls         position");
+    println!("                  rustDocument/implementations");
+    println!("                  used for 'find implementations'");
+    println!("");
+    println!("    rename        position new_name");
     println!("                  textDocument/rename");
     println!("                  used for 'rename'");
     println!("");
-    println!("    hover         file_name line_number column_number");
+    println!("    hover         position");
     println!("                  textDocument/hover");
     println!("                  used for 'hover'");
     println!("");
-    println!("    symbol        query");
+    println!("    symbol        query (or 'symbols')");
     println!("                  workspace/symbol");
     println!("");
     println!("    format        file_name [tab_size [insert_spaces]]");
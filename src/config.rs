@@ -13,6 +13,8 @@
 
 use build;
 
+use std::collections::HashMap;
+use std::env;
 use std::fmt::Debug;
 use std::io::sink;
 use std::path::{Path, PathBuf};
@@ -22,11 +24,15 @@ use cargo::util::important_paths;
 use cargo::core::{Shell, Workspace};
 
 use serde::de::{Deserialize, Deserializer};
+use serde_json::{self, Map, Value};
 
+#[cfg(feature = "rustfmt")]
 use rustfmt::config::Config as RustfmtConfig;
+#[cfg(feature = "rustfmt")]
 use rustfmt::config::WriteMode;
 
 const DEFAULT_WAIT_TO_BUILD: u64 = 500;
+const LOW_POWER_WAIT_TO_BUILD: u64 = 1500;
 
 /// Some values in the config can be inferred without an explicit value set by
 /// the user. There are no guarantees which values will or will not be passed
@@ -101,58 +107,410 @@ impl<T> AsRef<T> for Inferrable<T> {
     }
 }
 
+/// Configuration values given on the command line (`--config key=value`) or
+/// via `RLS_<KEY>` environment variables (e.g. `RLS_FEATURES=foo,bar`). These
+/// are applied to every configuration received from the client.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigOverrides {
+    values: Map<String, Value>,
+}
+
+impl ConfigOverrides {
+    /// Collect the overrides given via `RLS_<KEY>` environment variables.
+    /// Variables not corresponding to a configuration option are ignored.
+    pub fn from_env() -> ConfigOverrides {
+        let defaults = default_values();
+        let mut overrides = ConfigOverrides::default();
+        for (name, value) in env::vars() {
+            if !name.starts_with("RLS_") {
+                continue;
+            }
+            let key = name["RLS_".len()..].to_lowercase();
+            if let Some(default) = defaults.get(&key) {
+                overrides.values.insert(key.clone(), parse_override(&key, default, &value));
+            }
+        }
+        overrides
+    }
+
+    /// Add an override given as `key=value`, where the value is a plain string
+    /// for options taking strings and JSON otherwise. Lists can also be given
+    /// as comma separated values.
+    pub fn parse(&mut self, arg: &str) -> Result<(), String> {
+        let mut parts = arg.splitn(2, '=');
+        let key = parts.next().unwrap().trim();
+        let value = parts.next().ok_or_else(|| format!("expected `key=value`, found `{}`", arg))?;
+        let defaults = default_values();
+        let default = defaults.get(key).ok_or_else(|| format!("unknown configuration option `{}`", key))?;
+        self.values.insert(key.to_owned(), parse_override(key, default, value));
+        Ok(())
+    }
+
+    /// Overwrite the corresponding values of the given (JSON) settings.
+    pub fn apply(&self, settings: &mut Value) {
+        if let Value::Object(ref mut settings) = *settings {
+            for (key, value) in &self.values {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+// The default configuration as JSON, used to check which options exist and
+// what type they have.
+fn default_values() -> Map<String, Value> {
+    match serde_json::to_value(Config::default()) {
+        Ok(Value::Object(values)) => values,
+        _ => Map::new(),
+    }
+}
+
+// The value of option `key` given as `value`, interpreted by the type of its
+// `default`: strings are taken as they are and lists may be comma separated.
+// Other options (including those which are `None` by default, as their type
+// isn't known from that) take `value` as JSON if they accept that, otherwise as
+// a string, e.g. `toolchain=1.27` is a string but `memory_budget=1000` a number.
+fn parse_override(key: &str, default: &Value, value: &str) -> Value {
+    match *default {
+        Value::String(_) => Value::String(value.to_owned()),
+        Value::Array(_) => match serde_json::from_str(value) {
+            Ok(Value::Array(values)) => Value::Array(values),
+            _ => Value::Array(value.split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| Value::String(s.to_owned()))
+                .collect()),
+        },
+        _ => match serde_json::from_str(value) {
+            Ok(json) if accepts(key, &json) => json,
+            _ => Value::String(value.to_owned()),
+        },
+    }
+}
+
+// Whether option `key` can be set to `value`.
+fn accepts(key: &str, value: &Value) -> bool {
+    let mut values = Map::new();
+    values.insert(key.to_owned(), value.clone());
+    serde_json::from_value::<Config>(Value::Object(values)).is_ok()
+}
+
+// Accept `rustflags` both as `"-Zfoo --cfg bar"` and `["-Zfoo", "--cfg", "bar"]`.
+fn deserialize_rustflags<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    where D: Deserializer<'de>
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrArray {
+        String(String),
+        Array(Vec<String>),
+    }
+
+    Ok(match Option::<StringOrArray>::deserialize(deserializer)? {
+        None => None,
+        Some(StringOrArray::String(flags)) => Some(flags),
+        Some(StringOrArray::Array(flags)) => Some(flags.join(" ")),
+    })
+}
+
+/// Presets trading responsiveness for lower resource usage, see
+/// `Config::normalise`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PerformanceProfile {
+    /// Use the options as given.
+    #[serde(rename = "default")]
+    Default,
+    /// For laptops on battery and CI containers: builds are debounced for
+    /// longer and use a single job, the optional post-build passes are
+    /// disabled and the analysis data is read on one thread, after the
+    /// diagnostics have been published.
+    #[serde(rename = "low-power")]
+    LowPower,
+}
+
+impl Default for PerformanceProfile {
+    fn default() -> PerformanceProfile {
+        PerformanceProfile::Default
+    }
+}
+
+/// What starts a build of the project.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BuildOn {
+    /// Changing a document (after `wait_to_build`), as well as changes to
+    /// files on disk and to the configuration.
+    #[serde(rename = "change")]
+    Change,
+    /// Saving a document, as well as changes to files on disk and to the
+    /// configuration.
+    #[serde(rename = "save")]
+    Save,
+    /// Only the `rls.build` command (and the build when the RLS starts).
+    #[serde(rename = "manual")]
+    Manual,
+}
+
+impl Default for BuildOn {
+    fn default() -> BuildOn {
+        BuildOn::Change
+    }
+}
+
+/// How imports added by quick fixes are combined with the existing ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ImportGranularity {
+    /// One `use` statement per imported item.
+    #[serde(rename = "item")]
+    Item,
+    /// Merge into an existing `use` statement of the same module, e.g.
+    /// `use std::fmt::{Debug, Display};`.
+    #[serde(rename = "module")]
+    Module,
+}
+
+impl Default for ImportGranularity {
+    fn default() -> ImportGranularity {
+        ImportGranularity::Item
+    }
+}
+
+/// Where imports added by quick fixes are inserted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ImportPlacement {
+    /// Before all other imports of the file, after the crate attributes and
+    /// `extern crate`s.
+    #[serde(rename = "top")]
+    Top,
+    /// After the imports from the same crate (`std`, the current crate or a
+    /// dependency), or after all imports if there are none.
+    #[serde(rename = "group")]
+    Group,
+    /// Like `group`, but in the narrowest inline module containing the use of
+    /// the imported item.
+    #[serde(rename = "module")]
+    Module,
+}
+
+impl Default for ImportPlacement {
+    fn default() -> ImportPlacement {
+        ImportPlacement::Group
+    }
+}
+
 /// RLS configuration options.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[allow(missing_docs)]
 #[serde(default)]
 pub struct Config {
     pub sysroot: Option<String>,
+    /// The rustup toolchain of the project, e.g. `nightly-2018-01-01`. If not
+    /// set, the one named by a `rust-toolchain` file, see `build::toolchain`.
+    /// Default: None
+    pub toolchain: Option<String>,
+    /// Deprecated, use `target_triple` instead.
     pub target: Option<String>,
+    /// Target triple to check the project for (e.g. `wasm32-unknown-unknown`),
+    /// as if passed via `--target`. Defaults to the host. Racer and goto
+    /// definition into the standard library read its source in the sysroot,
+    /// which is the same for every target, so items only another target has
+    /// are still found there.
+    pub target_triple: Option<String>,
+    /// Flags added to every compiler invocation. Can be specified either as a
+    /// single string or as an array of flags.
+    #[serde(deserialize_with = "deserialize_rustflags")]
     pub rustflags: Option<String>,
+    /// Environment variables set for every build (including build scripts).
+    /// A `null` value unsets the variable.
+    pub env: HashMap<String, Option<String>>,
     pub build_lib: Inferrable<bool>,
     pub build_bin: Inferrable<Option<String>>,
     pub cfg_test: bool,
     pub unstable_features: bool,
     pub wait_to_build: u64,
+    /// Number of parallel jobs for building, as if passed via `--jobs`.
+    /// Default: the number of CPUs
+    pub build_jobs: Option<u32>,
+    /// A preset tuning several options at once. Default: "default"
+    pub performance_profile: PerformanceProfile,
     pub show_warnings: bool,
+    /// Codes of diagnostics not to report, e.g. `dead_code` or
+    /// `clippy::needless_return`. Default: []
+    pub ignored_lints: Vec<String>,
+    /// Report warnings as hints. Default: false
+    pub warnings_as_hints: bool,
+    /// When the analysis data doesn't know the definition of an item, ask
+    /// racer. While the project is building, when the analysis data may be out
+    /// of date, racer's answer comes first. Default: false
     pub goto_def_racer_fallback: bool,
+    /// Without analysis data or a racer result for a definition, search the
+    /// source files for lines defining the name. Default: true
+    pub goto_def_search_fallback: bool,
+    /// `workspace/symbol` also finds the public items of dependencies named
+    /// like the query, as if it started with `#`. Default: false
+    pub workspace_symbol_dependencies: bool,
     pub workspace_mode: bool,
     pub analyze_package: Option<String>,
+    /// Workspace members not to build and index in `workspace_mode`, as if
+    /// passed via `--exclude`. Ignored if `analyze_package` is set.
+    pub exclude_packages: Vec<String>,
     /// Clear the RUST_LOG env variable before calling rustc/cargo? Default: true
     pub clear_env_rust_log: bool,
-    /// Build the project only when a file got saved and not on file change. Default: false
+    /// Deprecated, use `build_on` instead.
     pub build_on_save: bool,
+    /// What starts a build: "change", "save" or "manual". Inferred from
+    /// `build_on_save` if not specified. Default: "change"
+    pub build_on: Inferrable<BuildOn>,
     pub use_crate_blacklist: bool,
     /// Cargo target dir. If set overrides the default one.
     #[serde(skip_deserializing, skip_serializing)]
     pub target_dir: Option<PathBuf>,
+    /// Cargo features to enable when building, as if passed via `--features`.
     pub features: Vec<String>,
+    /// Enable every feature of the analyzed packages (`--all-features`).
     pub all_features: bool,
+    /// Don't enable the `default` feature (`--no-default-features`).
     pub no_default_features: bool,
+    /// Run `cargo clippy` after the builds following a save and report its
+    /// lints. Default: false
+    pub clippy: bool,
+    /// Report `use` statements forming dependency cycles between modules.
+    /// Default: false
+    pub module_cycles: bool,
+    /// Report the dependencies in `Cargo.toml` which no crate of the package
+    /// uses, with a quick fix removing them. Default: false
+    pub unused_dependencies: bool,
+    /// Report code under a `#[cfg(...)]` which is false with the features and
+    /// target of the build, with hints. Default: false
+    pub inactive_code: bool,
+    /// Additional external linters to run after each build.
+    pub linters: Vec<LinterConfig>,
+    /// Run `cargo check` remotely (e.g. in a container or over SSH) instead
+    /// of in-process.
+    pub remote_build: Option<RemoteBuildConfig>,
+    /// Pairs of path prefixes, as the client sees them and as the RLS sees
+    /// them, e.g. `[["/home/me/src", "/src"]]` when the RLS runs in a
+    /// container with `/home/me/src` mounted at `/src`. See `paths`.
+    pub path_mappings: Vec<(PathBuf, PathBuf)>,
+    /// Look up missing documentation in the output of `cargo doc`. Default: false
+    pub cargo_doc_fallback: bool,
+    /// Maximum number of completions sent at once, the most relevant ones.
+    /// 0 for no limit. Default: 100
+    pub completion_limit: usize,
+    /// Ask racer for completions. Without it completions only come from the
+    /// analysis data and the RLS itself (keywords, attributes, paths).
+    /// Default: true
+    pub racer_completion: bool,
+    /// After initializing, have racer read and parse the standard library and
+    /// the crates of the workspace in the background, so the first completion
+    /// doesn't wait for it. Default: true
+    pub racer_warm_up: bool,
+    /// Memory budget in megabytes. When the analysis data takes up more after
+    /// a build, that of the least recently used crates is dropped. Default: None
+    pub memory_budget: Option<u64>,
+    /// When the RLS shuts down, write the metrics of the messages it handled
+    /// (see `rls/metrics`) as JSON to this file. Default: None
+    pub metrics_file: Option<String>,
+    /// Remove save-analysis data which is no longer needed on startup and
+    /// shutdown, see `actions::gc`. Default: true
+    pub analysis_gc: bool,
+    /// Age in days after which save-analysis data superseded by a newer build
+    /// of the same crate is removed. Default: 7
+    pub analysis_gc_age_days: u64,
+    /// When renaming a module in a file named after it, rename the file too,
+    /// if the client supports it. Otherwise such modules can't be renamed.
+    /// Default: true
+    pub rename_module_files: bool,
+    /// How imports added by quick fixes are combined with existing ones.
+    /// Default: "item"
+    pub import_granularity: ImportGranularity,
+    /// Where imports added by quick fixes are inserted. Default: "group"
+    pub import_placement: ImportPlacement,
+    /// Separate an import added where there are no imports yet from the
+    /// following code with a blank line. Default: true
+    pub import_blank_line: bool,
+    /// Glob patterns (relative to the project root) of files which are left
+    /// alone by `rls.formatWorkspace` and `format_on_save`.
+    pub format_ignore: Vec<String>,
+    /// A rustfmt binary used to format documents instead of the rustfmt built
+    /// into the RLS, if any. Default: null
+    pub rustfmt_path: Option<String>,
+    /// Show why formatting a document failed in a message, besides failing
+    /// the request. Default: false
+    pub show_format_errors: bool,
+    /// Format documents when they are saved, via `willSaveWaitUntil`.
+    /// Default: false
+    pub format_on_save: bool,
+    /// Locale of the messages generated by the RLS (e.g. `de-AT`), see
+    /// `messages`. Default: English
+    pub locale: Option<String>,
+    /// Translations of the messages generated by the RLS, by locale and
+    /// message id.
+    pub message_catalogs: HashMap<String, HashMap<String, String>>,
+    /// Values given on the command line or via the environment, which take
+    /// precedence over the ones sent by the client.
+    #[serde(skip_deserializing, skip_serializing)]
+    pub overrides: ConfigOverrides,
 }
 
 impl Default for Config {
     fn default() -> Config {
         let mut result = Config {
             sysroot: None,
+            toolchain: None,
             target: None,
+            target_triple: None,
             rustflags: None,
+            env: HashMap::new(),
             build_lib: Inferrable::Inferred(false),
             build_bin: Inferrable::Inferred(None),
             cfg_test: false,
             unstable_features: false,
             wait_to_build: DEFAULT_WAIT_TO_BUILD,
+            build_jobs: None,
+            performance_profile: PerformanceProfile::Default,
             show_warnings: true,
+            ignored_lints: vec![],
+            warnings_as_hints: false,
             goto_def_racer_fallback: false,
+            goto_def_search_fallback: true,
+            workspace_symbol_dependencies: false,
             workspace_mode: false,
             analyze_package: None,
+            exclude_packages: vec![],
             clear_env_rust_log: true,
             build_on_save: false,
+            build_on: Inferrable::Inferred(BuildOn::Change),
             use_crate_blacklist: true,
             target_dir: None,
             features: vec![],
             all_features: false,
             no_default_features: false,
+            clippy: false,
+            module_cycles: false,
+            unused_dependencies: false,
+            inactive_code: false,
+            linters: vec![],
+            remote_build: None,
+            path_mappings: vec![],
+            cargo_doc_fallback: false,
+            completion_limit: 100,
+            racer_completion: true,
+            racer_warm_up: true,
+            memory_budget: None,
+            metrics_file: None,
+            analysis_gc: true,
+            analysis_gc_age_days: 7,
+            rename_module_files: true,
+            import_granularity: ImportGranularity::Item,
+            import_placement: ImportPlacement::Group,
+            import_blank_line: true,
+            format_ignore: vec![],
+            rustfmt_path: None,
+            show_format_errors: false,
+            format_on_save: false,
+            locale: None,
+            message_catalogs: HashMap::new(),
+            overrides: ConfigOverrides::default(),
         };
         result.normalise();
         result
@@ -160,19 +518,47 @@ impl Default for Config {
 }
 
 impl Config {
+    /// Create the initial configuration, consisting of the defaults and the
+    /// given overrides.
+    pub fn with_overrides(overrides: ConfigOverrides) -> Result<Config, String> {
+        let mut settings = Value::Object(Map::new());
+        overrides.apply(&mut settings);
+        let mut config = Config::deserialize(settings)
+            .map_err(|e| format!("invalid configuration override: {}", e))?;
+        config.normalise();
+        config.overrides = overrides;
+        Ok(config)
+    }
+
     /// Join this configuration with the new config.
     pub fn update(&mut self, mut new: Config) {
         new.build_lib = self.build_lib.combine_with_default(&new.build_lib, false);
         new.build_bin = self.build_bin.combine_with_default(&new.build_bin, None);
+        new.overrides = self.overrides.clone();
 
         *self = new;
     }
 
     /// Ensures that unstable options are only allowed if `unstable_features` is
-    /// true and that is not allowed on stable release channels.
+    /// true and that is not allowed on stable release channels. Also resolves
+    /// deprecated options to their replacements and applies the performance
+    /// profile.
     pub fn normalise(&mut self) {
         let allow_unstable = option_env!("CFG_RELEASE_CHANNEL").map(|c| c == "nightly").unwrap_or(true);
 
+        // `target` is the old name of `target_triple`, keep honoring it.
+        if self.target_triple.is_none() {
+            self.target_triple = self.target.take();
+        }
+        // Likewise `build_on_save` for `build_on`, unless that is specified.
+        if let Inferrable::None = self.build_on {
+            self.build_on = Inferrable::Inferred(BuildOn::Change);
+        }
+        if self.build_on_save {
+            self.build_on.infer(BuildOn::Save);
+            self.build_on_save = false;
+        }
+
         if !allow_unstable {
             if self.unstable_features {
                 eprintln!("`unstable_features` setting can only be used on nightly channel");
@@ -186,9 +572,26 @@ impl Config {
             }
             self.workspace_mode = false;
             self.analyze_package = None;
+            self.exclude_packages = vec![];
+        }
+
+        if self.performance_profile == PerformanceProfile::LowPower {
+            self.wait_to_build = ::std::cmp::max(self.wait_to_build, LOW_POWER_WAIT_TO_BUILD);
+            self.build_jobs = Some(1);
+            self.clippy = false;
+            self.module_cycles = false;
+            self.inactive_code = false;
+            self.linters = vec![];
+            self.cargo_doc_fallback = false;
+            self.racer_warm_up = false;
         }
     }
 
+    /// Whether to save resources at the cost of responsiveness.
+    pub fn low_power(&self) -> bool {
+        self.performance_profile == PerformanceProfile::LowPower
+    }
+
     /// Is this config incomplete, and needs additional values to be inferred?
     pub fn needs_inference(&self) -> bool {
         match (&self.build_lib, &self.build_bin) {
@@ -270,13 +673,60 @@ impl Config {
     }
 }
 
+/// An external linter, run in the project directory after each build.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LinterConfig {
+    /// Name of the linter, reported as the source of its diagnostics.
+    pub name: String,
+    /// The program to run, followed by its arguments.
+    pub command: Vec<String>,
+    /// How the output of the linter should be parsed.
+    #[serde(default)]
+    pub format: LinterOutputFormat,
+    /// Pattern used to parse each output line when `format` is `line`, e.g.
+    /// `{file}:{line}:{column}: {severity}: {message}`. Not a regular
+    /// expression, see the `linters` option in the README.
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+/// Output formats of external linters.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinterOutputFormat {
+    /// One rustc-style JSON diagnostic per line.
+    Json,
+    /// One diagnostic per line, parsed using `LinterConfig::pattern`.
+    Line,
+}
+
+impl Default for LinterOutputFormat {
+    fn default() -> LinterOutputFormat {
+        LinterOutputFormat::Json
+    }
+}
+
+/// A build environment outside of the RLS process.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RemoteBuildConfig {
+    /// Command prefix used to run a shell command in the remote environment,
+    /// given as the last argument, e.g. `["ssh", "build-host"]` or
+    /// `["docker", "exec", "my-container", "sh", "-c"]`.
+    pub command: Vec<String>,
+    /// Path of the project root in the remote environment. Paths in the
+    /// remote diagnostics are mapped from here to the local project root.
+    pub remote_root: String,
+}
+
 /// A rustfmt config (typically specified via rustfmt.toml)
 /// The FmtConfig is not an exact translation of the config
 /// rustfmt generates from the user's toml file, since when
 /// using rustfmt with rls certain configuration options are
 /// always used. See `FmtConfig::set_rls_options`
+#[cfg(feature = "rustfmt")]
 pub struct FmtConfig(RustfmtConfig);
 
+#[cfg(feature = "rustfmt")]
 impl FmtConfig {
     /// Look for `.rustmt.toml` or `rustfmt.toml` in `path`, falling back
     /// to the default config if neither exist
@@ -289,6 +739,14 @@ impl FmtConfig {
         FmtConfig::default()
     }
 
+    /// The config for formatting `file`, from the `rustfmt.toml` closest to it.
+    pub fn for_file(file: &Path) -> FmtConfig {
+        match file.parent() {
+            Some(dir) => FmtConfig::from(dir),
+            None => FmtConfig::default(),
+        }
+    }
+
     /// Return an immutable borrow of the config, will always
     /// have any relevant rls specific options set
     pub fn get_rustfmt_config(&self) -> &RustfmtConfig {
@@ -302,6 +760,23 @@ impl FmtConfig {
     }
 }
 
+/// The `rustfmt.toml` (or `.rustfmt.toml`) which applies to files in `dir`,
+/// i.e. the one in `dir` or the closest of its parents.
+pub fn find_rustfmt_toml(dir: &Path) -> Option<PathBuf> {
+    let mut dir = Some(dir);
+    while let Some(d) = dir {
+        for name in &["rustfmt.toml", ".rustfmt.toml"] {
+            let file = d.join(name);
+            if file.is_file() {
+                return Some(file);
+            }
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+#[cfg(feature = "rustfmt")]
 impl Default for FmtConfig {
     fn default() -> FmtConfig {
         let config = RustfmtConfig::default();
@@ -310,3 +785,31 @@ impl Default for FmtConfig {
         config
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_override() {
+        let defaults = default_values();
+        let parse = |key: &str, value: &str| parse_override(key, &defaults[key], value);
+        // Strings stay strings, even if they look like JSON.
+        assert_eq!(parse("toolchain", "1.27"), json!("1.27"));
+        assert_eq!(parse("analyze_package", "123"), json!("123"));
+        assert_eq!(parse("target_triple", "wasm32-unknown-unknown"), json!("wasm32-unknown-unknown"));
+        assert_eq!(parse("build_on", "save"), json!("save"));
+        assert_eq!(parse("build_bin", "123"), json!("123"));
+        assert_eq!(parse("memory_budget", "1000"), json!(1000));
+        assert_eq!(parse("all_features", "true"), json!(true));
+        assert_eq!(parse("features", "foo, bar"), json!(["foo", "bar"]));
+        assert_eq!(parse("features", "[\"foo\"]"), json!(["foo"]));
+
+        let mut overrides = ConfigOverrides::default();
+        overrides.parse("toolchain=1.27").unwrap();
+        overrides.parse("analyze_package=123").unwrap();
+        let config = Config::with_overrides(overrides).unwrap();
+        assert_eq!(config.toolchain, Some("1.27".to_owned()));
+        assert_eq!(config.analyze_package, Some("123".to_owned()));
+    }
+}
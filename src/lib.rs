@@ -0,0 +1,112 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The Rust Language Server.
+//!
+//! The RLS provides a server that runs in the background, providing IDEs,
+//! editors, and other tools with information about Rust programs. It supports
+//! functionality such as 'goto definition', symbol search, reformatting, and
+//! code completion, and enables renaming and refactorings.
+//!
+//! The `rls` binary serves a client on stdin and stdout. Tools which would
+//! rather run it in their own process (e.g. editors with in-process plugins,
+//! or test rigs) use the library, through `Server`:
+//!
+//! ```no_run
+//! use rls::{Config, Server};
+//! use rls::config::{BuildOn, Inferrable};
+//!
+//! let mut config = Config::default();
+//! config.build_on = Inferrable::Specified(BuildOn::Save);
+//! // Reads the client's messages from stdin and writes responses to stdout.
+//! Server::new().config(config).run_stdio();
+//! ```
+//!
+//! `Server::run` takes any `MessageReader` and `Output` instead, and
+//! `Server::service` returns the `LsService` to drive one message at a time.
+//! The request and notification types (see `Action`) are the ones the server
+//! handles, e.g. to call them directly.
+
+#![feature(rustc_private)]
+#![feature(concat_idents)]
+#![feature(type_ascription)]
+#![feature(integer_atomics)]
+#![feature(fnbox)]
+#![deny(missing_docs)]
+
+extern crate cargo;
+extern crate env_logger;
+extern crate git2;
+extern crate languageserver_types as ls_types;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "racer")]
+extern crate racer;
+extern crate rls_analysis as analysis;
+extern crate rls_data as data;
+extern crate rls_span as span;
+extern crate rls_vfs as vfs;
+#[cfg(feature = "rustfmt")]
+extern crate rustfmt_nightly as rustfmt;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate rayon;
+
+#[macro_use]
+extern crate serde_json;
+
+extern crate toml;
+extern crate url;
+extern crate jsonrpc_core;
+
+pub mod actions;
+pub mod build;
+pub mod cmd;
+pub mod config;
+pub mod lsp_data;
+pub mod messages;
+pub mod paths;
+pub mod server;
+
+pub use actions::{notifications, requests};
+pub use config::Config;
+pub use server::{Action, LsService, MessageReader, Middleware, NotificationAction, Output, RawMessage,
+                 RequestAction, Server, ServerStateChange, StdioMsgReader, StdioOutput};
+
+#[cfg(test)]
+mod test;
+
+// Timeout = 1.5s (totally arbitrary).
+#[cfg(not(test))]
+const COMPILER_TIMEOUT: u64 = 1500;
+
+// Timeout for potenially very slow CPU CI boxes
+#[cfg(test)]
+const COMPILER_TIMEOUT: u64 = 3_600_000;
+
+const CRATE_BLACKLIST: [&'static str; 10] = [
+    "libc", "typenum", "alloc", "idna", "openssl", "libunicode_normalization", "serde",
+    "serde_json", "librustc_serialize", "libunicode_segmentation",
+];
+
+/// Set (to `1`) when the RLS runs itself as the rustc of a cargo build, see
+/// `build::cargo`. The `rls` binary then runs the compiler instead of a server.
+pub const RUSTC_SHIM_ENV_VAR_NAME: &'static str = "RLS_RUSTC_SHIM";
+
+type Span = span::Span<span::ZeroIndexed>;
+
+/// The version of the RLS and the commit it was built from.
+pub fn version() -> &'static str {
+    concat!(env!("CARGO_PKG_VERSION"), "-", include_str!(concat!(env!("OUT_DIR"), "/commit-info.txt")))
+}
+
@@ -10,20 +10,27 @@
 
 //! Types, helpers, and conversions to and from LSP and `racer` types.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Debug};
-use std::path::PathBuf;
+use std::env;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::error::Error;
 
 use analysis::DefKind;
+use paths::{self, PathMappings};
 use url::Url;
-use serde::Serialize;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as DeError;
+use serde::ser::Error as SerError;
+use serde_json;
 use span;
+#[cfg(feature = "racer")]
 use racer;
-use vfs::FileContents;
 
 pub use ls_types::*;
-use jsonrpc_core::version;
+use jsonrpc_core::{self as jsonrpc, version};
 
 /// Notification string for beginning diagnostics.
 pub const NOTIFICATION_DIAGNOSTICS_BEGIN: &'static str = "rustDocument/diagnosticsBegin";
@@ -31,9 +38,20 @@ pub const NOTIFICATION_DIAGNOSTICS_BEGIN: &'static str = "rustDocument/diagnosti
 pub const NOTIFICATION_DIAGNOSTICS_END:   &'static str = "rustDocument/diagnosticsEnd";
 /// Notification string for when a build begins.
 pub const NOTIFICATION_BUILD_BEGIN:       &'static str = "rustDocument/beginBuild";
+/// Notification string for when the status of the server changes.
+pub const NOTIFICATION_SERVER_STATUS:     &'static str = "rustDocument/serverStatus";
+/// Notification string for the progress of formatting the workspace.
+pub const NOTIFICATION_FORMAT_PROGRESS:   &'static str = "rustDocument/formatWorkspaceProgress";
+/// Notification string for a message to show to the user.
+pub const NOTIFICATION_SHOW_MESSAGE:      &'static str = "window/showMessage";
+/// Notification string for partial results of a request, or the progress of
+/// work done by the server.
+pub const NOTIFICATION_PROGRESS:          &'static str = "$/progress";
+/// Notification string for an event of the server, see `ServerEvent`.
+pub const NOTIFICATION_EVENT_STREAM:      &'static str = "rls/eventStream";
 
 /// Errors that can occur when parsing a file URI.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UrlFileParseError {
     /// The URI scheme is not `file`.
     InvalidScheme,
@@ -56,15 +74,117 @@ impl fmt::Display for UrlFileParseError where UrlFileParseError: Error {
     }
 }
 
-/// Parse the given URI into a `PathBuf`.
+/// Parse the given URI into a `PathBuf`, normalized (see `paths`) but as the
+/// client knows it, see `DocumentPaths::path` for the real path of the file.
 pub fn parse_file_path(uri: &Url) -> Result<PathBuf, UrlFileParseError> {
     if uri.scheme() != "file" {
         Err(UrlFileParseError::InvalidScheme)
     } else {
-        uri.to_file_path().map_err(|_err| UrlFileParseError::InvalidFilePath)
+        uri.to_file_path()
+            .map(|path| paths::normalize(&path))
+            .map_err(|_err| UrlFileParseError::InvalidFilePath)
+    }
+}
+
+/// The error to answer a request with if one of its URIs couldn't be parsed,
+/// the client sent something we can't work with.
+pub fn invalid_uri_error(uri: &Url, err: UrlFileParseError) -> jsonrpc::Error {
+    jsonrpc::Error {
+        code: jsonrpc::ErrorCode::InvalidParams,
+        message: format!("{}: {}", err, uri),
+        data: None,
     }
 }
 
+/// The paths of the documents of a server, and their URIs. Files are known by
+/// their real path, mapped with the server's `PathMappings`. Documents which
+/// aren't files (e.g. `untitled:Untitled-1` for a new buffer) get a path in a
+/// directory which doesn't exist, so they are only ever read from the VFS and
+/// are never part of a build. They are forgotten when the client closes them.
+pub struct DocumentPaths {
+    mappings: Mutex<PathMappings>,
+    virtual_documents: Mutex<VirtualDocuments>,
+}
+
+#[derive(Default)]
+struct VirtualDocuments {
+    by_uri: HashMap<Url, PathBuf>,
+    by_path: HashMap<PathBuf, Url>,
+    // The number of documents so far, which makes their paths unique.
+    count: usize,
+}
+
+impl DocumentPaths {
+    /// Construct without any documents.
+    pub fn new(mappings: PathMappings) -> DocumentPaths {
+        DocumentPaths {
+            mappings: Mutex::new(mappings),
+            virtual_documents: Mutex::new(VirtualDocuments::default()),
+        }
+    }
+
+    /// The path mappings.
+    pub fn mappings(&self) -> PathMappings {
+        self.mappings.lock().unwrap().clone()
+    }
+
+    /// Replace the path mappings, returning the old ones.
+    pub fn set_mappings(&self, mappings: PathMappings) -> PathMappings {
+        mem::replace(&mut *self.mappings.lock().unwrap(), mappings)
+    }
+
+    /// Parse the URI of a document into a `PathBuf`, making up one if it isn't
+    /// a file. See `uri` for the way back.
+    pub fn path(&self, uri: &Url) -> Result<PathBuf, UrlFileParseError> {
+        if uri.scheme() == "file" {
+            return parse_file_path(uri).map(|path| self.mappings.lock().unwrap().from_client(&path));
+        }
+        let mut documents = self.virtual_documents.lock().unwrap();
+        if let Some(path) = documents.by_uri.get(uri) {
+            return Ok(path.clone());
+        }
+        // Keep the name, e.g. for racer's messages.
+        let name: String = uri.path().rsplit('/').next().unwrap_or("").chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+            .collect();
+        let path = virtual_root().join(documents.count.to_string()).join(if name.is_empty() { "document" } else { &name });
+        documents.count += 1;
+        documents.by_uri.insert(uri.clone(), path.clone());
+        documents.by_path.insert(path.clone(), uri.clone());
+        Ok(path)
+    }
+
+    /// The URI of the document at `path`, the inverse of `path`. Fails if
+    /// `path` isn't absolute.
+    pub fn uri(&self, path: &Path) -> Result<Url, UrlFileParseError> {
+        if is_virtual_document(path) {
+            if let Some(uri) = self.virtual_documents.lock().unwrap().by_path.get(path) {
+                return Ok(uri.clone());
+            }
+        }
+        let path = self.mappings.lock().unwrap().to_client(path);
+        Url::from_file_path(path).map_err(|_err| UrlFileParseError::InvalidFilePath)
+    }
+
+    /// Forget the document at `path`, when the client closes it.
+    pub fn close(&self, path: &Path) {
+        let mut documents = self.virtual_documents.lock().unwrap();
+        if let Some(uri) = documents.by_path.remove(path) {
+            documents.by_uri.remove(&uri);
+        }
+    }
+}
+
+/// Whether `path` was made up for a document which isn't a file, see
+/// `DocumentPaths`.
+pub fn is_virtual_document(path: &Path) -> bool {
+    path.starts_with(virtual_root())
+}
+
+fn virtual_root() -> PathBuf {
+    paths::normalize(&env::temp_dir().join("rls-virtual-documents"))
+}
+
 /// Create an edit for the given location and text.
 pub fn make_workspace_edit(location: Location, new_text: String) -> WorkspaceEdit {
     let mut edit = WorkspaceEdit {
@@ -82,11 +202,16 @@ pub fn make_workspace_edit(location: Location, new_text: String) -> WorkspaceEdi
 /// Utilities for working with the language server protocol.
 pub mod ls_util {
     use super::*;
-    use Span;
 
+    use actions::FileAccess;
+    use actions::line_index::LineIndex;
     use std::path::Path;
     use vfs::Vfs;
 
+    // The conversions between ranges and positions keep the column as it is,
+    // they are only correct if the line is ASCII. Use `PositionConverter` for
+    // positions sent to or received from the client.
+
     /// Convert a language server protocol range into an RLS range.
     pub fn range_to_rls(r: Range) -> span::Range<span::ZeroIndexed> {
         span::Range::from_positions(position_to_rls(r.start), position_to_rls(r.end))
@@ -98,28 +223,6 @@ pub mod ls_util {
                             span::Column::new_zero_indexed(p.character as u32))
     }
 
-    /// Convert a language server protocol location into an RLS span.
-    pub fn location_to_rls(l: Location) -> Result<span::Span<span::ZeroIndexed>, UrlFileParseError> {
-        parse_file_path(&l.uri).map(|path| Span::from_range(range_to_rls(l.range), path))
-    }
-
-    /// Convert an RLS span into a language server protocol location.
-    pub fn rls_to_location(span: &Span) -> Location {
-        // An RLS span has the same info as an LSP Location
-        Location {
-            uri: Url::from_file_path(&span.file).unwrap(),
-            range: rls_to_range(span.range),
-        }
-    }
-
-    /// Convert an RLS location into a language server protocol location.
-    pub fn rls_location_to_location(l: &span::Location<span::ZeroIndexed>) -> Location {
-        Location {
-            uri: Url::from_file_path(&l.file).unwrap(),
-            range: rls_to_range(span::Range::from_positions(l.position, l.position)),
-        }
-    }
-
     /// Convert an RLS range into a language server protocol range.
     pub fn rls_to_range(r: span::Range<span::ZeroIndexed>) -> Range {
         Range {
@@ -136,16 +239,58 @@ pub mod ls_util {
         }
     }
 
+    /// Convert a column of `line` in the given encoding into a column in chars,
+    /// as used by the RLS. A column in the middle of a char is rounded up,
+    /// columns past the end of the line are kept past its end.
+    pub fn col_to_rls(line: &str, col: u64, encoding: PositionEncoding) -> u32 {
+        let mut units = 0;
+        let mut chars = 0;
+        for c in line.chars() {
+            if units >= col {
+                return chars;
+            }
+            units += encoding.units(c);
+            chars += 1;
+        }
+        chars + col.saturating_sub(units) as u32
+    }
+
+    /// Convert a column of `line` in chars into a column in the given
+    /// encoding, the inverse of `col_to_rls`.
+    pub fn col_from_rls(line: &str, col: u32, encoding: PositionEncoding) -> u64 {
+        let mut units = 0;
+        let mut chars = 0;
+        for c in line.chars() {
+            if chars == col {
+                return units;
+            }
+            units += encoding.units(c);
+            chars += 1;
+        }
+        units + (col - chars) as u64
+    }
+
+    /// Convert a language server protocol position into a byte offset in the
+    /// indexed text.
+    pub fn position_to_offset(index: &LineIndex, pos: Position) -> Option<usize> {
+        index.position_to_offset(pos)
+    }
+
+    /// Convert a byte offset in the indexed text into a language server
+    /// protocol position.
+    pub fn offset_to_position(index: &LineIndex, offset: usize) -> Option<Position> {
+        index.offset_to_position(offset)
+    }
+
     /// Creates a `Range` spanning the whole file as currently known by `Vfs`
     ///
     /// Panics if `Vfs` cannot load the file.
     pub fn range_from_vfs_file(vfs: &Vfs, fname: &Path) -> Range {
-        // FIXME load_file clones the entire file text, this could be much more
-        // efficient by adding a `with_file` fn to the VFS.
-        let content = match vfs.load_file(fname).unwrap() {
-            FileContents::Text(t) => t,
-            _ => panic!("unexpected binary file: {:?}", fname),
-        };
+        vfs.with_file(fname, range_from_text).unwrap()
+    }
+
+    /// Creates a `Range` spanning the whole given text.
+    pub fn range_from_text(content: &str) -> Range {
         if content.is_empty() {
             Range {start: Position::new(0, 0), end: Position::new(0, 0)}
         } else {
@@ -189,6 +334,7 @@ pub fn source_kind_from_def_kind(k: DefKind) -> SymbolKind {
 }
 
 /// What kind of completion is this racer match type?
+#[cfg(feature = "racer")]
 pub fn completion_kind_from_match_type(m : racer::MatchType) -> CompletionItemKind {
     match m {
         racer::MatchType::Crate |
@@ -216,6 +362,7 @@ pub fn completion_kind_from_match_type(m : racer::MatchType) -> CompletionItemKi
 }
 
 /// Convert a racer match into an RLS completion.
+#[cfg(feature = "racer")]
 pub fn completion_item_from_racer_match(m : racer::Match) -> CompletionItem {
     let mut item = CompletionItem::new_simple(m.matchstr.clone(), m.contextstr.clone());
     item.kind = Some(completion_kind_from_match_type(m.mtype));
@@ -223,22 +370,1094 @@ pub fn completion_item_from_racer_match(m : racer::Match) -> CompletionItem {
     item
 }
 
+/// How relevant a racer match is as a completion.
+#[cfg(feature = "racer")]
+pub fn completion_relevance(m: &racer::Match) -> CompletionRelevance {
+    match m.mtype {
+        racer::MatchType::Let |
+        racer::MatchType::IfLet |
+        racer::MatchType::WhileLet |
+        racer::MatchType::For |
+        racer::MatchType::MatchArm |
+        racer::MatchType::FnArg => CompletionRelevance::Local,
+        racer::MatchType::StructField |
+        racer::MatchType::EnumVariant => CompletionRelevance::Field,
+        racer::MatchType::Function if takes_self(&m.contextstr) => CompletionRelevance::Method,
+        racer::MatchType::Function |
+        racer::MatchType::Macro => CompletionRelevance::Function,
+        racer::MatchType::Crate |
+        racer::MatchType::Module => CompletionRelevance::Module,
+        _ => CompletionRelevance::Item,
+    }
+}
+
+// Whether the signature of a function has a `self` argument.
+#[cfg(feature = "racer")]
+fn takes_self(signature: &str) -> bool {
+    let args = match signature.find('(') {
+        Some(i) => signature[i + 1..].trim_left(),
+        None => return false,
+    };
+    let args = args.trim_left_matches('&');
+    let args = if args.starts_with('\'') { args.find(' ').map_or("", |i| args[i..].trim_left()) } else { args };
+    let args = if args.starts_with("mut ") { args["mut ".len()..].trim_left() } else { args };
+    args.starts_with("self") && !args["self".len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+/// How relevant a completion is likely to be, the most relevant first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CompletionRelevance {
+    /// Local variables and arguments.
+    Local,
+    /// Fields and enum variants.
+    Field,
+    /// Methods.
+    Method,
+    /// Free functions and macros.
+    Function,
+    /// Types, traits, constants and statics.
+    Item,
+    /// Modules and crates.
+    Module,
+    /// Keywords and snippets.
+    Keyword,
+}
+
+/// A completion, with what it takes to rank it, see `rank_completions`.
+#[derive(Debug)]
+pub struct RankedCompletion {
+    pub item: CompletionItem,
+    pub relevance: CompletionRelevance,
+    /// Whether the completion is defined in the document being completed.
+    pub in_document: bool,
+    /// Where the completion is defined (file and offset), if known. Racer
+    /// finds some definitions several times, e.g. through glob imports.
+    pub definition: Option<(PathBuf, usize)>,
+}
+
+/// Dedupes and sorts completions, and sets their `sort_text` to their rank,
+/// so that every client shows them in the same order. The most relevant come
+/// first (see `CompletionRelevance`), those defined in the document before
+/// those from elsewhere. Ties are ordered alphabetically, ignoring case, with
+/// names starting with `_` last. `filter_text` is set to the name without
+/// leading underscores, so that e.g. `foo` matches `_foo`.
+///
+/// Only the first `limit` completions are kept (all if `limit` is 0), the
+/// list is then incomplete and the client asks again as the user types.
+pub fn rank_completions(completions: Vec<RankedCompletion>, limit: usize) -> CompletionList {
+    let mut seen = HashSet::new();
+    let mut completions: Vec<RankedCompletion> = completions.into_iter().filter(|c| {
+        let detail = if c.definition.is_none() { c.item.detail.clone() } else { None };
+        seen.insert((c.item.label.clone(), c.definition.clone(), detail))
+    }).collect();
+
+    completions.sort_by(|a, b| {
+        a.relevance.cmp(&b.relevance)
+            .then_with(|| b.in_document.cmp(&a.in_document))
+            .then_with(|| a.item.label.starts_with('_').cmp(&b.item.label.starts_with('_')))
+            .then_with(|| a.item.label.to_lowercase().cmp(&b.item.label.to_lowercase()))
+            .then_with(|| a.item.label.cmp(&b.item.label))
+            .then_with(|| a.item.detail.cmp(&b.item.detail))
+    });
+    let is_incomplete = limit > 0 && completions.len() > limit;
+    if is_incomplete {
+        completions.truncate(limit);
+    }
+
+    let width = completions.len().to_string().len();
+    let items = completions.into_iter().enumerate().map(|(rank, c)| {
+        let mut item = c.item;
+        item.sort_text = Some(format!("{:0width$}", rank, width = width));
+        item.filter_text = Some(item.label.trim_left_matches('_').to_owned());
+        item
+    }).collect();
+    CompletionList { is_incomplete, items }
+}
+
+/// How the `character` of a `Position` counts columns. The protocol uses
+/// UTF-16 code units, clients can opt into UTF-8 (i.e., bytes) by listing
+/// `"utf-8"` in their `experimental.positionEncodings` capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PositionEncoding {
+    /// Columns are counted in bytes.
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// Columns are counted in UTF-16 code units, the protocol's default.
+    #[serde(rename = "utf-16")]
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// The number of units `c` takes up in this encoding.
+    pub fn units(&self, c: char) -> u64 {
+        match *self {
+            PositionEncoding::Utf8 => c.len_utf8() as u64,
+            PositionEncoding::Utf16 => c.len_utf16() as u64,
+        }
+    }
+}
+
+/// The capabilities of the client, as it sent them in `initialize`. Those
+/// added to the protocol after the version `languageserver-types` implements
+/// would be lost in its `ClientCapabilities`, what the RLS supports beyond the
+/// protocol is announced in the `experimental` capabilities.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RlsClientCapabilities(pub serde_json::Value);
+
+impl RlsClientCapabilities {
+    // The capability at `path`, e.g. `["textDocument", "hover"]`.
+    fn get(&self, path: &[&str]) -> Option<&serde_json::Value> {
+        path.iter().fold(Some(&self.0), |value, key| value.and_then(|v| v.get(key)))
+    }
+
+    fn lists(&self, path: &[&str], item: &str) -> Option<bool> {
+        self.get(path)
+            .and_then(|v| v.as_array())
+            .map(|items| items.iter().any(|i| i.as_str() == Some(item)))
+    }
+
+    fn flag(&self, path: &[&str]) -> Option<bool> {
+        self.get(path).and_then(|v| v.as_bool())
+    }
+
+    /// UTF-8 if the client lists it in its `experimental.positionEncodings`
+    /// capability, otherwise the protocol's default UTF-16.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        if self.lists(&["experimental", "positionEncodings"], "utf-8") == Some(true) {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    /// Whether the client can rename files as part of a workspace edit, i.e.
+    /// lists `"rename"` in `workspace.workspaceEdit.resourceOperations` (or the
+    /// `resourceOperations` experimental capability).
+    pub fn file_renames(&self) -> bool {
+        self.lists(&["workspace", "workspaceEdit", "resourceOperations"], "rename")
+            .or_else(|| self.lists(&["experimental", "resourceOperations"], "rename"))
+            .unwrap_or(false)
+    }
+
+    /// Whether the client renders markdown in hovers, i.e. lists `"markdown"`
+    /// in `textDocument.hover.contentFormat` (or the `hoverContentFormat`
+    /// experimental capability). Clients sending neither get markdown, as the
+    /// protocol always assumed.
+    pub fn markdown_hover(&self) -> bool {
+        self.lists(&["textDocument", "hover", "contentFormat"], "markdown")
+            .or_else(|| self.lists(&["experimental", "hoverContentFormat"], "markdown"))
+            .unwrap_or(true)
+    }
+
+    /// Whether the client shows the related information of diagnostics.
+    pub fn related_information(&self) -> bool {
+        self.flag(&["textDocument", "publishDiagnostics", "relatedInformation"])
+            .or_else(|| self.flag(&["experimental", "relatedInformation"]))
+            .unwrap_or(false)
+    }
+
+    /// Whether the client renders diagnostics according to their tags.
+    pub fn diagnostic_tags(&self) -> bool {
+        let tag_support = self.get(&["textDocument", "publishDiagnostics", "tagSupport"])
+            .map(|v| v.as_bool().unwrap_or(v.is_object()));
+        tag_support.or_else(|| self.flag(&["experimental", "diagnosticTags"])).unwrap_or(false)
+    }
+
+    /// Whether the client expands snippets in completions.
+    pub fn snippet_completions(&self) -> bool {
+        self.flag(&["textDocument", "completion", "completionItem", "snippetSupport"]).unwrap_or(false)
+    }
+
+    /// Whether the client shows document symbols as a tree, see
+    /// `DocumentSymbolResponse`.
+    pub fn hierarchical_symbols(&self) -> bool {
+        self.flag(&["textDocument", "documentSymbol", "hierarchicalDocumentSymbolSupport"]).unwrap_or(false)
+    }
+
+    /// Whether the client sends `textDocument/prepareRename` before renaming,
+    /// which the server may only advertise if it does.
+    pub fn prepare_rename(&self) -> bool {
+        self.flag(&["textDocument", "rename", "prepareSupport"]).unwrap_or(false)
+    }
+
+    /// Whether the client shows the progress of work done by the server, see
+    /// `WorkDoneProgress`.
+    pub fn work_done_progress(&self) -> bool {
+        self.flag(&["window", "workDoneProgress"]).unwrap_or(false)
+    }
+}
+
+/// Parameters of `initialize`, with the capabilities of the client as it sent
+/// them.
+#[derive(Debug)]
+pub struct RlsInitializeParams {
+    pub params: InitializeParams,
+    pub capabilities: RlsClientCapabilities,
+}
+
+impl From<InitializeParams> for RlsInitializeParams {
+    fn from(params: InitializeParams) -> RlsInitializeParams {
+        let capabilities = serde_json::to_value(&params.capabilities).unwrap_or_default();
+        RlsInitializeParams {
+            params,
+            capabilities: RlsClientCapabilities(capabilities),
+        }
+    }
+}
+
+impl Serialize for RlsInitializeParams {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.params).map_err(S::Error::custom)?;
+        if let Some(params) = value.as_object_mut() {
+            params.insert("capabilities".to_owned(), self.capabilities.0.clone());
+        }
+        value.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RlsInitializeParams {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let capabilities = value.get("capabilities").cloned().unwrap_or_default();
+        let params = serde_json::from_value(value).map_err(D::Error::custom)?;
+        Ok(RlsInitializeParams {
+            params,
+            capabilities: RlsClientCapabilities(capabilities),
+        })
+    }
+}
+
+/// A symbol of a document with the symbols it contains, for clients showing
+/// them as a tree (`DocumentSymbol` in later versions of the protocol).
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RlsDocumentSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The symbol and its children.
+    pub range: Range,
+    /// The name of the symbol.
+    pub selection_range: Range,
+    pub children: Vec<RlsDocumentSymbol>,
+}
+
+/// The response to `textDocument/documentSymbol`: a tree of symbols if the
+/// client supports them, a flat list otherwise.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum DocumentSymbolResponse {
+    Flat(Vec<SymbolInformation>),
+    Nested(Vec<RlsDocumentSymbol>),
+}
+
 /* -----------------  JSON-RPC protocol types ----------------- */
 
 /// Supported initilization options that can be passed in the `initialize`
 /// request, under `initialization_options` key. These are specific to the RLS.
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(default)]
 pub struct InitializationOptions {
     /// Should the build not be triggered immediately after receiving `initialize`
     #[serde(rename="omitInitBuild")]
     pub omit_init_build: bool,
+    /// Should the server send `rustDocument/serverStatus` notifications
+    #[serde(rename="statusNotifications")]
+    pub status_notifications: bool,
+    /// Should the server send `rls/eventStream` notifications
+    #[serde(rename="eventStream")]
+    pub event_stream: bool,
+    /// Path mappings applied before those of the config (see
+    /// `Config::path_mappings`), known from the start, so they apply to the
+    /// root of the workspace as well
+    #[serde(rename="pathMappings")]
+    pub path_mappings: Vec<(PathBuf, PathBuf)>,
 }
 
 impl Default for InitializationOptions {
     fn default() -> Self {
         InitializationOptions {
-            omit_init_build: false
+            omit_init_build: false,
+            status_notifications: false,
+            event_stream: false,
+            path_mappings: vec![],
+        }
+    }
+}
+
+/// Version of the RLS-specific extensions to the protocol (i.e., the
+/// parameters of the `rustDocument/*` notifications), advertised to the client
+/// as `rls.protocolVersion` in the response to `initialize`. Bumped whenever
+/// these change in an incompatible way.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The response to `initialize`, extended with the RLS protocol version.
+#[derive(Debug, Serialize)]
+pub struct RlsInitializeResult {
+    /// The capabilities the language server provides.
+    pub capabilities: RlsServerCapabilities,
+    /// The version of the RLS-specific extensions, see `PROTOCOL_VERSION`.
+    #[serde(rename = "rls.protocolVersion")]
+    pub protocol_version: u32,
+    /// How the columns of positions are counted, see `PositionEncoding`.
+    #[serde(rename = "positionEncoding")]
+    pub position_encoding: PositionEncoding,
+}
+
+/// Server capabilities, including those the `ServerCapabilities` of
+/// `languageserver-types` can't express yet: a rename provider is advertised
+/// as `{ "prepareProvider": true }`, for `textDocument/prepareRename`, to
+/// clients which support that, and `textDocument/typeDefinition`, `textDocument/declaration` and
+/// `textDocument/implementation` are supported. Document sync is advertised
+/// as `TextDocumentSyncOptions`: changes are incremental, though changes
+/// replacing the whole text are accepted too, saves are notified and, if
+/// documents can be formatted, `textDocument/willSaveWaitUntil` is supported.
+#[derive(Debug)]
+pub struct RlsServerCapabilities {
+    /// The capabilities `ServerCapabilities` can express.
+    pub capabilities: ServerCapabilities,
+    /// Whether the client supports `textDocument/prepareRename`, see
+    /// `RlsClientCapabilities::prepare_rename`.
+    pub prepare_rename: bool,
+}
+
+impl Serialize for RlsServerCapabilities {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.capabilities).map_err(S::Error::custom)?;
+        if let Some(rename_provider) = value.get_mut("renameProvider") {
+            if self.prepare_rename && *rename_provider == serde_json::Value::Bool(true) {
+                *rename_provider = json!({ "prepareProvider": true });
+            }
+        }
+        let formatting = value.get("documentFormattingProvider") == Some(&serde_json::Value::Bool(true));
+        if let Some(sync) = value.get_mut("textDocumentSync") {
+            if let Some(change) = sync.as_u64() {
+                *sync = json!({
+                    "openClose": true,
+                    "change": change,
+                    "willSaveWaitUntil": formatting,
+                    "save": { "includeText": false },
+                });
+            }
+        }
+        // Not known to `ServerCapabilities` yet, always supported.
+        if let Some(capabilities) = value.as_object_mut() {
+            capabilities.insert("typeDefinitionProvider".to_owned(), json!(true));
+            capabilities.insert("declarationProvider".to_owned(), json!(true));
+            capabilities.insert("implementationProvider".to_owned(), json!(true));
+        }
+        value.serialize(serializer)
+    }
+}
+
+/// A location related to a diagnostic, e.g. where a value was first borrowed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiagnosticRelatedInformation {
+    /// Where the related code is.
+    pub location: Location,
+    /// What the related code has to do with the diagnostic.
+    pub message: String,
+}
+
+/// How a diagnostic is rendered besides its severity, e.g. faded out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticTag {
+    /// Unused or unnecessary code.
+    Unnecessary,
+    /// Use of a deprecated item.
+    Deprecated,
+}
+
+impl Serialize for DiagnosticTag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(match *self {
+            DiagnosticTag::Unnecessary => 1,
+            DiagnosticTag::Deprecated => 2,
+        })
+    }
+}
+
+/// A `Diagnostic` with related information and tags, which
+/// `languageserver-types` can't express yet. Serialized as
+/// `relatedInformation` and `tags`, if there are any.
+#[derive(Debug, Clone)]
+pub struct RlsDiagnostic {
+    /// The diagnostic.
+    pub diagnostic: Diagnostic,
+    /// Locations related to the diagnostic.
+    pub related_information: Vec<DiagnosticRelatedInformation>,
+    /// How to render the diagnostic.
+    pub tags: Vec<DiagnosticTag>,
+}
+
+impl RlsDiagnostic {
+    /// A diagnostic without related information or tags.
+    pub fn new(diagnostic: Diagnostic) -> RlsDiagnostic {
+        RlsDiagnostic {
+            diagnostic,
+            related_information: vec![],
+            tags: vec![],
+        }
+    }
+}
+
+impl Serialize for RlsDiagnostic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.diagnostic).map_err(S::Error::custom)?;
+        if let Some(diagnostic) = value.as_object_mut() {
+            if !self.related_information.is_empty() {
+                let related = serde_json::to_value(&self.related_information).map_err(S::Error::custom)?;
+                diagnostic.insert("relatedInformation".to_owned(), related);
+            }
+            if !self.tags.is_empty() {
+                let tags = serde_json::to_value(&self.tags).map_err(S::Error::custom)?;
+                diagnostic.insert("tags".to_owned(), tags);
+            }
+        }
+        value.serialize(serializer)
+    }
+}
+
+/// Parameters of `textDocument/publishDiagnostics`, with related information.
+#[derive(Debug, Serialize)]
+pub struct RlsPublishDiagnosticsParams {
+    /// The document the diagnostics are about.
+    pub uri: Url,
+    /// The diagnostics of the document.
+    pub diagnostics: Vec<RlsDiagnostic>,
+}
+
+/// Parameters of `rustWorkspace/deglob`: a `Location`, optionally asking for
+/// every glob import of the document to be expanded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeglobParams {
+    /// The document.
+    pub uri: Url,
+    /// The glob to expand, or a position on the line of the globs to expand.
+    /// Ignored if `all` is set.
+    pub range: Range,
+    /// Expand every glob import of the document.
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// `ReferenceParams`, extended with a filter on the kind of the references.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RlsReferenceParams {
+    /// The document.
+    pub text_document: TextDocumentIdentifier,
+    /// The position of the symbol in the document.
+    pub position: Position,
+    /// Whether to include the declaration of the symbol.
+    pub context: ReferenceContext,
+    /// Only find references of these kinds. All references if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kinds: Vec<ReferenceKind>,
+    /// Send the results as partial results for this token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial_result_token: Option<NumberOrString>,
+}
+
+impl From<ReferenceParams> for RlsReferenceParams {
+    fn from(params: ReferenceParams) -> RlsReferenceParams {
+        RlsReferenceParams {
+            text_document: params.text_document,
+            position: params.position,
+            context: params.context,
+            kinds: vec![],
+            partial_result_token: None,
+        }
+    }
+}
+
+/// How a symbol is used by a reference, see `RlsReferenceParams`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceKind {
+    /// A call of a function or method.
+    #[serde(rename = "call")]
+    Call,
+    /// A struct literal, e.g. `Foo { x: 1 }`.
+    #[serde(rename = "structLiteral")]
+    StructLiteral,
+    /// An import in a `use` statement.
+    #[serde(rename = "import")]
+    Import,
+}
+
+/// A `WorkspaceEdit` which may rename files, which the `WorkspaceEdit` of
+/// `languageserver-types` can't express yet. Only sent to clients which
+/// support the `rename` resource operation.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RlsWorkspaceEdit {
+    /// Edits of the text of documents only.
+    Changes(WorkspaceEdit),
+    /// Changes applied in order, renaming files after editing them.
+    DocumentChanges {
+        /// The changes.
+        #[serde(rename = "documentChanges")]
+        document_changes: Vec<DocumentChange>,
+    },
+}
+
+/// An entry of `RlsWorkspaceEdit::DocumentChanges`.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum DocumentChange {
+    /// Edits of the text of a document.
+    Edit(DocumentEdit),
+    /// A renamed file or directory.
+    Rename(RenameFile),
+}
+
+/// Edits of the text of a document, whichever version the client has.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentEdit {
+    /// The document to edit, with a `null` version.
+    pub text_document: VersionedDocument,
+    /// The edits.
+    pub edits: Vec<TextEdit>,
+}
+
+/// A document identifier whose version may be `null`, for any version.
+#[derive(Debug, Serialize)]
+pub struct VersionedDocument {
+    /// The document's URI.
+    pub uri: Url,
+    /// The version of the document, if it matters.
+    pub version: Option<u64>,
+}
+
+/// Renames a file or directory.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameFile {
+    /// Always `"rename"`.
+    pub kind: &'static str,
+    /// The current URI.
+    pub old_uri: Url,
+    /// The new URI.
+    pub new_uri: Url,
+}
+
+impl RenameFile {
+    /// Rename `old` to `new`.
+    pub fn new(old: Url, new: Url) -> RenameFile {
+        RenameFile {
+            kind: "rename",
+            old_uri: old,
+            new_uri: new,
+        }
+    }
+}
+
+// Every build request gets a new session, identified by a `session_id`. Each
+// session sends the `beginBuild`, `diagnosticsBegin` and `diagnosticsEnd`
+// notifications exactly once, in that order.
+
+/// Parameters of `rustDocument/beginBuild`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BeginBuildParams {
+    /// Identifies the build session.
+    pub session_id: u64,
+}
+
+/// Parameters of `rustDocument/diagnosticsBegin`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsBeginParams {
+    /// Identifies the build session.
+    pub session_id: u64,
+}
+
+/// Parameters of `rustDocument/diagnosticsEnd`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsEndParams {
+    /// Identifies the build session.
+    pub session_id: u64,
+    /// Number of files with diagnostics (0 if the build was squashed or
+    /// failed to run).
+    pub file_count: usize,
+    /// Total number of diagnostics.
+    pub diagnostic_count: usize,
+    /// Time from requesting the build until all post-build work was done.
+    pub duration_ms: u64,
+}
+
+/// Whether the RLS is building the project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildState {
+    /// No build is running and the last one succeeded (or there was none).
+    Idle,
+    /// A build is running or about to run.
+    Building,
+    /// No build is running and the last one failed.
+    Failed,
+}
+
+/// The status of the RLS, the result of `rls/status` and the parameters of
+/// `rustDocument/serverStatus`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatus {
+    /// Whether the RLS is building the project.
+    pub build_state: BuildState,
+    /// Number of crates whose analysis data was loaded by the last build.
+    pub indexed_crates: usize,
+    /// Time since the analysis data was last reloaded, if it ever was.
+    pub analysis_age_ms: Option<u64>,
+    /// Resident memory used by the RLS in bytes, if available on this platform.
+    pub memory_usage: Option<u64>,
+    /// The configured memory budget in bytes, if any.
+    pub memory_budget: Option<u64>,
+    /// Number of crates whose analysis data was dropped to stay within the
+    /// memory budget.
+    pub evicted_crates: usize,
+    /// Progress of reading analysis data from disk, while that is happening.
+    pub indexing: Option<IndexingProgress>,
+    /// The crates compiled so far by the running Cargo build, if any.
+    pub compiling: Option<CompilingProgress>,
+}
+
+/// The result of `rls/metrics`: how often each method was handled and how
+/// long that took.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerMetrics {
+    /// Time since the server started.
+    pub uptime_ms: u64,
+    /// The methods handled so far, ordered by name.
+    pub methods: Vec<MethodMetrics>,
+}
+
+/// How often a method was handled and how long that took, measured until its
+/// handler returned (some requests answer later).
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodMetrics {
+    /// The method, e.g. `textDocument/hover`.
+    pub method: String,
+    /// Number of messages handled.
+    pub count: u64,
+    /// Number of them whose handler failed.
+    pub errors: u64,
+    /// Time spent handling all of them.
+    pub total_ms: u64,
+    /// The longest time handling one.
+    pub max_ms: u64,
+    /// The number of messages by how long they took, fastest first.
+    pub latency: Vec<LatencyBucket>,
+}
+
+/// The number of messages which took at most `max_ms` (and more than the
+/// bound of the previous bucket).
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyBucket {
+    /// The upper bound, `None` for the last bucket.
+    pub max_ms: Option<u64>,
+    /// Number of messages.
+    pub count: u64,
+}
+
+/// The result of `rls/diagnostics`: the diagnostics currently published.
+#[derive(Debug, Serialize)]
+pub struct DiagnosticsSummary {
+    /// Number of diagnostics of each severity, in all files.
+    pub counts: SeverityCounts,
+    /// The files with diagnostics, ordered by path.
+    pub files: Vec<FileDiagnostics>,
+}
+
+/// The diagnostics of a file, see `DiagnosticsSummary`.
+#[derive(Debug, Serialize)]
+pub struct FileDiagnostics {
+    /// The file.
+    pub uri: Url,
+    /// Number of diagnostics of each severity in the file.
+    pub counts: SeverityCounts,
+    /// The diagnostics, as published.
+    pub diagnostics: Vec<RlsDiagnostic>,
+}
+
+/// Number of diagnostics of each severity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SeverityCounts {
+    pub error: usize,
+    pub warning: usize,
+    pub information: usize,
+    pub hint: usize,
+}
+
+impl SeverityCounts {
+    /// Counts a diagnostic of the given severity (not at all if it has none).
+    pub fn add(&mut self, severity: Option<DiagnosticSeverity>) {
+        match severity {
+            Some(DiagnosticSeverity::Error) => self.error += 1,
+            Some(DiagnosticSeverity::Warning) => self.warning += 1,
+            Some(DiagnosticSeverity::Information) => self.information += 1,
+            Some(DiagnosticSeverity::Hint) => self.hint += 1,
+            None => {}
+        }
+    }
+}
+
+/// The result of `rustWorkspace/changedSinceBuild`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangedSinceBuild {
+    /// Time since the analysis data was last reloaded, if it ever was.
+    pub analysis_age_ms: Option<u64>,
+    /// The files changed since then.
+    pub files: Vec<ChangedFile>,
+    /// The crates containing changed files, whose analysis data is stale.
+    pub dirty_crates: Vec<String>,
+}
+
+/// A file changed since the analysis data was last reloaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangedFile {
+    /// The file.
+    pub uri: Url,
+    /// How the file changed.
+    pub change: FileChange,
+    /// The crate the file belongs to, if known.
+    #[serde(rename = "crate")]
+    pub crate_name: Option<String>,
+}
+
+/// How a file changed since the last build.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    /// The file was edited in the client, and not built since.
+    #[serde(rename = "edited")]
+    Edited,
+    /// The file was modified on disk since the analysis data was reloaded.
+    #[serde(rename = "modifiedOnDisk")]
+    ModifiedOnDisk,
+}
+
+/// How many crates' save-analysis data has been read so far.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct IndexingProgress {
+    /// Number of crates read.
+    pub done: usize,
+    /// Number of crates to read.
+    pub total: usize,
+}
+
+/// The crate Cargo started compiling last, while a build is running.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CompilingProgress {
+    /// Name of the crate.
+    pub crate_name: String,
+    /// How many crates started compiling so far, this one included.
+    pub index: usize,
+    /// How many crates the build compiles, as far as is known yet.
+    pub total: usize,
+}
+
+/// The result of the `rls.formatWorkspace` command.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatWorkspaceResult {
+    /// Number of files which were formatted (whether they changed or not).
+    pub files_checked: usize,
+    /// Files which were changed by formatting.
+    pub files_changed: Vec<Url>,
+    /// Files which could not be formatted, e.g. due to parse errors.
+    pub files_failed: Vec<Url>,
+    /// Whether formatting was cancelled before all files were checked.
+    pub cancelled: bool,
+}
+
+/// A `Location` answering `textDocument/definition`, with how it was found.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DefinitionLocation {
+    /// The file of the definition.
+    pub uri: Url,
+    /// The definition in the file.
+    pub range: Range,
+    /// Clients may show definitions not found by the compiler as less
+    /// certain.
+    pub source: DefinitionSource,
+}
+
+impl DefinitionLocation {
+    /// A definition at `location`, found by `source`.
+    pub fn new(location: Location, source: DefinitionSource) -> DefinitionLocation {
+        DefinitionLocation {
+            uri: location.uri,
+            range: location.range,
+            source,
+        }
+    }
+}
+
+/// How a definition was found, from the most to the least reliable.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionSource {
+    /// From the analysis data of the compiler.
+    #[serde(rename = "compiler")]
+    Compiler,
+    /// From racer, which parses the source on the fly.
+    #[serde(rename = "racer")]
+    Racer,
+    /// From searching the source for a definition of the name.
+    #[serde(rename = "search")]
+    Search,
+}
+
+/// A definition reported by the `rls.symbolStats` command.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SymbolStat {
+    /// The name of the definition.
+    pub name: String,
+    /// What kind of thing is defined.
+    pub kind: SymbolKind,
+    /// Where it is defined.
+    pub location: Location,
+    /// Number of references to the definition.
+    pub references: usize,
+}
+
+/// Parameters of `rustDocument/typeOfSelection`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeOfSelectionParams {
+    /// The document.
+    pub text_document: TextDocumentIdentifier,
+    /// The selected expression.
+    pub range: Range,
+}
+
+/// The result of `rustDocument/typeOfSelection`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TypeOfSelection {
+    /// The type of the expression.
+    #[serde(rename = "type")]
+    pub ty: String,
+    /// The identifier whose type it is, see `actions::expression_type`.
+    pub range: Range,
+}
+
+/// The result of `rustDocument/borrows`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Borrows {
+    /// Where values are borrowed.
+    pub borrows: Vec<BorrowRegion>,
+    /// Where values are moved.
+    pub moves: Vec<MoveSite>,
+}
+
+/// A borrow reported by the borrow checker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BorrowRegion {
+    /// The borrowing expression.
+    pub range: Range,
+    /// From the borrow to where it ends, or just the borrow if the compiler
+    /// doesn't say.
+    pub region: Range,
+    /// Whether the borrow is mutable, if the compiler says.
+    pub mutable: Option<bool>,
+    /// The compiler's label of the borrow.
+    pub message: String,
+}
+
+/// A move reported by the borrow checker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MoveSite {
+    /// The moving expression.
+    pub range: Range,
+    /// The compiler's label of the move.
+    pub message: String,
+}
+
+/// Parameters of `rustDocument/formatWorkspaceProgress`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FormatWorkspaceProgressParams {
+    /// Number of files formatted so far.
+    pub done: usize,
+    /// Number of files to format.
+    pub total: usize,
+}
+
+/// Parameters of the notifications sent by the RLS.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum NotificationParams {
+    /// Parameters of `textDocument/publishDiagnostics`.
+    PublishDiagnostics(RlsPublishDiagnosticsParams),
+    /// Parameters of `rustDocument/beginBuild`.
+    BeginBuild(BeginBuildParams),
+    /// Parameters of `rustDocument/diagnosticsBegin`.
+    DiagnosticsBegin(DiagnosticsBeginParams),
+    /// Parameters of `rustDocument/diagnosticsEnd`.
+    DiagnosticsEnd(DiagnosticsEndParams),
+    /// Parameters of `rustDocument/serverStatus`.
+    ServerStatus(ServerStatus),
+    /// Parameters of `rustDocument/formatWorkspaceProgress`.
+    FormatWorkspaceProgress(FormatWorkspaceProgressParams),
+    /// Parameters of `window/showMessage`.
+    ShowMessage(ShowMessageParams),
+    /// Parameters of `$/progress`.
+    Progress(ProgressParams),
+    /// Parameters of `rls/eventStream`.
+    EventStream(ServerEventParams),
+}
+
+/// Parameters of `rls/eventStream`.
+#[derive(Debug, Serialize)]
+pub struct ServerEventParams {
+    /// Milliseconds since the server started.
+    #[serde(rename = "timeMs")]
+    pub time_ms: u64,
+    /// What happened.
+    pub event: ServerEvent,
+}
+
+/// Something which happened in the server, for client analytics.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind")]
+pub enum ServerEvent {
+    /// A build was requested.
+    #[serde(rename = "buildStarted")]
+    BuildStarted {
+        /// See `BeginBuildParams`.
+        #[serde(rename = "sessionId")]
+        session_id: u64,
+    },
+    /// A build finished, before its analysis data is loaded.
+    #[serde(rename = "buildEnded")]
+    BuildEnded {
+        #[serde(rename = "sessionId")]
+        session_id: u64,
+        outcome: BuildOutcome,
+        /// Since the build was requested.
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+    },
+    /// The analysis data of a build was loaded.
+    #[serde(rename = "indexLoaded")]
+    IndexLoaded {
+        #[serde(rename = "sessionId")]
+        session_id: u64,
+        /// Number of crates whose analysis data was loaded.
+        #[serde(rename = "indexedCrates")]
+        indexed_crates: usize,
+        /// Since the build ended.
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+    },
+    /// A request was answered.
+    #[serde(rename = "requestServed")]
+    RequestServed {
+        method: &'static str,
+        /// Whether the request succeeded, rather than failing with an error.
+        success: bool,
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+    },
+    /// A cache was brought up to date, reusing `hits` entries and computing
+    /// `misses` entries again.
+    #[serde(rename = "cache")]
+    Cache {
+        /// Which cache, currently only `"symbolIndex"` (by file).
+        cache: &'static str,
+        hits: usize,
+        misses: usize,
+    },
+}
+
+/// How a build finished.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum BuildOutcome {
+    /// The build succeeded.
+    #[serde(rename = "success")]
+    Success,
+    /// The build failed, e.g. due to compile errors.
+    #[serde(rename = "failure")]
+    Failure,
+    /// The build was superseded by a later build.
+    #[serde(rename = "squashed")]
+    Squashed,
+    /// The build could not be run.
+    #[serde(rename = "error")]
+    Error,
+}
+
+/// Partial results of a request, see `actions::pages`, or the progress of work
+/// done by the server, see `WorkDoneProgress`.
+#[derive(Debug, Serialize)]
+pub struct ProgressParams {
+    /// The `partialResultToken` of the request, or the token the server
+    /// created for the work.
+    pub token: NumberOrString,
+    /// A page of results, or a `WorkDoneProgress`.
+    pub value: serde_json::Value,
+}
+
+/// Parameters of `window/workDoneProgress/create`.
+#[derive(Debug, Serialize)]
+pub struct WorkDoneProgressCreateParams {
+    /// The token the progress will be reported for.
+    pub token: NumberOrString,
+}
+
+/// The progress of work done by the server, the value of `$/progress`
+/// notifications for a token created with `window/workDoneProgress/create`.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum WorkDoneProgress {
+    /// The work started.
+    Begin {
+        /// What the work is, e.g. "Building".
+        title: String,
+        /// The details of the progress, e.g. "3/87 serde".
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        /// How much of the work is done, from 0 to 100.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percentage: Option<u32>,
+    },
+    /// The work progressed.
+    Report {
+        /// The details of the progress.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        /// How much of the work is done, from 0 to 100.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percentage: Option<u32>,
+    },
+    /// The work is done.
+    End {
+        /// The outcome of the work.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+/// `WorkspaceSymbolParams`, with a token for partial results.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RlsWorkspaceSymbolParams {
+    /// The query string.
+    pub query: String,
+    /// Send the results as partial results for this token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial_result_token: Option<NumberOrString>,
+}
+
+impl From<WorkspaceSymbolParams> for RlsWorkspaceSymbolParams {
+    fn from(params: WorkspaceSymbolParams) -> RlsWorkspaceSymbolParams {
+        RlsWorkspaceSymbolParams {
+            query: params.query,
+            partial_result_token: None,
         }
     }
 }
@@ -250,12 +1469,12 @@ pub struct NotificationMessage {
     /// The well-known language server protocol notification method string.
     pub method: &'static str,
     /// Extra notification parameters.
-    pub params: Option<PublishDiagnosticsParams>,
+    pub params: Option<NotificationParams>,
 }
 
 impl NotificationMessage {
     /// Construct a new notification message.
-    pub fn new(method: &'static str, params: Option<PublishDiagnosticsParams>) -> Self {
+    pub fn new(method: &'static str, params: Option<NotificationParams>) -> Self {
         NotificationMessage {
             jsonrpc: version::Version::V2,
             method,
@@ -289,3 +1508,179 @@ impl <T> RequestMessage<T> where T: Debug + Serialize {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::ls_util::{col_from_rls, col_to_rls};
+
+    fn completion(label: &str, relevance: CompletionRelevance) -> RankedCompletion {
+        RankedCompletion {
+            item: CompletionItem::new_simple(label.to_string(), String::new()),
+            relevance,
+            in_document: true,
+            definition: None,
+        }
+    }
+
+    #[test]
+    fn test_rank_completions() {
+        let items: Vec<_> = ["_private", "Zeta", "alpha", "beta", "Beta", "a", "b", "c", "d", "e"].iter()
+            .map(|label| completion(label, CompletionRelevance::Item))
+            .collect();
+        let list = rank_completions(items, 0);
+        assert!(!list.is_incomplete);
+
+        let labels: Vec<_> = list.items.iter().map(|i| &*i.label).collect();
+        assert_eq!(labels, ["a", "alpha", "b", "Beta", "beta", "c", "d", "e", "Zeta", "_private"]);
+        assert_eq!(list.items[0].sort_text, Some("00".to_owned()));
+        assert_eq!(list.items[9].sort_text, Some("09".to_owned()));
+        assert_eq!(list.items[9].filter_text, Some("private".to_owned()));
+        assert_eq!(list.items[1].filter_text, Some("alpha".to_owned()));
+    }
+
+    #[test]
+    fn test_document_path() {
+        let documents = DocumentPaths::new(PathMappings::default());
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        let path = documents.path(&uri).unwrap();
+        assert!(is_virtual_document(&path));
+        assert_eq!(path.file_name().unwrap(), "Untitled-1");
+        assert_eq!(documents.path(&uri).unwrap(), path);
+        assert_eq!(documents.uri(&path), Ok(uri.clone()));
+
+        documents.close(&path);
+        assert!(documents.uri(&path) != Ok(uri.clone()));
+        assert!(documents.path(&uri).unwrap() != path);
+
+        let file = Url::from_file_path(env::current_dir().unwrap().join("main.rs")).unwrap();
+        let path = documents.path(&file).unwrap();
+        assert!(!is_virtual_document(&path));
+        assert_eq!(documents.uri(&path), Ok(file));
+
+        let client_dir = env::current_dir().unwrap().join("client");
+        let server_dir = env::current_dir().unwrap().join("server");
+        documents.set_mappings(PathMappings::new(&[(client_dir.clone(), server_dir.clone())]));
+        let file = Url::from_file_path(client_dir.join("main.rs")).unwrap();
+        let path = documents.path(&file).unwrap();
+        assert_eq!(path, server_dir.join("main.rs"));
+        assert_eq!(documents.uri(&path), Ok(file));
+    }
+
+    #[test]
+    fn test_rank_completions_relevance() {
+        let mut far_fn = completion("a_far_fn", CompletionRelevance::Function);
+        far_fn.in_document = false;
+        let method = || {
+            let mut method = completion("method", CompletionRelevance::Method);
+            method.definition = Some((PathBuf::from("/foo.rs"), 10));
+            method
+        };
+        let items = vec![
+            completion("module", CompletionRelevance::Module),
+            far_fn,
+            completion("free_fn", CompletionRelevance::Function),
+            method(),
+            completion("field", CompletionRelevance::Field),
+            completion("local", CompletionRelevance::Local),
+            completion("free_fn", CompletionRelevance::Function),
+            method(),
+        ];
+
+        let list = rank_completions(items, 0);
+        let labels: Vec<_> = list.items.iter().map(|i| &*i.label).collect();
+        assert_eq!(labels, ["local", "field", "method", "free_fn", "a_far_fn", "module"]);
+
+        let list = rank_completions(vec![completion("b", CompletionRelevance::Item), completion("a", CompletionRelevance::Item)], 1);
+        assert!(list.is_incomplete);
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].label, "a");
+        assert_eq!(list.items[0].sort_text, Some("0".to_owned()));
+    }
+
+    #[cfg(feature = "racer")]
+    #[test]
+    fn test_takes_self() {
+        assert!(takes_self("pub fn len(&self) -> usize"));
+        assert!(takes_self("fn push(&'a mut self, value: T)"));
+        assert!(takes_self("fn into_iter(self) -> IntoIter<T>"));
+        assert!(!takes_self("pub fn new(selfish: bool) -> Foo"));
+        assert!(!takes_self("pub fn new() -> Foo"));
+    }
+
+    #[test]
+    fn test_col_conversion() {
+        // `é` is two bytes and one UTF-16 unit, `𝔸` four bytes and two units.
+        let line = "let é = \"𝔸\";";
+        assert_eq!(col_to_rls(line, 4, PositionEncoding::Utf16), 4);
+        assert_eq!(col_to_rls(line, 7, PositionEncoding::Utf16), 7);
+        assert_eq!(col_to_rls(line, 12, PositionEncoding::Utf16), 11);
+        assert_eq!(col_to_rls(line, 7, PositionEncoding::Utf8), 6);
+        assert_eq!(col_to_rls(line, 15, PositionEncoding::Utf8), 11);
+        // In the middle of `𝔸`.
+        assert_eq!(col_to_rls(line, 10, PositionEncoding::Utf16), 10);
+        // Past the end of the line.
+        assert_eq!(col_to_rls(line, 15, PositionEncoding::Utf16), 14);
+
+        assert_eq!(col_from_rls(line, 11, PositionEncoding::Utf16), 12);
+        assert_eq!(col_from_rls(line, 11, PositionEncoding::Utf8), 15);
+        assert_eq!(col_from_rls(line, 14, PositionEncoding::Utf16), 15);
+        assert_eq!(col_from_rls(line, 0, PositionEncoding::Utf8), 0);
+    }
+
+    #[test]
+    fn test_client_capabilities() {
+        let none = RlsClientCapabilities(json!({}));
+        assert_eq!(none.position_encoding(), PositionEncoding::Utf16);
+        assert!(!none.file_renames());
+        assert!(none.markdown_hover());
+        assert!(!none.related_information());
+        assert!(!none.diagnostic_tags());
+        assert!(!none.snippet_completions());
+        assert!(!none.hierarchical_symbols());
+
+        let standard = RlsClientCapabilities(json!({
+            "workspace": { "workspaceEdit": { "resourceOperations": ["create", "rename"] } },
+            "textDocument": {
+                "hover": { "contentFormat": ["plaintext"] },
+                "publishDiagnostics": { "relatedInformation": true, "tagSupport": { "valueSet": [1, 2] } },
+                "completion": { "completionItem": { "snippetSupport": true } },
+                "documentSymbol": { "hierarchicalDocumentSymbolSupport": true }
+            }
+        }));
+        assert!(standard.file_renames());
+        assert!(!standard.markdown_hover());
+        assert!(standard.related_information());
+        assert!(standard.diagnostic_tags());
+        assert!(standard.snippet_completions());
+        assert!(standard.hierarchical_symbols());
+
+        let experimental = RlsClientCapabilities(json!({
+            "experimental": {
+                "positionEncodings": ["utf-16", "utf-8"],
+                "resourceOperations": ["create"],
+                "hoverContentFormat": ["plaintext", "markdown"],
+                "relatedInformation": "yes",
+                "diagnosticTags": true
+            }
+        }));
+        assert_eq!(experimental.position_encoding(), PositionEncoding::Utf8);
+        assert!(!experimental.file_renames());
+        assert!(experimental.markdown_hover());
+        assert!(!experimental.related_information());
+        assert!(experimental.diagnostic_tags());
+    }
+
+    #[test]
+    fn test_initialize_params_keep_capabilities() {
+        let params: RlsInitializeParams = serde_json::from_value(json!({
+            "processId": null,
+            "rootUri": null,
+            "trace": "off",
+            "capabilities": { "textDocument": { "documentSymbol": { "hierarchicalDocumentSymbolSupport": true } } }
+        })).unwrap();
+        assert!(params.capabilities.hierarchical_symbols());
+        let value = serde_json::to_value(&params).unwrap();
+        assert_eq!(value["capabilities"]["textDocument"]["documentSymbol"]["hierarchicalDocumentSymbolSupport"], json!(true));
+    }
+}
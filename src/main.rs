@@ -8,103 +8,66 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-//! The Rust Language Server.
-//!
-//! The RLS provides a server that runs in the background, providing IDEs,
-//! editors, and other tools with information about Rust programs. It supports
-//! functionality such as 'goto definition', symbol search, reformatting, and
-//! code completion, and enables renaming and refactorings.
+//! The `rls` binary, serving a client on stdin and stdout (see the `rls`
+//! library), or running one of the commands given as arguments.
 
-#![feature(rustc_private)]
-#![feature(concat_idents)]
-#![feature(type_ascription)]
-#![feature(integer_atomics)]
-#![feature(fnbox)]
-#![deny(missing_docs)]
-
-extern crate cargo;
 extern crate env_logger;
-extern crate languageserver_types as ls_types;
-#[macro_use]
-extern crate lazy_static;
-#[macro_use]
-extern crate log;
-extern crate racer;
-extern crate rls_analysis as analysis;
-extern crate rls_data as data;
+extern crate rls;
 extern crate rls_rustc as rustc_shim;
-extern crate rls_span as span;
-extern crate rls_vfs as vfs;
-extern crate rustfmt_nightly as rustfmt;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate rayon;
-
-#[macro_use]
-extern crate serde_json;
 
-extern crate url;
-extern crate jsonrpc_core;
+use rls::{cmd, config, Server};
 
 use std::env;
-use std::sync::Arc;
-
-pub mod actions;
-pub mod build;
-pub mod cmd;
-pub mod config;
-pub mod lsp_data;
-pub mod server;
-
-#[cfg(test)]
-mod test;
-
-// Timeout = 1.5s (totally arbitrary).
-#[cfg(not(test))]
-const COMPILER_TIMEOUT: u64 = 1500;
-
-// Timeout for potenially very slow CPU CI boxes
-#[cfg(test)]
-const COMPILER_TIMEOUT: u64 = 3_600_000;
-
-const CRATE_BLACKLIST: [&'static str; 10] = [
-    "libc", "typenum", "alloc", "idna", "openssl", "libunicode_normalization", "serde",
-    "serde_json", "librustc_serialize", "libunicode_segmentation",
-];
-
-const RUSTC_SHIM_ENV_VAR_NAME: &'static str = "RLS_RUSTC_SHIM";
-
-type Span = span::Span<span::ZeroIndexed>;
+use std::process;
 
 /// The main entry point to the RLS. Parses CLI arguments and then runs the
 /// server.
-pub fn main() {
+fn main() {
     env_logger::init().unwrap();
 
-    if env::var(RUSTC_SHIM_ENV_VAR_NAME).map(|v| v != "0").unwrap_or(false) {
+    if env::var(rls::RUSTC_SHIM_ENV_VAR_NAME).map(|v| v != "0").unwrap_or(false) {
         rustc_shim::run();
         return;
     }
 
-    if let Some(first_arg) = ::std::env::args().skip(1).next() {
+    // Configuration overrides can be given before any other argument.
+    let mut overrides = config::ConfigOverrides::from_env();
+    let mut args = ::std::env::args().skip(1).peekable();
+    while args.peek().map_or(false, |a| a == "--config") {
+        args.next();
+        let result = match args.next() {
+            Some(arg) => overrides.parse(&arg),
+            None => Err("expected `key=value` after `--config`".to_owned()),
+        };
+        // Not on stdout, that's where a client expects the server to talk.
+        if let Err(e) = result {
+            eprintln!("{}. Supported arguments:\n{}", e, help());
+            process::exit(1);
+        }
+    }
+    let config = match config::Config::with_overrides(overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Some(first_arg) = args.next() {
         match first_arg.as_str() {
-            "--version" | "-V" => println!("rls-preview {}", version()),
+            "--version" | "-V" => println!("rls-preview {}", rls::version()),
             "--help" | "-h" => println!("{}", help()),
-            "--cli" => cmd::run(),
+            "--cli" if args.next().map_or(false, |a| a == "index") => {
+                ::std::process::exit(cmd::index(config));
+            }
+            "--once" => ::std::process::exit(cmd::index(config)),
+            "--cli" => cmd::run(config),
             unknown => println!("Unknown argument '{}'. Supported arguments:\n{}", unknown, help()),
         }
         return;
     }
 
-    let analysis = Arc::new(analysis::AnalysisHost::new(analysis::Target::Debug));
-    let vfs = Arc::new(vfs::Vfs::new());
-
-    server::run_server(analysis, vfs);
-}
-
-fn version() -> &'static str {
-    concat!(env!("CARGO_PKG_VERSION"), "-", include_str!(concat!(env!("OUT_DIR"), "/commit-info.txt")))
+    Server::new().config(config).run_stdio();
 }
 
 fn help() -> &'static str {
@@ -112,6 +75,11 @@ fn help() -> &'static str {
     --version or -V to print the version and commit info
     --help or -h for this message
     --cli starts the RLS in command line mode
+    --cli index or --once builds the project in the current directory once,
+        prints its diagnostics as JSON and exits (with 1 if there were errors)
+    --config key=value (before any other argument, can be repeated) overrides
+        a configuration option, taking precedence over the client's settings.
+        Options can also be set via RLS_<KEY> environment variables
     No input starts the RLS as a language server
     "#
 }
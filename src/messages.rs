@@ -0,0 +1,128 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Strings generated by the RLS itself (errors, code action titles), as
+//! opposed to the ones coming from the compiler or other tools, which are
+//! passed through unchanged.
+//!
+//! Every message has a stable id. Clients shipping a localized UI can pass
+//! translations by id in the `message_catalogs` option, the catalog for the
+//! `locale` option (or just its language, e.g. `de` for `de-AT`) is used for
+//! all messages it contains. Anything else is in English. In a message with
+//! `{}`, it stands for a name filled in by the RLS.
+
+use config::Config;
+
+macro_rules! messages {
+    ($($name: ident = $id: expr, $text: expr;)*) => {
+        /// A message generated by the RLS.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum Message {
+            $($name,)*
+        }
+
+        impl Message {
+            /// The id of the message in catalogs.
+            pub fn id(&self) -> &'static str {
+                match *self {
+                    $(Message::$name => $id,)*
+                }
+            }
+
+            /// The English text of the message.
+            pub fn english(&self) -> &'static str {
+                match *self {
+                    $(Message::$name => $text,)*
+                }
+            }
+
+            /// Every message.
+            pub fn all() -> &'static [Message] {
+                &[$(Message::$name,)*]
+            }
+        }
+    }
+}
+
+messages! {
+    UnknownCommand = "unknownCommand", "Unknown command";
+    BadCommandArguments = "badCommandArguments", "Invalid arguments for the command";
+    ReformatFailed = "reformatFailed", "Reformat failed to complete successfully";
+    FindImplsFailed = "findImplsFailed", "Find Implementations failed to complete successfully";
+    NoGlob = "noGlob", "No glob in selection.";
+    NotAGlob = "notAGlob", "Not a glob";
+    CouldNotOpenFile = "couldNotOpenFile", "Couldn't open file";
+    NoAnalysisInfo = "noAnalysisInfo", "Couldn't get info from analysis";
+    RestrictVisibility = "restrictVisibility", "Change visibility to `pub(crate)`";
+    RemoveDependency = "removeDependency", "Remove the unused dependency";
+    NestImports = "nestImports", "Merge into a nested `use`";
+    FlattenImports = "flattenImports", "Split into one `use` per import";
+    EditNotApplied = "editNotApplied", "The edit was not applied";
+    NothingToRename = "nothingToRename", "There is nothing to rename here";
+    RenameKeyword = "renameKeyword", "Keywords can't be renamed";
+    RenamePrimitive = "renamePrimitive", "Primitive types can't be renamed";
+    RenameExternal = "renameExternal", "Items defined outside of this crate can't be renamed";
+    RenameModule = "renameModule", "Renaming this module needs its file renamed, which is not supported or disabled";
+    InvalidName = "invalidName", "This name can't be used for an item";
+    RenameSkippedExpansions = "renameSkippedExpansions", "Some uses were only found in macro expansions and were not renamed";
+    RenameNoUri = "renameNoUri", "Some uses are in files which can't be edited, nothing was renamed";
+    RustSrcMissing = "rustSrcMissing", "The source of the standard library is not installed, run `rustup component add rust-src` to go to its definitions";
+    ComponentsMissing = "componentsMissing", "The RLS needs components of the toolchain which are not installed, without them it can't look into the standard library:";
+    InstallComponents = "installComponents", "Install";
+    ComponentsInstalled = "componentsInstalled", "The components were installed, they are used from the next build on";
+    ComponentsInstallFailed = "componentsInstallFailed", "Installing the components failed";
+    DidYouMean = "didYouMean", "Change to `{}`";
+    ForeignToolchain = "foreignToolchain", "The project uses another toolchain than the RLS, it is built with that toolchain but without the unsaved changes. To see them, run the toolchain's RLS:";
+    ToolchainFileDiffers = "toolchainFileDiffers", "The project's rust-toolchain file names another toolchain than the RLS's, the project is built with the RLS's compiler. To build it with its own, run the toolchain's RLS:";
+}
+
+impl Message {
+    /// The text of the message in the configured locale.
+    pub fn text(&self, config: &Config) -> String {
+        if let Some(ref locale) = config.locale {
+            let language = locale.split(|c| c == '-' || c == '_').next().unwrap_or(locale);
+            for name in &[&**locale, language] {
+                if let Some(text) = config.message_catalogs.get(*name).and_then(|c| c.get(self.id())) {
+                    return text.clone();
+                }
+            }
+        }
+        self.english().to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn test_ids_unique() {
+        let ids: HashSet<_> = Message::all().iter().map(|m| m.id()).collect();
+        assert_eq!(ids.len(), Message::all().len());
+    }
+
+    #[test]
+    fn test_text() {
+        let mut config = Config::default();
+        assert_eq!(Message::NotAGlob.text(&config), "Not a glob");
+
+        let mut catalog = HashMap::new();
+        catalog.insert("notAGlob".to_owned(), "Kein Glob".to_owned());
+        config.message_catalogs.insert("de".to_owned(), catalog);
+        config.locale = Some("de-AT".to_owned());
+        assert_eq!(Message::NotAGlob.text(&config), "Kein Glob");
+        // Not translated.
+        assert_eq!(Message::NoGlob.text(&config), "No glob in selection.");
+
+        config.locale = Some("fr".to_owned());
+        assert_eq!(Message::NotAGlob.text(&config), "Not a glob");
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Normalization of file paths, so that a file has the same path whether it
+//! comes from the client (in a URI), from Cargo or from the compiler. The VFS
+//! and the analysis data are keyed by path, a file under two paths would be
+//! two different files to them. So every path is normalized on its way in:
+//! URIs in `lsp_data`, compiler messages in `PathResolver`, the analysis data
+//! in `normalize_analysis` and the paths the compiler and racer read through
+//! the VFS.
+//!
+//! On Windows these disagree on the case of the drive letter (the client may
+//! send `c:\foo`, Cargo reports `C:\foo`) and canonicalized paths are verbatim
+//! (`\\?\C:\foo`, or `\\?\UNC\server\share\foo` for a share). The rest of a
+//! path is spelled the way the file system reports it, so only the drive
+//! letter is compared case-insensitively. Elsewhere paths are left as they
+//! are.
+//!
+//! A workspace may also be opened through a symlink, while the compiler
+//! reports the real paths of its files. And the client may see the files
+//! somewhere else entirely, if the RLS runs in a container or on another
+//! machine (see `Config::path_mappings`). Internally files always have their
+//! real path: the `PathMappings` of a context map the paths of the client to
+//! them and back, for the URIs sent to the client. Remote builds map the paths
+//! in their messages the same way, see `build::remote`.
+
+use data::Analysis;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Pairs of path prefixes (normalized). A path under the first prefix of a
+/// pair is mapped to the same path under the second one, and back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrefixMap {
+    pairs: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PrefixMap {
+    /// Construct from the given pairs, the first pair a path starts with
+    /// applies.
+    pub fn new(pairs: &[(PathBuf, PathBuf)]) -> PrefixMap {
+        PrefixMap {
+            pairs: pairs.iter().map(|&(ref first, ref second)| (normalize(first), normalize(second))).collect(),
+        }
+    }
+
+    /// `path` with the first prefix of a pair replaced by the second, if it
+    /// starts with one. Only whole components are prefixes.
+    pub fn forward(&self, path: &Path) -> Option<PathBuf> {
+        self.replace(path, false)
+    }
+
+    /// `path` with the second prefix of a pair replaced by the first, the
+    /// inverse of `forward`.
+    pub fn backward(&self, path: &Path) -> Option<PathBuf> {
+        self.replace(path, true)
+    }
+
+    fn replace(&self, path: &Path, backwards: bool) -> Option<PathBuf> {
+        for &(ref first, ref second) in &self.pairs {
+            let (from, to) = if backwards { (second, first) } else { (first, second) };
+            if let Ok(rest) = path.strip_prefix(from) {
+                // Joining an empty path would add a separator.
+                return Some(if rest.as_os_str().is_empty() { to.clone() } else { to.join(rest) });
+            }
+        }
+        None
+    }
+}
+
+/// How the paths the client knows files by map to their real paths, see the
+/// module docs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PathMappings {
+    // The prefixes of paths as the client sees them and as the RLS sees them.
+    prefixes: PrefixMap,
+    // The root of the workspace as the client knows it.
+    client_root: Option<PathBuf>,
+    // The root of the workspace (mapped by `prefixes`) and its real path, if
+    // they differ.
+    linked_root: PrefixMap,
+}
+
+impl PathMappings {
+    /// Construct with the mapped path prefixes, the client's first, see
+    /// `Config::path_mappings`.
+    pub fn new(prefixes: &[(PathBuf, PathBuf)]) -> PathMappings {
+        PathMappings {
+            prefixes: PrefixMap::new(prefixes),
+            client_root: None,
+            linked_root: PrefixMap::default(),
+        }
+    }
+
+    /// The same mappings with other prefixes. The root keeps its path as the
+    /// client knows it, its real path may change.
+    pub fn with_prefixes(&self, prefixes: &[(PathBuf, PathBuf)]) -> PathMappings {
+        let mut mappings = PathMappings::new(prefixes);
+        if let Some(ref root) = self.client_root {
+            mappings.set_root(root);
+        }
+        mappings
+    }
+
+    /// Sets the root of the workspace, as the client knows it. Returns its real
+    /// path, which differs if the root is a symlink or in a symlinked directory,
+    /// the files under it are then mapped between the two.
+    pub fn set_root(&mut self, client_root: &Path) -> PathBuf {
+        self.client_root = Some(normalize(client_root));
+        self.linked_root = PrefixMap::default();
+        let root = self.prefixes.forward(&normalize(client_root)).unwrap_or_else(|| normalize(client_root));
+        let real = match fs::canonicalize(&root) {
+            Ok(real) => normalize(&real),
+            Err(_) => return root,
+        };
+        if real != root {
+            self.linked_root = PrefixMap::new(&[(root, real.clone())]);
+        }
+        real
+    }
+
+    /// The real path of the root of the workspace, if it was set.
+    pub fn root(&self) -> Option<PathBuf> {
+        self.client_root.as_ref().map(|root| self.from_client(root))
+    }
+
+    /// The normalized, real path of the file the client refers to by `path`.
+    pub fn from_client(&self, path: &Path) -> PathBuf {
+        let path = normalize(path);
+        let path = self.prefixes.forward(&path).unwrap_or(path);
+        self.linked_root.forward(&path).unwrap_or(path)
+    }
+
+    /// The path the client knows the file at `path` by, the inverse of
+    /// `from_client`.
+    pub fn to_client(&self, path: &Path) -> PathBuf {
+        let path = normalize(path);
+        let path = self.linked_root.backward(&path).unwrap_or(path);
+        self.prefixes.backward(&path).unwrap_or(path)
+    }
+}
+
+/// The normalized form of `path`: on Windows without a verbatim prefix, with
+/// backslashes only and an upper case drive letter.
+#[cfg(windows)]
+pub fn normalize(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(s) => PathBuf::from(normalize_windows(s)),
+        None => path.to_owned(),
+    }
+}
+
+/// The normalized form of `path`, which is `path` itself on this platform.
+#[cfg(not(windows))]
+pub fn normalize(path: &Path) -> PathBuf {
+    path.to_owned()
+}
+
+/// Normalizes the paths of the files in the save-analysis data of a crate,
+/// before it is handed to rls-analysis.
+pub fn normalize_analysis(analysis: &mut Analysis) {
+    if let Some(ref mut prelude) = analysis.prelude {
+        prelude.span.file_name = normalize(&prelude.span.file_name);
+    }
+    for def in &mut analysis.defs {
+        def.span.file_name = normalize(&def.span.file_name);
+    }
+    for import in &mut analysis.imports {
+        import.span.file_name = normalize(&import.span.file_name);
+    }
+    for reference in &mut analysis.refs {
+        reference.span.file_name = normalize(&reference.span.file_name);
+    }
+    for macro_ref in &mut analysis.macro_refs {
+        macro_ref.span.file_name = normalize(&macro_ref.span.file_name);
+        macro_ref.callee_span.file_name = normalize(&macro_ref.callee_span.file_name);
+    }
+    for relation in &mut analysis.relations {
+        relation.span.file_name = normalize(&relation.span.file_name);
+    }
+}
+
+// Just string manipulation, so it's tested on every platform.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn normalize_windows(path: &str) -> String {
+    let mut path = if path.starts_with(r"\\?\UNC\") {
+        format!(r"\\{}", &path[8..])
+    } else if path.starts_with(r"\\?\") && has_drive(&path[4..]) {
+        path[4..].to_owned()
+    } else {
+        path.to_owned()
+    };
+    path = path.replace('/', r"\");
+    if has_drive(&path) {
+        path = format!("{}{}", path[..1].to_uppercase(), &path[1..]);
+    }
+    path
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn has_drive(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0] < 0x80 && (bytes[0] as char).is_alphabetic() && bytes[1] == b':'
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_windows() {
+        assert_eq!(normalize_windows(r"c:\foo\src\lib.rs"), r"C:\foo\src\lib.rs");
+        assert_eq!(normalize_windows(r"C:\foo\src\lib.rs"), r"C:\foo\src\lib.rs");
+        assert_eq!(normalize_windows(r"\\?\c:\foo"), r"C:\foo");
+        assert_eq!(normalize_windows(r"c:/foo/src/lib.rs"), r"C:\foo\src\lib.rs");
+        assert_eq!(normalize_windows(r"\\?\UNC\server\share\foo"), r"\\server\share\foo");
+        assert_eq!(normalize_windows(r"\\server\share\foo"), r"\\server\share\foo");
+        // Only the drive letter is upper cased.
+        assert_eq!(normalize_windows(r"d:\Foo\bar"), r"D:\Foo\bar");
+        assert_eq!(normalize_windows(r"foo\bar"), r"foo\bar");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_linked_root() {
+        use std::env;
+        use std::os::unix::fs::symlink;
+
+        let dir = env::temp_dir().join("rls-test-linked-root");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("real")).unwrap();
+        symlink(dir.join("real"), dir.join("link")).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+
+        let mut mappings = PathMappings::new(&[]);
+        let real = mappings.set_root(&dir.join("link"));
+        assert_eq!(real, dir.join("real"));
+        assert_eq!(mappings.root(), Some(real.clone()));
+        assert_eq!(mappings.from_client(&dir.join("link/src/lib.rs")), dir.join("real/src/lib.rs"));
+        assert_eq!(mappings.to_client(&dir.join("real/src/lib.rs")), dir.join("link/src/lib.rs"));
+        assert_eq!(mappings.to_client(&real), dir.join("link"));
+        // Files elsewhere keep their path.
+        assert_eq!(mappings.to_client(&dir.join("other.rs")), dir.join("other.rs"));
+
+        // The client's root is mapped to the link, which is resolved again.
+        let client_dir = PathBuf::from("/client");
+        let mappings = mappings.with_prefixes(&[(client_dir.clone(), dir.clone())]);
+        assert_eq!(mappings.root(), Some(dir.join("real")));
+        assert_eq!(mappings.to_client(&dir.join("real/src/lib.rs")), dir.join("link/src/lib.rs"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prefix_map() {
+        let mappings = PrefixMap::new(&[(PathBuf::from("/home/me/src"), PathBuf::from("/src"))]);
+        let client = PathBuf::from("/home/me/src/foo/lib.rs");
+        let server = PathBuf::from("/src/foo/lib.rs");
+        assert_eq!(mappings.forward(&client), Some(server.clone()));
+        assert_eq!(mappings.backward(&server), Some(client.clone()));
+        assert_eq!(mappings.backward(Path::new("/src")), Some(PathBuf::from("/home/me/src")));
+        // Only whole components are prefixes.
+        assert_eq!(mappings.backward(Path::new("/srcs/lib.rs")), None);
+    }
+
+    #[test]
+    fn test_path_mappings() {
+        let mappings = PathMappings::new(&[(PathBuf::from("/home/me/src"), PathBuf::from("/src"))]);
+        assert_eq!(mappings.from_client(Path::new("/home/me/src/lib.rs")), Path::new("/src/lib.rs"));
+        assert_eq!(mappings.to_client(Path::new("/src/lib.rs")), Path::new("/home/me/src/lib.rs"));
+        assert_eq!(mappings.to_client(Path::new("/other/lib.rs")), Path::new("/other/lib.rs"));
+        assert_eq!(mappings.root(), None);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let path = Path::new("/foo/src/lib.rs");
+        if cfg!(windows) {
+            assert_eq!(normalize(path), Path::new(r"\foo\src\lib.rs"));
+        } else {
+            assert_eq!(normalize(path), path);
+        }
+    }
+}
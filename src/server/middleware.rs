@@ -0,0 +1,62 @@
+// Copyright 2017 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Hooks around the handling of messages, for logging, metrics, rewriting
+//! messages or checking them in tests.
+
+use server::{Output, RawMessage};
+
+use std::sync::{Arc, RwLock};
+
+/// Hooks called with the messages between the client and the server, see
+/// `LsService::add_middleware`. Responses and notifications are often sent
+/// from other threads than the one reading messages.
+pub trait Middleware: Send + Sync {
+    /// Called with each request and notification from the client before it's
+    /// handled, in the order the middleware was added. It may be changed, e.g.
+    /// to another method.
+    fn on_request(&self, _message: &mut RawMessage) {}
+
+    /// Called with each response, notification and request sent to the client
+    /// (the JSON-RPC message, without headers) before it's sent, in the reverse
+    /// order the middleware was added. It may be changed.
+    fn on_response(&self, _message: &mut String) {}
+}
+
+/// The middleware of a service, shared with its output.
+pub(super) type MiddlewareStack = Arc<RwLock<Vec<Box<Middleware>>>>;
+
+/// An output passing what is sent through the middleware first.
+#[derive(Clone)]
+pub(super) struct MiddlewareOutput<O: Output> {
+    pub(super) output: O,
+    pub(super) middleware: MiddlewareStack,
+}
+
+impl<O: Output> MiddlewareOutput<O> {
+    pub(super) fn on_request(&self, message: &mut RawMessage) {
+        for middleware in self.middleware.read().unwrap().iter() {
+            middleware.on_request(message);
+        }
+    }
+}
+
+impl<O: Output> Output for MiddlewareOutput<O> {
+    fn response(&self, mut output: String) {
+        for middleware in self.middleware.read().unwrap().iter().rev() {
+            middleware.on_response(&mut output);
+        }
+        self.output.response(output);
+    }
+
+    fn provide_id(&self) -> u32 {
+        self.output.provide_id()
+    }
+}
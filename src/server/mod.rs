@@ -12,7 +12,8 @@
 //! interactions (for example, to add support for handling new types of
 //! requests).
 
-use analysis::AnalysisHost;
+use analysis::{AnalysisHost, Target};
+use build::standalone_project;
 use jsonrpc_core::{self as jsonrpc, Id};
 use vfs::Vfs;
 use serde;
@@ -21,29 +22,101 @@ use serde::Deserialize;
 
 use version;
 use lsp_data::*;
-use actions::{ActionContext, requests, notifications};
+use paths::{self, PathMappings};
+use actions::{path_mappings, ActionContext, commands, requests, notifications};
+use actions::client::PendingRequests;
 use config::Config;
-pub use server::io::{MessageReader, Output};
-use server::io::{StdioMsgReader, StdioOutput};
+pub use server::io::{MessageReader, Output, StdioMsgReader, StdioOutput};
+pub use server::middleware::Middleware;
+use server::middleware::{MiddlewareOutput, MiddlewareStack};
 
+use std::any::Any;
+use std::collections::HashMap;
 use std::fmt;
 use std::marker::PhantomData;
-use std::path::PathBuf;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 mod io;
+mod middleware;
 
 /// Run the Rust Language Server.
-pub fn run_server(analysis: Arc<AnalysisHost>, vfs: Arc<Vfs>) {
-    debug!("Language Server starting up. Version: {}", version());
-    let service = LsService::new(analysis,
-                                 vfs,
-                                 Arc::new(Mutex::new(Config::default())),
-                                 Box::new(StdioMsgReader),
-                                 StdioOutput::new());
-    LsService::run(service);
-    debug!("Server shutting down");
+pub fn run_server(analysis: Arc<AnalysisHost>, vfs: Arc<Vfs>, config: Config) {
+    Server::new().analysis(analysis).vfs(vfs).config(config).run_stdio();
+}
+
+/// Builds and runs a language server, for embedding the RLS in another
+/// program. The analysis data and the VFS are created by the server unless
+/// they are given, the config is the default one unless it's given (the
+/// client's settings still change it).
+pub struct Server {
+    analysis: Option<Arc<AnalysisHost>>,
+    vfs: Option<Arc<Vfs>>,
+    config: Config,
+}
+
+impl Server {
+    /// A server with the default config.
+    pub fn new() -> Server {
+        Server {
+            analysis: None,
+            vfs: None,
+            config: Config::default(),
+        }
+    }
+
+    /// Use `config` instead of the default config, e.g. one with overrides.
+    pub fn config(mut self, config: Config) -> Server {
+        self.config = config;
+        self
+    }
+
+    /// Share the analysis data with the rest of the program.
+    pub fn analysis(mut self, analysis: Arc<AnalysisHost>) -> Server {
+        self.analysis = Some(analysis);
+        self
+    }
+
+    /// Share the VFS (the text of the files, including unsaved changes) with
+    /// the rest of the program.
+    pub fn vfs(mut self, vfs: Arc<Vfs>) -> Server {
+        self.vfs = Some(vfs);
+        self
+    }
+
+    /// Serve a client speaking the protocol on stdin and stdout, until it
+    /// exits.
+    pub fn run_stdio(self) {
+        self.run(Box::new(StdioMsgReader), StdioOutput::new());
+    }
+
+    /// Serve a client whose messages are read by `reader` and which is sent
+    /// responses and notifications through `output`, until it exits (or
+    /// `reader` has no more messages).
+    pub fn run<O: Output>(self, reader: Box<MessageReader + Send + Sync>, output: O) {
+        debug!("Language Server starting up. Version: {}", version());
+        LsService::run(self.service(reader, output));
+        debug!("Server shutting down");
+    }
+
+    /// The service serving a client through `reader` and `output`, without
+    /// running it, e.g. to change which actions handle messages (see
+    /// `LsService::register_request`) or to handle one message at a time (see
+    /// `LsService::handle_message`).
+    pub fn service<O: Output>(self, reader: Box<MessageReader + Send + Sync>, output: O) -> LsService<O> {
+        let analysis = self.analysis.unwrap_or_else(|| Arc::new(AnalysisHost::new(Target::Debug)));
+        let vfs = self.vfs.unwrap_or_else(|| Arc::new(Vfs::new()));
+        LsService::new(analysis, vfs, Arc::new(Mutex::new(self.config)), reader, output)
+    }
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
 }
 
 /// A response that just acknowledges receipt of its request.
@@ -135,17 +208,23 @@ pub struct Notification<'a, A: NotificationAction<'a>> {
 impl<'a, A: RequestAction<'a>> Request<'a, A> {
     fn dispatch<O: Output>(self, state: &'a mut LsState, ctx: &mut ActionContext, out: O) -> Result<A::Response, ()> {
         let mut action = A::new(state);
-        let result = action.handle(self.id, self.params, ctx, out.clone())?;
-        result.send(self.id, out);
-        Ok(result)
+        let started = Instant::now();
+        let result = action.handle(self.id, self.params, ctx, out.clone());
+        if let Ok(ref response) = result {
+            response.send(self.id, out.clone());
+        }
+        ctx.request_served(&out, A::METHOD, result.is_ok(), started.elapsed());
+        result
     }
 }
 
 impl<'a, A: NotificationAction<'a>> Notification<'a, A> {
     fn dispatch<O: Output>(self, state: &'a mut LsState, ctx: &mut ActionContext, out: O) -> Result<(), ()> {
         let mut action = A::new(state);
-        action.handle(self.params, ctx, out)?;
-        Ok(())
+        let started = Instant::now();
+        let result = action.handle(self.params, ctx, out);
+        ctx.notification_handled(A::METHOD, result.is_ok(), started.elapsed());
+        result
     }
 }
 
@@ -170,68 +249,110 @@ impl<'a, A: NotificationAction<'a>> fmt::Display for Notification<'a, A> {
     }
 }
 
+/// Handles a message for a single method, see `LsService::register_request`.
+type Handler<O> = fn(&RawMessage, &mut LsState, &mut ActionContext, O) -> Result<(), jsonrpc::Error>;
+
+type MethodTable<O> = HashMap<&'static str, Handler<O>>;
+
+fn handle_request<A, O>(msg: &RawMessage, state: &mut LsState, ctx: &mut ActionContext, out: O) -> Result<(), jsonrpc::Error>
+    where A: for<'a> RequestAction<'a>,
+          O: Output
+{
+    let request = msg.parse_as_request::<A>()?;
+    if let Err(_) = request.dispatch(state, ctx, out) {
+        debug!("Error handling request: {:?}", msg);
+    }
+    Ok(())
+}
+
+fn handle_notification<A, O>(msg: &RawMessage, state: &mut LsState, ctx: &mut ActionContext, out: O) -> Result<(), jsonrpc::Error>
+    where A: for<'a> NotificationAction<'a>,
+          O: Output
+{
+    let notification = msg.parse_as_notification::<A>()?;
+    if let Err(_) = notification.dispatch(state, ctx, out) {
+        debug!("Error handling notification: {:?}", msg);
+    }
+    Ok(())
+}
+
 /// A service implementing a language server.
 pub struct LsService<O: Output> {
     msg_reader: Box<MessageReader + Send + Sync>,
-    output: O,
+    // Everything sent to the client passes through the middleware.
+    output: MiddlewareOutput<O>,
     ctx: ActionContext,
+    // The handlers of the methods the service supports, so that dispatching a
+    // message is a single lookup.
+    methods: MethodTable<MiddlewareOutput<O>>,
+    // Requests sent to the client, see `handle_response`.
+    pending: Arc<PendingRequests>,
     /// The public shared state for this language server.
     pub state: LsState,
 }
 
+// The JSON-RPC error code of requests before `initialize`, as defined by the
+// spec.
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
 /// Public shared state for this language server.
 #[derive(Debug)]
 pub struct LsState {
-    shut_down: AtomicBool,
+    shut_down: Arc<AtomicBool>,
+    // Whether `initialize` was handled, before that (almost) every message is
+    // refused.
+    initialized: bool,
 }
 
 /// A request to shutdown the language server and perform clean up, but not to
 /// exit the process. After receiving a response to this request, the client
 /// will send an `exit` notification, at which point we will actually exit the
-/// process.
-pub struct ShutdownRequest<'a> {
-    state: &'a mut LsState,
+/// process. The response is only sent once the running build is done, see
+/// `ActionContext::shutdown`.
+pub struct ShutdownRequest {
+    shut_down: Arc<AtomicBool>,
 }
 
-impl<'a> Action<'a> for ShutdownRequest<'a> {
+impl<'a> Action<'a> for ShutdownRequest {
     type Params = NoParams;
     const METHOD: &'static str = "shutdown";
 
     fn new(state: &'a mut LsState) -> Self {
         ShutdownRequest {
-            state
+            shut_down: state.shut_down.clone(),
         }
     }
 }
 
-impl<'a> RequestAction<'a> for ShutdownRequest<'a> {
+impl<'a> RequestAction<'a> for ShutdownRequest {
     type Response = Ack;
-    fn handle<O: Output>(&mut self, _id: usize, _params: Self::Params, _ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
-        self.state.shut_down.store(true, Ordering::SeqCst);
+    fn handle<O: Output>(&mut self, _id: usize, _params: Self::Params, ctx: &mut ActionContext, _out: O) -> Result<Self::Response, ()> {
+        self.shut_down.store(true, Ordering::SeqCst);
+        ctx.shutdown();
         Ok(Ack)
     }
 }
 
 /// Notification that it is time for the language server to exit its process.
 #[derive(Debug)]
-pub struct ExitNotification<'a> {
-    state: &'a mut LsState,
+pub struct ExitNotification {
+    shut_down: Arc<AtomicBool>,
 }
 
-impl<'a> Action<'a> for ExitNotification<'a> {
+impl<'a> Action<'a> for ExitNotification {
     type Params = NoParams;
     const METHOD: &'static str = "exit";
 
     fn new(state: &'a mut LsState) -> Self {
         ExitNotification {
-            state
+            shut_down: state.shut_down.clone(),
         }
     }
 }
 
-impl<'a> NotificationAction<'a> for ExitNotification<'a> {
+impl<'a> NotificationAction<'a> for ExitNotification {
     fn handle<O: Output>(&mut self, _params: Self::Params, _ctx: &mut ActionContext, _out: O) -> Result<(), ()> {
-        let shut_down = self.state.shut_down.load(Ordering::SeqCst);
+        let shut_down = self.shut_down.load(Ordering::SeqCst);
         ::std::process::exit(if shut_down { 0 } else { 1 });
     }
 }
@@ -240,7 +361,7 @@ impl<'a> NotificationAction<'a> for ExitNotification<'a> {
 pub struct InitializeRequest;
 
 impl<'a> Action<'a> for InitializeRequest {
-    type Params = InitializeParams;
+    type Params = RlsInitializeParams;
     const METHOD: &'static str = "initialize";
 
     fn new(_: &'a mut LsState) -> Self {
@@ -248,19 +369,60 @@ impl<'a> Action<'a> for InitializeRequest {
     }
 }
 
-fn get_root_path(params: &InitializeParams) -> PathBuf {
-    params.root_uri.as_ref().map(|uri| {
-        assert!(uri.scheme() == "file");
-        uri.to_file_path().expect("Could not convert URI to path")
-    }).unwrap_or_else(|| {
-        params.root_path.as_ref().map(PathBuf::from).expect("No root path or URI")
-    })
+// The root of the workspace as the client knows it, `None` if the client has
+// no workspace open.
+fn get_root_path(params: &InitializeParams) -> Result<Option<PathBuf>, jsonrpc::Error> {
+    if let Some(ref uri) = params.root_uri {
+        return parse_file_path(uri).map(Some).map_err(|err| invalid_uri_error(uri, err));
+    }
+    match params.root_path {
+        Some(ref path) if !Path::new(path).is_absolute() => Err(jsonrpc::Error {
+            code: jsonrpc::ErrorCode::InvalidParams,
+            message: format!("The root path is not absolute: {}", path),
+            data: None,
+        }),
+        Some(ref path) => Ok(Some(paths::normalize(Path::new(path)))),
+        None => Ok(None),
+    }
+}
+
+/// The capabilities the RLS advertises in its response to `initialize`.
+/// Formatting needs rustfmt, it is only advertised if `formatting`, i.e. if
+/// that was compiled in or the config names a rustfmt binary. Completions are
+/// always advertised, without racer they only come from the analysis data and
+/// the RLS' own sources (e.g. attributes and keywords).
+fn server_capabilities(formatting: bool) -> ServerCapabilities {
+    let completion_provider = Some(CompletionOptions {
+        resolve_provider: Some(true),
+        trigger_characters: vec![".".to_string(), ":".to_string()],
+    });
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncKind::Incremental),
+        hover_provider: Some(true),
+        completion_provider,
+        definition_provider: Some(true),
+        references_provider: Some(true),
+        document_highlight_provider: Some(true),
+        document_symbol_provider: Some(true),
+        workspace_symbol_provider: Some(true),
+        code_action_provider: Some(true),
+        document_formatting_provider: Some(formatting),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: commands::command_names(),
+        }),
+        rename_provider: Some(true),
+        document_range_formatting_provider: if formatting { Some(true) } else { None },
+
+        code_lens_provider: None,
+        document_on_type_formatting_provider: None,
+        signature_help_provider: None,
+    }
 }
 
 impl<'a> RequestAction<'a> for InitializeRequest {
     type Response = NoResponse;
     fn handle<O: Output>(&mut self, id: usize, params: Self::Params, ctx: &mut ActionContext, out: O) -> Result<NoResponse, ()> {
-        let init_options: InitializationOptions = params
+        let init_options: InitializationOptions = params.params
             .initialization_options
             .as_ref()
             .and_then(|options| serde_json::from_value(options.to_owned()).ok())
@@ -268,38 +430,43 @@ impl<'a> RequestAction<'a> for InitializeRequest {
 
         trace!("init: {:?}", init_options);
 
-        let result = InitializeResult {
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncKind::Incremental),
-                hover_provider: Some(true),
-                completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(true),
-                    trigger_characters: vec![".".to_string(), ":".to_string()],
-                }),
-                definition_provider: Some(true),
-                references_provider: Some(true),
-                document_highlight_provider: Some(true),
-                document_symbol_provider: Some(true),
-                workspace_symbol_provider: Some(true),
-                code_action_provider: Some(true),
-                document_formatting_provider: Some(true),
-                execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["rls.applySuggestion".to_owned()],
-                }),
-                rename_provider: Some(true),
-                // These are supported if the `unstable_features` option is set.
-                // We'll update these capabilities dynamically when we get config
-                // info from the client.
-                document_range_formatting_provider: Some(false),
-
-                code_lens_provider: None,
-                document_on_type_formatting_provider: None,
-                signature_help_provider: None,
+        // Without a workspace, the files the client opens are standalone.
+        // The root is mapped like any other path, with the mappings of the
+        // initialization options and of the config given on startup. Those
+        // of the client's settings come later and may move it.
+        let mut mappings = PathMappings::new(&path_mappings(&init_options, &ctx.config().lock().unwrap()));
+        // Everything in a workspace opened through a symlink is known by its
+        // real path, like the compiler reports it.
+        let root_path = match get_root_path(&params.params) {
+            Ok(Some(root_path)) => mappings.set_root(&root_path),
+            Err(e) => {
+                debug!("Invalid root: {:?}", e);
+                out.failure(Id::Num(id as u64), e);
+                return Err(());
             }
+            Ok(None) => match standalone_project() {
+                Ok(dir) => dir,
+                Err(e) => {
+                    debug!("Couldn't create a standalone project: {}", e);
+                    out.failure_message(id, jsonrpc::ErrorCode::InternalError, "No root path or URI");
+                    return Err(());
+                }
+            },
+        };
+
+        let position_encoding = params.capabilities.position_encoding();
+        let formatting = cfg!(feature = "rustfmt") || ctx.config().lock().unwrap().rustfmt_path.is_some();
+        let result = RlsInitializeResult {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: RlsServerCapabilities {
+                capabilities: server_capabilities(formatting),
+                prepare_rename: params.capabilities.prepare_rename(),
+            },
+            position_encoding,
         };
         out.success(id, &result);
 
-        ctx.init(get_root_path(&params), &init_options, out);
+        ctx.init(root_path, mappings, &init_options, position_encoding, params.capabilities, out);
 
         Ok(NoResponse)
     }
@@ -323,14 +490,59 @@ impl<O: Output> LsService<O> {
                reader: Box<MessageReader + Send + Sync>,
                output: O)
                -> LsService<O> {
-        LsService {
+        let pending = Arc::new(PendingRequests::new());
+        let mut service = LsService {
             msg_reader: reader,
-            output: output,
-            ctx: ActionContext::new(analysis, vfs, config),
+            output: MiddlewareOutput {
+                output,
+                middleware: MiddlewareStack::default(),
+            },
+            ctx: ActionContext::new(analysis, vfs, config, pending.clone()),
+            pending,
+            methods: HashMap::new(),
             state: LsState {
-                shut_down: AtomicBool::new(false),
+                shut_down: Arc::new(AtomicBool::new(false)),
+                initialized: false,
             }
-        }
+        };
+        service.register_default_actions();
+        service
+    }
+
+    /// Handle the requests of `A`'s method with `A`, instead of the handler
+    /// registered for it before, if any. Requests of methods without a
+    /// handler are ignored.
+    pub fn register_request<A>(&mut self)
+        where A: for<'a> RequestAction<'a>
+    {
+        self.methods.insert(<A as Action>::METHOD, handle_request::<A, MiddlewareOutput<O>>);
+    }
+
+    /// Handle the notifications of `A`'s method with `A`, instead of the
+    /// handler registered for it before, if any.
+    pub fn register_notification<A>(&mut self)
+        where A: for<'a> NotificationAction<'a>
+    {
+        self.methods.insert(<A as Action>::METHOD, handle_notification::<A, MiddlewareOutput<O>>);
+    }
+
+    /// Stop handling messages of `method`. Returns whether there was a handler
+    /// for it.
+    pub fn unregister(&mut self, method: &str) -> bool {
+        self.methods.remove(method).is_some()
+    }
+
+    /// Whether messages of `method` are handled.
+    pub fn handles(&self, method: &str) -> bool {
+        self.methods.contains_key(method)
+    }
+
+    /// Pass the messages from and to the client through `middleware`, after
+    /// the middleware added before (for messages from the client, the other
+    /// way around for messages to it). Responses to requests the server sent
+    /// the client don't pass through middleware.
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.output.middleware.write().unwrap().push(Box::new(middleware));
     }
 
     /// Run this language service.
@@ -347,9 +559,11 @@ impl<O: Output> LsService<O> {
 
         let method = match ls_command.get("method") {
             Some(method) => method,
-            // No method means this is a response to one of our requests. FIXME: we should
-            // confirm these, but currently just ignore them.
-            None => return Ok(None),
+            // No method means this is a response to one of our requests.
+            None => {
+                self.handle_response(&ls_command);
+                return Ok(None);
+            }
         };
 
         let method = method.as_str().ok_or_else(|| jsonrpc::Error::invalid_request())?.to_owned();
@@ -370,66 +584,108 @@ impl<O: Output> LsService<O> {
         Ok(Some(RawMessage { method, id, params }))
     }
 
+    // Passes a response from the client on to whoever sent the request.
+    fn handle_response(&self, response: &serde_json::Value) {
+        let id = match response.get("id").and_then(|id| id.as_u64()) {
+            Some(id) => id,
+            None => {
+                debug!("response without a numeric id: {:?}", response);
+                return;
+            }
+        };
+        let result = match response.get("error") {
+            Some(error) => Err(serde_json::from_value(error.to_owned()).unwrap_or_else(|_| jsonrpc::Error::internal_error())),
+            None => Ok(response.get("result").cloned().unwrap_or(serde_json::Value::Null)),
+        };
+        if !self.pending.handle_response(id, result) {
+            debug!("unexpected response: {:?}", response);
+        }
+    }
+
+    // Registers the handlers of every method the RLS supports.
+    fn register_default_actions(&mut self) {
+        self.register_notification::<ExitNotification>();
+        self.register_notification::<notifications::Initialized>();
+        self.register_notification::<notifications::DidOpen>();
+        self.register_notification::<notifications::DidClose>();
+        self.register_notification::<notifications::DidChange>();
+        self.register_notification::<notifications::DidSave>();
+        self.register_notification::<notifications::DidChangeConfiguration>();
+        self.register_notification::<notifications::DidChangeWatchedFiles>();
+        self.register_notification::<notifications::Cancel>();
+
+        self.register_request::<ShutdownRequest>();
+        self.register_request::<InitializeRequest>();
+        self.register_request::<requests::Definition>();
+        self.register_request::<requests::TypeDefinition>();
+        self.register_request::<requests::Declaration>();
+        self.register_request::<requests::References>();
+        self.register_request::<requests::Rename>();
+        self.register_request::<requests::PrepareRename>();
+        self.register_request::<requests::DocumentHighlight>();
+        self.register_request::<requests::ExecuteCommand>();
+        self.register_request::<requests::CodeAction>();
+        self.register_request::<requests::FindImpls>();
+        self.register_request::<requests::Implementation>();
+        self.register_request::<requests::Deglob>();
+        self.register_request::<requests::Symbols>();
+        self.register_request::<requests::WorkspaceSymbol>();
+        self.register_request::<requests::Hover>();
+        self.register_request::<requests::Completion>();
+        self.register_request::<requests::ResolveCompletion>();
+        self.register_request::<requests::TypeOfSelectionRequest>();
+        self.register_request::<requests::BorrowsRequest>();
+        self.register_request::<requests::Status>();
+        self.register_request::<requests::MetricsRequest>();
+        self.register_request::<requests::DiagnosticsRequest>();
+        self.register_request::<requests::ChangedSinceBuildRequest>();
+        self.register_request::<requests::Reload>();
+        // Without rustfmt compiled in, a rustfmt binary may format.
+        self.register_request::<requests::Formatting>();
+        self.register_request::<requests::RangeFormatting>();
+        self.register_request::<requests::WillSaveWaitUntil>();
+    }
+
     fn dispatch_message(&mut self, msg: &RawMessage) -> Result<(), jsonrpc::Error> {
-        macro_rules! match_action {
-            ($method: expr; notifications: $($n_action: ty),*; requests: $($r_action: ty),*;) => {
-                let mut handled = false;
-                trace!("Handling `{}`", $method);
-                $(
-                    if $method == <$n_action as Action>::METHOD {
-                        let notification = msg.parse_as_notification::<$n_action>()?;
-                        if let Err(_) = notification.dispatch(&mut self.state, &mut self.ctx, self.output.clone()) {
-                            debug!("Error handling notification: {:?}", msg);
-                        }
-                        handled = true;
-                    }
-                )*
-                $(
-                    if $method == <$r_action as Action>::METHOD {
-                        let request = msg.parse_as_request::<$r_action>()?;
-                        if let Err(_) = request.dispatch(&mut self.state, &mut self.ctx, self.output.clone()) {
-                            debug!("Error handling notification: {:?}", msg);
-                        }
-                        handled = true;
-                    }
-                )*
-                if !handled {
-                    debug!("Method not found: {}", $method);
+        trace!("Handling `{}`", msg.method);
+        let handler = match self.methods.get(&*msg.method) {
+            Some(handler) => *handler,
+            None => {
+                debug!("Method not found: {}", msg.method);
+                return Ok(());
+            }
+        };
+
+        // A bug in one action shouldn't take down the whole server. The state
+        // it was changing may be inconsistent afterwards, but that's better
+        // than losing everything. If the panic poisoned a lock, the context
+        // starts over before the next message.
+        let output = self.output.clone();
+        let state = &mut self.state;
+        let ctx = &mut self.ctx;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| handler(msg, state, ctx, output)));
+        match result {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = format!("The RLS crashed handling `{}`: {}", msg.method, panic_message(&*payload));
+                debug!("{}", message);
+                if let Some(ref id) = msg.id {
+                    self.output.failure(id.clone(), jsonrpc::Error {
+                        code: jsonrpc::ErrorCode::InternalError,
+                        message: message.clone(),
+                        data: None,
+                    });
                 }
+                self.output.notify(NotificationMessage::new(
+                    NOTIFICATION_SHOW_MESSAGE,
+                    Some(NotificationParams::ShowMessage(ShowMessageParams {
+                        typ: MessageType::Error,
+                        message,
+                    })),
+                ));
+                Ok(())
             }
         }
-
-        match_action!(
-            msg.method;
-            notifications:
-                ExitNotification,
-                notifications::Initialized,
-                notifications::DidOpen,
-                notifications::DidChange,
-                notifications::DidSave,
-                notifications::DidChangeConfiguration,
-                notifications::DidChangeWatchedFiles,
-                notifications::Cancel;
-            requests:
-                ShutdownRequest,
-                InitializeRequest,
-                requests::Definition,
-                requests::References,
-                requests::Completion,
-                requests::ResolveCompletion,
-                requests::Rename,
-                requests::DocumentHighlight,
-                requests::ExecuteCommand,
-                requests::CodeAction,
-                requests::FindImpls,
-                requests::Deglob,
-                requests::Symbols,
-                requests::WorkspaceSymbol,
-                requests::Formatting,
-                requests::RangeFormatting,
-                requests::Hover;
-        );
-        Ok(())
     }
 
     /// Read a message from the language server reader input and handle it with
@@ -447,7 +703,7 @@ impl<O: Output> LsService<O> {
 
         trace!("Read message `{}`", msg_string);
 
-        let raw_message = match self.parse_message(&msg_string) {
+        let mut raw_message = match self.parse_message(&msg_string) {
             Ok(Some(rm)) => rm,
             Ok(None) => return ServerStateChange::Continue,
             Err(e) => {
@@ -459,6 +715,8 @@ impl<O: Output> LsService<O> {
 
         trace!("Parsed message `{:?}`", raw_message);
 
+        self.output.on_request(&mut raw_message);
+
         // If we're in shutdown mode, ignore any messages other than 'exit'.
         // This is not actually in the spec, I'm not sure we should do this,
         // but it kinda makes sense.
@@ -470,21 +728,57 @@ impl<O: Output> LsService<O> {
             }
         }
 
+        // Before initialization requests must be answered with an error and
+        // notifications (but `exit`) dropped, see the spec.
+        let initializing = raw_message.method == InitializeRequest::METHOD;
+        if !self.state.initialized && !initializing && raw_message.method != ExitNotification::METHOD {
+            trace!("Not initialized, refusing {:?}", raw_message);
+            if let Some(id) = raw_message.id {
+                self.output.failure(id, jsonrpc::Error {
+                    code: jsonrpc::ErrorCode::ServerError(SERVER_NOT_INITIALIZED),
+                    message: "The server is not initialized".to_owned(),
+                    data: None,
+                });
+            }
+            return ServerStateChange::Continue;
+        }
+
+        // Initializing again (e.g. when an editor restarts the session of a
+        // workspace) starts from scratch.
+        if self.state.initialized && initializing {
+            debug!("Initializing again");
+            self.ctx.reset();
+            self.state.initialized = false;
+        }
+
+        // Also after panics on other threads, e.g. handling the results of a
+        // build.
+        self.ctx.restart_if_poisoned(self.output.clone());
+
         if let Err(e) = self.dispatch_message(&raw_message) {
             debug!("dispatch error, {:?}", e);
             self.output.failure(raw_message.id.unwrap_or(Id::Null), e);
             return ServerStateChange::Break;
         }
 
+        if initializing {
+            self.state.initialized = self.ctx.is_initialized();
+        }
+
         ServerStateChange::Continue
     }
 }
 
+/// A request or notification from the client, before its parameters are
+/// parsed for the action handling its method.
 #[derive(Debug)]
-struct RawMessage {
-    method: String,
-    id: Option<Id>,
-    params: serde_json::Value,
+pub struct RawMessage {
+    /// The method, e.g. `textDocument/hover`.
+    pub method: String,
+    /// The id of a request, `None` for notifications.
+    pub id: Option<Id>,
+    /// The parameters, `Null` if there are none.
+    pub params: serde_json::Value,
 }
 
 impl RawMessage {
@@ -532,6 +826,17 @@ impl RawMessage {
     }
 }
 
+// The message a panic was started with, e.g. by `panic!("...")`.
+fn panic_message(payload: &(Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        *message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "unknown error"
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -569,7 +874,7 @@ mod test {
         params.root_path = Some(root_path.to_str().unwrap().to_owned());
         params.root_uri = Some(Url::from_directory_path(&root_uri).unwrap());
 
-        assert_eq!(get_root_path(&params), root_uri);
+        assert_eq!(get_root_path(&params).unwrap(), Some(root_uri));
     }
 
     #[test]
@@ -580,7 +885,77 @@ mod test {
         params.root_path = Some(root_path.to_str().unwrap().to_owned());
         params.root_uri = None;
 
-        assert_eq!(get_root_path(&params), root_path);
+        assert_eq!(get_root_path(&params).unwrap(), Some(root_path));
+    }
+
+    #[test]
+    fn test_no_root() {
+        let mut params = get_default_params();
+        params.root_path = None;
+        params.root_uri = None;
+
+        assert_eq!(get_root_path(&params).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_root_uri() {
+        let mut params = get_default_params();
+        params.root_uri = Some(Url::parse("untitled:Untitled-1").unwrap());
+
+        let error = get_root_path(&params).unwrap_err();
+        assert_eq!(error.code, jsonrpc::ErrorCode::InvalidParams);
+
+        params.root_uri = None;
+        params.root_path = Some("relative/path".to_owned());
+        assert!(get_root_path(&params).is_err());
+    }
+
+    fn serialized_capabilities(prepare_rename: bool) -> serde_json::Value {
+        serde_json::to_value(&RlsServerCapabilities { capabilities: server_capabilities(true), prepare_rename }).unwrap()
+    }
+
+    #[test]
+    fn test_rename_provider_supports_prepare() {
+        let capabilities = serialized_capabilities(true);
+        assert_eq!(capabilities["renameProvider"], json!({ "prepareProvider": true }));
+        assert_eq!(capabilities["hoverProvider"], json!(true));
+        assert_eq!(capabilities["typeDefinitionProvider"], json!(true));
+        assert_eq!(capabilities["declarationProvider"], json!(true));
+        assert_eq!(capabilities["implementationProvider"], json!(true));
+    }
+
+    #[test]
+    fn test_completion_provider() {
+        // Not all completions come from racer, so they're advertised without it.
+        let capabilities = serialized_capabilities(false);
+        assert_eq!(capabilities["completionProvider"]["resolveProvider"], json!(true));
+    }
+
+    #[test]
+    fn test_rename_provider_without_prepare_support() {
+        assert_eq!(serialized_capabilities(false)["renameProvider"], json!(true));
+    }
+
+    #[test]
+    fn test_text_document_sync_options() {
+        let capabilities = serialized_capabilities(false);
+        assert_eq!(capabilities["textDocumentSync"], json!({
+            "openClose": true,
+            "change": 2,
+            "willSaveWaitUntil": true,
+            "save": { "includeText": false },
+        }));
+    }
+
+    #[test]
+    fn test_formatting_provider() {
+        let capabilities = RlsServerCapabilities { capabilities: server_capabilities(false), prepare_rename: false };
+        let capabilities = serde_json::to_value(&capabilities).unwrap();
+        assert_eq!(capabilities["documentFormattingProvider"], json!(false));
+        assert_eq!(capabilities["textDocumentSync"]["willSaveWaitUntil"], json!(false));
+        let advertised = advertised_methods(&capabilities);
+        assert!(!advertised.contains(&"textDocument/formatting"));
+        assert!(!advertised.contains(&"textDocument/rangeFormatting"));
     }
 
     #[test]
@@ -597,4 +972,80 @@ mod test {
             _action: PhantomData,
         }));
     }
+
+    // The methods a client may send, given the capabilities of the server as
+    // it sends them, see `serialized_capabilities`.
+    fn advertised_methods(capabilities: &serde_json::Value) -> Vec<&'static str> {
+        let enabled = |capability: &serde_json::Value| match *capability {
+            serde_json::Value::Bool(enabled) => enabled,
+            serde_json::Value::Null => false,
+            _ => true,
+        };
+        let sync = &capabilities["textDocumentSync"];
+        let advertised = vec![
+            (enabled(&sync["openClose"]), &["textDocument/didOpen", "textDocument/didClose"][..]),
+            (enabled(&sync["change"]), &["textDocument/didChange"][..]),
+            (enabled(&sync["save"]), &["textDocument/didSave"][..]),
+            (enabled(&sync["willSaveWaitUntil"]), &["textDocument/willSaveWaitUntil"][..]),
+            (enabled(&capabilities["hoverProvider"]), &["textDocument/hover"][..]),
+            (enabled(&capabilities["completionProvider"]), &["textDocument/completion"][..]),
+            (enabled(&capabilities["completionProvider"]["resolveProvider"]), &["completionItem/resolve"][..]),
+            (enabled(&capabilities["definitionProvider"]), &["textDocument/definition"][..]),
+            (enabled(&capabilities["typeDefinitionProvider"]), &["textDocument/typeDefinition"][..]),
+            (enabled(&capabilities["declarationProvider"]), &["textDocument/declaration"][..]),
+            (enabled(&capabilities["implementationProvider"]), &["textDocument/implementation"][..]),
+            (enabled(&capabilities["referencesProvider"]), &["textDocument/references"][..]),
+            (enabled(&capabilities["documentHighlightProvider"]), &["textDocument/documentHighlight"][..]),
+            (enabled(&capabilities["documentSymbolProvider"]), &["textDocument/documentSymbol"][..]),
+            (enabled(&capabilities["workspaceSymbolProvider"]), &["workspace/symbol"][..]),
+            (enabled(&capabilities["codeActionProvider"]), &["textDocument/codeAction"][..]),
+            (enabled(&capabilities["documentFormattingProvider"]), &["textDocument/formatting"][..]),
+            (enabled(&capabilities["documentRangeFormattingProvider"]), &["textDocument/rangeFormatting"][..]),
+            (enabled(&capabilities["executeCommandProvider"]), &["workspace/executeCommand"][..]),
+            (enabled(&capabilities["renameProvider"]), &["textDocument/rename"][..]),
+            (enabled(&capabilities["renameProvider"]["prepareProvider"]), &["textDocument/prepareRename"][..]),
+        ];
+        advertised.into_iter().filter(|&(enabled, _)| enabled).flat_map(|(_, methods)| methods.iter().cloned()).collect()
+    }
+
+    fn default_service() -> LsService<StdioOutput> {
+        Server::new().service(Box::new(StdioMsgReader), StdioOutput::new())
+    }
+
+    #[test]
+    fn test_method_table_handles_capabilities() {
+        let service = default_service();
+        for method in advertised_methods(&serialized_capabilities(true)) {
+            assert!(service.handles(method), "`{}` is advertised but has no handler", method);
+        }
+    }
+
+    #[test]
+    fn test_method_table_reachable() {
+        let service = default_service();
+        let advertised = advertised_methods(&serialized_capabilities(true));
+        let lifecycle = ["initialize", "initialized", "shutdown", "exit", "$/cancelRequest",
+                         "workspace/didChangeConfiguration", "workspace/didChangeWatchedFiles"];
+        // The RLS' own methods, which clients know about without capabilities.
+        let custom = ["rustDocument/implementations", "rustDocument/typeOfSelection", "rustDocument/borrows",
+                      "rustWorkspace/deglob", "rustWorkspace/changedSinceBuild",
+                      "rls/status", "rls/metrics", "rls/diagnostics", "rls/reload"];
+        for method in custom.iter() {
+            assert!(service.handles(method), "`{}` has no handler", method);
+        }
+        for method in service.methods.keys() {
+            let reachable = advertised.contains(method) || lifecycle.contains(method) || custom.contains(method);
+            assert!(reachable, "`{}` has a handler, but clients don't know about it", method);
+        }
+    }
+
+    #[test]
+    fn test_register_actions() {
+        let mut service = default_service();
+        assert!(service.unregister("textDocument/hover"));
+        assert!(!service.handles("textDocument/hover"));
+        assert!(!service.unregister("textDocument/hover"));
+        service.register_request::<requests::Hover>();
+        assert!(service.handles("textDocument/hover"));
+    }
 }
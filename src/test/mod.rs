@@ -11,19 +11,20 @@
 // Utilities and infrastructure for testing. Tests in this module test the
 // testing infrastructure *not* the RLS.
 
-mod harness;
+pub mod harness;
 
 use analysis;
-use actions::requests;
+use actions::ActionContext;
+use actions::{notifications, requests};
 use config::{Config, Inferrable};
-use server::{self as ls_server, Request, ShutdownRequest, NoParams};
+use server::{self as ls_server, Notification, Request, ShutdownRequest, NoParams};
 use jsonrpc_core;
 use vfs;
 
 use self::harness::{Environment, expect_messages, ExpectedMessage, RecordOutput, src};
 
 use ls_types::*;
-use lsp_data::InitializationOptions;
+use lsp_data::{InitializationOptions, RlsReferenceParams, RlsWorkspaceSymbolParams};
 
 use env_logger;
 use serde_json;
@@ -52,7 +53,7 @@ pub fn initialize_with_opts<'a>(id: usize, root_path: Option<String>, initializa
     };
     Request {
         id,
-        params,
+        params: params.into(),
         _action: PhantomData,
     }
 }
@@ -90,6 +91,125 @@ fn test_shutdown() {
     expect_messages(results.clone(), &[&ExpectedMessage::new(Some(1))]);
 }
 
+#[test]
+fn test_shutdown_waits_for_build() {
+    let mut env = Environment::new("common");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        request::<ShutdownRequest>(1, NoParams).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Shut down while building, the build is finished before the response.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd"),
+                                       &ExpectedMessage::new(Some(1))]);
+}
+
+#[test]
+fn test_not_initialized() {
+    let mut env = Environment::new("common");
+
+    let messages = vec![
+        request::<ShutdownRequest>(1, NoParams).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(1)).expect_contains("-32002")
+                                                                 .expect_contains("not initialized")]);
+}
+
+#[test]
+fn test_initialize_twice() {
+    let mut env = Environment::new("common");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        initialize(1, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+
+    // Initializing again builds again, in a new session.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(1)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+}
+
+#[test]
+fn test_reload() {
+    let mut env = Environment::new("common");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        request::<requests::Reload>(1, NoParams).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+
+    // Reloading builds again, without initializing again.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[&ExpectedMessage::new(Some(1)),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild").expect_contains(r#""sessionId":1"#),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+}
+
+#[test]
+fn test_build_session_ids() {
+    let mut env = Environment::new("common");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities").expect_contains(r#""rls.protocolVersion":1"#)
+                                                                 .expect_contains(r#""positionEncoding":"utf-16""#),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild").expect_contains(r#""sessionId":1"#),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin").expect_contains(r#""sessionId":1"#),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd").expect_contains(r#""sessionId":1"#)
+                                                                 .expect_contains(r#""diagnosticCount":0"#)]);
+}
+
 #[test]
 fn test_goto_def() {
     let mut env = Environment::new("common");
@@ -161,8 +281,9 @@ fn test_workspace_symbol() {
 
     let messages = vec![
         initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
-        request::<requests::WorkspaceSymbol>(42, WorkspaceSymbolParams {
+        request::<requests::WorkspaceSymbol>(42, RlsWorkspaceSymbolParams {
             query: "nemo".to_owned(),
+            partial_result_token: None,
         }).to_string(),
     ];
 
@@ -206,10 +327,12 @@ fn test_find_all_refs() {
 
     let messages = vec![
         initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
-        request::<requests::References>(42, ReferenceParams {
+        request::<requests::References>(42, RlsReferenceParams {
             text_document: TextDocumentIdentifier::new(url),
             position: env.cache.mk_ls_position(src(&source_file_path, 10, "Bar")),
-            context: ReferenceContext { include_declaration: true }
+            context: ReferenceContext { include_declaration: true },
+            kinds: vec![],
+            partial_result_token: None,
         }).to_string(),
     ];
 
@@ -241,10 +364,12 @@ fn test_find_all_refs_no_cfg_test() {
 
     let messages = vec![
         initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
-        request::<requests::References>(42, ReferenceParams {
+        request::<requests::References>(42, RlsReferenceParams {
             text_document: TextDocumentIdentifier::new(url),
             position: env.cache.mk_ls_position(src(&source_file_path, 10, "Bar")),
-            context: ReferenceContext { include_declaration: true }
+            context: ReferenceContext { include_declaration: true },
+            kinds: vec![],
+            partial_result_token: None,
         }).to_string(),
     ];
 
@@ -350,6 +475,7 @@ fn test_rename() {
 }
 
 #[test]
+#[cfg(feature = "rustfmt")]
 fn test_reformat() {
     let mut env = Environment::new("reformat");
 
@@ -381,11 +507,12 @@ fn test_reformat() {
 
     assert_eq!(ls_server::LsService::handle_message(&mut server),
                ls_server::ServerStateChange::Continue);
-    expect_messages(results.clone(), &[ExpectedMessage::new(Some(42)).expect_contains(r#"{"start":{"line":0,"character":0},"end":{"line":12,"character":0}}"#)
-                                            .expect_contains(r#"newText":"// Copyright 2017 The Rust Project Developers. See the COPYRIGHT\n// file at the top-level directory of this distribution and at\n// http://rust-lang.org/COPYRIGHT.\n//\n// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or\n// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license\n// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your\n// option. This file may not be copied, modified, or distributed\n// except according to those terms.\n\npub mod foo;\npub fn main() {\n    let world = \"world\";\n    println!(\"Hello, {}!\", world);\n}"#)]);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(42)).expect_contains(r#"{"start":{"line":10,"character":0},"end":{"line":12,"character":0}}"#)
+                                            .expect_contains(r#"newText":"pub mod foo;\npub fn main() {\n    let world = \"world\";\n    println!(\"Hello, {}!\", world);\n}"#)]);
 }
 
 #[test]
+#[cfg(feature = "rustfmt")]
 fn test_reformat_with_range() {
     let mut env = Environment::new("reformat_with_range");
     let source_file_path = Path::new("src").join("main.rs");
@@ -421,8 +548,8 @@ fn test_reformat_with_range() {
 
     assert_eq!(ls_server::LsService::handle_message(&mut server),
                ls_server::ServerStateChange::Continue);
-    expect_messages(results.clone(), &[ExpectedMessage::new(Some(42)).expect_contains(r#"{"start":{"line":0,"character":0},"end":{"line":15,"character":5}}"#)
-                                            .expect_contains(r#"newText":"// Copyright 2017 The Rust Project Developers. See the COPYRIGHT\n// file at the top-level directory of this distribution and at\n// http://rust-lang.org/COPYRIGHT.\n//\n// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or\n// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license\n// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your\n// option. This file may not be copied, modified, or distributed\n// except according to those terms.\n\npub fn main() {\n    let world1 = \"world\";\n    println!(\"Hello, {}!\", world1);\n    let world2 = \"world\";\n    println!(\"Hello, {}!\", world2);\n    let world3 = \"world\";\n    println!(\"Hello, {}!\", world3);\n}\n"#)]);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(42)).expect_contains(r#"{"start":{"line":10,"character":0},"end":{"line":15,"character":5}}"#)
+                                            .expect_contains(r#"newText":"pub fn main() {\n    let world1 = \"world\";\n    println!(\"Hello, {}!\", world1);\n    let world2 = \"world\";\n    println!(\"Hello, {}!\", world2);\n    let world3 = \"world\";\n    println!(\"Hello, {}!\", world3);\n}\n"#)]);
 }
 
 #[test]
@@ -511,6 +638,56 @@ fn test_bin_lib_project() {
                                        ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
 }
 
+#[test]
+fn test_edited_crate_diagnostics_first() {
+    let mut env = Environment::new("bin_lib");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+    let lib_path = env.cache.abs_path(Path::new("src/lib.rs"));
+    let change = DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier {
+            uri: Url::from_file_path(&lib_path).unwrap(),
+            version: 1,
+        },
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "pub struct LibStruct {}\n\n#[cfg(test)]\npub struct LibCfgTestStruct {}\n\nfn unused() {}\n".to_owned(),
+        }],
+    };
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        Notification::<notifications::DidChange> { params: change, _action: PhantomData }.to_string(),
+    ];
+
+    env.with_config(|c| {
+        c.workspace_mode = true;
+        c.cfg_test = true;
+    });
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+
+    // The library is built before the binary depending on it, its diagnostics
+    // are published before the build finishes.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("publishDiagnostics")
+                                                                 .expect_contains("lib.rs")
+                                                                 .expect_contains("never used"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("publishDiagnostics")
+                                                                 .expect_contains("never used"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+}
+
 // FIXME(#524) timing issues when run concurrently with `test_bin_lib_project`
 // #[test]
 // fn test_bin_lib_project_no_cfg_test() {
@@ -630,7 +807,7 @@ fn test_omit_init_build() {
 
     let root_path = env.cache.abs_path(Path::new("."));
     let root_path = root_path.as_os_str().to_str().map(|x| x.to_owned());
-    let init_options = Some(InitializationOptions { omit_init_build: true });
+    let init_options = Some(InitializationOptions { omit_init_build: true, ..InitializationOptions::default() });
     let initialize = initialize_with_opts(0, root_path, init_options);
 
     let messages = vec![initialize.to_string()];
@@ -642,6 +819,38 @@ fn test_omit_init_build() {
     expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities")]);
 }
 
+#[test]
+fn test_status() {
+    let mut env = Environment::new("common");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+    let root_path = root_path.as_os_str().to_str().map(|x| x.to_owned());
+    let init_options = Some(InitializationOptions { status_notifications: true, ..InitializationOptions::default() });
+
+    let messages = vec![
+        initialize_with_opts(0, root_path, init_options).to_string(),
+        request::<requests::Status>(1, NoParams).to_string(),
+    ];
+
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("serverStatus").expect_contains(r#""buildState":"building""#),
+                                       ExpectedMessage::new(None).expect_contains("serverStatus").expect_contains(r#""crateName":"completion""#)
+                                                                 .expect_contains(r#""index":1"#),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("serverStatus").expect_contains(r#""buildState":"idle""#),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(1)).expect_contains(r#""buildState":"idle""#)
+                                                                   .expect_contains("analysisAgeMs")]);
+}
+
 
 #[test]
 fn test_parse_error_on_malformed_input() {
@@ -672,6 +881,106 @@ fn test_parse_error_on_malformed_input() {
     assert!(failure.error.code == jsonrpc_core::ErrorCode::ParseError);
 }
 
+#[test]
+fn test_middleware() {
+    let mut env = Environment::new("common");
+
+    let root_path = env.cache.abs_path(Path::new("."));
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        request::<ShutdownRequest>(1, NoParams).to_string().replace("\"shutdown\"", "\"test/shutdown\""),
+    ];
+
+    struct Recorder {
+        methods: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ls_server::Middleware for Recorder {
+        fn on_request(&self, message: &mut ls_server::RawMessage) {
+            self.methods.lock().unwrap().push(message.method.clone());
+            if message.method == "test/shutdown" {
+                message.method = "shutdown".to_owned();
+            }
+        }
+
+        fn on_response(&self, message: &mut String) {
+            *message = message.replace("null", "\"recorded\"");
+        }
+    }
+
+    let methods = Arc::new(Mutex::new(vec![]));
+    let (mut server, results) = env.mock_server(messages);
+    // Initialize and build.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+
+    server.add_middleware(Recorder { methods: methods.clone() });
+
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    assert_eq!(*methods.lock().unwrap(), ["test/shutdown"]);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(1)).expect_contains("recorded")]);
+}
+
+#[test]
+fn test_panic_in_action() {
+    let mut env = Environment::new("common");
+
+    struct PanicRequest;
+
+    impl<'a> ls_server::Action<'a> for PanicRequest {
+        type Params = NoParams;
+        const METHOD: &'static str = "test/panic";
+
+        fn new(_: &'a mut ls_server::LsState) -> Self {
+            PanicRequest
+        }
+    }
+
+    impl<'a> ls_server::RequestAction<'a> for PanicRequest {
+        type Response = ls_server::Ack;
+
+        fn handle<O: ls_server::Output>(&mut self, _id: usize, _params: NoParams, _ctx: &mut ActionContext, _out: O) -> Result<ls_server::Ack, ()> {
+            panic!("oh no");
+        }
+    }
+
+    let root_path = env.cache.abs_path(Path::new("."));
+
+    let messages = vec![
+        initialize(0, root_path.as_os_str().to_str().map(|x| x.to_owned())).to_string(),
+        request::<PanicRequest>(1, NoParams).to_string(),
+        request::<ShutdownRequest>(2, NoParams).to_string(),
+    ];
+    let (mut server, results) = env.mock_server(messages);
+    server.register_request::<PanicRequest>();
+
+    // Initialize and build.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(0)).expect_contains("capabilities"),
+                                       ExpectedMessage::new(None).expect_contains("beginBuild"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsBegin"),
+                                       ExpectedMessage::new(None).expect_contains("diagnosticsEnd")]);
+
+
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[ExpectedMessage::new(Some(1)).expect_contains("crashed handling `test/panic`: oh no"),
+                                       ExpectedMessage::new(None).expect_contains("window/showMessage")
+                                                                 .expect_contains("oh no")]);
+
+    // The server keeps serving.
+    assert_eq!(ls_server::LsService::handle_message(&mut server),
+               ls_server::ServerStateChange::Continue);
+    expect_messages(results.clone(), &[&ExpectedMessage::new(Some(2))]);
+}
+
 #[test]
 fn test_find_impls() {
     let mut env = Environment::new("find_impls");